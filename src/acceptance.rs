@@ -0,0 +1,428 @@
+use std::fs::read;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::fs::{FsBlock, BLOCK_SIZE, FS_REGION_BLOCKS, SKSA_BLOCKS};
+use crate::player::Player;
+use crate::summary::RangeSummary;
+use crate::triage::fsck;
+use crate::verify::verify_ranges;
+
+// 'acceptance': the checks a flashing station runs on every console it's just written, with one
+// verdict. The image directory holds the image that was flashed, nand.bin, and optionally
+// files.txt, the file listing the console should have in '5 --porcelain' form; without it, the
+// listing in the image's own newest FS is expected.
+
+pub const IMAGE_FILE: &str = "nand.bin";
+pub const LISTING_FILE: &str = "files.txt";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Check {
+    // the whole card against the image
+    Verify,
+    // the console's current FS is consistent
+    Fsck,
+    // the SKSA's hash matches the image's
+    Sksa,
+    // the files on the card are the ones expected
+    Listing,
+}
+
+pub const CHECKS: [Check; 4] = [Check::Verify, Check::Fsck, Check::Sksa, Check::Listing];
+
+impl std::fmt::Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Verify => "verify",
+            Self::Fsck => "fsck",
+            Self::Sksa => "sksa",
+            Self::Listing => "listing",
+        })
+    }
+}
+
+// the '[acceptance]' section of the config file, for each station to set
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AcceptancePolicy {
+    // the checks to run; they always run in the order of CHECKS
+    pub checks: Vec<Check>,
+    // blocks that may differ from the image before 'verify' fails
+    pub max_mismatches: usize,
+    // whether files on the card that the listing doesn't have fail 'listing'
+    pub extra_files_ok: bool,
+    // where reports are saved (the image directory if not set)
+    pub report_dir: Option<String>,
+}
+
+impl Default for AcceptancePolicy {
+    fn default() -> Self {
+        Self {
+            checks: CHECKS.to_vec(),
+            max_mismatches: 0,
+            extra_files_ok: false,
+            report_dir: None,
+        }
+    }
+}
+
+// what a check found, before the policy's thresholds are applied: None if it wasn't run, an
+// error if it couldn't be done
+pub type Found<T> = Option<Result<T, String>>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Listings {
+    pub expected: Vec<(String, u32)>,
+    pub found: Vec<(String, u32)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Findings {
+    // blocks that didn't match the image
+    pub verify: Found<usize>,
+    pub fsck: Found<Vec<String>>,
+    // the SHA-256 of the image's SKSA blocks, and of the console's
+    pub sksa: Found<(String, String)>,
+    pub listing: Found<Listings>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Pass,
+    Fail,
+    // the check couldn't be done, which fails the console as surely as a failed check
+    Error,
+    Skipped,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub check: Check,
+    pub outcome: Outcome,
+    pub detail: String,
+}
+
+// how the listings differ: files missing, files with the wrong size, and (unless they're allowed)
+// files the card shouldn't have
+pub fn listing_problems(listings: &Listings, extra_files_ok: bool) -> Vec<String> {
+    let mut problems = vec![];
+    for (name, size) in &listings.expected {
+        match listings.found.iter().find(|(n, _)| n == name) {
+            None => problems.push(format!("{name} is missing")),
+            Some((_, s)) if s != size => problems.push(format!("{name} has {s} bytes, not {size}")),
+            Some(_) => {}
+        }
+    }
+    if !extra_files_ok {
+        for (name, _) in &listings.found {
+            if !listings.expected.iter().any(|(n, _)| n == name) {
+                problems.push(format!("{name} shouldn't be there"));
+            }
+        }
+    }
+    problems
+}
+
+fn judge<T>(
+    check: Check,
+    found: &Found<T>,
+    problems: impl FnOnce(&T) -> Result<String, String>,
+) -> CheckResult {
+    let (outcome, detail) = match found {
+        None => (Outcome::Skipped, String::new()),
+        Some(Err(e)) => (Outcome::Error, e.clone()),
+        Some(Ok(t)) => match problems(t) {
+            Ok(detail) => (Outcome::Pass, detail),
+            Err(detail) => (Outcome::Fail, detail),
+        },
+    };
+    CheckResult {
+        check,
+        outcome,
+        detail,
+    }
+}
+
+// each check's outcome under the policy, in the order of CHECKS
+pub fn evaluate(policy: &AcceptancePolicy, findings: &Findings) -> Vec<CheckResult> {
+    vec![
+        judge(Check::Verify, &findings.verify, |&mismatches| {
+            match mismatches <= policy.max_mismatches {
+                true => Ok(format!("{mismatches} blocks differ from the image")),
+                false => Err(format!(
+                    "{mismatches} blocks differ from the image (at most {} may)",
+                    policy.max_mismatches
+                )),
+            }
+        }),
+        judge(Check::Fsck, &findings.fsck, |problems| {
+            match problems.is_empty() {
+                true => Ok("the FS is consistent".to_string()),
+                false => Err(problems.join("; ")),
+            }
+        }),
+        judge(Check::Sksa, &findings.sksa, |(image, console)| {
+            match image == console {
+                true => Ok(format!("SHA-256 {console}")),
+                false => Err(format!("SHA-256 {console}, but the image's is {image}")),
+            }
+        }),
+        judge(Check::Listing, &findings.listing, |listings| {
+            let problems = listing_problems(listings, policy.extra_files_ok);
+            match problems.is_empty() {
+                true => Ok(format!("{} files as expected", listings.expected.len())),
+                false => Err(problems.join("; ")),
+            }
+        }),
+    ]
+}
+
+// the console passes if every check that ran passed, and at least one ran
+pub fn verdict(results: &[CheckResult]) -> bool {
+    results
+        .iter()
+        .all(|r| matches!(r.outcome, Outcome::Pass | Outcome::Skipped))
+        && results.iter().any(|r| r.outcome == Outcome::Pass)
+}
+
+// a listing in the porcelain form of '5': a name and a size in bytes, separated by a tab
+pub fn parse_listing(text: &str) -> Result<Vec<(String, u32)>> {
+    text.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| {
+            let (name, size) = l
+                .split_once('\t')
+                .ok_or_else(|| anyhow!("'{l}' isn't a name and a size separated by a tab"))?;
+            Ok((name.to_string(), size.trim().parse()?))
+        })
+        .collect()
+}
+
+// the newest FS in the image's FS region
+fn image_fs(nand: &[u8]) -> Result<FsBlock> {
+    let blocks = nand.len() / BLOCK_SIZE;
+    nand[blocks.saturating_sub(FS_REGION_BLOCKS) * BLOCK_SIZE..blocks * BLOCK_SIZE]
+        .chunks_exact(BLOCK_SIZE)
+        .filter_map(|data| FsBlock::parse(data).ok())
+        .max_by_key(|fs| fs.seqno)
+        .ok_or_else(|| anyhow!("the image has no valid FS blocks"))
+}
+
+fn files(fs: &FsBlock) -> Vec<(String, u32)> {
+    fs.entries
+        .iter()
+        .map(|e| (e.name.clone(), e.size))
+        .collect()
+}
+
+// runs the policy's checks against the image in `dir`, reading what each needs from the console
+// without writing anything; cancellable between blocks
+pub fn gather(
+    player: &dyn Player,
+    dir: &str,
+    policy: &AcceptancePolicy,
+    events: bool,
+    cancel: &CancelToken,
+) -> Result<Findings> {
+    let path = Path::new(dir).join(IMAGE_FILE);
+    let nand = read(&path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+    if nand.is_empty() || nand.len() % BLOCK_SIZE != 0 {
+        bail!("{} isn't a whole number of blocks", path.display());
+    }
+    let runs = |check| policy.checks.contains(&check);
+    let mut findings = Findings::default();
+
+    if runs(Check::Verify) {
+        let mut summary = RangeSummary::default();
+        let range = 0..(nand.len() / BLOCK_SIZE) as u16;
+        let result = verify_ranges(player, &nand, &[range], events, &mut summary, cancel);
+        cancel.check()?;
+        findings.verify = Some(
+            result
+                .map(|_| summary.total_mismatches())
+                .map_err(|e| e.to_string()),
+        );
+    }
+
+    if runs(Check::Fsck) {
+        let fs = player
+            .DumpCurrentFS()
+            .and_then(|data| Ok(FsBlock::parse(&data)?));
+        findings.fsck = Some(fs.map(|fs| fsck(&fs)).map_err(|e| e.to_string()));
+    }
+
+    // the SKSA is read back as 'verify' reads a range, for its hashes
+    if runs(Check::Sksa) {
+        let mut summary = RangeSummary::default();
+        let result = verify_ranges(
+            player,
+            &nand,
+            &std::iter::once(0..SKSA_BLOCKS).collect::<Vec<_>>(),
+            events,
+            &mut summary,
+            cancel,
+        );
+        cancel.check()?;
+        findings.sksa = Some(result.map_err(|e| e.to_string()).and_then(|_| {
+            summary
+                .outcomes
+                .first()
+                .and_then(|o| o.hashes.clone())
+                .ok_or_else(|| "the SKSA's blocks weren't hashed".to_string())
+        }));
+    }
+
+    if runs(Check::Listing) {
+        let listing_path = Path::new(dir).join(LISTING_FILE);
+        let expected = match read(&listing_path) {
+            Ok(text) => parse_listing(&String::from_utf8_lossy(&text))
+                .map_err(|e| anyhow!("{}: {e}", listing_path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                image_fs(&nand).map(|fs| files(&fs))
+            }
+            Err(e) => Err(anyhow!("{}: {e}", listing_path.display())),
+        };
+        findings.listing = Some(
+            expected
+                .and_then(|expected| {
+                    Ok(Listings {
+                        expected,
+                        found: player.ListFiles()?,
+                    })
+                })
+                .map_err(|e| e.to_string()),
+        );
+    }
+    Ok(findings)
+}
+
+// the report's file name: the console's BBID and when the check finished
+pub fn report_name(bbid: Option<u32>, finished: &chrono::DateTime<chrono::Local>) -> String {
+    let bbid = bbid.map_or("unknown".to_string(), |b| format!("{b:08X}"));
+    format!(
+        "acceptance-{bbid}-{}.json",
+        finished.format("%Y%m%d-%H%M%S")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acceptance() -> Result<()> {
+        let listings = |expected: &[(&str, u32)], found: &[(&str, u32)]| {
+            let own = |l: &[(&str, u32)]| l.iter().map(|(n, s)| (n.to_string(), *s)).collect();
+            Listings {
+                expected: own(expected),
+                found: own(found),
+            }
+        };
+
+        // findings that give a check the outcome `state`
+        fn found<T>(state: Outcome, pass: T, fail: T) -> Found<T> {
+            match state {
+                Outcome::Pass => Some(Ok(pass)),
+                Outcome::Fail => Some(Ok(fail)),
+                Outcome::Error => Some(Err("the console stopped answering".to_string())),
+                Outcome::Skipped => None,
+            }
+        }
+
+        // every check in each of the states it can end up in, in every combination
+        let states = [
+            Outcome::Pass,
+            Outcome::Fail,
+            Outcome::Error,
+            Outcome::Skipped,
+        ];
+        let policy = AcceptancePolicy::default();
+        for n in 0..states.len().pow(CHECKS.len() as u32) {
+            let wanted = (0..CHECKS.len())
+                .map(|i| states[n / states.len().pow(i as u32) % states.len()])
+                .collect::<Vec<_>>();
+            let findings = Findings {
+                verify: found(wanted[0], 0, 3),
+                fsck: found(
+                    wanted[1],
+                    vec![],
+                    vec!["block 0x50 belongs to both a.app and b.app".to_string()],
+                ),
+                sksa: found(
+                    wanted[2],
+                    ("ab".to_string(), "ab".to_string()),
+                    ("ab".to_string(), "cd".to_string()),
+                ),
+                listing: found(
+                    wanted[3],
+                    listings(&[("a.app", 0x4000)], &[("a.app", 0x4000)]),
+                    listings(&[("a.app", 0x4000)], &[]),
+                ),
+            };
+            let results = evaluate(&policy, &findings);
+            let outcomes = results.iter().map(|r| r.outcome).collect::<Vec<_>>();
+            if outcomes != wanted || results.iter().map(|r| r.check).ne(CHECKS) {
+                bail!("checks meant to end {wanted:?} were judged {results:?}");
+            }
+            let passes = wanted
+                .iter()
+                .all(|s| matches!(s, Outcome::Pass | Outcome::Skipped))
+                && wanted.contains(&Outcome::Pass);
+            if verdict(&results) != passes {
+                bail!(
+                    "checks that ended {wanted:?} gave the verdict {}",
+                    verdict(&results)
+                );
+            }
+        }
+
+        // the thresholds
+        let lenient = AcceptancePolicy {
+            max_mismatches: 2,
+            extra_files_ok: true,
+            ..AcceptancePolicy::default()
+        };
+        let findings = |mismatches, listing| Findings {
+            verify: Some(Ok(mismatches)),
+            listing: Some(Ok(listing)),
+            ..Findings::default()
+        };
+        let extra = listings(&[("a.app", 1)], &[("a.app", 1), ("0000ffff.sta", 0x4000)]);
+        if !verdict(&evaluate(&lenient, &findings(2, extra.clone()))) {
+            bail!("mismatches and extra files within the policy's thresholds failed the console");
+        }
+        if verdict(&evaluate(&lenient, &findings(3, extra.clone())))
+            || verdict(&evaluate(&policy, &findings(0, extra.clone())))
+        {
+            bail!("mismatches or extra files beyond the policy's thresholds passed the console");
+        }
+        let wrong_size = listings(&[("a.app", 1)], &[("a.app", 2)]);
+        if listing_problems(&wrong_size, true) != ["a.app has 2 bytes, not 1"] {
+            bail!(
+                "a file of the wrong size gave {:?}",
+                listing_problems(&wrong_size, true)
+            );
+        }
+
+        // nothing run proves nothing
+        if verdict(&evaluate(&policy, &Findings::default())) {
+            bail!("a console with no checks run passed");
+        }
+
+        if parse_listing("a.app\t16384\nticket.sys\t16384\n")?
+            != [
+                ("a.app".to_string(), 0x4000),
+                ("ticket.sys".to_string(), 0x4000),
+            ]
+            || parse_listing("a.app 16384").is_ok()
+        {
+            bail!("listings in the porcelain form weren't parsed as they should be");
+        }
+        Ok(())
+    }
+}
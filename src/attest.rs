@@ -0,0 +1,298 @@
+use std::fs::read;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::hashing::{hmac, HashAlgo, HashValue};
+use crate::{PROG_NAME, PROG_VER};
+
+// Attested read-only sessions, for evidence gathering: after 'session read-only-attest' the
+// console is sealed (see player::ReadOnly), so nothing that writes to it can be handed it, and
+// only the commands in `permits` run at all. Every command typed from then on, run or refused, is
+// logged, each entry hashed with the one before it so none can be changed, dropped or reordered
+// unnoticed. 'attest report <file>' saves the log with a statement of what was guaranteed, signed
+// (HMAC-SHA256) with the key in the file 'attest_key' in the config file names, if it's set.
+
+// commands that only read from the console (or don't reach it at all)
+pub fn permits(command: &[&str]) -> bool {
+    match command[0] {
+        "" | "h" | "?" | "l" | "device" | "I" | "L" | "5" | "F" | "X" | "C" | "1" | "3" | "cat"
+        | "stat" | "hash" | "stats" | "triage" | "history" | "status" | "verify" | "spotcheck"
+        | "scrub" | "fingerprint" | "export" | "dumpinfo" | "calc" | "lint" | "convert"
+        | "fsdiff" | "genimage" | "preflight" | "note" | "report" | "set" | "caps" | "selftest"
+        | "attest" | "q" => true,
+        "dupes" => !command.contains(&"--interactive"),
+        "backup" => command.get(1) == Some(&"incremental") || command.get(1) == Some(&"list"),
+        "session" => command.get(1) == Some(&"save"),
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Ran,
+    Refused,
+}
+
+impl Outcome {
+    fn name(self) -> &'static str {
+        match self {
+            Outcome::Ran => "ran",
+            Outcome::Refused => "refused",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub time: String,
+    pub line: String,
+    pub outcome: Outcome,
+    // over this entry and the previous one's hash (or, for the first, the session's start)
+    pub hash: HashValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attestation {
+    pub bbid: Option<u32>,
+    pub started: String,
+    pub entries: Vec<Entry>,
+}
+
+// what the first entry is chained to
+fn genesis(bbid: Option<u32>, started: &str) -> HashValue {
+    let console = bbid.map_or("unknown".to_string(), |b| format!("{b:08X}"));
+    HashAlgo::Sha256
+        .digest(format!("{PROG_NAME} attested session\t{console}\t{started}").as_bytes())
+}
+
+fn link(prev: &HashValue, seq: usize, time: &str, line: &str, outcome: Outcome) -> HashValue {
+    let text = format!("{}\n{seq}\t{time}\t{}\t{line}", prev.hex(), outcome.name());
+    HashAlgo::Sha256.digest(text.as_bytes())
+}
+
+impl Attestation {
+    pub fn start(bbid: Option<u32>, started: String) -> Self {
+        Self {
+            bbid,
+            started,
+            entries: vec![],
+        }
+    }
+
+    fn head(&self) -> HashValue {
+        self.entries
+            .last()
+            .map_or_else(|| genesis(self.bbid, &self.started), |e| e.hash.clone())
+    }
+
+    pub fn record(&mut self, time: String, line: &str, outcome: Outcome) {
+        let hash = link(&self.head(), self.entries.len() + 1, &time, line, outcome);
+        self.entries.push(Entry {
+            time,
+            line: line.to_string(),
+            outcome,
+            hash,
+        });
+    }
+
+    // logs `line`, and refuses it if it could write to the console; done before anything else
+    // about the command, so a refused one never gets near it
+    pub fn admit(&mut self, time: String, line: &str) -> Result<()> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        let command = line.split(' ').collect::<Vec<_>>();
+        if !permits(&command) {
+            self.record(time, line, Outcome::Refused);
+            bail!(
+                "'{}' isn't allowed in an attested session, which can only read from the console",
+                command[0]
+            );
+        }
+        self.record(time, line, Outcome::Ran);
+        Ok(())
+    }
+
+    // checks every entry's hash against the chain, naming the first that doesn't fit
+    pub fn verify(&self) -> Result<()> {
+        let mut prev = genesis(self.bbid, &self.started);
+        for (i, e) in self.entries.iter().enumerate() {
+            if link(&prev, i + 1, &e.time, &e.line, e.outcome) != e.hash {
+                bail!("Entry {} of the attested log doesn't match its hash", i + 1);
+            }
+            prev = e.hash.clone();
+        }
+        Ok(())
+    }
+}
+
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> HashValue {
+    hmac(HashAlgo::Sha256, key, message)
+}
+
+// the key named by 'attest_key', if it's set, with its path for the report
+pub fn load_key(path: Option<&str>) -> Result<Option<(&str, Vec<u8>)>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let key = read(path).map_err(|e| anyhow!("attest_key {path}: {e}"))?;
+    if key.is_empty() {
+        bail!("attest_key {path} is empty, so it can't sign anything");
+    }
+    Ok(Some((path, key)))
+}
+
+// the log, what it shows, and the signature over both; `now` is when it's reported
+pub fn report(attestation: &Attestation, now: &str, key: Option<(&str, &[u8])>) -> Result<String> {
+    attestation.verify()?;
+    let console = attestation
+        .bbid
+        .map_or("unknown BBID".to_string(), |b| format!("{b:08X}"));
+    let mut out = format!(
+        "{PROG_NAME} v{PROG_VER} attested read-only session\n\
+         Console:  {console}\n\
+         Started:  {}\n\
+         Reported: {now}\n\n",
+        attestation.started
+    );
+    for (i, e) in attestation.entries.iter().enumerate() {
+        out += &format!(
+            "{:>4}  {}  {:<7}  {}\n",
+            i + 1,
+            e.time,
+            e.outcome.name(),
+            e.line
+        );
+    }
+    let refused = attestation
+        .entries
+        .iter()
+        .filter(|e| e.outcome == Outcome::Refused)
+        .count();
+    out += &format!(
+        "\n{} commands, {refused} of them refused. Each entry is hashed (SHA-256) with the one \
+         before it, the first with the session's start; the last hash is {}.\n\n",
+        attestation.entries.len(),
+        attestation.head().hex()
+    );
+    out += "From the start of this session the console was held sealed read-only, by a wrapper \
+            that has only the console's read calls and no way back to the handle that writes, so \
+            no code that writes to the console could be reached with it. Commands that could \
+            write were refused before they were run, as marked above.\n\n";
+    out += &match key {
+        Some((path, key)) => format!(
+            "Signature (HMAC-SHA256 of everything above, with the key in {path}): {}\n",
+            hmac_sha256(key, out.as_bytes()).hex()
+        ),
+        None => "Unsigned: no 'attest_key' is set in the config file\n".to_string(),
+    };
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attestation() -> Result<()> {
+        use crate::help::{Feature, Help, HELP};
+
+        // every command only a writing build has is refused, as are those that change the console
+        // other than through its card, or would swap it for another console or a dump
+        let gated = HELP.iter().filter_map(|item| match item {
+            Help::Gated(Feature::Writing, usage, _) => Some(*usage),
+            _ => None,
+        });
+        let others = [
+            "Y 0 nand.bin spare.bin",
+            "J",
+            "H 2",
+            "H --during 1",
+            "B",
+            "Q",
+            "s 0",
+            "survey",
+            "reset-usb",
+            "retry-usb",
+            "finish",
+            "mount nand.bin spare.bin",
+            "unmount",
+            "commit",
+            "session load s.json",
+            "dupes --interactive",
+            "backup restore dir",
+            "acceptance imagedir",
+        ];
+        let mut attestation =
+            Attestation::start(Some(0x1234ABCD), "2024-05-01T10:00:00+08:00".into());
+        for line in gated.chain(others) {
+            if attestation.admit("t".into(), line).is_ok() {
+                bail!("'{line}' was allowed in an attested session");
+            }
+            if attestation
+                .entries
+                .last()
+                .map(|e| (e.line.as_str(), e.outcome))
+                != Some((line, Outcome::Refused))
+            {
+                bail!("'{line}' wasn't logged as refused");
+            }
+        }
+        for line in [
+            "I",
+            "1 nand.bin spare.bin",
+            "3 save.rec",
+            "verify",
+            "attest report r.txt",
+        ] {
+            attestation.admit("t".into(), line)?;
+        }
+        attestation.admit("t".into(), "")?;
+        if attestation.entries.last().map(|e| e.line.as_str()) != Some("attest report r.txt") {
+            bail!("the attested log was {:?}", attestation.entries.last());
+        }
+
+        // the chain holds, and breaks at any change to an entry, or their order
+        attestation.verify()?;
+        let mut edited = attestation.clone();
+        edited.entries[3].outcome = Outcome::Ran;
+        let mut reordered = attestation.clone();
+        reordered.entries.swap(1, 2);
+        let mut dropped = attestation.clone();
+        dropped.entries.remove(0);
+        let mut moved = attestation.clone();
+        moved.bbid = Some(0x1234ABCE);
+        for (what, log) in [
+            ("edited", edited),
+            ("reordered", reordered),
+            ("dropped", dropped),
+            ("moved", moved),
+        ] {
+            if log.verify().is_ok() || report(&log, "now", None).is_ok() {
+                bail!("the {what} log was accepted");
+            }
+        }
+
+        // RFC 4231's second case
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        if mac.hex() != "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843" {
+            bail!("HMAC-SHA256 gave {}", mac.hex());
+        }
+
+        let unsigned = report(&attestation, "now", None)?;
+        let signed = report(&attestation, "now", Some(("key", b"secret")))?;
+        let (body, signature) = signed
+            .rsplit_once("Signature")
+            .ok_or_else(|| anyhow!("the signed report was\n{signed}"))?;
+        if !unsigned.starts_with(body)
+            || !unsigned.contains("refused  Y 0 nand.bin spare.bin")
+            || !signature.ends_with(&format!(
+                "{}\n",
+                hmac_sha256(b"secret", body.as_bytes()).hex()
+            ))
+        {
+            bail!("the reports were\n{unsigned}\n{signed}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,474 @@
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::fs::{read, read_dir, read_to_string, remove_file};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::cancel::CancelToken;
+use crate::fs::{BLOCK_SIZE, SPARE_SIZE};
+use crate::nand_read::dump_nand;
+use crate::player::Player;
+use crate::sink::write_atomic;
+
+// An incremental backup is a directory of generations, each of them:
+//
+//   gen-NNNN.json        its index: every block's hash, its parent generation, and which blocks
+//                        its own file holds
+//   gen-NNNN.blocks      (or gen-NNNN.base.blocks, once pruning has made it a base) the blocks it
+//                        holds, each as the block's data followed by its spare data
+//
+// The first generation is a base, holding every block; each after it holds only the blocks
+// that differ from its parent's, and the rest are found by walking back along the parents. A
+// block's hash covers its spare data too, so a block newly marked bad counts as changed.
+
+const INDEX_SCHEMA: u32 = 1;
+const RECORD_SIZE: usize = BLOCK_SIZE + SPARE_SIZE;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Generation {
+    pub schema: u32,
+    pub generation: u32,
+    // None for a base, which holds every block itself
+    pub parent: Option<u32>,
+    pub created: String,
+    pub bbid: Option<u32>,
+    // SHA-256 of each block's data and spare data, in block order
+    pub hashes: Vec<String>,
+    // the blocks in this generation's file, in the order they're stored
+    pub stored: Vec<u16>,
+    pub file: String,
+}
+
+// where a block of a generation is to be found: the generation whose file holds it, and its
+// place in that file
+pub type Source = (u32, usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrunePlan {
+    // generations to delete, oldest first
+    pub remove: Vec<u32>,
+    // the oldest generation kept, if it has to be made a base first, with where each of its
+    // blocks is now
+    pub rebase: Option<(u32, Vec<Source>)>,
+}
+
+fn block_hash(data: &[u8], spare: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.update(spare);
+    format!("{:x}", hasher.finalize())
+}
+
+fn index_name(generation: u32) -> String {
+    format!("gen-{generation:04}.json")
+}
+
+// the blocks whose hashes differ from the previous generation's; all of them if there's no
+// previous generation, or the card has changed size
+pub fn changed_blocks(previous: Option<&[String]>, hashes: &[String]) -> Vec<u16> {
+    match previous {
+        Some(prev) if prev.len() == hashes.len() => (0..hashes.len())
+            .filter(|&b| prev[b] != hashes[b])
+            .map(|b| b as u16)
+            .collect(),
+        _ => (0..hashes.len() as u16).collect(),
+    }
+}
+
+// where each block of `target` is stored, walking back along its parents to a base
+pub fn resolve(generations: &BTreeMap<u32, Generation>, target: u32) -> Result<Vec<Source>> {
+    let get = |g: u32| {
+        generations
+            .get(&g)
+            .ok_or_else(|| anyhow!("generation {g} is missing"))
+    };
+    let num_blocks = get(target)?.hashes.len();
+    let mut sources: Vec<Option<Source>> = vec![None; num_blocks];
+    let mut left = num_blocks;
+    let mut visited = vec![];
+    let mut current = Some(target);
+    while let Some(g) = current {
+        if visited.contains(&g) {
+            bail!("generation {g}'s parents loop back to it");
+        }
+        visited.push(g);
+        let generation = get(g)?;
+        if generation.hashes.len() != num_blocks {
+            bail!(
+                "generation {g} has {:#X} blocks, but generation {target} has {num_blocks:#X}",
+                generation.hashes.len()
+            );
+        }
+        for (slot, &blk) in generation.stored.iter().enumerate() {
+            let Some(source) = sources.get_mut(blk as usize) else {
+                bail!("generation {g} stores block {blk:#X}, past the end of the card");
+            };
+            // the newest copy wins
+            if source.is_none() {
+                *source = Some((g, slot));
+                left -= 1;
+            }
+        }
+        current = generation.parent;
+    }
+    if left > 0 {
+        let missing = sources.iter().position(Option::is_none).unwrap_or_default();
+        bail!(
+            "{left} blocks of generation {target} (the first {missing:#X}) aren't stored in it or any generation before it"
+        );
+    }
+    Ok(sources.into_iter().flatten().collect())
+}
+
+// keeps the newest `keep` generations; the oldest of them is made a base (if it isn't one) so
+// nothing kept depends on what's removed
+pub fn plan_prune(generations: &BTreeMap<u32, Generation>, keep: usize) -> Result<PrunePlan> {
+    if keep == 0 {
+        bail!("at least one generation has to be kept");
+    }
+    let all = generations.keys().copied().collect::<Vec<_>>();
+    if all.len() <= keep {
+        return Ok(PrunePlan {
+            remove: vec![],
+            rebase: None,
+        });
+    }
+    let (remove, kept) = all.split_at(all.len() - keep);
+    // every kept generation must resolve before anything is touched
+    for &g in kept {
+        resolve(generations, g)?;
+    }
+    let oldest = kept[0];
+    for &g in &kept[1..] {
+        if let Some(p) = generations[&g].parent.filter(|p| remove.contains(p)) {
+            bail!("generation {g} is built on generation {p}, which would be removed");
+        }
+    }
+    let rebase = match generations[&oldest].parent {
+        None => None,
+        Some(_) => Some((oldest, resolve(generations, oldest)?)),
+    };
+    Ok(PrunePlan {
+        remove: remove.to_vec(),
+        rebase,
+    })
+}
+
+pub fn load_generations(dir: &Path) -> Result<BTreeMap<u32, Generation>> {
+    let mut generations = BTreeMap::new();
+    for entry in read_dir(dir).map_err(|e| anyhow!("{}: {e}", dir.display()))? {
+        let name = entry?.file_name();
+        let Some(number) = name
+            .to_str()
+            .and_then(|n| n.strip_prefix("gen-")?.strip_suffix(".json"))
+            .and_then(|n| n.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let path = dir.join(&name);
+        let generation: Generation = serde_json::from_str(&read_to_string(&path)?)
+            .map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        if generation.schema != INDEX_SCHEMA {
+            bail!(
+                "{}: unsupported index version {}",
+                path.display(),
+                generation.schema
+            );
+        }
+        if generation.generation != number {
+            bail!(
+                "{} is the index of generation {}",
+                path.display(),
+                generation.generation
+            );
+        }
+        generations.insert(number, generation);
+    }
+    Ok(generations)
+}
+
+// reads the blocks at `sources`, checking each against the hash it should have
+fn read_sources(
+    dir: &Path,
+    generations: &BTreeMap<u32, Generation>,
+    sources: &[Source],
+    hashes: &[String],
+) -> Result<Vec<u8>> {
+    let mut files = BTreeMap::new();
+    for &(g, _) in sources {
+        if let Entry::Vacant(e) = files.entry(g) {
+            let path = dir.join(&generations[&g].file);
+            e.insert(read(&path).map_err(|e| anyhow!("{}: {e}", path.display()))?);
+        }
+    }
+    let mut records = Vec::with_capacity(sources.len() * RECORD_SIZE);
+    for (blk, &(g, slot)) in sources.iter().enumerate() {
+        let record = files[&g]
+            .get(slot * RECORD_SIZE..(slot + 1) * RECORD_SIZE)
+            .ok_or_else(|| anyhow!("{} is cut short", generations[&g].file))?;
+        let (data, spare) = record.split_at(BLOCK_SIZE);
+        if block_hash(data, spare) != hashes[blk] {
+            bail!(
+                "block {blk:#X} (in {}) doesn't match its recorded hash",
+                generations[&g].file
+            );
+        }
+        records.extend_from_slice(record);
+    }
+    Ok(records)
+}
+
+// reads the whole card and stores what's changed since the newest generation as a new one;
+// returns the new generation and how many blocks it stores
+pub fn backup_incremental(
+    player: &dyn Player,
+    dir: &str,
+    events: bool,
+    cancel: &CancelToken,
+) -> Result<(u32, usize)> {
+    let dir = Path::new(dir);
+    std::fs::create_dir_all(dir)?;
+    let generations = load_generations(dir)?;
+    let previous = generations.values().next_back();
+    let bbid = player.GetBBID().ok();
+    if let Some(prev) = previous {
+        if prev.bbid.is_some() && bbid.is_some() && prev.bbid != bbid {
+            bail!(
+                "{} holds backups of console {:08X}, not this one ({:08X})",
+                dir.display(),
+                prev.bbid.unwrap_or_default(),
+                bbid.unwrap_or_default()
+            );
+        }
+    }
+
+    let (nand, spare) = dump_nand(player, events, false, cancel)?;
+    let hashes = nand
+        .chunks(BLOCK_SIZE)
+        .zip(spare.chunks(SPARE_SIZE))
+        .map(|(d, s)| block_hash(d, s))
+        .collect::<Vec<_>>();
+    let stored = changed_blocks(previous.map(|p| &p.hashes[..]), &hashes);
+    let base = previous.is_none_or(|p| p.hashes.len() != hashes.len());
+
+    let number = previous.map_or(1, |p| p.generation + 1);
+    let mut records = Vec::with_capacity(stored.len() * RECORD_SIZE);
+    for &blk in &stored {
+        let blk = blk as usize;
+        records.extend_from_slice(&nand[blk * BLOCK_SIZE..(blk + 1) * BLOCK_SIZE]);
+        records.extend_from_slice(&spare[blk * SPARE_SIZE..(blk + 1) * SPARE_SIZE]);
+    }
+    let generation = Generation {
+        schema: INDEX_SCHEMA,
+        generation: number,
+        parent: if base {
+            None
+        } else {
+            previous.map(|p| p.generation)
+        },
+        created: Local::now().to_rfc3339(),
+        bbid,
+        hashes,
+        stored,
+        file: format!("gen-{number:04}.blocks"),
+    };
+    // the index last, so a generation is only seen once its blocks are all there
+    write_atomic(dir.join(&generation.file), &records)?;
+    write_atomic(
+        dir.join(index_name(number)),
+        &serde_json::to_vec_pretty(&generation)?,
+    )?;
+    Ok((number, generation.stored.len()))
+}
+
+// rebuilds a generation (the newest, if not given) as flat NAND and spare files
+pub fn restore(dir: &str, generation: Option<u32>, nand_out: &str, spare_out: &str) -> Result<u32> {
+    let dir = Path::new(dir);
+    let generations = load_generations(dir)?;
+    let target = match generation {
+        Some(g) => g,
+        None => *generations
+            .keys()
+            .next_back()
+            .ok_or_else(|| anyhow!("{} holds no backups", dir.display()))?,
+    };
+    let sources = resolve(&generations, target)?;
+    let records = read_sources(dir, &generations, &sources, &generations[&target].hashes)?;
+    let (mut nand, mut spare) = (vec![], vec![]);
+    for record in records.chunks(RECORD_SIZE) {
+        nand.extend_from_slice(&record[..BLOCK_SIZE]);
+        spare.extend_from_slice(&record[BLOCK_SIZE..]);
+    }
+    write_atomic(nand_out, &nand)?;
+    write_atomic(spare_out, &spare)?;
+    Ok(target)
+}
+
+// removes all but the newest `keep` generations; returns those removed
+pub fn prune(dir: &str, keep: usize) -> Result<Vec<u32>> {
+    let dir = Path::new(dir);
+    let mut generations = load_generations(dir)?;
+    let plan = plan_prune(&generations, keep)?;
+
+    if let Some((g, sources)) = &plan.rebase {
+        let records = read_sources(dir, &generations, sources, &generations[g].hashes)?;
+        let old_file = generations[g].file.clone();
+        let rebased = Generation {
+            parent: None,
+            stored: (0..sources.len() as u16).collect(),
+            file: format!("gen-{g:04}.base.blocks"),
+            ..generations[g].clone()
+        };
+        // under a new name, so the old index and file stay usable until the new index replaces
+        // them
+        write_atomic(dir.join(&rebased.file), &records)?;
+        write_atomic(
+            dir.join(index_name(*g)),
+            &serde_json::to_vec_pretty(&rebased)?,
+        )?;
+        let _ = remove_file(dir.join(old_file));
+        generations.insert(*g, rebased);
+    }
+    // indexes first, so a generation that's half gone is never seen
+    for g in &plan.remove {
+        remove_file(dir.join(index_name(*g)))?;
+        let _ = remove_file(dir.join(&generations[g].file));
+    }
+    Ok(plan.remove)
+}
+
+// the generations, oldest first, for 'backup list'
+pub fn list(dir: &str) -> Result<Vec<Generation>> {
+    Ok(load_generations(Path::new(dir))?.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backup_chains() -> Result<()> {
+        // each "block" is a letter, and its hash the letter itself
+        let card = |s: &str| s.chars().map(String::from).collect::<Vec<_>>();
+        let generation = |number, parent, hashes: &[String], stored: Vec<u16>| Generation {
+            schema: INDEX_SCHEMA,
+            generation: number,
+            parent,
+            created: String::new(),
+            bbid: None,
+            hashes: hashes.to_vec(),
+            stored,
+            file: format!("gen-{number:04}.blocks"),
+        };
+
+        let states = ["abcdef", "abXdef", "abXdeY", "ZbXdeY"].map(card);
+        if changed_blocks(None, &states[0]) != [0, 1, 2, 3, 4, 5]
+            || changed_blocks(Some(&states[0]), &states[1]) != [2]
+            || !changed_blocks(Some(&states[1]), &states[1]).is_empty()
+            || changed_blocks(Some(&states[0]), &card("abc")) != [0, 1, 2]
+        {
+            bail!("the wrong blocks were taken as changed");
+        }
+
+        // a backup store in memory: each generation's file, as the hashes of the blocks in it
+        let mut gens: BTreeMap<u32, Generation> = BTreeMap::new();
+        let mut files: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+        let mut parent = None;
+        for (i, state) in states.iter().enumerate() {
+            let number = i as u32 + 1;
+            let stored = changed_blocks(parent.map(|p: u32| &gens[&p].hashes[..]), state);
+            files.insert(
+                number,
+                stored.iter().map(|&b| state[b as usize].clone()).collect(),
+            );
+            gens.insert(number, generation(number, parent, state, stored));
+            parent = Some(number);
+        }
+        let contents = |gens: &BTreeMap<u32, Generation>, files: &BTreeMap<u32, Vec<String>>, g| {
+            resolve(gens, g).map(|sources| {
+                sources
+                    .iter()
+                    .map(|&(g, slot)| files[&g][slot].clone())
+                    .collect::<Vec<_>>()
+            })
+        };
+        for (i, state) in states.iter().enumerate() {
+            if contents(&gens, &files, i as u32 + 1)? != *state {
+                bail!("generation {} didn't rebuild", i + 1);
+            }
+        }
+        if resolve(&gens, 4)?[1] != (1, 1) || resolve(&gens, 4)?[0] != (4, 0) {
+            bail!("blocks weren't taken from the newest generation holding them");
+        }
+
+        // pruning to each size keeps exactly the newest generations, rebuilding as before
+        for keep in 1..=5 {
+            let (mut gens, mut files) = (gens.clone(), files.clone());
+            let plan = plan_prune(&gens, keep)?;
+            let expected_removed = (1..=4u32)
+                .take(4usize.saturating_sub(keep))
+                .collect::<Vec<_>>();
+            if plan.remove != expected_removed {
+                bail!("keeping {keep} removed {:?}", plan.remove);
+            }
+            if let Some((g, sources)) = plan.rebase {
+                let blocks = sources
+                    .iter()
+                    .map(|&(s, slot)| files[&s][slot].clone())
+                    .collect::<Vec<_>>();
+                let rebased = gens.get_mut(&g).unwrap();
+                rebased.parent = None;
+                rebased.stored = (0..blocks.len() as u16).collect();
+                files.insert(g, blocks);
+            }
+            for g in &plan.remove {
+                gens.remove(g);
+                files.remove(g);
+            }
+            for g in gens.keys().copied().collect::<Vec<_>>() {
+                if contents(&gens, &files, g)? != states[g as usize - 1] {
+                    bail!("generation {g} didn't rebuild after keeping {keep}");
+                }
+            }
+            if keep < 4 && gens.values().next().is_some_and(|g| g.parent.is_some()) {
+                bail!("the oldest generation kept wasn't made a base");
+            }
+        }
+        if plan_prune(&gens, 0).is_ok() {
+            bail!("pruning every generation was allowed");
+        }
+
+        // damage that must be refused rather than rebuilt wrongly
+        let mut broken = gens.clone();
+        broken.remove(&2);
+        if resolve(&broken, 4).is_ok() || plan_prune(&broken, 1).is_ok() {
+            bail!("a generation with a missing parent was used");
+        }
+        let mut broken = gens.clone();
+        broken.get_mut(&1).unwrap().parent = Some(3);
+        broken.get_mut(&1).unwrap().stored.pop();
+        if resolve(&broken, 4).is_ok() {
+            bail!("a loop of parents was followed");
+        }
+        let mut broken = gens.clone();
+        broken.get_mut(&1).unwrap().stored.retain(|&b| b != 4);
+        if resolve(&broken, 3).is_ok() {
+            bail!("a block stored nowhere was rebuilt");
+        }
+        let mut broken = gens.clone();
+        broken.get_mut(&3).unwrap().stored.push(6);
+        if resolve(&broken, 3).is_ok() {
+            bail!("a block past the end of the card was used");
+        }
+        let mut broken = gens;
+        broken.get_mut(&2).unwrap().hashes.push("g".to_string());
+        if resolve(&broken, 4).is_ok() {
+            bail!("generations of different sizes were mixed");
+        }
+        Ok(())
+    }
+}
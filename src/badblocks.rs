@@ -0,0 +1,631 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{create_dir_all, read_to_string};
+use std::io::ErrorKind;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::cancel::CancelToken;
+use crate::config::config_dir;
+use crate::ecc::EccCheck;
+#[cfg(feature = "writing")]
+use crate::fs::{BLOCK_SIZE, SKSA_BLOCKS, SPARE_SIZE};
+use crate::heroic::differing_bits;
+#[cfg(feature = "writing")]
+use crate::player::Backend;
+use crate::player::Player;
+use crate::progress::Progress;
+use crate::ranges::{block_ranges, format_range};
+use crate::sink::write_atomic;
+#[cfg(feature = "writing")]
+use crate::spare::{clear_bad, synthesize_spare};
+use crate::spare::{ecc_check, is_bad_block};
+
+// 'badblocks audit': earlier tools have been known to mark good blocks bad in their spare data,
+// and everything that respects the marker then avoids them for good. The audit reads each marked
+// block carefully (and, when writing is allowed, writes test patterns to it and puts its contents
+// back) and calls its marker confirmed or suspicious. Its findings are kept per console in the
+// config dir, where the user can trust a suspicious block; writes and relocation then treat a
+// trusted block as good. Nothing is trusted without the user saying so, and a block any later
+// audit confirms bad, or that relocation marks bad, stops being trusted.
+
+// reads of each marked block; a marginal block rarely gets through this many the same
+const AUDIT_READS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    ConfirmedBad,
+    Suspicious,
+}
+
+impl Verdict {
+    fn as_str(self) -> &'static str {
+        match self {
+            Verdict::ConfirmedBad => "confirmed",
+            Verdict::Suspicious => "suspicious",
+        }
+    }
+}
+
+// what the reads (and the test cycle, if there was one) of a marked block showed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Evidence {
+    pub reads: usize,
+    pub failed: usize,
+    // bits the successful reads disagreed on, against the first of them
+    pub unstable_bits: u32,
+    // the worst ECC check of the successful reads
+    pub ecc: EccCheck,
+    // whether the test patterns read back as written; None if there was no test cycle
+    pub cycle: Option<bool>,
+}
+
+pub fn gather(reads: &[Result<(Vec<u8>, Vec<u8>)>]) -> Evidence {
+    let good = reads
+        .iter()
+        .filter_map(|r| r.as_ref().ok())
+        .collect::<Vec<_>>();
+    Evidence {
+        reads: reads.len(),
+        failed: reads.len() - good.len(),
+        unstable_bits: good.first().map_or(0, |(data, spare)| {
+            good.iter()
+                .map(|(d, s)| differing_bits(d, data) + differing_bits(s, spare))
+                .sum()
+        }),
+        ecc: good
+            .iter()
+            .map(|(d, s)| ecc_check(d, s))
+            .max()
+            .unwrap_or(EccCheck::Clean),
+        cycle: None,
+    }
+}
+
+// a marker is only suspicious if everything about the block says it's fine; anything odd
+// confirms it, since trusting a bad block puts data on it
+pub fn classify(e: &Evidence) -> (Verdict, String) {
+    use Verdict::*;
+    match e {
+        Evidence { reads: 0, .. } => (ConfirmedBad, "it wasn't read".to_string()),
+        Evidence { failed: 1.., .. } => (
+            ConfirmedBad,
+            format!("{} of {} reads failed", e.failed, e.reads),
+        ),
+        Evidence {
+            unstable_bits: 1.., ..
+        } => (
+            ConfirmedBad,
+            format!("its reads differed in {} bits", e.unstable_bits),
+        ),
+        Evidence {
+            cycle: Some(false), ..
+        } => (
+            ConfirmedBad,
+            "the test patterns didn't read back as written".to_string(),
+        ),
+        // the test cycle rewrote the data, so a stale ECC before it doesn't count against it
+        Evidence {
+            cycle: Some(true), ..
+        } => (
+            Suspicious,
+            format!(
+                "{} reads came back the same, and the test patterns read back as written",
+                e.reads
+            ),
+        ),
+        Evidence {
+            ecc: EccCheck::Correctable | EccCheck::Uncorrectable,
+            ..
+        } => (
+            ConfirmedBad,
+            "its data doesn't match its ECC, and it wasn't write-tested".to_string(),
+        ),
+        Evidence { .. } => (
+            Suspicious,
+            format!(
+                "{} reads came back the same and passed their ECC (not write-tested)",
+                e.reads
+            ),
+        ),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub block: u16,
+    pub verdict: Verdict,
+    pub reason: String,
+}
+
+// a check that writes test patterns to a block and puts back its data and spare data, saying
+// whether the patterns read back as written
+pub type Cycle<'a> = &'a dyn Fn(u16, &[u8], &[u8]) -> Result<bool>;
+
+// reads every block of `ranges` for its spare data, and audits those marked bad
+pub fn audit(
+    player: &dyn Player,
+    ranges: &[Range<u16>],
+    cycle: Option<Cycle>,
+    events: bool,
+    cancel: &CancelToken,
+) -> Result<Vec<Finding>> {
+    let total = ranges.iter().map(|r| r.len() as u64).sum::<u64>();
+    let mut progress = Progress::start("audit", total, crate::fs::BLOCK_SIZE, events);
+    progress.set_message("Looking for blocks marked bad".to_string());
+    let mut findings = vec![];
+    for blk in ranges.iter().flat_map(|r| r.clone()) {
+        if let Err(e) = cancel.check() {
+            progress.fail(&e.to_string());
+            return Err(e);
+        }
+        let first = player.ReadSingleBlock(blk as u32);
+        progress.inc(1);
+        // a block that can't be read at all isn't known to be marked, so there's nothing to audit
+        if !first.as_ref().is_ok_and(|(_, spare)| is_bad_block(spare)) {
+            continue;
+        }
+        let mut reads = vec![first];
+        for _ in 1..AUDIT_READS {
+            reads.push(player.ReadSingleBlock(blk as u32));
+        }
+        let mut evidence = gather(&reads);
+        if let (Some(cycle), Some(Ok((data, spare)))) = (cycle, reads.first()) {
+            // a block the reads already condemn isn't worth writing to
+            if evidence.failed == 0 && evidence.unstable_bits == 0 {
+                evidence.cycle = match cycle(blk, data, spare) {
+                    Ok(took) => Some(took),
+                    Err(e) => {
+                        progress.fail(&e.to_string());
+                        return Err(e);
+                    }
+                };
+            }
+        }
+        let (verdict, reason) = classify(&evidence);
+        progress.println(format!("Block {blk:#X}: {}, {reason}", verdict.as_str()));
+        findings.push(Finding {
+            block: blk,
+            verdict,
+            reason,
+        });
+    }
+    progress.finish();
+    Ok(findings)
+}
+
+// two patterns, each the other's inverse, so every bit of the block is written both ways
+#[cfg(feature = "writing")]
+fn test_patterns(blk: u16) -> [Vec<u8>; 2] {
+    let pattern = (0..BLOCK_SIZE)
+        .map(|i| (i as u8 ^ 0x55).wrapping_add(blk as u8))
+        .collect::<Vec<_>>();
+    let inverse = pattern.iter().map(|b| !b).collect();
+    [pattern, inverse]
+}
+
+// the test cycle: the marker is written with each pattern, so an interruption part way leaves
+// the block marked bad as it was; whatever the patterns do, the block's old data and spare data
+// go back, and it's an error if they don't read back that way
+#[cfg(feature = "writing")]
+pub fn test_cycle(player: &dyn Backend, blk: u16, data: &[u8], spare: &[u8]) -> Result<bool> {
+    let mut took = true;
+    for pattern in test_patterns(blk) {
+        let pattern_spare = synthesize_spare(&pattern, spare, blk < SKSA_BLOCKS)?;
+        took &= player
+            .WriteSingleBlock(blk as u32, &pattern, &pattern_spare)
+            .and_then(|_| player.ReadSingleBlock(blk as u32))
+            .is_ok_and(|(d, s)| d == pattern && ecc_check(&d, &s) == EccCheck::Clean);
+        if !took {
+            break;
+        }
+    }
+    let restored = player
+        .WriteSingleBlock(blk as u32, data, spare)
+        .and_then(|_| player.ReadSingleBlock(blk as u32))
+        .map(|(d, s)| d == data && is_bad_block(&s));
+    match restored {
+        Ok(true) => Ok(took),
+        Ok(false) => bail!("block {blk:#X} didn't read back as it was before its test; it may have lost its bad block marker, so don't write to the card until it's been audited again"),
+        Err(e) => bail!("couldn't put block {blk:#X} back as it was before its test ({e}); it may have lost its bad block marker, so don't write to the card until it's been audited again"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Override {
+    pub verdict: Verdict,
+    // whether the user has said to treat the block as good; only ever set on a suspicious block
+    pub trusted: bool,
+    pub reason: String,
+}
+
+// the audit's findings for one console, and which of its suspicious blocks the user trusts
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Overrides {
+    pub blocks: BTreeMap<u16, Override>,
+}
+
+// <config dir>/bad-block-overrides/<BBID>.txt
+fn overrides_path(bbid: u32) -> Result<PathBuf> {
+    let dir = config_dir()
+        .ok_or_else(|| anyhow!("no config directory to keep bad block overrides in"))?
+        .join("bad-block-overrides");
+    create_dir_all(&dir)?;
+    Ok(dir.join(format!("{bbid:08X}.txt")))
+}
+
+impl Overrides {
+    // a line per block: "<block> <confirmed|suspicious> [trusted] # <reason>"
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut blocks = BTreeMap::new();
+        for line in text.lines() {
+            let (fields, reason) = line.split_once('#').unwrap_or((line, ""));
+            let fields = fields.split_whitespace().collect::<Vec<_>>();
+            let (block, verdict, trusted) = match fields[..] {
+                [] => continue,
+                [block, verdict] => (block, verdict, false),
+                [block, verdict, "trusted"] => (block, verdict, true),
+                _ => bail!("'{line}' isn't '<block> <confirmed|suspicious> [trusted]'"),
+            };
+            let block = parse_int::parse::<u16>(block)
+                .map_err(|_| anyhow!("'{block}' in '{line}' isn't a block number"))?;
+            let verdict = match verdict {
+                "confirmed" => Verdict::ConfirmedBad,
+                "suspicious" => Verdict::Suspicious,
+                _ => bail!("'{verdict}' in '{line}' isn't 'confirmed' or 'suspicious'"),
+            };
+            if trusted && verdict == Verdict::ConfirmedBad {
+                bail!("block {block:#X} is confirmed bad, so it can't be trusted");
+            }
+            blocks.insert(
+                block,
+                Override {
+                    verdict,
+                    trusted,
+                    reason: reason.trim().to_string(),
+                },
+            );
+        }
+        Ok(Self { blocks })
+    }
+
+    pub fn to_text(&self) -> String {
+        self.blocks
+            .iter()
+            .map(|(block, o)| {
+                format!(
+                    "{block:#06X} {}{} # {}\n",
+                    o.verdict.as_str(),
+                    if o.trusted { " trusted" } else { "" },
+                    o.reason
+                )
+            })
+            .collect()
+    }
+
+    pub fn load(bbid: u32) -> Result<Self> {
+        match read_to_string(overrides_path(bbid)?) {
+            Ok(text) => Self::parse(&text),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, bbid: u32) -> Result<()> {
+        write_atomic(overrides_path(bbid)?, self.to_text().as_bytes())
+    }
+
+    // the blocks to treat as good on a console; if its list can't be read, none are, which is
+    // the safe way to be wrong
+    #[cfg(feature = "writing")]
+    pub fn trusted_on(player: &dyn Player) -> BTreeSet<u16> {
+        match player.GetBBID().and_then(Self::load) {
+            Ok(o) => o.trusted(),
+            Err(e) => {
+                eprintln!("Note: couldn't read the console's bad block overrides, so every marked block is treated as bad: {e}");
+                BTreeSet::new()
+            }
+        }
+    }
+
+    pub fn trusted(&self) -> BTreeSet<u16> {
+        self.blocks
+            .iter()
+            .filter(|(_, o)| o.trusted && o.verdict == Verdict::Suspicious)
+            .map(|(&b, _)| b)
+            .collect()
+    }
+
+    // an audit's findings replace what was known of those blocks; a block that's still
+    // suspicious stays trusted if it was, and one that's now confirmed bad stops being trusted
+    pub fn record(&mut self, findings: &[Finding]) {
+        for f in findings {
+            let trusted = f.verdict == Verdict::Suspicious
+                && self.blocks.get(&f.block).is_some_and(|o| o.trusted);
+            self.blocks.insert(
+                f.block,
+                Override {
+                    verdict: f.verdict,
+                    trusted,
+                    reason: f.reason.clone(),
+                },
+            );
+        }
+    }
+
+    // trusts (or stops trusting) blocks; only blocks the audit found suspicious can be trusted
+    pub fn set_trusted(&mut self, blocks: &[u16], trusted: bool) -> Result<()> {
+        for &b in blocks {
+            match self.blocks.get(&b) {
+                None if trusted => {
+                    bail!("block {b:#X} hasn't been audited; run 'badblocks audit' first")
+                }
+                Some(o) if trusted && o.verdict == Verdict::ConfirmedBad => {
+                    bail!(
+                        "the audit confirmed block {b:#X} bad ({}), so it can't be trusted",
+                        o.reason
+                    )
+                }
+                _ => {}
+            }
+        }
+        for b in blocks {
+            if let Some(o) = self.blocks.get_mut(b) {
+                o.trusted = trusted;
+            }
+        }
+        Ok(())
+    }
+
+    // blocks that have been marked bad again on purpose (by relocation) go back to being bad
+    #[cfg(feature = "writing")]
+    pub fn forget(&mut self, blocks: &[u16]) -> bool {
+        let before = self.blocks.len();
+        self.blocks.retain(|b, _| !blocks.contains(b));
+        self.blocks.len() != before
+    }
+
+    // what 'badblocks list' prints
+    pub fn describe(&self) -> Vec<String> {
+        if self.blocks.is_empty() {
+            return vec!["No blocks have been audited on this console".to_string()];
+        }
+        let trusted = self.trusted().into_iter().collect::<Vec<_>>();
+        let mut lines = self
+            .blocks
+            .iter()
+            .map(|(b, o)| {
+                format!(
+                    "{b:#06X}  {:<10} {:<8} {}",
+                    o.verdict.as_str(),
+                    if o.trusted { "trusted" } else { "" },
+                    o.reason
+                )
+            })
+            .collect::<Vec<_>>();
+        lines.push(match trusted.is_empty() {
+            true => "No blocks are trusted; every marked block is treated as bad".to_string(),
+            false => format!(
+                "Treated as good: {}",
+                block_ranges(&trusted)
+                    .iter()
+                    .map(format_range)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        });
+        lines
+    }
+}
+
+// the blocks to treat as bad: those marked, less those the user trusts
+#[cfg(feature = "writing")]
+pub fn effective_bad(marked: &BTreeSet<u16>, trusted: &BTreeSet<u16>) -> BTreeSet<u16> {
+    marked.difference(trusted).copied().collect()
+}
+
+// whether a block whose spare data is `spare` is to be treated as bad
+#[cfg(feature = "writing")]
+pub fn treat_as_bad(blk: u16, spare: &[u8], trusted: &BTreeSet<u16>) -> bool {
+    is_bad_block(spare) && !trusted.contains(&blk)
+}
+
+// spare data for writing `data` to a block outside the SKSA: a block marked bad is refused unless
+// it's trusted, in which case it's written without the marker
+#[cfg(feature = "writing")]
+pub fn spare_for(
+    blk: u16,
+    data: &[u8],
+    existing: &[u8],
+    trusted: &BTreeSet<u16>,
+) -> Result<[u8; SPARE_SIZE]> {
+    if treat_as_bad(blk, existing, trusted) {
+        bail!("block {blk:#X} is marked bad in its spare data, though the FAT has it free; run 'badblocks audit' to check it");
+    }
+    let mut existing = existing.to_vec();
+    clear_bad(&mut existing);
+    synthesize_spare(data, &existing, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_blocks() -> Result<()> {
+        use crate::ecc::page_ecc;
+        use crate::fs::{BLOCK_SIZE, SPARE_SIZE};
+        use crate::spare::mark_bad;
+
+        // a block with data and its ECC, marked bad
+        let data = (0..BLOCK_SIZE).map(|i| (i * 3) as u8).collect::<Vec<_>>();
+        let mut spare = vec![0xFF; SPARE_SIZE];
+        page_ecc(&data, &mut spare)?;
+        mark_bad(&mut spare);
+        let read = || Ok((data.clone(), spare.clone()));
+
+        // the same clean reads every time: suspicious, whether or not a test cycle agreed
+        let steady = gather(&[read(), read(), read(), read()]);
+        if steady.failed != 0 || steady.unstable_bits != 0 || steady.ecc != EccCheck::Clean {
+            bail!("four identical clean reads gave {steady:?}");
+        }
+        for cycle in [None, Some(true)] {
+            let (verdict, reason) = classify(&Evidence {
+                cycle,
+                ..steady.clone()
+            });
+            if verdict != Verdict::Suspicious {
+                bail!("a block that read cleanly (test cycle {cycle:?}) was {verdict:?}: {reason}");
+            }
+        }
+
+        // anything else confirms the marker: a failed read, a bit that changes between reads, a
+        // test pattern that didn't take, or an ECC mismatch that no test cycle explained
+        let mut flipped = data.clone();
+        flipped[0x1000] ^= 4;
+        let mut stale = data.clone();
+        stale[3] ^= 1;
+        let cases = [
+            (
+                "a failed read",
+                gather(&[read(), Err(anyhow!("timed out")), read()]),
+                "1 of 3 reads failed",
+            ),
+            (
+                "a changing bit",
+                gather(&[read(), Ok((flipped, spare.clone())), read()]),
+                "differed in 1 bits",
+            ),
+            (
+                "a failed test cycle",
+                Evidence {
+                    cycle: Some(false),
+                    ..steady.clone()
+                },
+                "test patterns",
+            ),
+            (
+                "a bit off the ECC",
+                gather(&[
+                    Ok((stale.clone(), spare.clone())),
+                    Ok((stale.clone(), spare.clone())),
+                ]),
+                "doesn't match its ECC",
+            ),
+            ("no reads", gather(&[]), "wasn't read"),
+        ];
+        for (what, evidence, expected) in cases {
+            match classify(&evidence) {
+                (Verdict::ConfirmedBad, reason) if reason.contains(expected) => {}
+                other => bail!("{what} gave {other:?} from {evidence:?}"),
+            }
+        }
+        // a failure outranks a passing test cycle, as does a changing bit
+        let failing = Evidence {
+            cycle: Some(true),
+            ..gather(&[read(), Err(anyhow!("timed out"))])
+        };
+        if classify(&failing).0 != Verdict::ConfirmedBad {
+            bail!("a failed read was outweighed by a test cycle");
+        }
+        // but a stale ECC is explained by a test cycle that rewrote the block
+        let rewritten = Evidence {
+            cycle: Some(true),
+            ..gather(&[Ok((stale.clone(), spare.clone()))])
+        };
+        if classify(&rewritten).0 != Verdict::Suspicious {
+            bail!("a stale ECC counted against a block that took its test patterns");
+        }
+
+        // the list: findings are recorded, only suspicious blocks can be trusted, and trust survives
+        // a re-audit that agrees but not one that confirms the marker
+        let finding = |block, verdict| Finding {
+            block,
+            verdict,
+            reason: "because".to_string(),
+        };
+        let mut overrides = Overrides::default();
+        overrides.record(&[
+            finding(0x100, Verdict::Suspicious),
+            finding(0x200, Verdict::ConfirmedBad),
+            finding(0x300, Verdict::Suspicious),
+        ]);
+        overrides.set_trusted(&[0x100, 0x300], true)?;
+        if overrides.set_trusted(&[0x200], true).is_ok()
+            || overrides.set_trusted(&[0x400], true).is_ok()
+        {
+            bail!("a confirmed or unaudited block was trusted");
+        }
+        overrides.record(&[
+            finding(0x100, Verdict::Suspicious),
+            finding(0x300, Verdict::ConfirmedBad),
+        ]);
+        if overrides.trusted() != BTreeSet::from([0x100]) {
+            bail!("after a re-audit, {:X?} were trusted", overrides.trusted());
+        }
+        let text = overrides.to_text();
+        if Overrides::parse(&text)? != overrides
+            || !text.contains("0x0100 suspicious trusted # because\n")
+        {
+            bail!("the list didn't round trip:\n{text}");
+        }
+        if Overrides::parse("0x200 confirmed trusted # no").is_ok()
+            || Overrides::parse("0x200 fine").is_ok()
+            || Overrides::parse("bad suspicious").is_ok()
+        {
+            bail!("a malformed or contradictory list was taken");
+        }
+        // even set directly, trust on a confirmed block counts for nothing
+        let mut forced = overrides.clone();
+        forced.blocks.get_mut(&0x200).unwrap().trusted = true;
+        if forced.trusted().contains(&0x200) {
+            bail!("a confirmed block was trusted");
+        }
+
+        #[cfg(feature = "writing")]
+        {
+            // the effective map: marked blocks less the trusted ones; trusting a block that isn't marked
+            // doesn't make anything bad, and nothing unmarked is ever added
+            let marked = BTreeSet::from([0x100, 0x200, 0x300]);
+            let trusted = BTreeSet::from([0x100, 0x500]);
+            if effective_bad(&marked, &trusted) != BTreeSet::from([0x200, 0x300]) {
+                bail!(
+                    "the effective map was {:X?}",
+                    effective_bad(&marked, &trusted)
+                );
+            }
+            if effective_bad(&marked, &BTreeSet::new()) != marked {
+                bail!("with nothing trusted, the effective map wasn't the marked blocks");
+            }
+            if !treat_as_bad(0x200, &spare, &trusted) || treat_as_bad(0x100, &spare, &trusted) {
+                bail!("the marker wasn't weighed against the trusted blocks");
+            }
+            let mut unmarked = spare.clone();
+            unmarked[5] = 0xFF;
+            if treat_as_bad(0x200, &unmarked, &trusted) {
+                bail!("an unmarked block was treated as bad");
+            }
+            if !overrides.forget(&[0x100]) || !overrides.trusted().is_empty() {
+                bail!("a block relocation marked bad stayed trusted");
+            }
+
+            // a trusted block is written without its marker; an untrusted one isn't written at all
+            let written = spare_for(0x100, &data, &spare, &BTreeSet::from([0x100]))?;
+            if is_bad_block(&written) || ecc_check(&data, &written) != EccCheck::Clean {
+                bail!("a trusted block's spare data was {written:02X?}");
+            }
+            if spare_for(0x200, &data, &spare, &BTreeSet::from([0x100])).is_ok() {
+                bail!("data was placed on a block marked bad and not trusted");
+            }
+            if test_patterns(7)[0]
+                .iter()
+                .zip(&test_patterns(7)[1])
+                .any(|(a, b)| a & b != 0)
+            {
+                bail!("the test patterns don't cover every bit both ways");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+// The state behind 'browse', kept apart from drawing it and from the terminal so it can be driven
+// by a list of keys. It never touches the console: what the keys ask for comes back as a command
+// line, which is run by the same dispatcher as one typed at the prompt.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Esc,
+    Backspace,
+    Char(char),
+}
+
+// keys that move by a page move this many rows
+pub const PAGE: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub name: String,
+    pub size: u32,
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    List,
+    // deleting this file, once confirmed
+    ConfirmDelete(String),
+    // the new name for this file, as typed so far
+    Rename { from: String, to: String },
+    // choosing a local file to upload
+    Pick { files: Vec<String>, selected: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    None,
+    // run this line through the dispatcher, then refresh the list
+    Run(String),
+    // the local files are needed for the upload picker; see `Browser::pick`
+    ListLocal,
+    Quit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Browser {
+    pub rows: Vec<Row>,
+    pub selected: usize,
+    pub mode: Mode,
+    // what the last operation did, or why a key did nothing
+    pub status: String,
+}
+
+// the dispatcher splits lines on spaces, so a name with one can't be passed to it
+fn usable(name: &str) -> bool {
+    !name.is_empty() && !name.contains(' ')
+}
+
+fn moved(selected: usize, len: usize, key: Key) -> usize {
+    let last = len.saturating_sub(1);
+    match key {
+        Key::Up => selected.saturating_sub(1),
+        Key::Down => (selected + 1).min(last),
+        Key::PageUp => selected.saturating_sub(PAGE),
+        Key::PageDown => (selected + PAGE).min(last),
+        Key::Home => 0,
+        Key::End => last,
+        _ => selected,
+    }
+}
+
+impl Browser {
+    pub fn new(rows: Vec<Row>) -> Self {
+        Self {
+            rows,
+            selected: 0,
+            mode: Mode::List,
+            status:
+                "↑↓ move  d download  u upload  x delete  r rename  h hash  Esc back to the prompt"
+                    .to_string(),
+        }
+    }
+
+    pub fn current(&self) -> Option<&Row> {
+        self.rows.get(self.selected)
+    }
+
+    // a fresh list after an operation; the selection stays on the same file if it's still there
+    pub fn refresh(&mut self, rows: Vec<Row>) {
+        let name = self.current().map(|r| r.name.clone());
+        self.selected = name
+            .and_then(|n| rows.iter().position(|r| r.name == n))
+            .unwrap_or(self.selected)
+            .min(rows.len().saturating_sub(1));
+        self.rows = rows;
+    }
+
+    // the local files to choose from, after `Action::ListLocal`
+    pub fn pick(&mut self, mut files: Vec<String>) {
+        files.retain(|f| usable(f));
+        files.sort();
+        if files.is_empty() {
+            self.status = "No files here to upload".to_string();
+            return;
+        }
+        self.mode = Mode::Pick { files, selected: 0 };
+    }
+
+    pub fn handle(&mut self, key: Key) -> Action {
+        match std::mem::replace(&mut self.mode, Mode::List) {
+            Mode::List => self.handle_list(key),
+            Mode::ConfirmDelete(name) => match key {
+                Key::Char('y' | 'Y') => Action::Run(format!("6 {name}")),
+                _ => {
+                    self.status = format!("Kept {name}");
+                    Action::None
+                }
+            },
+            Mode::Rename { from, mut to } => match key {
+                Key::Esc => Action::None,
+                Key::Enter if !usable(&to) => {
+                    self.status = "The new name can't be empty or contain spaces".to_string();
+                    self.mode = Mode::Rename { from, to };
+                    Action::None
+                }
+                Key::Enter if to == from => Action::None,
+                Key::Enter => Action::Run(format!("7 {from} {to}")),
+                other => {
+                    match other {
+                        Key::Backspace => {
+                            to.pop();
+                        }
+                        Key::Char(c) => to.push(c),
+                        _ => {}
+                    }
+                    self.mode = Mode::Rename { from, to };
+                    Action::None
+                }
+            },
+            Mode::Pick { files, selected } => match key {
+                Key::Esc => Action::None,
+                Key::Enter => Action::Run(format!("4 {}", files[selected])),
+                other => {
+                    let selected = moved(selected, files.len(), other);
+                    self.mode = Mode::Pick { files, selected };
+                    Action::None
+                }
+            },
+        }
+    }
+
+    fn handle_list(&mut self, key: Key) -> Action {
+        let name = self.current().map(|r| r.name.clone());
+        match (key, name) {
+            (Key::Esc | Key::Char('q'), _) => Action::Quit,
+            (Key::Char('u'), _) => Action::ListLocal,
+            (Key::Char('d' | 'x' | 'r' | 'h') | Key::Enter, None) => {
+                self.status = "There are no files on the card".to_string();
+                Action::None
+            }
+            (Key::Char(_) | Key::Enter, Some(name)) if !usable(&name) => {
+                self.status = format!("'{name}' has a space in its name, so can't be used here");
+                Action::None
+            }
+            (Key::Char('d') | Key::Enter, Some(name)) => Action::Run(format!("3 {name}")),
+            (Key::Char('h'), Some(name)) => Action::Run(format!("hash {name}")),
+            (Key::Char('x'), Some(name)) => {
+                self.status = format!("Delete {name}? [y/N]");
+                self.mode = Mode::ConfirmDelete(name);
+                Action::None
+            }
+            (Key::Char('r'), Some(name)) => {
+                self.mode = Mode::Rename {
+                    from: name.clone(),
+                    to: name,
+                };
+                Action::None
+            }
+            (other, _) => {
+                self.selected = moved(self.selected, self.rows.len(), other);
+                Action::None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Result};
+
+    #[test]
+    fn file_browser() -> Result<()> {
+        let row = |name: &str, size| Row {
+            name: name.to_string(),
+            size,
+            title: None,
+        };
+        let rows = vec![
+            row("0012d687.app", 0x100000),
+            row("0012d687.sta", 0x4000),
+            row("ticket.sys", 0x8000),
+            row("odd name", 1),
+        ];
+        let run = |keys: &[Key]| {
+            let mut browser = Browser::new(rows.clone());
+            let actions = keys.iter().map(|k| browser.handle(*k)).collect::<Vec<_>>();
+            (browser, actions)
+        };
+        let last = |keys: &[Key]| run(keys).1.pop().unwrap();
+        use Key::*;
+
+        let (browser, _) = run(&[Down, Down, Down, Down, Up]);
+        if browser.selected != 2 {
+            bail!("the selection ended at {}, not 2", browser.selected);
+        }
+        if run(&[PageDown]).0.selected != 3 || run(&[End, PageUp]).0.selected != 0 {
+            bail!("paging went past the ends of the list");
+        }
+        if last(&[Down, Char('d')]) != Action::Run("3 0012d687.sta".to_string())
+            || last(&[Enter]) != Action::Run("3 0012d687.app".to_string())
+            || last(&[Char('h')]) != Action::Run("hash 0012d687.app".to_string())
+        {
+            bail!("downloading or hashing didn't give the CLI's command");
+        }
+
+        // deleting needs a 'y'; anything else keeps the file
+        if last(&[End, Up, Char('x'), Char('y')]) != Action::Run("6 ticket.sys".to_string()) {
+            bail!("a confirmed delete didn't give '6'");
+        }
+        let (browser, actions) = run(&[Char('x'), Char('n'), Char('x'), Esc]);
+        if actions.iter().any(|a| matches!(a, Action::Run(_))) || browser.mode != Mode::List {
+            bail!("an unconfirmed delete went ahead: {actions:?}");
+        }
+
+        let rename = [
+            Char('r'),
+            Backspace,
+            Backspace,
+            Backspace,
+            Char('b'),
+            Char('a'),
+            Char('k'),
+            Enter,
+        ];
+        if last(&rename) != Action::Run("7 0012d687.app 0012d687.bak".to_string()) {
+            bail!("renaming gave {:?}", last(&rename));
+        }
+        let (browser, actions) = run(&[Char('r'), Char(' '), Enter]);
+        if actions[2] != Action::None || !matches!(browser.mode, Mode::Rename { .. }) {
+            bail!("a name with a space was passed on");
+        }
+        if last(&[Char('r'), Enter]) != Action::None
+            || last(&[Char('r'), Char('x'), Esc]) != Action::None
+        {
+            bail!("an unchanged or abandoned rename was run");
+        }
+        if last(&[End, Char('d')]) != Action::None {
+            bail!("a file with a space in its name was passed on");
+        }
+
+        // uploading asks for the local files, then picks from them
+        let mut browser = Browser::new(rows.clone());
+        if browser.handle(Char('u')) != Action::ListLocal {
+            bail!("'u' didn't ask for the local files");
+        }
+        browser.pick(vec!["b.bin".into(), "has space.bin".into(), "a.bin".into()]);
+        if browser.handle(Down) != Action::None
+            || browser.handle(Down) != Action::None
+            || browser.handle(Enter) != Action::Run("4 b.bin".to_string())
+            || browser.mode != Mode::List
+        {
+            bail!("the upload picker didn't give '4 b.bin'");
+        }
+
+        // the selection follows its file through a refresh, and stays in the list when it's gone
+        let mut browser = Browser::new(rows.clone());
+        browser.handle(Down);
+        browser.refresh(vec![row("00000001.app", 1), row("0012d687.sta", 1)]);
+        if browser.selected != 1 {
+            bail!("the selection didn't follow its file");
+        }
+        browser.refresh(vec![row("00000001.app", 1)]);
+        if browser.selected != 0 {
+            bail!("the selection was left past the end of the list");
+        }
+        let mut empty = Browser::new(vec![]);
+        if empty.handle(Char('d')) != Action::None
+            || empty.handle(Down) != Action::None
+            || empty.selected != 0
+        {
+            bail!("an empty list didn't stay put");
+        }
+        if empty.handle(Esc) != Action::Quit {
+            bail!("Esc didn't go back to the prompt");
+        }
+        Ok(())
+    }
+}
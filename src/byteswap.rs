@@ -0,0 +1,96 @@
+use anyhow::{bail, Result};
+
+use crate::fs::{synthetic_block, FsBlock, BLOCK_SIZE, FS_REGION_BLOCKS};
+
+// which way round a NAND image's 16-bit words look to be; some dumpers read the card a word at a
+// time and store each one little-endian
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Normal,
+    Swapped,
+    // valid FS blocks were found both ways round, so it can't be told
+    Ambiguous { normal: usize, swapped: usize },
+    // no valid FS blocks were found either way round (a blank or corrupt FS region)
+    Unknown,
+}
+
+// swaps the bytes of each 16-bit word; an odd trailing byte is left alone
+pub fn swap16(data: &mut [u8]) {
+    for word in data.chunks_exact_mut(2) {
+        word.swap(0, 1);
+    }
+}
+
+// looks for valid FS blocks in the image's FS region as it is and byte-swapped. The FS magic
+// swapped is "BBSF", so no block can be valid both ways round. Block 0 isn't looked at: the SK is
+// encrypted, so there's no plaintext magic there to go by
+pub fn detect_orientation(nand: &[u8]) -> Orientation {
+    let num_blocks = nand.len() / BLOCK_SIZE;
+    let start = num_blocks.saturating_sub(FS_REGION_BLOCKS);
+    let (mut normal, mut swapped) = (0, 0);
+    for block in nand[start * BLOCK_SIZE..num_blocks * BLOCK_SIZE].chunks_exact(BLOCK_SIZE) {
+        if FsBlock::parse(block).is_ok() {
+            normal += 1;
+        }
+        let mut block = block.to_vec();
+        swap16(&mut block);
+        if FsBlock::parse(&block).is_ok() {
+            swapped += 1;
+        }
+    }
+    match (normal, swapped) {
+        (0, 0) => Orientation::Unknown,
+        (_, 0) => Orientation::Normal,
+        (0, _) => Orientation::Swapped,
+        (normal, swapped) => Orientation::Ambiguous { normal, swapped },
+    }
+}
+
+pub fn self_test() -> Result<()> {
+    // an FS region with one valid block in the middle of erased ones
+    let mut nand = vec![0xFF; FS_REGION_BLOCKS * BLOCK_SIZE];
+    nand[3 * BLOCK_SIZE..4 * BLOCK_SIZE].copy_from_slice(&synthetic_block());
+    let original = nand.clone();
+
+    let detected = detect_orientation(&nand);
+    if detected != Orientation::Normal {
+        bail!("a normal image was detected as {detected:?}");
+    }
+    swap16(&mut nand);
+    let detected = detect_orientation(&nand);
+    if detected != Orientation::Swapped {
+        bail!("a byte-swapped image was detected as {detected:?}");
+    }
+    swap16(&mut nand);
+    if nand != original {
+        bail!("swapping twice didn't give back the original");
+    }
+
+    let mut mixed = nand.clone();
+    let mut block = synthetic_block();
+    swap16(&mut block);
+    mixed[7 * BLOCK_SIZE..8 * BLOCK_SIZE].copy_from_slice(&block);
+    let detected = detect_orientation(&mixed);
+    if detected
+        != (Orientation::Ambiguous {
+            normal: 1,
+            swapped: 1,
+        })
+    {
+        bail!("an image with FS blocks both ways round was detected as {detected:?}");
+    }
+
+    let detected = detect_orientation(&vec![0xFF; FS_REGION_BLOCKS * BLOCK_SIZE]);
+    if detected != Orientation::Unknown {
+        bail!("a blank image was detected as {detected:?}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn vectors() -> anyhow::Result<()> {
+        super::self_test()
+    }
+}
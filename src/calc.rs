@@ -0,0 +1,408 @@
+use std::fmt::{self, Display};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::fs::{BLOCK_SIZE, FS_REGION_BLOCKS, SKSA_BLOCKS};
+
+// 'calc': arithmetic on offsets and sizes, in the units the card is laid out in. A quantity is a
+// number (decimal, or hex with 0x) with an optional unit after it ('3 blocks', '16KiB') or before
+// it ('block 500'); quantities combine with + - * / and parentheses, and the result is in bytes
+// unless 'to <unit>' (or 'offset', for bytes) asks for another:
+//
+//   calc 0x1F4000 to blocks
+//   calc block 500 offset
+//   calc (2 MiB - 3 blocks) / 2
+//
+// Every result is printed in each unit, with where on the card it lands, for the card assumed.
+
+pub const PAGE_SIZE: usize = 0x200;
+
+// what's assumed without a console or dump to ask: a retail console's 64 MiB card
+pub const DEFAULT_BLOCKS: u32 = 0x1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    Bytes,
+    Pages,
+    Blocks,
+    KiB,
+    MiB,
+}
+
+impl Unit {
+    pub const ALL: [Unit; 5] = [Unit::Bytes, Unit::Pages, Unit::Blocks, Unit::KiB, Unit::MiB];
+
+    pub fn parse(word: &str) -> Option<Self> {
+        match word.to_ascii_lowercase().as_str() {
+            "b" | "byte" | "bytes" => Some(Self::Bytes),
+            "page" | "pages" => Some(Self::Pages),
+            "blk" | "block" | "blocks" => Some(Self::Blocks),
+            "k" | "kib" | "kb" => Some(Self::KiB),
+            "m" | "mib" | "mb" => Some(Self::MiB),
+            _ => None,
+        }
+    }
+
+    pub fn size(self) -> u64 {
+        match self {
+            Self::Bytes => 1,
+            Self::Pages => PAGE_SIZE as u64,
+            Self::Blocks => BLOCK_SIZE as u64,
+            Self::KiB => 1024,
+            Self::MiB => 1024 * 1024,
+        }
+    }
+}
+
+impl Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Bytes => "bytes",
+            Self::Pages => "pages",
+            Self::Blocks => "blocks",
+            Self::KiB => "KiB",
+            Self::MiB => "MiB",
+        })
+    }
+}
+
+// the card a result is placed on, and where that came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Geometry {
+    pub blocks: u32,
+    pub source: String,
+}
+
+impl Geometry {
+    pub fn default_card(why: &str) -> Self {
+        Self {
+            blocks: DEFAULT_BLOCKS,
+            source: format!("the default, a 64 MiB retail card, as {why}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calculation {
+    pub bytes: u64,
+    // the unit asked for with 'to', if one was
+    pub target: Option<Unit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u64),
+    Unit(Unit),
+    Word(String),
+    Op(char),
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = expr.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if "+-*/()".contains(c) {
+            tokens.push(Token::Op(c));
+            chars.next();
+        } else if c.is_ascii_alphanumeric() {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek().filter(|(_, c)| c.is_ascii_alphanumeric()) {
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let word = &expr[start..end];
+            if !c.is_ascii_digit() {
+                tokens.push(Unit::parse(word).map_or(Token::Word(word.to_string()), Token::Unit));
+            } else if let Some(n) = parse_number(word) {
+                tokens.push(Token::Number(n));
+            } else {
+                // a decimal number with its unit straight after it, as in '16KiB'
+                let split = word
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(word.len());
+                match (word[..split].parse(), Unit::parse(&word[split..])) {
+                    (Ok(n), Some(unit)) => tokens.extend([Token::Number(n), Token::Unit(unit)]),
+                    _ => bail!("'{word}' isn't a number (hex ones start with 0x)"),
+                }
+            }
+        } else {
+            bail!("'{c}' can't be used in a calculation; use + - * / and parentheses");
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expr(&mut self) -> Result<u64> {
+        let mut value = self.term()?;
+        while let Some(Token::Op(op @ ('+' | '-'))) = self.peek().cloned() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            value = match op {
+                '+' => value
+                    .checked_add(rhs)
+                    .ok_or_else(|| anyhow!("the result is too big"))?,
+                _ => value
+                    .checked_sub(rhs)
+                    .ok_or_else(|| anyhow!("the result would be negative"))?,
+            };
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<u64> {
+        let mut value = self.factor()?;
+        while let Some(Token::Op(op @ ('*' | '/'))) = self.peek().cloned() {
+            self.pos += 1;
+            let rhs = self.factor()?;
+            value = match op {
+                '*' => value
+                    .checked_mul(rhs)
+                    .ok_or_else(|| anyhow!("the result is too big"))?,
+                _ => value
+                    .checked_div(rhs)
+                    .ok_or_else(|| anyhow!("can't divide by zero"))?,
+            };
+        }
+        Ok(value)
+    }
+
+    // a number or parenthesised expression, with a unit after it, or a unit and then a number
+    fn factor(&mut self) -> Result<u64> {
+        let value = match self.next() {
+            Some(Token::Number(n)) => n,
+            Some(Token::Op('(')) => {
+                let value = self.expr()?;
+                if self.next() != Some(Token::Op(')')) {
+                    bail!("a '(' isn't closed");
+                }
+                value
+            }
+            Some(Token::Unit(unit)) => match self.next() {
+                Some(Token::Number(n)) => return scale(n, unit),
+                _ => bail!("'{unit}' needs a number after it, as in 'block 500'"),
+            },
+            Some(Token::Op(op)) => bail!("'{op}' is missing a number before it"),
+            Some(Token::Word(w)) => {
+                bail!("'{w}' isn't a unit; use bytes, pages, blocks, KiB or MiB")
+            }
+            None => bail!("the calculation ends too soon"),
+        };
+        match self.peek() {
+            Some(&Token::Unit(unit)) => {
+                self.pos += 1;
+                scale(value, unit)
+            }
+            _ => Ok(value),
+        }
+    }
+}
+
+fn scale(n: u64, unit: Unit) -> Result<u64> {
+    n.checked_mul(unit.size())
+        .ok_or_else(|| anyhow!("the result is too big"))
+}
+
+pub fn evaluate(expr: &str) -> Result<Calculation> {
+    let mut tokens = tokenize(expr)?;
+    let target = match tokens.as_slice() {
+        [.., Token::Word(w)] if w.eq_ignore_ascii_case("offset") => {
+            tokens.pop();
+            Some(Unit::Bytes)
+        }
+        [.., Token::Word(to), Token::Unit(unit)] if to.eq_ignore_ascii_case("to") => {
+            let unit = *unit;
+            tokens.truncate(tokens.len() - 2);
+            Some(unit)
+        }
+        _ => None,
+    };
+    if tokens.is_empty() {
+        bail!("there's nothing to calculate");
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let bytes = parser.expr()?;
+    match parser.peek() {
+        None => Ok(Calculation { bytes, target }),
+        Some(Token::Word(w)) if w.eq_ignore_ascii_case("to") => {
+            bail!("'to' needs a unit after it: bytes, pages, blocks, KiB or MiB")
+        }
+        Some(Token::Word(w)) => bail!("'{w}' isn't a unit; use bytes, pages, blocks, KiB or MiB"),
+        Some(Token::Number(n)) => bail!("{n:#X} is missing a + - * or / before it"),
+        Some(Token::Unit(unit)) => bail!("'{unit}' comes after something that already has a unit"),
+        Some(Token::Op(op)) => bail!("the '{op}' doesn't belong there"),
+    }
+}
+
+// `bytes` in `unit`: whole units where it divides exactly, else whole units and what's left over
+// (for blocks and pages, where an offset is a position in one), else a fraction
+pub fn in_unit(bytes: u64, unit: Unit) -> String {
+    let size = unit.size();
+    let (whole, rest) = (bytes / size, bytes % size);
+    match unit {
+        Unit::Bytes => format!("{bytes:#X} bytes ({bytes})"),
+        _ if rest == 0 => format!("{whole:#X} {unit} ({whole})"),
+        Unit::Pages | Unit::Blocks => {
+            format!("{whole:#X} {unit} ({whole}) + {rest:#X} bytes")
+        }
+        _ => format!("{:.3} {unit}", bytes as f64 / size as f64),
+    }
+}
+
+// which part of the card the block holding byte `bytes` is in
+pub fn region(bytes: u64, geometry: &Geometry) -> String {
+    let block = bytes / BLOCK_SIZE as u64;
+    let fs_start = geometry.blocks.saturating_sub(FS_REGION_BLOCKS as u32) as u64;
+    let part = match block {
+        b if b < SKSA_BLOCKS as u64 => "the SKSA",
+        b if b < fs_start => "the file data",
+        b if b < geometry.blocks as u64 => "the FS region",
+        _ => "past the end of the card",
+    };
+    format!(
+        "Block {block:#X} is in {part}; its spare data is at {:#X} in a spare file",
+        block * 0x10
+    )
+}
+
+pub fn render(calc: &Calculation, geometry: &Geometry) -> Vec<String> {
+    let mut lines = vec![in_unit(calc.bytes, calc.target.unwrap_or(Unit::Bytes))];
+    for unit in Unit::ALL {
+        if Some(unit) != calc.target.or(Some(Unit::Bytes)) {
+            lines.push(format!("  = {}", in_unit(calc.bytes, unit)));
+        }
+    }
+    lines.push(region(calc.bytes, geometry));
+    lines.push(format!(
+        "Assuming {:#X} blocks ({} MiB) of {BLOCK_SIZE:#X} bytes, with {PAGE_SIZE:#X}-byte pages: {}",
+        geometry.blocks,
+        geometry.blocks as usize * BLOCK_SIZE / (1024 * 1024),
+        geometry.source
+    ));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calculator() -> Result<()> {
+        let bytes = |expr: &str| evaluate(expr).map(|c| c.bytes);
+
+        for (expr, expected) in [
+            ("0x1F4000", 0x1F4000),
+            ("500", 500),
+            ("block 500", 500 * 0x4000),
+            ("500 blocks", 500 * 0x4000),
+            ("3 pages", 0x600),
+            ("16KiB", 0x4000),
+            ("2 MiB - 3 blocks", 0x200000 - 0xC000),
+            ("(2 MiB - 3 blocks) / 2", (0x200000 - 0xC000) / 2),
+            ("1 + 2 * 3", 7),
+            ("(1 + 2) * 3", 9),
+            ("(1 + 2) blocks", 0xC000),
+            ("0x10 blk + 0x20", 0x40020),
+        ] {
+            if bytes(expr)? != expected {
+                bail!("'{expr}' came to {:#X}, not {expected:#X}", bytes(expr)?);
+            }
+        }
+
+        // 'to' and 'offset' choose the unit the result's given in
+        let to_blocks = evaluate("0x1F4000 to blocks")?;
+        let offset = evaluate("block 500 offset")?;
+        if to_blocks
+            != (Calculation {
+                bytes: 0x1F4000,
+                target: Some(Unit::Blocks),
+            })
+            || offset.bytes != 0x7D0000
+            || offset.target != Some(Unit::Bytes)
+            || evaluate("1 block")?.target.is_some()
+        {
+            bail!("'to' and 'offset' were read as {to_blocks:?} and {offset:?}");
+        }
+
+        for bad in [
+            "",
+            "to blocks",
+            "1 -",
+            "1 - 2",
+            "1 / 0",
+            "(1 + 2",
+            "block",
+            "5 furlongs",
+            "0xZZ",
+            "1 % 2",
+            "1 to",
+            "0xFFFFFFFFFFFFFFFF blocks",
+        ] {
+            if evaluate(bad).is_ok() {
+                bail!("'{bad}' was accepted");
+            }
+        }
+
+        if in_unit(0x7D0000, Unit::Blocks) != "0x1F4 blocks (500)"
+            || in_unit(0x7D0100, Unit::Blocks) != "0x1F4 blocks (500) + 0x100 bytes"
+            || in_unit(0x600, Unit::KiB) != "1.500 KiB"
+            || in_unit(0x10, Unit::Bytes) != "0x10 bytes (16)"
+        {
+            bail!("results were put in units wrongly");
+        }
+
+        // where a result lands depends on the card, which is said
+        let retail = Geometry::default_card("no console is selected");
+        let dev = Geometry {
+            blocks: 0x2000,
+            source: "the console's card".to_string(),
+        };
+        let fs = 0xFF8 * BLOCK_SIZE as u64;
+        if !region(0x3F * BLOCK_SIZE as u64, &retail).contains("in the SKSA")
+            || !region(fs, &retail).contains("in the FS region")
+            || !region(fs, &dev).contains("in the file data")
+            || !region(0x2000 * BLOCK_SIZE as u64, &dev).contains("past the end")
+            || !region(fs, &retail).ends_with("at 0xFF80 in a spare file")
+        {
+            bail!("results were placed on the card wrongly");
+        }
+        let lines = render(&to_blocks, &retail);
+        let expected = [
+            "0x7D blocks (125)",
+            "  = 0x1F4000 bytes (2048000)",
+            "  = 0xFA0 pages (4000)",
+            "  = 0x7D0 KiB (2000)",
+            "  = 1.953 MiB",
+            "Block 0x7D is in the file data; its spare data is at 0x7D0 in a spare file",
+            "Assuming 0x1000 blocks (64 MiB) of 0x4000 bytes, with 0x200-byte pages: the default, a 64 MiB retail card, as no console is selected",
+        ];
+        if lines != expected {
+            bail!("'calc 0x1F4000 to blocks' printed {lines:#?}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,405 @@
+#![allow(non_snake_case)]
+
+use std::fmt::{self, Display};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use bbrdb::CardStats;
+
+use crate::fs::{
+    FsBlock, FsEntry, BLOCK_SIZE, FAT_END, FAT_ENTRIES, FAT_FREE, FAT_RESERVED, FS_REGION_BLOCKS,
+    SKSA_BLOCKS, SPARE_SIZE,
+};
+use crate::player::Player;
+#[cfg(feature = "writing")]
+use crate::player::PlayerWrite;
+
+// 'set dry-run trace': commands that use the console are run against a player that makes none of
+// the calls they ask of it, but writes each one down, and then the list is printed. Where a call
+// has to answer for the command to carry on, it's given a made-up reply, which is shown next to
+// it: a card with nothing on it but one block for each file the command names, every block of it
+// erased. Only calls made through the player are seen, so commands that reach the console (or
+// write local files) any other way aren't traced at all.
+
+// the made-up card's sequence number and BBID
+const PLACEHOLDER_SEQNO: u32 = 1;
+const PLACEHOLDER_BBID: u32 = 0;
+
+// a call that would have been made of the console
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call {
+    GetBBID,
+    SetLED(u32),
+    ListFiles,
+    DumpCurrentFS,
+    ReadFile(String),
+    ReadSingleBlock(u32),
+    CardStats,
+    #[cfg(feature = "writing")]
+    WriteFile {
+        name: String,
+        len: usize,
+    },
+    #[cfg(feature = "writing")]
+    DeleteFile(String),
+    #[cfg(feature = "writing")]
+    RenameFile {
+        from: String,
+        to: String,
+    },
+}
+
+impl Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GetBBID => write!(f, "GetBBID()"),
+            Self::SetLED(value) => write!(f, "SetLED({value})"),
+            Self::ListFiles => write!(f, "ListFiles()"),
+            Self::DumpCurrentFS => write!(f, "DumpCurrentFS()"),
+            Self::ReadFile(name) => write!(f, "ReadFile({name:?})"),
+            Self::ReadSingleBlock(blk) => write!(f, "ReadSingleBlock({blk:#X})"),
+            Self::CardStats => write!(f, "CardStats()"),
+            #[cfg(feature = "writing")]
+            Self::WriteFile { name, len } => write!(f, "WriteFile({name:?}, {len:#X} bytes)"),
+            #[cfg(feature = "writing")]
+            Self::DeleteFile(name) => write!(f, "DeleteFile({name:?})"),
+            #[cfg(feature = "writing")]
+            Self::RenameFile { from, to } => write!(f, "RenameFile({from:?}, {to:?})"),
+        }
+    }
+}
+
+// a call, and the placeholder it was answered with if it needed one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub call: Call,
+    pub reply: Option<String>,
+}
+
+struct Trace {
+    steps: Vec<Step>,
+    // the files the made-up card holds
+    files: Vec<String>,
+}
+
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+// starts writing calls down; `args` are the command's (after its name), and those that could be file names are
+// the files on the made-up card
+pub fn start(args: &[&str]) {
+    let files = args
+        .iter()
+        .filter(|a| !a.starts_with("--") && FsEntry::fits(a))
+        .map(|a| a.to_string())
+        .collect();
+    if let Ok(mut trace) = TRACE.lock() {
+        *trace = Some(Trace {
+            steps: vec![],
+            files,
+        });
+    }
+}
+
+// stops, with the calls written down since 'start'
+pub fn finish() -> Vec<Step> {
+    TRACE
+        .lock()
+        .ok()
+        .and_then(|mut t| t.take())
+        .map(|t| t.steps)
+        .unwrap_or_default()
+}
+
+pub fn active() -> bool {
+    TRACE.lock().is_ok_and(|t| t.is_some())
+}
+
+// the made-up card's FS: the SKSA's and the FS's own blocks reserved, and each file in a block of
+// its own after the SKSA
+fn placeholder_fs(files: &[String]) -> FsBlock {
+    let mut fat = vec![FAT_FREE; FAT_ENTRIES];
+    fat[..SKSA_BLOCKS as usize].fill(FAT_RESERVED);
+    fat[FAT_ENTRIES - FS_REGION_BLOCKS..].fill(FAT_RESERVED);
+    let entries = files
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let start = SKSA_BLOCKS + i as u16;
+            fat[start as usize] = FAT_END;
+            FsEntry::new(name, start, BLOCK_SIZE as u32)
+        })
+        .collect();
+    FsBlock {
+        fat,
+        entries,
+        linked: false,
+        seqno: PLACEHOLDER_SEQNO,
+    }
+}
+
+fn count_files(files: &[String]) -> String {
+    match files.len() {
+        1 => "1 file".to_string(),
+        n => format!("{n} files"),
+    }
+}
+
+// writes `call` down, answering it with `reply` (given the made-up card's files), which also says
+// what it answered with
+fn record<T>(call: Call, reply: impl FnOnce(&[String]) -> (T, Option<String>)) -> T {
+    let mut trace = TRACE.lock().unwrap_or_else(|e| e.into_inner());
+    let files = trace.as_ref().map(|t| t.files.clone()).unwrap_or_default();
+    let (value, said) = reply(&files);
+    if let Some(t) = trace.as_mut() {
+        t.steps.push(Step { call, reply: said });
+    }
+    value
+}
+
+// answers every call with a placeholder, writing it down instead of making it
+pub struct Tracer;
+
+impl Player for Tracer {
+    fn GetBBID(&self) -> Result<u32> {
+        Ok(record(Call::GetBBID, |_| {
+            (
+                PLACEHOLDER_BBID,
+                Some(format!("BBID {PLACEHOLDER_BBID:08X}")),
+            )
+        }))
+    }
+
+    fn SetLED(&self, value: u32) -> Result<()> {
+        record(Call::SetLED(value), |_| ((), None));
+        Ok(())
+    }
+
+    fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+        Ok(record(Call::ListFiles, |files| {
+            let list = files.iter().map(|f| (f.clone(), BLOCK_SIZE as u32));
+            (list.collect(), Some(count_files(files)))
+        }))
+    }
+
+    fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+        record(Call::DumpCurrentFS, |files| {
+            (
+                placeholder_fs(files).to_bytes(),
+                Some(format!(
+                    "FS #{PLACEHOLDER_SEQNO}, with {}",
+                    count_files(files)
+                )),
+            )
+        })
+    }
+
+    fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        Ok(record(
+            Call::ReadFile(name.to_string()),
+            |files| match files.iter().any(|f| f == name) {
+                true => (
+                    Some(vec![0; BLOCK_SIZE]),
+                    Some(format!("{BLOCK_SIZE:#X} bytes of zeroes")),
+                ),
+                false => (None, Some("not found".to_string())),
+            },
+        ))
+    }
+
+    fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        Ok(record(Call::ReadSingleBlock(blk), |_| {
+            (
+                (vec![0xFF; BLOCK_SIZE], vec![0xFF; SPARE_SIZE]),
+                Some("erased".to_string()),
+            )
+        }))
+    }
+
+    fn CardStats(&self) -> Result<CardStats> {
+        Ok(record(Call::CardStats, |files| {
+            let fat = &placeholder_fs(files).fat;
+            let free = fat.iter().filter(|&&v| v == FAT_FREE).count() as u32;
+            let stats = CardStats {
+                free,
+                used: FAT_ENTRIES as u32 - free,
+                bad: 0,
+                seqno: PLACEHOLDER_SEQNO,
+            };
+            let said = format!(
+                "{} free, {} used, {} bad, FS #{}",
+                stats.free, stats.used, stats.bad, stats.seqno
+            );
+            (stats, Some(said))
+        }))
+    }
+}
+
+#[cfg(feature = "writing")]
+impl PlayerWrite for Tracer {
+    fn WriteFile(&mut self, data: &[u8], name: &str) -> Result<()> {
+        let call = Call::WriteFile {
+            name: name.to_string(),
+            len: data.len(),
+        };
+        record(call, |_| ((), None));
+        Ok(())
+    }
+
+    fn DeleteFile(&mut self, name: &str) -> Result<()> {
+        record(Call::DeleteFile(name.to_string()), |_| ((), None));
+        Ok(())
+    }
+
+    fn RenameFile(&mut self, from: &str, to: &str) -> Result<()> {
+        let call = Call::RenameFile {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        record(call, |_| ((), None));
+        Ok(())
+    }
+}
+
+// one line a call, numbered, with a run of reads of consecutive blocks given the same reply
+// on one line
+pub fn render(command: &str, steps: &[Step]) -> String {
+    let mut lines = vec![];
+    let mut i = 0;
+    while i < steps.len() {
+        let mut end = i + 1;
+        if let Call::ReadSingleBlock(first) = steps[i].call {
+            while end < steps.len()
+                && steps[end].call == Call::ReadSingleBlock(first + (end - i) as u32)
+                && steps[end].reply == steps[i].reply
+            {
+                end += 1;
+            }
+        }
+        let call = match end - i {
+            1 => steps[i].call.to_string(),
+            n => format!("{} .. {} ({n} calls)", steps[i].call, steps[end - 1].call),
+        };
+        lines.push(match &steps[i].reply {
+            Some(reply) => format!("{call}  -> placeholder: {reply}"),
+            None => call,
+        });
+        i = end;
+    }
+    let mut out = match lines.len() {
+        0 => format!("'{command}' would make no calls of the console\n"),
+        _ => format!("'{command}' would make these calls of the console (none were made):\n"),
+    };
+    let width = lines.len().to_string().len();
+    for (n, line) in lines.iter().enumerate() {
+        out += &format!("  {:>width$}. {line}\n", n + 1);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_traces() -> Result<()> {
+        use anyhow::bail;
+
+        use crate::cancel::CancelToken;
+        use crate::download::read_head;
+        use crate::nand_read::card_blocks;
+
+        let check = |what: &str, found: String, expected: &str| -> Result<()> {
+            if found != expected {
+                bail!("{what} was traced as\n{found}\nnot\n{expected}");
+            }
+            Ok(())
+        };
+        let cancel = CancelToken::default();
+
+        // nothing is written down unless a trace is going
+        Tracer.GetBBID()?;
+        if active() || !finish().is_empty() {
+            bail!("a call was written down without a trace");
+        }
+
+        // reading a file follows the made-up card's FS to the block it's in
+        start(&["GAME.app"]);
+        let (data, size) = read_head(&Tracer, "GAME.app", 0x100, &cancel)?;
+        let steps = finish();
+        if data != vec![0xFF; 0x100] || size != BLOCK_SIZE as u32 || active() {
+            bail!("the traced read gave {:#X} bytes of {size:#X}", data.len());
+        }
+        check(
+            "'cat GAME.app'",
+            render("cat GAME.app", &steps),
+            "'cat GAME.app' would make these calls of the console (none were made):\n  \
+             1. DumpCurrentFS()  -> placeholder: FS #1, with 1 file\n  \
+             2. ReadSingleBlock(0x40)  -> placeholder: erased\n",
+        )?;
+
+        // and a file that isn't one of the command's arguments isn't on it
+        start(&["GAME.app"]);
+        let missing = read_head(&Tracer, "OTHER.app", 16, &cancel);
+        check(
+            "'cat' of a file that isn't there",
+            render("cat OTHER.app", &finish()),
+            "'cat OTHER.app' would make these calls of the console (none were made):\n  \
+             1. DumpCurrentFS()  -> placeholder: FS #1, with 1 file\n",
+        )?;
+        if missing.is_ok() {
+            bail!("a file that isn't on the made-up card was read");
+        }
+
+        // a dump's reads of the whole card go on one line
+        start(&[]);
+        let blocks = card_blocks(&Tracer)?;
+        for blk in 0..blocks {
+            Tracer.ReadSingleBlock(blk)?;
+        }
+        check(
+            "'1'",
+            render("1", &finish()),
+            "'1' would make these calls of the console (none were made):\n  \
+             1. CardStats()  -> placeholder: 4016 free, 80 used, 0 bad, FS #1\n  \
+             2. ReadSingleBlock(0x0) .. ReadSingleBlock(0xFFF) (4096 calls)  -> placeholder: erased\n",
+        )?;
+
+        // calls that don't answer with anything are written down as they were asked
+        start(&["3"]);
+        Tracer.SetLED(3)?;
+        Tracer.ReadSingleBlock(5)?;
+        Tracer.ReadSingleBlock(7)?;
+        Tracer.ListFiles()?;
+        Tracer.ReadFile("3")?;
+        Tracer.ReadFile("4")?;
+        #[cfg(feature = "writing")]
+        {
+            Tracer.WriteFile(&[0; 0x100], "GAME.app")?;
+            Tracer.RenameFile("A.sys", "B.sys")?;
+            Tracer.DeleteFile("B.sys")?;
+        }
+        let expected = "'H 3' would make these calls of the console (none were made):\n  \
+             1. SetLED(3)\n  \
+             2. ReadSingleBlock(0x5)  -> placeholder: erased\n  \
+             3. ReadSingleBlock(0x7)  -> placeholder: erased\n  \
+             4. ListFiles()  -> placeholder: 1 file\n  \
+             5. ReadFile(\"3\")  -> placeholder: 0x4000 bytes of zeroes\n  \
+             6. ReadFile(\"4\")  -> placeholder: not found\n";
+        #[cfg(feature = "writing")]
+        let expected = format!(
+            "{expected}  \
+             7. WriteFile(\"GAME.app\", 0x100 bytes)\n  \
+             8. RenameFile(\"A.sys\", \"B.sys\")\n  \
+             9. DeleteFile(\"B.sys\")\n"
+        );
+        #[cfg(not(feature = "writing"))]
+        let expected = expected.to_string();
+        check("a mix of calls", render("H 3", &finish()), &expected)?;
+        check(
+            "nothing",
+            render("h", &[]),
+            "'h' would make no calls of the console\n",
+        )?;
+        Ok(())
+    }
+}
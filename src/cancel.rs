@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+
+/// A request to stop a long operation, from Ctrl+C or from another part of the program. Operations
+/// check it between transfers (blocks, or files for whole-file operations), never during one, so
+/// the console is never left partway through a request and the next command works without
+/// selecting it again. What a cancelled operation leaves behind is whatever it leaves when a block
+/// fails at the same point: a dump or write stops where it was, a download keeps its '.partial'.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Asks whatever's running to stop at its next cancellation point.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether it's been asked to stop since the last [`reset`](Self::reset).
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clears the request; called before each command, so a cancellation only stops the command
+    /// it was meant for.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    /// The check made at each cancellation point: an error if it's been asked to stop.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            bail!("Cancelled");
+        }
+        Ok(())
+    }
+}
+
+/// The one place SIGINT is handled: Ctrl+C while a command runs cancels it through `token`, and
+/// a second one before it has stopped quits, for an operation stuck inside a single transfer. At
+/// the prompt, rustyline sees Ctrl+C itself.
+#[cfg(feature = "cli")]
+pub fn install_sigint_handler(token: CancelToken) -> Result<()> {
+    ctrlc::set_handler(move || {
+        if token.is_cancelled() {
+            crate::instance_lock::release_all();
+            std::process::exit(130);
+        }
+        eprintln!("\nCancelling at the end of the current block; press Ctrl+C again to quit");
+        token.cancel();
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{BLOCK_SIZE, SPARE_SIZE};
+    use crate::led::{LedGuard, LedState, LED_ON};
+    use crate::nand_read::dump_nand;
+    use crate::player::Player;
+    use bbrdb::CardStats;
+    use std::cell::{Cell, RefCell};
+
+    #[test]
+    fn cancellation() -> Result<()> {
+        // a card that cancels the dump while block 5 is being read, and remembers what it was asked
+        struct Card {
+            cancel: CancelToken,
+            reads: Cell<u32>,
+            leds: RefCell<Vec<u32>>,
+        }
+
+        impl Player for Card {
+            fn GetBBID(&self) -> Result<u32> {
+                Ok(0x1234)
+            }
+
+            fn SetLED(&self, value: u32) -> Result<()> {
+                self.leds.borrow_mut().push(value);
+                Ok(())
+            }
+
+            fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+                Ok(vec![])
+            }
+
+            fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+                Ok(vec![0xFF; BLOCK_SIZE])
+            }
+
+            fn ReadFile(&self, _name: &str) -> Result<Option<Vec<u8>>> {
+                Ok(None)
+            }
+
+            fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+                self.reads.set(self.reads.get() + 1);
+                if blk == 5 {
+                    self.cancel.cancel();
+                }
+                Ok((vec![0xFF; BLOCK_SIZE], vec![0xFF; SPARE_SIZE]))
+            }
+
+            fn CardStats(&self) -> Result<CardStats> {
+                Ok(CardStats {
+                    free: 0x40,
+                    used: 0,
+                    bad: 0,
+                    seqno: 1,
+                })
+            }
+        }
+
+        let cancel = CancelToken::default();
+        let card = Card {
+            cancel: cancel.clone(),
+            reads: Cell::new(0),
+            leds: RefCell::new(vec![]),
+        };
+        let mut led = LedState::default();
+        let result = {
+            let _led = LedGuard::start(&card, &mut led, true);
+            dump_nand(&card, false, false, &cancel)
+        };
+        match result {
+            Err(e) if e.to_string().ends_with("Cancelled") => {}
+            Err(e) => bail!("the dump failed with '{e}' rather than being cancelled"),
+            Ok(_) => bail!("the dump wasn't cancelled"),
+        }
+        if card.reads.get() != 6 {
+            bail!(
+                "the dump read {} blocks, not stopping after block 5",
+                card.reads.get()
+            );
+        }
+        if card.leds.borrow().last() != Some(&LED_ON) || !led.restore_on_next_command {
+            bail!("the LED wasn't left solid after the cancelled dump");
+        }
+
+        cancel.reset();
+        if cancel.check().is_err() {
+            bail!("a reset token still reads as cancelled");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1582 @@
+use std::fs::read;
+use std::io::{stdin, stdout, IsTerminal};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::acceptance;
+use crate::attest::{load_key, report, Attestation};
+use crate::backup::{self, backup_incremental};
+#[cfg(feature = "writing")]
+use crate::badblocks::effective_bad;
+use crate::badblocks::{self, audit, Overrides};
+#[cfg(feature = "writing")]
+use crate::byteswap::{detect_orientation, swap16, Orientation};
+use crate::calc::{self, Geometry};
+use crate::call_trace;
+use crate::cancel::CancelToken;
+use crate::clock::{check as check_clock, configured_zone, ConsoleClock, PcClock};
+use crate::compress::{check_resumable, read_input, with_codec};
+use crate::config::Config;
+#[cfg(feature = "writing")]
+use crate::danger::{confirm_dangerous, touches_protected, DangerLock};
+use crate::dat;
+use crate::dedupe::{dedupe_archive, rehydrate};
+use crate::device::{
+    init_with_reset, parse_index, reset_device, scan_listed, scan_sorted, DeviceInfo, DeviceList,
+    DeviceLocation, Initialised, Listed, SelectedConsole,
+};
+use crate::download::{download_file, download_with_spare, read_head};
+#[cfg(feature = "writing")]
+use crate::dupes::delete_extras;
+use crate::dupes::{find_duplicates, print_dupes};
+use crate::error_chain::print_error;
+use crate::file_digest::FileDigest;
+use crate::file_times::set_modified;
+use crate::fingerprint::{print_fingerprint, Fingerprint};
+use crate::finish::{finish, PostState};
+#[cfg(feature = "writing")]
+use crate::foreign_bad::{
+    compare_bad, describe_remap, explain_bad, fat_bad, image_fs, remap, skip_bad, source_bad,
+};
+use crate::fs::BLOCK_SIZE;
+#[cfg(feature = "writing")]
+use crate::fs::SPARE_SIZE;
+use crate::fs::{stat_lines, FsBlock, FS_REGION_BLOCKS, SKSA_BLOCKS};
+use crate::fs_cache::FsCache;
+#[cfg(feature = "writing")]
+use crate::fscheck;
+use crate::fsdiff::fsdiff;
+use crate::genimage::genimage;
+#[cfg(feature = "writing")]
+use crate::geometry::{explain, fitting_end, lay_out, range_fits, restore_options};
+use crate::hashing::{choose, format_hashes, parse_algos, HashAlgo, HashFormat};
+use crate::help;
+use crate::heroic::{heroic_read, ReadOutcome};
+use crate::hexfile::convert;
+#[cfg(feature = "writing")]
+use crate::hexfile::{byte_ranges, load_input, RecordFormat};
+use crate::history::{read_region, read_region_file, timeline};
+use crate::hooks::{self, Guard};
+use crate::hotplug::{watch, Reaction, Tracker, Unplugged};
+use crate::instance_lock::{holder, DeviceLock};
+use crate::jobs::{Finished, Jobs, Work};
+#[cfg(feature = "writing")]
+use crate::journal::read_entries;
+use crate::keepalive::{self, KeepAlive, Link};
+use crate::kit::{build, read_blocks, system_files, KitInfo};
+#[cfg(feature = "writing")]
+use crate::kit::{check_fit, place, plan, MANIFEST_FILE};
+use crate::known_errors;
+use crate::led::{LedGuard, LedState};
+#[cfg(feature = "writing")]
+use crate::leftovers::{self, empty_files, replace_file};
+use crate::lint::lint_files;
+use crate::listing::{games, render};
+#[cfg(feature = "writing")]
+use crate::mount::source_mut;
+#[cfg(not(feature = "writing"))]
+use crate::mount::Sandbox;
+use crate::mount::{source, MountedImage};
+use crate::nand_read::card_blocks;
+use crate::nand_read::dump_nand;
+#[cfg(feature = "writing")]
+use crate::nand_write::{synthesize_spares, write_args, write_ranges};
+use crate::notes::{self, lookup, remember_serial, Note};
+use crate::notify::{fire, notify, Outcome};
+use crate::offline::dumpinfo;
+use crate::oplog::OpLog;
+use crate::options::{take_flag_value, Options};
+use crate::organize::{self, layout, move_file, survey_dir, Pairing, ARCHIVE_DIR};
+use crate::page::{parse_page, render_page, slice_page};
+use crate::patch::Patch;
+use crate::paths::check_distinct;
+use crate::player::{Backend, Console, Player, ReadOnly};
+use crate::preflight;
+use crate::preset::{all, find, preset_arg, resolve};
+use crate::preview::{self, DEFAULT_MAX_BYTES};
+use crate::profile::{profile_arg, ActiveProfile};
+use crate::progress::Follow;
+use crate::prompt::Prompt;
+use crate::provenance::{dump_manifest, manifest_path};
+#[cfg(feature = "writing")]
+use crate::provenance::{origin_from_tickets, policy, ticket_bbids, Confidence, Origin, Verdict};
+#[cfg(feature = "writing")]
+use crate::provision::read_local;
+use crate::provision::{self, print_diff};
+#[cfg(feature = "writing")]
+use crate::range_builder::{RangeBuilder, Step};
+#[cfg(feature = "writing")]
+use crate::ranges::format_range;
+use crate::ranges::parse_ranges;
+#[cfg(feature = "writing")]
+use crate::relocate::{newest_fs, relocate};
+use crate::report::{AcceptanceReport, ScrubReport, VerifyReport};
+#[cfg(feature = "writing")]
+use crate::require_backup::{dump_names, recent_backup};
+#[cfg(feature = "writing")]
+use crate::roles::{allow_conflicts, role_conflict, role_conflicts, sa_chain};
+#[cfg(feature = "writing")]
+use crate::sandbox::Sandbox;
+use crate::scrub::{recommend, scrub, Health};
+use crate::selftest::selftest;
+use crate::session::{reselect, MountSnapshot, Snapshot, SESSION_SCHEMA};
+use crate::sign::{sign_file, verify_file};
+use crate::similar::{either, retry_with, similar_names};
+use crate::sink::{write_atomic, OutputSink};
+use crate::sizes::format_size;
+use crate::sksa;
+#[cfg(feature = "devtools")]
+use crate::slowlink::{simulation_args, start_simulation};
+use crate::spotcheck::{block_hashes, sidecar_to_csv, spotcheck, SampleRng};
+use crate::staleness::CardView;
+use crate::startup::select_at_startup;
+use crate::stats_history::{load_history, print_history, record_stats};
+#[cfg(feature = "writing")]
+use crate::strict::{card_write, check_command, Manifest, STRICT_FLAGS};
+use crate::summary::RangeSummary;
+use crate::survey::{render_table, survey, survey_to_csv};
+#[cfg(feature = "writing")]
+use crate::throughput::ThroughputStats;
+#[cfg(feature = "writing")]
+use crate::ticket::TICKET_FILE;
+use crate::ticket_backup::list_backups;
+#[cfg(feature = "writing")]
+use crate::ticket_backup::{backup_tickets, load_backup, touches_tickets};
+use crate::triage::triage;
+#[cfg(feature = "tui")]
+use crate::tui::browse;
+#[cfg(feature = "writing")]
+use crate::txn::{commit_console, parse_op, restore, Op};
+#[cfg(feature = "writing")]
+use crate::upload::{self, resume_dir, Decision};
+use crate::usb::{print_unavailable, Usb};
+pub use crate::usb::{UsbHold, UsbInit};
+use crate::verify::verify_ranges;
+#[cfg(feature = "writing")]
+use crate::wear::assess;
+use crate::wrap::stdout_width;
+#[cfg(feature = "writing")]
+use crate::write_preview::{
+    preview_block, preview_image, preview_relocate, preview_tickets, preview_txn,
+};
+use crate::{PROG_NAME, PROG_VER};
+use anyhow::{anyhow, bail, Result};
+use bbrdb::{CardStats, GlobalHandle};
+use chrono::{DateTime, FixedOffset, Local};
+
+// each command is a function in the module for its kind, taking what it needs of the session
+// and the line
+mod blocks;
+mod checks;
+mod console;
+mod dumps;
+mod files;
+mod session;
+
+// commands that change the console or only make sense on real hardware, so can't be used on a mounted dump
+// ('4', '6' and '7' are handled by the dump itself, which refuses them unless it was mounted with --rw)
+const DEVICE_ONLY_COMMANDS: [&str; 11] = [
+    "B",
+    "H",
+    "S",
+    "J",
+    "K",
+    "Y",
+    "Q",
+    "2",
+    "finish",
+    "reset-usb",
+    "relocate",
+];
+
+// commands that change the console's card, so are checked first against the FS generation the
+// session last saw
+fn changes_card(command: &[&str]) -> bool {
+    cfg!(feature = "writing")
+        && match command[0] {
+            "Y" | "2" | "4" | "6" | "7" | "relocate" | "clean" => true,
+            "ticket" => command.get(1) == Some(&"restore"),
+            "dupes" => command.contains(&"--interactive"),
+            "provision" => command.get(1) == Some(&"apply") && !command.contains(&"--dry-run"),
+            "txn" => command.get(1) == Some(&"commit"),
+            "patch" => command.get(1) != Some(&"--local"),
+            _ => false,
+        }
+}
+
+// commands that can run in the background with '&': long reads of the console, none of which
+// change it or ask anything partway through
+const BACKGROUND_COMMANDS: [&str; 2] = ["verify", "scrub"];
+
+// commands that never need the console, so can run while a background job has it
+fn without_console(command: &[&str]) -> bool {
+    match command[0] {
+        "" | "h" | "?" | "calc" | "status" | "jobs" | "fg" | "set" | "preset" | "report"
+        | "selftest" | "caps" | "lint" | "organize" | "genimage" | "convert" | "dumpinfo"
+        | "dedupe-archive" | "rehydrate" | "q" => true,
+        "note" => command.get(1) == Some(&"list"),
+        _ => false,
+    }
+}
+
+// commands that would change the console other than through its files, or put another console
+// or a dump in place of the one the sandbox is over, so are refused while it's on
+#[cfg(feature = "writing")]
+fn leaves_sandbox(command: &[&str]) -> bool {
+    match command[0] {
+        "Y" | "2" | "J" | "relocate" | "s" | "mount" | "retry-usb" => true,
+        "session" => command.get(1) == Some(&"load"),
+        "badblocks" => command.contains(&"--test"),
+        _ => false,
+    }
+}
+
+// checks the link before a write of `bytes` to the console, if it's big enough for
+// 'set preflight'; whether the write should go ahead
+#[cfg(feature = "writing")]
+fn preflight_passes(
+    rl: &mut dyn Prompt,
+    player: &dyn Player,
+    options: &Options,
+    cancel: &CancelToken,
+    bytes: u64,
+    args: &[&str],
+) -> bool {
+    if options
+        .preflight_above
+        .is_none_or(|mib| bytes < mib * 1024 * 1024)
+    {
+        return true;
+    }
+    println!(
+        "Checking the link before writing {} MiB",
+        bytes / (1024 * 1024)
+    );
+    let assessment = match preflight::measure(player, preflight::SAMPLE_BLOCKS, cancel) {
+        Ok(metrics) => preflight::score(&metrics),
+        Err(e) => {
+            print_error(&*e, options.progress_events);
+            return false;
+        }
+    };
+    for line in preflight::report(&assessment, options.preflight_score) {
+        println!("{line}");
+    }
+    if assessment.score >= options.preflight_score || args.contains(&"--accept-link") {
+        return true;
+    }
+    if options.strict_writes {
+        eprintln!(
+            "Not writing with strict-writes on, as the link scored under {}",
+            options.preflight_score
+        );
+        return false;
+    }
+    if !stdin().is_terminal() {
+        eprintln!("Add '--accept-link' to write anyway when not running interactively.");
+        return false;
+    }
+    let answer = rl.readline("Write over this link anyway? [y/N] ");
+    if !matches!(answer.as_deref().map(str::trim), Ok("y" | "Y")) {
+        eprintln!("Cancelled");
+        return false;
+    }
+    true
+}
+
+// the strict-writes gate, for a command that writes to the card: reads the files it writes from
+// (a transaction's uploads are already read) and checks them with the rest. The gate's flags are
+// then taken out of the command, except for '2', which also goes by '--manifest' for where the
+// dump came from
+#[cfg(feature = "writing")]
+fn strict_gate(context: &CliContext, command: &mut Vec<&str>) -> Result<()> {
+    let Some(write) = card_write(command) else {
+        return Ok(());
+    };
+    if context.options.strict_writes {
+        let manifest = command
+            .iter()
+            .position(|a| *a == "--manifest")
+            .and_then(|i| command.get(i + 1))
+            .map(|path| Manifest::load(path))
+            .transpose()?;
+        let mut inputs = write
+            .inputs
+            .iter()
+            .map(|path| Ok((path.to_string(), read_input(path)?)))
+            .collect::<Result<Vec<_>>>()?;
+        // system files in the directory are never uploaded
+        if let Some(dir) = write.dir {
+            for entry in std::fs::read_dir(dir).map_err(|e| anyhow!("{dir}: {e}"))? {
+                let path = entry?.path();
+                let name = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or_default();
+                if path.is_file() && !provision::is_protected(name) {
+                    inputs.push((path.to_string_lossy().into_owned(), std::fs::read(&path)?));
+                }
+            }
+        }
+        let queued = context
+            .txn
+            .iter()
+            .flatten()
+            .filter(|_| command[0] == "txn")
+            .filter_map(|op| match op {
+                Op::Upload { name, data, source } => {
+                    Some((source.as_deref().unwrap_or(name), &data[..]))
+                }
+                _ => None,
+            });
+        let files = inputs
+            .iter()
+            .map(|(path, data)| (path.as_str(), &data[..]))
+            .chain(queued)
+            .collect();
+        let console_bbid = source(&context.mounted, &context.sandbox, &context.player)
+            .and_then(|p| p.GetBBID().ok());
+        check_command(true, command, files, manifest.as_ref(), console_bbid)?;
+    }
+    if command[0] != "2" {
+        for flag in STRICT_FLAGS {
+            take_flag_value(command, flag)?;
+        }
+    }
+    Ok(())
+}
+
+// with 'set post-write-fscheck', reads the FS region back after a write that reached into it and
+// checks its newest FS; None if it passed or wasn't run, else why the write can't count as done.
+// A failure says loudly where the card's backup from before the write is, if one's known
+#[cfg(feature = "writing")]
+fn post_write_fscheck(
+    options: &Options,
+    player: &dyn Player,
+    expected_seqno: Option<u32>,
+    backup: Option<&Path>,
+) -> Option<String> {
+    if !options.post_write_fscheck {
+        return None;
+    }
+    let checked =
+        card_blocks(player).and_then(|card| fscheck::check_fs(player, card as u16, expected_seqno));
+    match checked {
+        Ok(c) => {
+            println!(
+                "Post-write FS check: FS #{} parses, with {} files and no problems",
+                c.seqno, c.files
+            );
+            None
+        }
+        Err(e) => {
+            eprintln!("Post-write FS check failed: {e}");
+            match backup {
+                Some(b) => eprintln!("*** The card's backup from before this write is {}; restore it before writing anything else ***", b.display()),
+                None => eprintln!("*** No backup of the card from before this write is known; dump it as it is now with '1' before trying to repair it ***"),
+            }
+            Some(format!(
+                "the write finished, but the FS check after it failed: {e}"
+            ))
+        }
+    }
+}
+
+// how a command is run under 'set dry-run trace'
+enum Tracing {
+    // against the tracer, which lists the calls it would make of the console
+    Traced,
+    // as usual, as it doesn't use the console
+    Untouched,
+    // not at all, as it uses the console (or local files) other than through the player calls
+    Refused,
+}
+
+fn tracing(command: &[&str]) -> Tracing {
+    match command[0] {
+        "I" | "L" | "5" | "F" | "X" | "C" | "1" | "hash" | "cat" | "stat" => Tracing::Traced,
+        // the raw and resumed reads save to local files themselves
+        "3" if !command.contains(&"--with-spare") && !command.contains(&"--continue") => {
+            Tracing::Traced
+        }
+        // changes to the tickets back them up first
+        #[cfg(feature = "writing")]
+        "4" | "6" if !touches_tickets(command.get(1..2).unwrap_or(&[])) => Tracing::Traced,
+        #[cfg(feature = "writing")]
+        "7" if !touches_tickets(command.get(1..3).unwrap_or(&[])) => Tracing::Traced,
+        "" | "h" | "?" | "set" | "preset" | "status" | "report" | "selftest" | "q" => {
+            Tracing::Untouched
+        }
+        _ => Tracing::Refused,
+    }
+}
+
+/// Everything a session keeps between commands. The default has no console, mount or profile,
+/// and the default config and options.
+#[derive(Default)]
+pub struct CliContext {
+    player: Option<Console>,
+    // where 'player' was found when it was selected
+    selected: Option<DeviceLocation>,
+    // the devices as 'l' last listed them, which 's' picks from by number
+    listing: Option<DeviceList>,
+    // this process's claim on 'player', so another copy of the program can't select it too
+    lock: Option<DeviceLock>,
+    // an offline dump that read-only commands use instead of the console while it's mounted
+    mounted: Option<MountedImage>,
+    // while 'sandbox on' is in effect, the changes made to the console's files since, which are
+    // kept in memory and never written to it
+    sandbox: Option<Sandbox>,
+    config: Config,
+    options: Options,
+    sink: OutputSink,
+    // free blocks as of the last 'C', for checking uploads without asking the console again, and
+    // the FS generation they're from
+    card: CardView,
+    // what 'J' last set the console's clock to, for checking the PC's against
+    clock: Option<ConsoleClock>,
+    led: LedState,
+    keepalive: KeepAlive,
+    // what this session's changes should have left on the console's card, for 'finish'
+    post_state: PostState,
+    // the last few commands, for 'report save'
+    ops: OpLog,
+    // the USB subsystem, brought up when a command first looks for consoles
+    usb: Usb,
+    // the permission profile chosen with '--profile', if any
+    profile: Option<ActiveProfile>,
+    // the preset chosen with 'preset' or '--preset', if any, and the options 'set' since (in
+    // order), which go over it
+    preset: Option<String>,
+    sets: Vec<(String, String)>,
+    // stops the running command between blocks
+    cancel: CancelToken,
+    #[cfg(feature = "writing")]
+    danger: DangerLock,
+    // the console's FS as last fetched, for listing its files
+    fs_cache: FsCache,
+    // the operations of the transaction begun with 'txn begin', if one is open
+    #[cfg(feature = "writing")]
+    txn: Option<Vec<Op>>,
+    // why the transaction restored by 'session load' has to be confirmed before it's committed
+    #[cfg(feature = "writing")]
+    txn_unconfirmed: Vec<String>,
+    // whether a console failed 'acceptance' this session, so the program exits with an error
+    failed_acceptance: bool,
+    // whether 'player' was opened at startup rather than with 's', so 's' can release it
+    auto_opened: bool,
+    // once 'session read-only-attest' has sealed the console, the log of every command since
+    attestation: Option<Attestation>,
+    // while a command is being run again with a name 'did you mean' suggested, so it isn't
+    // offered again if that fails too
+    retrying: bool,
+    // the consoles as the hotplug watcher sees them, if it's running
+    hotplug: Option<Arc<Mutex<Tracker>>>,
+    unplugged: Option<Unplugged>,
+    // reads running in the background with '&'; one that's running has the console
+    jobs: Jobs<Console>,
+}
+
+/// What the caller should do after a line has been dispatched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Read the next line.
+    Continue,
+    /// End the session, with [`CliContext::finish`].
+    Quit,
+}
+
+impl CliContext {
+    /// Loads the config, applies the profile named by '--profile' in `args` if there is one,
+    /// and selects a console to start with; fails if the profile can't be used.
+    pub fn start(args: &[String]) -> Result<Self> {
+        let mut context = CliContext {
+            config: Config::load().unwrap_or_else(|e| {
+                eprintln!("{e}");
+                Config::default()
+            }),
+            ..Default::default()
+        };
+        let profile = profile_arg(args).and_then(|name| {
+            name.map(|n| ActiveProfile::select(&context.config.profiles, n))
+                .transpose()
+        })?;
+        if let Some(profile) = profile {
+            println!("Using the '{}' profile", profile.name);
+            context.profile = Some(profile);
+        }
+        let preset = preset_arg(args)?;
+        context.options = context.resolve_options(preset)?;
+        if let Some(name) = preset {
+            println!("Using the '{name}' preset");
+            context.preset = Some(name.to_string());
+        }
+        #[cfg(feature = "devtools")]
+        if let Some(simulation) = simulation_args(args)? {
+            println!(
+                "Simulating a slow link: {}ms a block{}",
+                simulation.per_block.as_millis(),
+                simulation
+                    .fail_at
+                    .map(|b| format!(", failing once at block {b:#X}"))
+                    .unwrap_or_default()
+            );
+            start_simulation(simulation)?;
+        }
+        if context.usb.ready() {
+            if let Some((player, selected)) = select_at_startup(&context.config) {
+                if let Err(e) = context.adopt(player, selected, true) {
+                    print_error(&*e, context.options.progress_events);
+                }
+            }
+        }
+        Ok(context)
+    }
+
+    /// Sets how the USB subsystem is brought up, in place of libusb, for a front end that finds
+    /// consoles its own way; it's tried when a command first looks for consoles, and again with
+    /// 'retry-usb' if that fails. One already brought up is let go.
+    pub fn set_usb(&mut self, init: UsbInit) {
+        self.usb = Usb::new(init);
+    }
+
+    /// Selects `player` as the session's console, as 's' selects one that's plugged in, closing
+    /// any selected before; it's used as it is, so one that isn't initialised needs 'B' first.
+    /// Anything that implements [`Backend`] will do, such as a console simulated in memory. Fails
+    /// while a background job has the console, or once the session is attested.
+    pub fn select_player(&mut self, player: Box<dyn Backend>) -> Result<()> {
+        self.wake("");
+        if let Some(busy) = self.jobs.busy() {
+            bail!("{busy}");
+        }
+        if self.attestation.is_some() {
+            bail!("The session is attested, so its console can't be changed");
+        }
+        if let Some(Console::Open(mut old)) = self.player.take() {
+            let _ = old.Close();
+        }
+        #[cfg(feature = "writing")]
+        self.danger.lock();
+        self.forget_unplugged();
+        self.card.forget();
+        self.clock = None;
+        self.led = LedState::default();
+        self.fs_cache.invalidate();
+        self.player = Some(Console::Open(player));
+        self.selected = None;
+        self.lock = None;
+        self.auto_opened = false;
+        Ok(())
+    }
+
+    // selects a console found without 's', unless another copy of the program has it; `automatic`
+    // if it was opened at startup rather than asked for
+    fn adopt(
+        &mut self,
+        mut player: GlobalHandle,
+        selected: DeviceLocation,
+        automatic: bool,
+    ) -> Result<()> {
+        let lock = match DeviceLock::acquire(&selected.lock_key()) {
+            Ok(l) => l,
+            Err(e) => {
+                let _ = player.Close();
+                return Err(e);
+            }
+        };
+        self.player = Some(Console::Open(Box::new(player)));
+        self.selected = Some(selected);
+        self.lock = Some(lock);
+        self.auto_opened = automatic;
+        Ok(())
+    }
+
+    /// Starts watching for consoles being plugged in and unplugged, unless the config turns it
+    /// off; `print` puts what's said about them above the prompt without disturbing what's
+    /// being typed.
+    pub fn watch_hotplug(&mut self, print: Box<dyn FnMut(String) + Send>) {
+        if !self.usb.ready() || self.config.watch_hotplug == Some(false) {
+            return;
+        }
+        let devices = match scan_listed() {
+            Ok(d) => d.into_iter().map(|(listed, _)| listed).collect(),
+            Err(e) => {
+                eprintln!("Couldn't watch for consoles being plugged in: {e}");
+                return;
+            }
+        };
+        let tracker = Arc::new(Mutex::new(Tracker::new(devices)));
+        watch(tracker.clone(), print);
+        self.hotplug = Some(tracker);
+        self.sync_hotplug();
+    }
+
+    // acts on the open console being unplugged or coming back since the last command, and tells
+    // the watcher what's open now
+    fn sync_hotplug(&mut self) {
+        let Some(tracker) = self.hotplug.clone() else {
+            return;
+        };
+        let mut tracker = tracker.lock().unwrap_or_else(PoisonError::into_inner);
+        for reaction in tracker.take() {
+            match reaction {
+                Reaction::Lost(listed) => self.lose_console(&listed),
+                Reaction::Returned(listed) => self.reopen_console(&listed),
+            }
+        }
+        tracker.open = match (&self.player, &self.selected) {
+            (Some(_), Some(selected)) => Some(selected.bus_address()),
+            _ => None,
+        };
+        tracker.auto_open = self.config.auto_open;
+        tracker.preferred = self
+            .config
+            .preferred_console
+            .as_deref()
+            .and_then(|p| p.parse().ok());
+        self.listing = Some(DeviceList {
+            devices: tracker.devices.clone(),
+        });
+    }
+
+    // the open console was unplugged: as with a reconnect that failed, the connection is closed,
+    // but the console is kept to reopen if it's plugged back in where it was
+    fn lose_console(&mut self, listed: &Listed) {
+        let at = (listed.bus, listed.address);
+        if self.selected.as_ref().map(DeviceLocation::bus_address) != Some(at) {
+            return;
+        }
+        let (Some(console), Some(selected), Some(lock)) =
+            (self.player.take(), self.selected.take(), self.lock.take())
+        else {
+            return;
+        };
+        #[cfg(feature = "writing")]
+        self.danger.lock();
+        self.card.forget();
+        self.clock = None;
+        self.fs_cache.invalidate();
+        let initialised = console.initialised().unwrap_or(false);
+        let sealed = matches!(console, Console::Sealed(_));
+        // a sealed one can't be closed, only let go
+        if let Console::Open(mut player) = console {
+            let _ = player.Close();
+        }
+        eprintln!(
+            "Player {} was unplugged; it'll be reopened if it's plugged back into the same port, or use 'l' and 's' to select another",
+            selected.index
+        );
+        self.unplugged = Some(Unplugged {
+            index: selected.index,
+            lock,
+            initialised,
+            sealed,
+        });
+    }
+
+    // the unplugged console is back: it's opened where it is now, and initialised again if it
+    // was, as a reconnect would
+    fn reopen_console(&mut self, listed: &Listed) {
+        let Some(unplugged) = self.unplugged.take() else {
+            return;
+        };
+        let found = scan_sorted().map(|devices| {
+            devices
+                .into_iter()
+                .enumerate()
+                .find(|(_, d)| d.bus_number() == listed.bus && d.address() == listed.address)
+        });
+        let (index, device) = match found {
+            Ok(Some(found)) => found,
+            Ok(None) => {
+                eprintln!(
+                    "Player {} went again before it could be reopened; use 'l' and 's' to select it",
+                    unplugged.index
+                );
+                return;
+            }
+            Err(e) => {
+                print_error(&*e, self.options.progress_events);
+                return;
+            }
+        };
+        let mut player = match GlobalHandle::new(&device) {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("Couldn't reopen player {index}: {e}. Use 'l' and 's' to select it.");
+                return;
+            }
+        };
+        match unplugged.initialised {
+            true => match player.Init() {
+                Ok(_) => println!("Reconnected"),
+                Err(e) => eprintln!("Couldn't reconnect: {e}. Try 'Q' and 'B'."),
+            },
+            false => println!("Reopened player {index}"),
+        }
+        self.player = Some(match unplugged.sealed {
+            true => Console::Sealed(ReadOnly::seal(Box::new(player))),
+            false => Console::Open(Box::new(player)),
+        });
+        self.selected = Some(DeviceLocation::new(index, &device));
+        self.lock = Some(unplugged.lock);
+    }
+
+    // stops waiting for an unplugged console to come back, once another is selected or the
+    // connection is closed
+    fn forget_unplugged(&mut self) {
+        self.unplugged = None;
+        if let Some(tracker) = &self.hotplug {
+            tracker
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .forget_lost();
+        }
+    }
+
+    // the console offered for selecting when it was plugged in, which lapses at the next command
+    fn take_offer(&mut self) -> Option<usize> {
+        let tracker = self.hotplug.as_ref()?;
+        let offer = tracker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .offer
+            .take();
+        offer.map(|(index, _)| index)
+    }
+
+    /// Has background jobs say when they finish with `print`, above the prompt, rather than at
+    /// the next command.
+    pub fn announce_jobs(&mut self, print: Box<dyn FnMut(String) + Send>) {
+        self.jobs.announce_with(print);
+    }
+
+    // takes the console back from a background job that's finished with it, and says what's
+    // finished that hasn't been said
+    fn sync_jobs(&mut self) {
+        let (console, notices) = self.jobs.reap();
+        if let Some(console) = console {
+            self.player = Some(console);
+        }
+        for notice in notices {
+            println!("{notice}");
+        }
+    }
+
+    /// Does what's due between commands (keeping the connection alive, saying what background
+    /// jobs and the hotplug watcher have seen) and returns the prompt to show for the next one.
+    pub fn next_prompt(&mut self) -> String {
+        self.wake("");
+        self.sync_jobs();
+        self.sync_hotplug();
+        // the console's kept warm while the prompt waits, unless a dump or trace stands in for it
+        if let Some(secs) = self.options.keepalive {
+            if !self.options.dry_run_trace && self.mounted.is_none() {
+                if let Some(Console::Open(player)) =
+                    self.player.take_if(|p| matches!(p, Console::Open(_)))
+                {
+                    self.keepalive.idle(player, Duration::from_secs(secs));
+                }
+            }
+        }
+        #[cfg(feature = "writing")]
+        let prompt = match self.danger.remaining(Instant::now()) {
+            Some(left) => format!("[unlocked {}m] > ", left.as_secs().div_ceil(60)),
+            None => "> ".to_string(),
+        };
+        #[cfg(feature = "writing")]
+        let prompt = match self.sandbox {
+            Some(_) => format!("[sandbox] {prompt}"),
+            None => prompt,
+        };
+        #[cfg(not(feature = "writing"))]
+        let prompt = "> ".to_string();
+        prompt
+    }
+
+    // the options as `preset`, the config file, this session's 'set' commands and the profile's
+    // pins make them, in that order
+    fn resolve_options(&self, preset: Option<&str>) -> Result<Options> {
+        let presets = all(&self.config.presets);
+        let preset = preset
+            .map(|name| find(&presets, name).map(|p| (name, p)))
+            .transpose()?;
+        let mut options = resolve(preset, &self.config.options, &self.sets)?;
+        if let Some(profile) = &self.profile {
+            profile.apply(&mut options)?;
+        }
+        Ok(options)
+    }
+
+    // whether commands are served from memory, by a mounted dump, the sandbox or the tracer,
+    // rather than by the console's card
+    fn in_memory(&self) -> bool {
+        self.mounted.is_some() || self.sandbox.is_some() || call_trace::active()
+    }
+
+    // the files on the console (or mounted dump), for 'browse'
+    #[cfg(feature = "tui")]
+    pub(crate) fn list_files(&mut self) -> Result<Vec<(String, u32)>> {
+        let in_memory = self.in_memory();
+        match source(&self.mounted, &self.sandbox, &self.player) {
+            Some(player) => self.fs_cache.list_files(&*player, in_memory),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// The token that cancels the command running at the time, for Ctrl+C handlers and anything
+    /// else that needs to stop it; it's reset before each command.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Ends the session, finishing off whatever output was being saved; fails if a console
+    /// failed 'acceptance' during it, so scripts can tell.
+    // at the end of input (Ctrl+D), whether to quit; not if there are changes to keep
+    pub fn quit_at_eof(&mut self, rl: &mut dyn Prompt) -> bool {
+        #[cfg(feature = "writing")]
+        if keep_uncommitted(self, rl) {
+            return false;
+        }
+        #[cfg(not(feature = "writing"))]
+        let _ = rl;
+        true
+    }
+
+    // takes the console back from the keep-alive before a command, saying what happened to it
+    // while idle; one that stopped answering and couldn't be reconnected is tried again, unless
+    // the command ('B', 'Q') does that itself
+    fn wake(&mut self, command: &str) {
+        let Some((mut player, health)) = self.keepalive.resume() else {
+            return;
+        };
+        match health {
+            keepalive::Health::Fine => {}
+            keepalive::Health::Reconnected(why) => {
+                println!("The console stopped answering while idle ({why}); reconnected")
+            }
+            keepalive::Health::Suspect(why) if ["B", "Q"].contains(&command) => {
+                eprintln!("The console stopped answering while idle ({why})")
+            }
+            keepalive::Health::Suspect(why) => {
+                eprintln!("The console stopped answering while idle ({why}); reconnecting");
+                match player.reconnect() {
+                    Ok(_) => println!("Reconnected"),
+                    Err(e) => eprintln!("Couldn't reconnect: {e}. Try 'Q' and 'B'."),
+                }
+            }
+        }
+        self.player = Some(Console::Open(player));
+    }
+
+    /// Ends the session: stops background jobs and finishes the output sink. Fails if a console
+    /// failed 'acceptance' during it, so the program can exit with an error.
+    pub fn finish(mut self) -> Result<()> {
+        self.wake("q");
+        // a job still running has the console, which is closed when it's let go
+        let _ = self.jobs.stop_all();
+        self.sink.finish()?;
+        if self.failed_acceptance {
+            bail!("A console failed its acceptance checks this session");
+        }
+        Ok(())
+    }
+}
+
+// whether to stay rather than lose a dump mounted with 'mount --rw' with uncommitted changes; asked
+// at 'q' and at the end of input, when there's a terminal to ask on
+#[cfg(feature = "writing")]
+fn keep_uncommitted(context: &CliContext, rl: &mut dyn Prompt) -> bool {
+    let Some(m) = context.mounted.as_ref().filter(|m| m.dirty) else {
+        return false;
+    };
+    if !stdin().is_terminal() {
+        eprintln!("{} has uncommitted changes, which are lost", m.name);
+        return false;
+    }
+    let answer = rl.readline(&format!(
+        "{} has uncommitted changes. Quit without committing them? [y/N] ",
+        m.name
+    ));
+    if matches!(answer.as_deref().map(str::trim), Ok("y" | "Y")) {
+        return false;
+    }
+    println!("Not quitting; use 'commit' to save the changes");
+    true
+}
+
+// the hash algorithms a command uses: its '--algo' (taken out of `args`) if given, or else the
+// session's 'set hash-algos', or else `default`
+fn hash_algos(
+    context: &CliContext,
+    args: &mut Vec<&str>,
+    default: HashAlgo,
+) -> Result<Vec<HashAlgo>> {
+    let flag = take_flag_value(args, "--algo")?;
+    choose(flag, context.options.hash_algos.as_deref(), default)
+}
+
+// what 'verify' is asked to do: the file to compare the console with, the ranges of it, and
+// where to save a report
+struct VerifyArgs {
+    nand: Vec<u8>,
+    ranges: Vec<Range<u16>>,
+    report: Option<String>,
+}
+
+fn verify_args(command: &[&str]) -> Result<VerifyArgs> {
+    let mut args = command.to_vec();
+    let report = take_flag_value(&mut args, "--report")?.map(str::to_string);
+    let nand = read_input(args.get(1).copied().unwrap_or("nand.bin"))?;
+    let num_blocks = (nand.len() / BLOCK_SIZE) as u16;
+    let ranges = match args.get(2) {
+        Some(r) => parse_ranges(r, num_blocks)?,
+        None => std::iter::once(0..num_blocks).collect(),
+    };
+    Ok(VerifyArgs {
+        nand,
+        ranges,
+        report,
+    })
+}
+
+// compares the console with the file, saving the report if one was asked for; the error that
+// fails the verify, if one does
+fn run_verify(
+    player: &dyn Player,
+    args: &VerifyArgs,
+    events: bool,
+    summary: &mut RangeSummary,
+    cancel: &CancelToken,
+) -> Option<String> {
+    let started_at = Local::now();
+    let result = verify_ranges(player, &args.nand, &args.ranges, events, summary, cancel);
+    if let Some(path) = &args.report {
+        VerifyReport::new("verify", player, summary, started_at, result.is_ok()).save(path);
+    }
+    match result {
+        Ok(_) if summary.total_mismatches() == 0 => None,
+        Ok(_) => Some(format!(
+            "Verify finished, but {} blocks didn't match",
+            summary.total_mismatches()
+        )),
+        Err(e) => Some(e.to_string()),
+    }
+}
+
+// runs the hooks the config file sets for after an operation, with placeholders for its `files`;
+// returns the error that fails the command, if one didn't run and the config says that should
+fn run_hooks(
+    context: &CliContext,
+    label: &str,
+    hooks: &[String],
+    player: &dyn Player,
+    files: &[(&'static str, &str)],
+) -> Option<String> {
+    if call_trace::active() {
+        if !hooks.is_empty() {
+            println!("Not running the {label} hooks in a dry run");
+        }
+        return None;
+    }
+    let guard = Guard {
+        strict_writes: context.options.strict_writes,
+        profile: context.profile.as_ref().map(|p| p.name.clone()),
+        to_archive: !matches!(context.sink, OutputSink::Files),
+    };
+    let values = hooks::values(player.GetBBID().ok(), &Local::now(), files);
+    let timeout = Duration::from_secs(context.config.hooks.timeout_secs);
+    let result = hooks::plan(hooks, &values, &guard, cfg!(windows))
+        .and_then(|commands| hooks::run_all(&commands, timeout, label));
+    let e = result
+        .err()?
+        .context(format!("The {label} hooks didn't all run"));
+    print_error(&*e, context.options.progress_events);
+    context.config.hooks.fail_command.then(|| format!("{e:#}"))
+}
+
+// lists the temporary files interrupted operations left on the card and offers to clean them up
+// (without asking, with 'set auto-clean on'); on connecting, only if there are any, and not from
+// a script, where the next line is a command rather than an answer
+#[cfg(feature = "writing")]
+fn offer_clean(context: &mut CliContext, rl: &mut dyn Prompt, connecting: bool) -> Result<()> {
+    let Some(mut player) = source_mut(
+        &mut context.mounted,
+        &mut context.sandbox,
+        &mut context.player,
+    ) else {
+        return Ok(());
+    };
+    let found = leftovers::scan(&player.ListFiles()?, &read_entries()?, player.GetBBID()?);
+    if found.leftovers.is_empty() {
+        if !connecting {
+            println!("No temporary files left on the card by {PROG_NAME}");
+        }
+        // closes the journal's entries for them
+        return match found.gone.is_empty() {
+            true => Ok(()),
+            false => leftovers::clean(&mut *player, &found),
+        };
+    }
+    println!("Temporary files left on the card by interrupted {PROG_NAME} operations:");
+    for leftover in &found.leftovers {
+        println!("  {leftover}");
+    }
+    let go = context.options.auto_clean
+        || match connecting && !stdin().is_terminal() {
+            true => false,
+            false => {
+                let answer = rl.readline("Clean them up? [y/N] ");
+                matches!(answer.as_deref().map(str::trim), Ok("y" | "Y"))
+            }
+        };
+    if !go {
+        println!("Left as they are; 'clean' offers again");
+        return Ok(());
+    }
+    leftovers::clean(&mut *player, &found)
+}
+
+// after `line` failed on the file `name`, names on the card close to it, if it isn't there; at a
+// terminal, offers to run `line` again with the closest
+fn offer_similar(context: &mut CliContext, rl: &mut dyn Prompt, line: &str, name: &str) -> Flow {
+    let in_memory = context.in_memory();
+    let Some(player) = source(&context.mounted, &context.sandbox, &context.player) else {
+        return Flow::Continue;
+    };
+    let Ok(files) = context.fs_cache.list_files(&*player, in_memory) else {
+        return Flow::Continue;
+    };
+    let names = files.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+    // it's there, so it was something else that failed
+    if names.contains(&name) {
+        return Flow::Continue;
+    }
+    let close = similar_names(name, &names);
+    if close.is_empty() {
+        return Flow::Continue;
+    }
+    eprintln!("Did you mean {}?", either(&close));
+
+    let mut ask = |top: &str| {
+        let retry = with_name(line, name, top);
+        let answer = rl.readline(&format!("Run '{retry}' instead? [y/N] "));
+        matches!(answer.as_deref().map(str::trim), Ok("y" | "Y"))
+    };
+    let offer = !context.retrying && stdin().is_terminal();
+    let Some(top) = retry_with(&close, !offer, &mut ask) else {
+        return Flow::Continue;
+    };
+    let retry = with_name(line, name, top);
+    context.retrying = true;
+    let flow = dispatch(context, rl, &retry);
+    context.retrying = false;
+    flow
+}
+
+// `line` with its first argument that's `name` replaced by `with`
+fn with_name(line: &str, name: &str, with: &str) -> String {
+    let mut command = line.split(' ').collect::<Vec<_>>();
+    if let Some(arg) = command.iter_mut().skip(1).find(|a| **a == name) {
+        *arg = with;
+    }
+    command.join(" ")
+}
+
+/// Runs one line of input as a command, asking any questions it has through `rl`. Errors are
+/// reported on stderr and the session carries on, as at the prompt.
+pub fn dispatch(context: &mut CliContext, rl: &mut dyn Prompt, line: &str) -> Flow {
+    context.wake(line.split(' ').next().unwrap_or_default());
+    context.sync_jobs();
+    context.sync_hotplug();
+    // Enter at an empty prompt takes up the offer of a console that was just plugged in
+    if let Some(index) = context.take_offer().filter(|_| line.trim().is_empty()) {
+        return select_offered(context, rl, index);
+    }
+    let command = line.split(' ').collect::<Vec<_>>();
+    if let Some(attestation) = &mut context.attestation {
+        if let Err(e) = attestation.admit(Local::now().to_rfc3339(), line) {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    }
+    if context.options.dry_run_trace {
+        match tracing(&command) {
+            Tracing::Traced => return trace_command(context, rl, line, &command),
+            Tracing::Untouched => {}
+            Tracing::Refused => {
+                eprintln!("'{}' can't be traced, as it reaches the console (or local files) other than through the calls a trace lists. Use 'set dry-run off' to run it.", command[0]);
+                return Flow::Continue;
+            }
+        }
+    }
+    let flow = run_command(context, rl, line);
+    // 'H --during' puts the LED back as soon as the command it wrapped is done
+    if context.led.restore_after_command {
+        context
+            .led
+            .restore(source(&context.mounted, &context.sandbox, &context.player).as_deref());
+    }
+    // the card may have changed without its sequence number moving yet, or be another console's
+    if changes_card(&command) || ["s", "B", "Q", "session", "sandbox"].contains(&command[0]) {
+        context.fs_cache.invalidate();
+    }
+    // whatever the command wrote is this session's own generation, not someone else's
+    if context.card.changing() {
+        let stats = source(&context.mounted, &context.sandbox, &context.player)
+            .and_then(|p| p.CardStats().ok());
+        context.card.end_change(
+            stats.as_ref().map(|s| s.seqno),
+            stats.as_ref().map(|s| s.free),
+        );
+    }
+    flow
+}
+
+// selects the console offered when it was plugged in, and initialises it if the config says to,
+// as at startup
+fn select_offered(context: &mut CliContext, rl: &mut dyn Prompt, index: usize) -> Flow {
+    let flow = dispatch(context, rl, &format!("s {index}"));
+    if flow == Flow::Continue && context.config.auto_init && context.player.is_some() {
+        return dispatch(context, rl, "B");
+    }
+    flow
+}
+
+// runs a command against the tracer instead of the console, saving nothing, and lists the calls it
+// made of it
+fn trace_command(
+    context: &mut CliContext,
+    rl: &mut dyn Prompt,
+    line: &str,
+    command: &[&str],
+) -> Flow {
+    let sink = std::mem::replace(&mut context.sink, OutputSink::Discard);
+    call_trace::start(&command[1..]);
+    let flow = run_command(context, rl, line);
+    // 'H --during' puts the LED back as soon as the command it wrapped is done
+    if context.led.restore_after_command {
+        context
+            .led
+            .restore(source(&context.mounted, &context.sandbox, &context.player).as_deref());
+    }
+    let steps = call_trace::finish();
+    context.sink = sink;
+    // nothing the tracer answered with is the card's
+    context.fs_cache.invalidate();
+    print!("{}", call_trace::render(line, &steps));
+    flow
+}
+
+// starts a command given with '&' as a background job, which takes the console with it
+fn start_job(context: &mut CliContext, command: &[&str]) -> Flow {
+    if !BACKGROUND_COMMANDS.contains(&command[0]) {
+        eprintln!(
+            "'{}' can't run in the background; only long reads of the console can ({}), never anything that changes it.",
+            command[0],
+            BACKGROUND_COMMANDS.join(", ")
+        );
+        return Flow::Continue;
+    }
+    if context.in_memory() {
+        eprintln!("Background jobs only run on the console itself, not a mounted dump or the sandbox. Run it without '&'.");
+        return Flow::Continue;
+    }
+    if context.player.is_none() {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    }
+    let options = context.options.clone();
+    let work: Work<Console> = match command[0] {
+        "verify" => {
+            let args = match verify_args(command) {
+                Ok(a) => a,
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            };
+            Box::new(move |console, cancel| {
+                let player = console.reads();
+                let mut summary = RangeSummary::default();
+                let started = Instant::now();
+                let error = run_verify(player, &args, false, &mut summary, cancel);
+                let outputs = args.report.as_deref().into_iter().collect::<Vec<_>>();
+                notify(&options, "verify", player, started, error.clone(), &outputs);
+                let verdict = if error.is_none() {
+                    "Verify success"
+                } else {
+                    ""
+                };
+                Finished {
+                    output: summary.render() + verdict,
+                    error,
+                }
+            })
+        }
+        _ => {
+            let mut args = command.to_vec();
+            let report = match take_flag_value(&mut args, "--report") {
+                Ok(r) => r.map(str::to_string),
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            };
+            Box::new(move |console, cancel| {
+                let player = console.reads();
+                let mut results = vec![];
+                let (started, started_at) = (Instant::now(), Local::now());
+                let result = scrub(player, false, &mut results, cancel);
+                if let Some(path) = &report {
+                    ScrubReport::new(player, &results, started_at, result.is_ok()).save(path);
+                }
+                let error = result.err().map(|e| e.to_string());
+                let outputs = report.as_deref().into_iter().collect::<Vec<_>>();
+                notify(&options, "scrub", player, started, error.clone(), &outputs);
+                let mut output = recommend(&results).join("\n");
+                // marking blocks bad is a write, so it's left to be done in the foreground
+                let failed = results
+                    .iter()
+                    .filter(|r| r.health == Health::Failed)
+                    .map(|r| format!("{:#X}", r.block))
+                    .collect::<Vec<_>>();
+                if cfg!(feature = "writing") && error.is_none() && !failed.is_empty() {
+                    output += &format!(
+                        "\nUse 'relocate {}' to mark the blocks that couldn't be read bad",
+                        failed.join(" ")
+                    );
+                }
+                Finished { output, error }
+            })
+        }
+    };
+    let Some(console) = context.player.take() else {
+        return Flow::Continue;
+    };
+    let id = context.jobs.spawn(&command.join(" "), console, work);
+    println!("[{id}] {} is running in the background; 'jobs' shows how it's doing, and 'fg {id}' waits for it", command.join(" "));
+    context.ops.succeed();
+    Flow::Continue
+}
+
+fn run_command(context: &mut CliContext, rl: &mut dyn Prompt, line: &str) -> Flow {
+    context.cancel.reset();
+    let mut command = line.split(' ').collect::<Vec<_>>();
+
+    if command.is_empty() {
+        return Flow::Continue;
+    }
+
+    // a trailing '&' runs the command in the background
+    let background = command.len() > 1 && command.last() == Some(&"&");
+    if background {
+        command.pop();
+    }
+    // while a job has the console, only what doesn't need it can run
+    if let Some(busy) = context.jobs.busy().filter(|_| !without_console(&command)) {
+        eprintln!("{busy}");
+        return Flow::Continue;
+    }
+
+    if context.led.restore_on_next_command {
+        context
+            .led
+            .restore(source(&context.mounted, &context.sandbox, &context.player).as_deref());
+    }
+
+    // 'H --during <command...>' flashes the LED while running the command
+    if command.len() > 2 && command[0] == "H" && command[1] == "--during" {
+        if let Some(Err(e)) = context.profile.as_ref().map(|p| p.check("H")) {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+        let Some(player) = source(&context.mounted, &context.sandbox, &context.player) else {
+            eprintln!(
+                "No console selected. Have you used the 'l' and 's' commands to select a console?"
+            );
+            return Flow::Continue;
+        };
+        context.led.flash(&*player);
+        context.led.restore_after_command = true;
+        command.drain(..2);
+    }
+
+    if context.mounted.is_some()
+        && DEVICE_ONLY_COMMANDS.contains(&command[0])
+        && !without_console(&command)
+    {
+        eprintln!(
+            "'{}' can't be used on a mounted dump. Use 'unmount' to return to the console first.",
+            command[0]
+        );
+        return Flow::Continue;
+    }
+
+    if let Some(Err(e)) = context.profile.as_ref().map(|p| p.check(command[0])) {
+        print_error(&*e, context.options.progress_events);
+        return Flow::Continue;
+    }
+
+    #[cfg(feature = "writing")]
+    if context.sandbox.is_some() && leaves_sandbox(&command) {
+        eprintln!(
+            "'{}' can't be used in the sandbox, which only holds back changes to the console's files. Use 'sandbox off' first.",
+            command[0]
+        );
+        return Flow::Continue;
+    }
+
+    if background {
+        return start_job(context, &command);
+    }
+
+    // with strict-writes, everything that writes to the card has the same preconditions to meet
+    #[cfg(feature = "writing")]
+    if let Err(e) = strict_gate(context, &mut command) {
+        print_error(&*e, context.options.progress_events);
+        return Flow::Continue;
+    }
+
+    // if another tool has changed the card since, plans and numbers from before are out of date
+    if !context.in_memory() && changes_card(&command) {
+        if let Some(Ok(stats)) =
+            source(&context.mounted, &context.sandbox, &context.player).map(|p| p.CardStats())
+        {
+            if let Some(stale) = context.card.begin_change(stats.seqno) {
+                eprintln!("{stale}");
+                if !stdin().is_terminal() {
+                    eprintln!(
+                        "Run '{}' again to go ahead with the card as it is now.",
+                        command[0]
+                    );
+                    return Flow::Continue;
+                }
+                let answer = rl.readline("Go ahead with the card as it is now? [y/N] ");
+                if !matches!(answer.as_deref().map(str::trim), Ok("y" | "Y")) {
+                    eprintln!("Cancelled");
+                    return Flow::Continue;
+                }
+            }
+        }
+    }
+
+    if !["", "report"].contains(&command[0]) {
+        context.ops.begin(line);
+    }
+
+    match command[0] {
+        "" => Flow::Continue,
+        "h" => session::show_help(&command),
+        "?" => session::about(),
+        "l" => console::list_players(context),
+        "s" => console::select(context, &command),
+        "survey" => console::survey_consoles(context, &command),
+        "device" => console::describe_device(context),
+        "B" => console::init(context, rl),
+        "I" => console::read_bbid(context),
+        "H" => console::set_led(context, &command),
+        "S" => console::sign_hash(context, &command),
+        "J" => console::set_time(context, rl, &command),
+        "K" => console::read_sksa(context, &command),
+        "L" => files::list_games(context, &command),
+        "F" => console::dump_fs(context, &command),
+        "X" => blocks::read_block(context, &command),
+        "page" => blocks::print_page(context, &command),
+        #[cfg(feature = "writing")]
+        "Y" => blocks::write_block(context, rl, &command),
+        "C" => console::card_stats(context),
+        "stats" => console::stats(context, &command),
+        "reset-usb" => console::reset_usb(context),
+        "retry-usb" => console::retry_usb(context),
+        "Q" => console::close(context),
+        "1" => blocks::dump(context, &command),
+        #[cfg(feature = "writing")]
+        "2" => blocks::write_dump(context, rl, &command, line),
+        "dupes" => files::duplicates(context, rl, &command),
+        "note" => session::note(context, &command),
+        "jobs" => session::list_jobs(context),
+        "fg" => session::foreground(context, &command),
+        "status" => session::status(context, &command),
+        "report" => session::report_ops(context, &command),
+        "acceptance" => checks::accept(context, &command),
+        "spotcheck" => checks::spot_check(context, &command),
+        "notify" => session::notify_test(context, &command),
+        "caps" => session::capabilities(context, &command),
+        "selftest" => session::self_test(),
+        "triage" => checks::triage_console(context, &command),
+        "badblocks" => checks::bad_blocks(context, rl, &command),
+        "history" => checks::fs_history(context, &command),
+        "verify" => checks::verify_dump(context, &command),
+        "scrub" => checks::scrub_card(context, rl, &command),
+        "3" => files::read_file(context, rl, &command, line),
+        "hash" => files::hash_file(context, rl, &command, line),
+        "calc" => dumps::calculate(context, &command),
+        "organize" => dumps::organize_dumps(context, rl, &command),
+        "genimage" => dumps::generate_image(context, &command),
+        "preflight" => checks::preflight_check(context),
+        "stat" => files::stat_file(context, &command),
+        "provision" => files::provision_files(context, rl, &command),
+        #[cfg(feature = "writing")]
+        "clean" => files::clean_up(context, rl),
+        #[cfg(feature = "tui")]
+        "browse" => files::browse_files(context, rl),
+        "cat" => files::cat(context, &command),
+        #[cfg(feature = "writing")]
+        "4" => files::write_file(context, rl, &command),
+        "patch" => files::patch_file(context, &command),
+        "5" => files::list_files(context, &command),
+        #[cfg(feature = "writing")]
+        "6" => files::delete_file(context, rl, &command, line),
+        #[cfg(feature = "writing")]
+        "txn" => files::transaction(context, rl, &command),
+        #[cfg(feature = "writing")]
+        "sandbox" => files::sandbox_mode(context, &command),
+        #[cfg(feature = "writing")]
+        "relocate" => blocks::relocate_blocks(context, rl, &command),
+        #[cfg(feature = "writing")]
+        "7" => files::rename_file(context, rl, &command, line),
+        "ticket" => files::tickets(context, rl, &command),
+        "preset" => session::select_preset(context, &command),
+        "set" => session::set_option(context, &command),
+        #[cfg(feature = "writing")]
+        "lock" => session::lock(context),
+        "lint" => files::check_names(context, &command),
+        "fingerprint" => checks::fingerprint_nand(context, &command),
+        "export" => dumps::export_dat(context, &command),
+        "dumpinfo" => dumps::dump_info(context, &command),
+        "mount" => dumps::mount_dump(context, &command),
+        "session" => session::session_state(context, &command),
+        "attest" => session::attest_report(context, &command),
+        "unmount" => dumps::unmount(context, rl),
+        #[cfg(feature = "writing")]
+        "commit" => dumps::commit(context),
+        "convert" => dumps::convert_image(context, &command),
+        "fsdiff" => dumps::diff_fs(context, &command),
+        "backup" => dumps::backups(context, &command),
+        "kit" => dumps::recovery_kit(context, rl, &command),
+        "dedupe-archive" => dumps::dedupe_dumps(context, &command),
+        "rehydrate" => dumps::rehydrate_archive(context, &command),
+        "finish" => console::finish_session(context),
+        "q" => session::quit(context, rl),
+
+        #[cfg(not(feature = "writing"))]
+        "Y" | "2" | "4" | "6" | "7" | "clean" | "txn" | "sandbox" | "relocate" => {
+            eprintln!("This version of {PROG_NAME} was built without support for writing; rebuild with `-F writing` to use this command.");
+            Flow::Continue
+        }
+        #[cfg(not(feature = "tui"))]
+        "browse" => {
+            eprintln!("This version of {PROG_NAME} was built without the file browser; rebuild with `-F tui` to use this command.");
+            Flow::Continue
+        }
+        _ => {
+            match help::suggest(command[0]) {
+                Some(name) => eprintln!("Invalid command. Did you mean '{name}'? Type 'h' for a list of valid commands."),
+                None => eprintln!("Invalid command. Type 'h' for a list of valid commands."),
+            }
+            Flow::Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use std::collections::VecDeque;
+
+    use crate::genimage::{generate, Spec};
+
+    // a session saved and loaded into a fresh one comes back as it was, and one that can only be
+    // partly restored still has the rest restored
+    #[test]
+    fn session_round_trip() -> Result<()> {
+        let dir = TestDir::new("session")?;
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let (nand, spare, saved) = (path("nand.bin"), path("spare.bin"), path("session.json"));
+        let generated = generate(
+            &Spec {
+                blocks: 0x80,
+                ..Spec::default()
+            },
+            &|path| bail!("{path}: no local files"),
+        )?;
+        write_atomic(&nand, &generated.nand)?;
+        write_atomic(&spare, &generated.spare)?;
+        write_atomic(path("NEW.app"), &[7; 0x100])?;
+
+        let mut rl = VecDeque::new();
+        let mut run = |context: &mut CliContext, line: &str| {
+            dispatch(context, &mut rl, line);
+        };
+        let mut context = CliContext::default();
+        let mount = if cfg!(feature = "writing") {
+            "mount --rw"
+        } else {
+            "mount"
+        };
+        run(&mut context, &format!("{mount} {nand} {spare}"));
+        run(&mut context, "set keepalive 30");
+        run(&mut context, "set lint off");
+        #[cfg(feature = "writing")]
+        {
+            run(&mut context, "txn begin");
+            run(&mut context, &format!("txn add 4 {}", path("NEW.app")));
+            run(&mut context, "txn add 6 OLD.app");
+        }
+        run(&mut context, &format!("session save {saved}"));
+
+        let mut loaded = CliContext::default();
+        run(&mut loaded, &format!("session load {saved}"));
+        assert_eq!(loaded.options.keepalive, Some(30));
+        assert!(!loaded.options.lint);
+        let mounted = loaded.mounted.as_ref().map(|m| m.source_files());
+        assert_eq!(
+            mounted,
+            Some((nand.as_str(), spare.as_str(), cfg!(feature = "writing")))
+        );
+        #[cfg(feature = "writing")]
+        {
+            assert_eq!(loaded.txn, context.txn);
+            assert!(loaded.txn_unconfirmed.is_empty());
+        }
+
+        // the dump's gone and the console isn't here, but the options and transaction aren't
+        // lost with them; an upload that's changed since has to be confirmed
+        let mut snapshot = Snapshot::load(&saved)?;
+        snapshot
+            .mounted
+            .iter_mut()
+            .for_each(|m| m.nand = path("gone.bin"));
+        snapshot.bbid = Some(0x1234);
+        snapshot.save(&saved)?;
+        write_atomic(path("NEW.app"), &[8; 0x100])?;
+        let mut partial = CliContext::default();
+        // without USB, so the console it had isn't looked for
+        partial.set_usb(Box::new(|| Err(rusb::Error::NotSupported)));
+        run(&mut partial, &format!("session load {saved}"));
+        assert!(partial.mounted.is_none() && partial.player.is_none());
+        assert_eq!(partial.options.keepalive, Some(30));
+        #[cfg(feature = "writing")]
+        {
+            assert_eq!(partial.txn.as_ref().map(Vec::len), Some(2));
+            assert_eq!(partial.txn_unconfirmed.len(), 1);
+        }
+
+        // and one from a newer version isn't taken at all
+        let newer = serde_json::to_string(&Snapshot {
+            schema: SESSION_SCHEMA + 1,
+            options: Options::default(),
+            ..Snapshot::default()
+        })?;
+        write_atomic(&saved, newer.as_bytes())?;
+        run(&mut partial, &format!("session load {saved}"));
+        assert_eq!(partial.options.keepalive, Some(30));
+        Ok(())
+    }
+}
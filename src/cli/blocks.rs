@@ -0,0 +1,1034 @@
+use super::*;
+
+// The commands that read and write the card block by block, beneath its FS.
+
+// 'X': reading one block and its spare data
+pub(super) fn read_block(context: &mut CliContext, command: &[&str]) -> Flow {
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        let heroic = command.contains(&"--heroic");
+        let command = command
+            .iter()
+            .copied()
+            .filter(|a| !a.starts_with("--"))
+            .collect::<Vec<_>>();
+        if command.len() < 4 {
+            eprintln!("'X' requires three arguments, 'blkno', 'nand' and 'spare'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+        if let Err(e) = check_distinct(&[("nand", command[2]), ("spare", command[3])]) {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+        let blk_num: u32 = match command[1].parse() {
+            Ok(v) => v,
+            Err(e) => {
+                print_error(&e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        let read = if heroic {
+            heroic_read(&*player, blk_num, &context.cancel).map(|(n, s, outcome)| {
+                    match outcome {
+                        ReadOutcome::Clean => {}
+                        ReadOutcome::Rescued { reads, bits } => println!("Rescued {bits} bits by voting across {reads} reads; the result passes its ECC"),
+                        ReadOutcome::Unrescued { reads } => eprintln!("The block still fails its ECC after {reads} reads; saving the first"),
+                    }
+                    (n, s)
+                })
+        } else {
+            player.ReadSingleBlock(blk_num)
+        };
+        let (nand, spare) = match read {
+            Ok(ns) => ns,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        match context.sink.put(command[2], &nand) {
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{e}")
+            }
+        }
+        match context.sink.put(command[3], &spare) {
+            Ok(_) => {
+                println!("ReadSingleBlock success")
+            }
+            Err(e) => {
+                eprintln!("{e}")
+            }
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'page': printing a page of a block as a hex dump
+pub(super) fn print_page(context: &mut CliContext, command: &[&str]) -> Flow {
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        if command.len() < 3 {
+            eprintln!("'page' requires two arguments, 'blk' and 'page'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+        let blocks = match card_blocks(&*player) {
+            Ok(b) => b,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        let (blk, page) = match parse_page(command[1], command[2], blocks) {
+            Ok(bp) => bp,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        let (nand, spare) = match player.ReadSingleBlock(blk) {
+            Ok(ns) => ns,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        let Some(out) = command.get(3) else {
+            print!("{}", render_page(blk, page, &nand, &spare));
+            return Flow::Continue;
+        };
+        if let Err(e) = context.sink.put(out, slice_page(&nand, page)) {
+            eprintln!("{e}");
+            return Flow::Continue;
+        }
+        println!(
+            "Saved page {page} of block {blk:#X} to {out}, cut from a read of the whole block"
+        );
+        // the block's spare data is the first page's
+        if page == 0 {
+            let spare_out = format!("{out}.spare");
+            match context.sink.put(&spare_out, &spare) {
+                Ok(_) => println!("Saved its spare data to {spare_out}"),
+                Err(e) => eprintln!("{e}"),
+            }
+        } else {
+            println!("Only the first page's spare data is kept, so there's none for page {page}");
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'Y': writing one block and its spare data
+#[cfg(feature = "writing")]
+pub(super) fn write_block(context: &mut CliContext, rl: &mut dyn Prompt, command: &[&str]) -> Flow {
+    if let Some(Console::Open(player)) = &mut context.player {
+        let force = command.contains(&"--force");
+        let args = command
+            .iter()
+            .copied()
+            .filter(|a| !a.starts_with("--"))
+            .collect::<Vec<_>>();
+        if args.len() < 4 {
+            eprintln!("'Y' requires three arguments, 'blkno', 'nand' and 'spare'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+        if let Err(e) = check_distinct(&[("nand", args[2]), ("spare", args[3])]) {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+        let blk_num: u32 = match args[1].parse() {
+            Ok(v) => v,
+            Err(e) => {
+                print_error(&e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        // the FS addresses blocks with 16 bits, and the range ending after the block must fit too
+        let Some(block) = u16::try_from(blk_num)
+            .ok()
+            .and_then(|b| Some(b..b.checked_add(1)?))
+        else {
+            eprintln!(
+                "There's no block {blk_num:#X}; blocks go up to {:#X}",
+                u16::MAX - 1
+            );
+            return Flow::Continue;
+        };
+        let num_blocks = card_blocks(player).unwrap_or(0) as u16;
+        let b = blk_num as usize;
+        let nand = match load_input(args[2], b * BLOCK_SIZE, BLOCK_SIZE, None) {
+            Ok(n) => n,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        let spare = match load_input(args[3], b * SPARE_SIZE, SPARE_SIZE, None) {
+            Ok(s) => s,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        // whether the SA chain reaches a block of the SKSA depends on the blocks before it, as
+        // the console has them
+        let chain = match blk_num < SKSA_BLOCKS as u32 {
+            true => {
+                let mut spares = vec![0xFF; SKSA_BLOCKS as usize * SPARE_SIZE];
+                for (blk, to) in (0..SKSA_BLOCKS as u32).zip(spares.chunks_exact_mut(SPARE_SIZE)) {
+                    match blk == blk_num {
+                        true => to.copy_from_slice(&spare),
+                        false => match player.ReadSingleBlock(blk) {
+                            Ok((_, existing)) => to.copy_from_slice(&existing[..SPARE_SIZE]),
+                            Err(e) => {
+                                print_error(&*e, context.options.progress_events);
+                                return Flow::Continue;
+                            }
+                        },
+                    }
+                }
+                sa_chain(&spares)
+            }
+            false => std::collections::BTreeSet::new(),
+        };
+        let conflict = role_conflict(block.start, num_blocks, &nand, &spare, &chain);
+        if !allow_conflicts(conflict.as_slice(), force) {
+            return Flow::Continue;
+        }
+        if touches_protected(&block, num_blocks) {
+            preview_block(&*player, block.start, num_blocks, &nand);
+            if let Err(e) = confirm_dangerous(
+                rl,
+                &mut context.danger,
+                player,
+                &format!("Block {blk_num:#X} is in the SKSA or FS region"),
+                command,
+            ) {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        }
+        context.post_state.wrote_blocks();
+        match player.WriteSingleBlock(blk_num, &nand, &spare) {
+            Ok(_) => {
+                let problem = fscheck::overlaps_fs(std::slice::from_ref(&block), num_blocks)
+                    .then(|| post_write_fscheck(&context.options, &*player, None, None))
+                    .flatten();
+                match problem {
+                    None => {
+                        println!("WriteSingleBlock success");
+                        context.ops.succeed();
+                    }
+                    Some(p) => context.ops.fail(
+                        &p,
+                        Some(format!("after writing block {blk_num:#X}")),
+                        Instant::now(),
+                    ),
+                }
+            }
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                context.ops.fail(
+                    &e.to_string(),
+                    Some(format!("while writing block {blk_num:#X}")),
+                    Instant::now(),
+                );
+            }
+        };
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// '1': dumping the whole NAND, or ranges of it
+pub(super) fn dump(context: &mut CliContext, command: &[&str]) -> Flow {
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        let crcs = command.contains(&"--crcs");
+        let with_manifest = command.contains(&"--manifest") || context.options.manifest;
+        let mut args = command.to_vec();
+        // the sidecar is CRC-32s and the manifest SHA-256s unless asked otherwise
+        let session = context.options.hash_algos.as_deref();
+        let algos = take_flag_value(&mut args, "--algo").and_then(|flag| {
+            Ok((
+                choose(flag, session, HashAlgo::Crc32)?,
+                choose(flag, session, HashAlgo::Sha256)?,
+            ))
+        });
+        let (crc_algos, manifest_algos) = match algos {
+            Ok(a) => a,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        let args = args
+            .iter()
+            .copied()
+            .filter(|a| !a.starts_with("--"))
+            .collect::<Vec<_>>();
+        let (nand_filename, spare_filename) = if args.len() < 3 {
+            ("nand.bin", "spare.bin")
+        } else {
+            (args[1], args[2])
+        };
+        // with 'set compress', the dump is saved compressed unless its name says otherwise
+        let (nand_filename, spare_filename) = (
+            with_codec(nand_filename, context.options.compress),
+            with_codec(spare_filename, context.options.compress),
+        );
+        let (nand_filename, spare_filename) = (&*nand_filename, &*spare_filename);
+        if let Err(e) = check_distinct(&[("nand", nand_filename), ("spare", spare_filename)]) {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+        let started = Instant::now();
+        let led = LedGuard::start(&*player, &mut context.led, context.options.led_feedback);
+        let (nand, spare) = match dump_nand(
+            &*player,
+            context.options.progress_events,
+            command.contains(&"--heroic"),
+            &context.cancel,
+        ) {
+            Ok(ns) => {
+                led.succeed();
+                println!("DumpNAND success");
+                ns
+            }
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                context.ops.fail(&e.to_string(), None, Instant::now());
+                notify(
+                    &context.options,
+                    "1",
+                    &*player,
+                    started,
+                    Some(e.to_string()),
+                    &[],
+                );
+                return Flow::Continue;
+            }
+        };
+        match context.sink.put(nand_filename, &nand) {
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{e}")
+            }
+        }
+        match context.sink.put(spare_filename, &spare) {
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{e}")
+            }
+        }
+        let crcs_filename = format!("{nand_filename}.crcs");
+        if crcs {
+            let csv = sidecar_to_csv(&block_hashes(&nand, &crc_algos));
+            match context.sink.put(&crcs_filename, csv.as_bytes()) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("{e}")
+                }
+            }
+        }
+        // records which console the dump is from, so '2' can tell if it's written elsewhere
+        let manifest_filename = manifest_path(nand_filename);
+        if with_manifest {
+            let text = dump_manifest(
+                player.GetBBID().ok(),
+                Some(&Local::now().to_rfc3339()),
+                &[(nand_filename, &nand), (spare_filename, &spare)],
+                &manifest_algos,
+            );
+            match context.sink.put(&manifest_filename, text.as_bytes()) {
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("{e}")
+                }
+            }
+        }
+        let mut outputs = vec![nand_filename, spare_filename];
+        if crcs {
+            outputs.push(&crcs_filename);
+        }
+        if with_manifest {
+            outputs.push(&manifest_filename);
+        }
+        let mut files = vec![("nand", nand_filename), ("spare", spare_filename)];
+        if crcs {
+            files.push(("crcs", &crcs_filename));
+        }
+        if with_manifest {
+            files.push(("manifest", &manifest_filename));
+        }
+        let hooks = &context.config.hooks.post_dump;
+        let error = run_hooks(context, "post_dump", hooks, &*player, &files);
+        context.ops.record(error.as_deref(), None);
+        notify(&context.options, "1", &*player, started, error, &outputs);
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// '2': writing a dump back to the card, whole or in ranges
+#[cfg(feature = "writing")]
+pub(super) fn write_dump(
+    context: &mut CliContext,
+    rl: &mut dyn Prompt,
+    command: &[&str],
+    line: &str,
+) -> Flow {
+    if let Some(Console::Open(player)) = &mut context.player {
+        let verify = command.contains(&"--verify") || context.options.verify_writes;
+        let mut command = command.to_vec();
+        // the strict-writes gate has checked the files against '--manifest' already, and
+        // '--bbid' against the console
+        let (report, manifest, _) = match (
+            take_flag_value(&mut command, "--report"),
+            take_flag_value(&mut command, "--manifest"),
+            take_flag_value(&mut command, "--bbid"),
+        ) {
+            (Ok(r), Ok(m), Ok(b)) => (r, m, b),
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        let restore = match take_flag_value(&mut command, "--restore") {
+            Ok(r) => r,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        if report.is_some() && !verify {
+            eprintln!("'--report' requires '--verify'.");
+            return Flow::Continue;
+        }
+        let args = command
+            .iter()
+            .copied()
+            .filter(|a| !a.starts_with("--"))
+            .collect::<Vec<_>>();
+        let Some((nand_filename, spare_filename, ranges_arg)) =
+            write_args(&args[1..], command.contains(&"--no-spare"))
+        else {
+            eprintln!(
+                "Too many arguments for '2'. Type 'h' for a list of commands and their arguments."
+            );
+            return Flow::Continue;
+        };
+        let no_spare = spare_filename == "-";
+        if !no_spare {
+            if let Err(e) = check_distinct(&[("nand", nand_filename), ("spare", spare_filename)]) {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        }
+
+        // a HEX/SREC image only covers some blocks, so it's laid out over the whole card
+        let records = RecordFormat::from_path(nand_filename).is_some();
+        let raw_nand = if records {
+            None
+        } else {
+            match read_input(nand_filename) {
+                Ok(n) => Some(n),
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            }
+        };
+
+        let mut num_blocks = match &raw_nand {
+            Some(n) => (n.len() / BLOCK_SIZE) as u16,
+            None => match card_blocks(player) {
+                Ok(b) => b as u16,
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            },
+        };
+        let mut ranges = match ranges_arg.map(|r| parse_ranges(r, num_blocks)).transpose() {
+            Ok(r) => r,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        if command.contains(&"--interactive") {
+            let Some(image) = raw_nand.as_deref().filter(|_| ranges.is_none()) else {
+                eprintln!("'--interactive' can't be used with [ranges] or a .hex or .srec image");
+                return Flow::Continue;
+            };
+            let mut builder =
+                RangeBuilder::new(image, ThroughputStats::load(), player.GetBBID().ok());
+            println!("{}", builder.start());
+            ranges = loop {
+                let line = match rl.readline(builder.prompt()) {
+                    Ok(l) => l,
+                    Err(_) => break None,
+                };
+                match builder.handle(&line) {
+                    Step::Continue(output) => println!("{output}"),
+                    Step::Done(r) => break Some(r),
+                    Step::Cancelled => break None,
+                }
+            };
+            if ranges.is_none() {
+                eprintln!("Cancelled");
+                return Flow::Continue;
+            }
+        }
+        let mut nand = match (raw_nand, &ranges) {
+            (Some(n), _) => n,
+            (None, None) => {
+                eprintln!("A .hex or .srec image can only be written to the [ranges] it's for; give them after the files.");
+                return Flow::Continue;
+            }
+            (None, Some(r)) => {
+                match load_input(
+                    nand_filename,
+                    0,
+                    num_blocks as usize * BLOCK_SIZE,
+                    Some(&byte_ranges(r, BLOCK_SIZE)),
+                ) {
+                    Ok(n) => n,
+                    Err(e) => {
+                        print_error(&*e, context.options.progress_events);
+                        return Flow::Continue;
+                    }
+                }
+            }
+        };
+        let mut spare_file = if no_spare {
+            None
+        } else {
+            let allowed = ranges.as_ref().map(|r| byte_ranges(r, SPARE_SIZE));
+            match load_input(
+                spare_filename,
+                0,
+                num_blocks as usize * SPARE_SIZE,
+                allowed.as_deref(),
+            ) {
+                Ok(n) => Some(n),
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            }
+        };
+
+        let manifest = match manifest.map(Manifest::load).transpose() {
+            Ok(m) => m,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        // a dump saved with each 16-bit word byte-swapped would leave the console unbootable
+        if command.contains(&"--byteswap") {
+            swap16(&mut nand);
+        } else if ranges.is_none() {
+            match detect_orientation(&nand) {
+                    Orientation::Normal => {}
+                    Orientation::Swapped => {
+                        eprintln!("{nand_filename} looks byte-swapped: its FS blocks only check out with each pair of bytes swapped, as some dumpers save them.");
+                        eprintln!("Add '--byteswap' to swap it back as it's written, or fix the file with 'convert {nand_filename} <out> --byteswap'.");
+                        return Flow::Continue;
+                    }
+                    Orientation::Ambiguous { normal, swapped } => eprintln!("Note: couldn't tell whether {nand_filename} is byte-swapped ({normal} FS blocks check out as it is, {swapped} swapped); writing it as it is."),
+                    Orientation::Unknown => eprintln!("Note: {nand_filename} has no valid FS blocks either way round, so it couldn't be checked for byte-swapping."),
+                }
+        }
+
+        // a manifest from '1 --manifest' beside the dump is used without being asked for,
+        // but only for where the dump came from; its hashes are checked when it's given
+        let beside = || Manifest::load(&manifest_path(nand_filename)).ok();
+        let origin = match manifest
+            .as_ref()
+            .and_then(|m| m.source_bbid)
+            .or_else(|| beside()?.source_bbid)
+        {
+            Some(bbid) => Some(Origin {
+                bbid,
+                confidence: Confidence::Certain,
+            }),
+            None => origin_from_tickets(&ticket_bbids(&nand, spare_file.as_deref())),
+        };
+        match policy(
+            origin,
+            player.GetBBID().ok(),
+            context.options.strict_writes,
+            command.contains(&"--cross-console"),
+        ) {
+            Verdict::Proceed => {}
+            Verdict::Warn(w) => eprintln!("Warning: {w}"),
+            Verdict::Refuse(e) => {
+                eprintln!("{e}");
+                return Flow::Continue;
+            }
+        }
+
+        // a dump of a different size of card can only be partly written; a .hex or .srec
+        // image is already laid out over this card
+        let card = if records {
+            Ok(num_blocks)
+        } else {
+            card_blocks(player).map(|b| b as u16)
+        };
+        let card = match card {
+            Ok(c) => c,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        if card != num_blocks {
+            let fitting = fitting_end(num_blocks, card);
+            if let Some(r) = &ranges {
+                if let Some(r) = r.iter().find(|r| !range_fits(r, num_blocks, card)) {
+                    eprintln!("{}", explain(num_blocks, card));
+                    eprintln!("{} reaches one of the FS regions; ranges written straight across must end by block {fitting:#X}.", format_range(r));
+                    return Flow::Continue;
+                }
+            } else {
+                eprintln!("{}", explain(num_blocks, card));
+                let options = restore_options(num_blocks, card);
+                eprintln!("What can be written instead:");
+                for o in &options {
+                    eprintln!("  {:<5} {}", o.name, o.description);
+                }
+                eprintln!("  or [ranges] ending by block {fitting:#X}, written to the same blocks");
+                let answer = match restore {
+                    Some(r) => r.to_string(),
+                    None if stdin().is_terminal() => rl
+                        .readline("Write which of these? (e.g. 'sksa data fs'; nothing to cancel) ")
+                        .unwrap_or_default(),
+                    None => {
+                        eprintln!(
+                            "Add '--restore <parts>' (e.g. '--restore sksa,data,fs') to choose."
+                        );
+                        return Flow::Continue;
+                    }
+                };
+                let names = answer
+                    .split([',', ' '])
+                    .map(str::trim)
+                    .filter(|n| !n.is_empty())
+                    .collect::<Vec<_>>();
+                if names.is_empty() {
+                    eprintln!("Cancelled");
+                    return Flow::Continue;
+                }
+                let chosen = names
+                    .iter()
+                    .map(|n| options.iter().find(|o| o.name == *n).ok_or(n))
+                    .collect::<Result<Vec<_>, _>>();
+                let chosen = match chosen {
+                    Ok(c) => c,
+                    Err(n) => {
+                        eprintln!("'{n}' isn't one of the parts that can be written");
+                        return Flow::Continue;
+                    }
+                };
+                let laid_out = lay_out(&nand, BLOCK_SIZE, card, &chosen).and_then(|n| {
+                    let spare = spare_file
+                        .as_ref()
+                        .map(|s| lay_out(s, SPARE_SIZE, card, &chosen))
+                        .transpose()?;
+                    Ok((n, spare))
+                });
+                match laid_out {
+                    Ok((n, s)) => {
+                        nand = n;
+                        spare_file = s;
+                    }
+                    Err(e) => {
+                        print_error(&*e, context.options.progress_events);
+                        return Flow::Continue;
+                    }
+                }
+                ranges = Some(chosen.iter().map(|o| o.destination()).collect());
+                num_blocks = card;
+            }
+        }
+
+        // a dump laid out around another card's bad blocks would put files on this one's
+        let asked = ranges
+            .clone()
+            .unwrap_or_else(|| std::iter::once(0..num_blocks).collect());
+        // blocks the user trusts after an audit count as good here
+        let trusted = Overrides::trusted_on(&*player);
+        let compared = newest_fs(player, card).map(|(_, fs)| {
+            compare_bad(
+                &source_bad(&nand, spare_file.as_deref()),
+                &effective_bad(&fat_bad(&fs, card), &trusted),
+                &asked,
+            )
+        });
+        match compared {
+            Ok(d) if d.is_empty() => {}
+            Ok(d) => {
+                for line in explain_bad(&d, image_fs(&nand).map(|(_, fs)| fs).as_ref()) {
+                    eprintln!("{line}");
+                }
+                let choice = if command.contains(&"--skip-foreign-bad") {
+                    "s".to_string()
+                } else if command.contains(&"--remap") {
+                    "r".to_string()
+                } else if stdin().is_terminal() {
+                    rl.readline("Skip those blocks, remap the files off this card's bad blocks, or abort? [s/r/A] ").unwrap_or_default()
+                } else {
+                    eprintln!("Add '--skip-foreign-bad' to leave out the blocks either marks bad, or '--remap' to move the files off this card's bad blocks.");
+                    return Flow::Continue;
+                };
+                match choice.trim() {
+                    "s" | "S" => {
+                        let kept = skip_bad(&asked, &d);
+                        println!(
+                            "Leaving out {} blocks",
+                            asked.iter().map(|r| r.len()).sum::<usize>()
+                                - kept.iter().map(|r| r.len()).sum::<usize>()
+                        );
+                        ranges = Some(kept);
+                    }
+                    "r" | "R" => match remap(&mut nand, spare_file.as_deref_mut(), &d, &asked) {
+                        Ok(remapped) => {
+                            println!("Remapping:");
+                            for line in describe_remap(&remapped) {
+                                println!("{line}");
+                            }
+                            ranges = Some(remapped.ranges);
+                        }
+                        Err(e) => {
+                            print_error(&*e, context.options.progress_events);
+                            return Flow::Continue;
+                        }
+                    },
+                    _ => {
+                        eprintln!("Cancelled");
+                        return Flow::Continue;
+                    }
+                }
+            }
+            Err(e) => eprintln!(
+                "Note: couldn't read the card's FS to compare its bad blocks with the image's: {e}"
+            ),
+        }
+
+        // the dump require-backup found, for the post-write FS check to point at
+        let mut backup_manifest = None;
+        let protected = ranges
+            .as_ref()
+            .is_none_or(|r| r.iter().any(|r| touches_protected(r, num_blocks)));
+        if protected {
+            // a write that can lose what's on the card needs a recent dump of it to go back to
+            if let Some(hours) = context.options.require_backup {
+                let dir = context
+                    .options
+                    .backup_dir
+                    .clone()
+                    .unwrap_or_else(|| ".".to_string());
+                let bbid = match player.GetBBID() {
+                    Ok(b) => b,
+                    Err(e) => {
+                        eprintln!("require-backup: couldn't read the console's BBID to look for its dump: {e}");
+                        return Flow::Continue;
+                    }
+                };
+                match recent_backup(Path::new(&dir), bbid, hours) {
+                    Ok(backup) => {
+                        println!("Backed up in {}", backup.manifest.display());
+                        backup_manifest = Some(backup.manifest);
+                    }
+                    Err(e) => {
+                        eprintln!("require-backup: {e}.");
+                        let dump = stdin().is_terminal() && {
+                            let answer = rl.readline("Dump the console now, then write? [y/N] ");
+                            matches!(answer.as_deref().map(str::trim), Ok("y" | "Y"))
+                        };
+                        if !dump {
+                            eprintln!("Dump it first with '1 --manifest' into {dir}, or use 'set require-backup off'.");
+                            return Flow::Continue;
+                        }
+                        // the write is run again once the dump is made, and checks it like any other
+                        let (nand, spare) = dump_names(&dir, bbid);
+                        if run_command(context, rl, &format!("1 {nand} {spare} --manifest"))
+                            == Flow::Quit
+                        {
+                            return Flow::Quit;
+                        }
+                        return run_command(context, rl, line);
+                    }
+                }
+            }
+            let all = std::iter::once(0..num_blocks).collect::<Vec<_>>();
+            preview_image(
+                &*player,
+                &nand,
+                ranges.as_deref().unwrap_or(&all),
+                num_blocks,
+            );
+            if let Err(e) = confirm_dangerous(
+                rl,
+                &mut context.danger,
+                player,
+                "This write covers the SKSA or FS region",
+                &command,
+            ) {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        }
+
+        // without a selection, the whole image is written in one go
+        let bulk = ranges.is_none();
+        let ranges = ranges.unwrap_or_else(|| std::iter::once(0..num_blocks).collect());
+
+        // a write over much of the card may cost blocks erase cycles they don't need to
+        let differing = if command.contains(&"--prescan") {
+            println!("Reading the console to find the blocks that differ");
+            let mut summary = RangeSummary::default();
+            match verify_ranges(
+                &*player,
+                &nand,
+                &ranges,
+                context.options.progress_events,
+                &mut summary,
+                &context.cancel,
+            ) {
+                Ok(_) => Some(
+                    summary
+                        .outcomes
+                        .into_iter()
+                        .flat_map(|o| o.mismatches.unwrap_or_default())
+                        .collect(),
+                ),
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            }
+        } else {
+            None
+        };
+        let blocks = ranges.iter().map(|r| r.len()).sum();
+        if let Some(advisory) = assess(&context.config.wear, blocks, num_blocks as usize, differing)
+        {
+            eprintln!("{advisory}");
+            if advisory.confirm && !command.contains(&"--accept-wear") {
+                if !stdin().is_terminal() {
+                    eprintln!(
+                        "Add '--accept-wear' to write it anyway when not running interactively."
+                    );
+                    return Flow::Continue;
+                }
+                let answer = rl.readline("Write all of these blocks anyway? [y/N] ");
+                if !matches!(answer.as_deref().map(str::trim), Ok("y" | "Y")) {
+                    eprintln!("Cancelled");
+                    return Flow::Continue;
+                }
+            }
+        }
+
+        if !preflight_passes(
+            rl,
+            &*player,
+            &context.options,
+            &context.cancel,
+            (blocks * BLOCK_SIZE) as u64,
+            &command,
+        ) {
+            return Flow::Continue;
+        }
+
+        let spare = match spare_file {
+            Some(s) => s,
+            None => match synthesize_spares(
+                player,
+                &nand,
+                &ranges,
+                &trusted,
+                context.options.progress_events,
+            ) {
+                Ok(s) => s,
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            },
+        };
+
+        let conflicts = role_conflicts(
+            ranges.iter().flat_map(|r| r.clone()),
+            num_blocks,
+            &nand,
+            &spare,
+        );
+        if !allow_conflicts(&conflicts, command.contains(&"--force")) {
+            return Flow::Continue;
+        }
+
+        context.post_state.wrote_blocks();
+        let started = Instant::now();
+        let led = LedGuard::start(player, &mut context.led, context.options.led_feedback);
+        // the bulk write has its own progress output, so it can only be used when
+        // neither verification nor progress events are wanted
+        if bulk && !verify && !context.options.progress_events {
+            let error = match player.WriteNANDSpare(&nand, &spare, None) {
+                // the whole card, so the FS region with it
+                Ok(_) => match post_write_fscheck(
+                    &context.options,
+                    &*player,
+                    fscheck::expected_seqno(&nand, &ranges, num_blocks),
+                    backup_manifest.as_deref(),
+                ) {
+                    None => {
+                        led.succeed();
+                        println!("WriteNAND success");
+                        None
+                    }
+                    problem => problem,
+                },
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    Some(e.to_string())
+                }
+            };
+            context.ops.record(error.as_deref(), None);
+            notify(&context.options, "2", player, started, error, &[]);
+            return Flow::Continue;
+        }
+
+        let mut summary = RangeSummary::default();
+        let started_at = Local::now();
+        let result = write_ranges(
+            player,
+            &nand,
+            &spare,
+            &ranges,
+            verify,
+            context.options.progress_events,
+            &mut summary,
+            &context.cancel,
+        );
+        summary.print();
+        if let Some(path) = report {
+            VerifyReport::new("write-verify", player, &summary, started_at, result.is_ok())
+                .save(path);
+        }
+        let error = match result {
+            Ok(_) if summary.total_mismatches() > 0 => Some(format!(
+                "WriteNAND finished, but {} blocks failed verification",
+                summary.total_mismatches()
+            )),
+            Ok(_) => fscheck::overlaps_fs(&ranges, num_blocks)
+                .then(|| {
+                    post_write_fscheck(
+                        &context.options,
+                        &*player,
+                        fscheck::expected_seqno(&nand, &ranges, num_blocks),
+                        backup_manifest.as_deref(),
+                    )
+                })
+                .flatten(),
+            Err(e) => Some(e.to_string()),
+        };
+        if error.is_none() {
+            led.succeed();
+            println!("WriteNAND success");
+        }
+        if let Some(e) = &error {
+            eprintln!("{e}");
+        }
+        context
+            .ops
+            .record(error.as_deref(), summary.failure_context("writing"));
+        notify(&context.options, "2", player, started, error, &[]);
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'relocate': moving data off blocks that are going bad
+#[cfg(feature = "writing")]
+pub(super) fn relocate_blocks(
+    context: &mut CliContext,
+    rl: &mut dyn Prompt,
+    command: &[&str],
+) -> Flow {
+    let Some(Console::Open(player)) = &mut context.player else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    let blocks = command[1..]
+        .iter()
+        .filter(|a| !a.starts_with("--"))
+        .map(|b| parse_int::parse::<u16>(b))
+        .collect::<Result<Vec<_>, _>>();
+    let blocks = match blocks {
+        Ok(b) if !b.is_empty() => b,
+        Ok(_) => {
+            eprintln!("'relocate' requires an argument, 'blkno'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+        Err(e) => {
+            print_error(&e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    if let Ok(num_blocks) = card_blocks(player) {
+        preview_relocate(&*player, &blocks, num_blocks as u16);
+    }
+    if let Err(e) = confirm_dangerous(
+        rl,
+        &mut context.danger,
+        player,
+        "Relocating blocks writes a new generation to the FS region",
+        command,
+    ) {
+        print_error(&*e, context.options.progress_events);
+        return Flow::Continue;
+    }
+    context.post_state.wrote_blocks();
+    let result = relocate(player, &blocks, command.contains(&"--heroic")).and_then(|seqno| {
+        match post_write_fscheck(&context.options, &*player, Some(seqno), None) {
+            None => Ok(seqno),
+            Some(problem) => Err(anyhow!(problem)),
+        }
+    });
+    match &result {
+        Ok(_) => println!(
+            "Relocated {} blocks; use 'finish' to reopen the console and check its FS",
+            blocks.len()
+        ),
+        Err(e) => eprintln!("{e}"),
+    }
+    let error = result.err().map(|e| e.to_string());
+    context.ops.record(
+        error.as_deref(),
+        error.is_some().then(|| {
+            "while relocating blocks; see the write journal for the steps that were done"
+                .to_string()
+        }),
+    );
+    Flow::Continue
+}
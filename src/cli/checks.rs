@@ -0,0 +1,481 @@
+use super::*;
+
+// The commands that check a card's health, or that it holds what it should.
+
+// 'acceptance': checking a console against a directory of expected images
+pub(super) fn accept(context: &mut CliContext, command: &[&str]) -> Flow {
+    let Some(dir) = command.get(1) else {
+        eprintln!("'acceptance' requires an argument, 'imagedir'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    };
+    let Some(player) = source(&context.mounted, &context.sandbox, &context.player) else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    let policy = &context.config.acceptance;
+    let started = Local::now();
+    let findings = match acceptance::gather(
+        &*player,
+        dir,
+        policy,
+        context.options.progress_events,
+        &context.cancel,
+    ) {
+        Ok(f) => f,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            context.ops.record(Some(&e.to_string()), None);
+            context.failed_acceptance = true;
+            return Flow::Continue;
+        }
+    };
+    let results = acceptance::evaluate(policy, &findings);
+    let passed = acceptance::verdict(&results);
+    for r in &results {
+        let outcome = format!("{:?}", r.outcome).to_uppercase();
+        println!("{:<8} {outcome:<8} {}", r.check.to_string(), r.detail);
+    }
+    let message = match passed {
+        true => "PASS: the console is as the image says it should be",
+        false => "FAIL: the console didn't pass its acceptance checks",
+    };
+    if stdout().is_terminal() {
+        println!("\x1b[{}m{message}\x1b[0m", if passed { 32 } else { 31 });
+    } else {
+        println!("{message}");
+    }
+
+    let finished = Local::now();
+    let name = acceptance::report_name(player.GetBBID().ok(), &finished);
+    let report_dir = policy.report_dir.as_deref().unwrap_or(dir);
+    let path = Path::new(report_dir).join(name);
+    AcceptanceReport::new(&*player, dir, results, passed, started, finished)
+        .save(&path.to_string_lossy());
+    if !passed {
+        context.failed_acceptance = true;
+    }
+    context.ops.record((!passed).then_some(message), None);
+    Flow::Continue
+}
+
+// 'spotcheck': checking sampled blocks against a sidecar of their CRCs
+pub(super) fn spot_check(context: &mut CliContext, command: &[&str]) -> Flow {
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        let mut args = command.to_vec();
+        let (count, seed, algos) = match (
+            take_flag_value(&mut args, "--blocks"),
+            take_flag_value(&mut args, "--seed"),
+            take_flag_value(&mut args, "--algo").and_then(|a| a.map(parse_algos).transpose()),
+        ) {
+            (Ok(c), Ok(s), Ok(a)) => (c, s, a),
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        if args.len() < 2 {
+            eprintln!("'spotcheck' requires an argument, 'crcs'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+        let count = match count.map(str::parse::<usize>).transpose() {
+            Ok(c) => c.unwrap_or(64),
+            Err(e) => {
+                print_error(&e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        let seed = match seed.map(str::parse::<u64>).transpose() {
+            Ok(s) => s.unwrap_or_else(SampleRng::from_time),
+            Err(e) => {
+                print_error(&e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        // the sidecar's columns for the algorithms asked for, or else all of them
+        let algos = algos.or_else(|| context.options.hash_algos.clone());
+        if let Err(e) = spotcheck(
+            &*player,
+            args[1],
+            algos.as_deref(),
+            count,
+            seed,
+            context.options.progress_events,
+            &context.cancel,
+        ) {
+            print_error(&*e, context.options.progress_events);
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'triage': a quick look at whether the card is healthy
+pub(super) fn triage_console(context: &mut CliContext, command: &[&str]) -> Flow {
+    let mut args = command.to_vec();
+    let save_dir = match take_flag_value(&mut args, "--save") {
+        Ok(d) => d,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    // the only thing triage changes is whether the connection is initialised
+    if context.mounted.is_none() {
+        if let Some(Console::Open(player)) = &mut context.player {
+            if !player.initialised().unwrap_or(false) {
+                if let Err(e) = player.Init() {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            }
+        }
+    }
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        if let Err(e) = triage(&*player, save_dir) {
+            print_error(&*e, context.options.progress_events);
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'badblocks': the bad block list, its overrides, and testing blocks
+pub(super) fn bad_blocks(context: &mut CliContext, rl: &mut dyn Prompt, command: &[&str]) -> Flow {
+    #[cfg(not(feature = "writing"))]
+    let _ = rl;
+    let Some(console) = &context.player else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    let player = console.reads();
+    let (bbid, mut overrides) = match player.GetBBID().and_then(|b| Ok((b, Overrides::load(b)?))) {
+        Ok(o) => o,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    match command.get(1).copied() {
+            Some("audit") => {
+                let test = command.contains(&"--test");
+                #[cfg(not(feature = "writing"))]
+                if test {
+                    eprintln!("This version of {PROG_NAME} was built without support for writing; rebuild with `-F writing` to use '--test'.");
+                    return Flow::Continue;
+                }
+                let num_blocks = match card_blocks(player) {
+                    Ok(n) => n as u16,
+                    Err(e) => {
+                        print_error(&*e, context.options.progress_events);
+                        return Flow::Continue;
+                    }
+                };
+                let ranges = match command.get(2).filter(|a| !a.starts_with("--")) {
+                    Some(r) => match parse_ranges(r, num_blocks) {
+                        Ok(r) => r,
+                        Err(e) => {
+                            print_error(&*e, context.options.progress_events);
+                            return Flow::Continue;
+                        }
+                    },
+                    None => std::iter::once(0..num_blocks).collect(),
+                };
+                #[cfg(feature = "writing")]
+                let cycle = |blk: u16, data: &[u8], spare: &[u8]| match console {
+                    Console::Open(handle) => badblocks::test_cycle(handle, blk, data, spare),
+                    Console::Sealed(_) => bail!("the console is sealed, so it can't be written to"),
+                };
+                #[cfg(feature = "writing")]
+                let cycle: Option<badblocks::Cycle> = match (test, console) {
+                    (false, _) => None,
+                    (true, Console::Open(handle)) => {
+                        if let Err(e) = confirm_dangerous(
+                            rl,
+                            &mut context.danger,
+                            handle,
+                            "The test writes patterns to each block marked bad that reads cleanly, then puts its contents back",
+                            command,
+                        ) {
+                            print_error(&*e, context.options.progress_events);
+                            return Flow::Continue;
+                        }
+                        context.post_state.wrote_blocks();
+                        Some(&cycle)
+                    }
+                    (true, Console::Sealed(_)) => {
+                        eprintln!("The console is sealed for an attested session, so '--test' can't write to it");
+                        return Flow::Continue;
+                    }
+                };
+                #[cfg(not(feature = "writing"))]
+                let cycle = None;
+                let findings = match audit(player, &ranges, cycle, context.options.progress_events, &context.cancel) {
+                    Ok(f) => f,
+                    Err(e) => {
+                        print_error(&*e, context.options.progress_events);
+                        return Flow::Continue;
+                    }
+                };
+                overrides.record(&findings);
+                if let Err(e) = overrides.save(bbid) {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+                let suspicious = findings.iter().filter(|f| f.verdict == badblocks::Verdict::Suspicious).count();
+                println!(
+                    "{} blocks marked bad: {} confirmed, {suspicious} suspicious",
+                    findings.len(),
+                    findings.len() - suspicious
+                );
+                if suspicious > 0 {
+                    println!("Review them with 'badblocks list'; 'badblocks trust <ranges>' treats suspicious blocks as good");
+                }
+            }
+            Some("list") => {
+                for line in overrides.describe() {
+                    println!("{line}");
+                }
+            }
+            Some(verb @ ("trust" | "untrust")) => {
+                let Some(selection) = command.get(2) else {
+                    eprintln!("'badblocks {verb}' requires an argument, 'ranges'. Type 'h' for a list of commands and their arguments.");
+                    return Flow::Continue;
+                };
+                let blocks = parse_ranges(selection, u16::MAX)
+                    .map(|r| r.into_iter().flatten().collect::<Vec<_>>())
+                    .and_then(|b| overrides.set_trusted(&b, verb == "trust").map(|_| b))
+                    .and_then(|b| overrides.save(bbid).map(|_| b));
+                match blocks {
+                    Ok(b) if verb == "trust" => println!("{} blocks are treated as good from now on", b.len()),
+                    Ok(b) => println!("{} blocks are treated as bad again", b.len()),
+                    Err(e) => print_error(&*e, context.options.progress_events),
+                }
+            }
+            _ => eprintln!("'badblocks' requires a subcommand, 'audit', 'list', 'trust' or 'untrust'. Type 'h' for a list of commands and their arguments."),
+        }
+    Flow::Continue
+}
+
+// 'history': what changed between the FS generations
+pub(super) fn fs_history(context: &mut CliContext, command: &[&str]) -> Flow {
+    let region = match (
+        command.get(1),
+        source(&context.mounted, &context.sandbox, &context.player),
+    ) {
+        (Some(nand), _) => read_region_file(nand),
+        (None, Some(player)) => read_region(&*player),
+        (None, None) => {
+            eprintln!(
+                "No console selected. Have you used the 'l' and 's' commands to select a console?"
+            );
+            return Flow::Continue;
+        }
+    };
+    match region {
+        Ok(region) => timeline(region).print(),
+        Err(e) => eprintln!("{e}"),
+    }
+    Flow::Continue
+}
+
+// 'verify': comparing the card with a dump
+pub(super) fn verify_dump(context: &mut CliContext, command: &[&str]) -> Flow {
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        let args = match verify_args(command) {
+            Ok(a) => a,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        let mut summary = RangeSummary::default();
+        let started = Instant::now();
+        let error = run_verify(
+            &*player,
+            &args,
+            context.options.progress_events,
+            &mut summary,
+            &context.cancel,
+        );
+        summary.print();
+        match &error {
+            None => println!("Verify success"),
+            Some(e) => eprintln!("{e}"),
+        }
+        context
+            .ops
+            .record(error.as_deref(), summary.failure_context("reading"));
+        let outputs = args.report.as_deref().into_iter().collect::<Vec<_>>();
+        notify(
+            &context.options,
+            "verify",
+            &*player,
+            started,
+            error,
+            &outputs,
+        );
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'scrub': reading every block to find ones that are going bad
+pub(super) fn scrub_card(context: &mut CliContext, rl: &mut dyn Prompt, command: &[&str]) -> Flow {
+    let Some(player) = source(&context.mounted, &context.sandbox, &context.player) else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    let mut args = command.to_vec();
+    let report = match take_flag_value(&mut args, "--report") {
+        Ok(r) => r,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+
+    let mut results = vec![];
+    let (started, started_at) = (Instant::now(), Local::now());
+    let result = scrub(
+        &*player,
+        context.options.progress_events,
+        &mut results,
+        &context.cancel,
+    );
+    if let Some(path) = report {
+        ScrubReport::new(&*player, &results, started_at, result.is_ok()).save(path);
+    }
+    let error = match &result {
+        Ok(_) => {
+            for advice in recommend(&results) {
+                println!("{advice}");
+            }
+            None
+        }
+        Err(e) => {
+            print_error(&**e, context.options.progress_events);
+            Some(e.to_string())
+        }
+    };
+    context.ops.record(
+        error.as_deref(),
+        error
+            .as_ref()
+            .map(|_| format!("after scrubbing {} blocks", results.len())),
+    );
+    notify(
+        &context.options,
+        "scrub",
+        &*player,
+        started,
+        error,
+        report.as_slice(),
+    );
+
+    // marking blocks bad is a write, so it's its own command, with its own confirmation
+    let failed = results
+        .iter()
+        .filter(|r| r.health == Health::Failed)
+        .map(|r| format!("{:#X}", r.block))
+        .collect::<Vec<_>>();
+    if cfg!(feature = "writing")
+        && result.is_ok()
+        && !failed.is_empty()
+        && !context.in_memory()
+        && stdin().is_terminal()
+    {
+        let answer = rl.readline(&format!(
+            "Mark the {} blocks that couldn't be read bad now? [y/N] ",
+            failed.len()
+        ));
+        if matches!(answer.as_deref().map(str::trim), Ok("y" | "Y")) {
+            return dispatch(context, rl, &format!("relocate {}", failed.join(" ")));
+        }
+    }
+    Flow::Continue
+}
+
+// 'preflight': whether the card's worth writing to
+pub(super) fn preflight_check(context: &mut CliContext) -> Flow {
+    if context.in_memory() {
+        eprintln!("'preflight' checks the link to a console, so there's nothing for it to check with a dump mounted");
+        return Flow::Continue;
+    }
+    let Some(player) = context.player.as_ref().map(Console::reads) else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    match preflight::measure(player, preflight::SAMPLE_BLOCKS, &context.cancel) {
+        Ok(metrics) => {
+            let assessment = preflight::score(&metrics);
+            for line in preflight::report(&assessment, context.options.preflight_score) {
+                println!("{line}");
+            }
+        }
+        Err(e) => print_error(&*e, context.options.progress_events),
+    }
+    Flow::Continue
+}
+
+// 'fingerprint': an identity for a card or a dump
+pub(super) fn fingerprint_nand(context: &mut CliContext, command: &[&str]) -> Flow {
+    let mut args = command.to_vec();
+    let algos = match hash_algos(context, &mut args, HashAlgo::Sha256) {
+        Ok(a) => a,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    let dump = match (
+        args.get(1),
+        args.get(2),
+        source(&context.mounted, &context.sandbox, &context.player),
+    ) {
+        (Some(nand), Some(spare), _) => read_input(nand).and_then(|n| Ok((n, read_input(spare)?))),
+        (Some(_), None, _) => {
+            eprintln!("'fingerprint' requires two arguments, 'nand' and 'spare', to fingerprint a dump. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+        (None, _, Some(player)) => dump_nand(
+            &*player,
+            context.options.progress_events,
+            false,
+            &context.cancel,
+        ),
+        (None, _, None) => {
+            eprintln!(
+                "No console selected. Have you used the 'l' and 's' commands to select a console?"
+            );
+            return Flow::Continue;
+        }
+    };
+    let fps = dump.and_then(|(nand, spare)| {
+        algos
+            .iter()
+            .map(|&a| Fingerprint::compute(&nand, &spare, a))
+            .collect::<Result<Vec<_>>>()
+    });
+    match fps {
+        Ok(fps) => fps.iter().for_each(print_fingerprint),
+        Err(e) => eprintln!("{e}"),
+    }
+    Flow::Continue
+}
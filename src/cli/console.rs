@@ -0,0 +1,678 @@
+use super::*;
+
+// The commands that find and select a console, and those that are calls of its own.
+
+// 'l': listing the consoles plugged in
+pub(super) fn list_players(context: &mut CliContext) -> Flow {
+    if !context.usb.ready() {
+        print_unavailable();
+        return Flow::Continue;
+    }
+    let players = match scan_listed() {
+        Ok(p) => p,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    for (index, (listed, player)) in players.iter().enumerate() {
+        println!("{index}: {player:?}");
+        if let Some(h) = holder(&DeviceLocation::new(index, player).lock_key()) {
+            println!("    In use by another {PROG_NAME} (PID {})", h.pid);
+        }
+        // only consoles whose BBID was seen with their serial before can be matched
+        // to a note without opening them
+        if let Ok(Some(note)) = lookup(None, listed.serial.as_deref()) {
+            println!("    Note: {}", note.summary());
+        }
+    }
+    // 's' picks from this, so the numbers are the ones that were just shown
+    context.listing = Some(DeviceList {
+        devices: players.into_iter().map(|(listed, _)| listed).collect(),
+    });
+    Flow::Continue
+}
+
+// 's': selecting one of them
+pub(super) fn select(context: &mut CliContext, command: &[&str]) -> Flow {
+    #[cfg(feature = "writing")]
+    context.danger.lock();
+    context.forget_unplugged();
+    context.card.forget();
+    context.clock = None;
+    context.led = LedState::default();
+    if let Some(Console::Open(player)) = &mut context.player {
+        // one opened at startup was never asked for, so it's released whatever state it's in
+        if let (Ok(true), false) = (player.initialised(), context.auto_opened) {
+            eprintln!(
+                "Device already opened! Please close it with 'Q' before selecting a new device."
+            );
+            return Flow::Continue;
+        }
+        let _ = player.Close();
+        if context.auto_opened {
+            if let Some(selected) = &context.selected {
+                println!(
+                    "Released player {}, which was opened at startup",
+                    selected.index
+                );
+            }
+        }
+        context.player = None;
+        context.selected = None;
+        context.lock = None;
+        context.auto_opened = false;
+    }
+    if !context.usb.ready() {
+        print_unavailable();
+        return Flow::Continue;
+    }
+    if command.len() < 2 {
+        eprintln!("'s' requires an argument, 'device'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    let device = match parse_index(command[1]) {
+        Ok(d) => d,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    let players = match scan_listed() {
+        Ok(p) => p,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    let now = players
+        .iter()
+        .map(|(listed, _)| listed.clone())
+        .collect::<Vec<_>>();
+    // without an 'l' first, this scan is the list; nothing can have changed in between
+    let listing = context.listing.get_or_insert_with(|| DeviceList {
+        devices: now.clone(),
+    });
+    let player = match listing.pick(device, &now) {
+        Ok(i) => &players[i].1,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    // before opening it, so a console another copy has isn't disturbed
+    let location = DeviceLocation::new(device, player);
+    let lock = match DeviceLock::acquire(&location.lock_key()) {
+        Ok(l) => l,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    match GlobalHandle::new(player) {
+        Ok(p) => {
+            context.player = Some(Console::Open(Box::new(p)));
+            context.selected = Some(location);
+            context.lock = Some(lock);
+        }
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            context.player = None;
+            return Flow::Continue;
+        }
+    };
+    println!("Selected player {device} successfully");
+    Flow::Continue
+}
+
+// 'survey': reading every console plugged in
+pub(super) fn survey_consoles(context: &mut CliContext, command: &[&str]) -> Flow {
+    if !context.usb.ready() {
+        print_unavailable();
+        return Flow::Continue;
+    }
+    let mut args = command.to_vec();
+    let (csv, json) = match (
+        take_flag_value(&mut args, "--csv"),
+        take_flag_value(&mut args, "--json"),
+    ) {
+        (Ok(csv), Ok(json)) => (csv, json),
+        (Err(e), _) | (_, Err(e)) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    let current = match (&context.selected, &mut context.player) {
+        (Some(selected), Some(Console::Open(player))) => Some((selected, player)),
+        _ => None,
+    };
+    let rows = match survey(current, &context.cancel) {
+        Ok(rows) if rows.is_empty() => {
+            println!("No consoles found");
+            return Flow::Continue;
+        }
+        Ok(rows) => rows,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    print!("{}", render_table(&rows));
+    let failed = rows.iter().filter(|r| r.error.is_some()).count();
+    match failed {
+        0 => println!("{} consoles surveyed", rows.len()),
+        n => println!("{} consoles surveyed; {n} couldn't be read", rows.len()),
+    }
+    if context.cancel.is_cancelled() {
+        eprintln!("Cancelled; the consoles after the last one listed weren't surveyed");
+    }
+    let outputs = [
+        (csv, Ok(survey_to_csv(&rows).into_bytes())),
+        (
+            json,
+            serde_json::to_vec_pretty(&rows).map_err(anyhow::Error::from),
+        ),
+    ];
+    for (path, data) in outputs {
+        let Some(path) = path else {
+            continue;
+        };
+        match data.and_then(|data| write_atomic(path, &data)) {
+            Ok(_) => println!("Saved the survey to {path}"),
+            Err(e) => print_error(&*e, context.options.progress_events),
+        }
+    }
+    Flow::Continue
+}
+
+// 'device': what's known of the selected console's USB device
+pub(super) fn describe_device(context: &mut CliContext) -> Flow {
+    let (Some(player), Some(selected)) = (&context.player, &context.selected) else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    if !context.usb.ready() {
+        print_unavailable();
+        return Flow::Continue;
+    }
+    let device = match selected.find() {
+        Ok(Some(d)) => d,
+        Ok(None) => {
+            eprintln!("Player {} has been disconnected since it was selected. Use 'l' to rescan, then 's' to select it again.", selected.index);
+            return Flow::Continue;
+        }
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    let info = match DeviceInfo::query(&device) {
+        Ok(info) => info,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    println!("Player {}:\n{info}", selected.index);
+    let bbid = player.reads().GetBBID().ok();
+    if let (Some(serial), Some(bbid)) = (&info.serial, bbid) {
+        if let Err(e) = remember_serial(serial, bbid) {
+            print_error(&*e, context.options.progress_events);
+        }
+    }
+    match lookup(bbid, info.serial.as_deref()) {
+        Ok(Some(note)) => print!("Note:\n{note}"),
+        Ok(None) => {}
+        Err(e) => eprintln!("{e}"),
+    }
+    // only this session's own state; a previous session's can't be seen without 'B'
+    match player.initialised() {
+        Ok(true) => println!("Initialised:   yes (by this session)"),
+        Ok(false) => println!("Initialised:   not by this session; use 'B' to initialise"),
+        Err(e) => eprintln!("Initialised:   unknown ({e})"),
+    }
+    Flow::Continue
+}
+
+// 'B': initialising the connection
+pub(super) fn init(context: &mut CliContext, rl: &mut dyn Prompt) -> Flow {
+    #[cfg(feature = "writing")]
+    context.danger.lock();
+    if let Some(Console::Open(player)) = &mut context.player {
+        let events = context.options.progress_events;
+        let auto_reset = context.options.auto_reset;
+        let can_reset = context.selected.is_some();
+        let mut console = SelectedConsole {
+            handle: player,
+            location: &mut context.selected,
+        };
+        let result = init_with_reset(&mut console, |e, may_help| {
+            print_error(&**e, events);
+            // some consoles only answer after being replugged, which a port reset does
+            // without touching the cable
+            let reset = may_help
+                && can_reset
+                && (auto_reset || {
+                    let answer = rl.readline(
+                        "The console may need a USB reset. Reset it and try again? [y/N] ",
+                    );
+                    matches!(answer.as_deref().map(str::trim), Ok("y" | "Y"))
+                });
+            if reset {
+                println!("Resetting the console's USB port, then retrying Init");
+            }
+            reset
+        });
+        match result {
+            Ok(Some(Initialised::First)) => println!("Init success"),
+            Ok(Some(Initialised::AfterReset)) => println!("Init success (after a USB reset)"),
+            Ok(None) => return Flow::Continue,
+            Err(e) => {
+                eprintln!("{e}");
+                return Flow::Continue;
+            }
+        }
+        #[cfg(feature = "writing")]
+        if let Err(e) = offer_clean(context, rl, true) {
+            eprintln!("Couldn't check for temporary files left on the card: {e}");
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'I': the console's BBID
+pub(super) fn read_bbid(context: &mut CliContext) -> Flow {
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        match player.GetBBID() {
+            Ok(bbid) => println!("BBID: {bbid:04X}"),
+            Err(e) => {
+                eprintln!("{e}")
+            }
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'H': the LED
+pub(super) fn set_led(context: &mut CliContext, command: &[&str]) -> Flow {
+    if let Some(Console::Open(player)) = &mut context.player {
+        if command.len() < 2 {
+            eprintln!("'H' requires an argument, 'value'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+        let value: u32 = match command[1].parse() {
+            Ok(v) => v,
+            Err(e) => {
+                print_error(&e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        match player.SetLED(value) {
+            Ok(_) => {
+                context.led.last = value;
+                println!("SetLED success")
+            }
+            Err(e) => {
+                eprintln!("{e}")
+            }
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'S': signing a hash, on the console or the PC, and checking signatures
+pub(super) fn sign_hash(context: &mut CliContext, command: &[&str]) -> Flow {
+    match command.get(1).copied() {
+        Some("--local") if command.len() < 4 => {
+            eprintln!("'S --local' requires two arguments, 'key' and 'hash_file'. Type 'h' for a list of commands and their arguments.");
+        }
+        Some("--local") => {
+            let output = command
+                .get(4)
+                .map_or_else(|| format!("{}.sig", command[3]), |o| o.to_string());
+            match sign_file(command[2], command[3], &output) {
+                Ok(public) => println!(
+                    "Signed the hash in {} into {output}, with the key whose public half is {}",
+                    command[3],
+                    public.hex()
+                ),
+                Err(e) => print_error(&*e, context.options.progress_events),
+            }
+        }
+        Some("--verify") if command.len() < 5 => {
+            eprintln!("'S --verify' requires three arguments, 'sig_file', 'pubkey' and 'hash_file'. Type 'h' for a list of commands and their arguments.");
+        }
+        Some("--verify") => match verify_file(command[2], command[3], command[4]) {
+            Ok(()) => println!(
+                "{} is a good signature of the hash in {}",
+                command[2], command[4]
+            ),
+            Err(e) => print_error(&*e, context.options.progress_events),
+        },
+        _ => {
+            // the console's library has no call to sign with
+            eprintln!("Signing on the console is unimplemented; use 'S --local' to sign with a key on the PC");
+        }
+    }
+    Flow::Continue
+}
+
+// 'J': setting the console's clock
+pub(super) fn set_time(context: &mut CliContext, rl: &mut dyn Prompt, command: &[&str]) -> Flow {
+    if let Some(Console::Open(player)) = &mut context.player {
+        let time = if command.len() < 2 {
+            let now: DateTime<FixedOffset> = Local::now().into();
+            let zone = configured_zone();
+            let pc = PcClock {
+                timestamp: now.timestamp(),
+                utc_offset: now.offset().local_minus_utc(),
+                zone: zone.as_deref(),
+            };
+            let warnings = check_clock(&pc, context.clock.map(|c| c.expected(Instant::now())));
+            if !warnings.is_empty() {
+                for w in &warnings {
+                    eprintln!("{w}");
+                }
+                if !stdin().is_terminal() {
+                    eprintln!("Not setting the console's clock; give the time to set it to, as 'J <time>'");
+                    return Flow::Continue;
+                }
+                let answer = rl.readline(&format!(
+                    "Set the console's clock to {} anyway? [y/N] ",
+                    now.to_rfc3339()
+                ));
+                if !matches!(answer.as_deref().map(str::trim), Ok("y" | "Y")) {
+                    eprintln!("Cancelled");
+                    return Flow::Continue;
+                }
+            }
+            now
+        } else if let Ok(dt) = DateTime::parse_from_rfc3339(command[1]) {
+            dt
+        } else {
+            eprintln!("Invalid time; 'J' requires a date given in RFC 3339 format, or none to use the current local time. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        };
+        match player.SetTime(time) {
+            Ok(_) => {
+                println!("SetTime success");
+                context.clock = Some(ConsoleClock::set(time.timestamp(), Instant::now()));
+            }
+            Err(e) => {
+                eprintln!("{e}")
+            }
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'K': dumping the SKSA
+pub(super) fn read_sksa(context: &mut CliContext, command: &[&str]) -> Flow {
+    if let Some(Console::Open(player)) = &context.player {
+        let kernel_filename = command[1..]
+            .iter()
+            .find(|a| !a.starts_with("--"))
+            .copied()
+            .unwrap_or("sksa");
+        let (call, region) = match (
+            command.contains(&"--raw-region"),
+            command.contains(&"--both"),
+        ) {
+            (true, true) => {
+                eprintln!("'K' takes one of '--raw-region' and '--both'. Type 'h' for a list of commands and their arguments.");
+                return Flow::Continue;
+            }
+            (true, false) => (false, true),
+            (false, both) => (true, both),
+        };
+        // the SKSA region is the same size on every card, but not past the end of one
+        let card = card_blocks(player).unwrap_or(SKSA_BLOCKS as u32);
+
+        let mut called = None;
+        if call {
+            match sksa::read_call(player, card, context.options.progress_events) {
+                Ok(data) => {
+                    println!("ReadSKSA success");
+                    if let Some(warning) = sksa::check_length(data.len(), sksa::expected_len(card))
+                    {
+                        eprintln!("Warning: {warning}");
+                    }
+                    if let Err(e) = context.sink.put(kernel_filename, &data) {
+                        print_error(&*e, context.options.progress_events);
+                        return Flow::Continue;
+                    }
+                    called = Some(data);
+                }
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    if !region {
+                        return Flow::Continue;
+                    }
+                }
+            }
+        }
+        if region {
+            let (nand, spare) = match sksa::read_region(
+                player,
+                card,
+                context.options.progress_events,
+                &context.cancel,
+            ) {
+                Ok(r) => r,
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            };
+            // beside ReadSKSA's file when both are kept, in its place otherwise
+            let name = match call {
+                true => format!("{kernel_filename}.region"),
+                false => kernel_filename.to_string(),
+            };
+            for (name, data) in [(name.clone(), &nand), (format!("{name}.spare"), &spare)] {
+                if let Err(e) = context.sink.put(&name, data) {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            }
+            if let Some(called) = &called {
+                for line in sksa::describe(&sksa::compare(called, &nand, &spare)) {
+                    println!("{line}");
+                }
+            }
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'F': dumping the current FS block
+pub(super) fn dump_fs(context: &mut CliContext, command: &[&str]) -> Flow {
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        if command.len() < 2 {
+            eprintln!("'F' requires an argument, 'file'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+        match player.DumpCurrentFS() {
+            Ok(fs) => match context.sink.put(command[1], &fs) {
+                Ok(_) => println!("DumpCurrentFS success"),
+                Err(e) => {
+                    eprintln!("{e}")
+                }
+            },
+            Err(e) => {
+                eprintln!("{e}")
+            }
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'C': the card's stats
+pub(super) fn card_stats(context: &mut CliContext) -> Flow {
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        match player.CardStats() {
+            Ok(stats) => {
+                let CardStats {
+                    free,
+                    used,
+                    bad,
+                    seqno,
+                } = stats;
+                // a mounted dump's (or the sandbox's) sequence number isn't the console's
+                match context.in_memory() {
+                    true => context.card.set_free_blocks(free),
+                    false => context.card.read_stats(free, seqno),
+                }
+                println!("Free: {free} ({})\nUsed: {used} ({})\nBad: {bad} ({})\nSequence Number: {seqno}", 
+                        format_size(free as u64 * BLOCK_SIZE as u64, 2),
+                        format_size(used as u64 * BLOCK_SIZE as u64, 2),
+                        format_size(bad as u64 * BLOCK_SIZE as u64, 2));
+                // a mounted dump's stats don't say anything about the console's health
+                if context.config.stats_history && !context.in_memory() {
+                    if let Err(e) = player.GetBBID().and_then(|bbid| {
+                        record_stats(bbid, &stats, context.config.stats_history_limit)
+                    }) {
+                        eprintln!("Couldn't record stats history: {e}");
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("{e}")
+            }
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'stats': the card stats recorded by 'C'
+pub(super) fn stats(context: &mut CliContext, command: &[&str]) -> Flow {
+    if command.get(1) != Some(&"history") {
+        eprintln!("'stats' requires an argument, 'history'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        match player.GetBBID().and_then(load_history) {
+            Ok(records) => print_history(&records, command.contains(&"--graph")),
+            Err(e) => eprintln!("{e}"),
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'reset-usb': resetting the console's USB port
+pub(super) fn reset_usb(context: &mut CliContext) -> Flow {
+    #[cfg(feature = "writing")]
+    context.danger.lock();
+    let (Some(Console::Open(player)), Some(selected)) = (&mut context.player, &context.selected)
+    else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    match reset_device(player, selected) {
+        Ok((handle, location)) => {
+            *player = Box::new(handle);
+            context.selected = Some(location);
+            println!("Reset the console's USB port and reopened it; use 'B' to initialise it");
+        }
+        Err(e) => eprintln!("{e}"),
+    }
+    Flow::Continue
+}
+
+// 'retry-usb': trying to start libusb again
+pub(super) fn retry_usb(context: &mut CliContext) -> Flow {
+    if context.usb.is_ready() {
+        println!("The USB subsystem is already available");
+        return Flow::Continue;
+    }
+    if context.usb.retry() {
+        println!("USB subsystem initialised");
+        if let Some((player, selected)) = select_at_startup(&context.config) {
+            if let Err(e) = context.adopt(player, selected, true) {
+                print_error(&*e, context.options.progress_events);
+            }
+        }
+    }
+    Flow::Continue
+}
+
+// 'Q': closing the connection
+pub(super) fn close(context: &mut CliContext) -> Flow {
+    #[cfg(feature = "writing")]
+    context.danger.lock();
+    context.forget_unplugged();
+    if let Some(Console::Open(player)) = &mut context.player {
+        match player.Close() {
+            Ok(_) => println!("Close success"),
+            Err(e) => {
+                eprintln!("{e}")
+            }
+        }
+        context.player = None;
+        context.selected = None;
+        context.lock = None;
+        context.auto_opened = false;
+        context.clock = None;
+        #[cfg(feature = "writing")]
+        if context.sandbox.take().is_some() {
+            println!("Discarded the sandbox along with the connection");
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'finish': closing the connection once the card is checked
+pub(super) fn finish_session(context: &mut CliContext) -> Flow {
+    let Some(Console::Open(player)) = &mut context.player else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    match finish(player, &context.post_state) {
+        Ok(true) => context.post_state = PostState::default(),
+        Ok(false) => {}
+        Err(e) => eprintln!("{e}"),
+    }
+    Flow::Continue
+}
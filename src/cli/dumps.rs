@@ -0,0 +1,734 @@
+use super::*;
+
+// The commands that work on dumps on the PC: making, mounting, comparing and archiving them.
+
+// 'calc': working out offsets and sizes
+pub(super) fn calculate(context: &mut CliContext, command: &[&str]) -> Flow {
+    if command.len() < 2 {
+        eprintln!("'calc' requires an argument, 'expr'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    let calculation = match calc::evaluate(&command[1..].join(" ")) {
+        Ok(c) => c,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    let card = match &context.mounted {
+        Some(m) => format!("the mounted dump, {}", m.name),
+        None => "the console's card".to_string(),
+    };
+    let geometry = match source(&context.mounted, &context.sandbox, &context.player)
+        .map(|p| card_blocks(&*p))
+    {
+        Some(Ok(blocks)) => Geometry {
+            blocks,
+            source: card,
+        },
+        Some(Err(_)) => Geometry::default_card("the console couldn't be asked"),
+        None => Geometry::default_card("no console is selected"),
+    };
+    for line in calc::render(&calculation, &geometry) {
+        println!("{line}");
+    }
+    Flow::Continue
+}
+
+// 'organize': sorting dumps into a directory per console
+pub(super) fn organize_dumps(
+    context: &mut CliContext,
+    rl: &mut dyn Prompt,
+    command: &[&str],
+) -> Flow {
+    let undoing = command.get(1) == Some(&"--undo");
+    let dir = Path::new(
+        command
+            .get(if undoing { 2 } else { 1 })
+            .copied()
+            .unwrap_or("."),
+    );
+    if undoing {
+        match organize::undo(dir) {
+            Ok((undone, skipped)) => {
+                println!("Put back {undone} files");
+                for name in skipped {
+                    eprintln!("Left {name} where it is; it was moved again, or its old name has been taken");
+                }
+            }
+            Err(e) => print_error(&*e, context.options.progress_events),
+        }
+        return Flow::Continue;
+    }
+    let mut survey = match survey_dir(dir) {
+        Ok(s) => s,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    for dump in &mut survey.dumps {
+        if dump.bbid.is_none() && stdin().is_terminal() {
+            let answer = rl.readline(&format!(
+                "Which console is {} from? (BBID, or nothing to leave it) ",
+                dump.nand
+            ));
+            dump.bbid = answer
+                .ok()
+                .and_then(|a| u32::from_str_radix(a.trim().trim_start_matches("0x"), 16).ok());
+        }
+        let pairing = match (&dump.spare, dump.pairing) {
+            (Some(spare), Pairing::Ecc) => format!(" with {spare} (its ECC fits)"),
+            (Some(spare), _) => format!(" with {spare} (by its manifest)"),
+            (None, _) => " without a spare".to_string(),
+        };
+        match dump.bbid {
+            Some(bbid) => println!("{}{pairing}: console {bbid:08X}, {}", dump.nand, dump.date),
+            None => survey.left.push((
+                dump.nand.clone(),
+                "which console it's from isn't known".to_string(),
+            )),
+        }
+    }
+    for (name, why) in &survey.left {
+        eprintln!("Leaving {name}: {why}");
+    }
+    let moves = layout(&survey.dumps, &|d| dir.join(d).exists());
+    let mut moved = 0;
+    for mv in &moves {
+        if let Err(e) = move_file(dir, mv) {
+            print_error(&*e, context.options.progress_events);
+            break;
+        }
+        moved += 1;
+    }
+    if moved > 0 {
+        println!("Moved {moved} files into {ARCHIVE_DIR}/; 'organize --undo' puts them back");
+    } else {
+        println!("Nothing to move");
+    }
+    Flow::Continue
+}
+
+// 'genimage': generating a dump from a spec
+pub(super) fn generate_image(context: &mut CliContext, command: &[&str]) -> Flow {
+    let [spec, nand, spare] = match command[1..] {
+        [spec, nand, spare, ..] => [spec, nand, spare],
+        _ => {
+            eprintln!("'genimage' requires three arguments, 'spec', 'nand' and 'spare'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+    };
+    if let Err(e) = check_distinct(&[("spec", spec), ("nand", nand), ("spare", spare)]) {
+        print_error(&*e, context.options.progress_events);
+        return Flow::Continue;
+    }
+    match genimage(spec, nand, spare) {
+        Ok(generated) => {
+            let m = &generated.manifest;
+            println!(
+                    "Wrote a card of {:#X} blocks with {} files over {} FS generations to {nand} and {spare}, and what's on it to {nand}.manifest.json{}",
+                    m.blocks,
+                    m.files.len(),
+                    m.generations.len(),
+                    if m.fsck_clean { "" } else { "; it's corrupted as the spec asks" }
+                );
+        }
+        Err(e) => print_error(&*e, context.options.progress_events),
+    }
+    Flow::Continue
+}
+
+// 'export': a datafile of the console's files for ROM managers
+pub(super) fn export_dat(context: &mut CliContext, command: &[&str]) -> Flow {
+    if command.get(1) != Some(&"dat") {
+        eprintln!("'export' requires an argument, the format to export in; 'dat' is the only one. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    let Some(&out) = command.get(2) else {
+        eprintln!("'export dat' requires an argument, 'file'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    };
+    let in_memory = context.in_memory();
+    let exported = match (
+        command.get(3),
+        command.get(4),
+        source(&context.mounted, &context.sandbox, &context.player),
+    ) {
+        (Some(nand), Some(spare), _) => MountedImage::load(nand, spare).and_then(|m| {
+            let fs = FsBlock::parse(&m.DumpCurrentFS()?)?;
+            dat::export(&m, &fs, dat::dump_date(Some(nand)), out, &context.cancel)
+        }),
+        (Some(_), None, _) => {
+            eprintln!("'export dat' requires two more arguments, 'nand' and 'spare', to export a dump. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+        (None, _, Some(player)) => {
+            let date = dat::dump_date(context.mounted.as_ref().map(|m| m.name.as_str()));
+            context
+                .fs_cache
+                .current_fs(&*player, in_memory)
+                .and_then(|fs| dat::export(&*player, &fs, date, out, &context.cancel))
+        }
+        (None, _, None) => {
+            eprintln!(
+                "No console selected. Have you used the 'l' and 's' commands to select a console?"
+            );
+            return Flow::Continue;
+        }
+    };
+    match exported {
+        Ok(count) => {
+            println!("Saved {count} files to {out}");
+            context.ops.succeed();
+        }
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            context.ops.fail(&e.to_string(), None, Instant::now());
+        }
+    }
+    Flow::Continue
+}
+
+// 'dumpinfo': a report on a dump, without a console
+pub(super) fn dump_info(context: &mut CliContext, command: &[&str]) -> Flow {
+    if command.len() < 3 {
+        eprintln!("'dumpinfo' requires two arguments, 'nand' and 'spare'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    if let Err(e) = dumpinfo(command[1], command[2]) {
+        print_error(&*e, context.options.progress_events);
+    }
+    Flow::Continue
+}
+
+// 'mount': mounting a dump to stand in for the console
+pub(super) fn mount_dump(context: &mut CliContext, command: &[&str]) -> Flow {
+    let rw = command.get(1) == Some(&"--rw");
+    let args = if rw { &command[1..] } else { command };
+    if args.len() < 3 {
+        eprintln!("'mount' requires two arguments, 'nand' and 'spare'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    #[cfg(feature = "writing")]
+    if context.mounted.as_ref().is_some_and(|m| m.dirty) {
+        eprintln!("The mounted dump has uncommitted changes; 'commit' or 'unmount' it first");
+        return Flow::Continue;
+    }
+    #[cfg(feature = "writing")]
+    let mounted = if rw {
+        MountedImage::load_rw(args[1], args[2])
+    } else {
+        MountedImage::load(args[1], args[2])
+    };
+    #[cfg(not(feature = "writing"))]
+    let mounted = if rw {
+        eprintln!("This version of {PROG_NAME} was built without support for writing; rebuild with `-F writing` to use 'mount --rw'.");
+        return Flow::Continue;
+    } else {
+        MountedImage::load(args[1], args[2])
+    };
+    match mounted {
+        Ok(m) => {
+            println!(
+                "Mounted {}; commands now read from it instead of the console",
+                m.name
+            );
+            context.mounted = Some(m);
+        }
+        Err(e) => eprintln!("{e}"),
+    }
+    Flow::Continue
+}
+
+// 'unmount': unmounting it
+pub(super) fn unmount(context: &mut CliContext, rl: &mut dyn Prompt) -> Flow {
+    #[cfg(not(feature = "writing"))]
+    let _ = rl;
+    #[cfg(feature = "writing")]
+    if let Some(m) = context.mounted.as_ref().filter(|m| m.dirty) {
+        let answer = rl.readline(&format!(
+            "{} has uncommitted changes. Discard them? [y/N] ",
+            m.name
+        ));
+        if !matches!(answer.as_deref().map(str::trim), Ok("y" | "Y")) {
+            println!("Not unmounting; use 'commit' to save the changes");
+            return Flow::Continue;
+        }
+    }
+    match context.mounted.take() {
+        Some(m) => println!("Unmounted {}", m.name),
+        None => eprintln!("Nothing is mounted"),
+    }
+    Flow::Continue
+}
+
+// 'commit': writing a dump mounted with '--rw' back to its files
+#[cfg(feature = "writing")]
+pub(super) fn commit(context: &mut CliContext) -> Flow {
+    match &mut context.mounted {
+        Some(m) => match m.commit() {
+            Ok(made) if made.is_empty() => println!(
+                "Wrote the changes back to {} (the originals were kept by the first commit)",
+                m.name
+            ),
+            Ok(made) => println!(
+                "Wrote the changes back to {}, keeping the originals as {}",
+                m.name,
+                made.join(" and ")
+            ),
+            Err(e) => eprintln!("{e}"),
+        },
+        None => eprintln!("Nothing is mounted"),
+    }
+    Flow::Continue
+}
+
+// 'convert': converting between binary, Intel HEX and SREC
+pub(super) fn convert_image(context: &mut CliContext, command: &[&str]) -> Flow {
+    let byteswap = command.contains(&"--byteswap");
+    let mut args = command.to_vec();
+    args.retain(|a| *a != "--byteswap");
+    let base = match take_flag_value(&mut args, "--base") {
+        Ok(b) => b,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    if args.len() < 3 {
+        eprintln!("'convert' requires two arguments, 'in' and 'out'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    let base = match base.map(parse_int::parse::<u32>).transpose() {
+        Ok(b) => b.unwrap_or(0),
+        Err(e) => {
+            print_error(&e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    match convert(args[1], args[2], base, byteswap)
+        .and_then(|data| context.sink.put(args[2], &data))
+    {
+        Ok(_) => println!("Converted {} to {}", args[1], args[2]),
+        Err(e) => eprintln!("{e}"),
+    }
+    Flow::Continue
+}
+
+// 'fsdiff': comparing the FS of two dumps
+pub(super) fn diff_fs(context: &mut CliContext, command: &[&str]) -> Flow {
+    if command.len() < 3 {
+        eprintln!("'fsdiff' requires two arguments, 'old' and 'new'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    if let Err(e) = fsdiff(command[1], command[2]) {
+        print_error(&*e, context.options.progress_events);
+    }
+    Flow::Continue
+}
+
+// 'backup': incremental backups of the console
+pub(super) fn backups(context: &mut CliContext, command: &[&str]) -> Flow {
+    match command.get(1).copied() {
+        Some("incremental") if command.len() >= 3 => {
+            let Some(player) = source(&context.mounted, &context.sandbox, &context.player) else {
+                eprintln!("No console selected. Have you used the 'l' and 's' commands to select a console?");
+                return Flow::Continue;
+            };
+            let started = Instant::now();
+            let led = LedGuard::start(&*player, &mut context.led, context.options.led_feedback);
+            match backup_incremental(
+                &*player,
+                command[2],
+                context.options.progress_events,
+                &context.cancel,
+            ) {
+                Ok((generation, stored)) => {
+                    led.succeed();
+                    println!(
+                        "Backed up generation {generation} to {}: {stored} blocks stored",
+                        command[2]
+                    );
+                    context.ops.succeed();
+                    notify(
+                        &context.options,
+                        "backup",
+                        &*player,
+                        started,
+                        None,
+                        &[command[2]],
+                    );
+                }
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    context.ops.fail(
+                        &e.to_string(),
+                        Some(format!("while backing up to {}", command[2])),
+                        Instant::now(),
+                    );
+                    notify(
+                        &context.options,
+                        "backup",
+                        &*player,
+                        started,
+                        Some(e.to_string()),
+                        &[],
+                    );
+                }
+            }
+        }
+        Some("restore") if command.len() >= 3 => {
+            let mut args = command[2..].to_vec();
+            let generation = match take_flag_value(&mut args, "--generation")
+                .and_then(|g| g.map(|g| Ok(g.parse::<u32>()?)).transpose())
+            {
+                Ok(g) => g,
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            };
+            let (nand_filename, spare_filename) = match args[..] {
+                [_, nand, spare, ..] => (nand, spare),
+                _ => ("nand.bin", "spare.bin"),
+            };
+            if let Err(e) = check_distinct(&[("nand", nand_filename), ("spare", spare_filename)]) {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+            match backup::restore(args[0], generation, nand_filename, spare_filename) {
+                Ok(g) => println!("Restored generation {g} to {nand_filename} and {spare_filename}; every block matched its recorded hash"),
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+        Some("prune") if command.len() >= 3 => {
+            let mut args = command[2..].to_vec();
+            let keep = match take_flag_value(&mut args, "--keep") {
+                Ok(Some(k)) => match k.parse::<usize>() {
+                    Ok(k) => k,
+                    Err(e) => {
+                        eprintln!("'{k}': {e}");
+                        return Flow::Continue;
+                    }
+                },
+                Ok(None) => {
+                    eprintln!("'backup prune' requires '--keep N'. Type 'h' for a list of commands and their arguments.");
+                    return Flow::Continue;
+                }
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            };
+            match backup::prune(args[0], keep) {
+                Ok(removed) if removed.is_empty() => println!(
+                    "Nothing to prune; {} holds no more than {keep} generations",
+                    args[0]
+                ),
+                Ok(removed) => println!(
+                    "Removed generations {}",
+                    removed
+                        .iter()
+                        .map(u32::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+        Some("list") if command.len() >= 3 => match backup::list(command[2]) {
+            Ok(generations) if generations.is_empty() => {
+                println!("{} holds no backups", command[2])
+            }
+            Ok(generations) => {
+                for g in generations {
+                    let kind = match g.parent {
+                        Some(p) => format!("changes from {p}"),
+                        None => "base".to_string(),
+                    };
+                    println!(
+                        "{:>4}  {}  {:>5} blocks stored  ({kind})",
+                        g.generation,
+                        g.created,
+                        g.stored.len()
+                    );
+                }
+            }
+            Err(e) => eprintln!("{e}"),
+        },
+        _ => {
+            eprintln!("'backup' requires a subcommand, 'incremental', 'restore', 'prune' or 'list', and a directory. Type 'h' for a list of commands and their arguments.");
+        }
+    }
+    Flow::Continue
+}
+
+// 'kit': recovery kits: making, checking and restoring them
+pub(super) fn recovery_kit(
+    context: &mut CliContext,
+    rl: &mut dyn Prompt,
+    command: &[&str],
+) -> Flow {
+    #[cfg(not(feature = "writing"))]
+    let _ = rl;
+    match (command.get(1).copied(), command.get(2).copied()) {
+        (Some("create"), Some(dir)) => {
+            let Some(player) = source(&context.mounted, &context.sandbox, &context.player) else {
+                eprintln!("No console selected. Have you used the 'l' and 's' commands to select a console?");
+                return Flow::Continue;
+            };
+            let serial = context.selected.as_ref().and_then(DeviceLocation::serial);
+            let gathered = (|| -> Result<_> {
+                let bbid = player.GetBBID()?;
+                let stats = player.CardStats()?;
+                let blocks = stats.free + stats.used + stats.bad;
+                println!("Reading the SKSA, the FS region and the system files");
+                let sksa = read_blocks(&*player, 0..SKSA_BLOCKS as u32, &context.cancel)?;
+                let fs = read_blocks(
+                    &*player,
+                    blocks.saturating_sub(FS_REGION_BLOCKS as u32)..blocks,
+                    &context.cancel,
+                )?;
+                let files = system_files(&*player)?;
+                let info = KitInfo {
+                    bbid,
+                    serial,
+                    blocks,
+                    stats: format!(
+                        "{} free, {} used, {} bad, FS sequence {}",
+                        stats.free, stats.used, stats.bad, stats.seqno
+                    ),
+                    made: Local::now().to_rfc3339(),
+                    files: files.iter().map(|(n, _)| n.clone()).collect(),
+                };
+                let kit = build(&info, sksa, fs, files, &[HashAlgo::Sha256]);
+                Ok((info, kit))
+            })();
+            let (info, kit) = match gathered {
+                Ok(k) => k,
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    context.ops.fail(
+                        &e.to_string(),
+                        Some("while making a recovery kit".to_string()),
+                        Instant::now(),
+                    );
+                    return Flow::Continue;
+                }
+            };
+            if matches!(context.sink, OutputSink::Files) {
+                if let Err(e) = std::fs::create_dir_all(dir) {
+                    eprintln!("{dir}: {e}");
+                    return Flow::Continue;
+                }
+            }
+            for (name, data) in &kit {
+                if let Err(e) = context
+                    .sink
+                    .put(&Path::new(dir).join(name).to_string_lossy(), data)
+                {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            }
+            let size = kit.iter().map(|(_, d)| d.len() as u64).sum::<u64>();
+            println!(
+                "Saved a recovery kit of console {:08X} to {dir} ({}): the SKSA, the FS region and {} system files",
+                info.bbid,
+                format_size(size, 2),
+                info.files.len()
+            );
+            context.ops.succeed();
+        }
+        #[cfg(not(feature = "writing"))]
+        (Some("restore"), Some(_)) => {
+            eprintln!("This version of {PROG_NAME} was built without support for writing; rebuild with `-F writing` to use this command.")
+        }
+        #[cfg(feature = "writing")]
+        (Some("restore"), Some(dir)) => {
+            let Some(Console::Open(player)) = &mut context.player else {
+                eprintln!("No console selected. Have you used the 'l' and 's' commands to select a console?");
+                return Flow::Continue;
+            };
+            let chosen = command[3..]
+                .iter()
+                .copied()
+                .filter(|a| !a.starts_with("--"))
+                .collect::<Vec<_>>();
+            let planned = std::fs::read_to_string(Path::new(dir).join(MANIFEST_FILE))
+                .map_err(|e| {
+                    anyhow!("{dir} has no readable {MANIFEST_FILE}, so isn't a recovery kit: {e}")
+                })
+                .and_then(|manifest| {
+                    plan(
+                        &manifest,
+                        &mut |name| Ok(read(Path::new(dir).join(name))?),
+                        &chosen,
+                    )
+                });
+            let checked = planned.and_then(|(info, restores)| {
+                let blocks = card_blocks(player)?;
+                check_fit(&info, &restores, blocks)?;
+                Ok((info, restores, blocks as u16, player.GetBBID()?))
+            });
+            let (info, restores, blocks, bbid) = match checked {
+                Ok(c) => c,
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            };
+            println!(
+                "The kit of console {:08X} made {} checks out; restoring {}",
+                info.bbid,
+                info.made,
+                restores
+                    .iter()
+                    .map(|r| r.item.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", then ")
+            );
+            if info.bbid != bbid {
+                eprintln!("This console is {bbid:08X}, not the one the kit is from; its SKSA and tickets belong to that console");
+            }
+            let mut restored = vec![];
+            for restore in &restores {
+                match restore.item.blocks(blocks) {
+                    Some(range) => {
+                        let what = format!(
+                            "Restoring {} from the kit rewrites blocks {}",
+                            restore.item,
+                            format_range(&range)
+                        );
+                        if let Err(e) =
+                            confirm_dangerous(rl, &mut context.danger, player, &what, command)
+                        {
+                            eprintln!("{e}; not restoring {}", restore.item);
+                            continue;
+                        }
+                        let (nand, spare) = place(&range, &restore.data, &restore.spare);
+                        let conflicts = role_conflicts(range.clone(), blocks, &nand, &spare);
+                        if !allow_conflicts(&conflicts, command.contains(&"--force")) {
+                            return Flow::Continue;
+                        }
+                        context.post_state.wrote_blocks();
+                        let mut summary = RangeSummary::default();
+                        let written = [range];
+                        let result = write_ranges(
+                            player,
+                            &nand,
+                            &spare,
+                            &written,
+                            true,
+                            context.options.progress_events,
+                            &mut summary,
+                            &context.cancel,
+                        );
+                        summary.print();
+                        // what's restored after it depends on it, so a failure stops the restore
+                        let error = match result {
+                            Ok(_) if summary.total_mismatches() > 0 => Some(anyhow!(
+                                "{} blocks failed verification",
+                                summary.total_mismatches()
+                            )),
+                            // the kit itself is the backup to go back to
+                            Ok(_) => fscheck::overlaps_fs(&written, blocks)
+                                .then(|| {
+                                    post_write_fscheck(
+                                        &context.options,
+                                        &*player,
+                                        fscheck::expected_seqno(&nand, &written, blocks),
+                                        Some(Path::new(dir)),
+                                    )
+                                })
+                                .flatten()
+                                .map(|problem| anyhow!(problem)),
+                            Err(e) => Some(e),
+                        };
+                        if let Some(e) = error {
+                            print_error(&*e, context.options.progress_events);
+                            eprintln!(
+                                "Stopped restoring after {}; restored so far: {}",
+                                restore.item,
+                                if restored.is_empty() {
+                                    "nothing".to_string()
+                                } else {
+                                    restored.join(", ")
+                                }
+                            );
+                            context.ops.fail(
+                                &e.to_string(),
+                                Some(format!("while restoring {} from {dir}", restore.item)),
+                                Instant::now(),
+                            );
+                            return Flow::Continue;
+                        }
+                    }
+                    None => {
+                        let name = restore.item.key();
+                        let answer = rl.readline(&format!(
+                            "Replace the console's {name} with the kit's ({})? [y/N] ",
+                            format_size(restore.data.len() as u64, 2)
+                        ));
+                        if !matches!(answer.as_deref().map(str::trim), Ok("y" | "Y")) {
+                            println!("Not restoring {name}");
+                            continue;
+                        }
+                        if name == TICKET_FILE {
+                            if let Err(e) = backup_tickets(&*player, context.config.ticket_backups)
+                            {
+                                eprintln!("{e}; not restoring {name}");
+                                continue;
+                            }
+                        }
+                        if let Err(e) = replace_file(player, name, &restore.data, "kit restore") {
+                            print_error(&*e, context.options.progress_events);
+                            continue;
+                        }
+                        context
+                            .post_state
+                            .wrote_file(name, restore.data.len() as u32);
+                    }
+                }
+                println!("Restored {}", restore.item);
+                restored.push(restore.item.to_string());
+            }
+            match restored.is_empty() {
+                true => println!("Nothing was restored"),
+                false => {
+                    println!("Restored {} from {dir}", restored.join(", "));
+                    context.ops.succeed();
+                }
+            }
+        }
+        _ => {
+            eprintln!("'kit' requires a subcommand, 'create' or 'restore', and a directory. Type 'h' for a list of commands and their arguments.");
+        }
+    }
+    Flow::Continue
+}
+
+// 'dedupe-archive': sharing the blocks dumps have in common
+pub(super) fn dedupe_dumps(context: &mut CliContext, command: &[&str]) -> Flow {
+    if command.len() < 2 {
+        eprintln!("'dedupe-archive' requires an argument, 'dir'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    if let Err(e) = dedupe_archive(command[1]) {
+        print_error(&*e, context.options.progress_events);
+    }
+    Flow::Continue
+}
+
+// 'rehydrate': rebuilding a dump from a deduplicated archive
+pub(super) fn rehydrate_archive(context: &mut CliContext, command: &[&str]) -> Flow {
+    if command.len() < 3 {
+        eprintln!("'rehydrate' requires two arguments, 'index' and 'out'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    if let Err(e) = rehydrate(command[1], command[2]) {
+        print_error(&*e, context.options.progress_events);
+    }
+    Flow::Continue
+}
@@ -0,0 +1,1311 @@
+use super::*;
+
+// The commands that work on the files on the card (or on a mounted dump, or in the sandbox).
+
+// 'L': listing the games on the card
+pub(super) fn list_games(context: &mut CliContext, command: &[&str]) -> Flow {
+    let in_memory = context.in_memory();
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        match context.fs_cache.list_files(&*player, in_memory) {
+            Ok(files) => print!("{}", render(&games(files), command)),
+            Err(e) => {
+                eprintln!("{e}")
+            }
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'dupes': files with identical contents
+pub(super) fn duplicates(context: &mut CliContext, rl: &mut dyn Prompt, command: &[&str]) -> Flow {
+    #[cfg(not(feature = "writing"))]
+    let _ = rl;
+    let interactive = command.contains(&"--interactive");
+    #[cfg(not(feature = "writing"))]
+    if interactive {
+        eprintln!("This version of {PROG_NAME} was built without support for writing; rebuild with `-F writing` to use this command.");
+        return Flow::Continue;
+    }
+    let Some(player) = source(&context.mounted, &context.sandbox, &context.player) else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    let sets = match find_duplicates(&*player, &context.cancel) {
+        Ok(s) => s,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    print_dupes(&sets);
+    #[cfg(feature = "writing")]
+    if interactive && !sets.is_empty() {
+        if let Some(mut player) = source_mut(
+            &mut context.mounted,
+            &mut context.sandbox,
+            &mut context.player,
+        ) {
+            if let Err(e) = delete_extras(rl, &mut *player, &sets, context.config.ticket_backups) {
+                print_error(&*e, context.options.progress_events);
+            }
+            if !context.in_memory() {
+                context.post_state.changed();
+            }
+        }
+    }
+    Flow::Continue
+}
+
+// '3': reading a file off the card
+pub(super) fn read_file(
+    context: &mut CliContext,
+    rl: &mut dyn Prompt,
+    command: &[&str],
+    line: &str,
+) -> Flow {
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        if command.len() < 2 {
+            eprintln!("'3' requires an argument, 'file'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+
+        if command[1] == "--with-spare" {
+            let [name, out, spare_out] = match command[2..] {
+                [name, out, spare_out, ..] => [name, out, spare_out],
+                _ => {
+                    eprintln!("'3 --with-spare' requires three arguments, 'file', 'out' and 'spareout'. Type 'h' for a list of commands and their arguments.");
+                    return Flow::Continue;
+                }
+            };
+            if let Err(e) = check_distinct(&[("out", out), ("spareout", spare_out)]) {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+            let sidecar = format!("{out}.chain.json");
+            let dumped = context.mounted.as_ref().and_then(MountedImage::dumped);
+            let result = download_with_spare(
+                &*player,
+                name,
+                context.options.progress_events,
+                &context.cancel,
+            )
+            .and_then(|raw| {
+                write_atomic(out, &raw.data)?;
+                write_atomic(spare_out, &raw.spare)?;
+                write_atomic(&sidecar, raw.sidecar(name, dumped)?.as_bytes())?;
+                if let Some(time) = dumped {
+                    for path in [out, spare_out] {
+                        if let Err(e) = set_modified(path, time) {
+                            println!(
+                                "Note: {path} dates from {time}, but couldn't be dated then: {e}"
+                            );
+                        }
+                    }
+                }
+                Ok(raw)
+            });
+            match result {
+                Ok(raw) => {
+                    println!("Wrote {name} to {out}, the spare data of its {} blocks to {spare_out} and its chain to {sidecar}", raw.chain.len());
+                    context.ops.succeed();
+                }
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    context.ops.fail(
+                        &e.to_string(),
+                        Some(format!("while reading {name}")),
+                        Instant::now(),
+                    );
+                }
+            }
+            return Flow::Continue;
+        }
+
+        let resume = command[1] == "--continue";
+        let name = if resume {
+            match command.get(2) {
+                Some(n) => *n,
+                None => {
+                    eprintln!("'3 --continue' requires an argument, 'file'. Type 'h' for a list of commands and their arguments.");
+                    return Flow::Continue;
+                }
+            }
+        } else {
+            command[1]
+        };
+        if resume {
+            if let Err(e) = check_resumable(name) {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        }
+
+        let started = Instant::now();
+        let led = LedGuard::start(&*player, &mut context.led, context.options.led_feedback);
+        let file = match download_file(
+            &*player,
+            name,
+            resume,
+            context.options.progress_events,
+            &context.cancel,
+        ) {
+            Ok(d) => {
+                led.succeed();
+                println!("ReadFile success");
+                d
+            }
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                context.ops.fail(
+                    &e.to_string(),
+                    Some(format!("while reading {name}")),
+                    Instant::now(),
+                );
+                notify(
+                    &context.options,
+                    "3",
+                    &*player,
+                    started,
+                    Some(e.to_string()),
+                    &[],
+                );
+                drop(led);
+                return offer_similar(context, rl, line, name);
+            }
+        };
+
+        // a file read from a dump dates from when the dump was made
+        let dumped = context.mounted.as_ref().and_then(MountedImage::dumped);
+        match context.sink.put_dated(name, &file, dumped) {
+            Ok(_) => {
+                let hooks = &context.config.hooks.post_read;
+                let error = run_hooks(context, "post_read", hooks, &*player, &[("file", name)]);
+                context.ops.record(error.as_deref(), None);
+                notify(&context.options, "3", &*player, started, error, &[name]);
+            }
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                context.ops.fail(
+                    &e.to_string(),
+                    Some(format!("while reading {name}")),
+                    Instant::now(),
+                );
+                notify(
+                    &context.options,
+                    "3",
+                    &*player,
+                    started,
+                    Some(e.to_string()),
+                    &[],
+                );
+            }
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'hash': hashing a file on the card
+pub(super) fn hash_file(
+    context: &mut CliContext,
+    rl: &mut dyn Prompt,
+    command: &[&str],
+    line: &str,
+) -> Flow {
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        let mut args = command.to_vec();
+        let options = take_flag_value(&mut args, "--format").and_then(|f| {
+            let format = f.map_or(Ok(HashFormat::Sum), str::parse)?;
+            Ok((format, hash_algos(context, &mut args, HashAlgo::Sha256)?))
+        });
+        let (format, algos) = match options {
+            Ok(o) => o,
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        if args.len() < 2 {
+            eprintln!("'hash' requires an argument, 'file'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+        let name = args[1];
+        match player.ReadFile(name) {
+            Ok(Some(data)) => {
+                let hashes = algos.iter().map(|a| a.digest(&data)).collect::<Vec<_>>();
+                println!("{}", format_hashes(format, name, &hashes));
+                context.ops.succeed();
+            }
+            Ok(None) => {
+                eprintln!("File {name} not found");
+                return offer_similar(context, rl, line, name);
+            }
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                context.ops.fail(
+                    &e.to_string(),
+                    Some(format!("while reading {name}")),
+                    Instant::now(),
+                );
+            }
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'stat': a file's FS entry, field by field
+pub(super) fn stat_file(context: &mut CliContext, command: &[&str]) -> Flow {
+    if command.len() < 2 {
+        eprintln!("'stat' requires an argument, 'file'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    let in_memory = context.in_memory();
+    let Some(player) = source(&context.mounted, &context.sandbox, &context.player) else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    let name = command[1];
+    let lines = context
+        .fs_cache
+        .current_fs(&*player, in_memory)
+        .and_then(|fs| match fs.find(name) {
+            Some(entry) => stat_lines(entry),
+            None => Err(anyhow!("File {name} not found")),
+        });
+    match lines {
+        Ok(lines) => lines.iter().for_each(|l| println!("{l}")),
+        Err(e) => print_error(&*e, context.options.progress_events),
+    }
+    Flow::Continue
+}
+
+// 'provision': comparing the card's files with a directory, and bringing it in line
+pub(super) fn provision_files(
+    context: &mut CliContext,
+    rl: &mut dyn Prompt,
+    command: &[&str],
+) -> Flow {
+    #[cfg(not(feature = "writing"))]
+    let _ = rl;
+    let mut args = command.to_vec();
+    // the first algorithm is the one the directory and console are compared with
+    let algo = match hash_algos(context, &mut args, HashAlgo::Sha256) {
+        Ok(a) => a[0],
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    let (sub, dir) = match args[1..] {
+        [sub @ ("check" | "apply"), dir, ..] => (sub, dir),
+        _ => {
+            eprintln!("'provision' requires a subcommand, 'check' or 'apply', and an argument, 'dir'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+    };
+    #[cfg(not(feature = "writing"))]
+    if sub == "apply" {
+        eprintln!("This version of {PROG_NAME} was built without support for writing; rebuild with `-F writing` to use this command.");
+        return Flow::Continue;
+    }
+    let in_memory = context.in_memory();
+    let compared = match source(&context.mounted, &context.sandbox, &context.player) {
+        Some(player) => context
+            .fs_cache
+            .current_fs(&*player, in_memory)
+            .and_then(|fs| provision::compare(&*player, &fs, dir, algo, &context.cancel)),
+        None => {
+            eprintln!(
+                "No console selected. Have you used the 'l' and 's' commands to select a console?"
+            );
+            return Flow::Continue;
+        }
+    };
+    let compared = match compared {
+        Ok(c) => c,
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            context.ops.fail(
+                &e.to_string(),
+                Some(format!("while comparing with {dir}")),
+                Instant::now(),
+            );
+            return Flow::Continue;
+        }
+    };
+    print_diff(&compared.diff, dir);
+    if sub == "check" || compared.diff.in_sync() {
+        context.ops.succeed();
+        return Flow::Continue;
+    }
+
+    #[cfg(feature = "writing")]
+    {
+        let mounted = context.in_memory();
+        let Some(mut player) = source_mut(
+            &mut context.mounted,
+            &mut context.sandbox,
+            &mut context.player,
+        ) else {
+            return Flow::Continue;
+        };
+        let steps = match player.CardStats().and_then(|stats| {
+            provision::plan(
+                &compared.diff,
+                &compared.wanted,
+                &compared.present,
+                stats.free as usize,
+            )
+        }) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("{e}; not changing anything");
+                context.ops.fail(
+                    &e.to_string(),
+                    Some(format!("while planning to match {dir}")),
+                    Instant::now(),
+                );
+                return Flow::Continue;
+            }
+        };
+        println!("Plan:");
+        for step in &steps {
+            println!("  {step}");
+        }
+        if command.contains(&"--dry-run") {
+            return Flow::Continue;
+        }
+        if !command.contains(&"--yes") {
+            if !stdin().is_terminal() {
+                eprintln!("Add '--yes' to carry out the plan without being asked.");
+                return Flow::Continue;
+            }
+            let answer = rl.readline("Carry out this plan? [y/N] ");
+            if !matches!(answer.as_deref().map(str::trim), Ok("y" | "Y")) {
+                println!("Cancelled");
+                return Flow::Continue;
+            }
+        }
+        for step in &steps {
+            let result = context.cancel.check().and_then(|_| match step {
+                provision::Step::Delete(name) => player.DeleteFile(name).map(|_| 0),
+                provision::Step::Upload(name) => read_local(dir, name)
+                    .and_then(|data| player.WriteFile(&data, name).map(|_| data.len())),
+            });
+            match result {
+                Ok(_) if mounted => {}
+                Ok(_) if matches!(step, provision::Step::Delete(_)) => {
+                    context.post_state.deleted_file(step.name())
+                }
+                Ok(len) => context.post_state.wrote_file(step.name(), len as u32),
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    eprintln!("Stopped at '{step}'; run 'provision apply {dir}' again to carry on from where the console is now");
+                    context
+                        .ops
+                        .fail(&e.to_string(), Some(format!("at '{step}'")), Instant::now());
+                    return Flow::Continue;
+                }
+            }
+            println!("Done: {step}");
+        }
+    }
+
+    // the uploads are new to the hash cache, so this reads them back
+    context.fs_cache.invalidate();
+    let Some(player) = source(&context.mounted, &context.sandbox, &context.player) else {
+        return Flow::Continue;
+    };
+    let after = context
+        .fs_cache
+        .current_fs(&*player, in_memory)
+        .and_then(|fs| provision::compare(&*player, &fs, dir, algo, &context.cancel));
+    match after {
+        Ok(after) if after.diff.in_sync() => {
+            println!("Checked: the console's files now match {dir}");
+            context.ops.succeed();
+        }
+        Ok(after) => {
+            eprintln!("The console still doesn't match {dir} after carrying out the plan:");
+            print_diff(&after.diff, dir);
+            context.ops.fail(
+                "the console didn't match afterwards",
+                Some(format!("while checking against {dir}")),
+                Instant::now(),
+            );
+        }
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            context.ops.fail(
+                &e.to_string(),
+                Some(format!("while checking against {dir}")),
+                Instant::now(),
+            );
+        }
+    }
+    Flow::Continue
+}
+
+// 'clean': removing leftovers of interrupted writes
+#[cfg(feature = "writing")]
+pub(super) fn clean_up(context: &mut CliContext, rl: &mut dyn Prompt) -> Flow {
+    // read before anything's cleaned, as the cleanup changes the listing
+    let junk = match source(&context.mounted, &context.sandbox, &context.player) {
+        Some(player) => player.ListFiles().and_then(|files| {
+            let dupes = find_duplicates(&*player, &context.cancel)?;
+            Ok((empty_files(&files), dupes))
+        }),
+        None => {
+            eprintln!(
+                "No console selected. Have you used the 'l' and 's' commands to select a console?"
+            );
+            return Flow::Continue;
+        }
+    };
+    match offer_clean(context, rl, false) {
+        Ok(()) => context.ops.succeed(),
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            context.ops.fail(
+                &e.to_string(),
+                Some("while cleaning up temporary files".to_string()),
+                Instant::now(),
+            );
+            return Flow::Continue;
+        }
+    }
+    match junk {
+        Ok((empty, dupes)) if empty.is_empty() && dupes.is_empty() => {}
+        Ok((empty, dupes)) => {
+            println!("\nMay be junk (left as they are; delete any that aren't wanted with '6'):");
+            for name in empty {
+                println!("  {name}: empty");
+            }
+            for set in dupes {
+                println!(
+                    "  {}: the same contents ({} bytes)",
+                    set.names.join(", "),
+                    set.size
+                );
+            }
+        }
+        Err(e) => eprintln!("Couldn't look for other junk: {e}"),
+    }
+    Flow::Continue
+}
+
+// 'browse': the full-screen file browser
+#[cfg(feature = "tui")]
+pub(super) fn browse_files(context: &mut CliContext, rl: &mut dyn Prompt) -> Flow {
+    if source(&context.mounted, &context.sandbox, &context.player).is_none() {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    }
+    if let Err(e) = browse(context, rl) {
+        print_error(&*e, context.options.progress_events);
+    }
+    Flow::Continue
+}
+
+// 'cat': printing a file on the card
+pub(super) fn cat(context: &mut CliContext, command: &[&str]) -> Flow {
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        let mut args = command.to_vec();
+        let max = match take_flag_value(&mut args, "--max-bytes").and_then(|m| {
+            m.map(parse_int::parse::<usize>)
+                .transpose()
+                .map_err(Into::into)
+        }) {
+            Ok(m) => m
+                .or(context.config.cat_max_bytes)
+                .unwrap_or(DEFAULT_MAX_BYTES),
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+        };
+        let as_strings = args.contains(&"--strings");
+        args.retain(|a| *a != "--strings");
+        if args.len() < 2 {
+            eprintln!("'cat' requires an argument, 'file'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+        match read_head(&*player, args[1], max, &context.cancel) {
+            Ok((data, size)) => print!("{}", preview::render(&data, size, as_strings)),
+            Err(e) => eprintln!("{e}"),
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// '4': writing a file to the card
+#[cfg(feature = "writing")]
+pub(super) fn write_file(context: &mut CliContext, rl: &mut dyn Prompt, command: &[&str]) -> Flow {
+    let in_memory = context.in_memory();
+    let events = context.options.progress_events;
+    let Some(reads) = source(&context.mounted, &context.sandbox, &context.player) else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    // '--blocks' sends a console the file a block at a time, so an upload that fails partway
+    // can be continued; '--continue' does that without asking
+    let resume = command.contains(&"--continue");
+    let blocks = resume || command.contains(&"--blocks");
+    let Some(&path) = command[1..].iter().find(|a| !a.starts_with("--")) else {
+        eprintln!("'4' requires an argument, 'file'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    };
+    // the card's FS keeps no times, so there's nowhere to put the file's
+    if command.contains(&"--preserve-times") {
+        eprintln!("The console's FS entries have no field for a file's time, so {path}'s can't be kept; upload it without '--preserve-times'");
+        return Flow::Continue;
+    }
+    // the card's FS has no directories, so the file goes on it by its own name
+    let name = Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(path);
+
+    // an empty file is nearly always a failed download or copy, not something to upload
+    let size = std::fs::metadata(path).map_or(0, |m| m.len());
+    if size == 0 && !command.contains(&"--allow-empty") {
+        eprintln!(
+            "{path} is empty (or can't be read); add '--allow-empty' to upload an empty file"
+        );
+        return Flow::Continue;
+    }
+
+    if context.options.lint && !lint_files(&[path], context.card.free_blocks()) {
+        eprintln!(
+            "Not uploading {path} as it failed the checks; use 'set lint off' to upload it anyway"
+        );
+        return Flow::Continue;
+    }
+
+    if touches_tickets(&[name]) {
+        if let Err(e) = backup_tickets(&*reads, context.config.ticket_backups) {
+            eprintln!("{e}; not continuing");
+            return Flow::Continue;
+        }
+    }
+
+    if !in_memory
+        && !preflight_passes(
+            rl,
+            &*reads,
+            &context.options,
+            &context.cancel,
+            size,
+            command,
+        )
+    {
+        return Flow::Continue;
+    }
+
+    let data = match read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            print_error(&e, events);
+            context.ops.fail(
+                &e.to_string(),
+                Some(format!("while uploading {path}")),
+                Instant::now(),
+            );
+            return Flow::Continue;
+        }
+    };
+
+    if let (false, Some(Console::Open(player))) = (in_memory, &mut context.player) {
+        let result = match blocks {
+                false => resume_dir().and_then(|dir| {
+                    if let Some(earlier) = upload::interrupted(&dir, name) {
+                        let kept = match earlier.done {
+                            0 => String::new(),
+                            done => format!(" ('4 --continue {path}' may be able to keep the {done} blocks it wrote)"),
+                        };
+                        println!("An earlier upload of {name} didn't finish. WriteFile sends a file in one call, and the console can't add to a file, so it can't be continued; starting again from the beginning{kept}");
+                    }
+                    let record = upload::whole(player, name, &data)?;
+                    upload::write_whole(player, &dir, &record, &data)?;
+                    println!("WriteFile success; {name} read back from the console matches");
+                    Ok(None)
+                }),
+                true => resume_dir().and_then(|dir| {
+                    let planned = upload::plan(player, &dir, name, &data)?;
+                    let from = match &planned.decision {
+                        Decision::Fresh if resume => {
+                            println!("No interrupted upload of {name} found; starting from the beginning");
+                            0
+                        }
+                        Decision::Fresh => 0,
+                        Decision::Restart(why) => {
+                            println!("The interrupted upload of {name} can't be continued: {why}; starting again from the beginning");
+                            0
+                        }
+                        &Decision::Continue(done) => {
+                            let total = planned.record.chain.len();
+                            let stopped = format!("An earlier upload of {name} stopped after {done} of its {total} blocks");
+                            let answer = if resume {
+                                true
+                            } else if stdin().is_terminal() {
+                                let answer = rl.readline(&format!("{stopped}. Continue it from there? [y/N] "));
+                                matches!(answer.as_deref().map(str::trim), Ok("y" | "Y"))
+                            } else {
+                                println!("{stopped}; use '4 --continue {path}' to carry on from there");
+                                false
+                            };
+                            if answer {
+                                println!("Continuing {name} from block {} of {total}", done + 1);
+                                done
+                            } else {
+                                println!("Starting {name} again from the beginning");
+                                0
+                            }
+                        }
+                    };
+                    context.post_state.wrote_blocks();
+                    let uploaded = upload::upload(player, &dir, &planned, from, events, &context.cancel)?;
+                    println!("Uploaded {name} a block at a time; it reads back from the console as it was sent");
+                    if uploaded.kept > 0 {
+                        println!("({} blocks were kept from the earlier attempt)", uploaded.kept);
+                    }
+                    Ok(Some(uploaded.seqno))
+                }),
+            };
+        match result {
+            Ok(seqno) => {
+                context.post_state.wrote_file(name, data.len() as u32);
+                if let Some(seqno) = seqno {
+                    if let Some(problem) =
+                        post_write_fscheck(&context.options, player, Some(seqno), None)
+                    {
+                        context.ops.fail(
+                            &problem,
+                            Some(format!("after uploading {name}")),
+                            Instant::now(),
+                        );
+                        return Flow::Continue;
+                    }
+                }
+                context.ops.succeed();
+            }
+            Err(e) => {
+                print_error(&*e, events);
+                context.ops.fail(
+                    &e.to_string(),
+                    Some(format!("while uploading {path}")),
+                    Instant::now(),
+                );
+            }
+        }
+        return Flow::Continue;
+    }
+
+    if blocks {
+        eprintln!("'4 --blocks' and '4 --continue' only work on a console; uploading {name} with WriteFile");
+    }
+    let Some(mut player) = source_mut(
+        &mut context.mounted,
+        &mut context.sandbox,
+        &mut context.player,
+    ) else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    match player.WriteFile(&data, name) {
+        Ok(_) => {
+            println!("WriteFile success");
+            if !in_memory {
+                context.post_state.wrote_file(name, data.len() as u32);
+            }
+            context.ops.succeed();
+        }
+        Err(e) => {
+            print_error(&*e, events);
+            context.ops.fail(
+                &e.to_string(),
+                Some(format!("while uploading {path}")),
+                Instant::now(),
+            );
+            return Flow::Continue;
+        }
+    }
+    Flow::Continue
+}
+
+// 'patch': patching a file, on the card or the PC
+pub(super) fn patch_file(context: &mut CliContext, command: &[&str]) -> Flow {
+    #[cfg(not(feature = "writing"))]
+    let _ = context;
+    match command.get(1).copied() {
+        Some("--blocks") if command.len() < 4 => {
+            eprintln!("'patch --blocks' requires two arguments, 'range' and 'patchfile'. Type 'h' for a list of commands and their arguments.");
+        }
+        Some("--local") if command.len() < 5 => {
+            eprintln!("'patch --local' requires three arguments, 'in', 'patchfile' and 'out'. Type 'h' for a list of commands and their arguments.");
+        }
+        Some("--local") => {
+            let output = command[4];
+            let result = Patch::load(command[3]).and_then(|patch| {
+                let source = read(command[2])?;
+                let target = patch.apply(&source)?;
+                write_atomic(output, &target)?;
+                Ok((patch, source.len(), target.len()))
+            });
+            match result {
+                Ok((patch, from, to)) => {
+                    if !patch.checked() {
+                        println!("Note: IPS patches carry no checksums, so whether {} is the file it's for can't be checked", command[2]);
+                    }
+                    println!(
+                        "Patched {} with {} into {output} ({from:#X} -> {to:#X} bytes)",
+                        command[2],
+                        patch.format()
+                    );
+                }
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+        #[cfg(not(feature = "writing"))]
+        Some(_) if command.len() >= 3 => {
+            eprintln!("This version of {PROG_NAME} was built without support for writing; rebuild with `-F writing` to use this command.")
+        }
+        #[cfg(feature = "writing")]
+        Some("--blocks") => {
+            let Some(mounted) = &mut context.mounted else {
+                eprintln!("'patch --blocks' only works on a dump mounted with 'mount --rw'");
+                return Flow::Continue;
+            };
+            let range = match parse_ranges(command[2], mounted.num_blocks()).as_deref() {
+                Ok([range]) => range.clone(),
+                Ok(_) => {
+                    eprintln!("'patch --blocks' takes a single range of blocks");
+                    return Flow::Continue;
+                }
+                Err(e) => {
+                    print_error(&**e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            };
+            // the blocks must keep their size, which write_blocks checks
+            let result = Patch::load(command[3]).and_then(|patch| {
+                let target = patch.apply(&mounted.read_blocks(range.clone())?)?;
+                mounted.write_blocks(range.clone(), &target)?;
+                Ok(patch)
+            });
+            match result {
+                Ok(patch) => println!(
+                    "Patched blocks {} with {}; use 'commit' to keep the change",
+                    format_range(&range),
+                    patch.format()
+                ),
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+        #[cfg(feature = "writing")]
+        Some(name) if command.len() >= 3 => {
+            let Some(mut player) = source_mut(
+                &mut context.mounted,
+                &mut context.sandbox,
+                &mut context.player,
+            ) else {
+                eprintln!("No console selected. Have you used the 'l' and 's' commands to select a console?");
+                return Flow::Continue;
+            };
+            let source = match player.ReadFile(name) {
+                Ok(Some(data)) => data,
+                Ok(None) => {
+                    eprintln!("File {name} not found");
+                    return Flow::Continue;
+                }
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            };
+            let (patch, target) = match Patch::load(command[2]).and_then(|patch| {
+                let target = patch.apply(&source)?;
+                Ok((patch, target))
+            }) {
+                Ok(p) => p,
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            };
+            if !patch.checked() {
+                println!("Note: IPS patches carry no checksums, so whether {name} is the file it's for can't be checked");
+            }
+            if target == source {
+                println!("The patch doesn't change {name}");
+                return Flow::Continue;
+            }
+
+            // it's written beside the old copy, which is only deleted once the new one checks out
+            let needed = target.len().div_ceil(BLOCK_SIZE);
+            match player.CardStats() {
+                Ok(stats) if (stats.free as usize) < needed => {
+                    eprintln!("The patched {name} needs {needed} free blocks beside the old copy while it's written, but only {} are free; not writing it", stats.free);
+                    return Flow::Continue;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            }
+            if touches_tickets(&[name]) {
+                if let Err(e) = backup_tickets(&*player, context.config.ticket_backups) {
+                    eprintln!("{e}; not continuing");
+                    return Flow::Continue;
+                }
+            }
+
+            match replace_file(&mut *player, name, &target, "patch")
+                .and_then(|_| player.ReadFile(name))
+            {
+                Ok(back) if back.as_ref() != Some(&target) => {
+                    eprintln!("{name} didn't read back as it was written");
+                    context.ops.fail(
+                        "the file didn't read back as it was written",
+                        Some(format!("while patching {name}")),
+                        Instant::now(),
+                    );
+                }
+                Ok(_) => {
+                    println!(
+                        "Patched {name} with {} ({:#X} -> {:#X} bytes); verified",
+                        patch.format(),
+                        source.len(),
+                        target.len()
+                    );
+                    if !context.in_memory() {
+                        context.post_state.wrote_file(name, target.len() as u32);
+                    }
+                    context.ops.succeed();
+                }
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    context.ops.fail(
+                        &e.to_string(),
+                        Some(format!("while patching {name}")),
+                        Instant::now(),
+                    );
+                }
+            }
+        }
+        _ => {
+            eprintln!("'patch' requires two arguments, 'file' and 'patchfile'. Type 'h' for a list of commands and their arguments.");
+        }
+    }
+    Flow::Continue
+}
+
+// '5': listing the files on the card
+pub(super) fn list_files(context: &mut CliContext, command: &[&str]) -> Flow {
+    let in_memory = context.in_memory();
+    if let Some(player) = source(&context.mounted, &context.sandbox, &context.player) {
+        match context.fs_cache.list_files(&*player, in_memory) {
+            Ok(files) => print!("{}", render(&files, command)),
+            Err(e) => {
+                eprintln!("{e}")
+            }
+        }
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// '6': deleting a file
+#[cfg(feature = "writing")]
+pub(super) fn delete_file(
+    context: &mut CliContext,
+    rl: &mut dyn Prompt,
+    command: &[&str],
+    line: &str,
+) -> Flow {
+    if let Some(mut player) = source_mut(
+        &mut context.mounted,
+        &mut context.sandbox,
+        &mut context.player,
+    ) {
+        if command.len() < 2 {
+            eprintln!("'6' requires an argument, 'file'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+
+        if touches_tickets(&command[1..2]) {
+            if let Err(e) = backup_tickets(&*player, context.config.ticket_backups) {
+                eprintln!("{e}; not continuing");
+                return Flow::Continue;
+            }
+        }
+
+        match player.DeleteFile(command[1]) {
+            Ok(_) => {
+                println!("DeleteFile success");
+                if !context.in_memory() {
+                    context.post_state.deleted_file(command[1]);
+                }
+                context.ops.succeed();
+            }
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                context.ops.fail(
+                    &e.to_string(),
+                    Some(format!("while deleting {}", command[1])),
+                    Instant::now(),
+                );
+                return offer_similar(context, rl, line, command[1]);
+            }
+        };
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'txn': transactions: several file changes committed as one FS generation
+#[cfg(feature = "writing")]
+pub(super) fn transaction(context: &mut CliContext, rl: &mut dyn Prompt, command: &[&str]) -> Flow {
+    match command.get(1).copied() {
+        Some("begin") => match &context.txn {
+            Some(ops) => eprintln!("A transaction is already open, with {} operations; use 'txn commit' or 'txn abort' first", ops.len()),
+            None => {
+                context.txn = Some(vec![]);
+                context.txn_unconfirmed.clear();
+                println!("Transaction begun; add operations with 'txn add', then use 'txn commit' to carry them all out");
+            }
+        },
+        Some("add") => {
+            let Some(ops) = &mut context.txn else {
+                eprintln!("No transaction is open; use 'txn begin' first");
+                return Flow::Continue;
+            };
+            if command.get(2) == Some(&"4") && command.len() == 4 && context.options.lint && !lint_files(&command[3..4], context.card.free_blocks()) {
+                eprintln!("Not adding {} as it failed the checks; use 'set lint off' to add it anyway", command[3]);
+                return Flow::Continue;
+            }
+            match parse_op(&command[2..], |path| Ok(read(path)?)) {
+                Ok(op) => {
+                    println!("{}: {op}", ops.len() + 1);
+                    ops.push(op);
+                }
+                Err(e) => print_error(&*e, context.options.progress_events),
+            }
+        }
+        Some("show") => {
+            match &context.txn {
+                Some(ops) if ops.is_empty() => println!("The open transaction has no operations yet"),
+                Some(ops) => {
+                    for (i, op) in ops.iter().enumerate() {
+                        println!("{}: {op}", i + 1);
+                    }
+                }
+                None => println!("No transaction is open"),
+            }
+            if !context.txn_unconfirmed.is_empty() {
+                println!("Needs 'txn confirm' before it can be committed:");
+                for f in &context.txn_unconfirmed {
+                    println!("  {f}");
+                }
+            }
+        }
+        Some("confirm") => {
+            if context.txn.is_none() {
+                eprintln!("No transaction is open");
+            } else if context.txn_unconfirmed.is_empty() {
+                println!("Nothing in the transaction needs confirming");
+            } else {
+                context.txn_unconfirmed.clear();
+                println!("Confirmed; the transaction can be committed as it is now");
+            }
+        }
+        Some("abort") => match context.txn.take() {
+            Some(_) => {
+                context.txn_unconfirmed.clear();
+                println!("Transaction abandoned; nothing was changed");
+            }
+            None => eprintln!("No transaction is open"),
+        },
+        Some("commit") => {
+            if context.txn.is_some() && !context.txn_unconfirmed.is_empty() {
+                eprintln!("The transaction was restored with files that have changed since they were queued; check it with 'txn show', then use 'txn confirm' to commit it");
+                return Flow::Continue;
+            }
+            let ops = match context.txn.take() {
+                Some(ops) if !ops.is_empty() => ops,
+                Some(ops) => {
+                    eprintln!("The transaction has no operations to commit; add some with 'txn add', or use 'txn abort'");
+                    context.txn = Some(ops);
+                    return Flow::Continue;
+                }
+                None => {
+                    eprintln!("No transaction is open; use 'txn begin' first");
+                    return Flow::Continue;
+                }
+            };
+            let names = ops.iter().flat_map(Op::names).collect::<Vec<_>>();
+            let result = match (&mut context.mounted, &mut context.sandbox, &mut context.player) {
+                (Some(mounted), _, _) => mounted.commit_txn(&ops).map(|applied| format!("FS #{}; use 'commit' to write the dump back", applied.fs.seqno)),
+                (None, Some(sandbox), Some(_)) => sandbox.commit_txn(&ops).map(|applied| format!("FS #{} in the sandbox; nothing was written to the console", applied.fs.seqno)),
+                (None, _, Some(Console::Open(player))) => {
+                    let prepared = if touches_tickets(&names) { backup_tickets(&*player, context.config.ticket_backups) } else { Ok(()) };
+                    if let Ok(num_blocks) = card_blocks(player) {
+                        preview_txn(&*player, &ops, num_blocks as u16);
+                    }
+                    let result = prepared
+                        .and_then(|_| confirm_dangerous(rl, &mut context.danger, player, "Committing the transaction writes file data and a new FS generation straight to the card", command))
+                        .and_then(|_| {
+                            context.post_state.wrote_blocks();
+                            commit_console(player, &ops, &context.cancel)
+                        });
+                    if result.is_ok() {
+                        for op in &ops {
+                            match op {
+                                Op::Upload { name, data, .. } => context.post_state.wrote_file(name, data.len() as u32),
+                                Op::Delete(name) => context.post_state.deleted_file(name),
+                                Op::Rename { from, to } => context.post_state.renamed_file(from, to),
+                            }
+                        }
+                    }
+                    // it's been written, so a failed check doesn't leave the transaction open to commit again
+                    if let Some(problem) = result.as_ref().ok().and_then(|applied| post_write_fscheck(&context.options, &*player, Some(applied.fs.seqno), None)) {
+                        context.ops.fail(&problem, Some("after committing a transaction".to_string()), Instant::now());
+                        return Flow::Continue;
+                    }
+                    result.map(|applied| format!("FS #{}; use 'finish' to reopen the console and check its FS", applied.fs.seqno))
+                }
+                (None, _, Some(Console::Sealed(_)) | None) => {
+                    eprintln!("No console selected. Have you used the 'l' and 's' commands to select a console?");
+                    context.txn = Some(ops);
+                    return Flow::Continue;
+                }
+            };
+            match result {
+                Ok(done) => {
+                    println!("Committed {} operations as {done}", ops.len());
+                    context.ops.succeed();
+                }
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    eprintln!("The transaction is still open; see the write journal for what was done before it stopped");
+                    context.ops.fail(&e.to_string(), Some("while committing a transaction".to_string()), Instant::now());
+                    context.txn = Some(ops);
+                }
+            }
+        }
+        _ => {
+            eprintln!("'txn' requires a subcommand, 'begin', 'add', 'show', 'confirm', 'commit' or 'abort'. Type 'h' for a list of commands and their arguments.");
+        }
+    }
+    Flow::Continue
+}
+
+// 'sandbox': keeping changes to files in memory instead of writing them
+#[cfg(feature = "writing")]
+pub(super) fn sandbox_mode(context: &mut CliContext, command: &[&str]) -> Flow {
+    match command.get(1).copied() {
+        Some("on") => {
+            if context.sandbox.is_some() {
+                eprintln!("The sandbox is already on; 'sandbox diff' shows what's changed in it, and 'sandbox off' discards that");
+                return Flow::Continue;
+            }
+            if context.mounted.is_some() {
+                eprintln!("The sandbox lays changes over the console, not a mounted dump. Use 'unmount' to return to the console first.");
+                return Flow::Continue;
+            }
+            let Some(player) = context.player.as_ref().map(Console::reads) else {
+                eprintln!("No console selected. Have you used the 'l' and 's' commands to select a console?");
+                return Flow::Continue;
+            };
+            match Sandbox::start(player) {
+                Ok(sandbox) => {
+                    println!("Sandbox on, over the console's FS #{}: changes to its files are kept in memory from now on, and never written to it", sandbox.seqno());
+                    println!("'sandbox diff' shows what would have changed; 'sandbox off' discards it all");
+                    context.sandbox = Some(sandbox);
+                }
+                Err(e) => print_error(&*e, context.options.progress_events),
+            }
+        }
+        Some("diff") => match &context.sandbox {
+            Some(sandbox) => {
+                sandbox.diff().print();
+                println!(
+                    "Blocks of file data that would have been written: {}",
+                    sandbox.blocks_written()
+                );
+            }
+            None => eprintln!("The sandbox isn't on; 'sandbox on' starts it"),
+        },
+        Some("off") => match context.sandbox.take() {
+            Some(sandbox) => println!(
+                "Sandbox off; discarded {} changes to files, and commands use the console again",
+                sandbox.diff().changes.len()
+            ),
+            None => eprintln!("The sandbox isn't on"),
+        },
+        _ => {
+            eprintln!("'sandbox' requires a subcommand, 'on', 'diff' or 'off'. Type 'h' for a list of commands and their arguments.");
+        }
+    }
+    Flow::Continue
+}
+
+// '7': renaming a file
+#[cfg(feature = "writing")]
+pub(super) fn rename_file(
+    context: &mut CliContext,
+    rl: &mut dyn Prompt,
+    command: &[&str],
+    line: &str,
+) -> Flow {
+    if let Some(mut player) = source_mut(
+        &mut context.mounted,
+        &mut context.sandbox,
+        &mut context.player,
+    ) {
+        if command.len() < 3 {
+            eprintln!("'7' requires two arguments, 'from' and 'to'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+
+        if touches_tickets(&command[1..3]) {
+            if let Err(e) = backup_tickets(&*player, context.config.ticket_backups) {
+                eprintln!("{e}; not continuing");
+                return Flow::Continue;
+            }
+        }
+
+        let (from, to) = (command[1], command[2]);
+        match player.RenameFile(from, to) {
+            Ok(_) => {
+                println!("RenameFile success");
+                if !context.in_memory() {
+                    context.post_state.renamed_file(from, to);
+                }
+                context.ops.succeed();
+            }
+            Err(e) => {
+                print_error(&*e, context.options.progress_events);
+                context.ops.fail(
+                    &e.to_string(),
+                    Some(format!("while renaming {} to {}", command[1], command[2])),
+                    Instant::now(),
+                );
+                return offer_similar(context, rl, line, from);
+            }
+        };
+    } else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+    }
+    Flow::Continue
+}
+
+// 'ticket': the backups of ticket.sys, and restoring them
+pub(super) fn tickets(context: &mut CliContext, rl: &mut dyn Prompt, command: &[&str]) -> Flow {
+    #[cfg(not(feature = "writing"))]
+    let _ = rl;
+    match command.get(1).copied() {
+        Some("backups") => {
+            let Some(player) = source(&context.mounted, &context.sandbox, &context.player) else {
+                eprintln!("No console selected. Have you used the 'l' and 's' commands to select a console?");
+                return Flow::Continue;
+            };
+            match player.GetBBID().and_then(list_backups) {
+                Ok(stamps) if stamps.is_empty() => println!("No ticket.sys backups for this console"),
+                Ok(stamps) => {
+                    for stamp in stamps {
+                        println!("{stamp}");
+                    }
+                }
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+        #[cfg(not(feature = "writing"))]
+        Some("restore") => {
+            eprintln!("This version of {PROG_NAME} was built without support for writing; rebuild with `-F writing` to use this command.")
+        }
+        #[cfg(feature = "writing")]
+        Some("restore") => {
+            let Some(stamp) = command.get(2) else {
+                eprintln!("'ticket restore' requires an argument, 'timestamp'. Type 'h' for a list of commands and their arguments.");
+                return Flow::Continue;
+            };
+            let Some(mut player) = source_mut(&mut context.mounted, &mut context.sandbox, &mut context.player) else {
+                eprintln!("No console selected. Have you used the 'l' and 's' commands to select a console?");
+                return Flow::Continue;
+            };
+            let data = match player.GetBBID().and_then(|bbid| load_backup(bbid, stamp)) {
+                Ok(d) => d,
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            };
+            preview_tickets(&*player, Some(&data));
+            let answer = rl.readline(&format!("Replace the console's {TICKET_FILE} with the backup from {stamp}? [y/N] "));
+            if !matches!(answer.as_deref().map(str::trim), Ok("y" | "Y")) {
+                println!("Cancelled");
+                return Flow::Continue;
+            }
+            if let Err(e) = backup_tickets(&*player, context.config.ticket_backups) {
+                eprintln!("{e}; not continuing");
+                return Flow::Continue;
+            }
+            match replace_file(&mut *player, TICKET_FILE, &data, "ticket restore") {
+                Ok(_) => {
+                    println!("Restored {TICKET_FILE} from {stamp}");
+                    if !context.in_memory() {
+                        context.post_state.wrote_file(TICKET_FILE, data.len() as u32);
+                    }
+                }
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+        _ => eprintln!("'ticket' requires a subcommand, 'backups' or 'restore'. Type 'h' for a list of commands and their arguments."),
+    }
+    Flow::Continue
+}
+
+// 'lint': checking files can be uploaded
+pub(super) fn check_names(context: &mut CliContext, command: &[&str]) -> Flow {
+    if command.len() < 2 {
+        eprintln!("'lint' requires at least one argument, 'files'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    lint_files(&command[1..], context.card.free_blocks());
+    Flow::Continue
+}
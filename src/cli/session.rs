@@ -0,0 +1,644 @@
+use super::*;
+
+// The commands about the session itself: help, options, jobs, notes and attestation.
+
+// 'h': the help
+pub(super) fn show_help(command: &[&str]) -> Flow {
+    match command.get(1) {
+        None => print!("{}", help::render(stdout_width())),
+        Some(name) => match help::entries(name, stdout_width()) {
+            Some(entries) => print!("{entries}"),
+            None => eprintln!("'{name}' isn't a command. Type 'h' for a list of commands."),
+        },
+    }
+    Flow::Continue
+}
+
+// '?': the version and licence
+pub(super) fn about() -> Flow {
+    println!(
+        "{PROG_NAME} v{PROG_VER}
+Copyright © 2023, 2024 Jhynjhiruu (https://github.com/Jhynjhiruu)
+{PROG_NAME} is licensed under the GPL v3 (or any later version).
+
+{PROG_NAME} and libbbrdb based on aulon by Jbop; copyright notice reproduced here:
+
+aulon © 2018, 2019, 2020 Jbop (https://github.com/jbop1626)
+aulon is licensed under the GPL v3 (or any later version).
+
+Portions Copyright (c) 2012-2018 Mike Ryan
+Originally released under the MIT license
+
+libusb is licensed under the LGPL v2.1 (or any later version)
+Copyright (c) 2001 Johannes Erdfelt <johannes@erdfelt.com>
+Copyright (c) 2007 - 2009 Daniel Drake <dsd@gentoo.org>
+Copyright (c) 2010 - 2012 Peter Stuge <peter@stuge.se>
+Copyright (c) 2008 - 2016 Nathan Hjelm <hjelmn@users.sourceforge.net>
+Copyright (c) 2009 - 2013 Pete Batard <pete@akeo.ie>
+Copyright (c) 2009 - 2013 Ludovic Rousseau <ludovic.rousseau@gmail.com>
+Copyright (c) 2010 - 2012 Michael Plante <michael.plante@gmail.com>
+Copyright (c) 2011 - 2013 Hans de Goede <hdegoede@redhat.com>
+Copyright (c) 2012 - 2013 Martin Pieuchot <mpi@openbsd.org>
+Copyright (c) 2012 - 2013 Toby Gray <toby.gray@realvnc.com>
+Copyright (c) 2013 - 2018 Chris Dickens <christopher.a.dickens@gmail.com>
+
+See the included file LIBUSB_AUTHORS.txt for more."
+    );
+    Flow::Continue
+}
+
+// 'note': notes and tags on consoles
+pub(super) fn note(context: &mut CliContext, command: &[&str]) -> Flow {
+    let sub = command.get(1).copied().unwrap_or_default();
+    if sub == "list" {
+        match notes::list() {
+            Ok(notes) if notes.is_empty() => println!("No notes yet"),
+            Ok(notes) => {
+                for (key, note) in notes {
+                    println!("{key}: {}", note.summary());
+                }
+            }
+            Err(e) => eprintln!("{e}"),
+        }
+        return Flow::Continue;
+    }
+    if !["set", "tag", "show"].contains(&sub) {
+        eprintln!("'note' requires a subcommand, 'set', 'tag', 'show' or 'list'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    let Some(player) = source(&context.mounted, &context.sandbox, &context.player) else {
+        eprintln!(
+            "No console selected. Have you used the 'l' and 's' commands to select a console?"
+        );
+        return Flow::Continue;
+    };
+    let bbid = player.GetBBID().ok();
+    let serial = match (&context.mounted, &context.selected) {
+        (None, Some(selected)) => selected.serial(),
+        _ => None,
+    };
+    let note = match lookup(bbid, serial.as_deref()) {
+        Ok(n) => n.unwrap_or_default(),
+        Err(e) => {
+            print_error(&*e, context.options.progress_events);
+            return Flow::Continue;
+        }
+    };
+    let note = match (sub, command.len()) {
+        ("show", _) => {
+            if note.is_empty() {
+                println!("No note for this console");
+            } else {
+                print!("{note}");
+            }
+            return Flow::Continue;
+        }
+        ("set", 3..) => Note {
+            text: command[2..].join(" "),
+            ..note
+        },
+        ("tag", 3..) => {
+            let mut note = note;
+            note.set_tag(command[2], &command[3..].join(" "));
+            note
+        }
+        ("set", _) => {
+            eprintln!("'note set' requires an argument, 'text'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+        _ => {
+            eprintln!("'note tag' requires an argument, 'key'. Type 'h' for a list of commands and their arguments.");
+            return Flow::Continue;
+        }
+    };
+    match notes::save(bbid, serial.as_deref(), &note) {
+        Ok(_) => println!("Saved the note"),
+        Err(e) => eprintln!("{e}"),
+    }
+    Flow::Continue
+}
+
+// 'jobs': the background jobs
+pub(super) fn list_jobs(context: &mut CliContext) -> Flow {
+    if context.jobs.list().is_empty() {
+        println!("No jobs yet; end a command with '&' to run it in the background");
+    }
+    for job in context.jobs.list() {
+        println!("{}", job.line());
+    }
+    Flow::Continue
+}
+
+// 'fg': waiting for one
+pub(super) fn foreground(context: &mut CliContext, command: &[&str]) -> Flow {
+    let id = match command.get(1).map(|id| id.parse::<usize>()) {
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => {
+            eprintln!("'{}' isn't a job number; 'jobs' lists them", command[1]);
+            return Flow::Continue;
+        }
+        None => context.jobs.list().last().map(|j| j.id),
+    };
+    let Some(id) = id else {
+        eprintln!("There are no jobs; end a command with '&' to run it in the background");
+        return Flow::Continue;
+    };
+    // Ctrl+C while waiting stops the job, as it would have in the foreground
+    let mut follow = Follow::start(context.options.progress_events);
+    let cancel = &context.cancel;
+    let waited = context.jobs.wait(id, &mut |job| {
+        follow.update(&job.meter, &job.command);
+        !cancel.is_cancelled()
+    });
+    follow.finish();
+    match waited {
+        Ok((console, notice)) => {
+            if let Some(console) = console {
+                context.player = Some(console);
+            }
+            println!("{notice}");
+        }
+        Err(e) => print_error(&*e, context.options.progress_events),
+    }
+    Flow::Continue
+}
+
+// 'status': the state of the session
+pub(super) fn status(context: &mut CliContext, command: &[&str]) -> Flow {
+    match (&context.mounted, &context.selected) {
+        (Some(mounted), _) => {
+            let (nand, _, rw) = mounted.source_files();
+            println!("Mounted: {nand}{}", if rw { " (read-write)" } else { "" });
+        }
+        (None, Some(selected)) => println!(
+            "Console: player {}{}",
+            selected.index,
+            if context.auto_opened {
+                " (opened automatically at startup)"
+            } else {
+                ""
+            }
+        ),
+        (None, None) => println!("No console selected and no dump mounted"),
+    }
+    if let Some(job) = context.jobs.holder() {
+        println!("Console busy with job {} ({})", job.id, job.status());
+    }
+    match (context.card.seqno(), context.card.free_blocks()) {
+        (Some(seqno), Some(free)) => println!("Card: FS #{seqno}, {free} blocks free"),
+        (Some(seqno), None) => println!("Card: FS #{seqno}"),
+        (None, Some(free)) => println!("Card: {free} blocks free"),
+        (None, None) => {}
+    }
+    if let Some(preset) = &context.preset {
+        println!("Preset: {preset}");
+    }
+    #[cfg(feature = "writing")]
+    if let Some(ops) = &context.txn {
+        println!("Transaction open: {} operations", ops.len());
+    }
+    #[cfg(feature = "writing")]
+    if let Some(sandbox) = &context.sandbox {
+        println!(
+            "Sandbox on, over FS #{}: {} changes to files held back",
+            sandbox.seqno(),
+            sandbox.diff().changes.len()
+        );
+    }
+    if command.contains(&"--debug") {
+        let holding = match context.fs_cache.seqno() {
+            Some(seqno) => format!("holding FS #{seqno}"),
+            None => "empty".to_string(),
+        };
+        println!(
+            "FS cache: {} hits, {} misses; {holding}",
+            context.fs_cache.hits, context.fs_cache.misses
+        );
+    }
+    Flow::Continue
+}
+
+// 'report': saving the last few commands
+pub(super) fn report_ops(context: &mut CliContext, command: &[&str]) -> Flow {
+    if command.get(1) != Some(&"save") || command.len() < 3 {
+        eprintln!("'report save' requires an argument, 'file'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    match context.ops.save(
+        command[2],
+        &format!("{PROG_NAME} v{PROG_VER}"),
+        &context.options.lines(),
+        known_errors::last_match().as_deref(),
+    ) {
+        Ok(_) => println!(
+            "Saved a report of the last error and recent commands to {}",
+            command[2]
+        ),
+        Err(e) => eprintln!("{e}"),
+    }
+    Flow::Continue
+}
+
+// 'notify': trying the notification hooks
+pub(super) fn notify_test(context: &mut CliContext, command: &[&str]) -> Flow {
+    if command.get(1) != Some(&"test") {
+        eprintln!("'notify' requires an argument, 'test'. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    }
+    let Some(hook) = &context.options.notify_command else {
+        eprintln!("No notify-command is set; use 'set notify-command <command>' first");
+        return Flow::Continue;
+    };
+    fire(
+        hook,
+        &Outcome {
+            operation: "test".to_string(),
+            bbid: source(&context.mounted, &context.sandbox, &context.player)
+                .and_then(|p| p.GetBBID().ok()),
+            duration: std::time::Duration::ZERO,
+            error: None,
+            outputs: vec![],
+        },
+    );
+    println!("Test notification sent");
+    Flow::Continue
+}
+
+// 'caps': what this build can do
+pub(super) fn capabilities(context: &mut CliContext, command: &[&str]) -> Flow {
+    let caps = help::capabilities();
+    if command.contains(&"--json") {
+        match serde_json::to_string(&caps) {
+            Ok(json) => println!("{json}"),
+            Err(e) => print_error(&e, context.options.progress_events),
+        }
+    } else {
+        for (feature, built) in &caps.features {
+            println!(
+                "{feature}: {}",
+                if *built { "built in" } else { "not built in" }
+            );
+        }
+        for c in caps.commands.iter().filter(|c| !c.available) {
+            let needs = c.needs.map(|f| f.name()).unwrap_or_default();
+            println!("unavailable: {} (needs {needs})", c.usage);
+        }
+    }
+    Flow::Continue
+}
+
+// 'selftest': checking the program's own code paths
+pub(super) fn self_test() -> Flow {
+    if !selftest() {
+        eprintln!(
+            "Self-test failed; this build of {PROG_NAME} shouldn't be trusted with a console"
+        );
+    }
+    Flow::Continue
+}
+
+// 'preset': choosing a preset
+pub(super) fn select_preset(context: &mut CliContext, command: &[&str]) -> Flow {
+    match (command.get(1).copied(), command.get(2).copied()) {
+        (None, _) => {
+            for (name, preset) in all(&context.config.presets) {
+                let active = if context.preset.as_deref() == Some(&*name) {
+                    " (in use)"
+                } else {
+                    ""
+                };
+                let from = if context.config.presets.contains_key(&name) {
+                    "config file"
+                } else {
+                    "built in"
+                };
+                let options = preset
+                    .iter()
+                    .map(|(o, v)| format!("{o} {v}"))
+                    .collect::<Vec<_>>();
+                println!("{name}{active} [{from}]: {}", options.join(", "));
+            }
+        }
+        (Some("show"), None) => {
+            eprintln!("'preset show' requires an argument, 'name'. Type 'h' for a list of commands and their arguments.");
+        }
+        (Some("show"), Some(name)) => match context.resolve_options(Some(name)) {
+            Ok(options) => {
+                println!("With the '{name}' preset, and the config file's [options], this session's 'set' commands and any profile's pins over it:");
+                options.print();
+            }
+            Err(e) => print_error(&*e, context.options.progress_events),
+        },
+        (Some(name), _) => {
+            let preset = (name != "off").then_some(name);
+            match context.resolve_options(preset) {
+                Ok(options) => {
+                    context.options = options;
+                    context.preset = preset.map(str::to_string);
+                    match preset {
+                        Some(name) => println!("Using the '{name}' preset; options set with 'set' this session still go over it"),
+                        None => println!("No preset in use"),
+                    }
+                }
+                Err(e) => print_error(&*e, context.options.progress_events),
+            }
+        }
+    }
+    Flow::Continue
+}
+
+// 'set': changing options
+pub(super) fn set_option(context: &mut CliContext, command: &[&str]) -> Flow {
+    match command.len() {
+        1 => {
+            context.options.print();
+            println!("sink: {}", context.sink);
+        }
+        2 => eprintln!(
+            "'set' requires a value for '{}'. Type 'h' for a list of commands and their arguments.",
+            command[1]
+        ),
+        _ if command[1] == "sink" => match OutputSink::open(command[2]) {
+            Ok(sink) => {
+                if let Err(e) = std::mem::replace(&mut context.sink, sink).finish() {
+                    print_error(&*e, context.options.progress_events);
+                }
+                println!("sink set to {}", context.sink);
+            }
+            Err(e) => eprintln!("{e}"),
+        },
+        _ => {
+            if let Some(Err(e)) = context.profile.as_ref().map(|p| p.check_set(command[1])) {
+                print_error(&*e, context.options.progress_events);
+                return Flow::Continue;
+            }
+            let value = command[2..].join(" ");
+            match context.options.set(command[1], &value) {
+                Ok(_) => {
+                    println!("{} set to {value}", command[1]);
+                    context.sets.push((command[1].to_string(), value));
+                }
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+    }
+    Flow::Continue
+}
+
+// 'lock': locking dangerous operations again
+#[cfg(feature = "writing")]
+pub(super) fn lock(context: &mut CliContext) -> Flow {
+    if context.danger.lock() {
+        println!("Dangerous operations locked");
+    } else {
+        println!("Dangerous operations are already locked");
+    }
+    Flow::Continue
+}
+
+// 'session': saving and loading sessions, and attesting them
+pub(super) fn session_state(context: &mut CliContext, command: &[&str]) -> Flow {
+    match (command.get(1).copied(), command.get(2)) {
+        (Some("save"), Some(path)) => {
+            #[cfg(feature = "writing")]
+            if let Some(m) = context.mounted.as_ref().filter(|m| m.dirty) {
+                eprintln!("Note: {}'s uncommitted changes aren't saved in the snapshot; 'commit' them to keep them", m.name);
+            }
+            let snapshot = Snapshot {
+                schema: SESSION_SCHEMA,
+                saved_by: format!("{PROG_NAME} v{PROG_VER}"),
+                options: context.options.clone(),
+                cwd: std::env::current_dir().ok().map(|d| d.display().to_string()),
+                mounted: context.mounted.as_ref().map(|m| {
+                    let (nand, spare, rw) = m.source_files();
+                    MountSnapshot {
+                        nand: nand.to_string(),
+                        spare: spare.to_string(),
+                        rw,
+                        digests: [nand, spare].into_iter().filter_map(|f| FileDigest::of_file(f).ok()).collect(),
+                    }
+                }),
+                bbid: context.player.as_ref().and_then(|p| p.reads().GetBBID().ok()),
+                free_blocks: context.card.free_blocks(),
+                seqno: context.card.seqno(),
+                #[cfg(feature = "writing")]
+                txn: context.txn.as_ref().map(|ops| ops.iter().filter_map(Op::saved).collect()),
+                #[cfg(not(feature = "writing"))]
+                txn: None,
+            };
+            match snapshot.save(path) {
+                Ok(_) => println!("Saved the session to {path}"),
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+        (Some("load"), Some(path)) => {
+            #[cfg(feature = "writing")]
+            if context.mounted.as_ref().is_some_and(|m| m.dirty) {
+                eprintln!("The mounted dump has uncommitted changes; 'commit' or 'unmount' it first");
+                return Flow::Continue;
+            }
+            let snapshot = match Snapshot::load(path) {
+                Ok(s) => s,
+                Err(e) => {
+                    print_error(&*e, context.options.progress_events);
+                    return Flow::Continue;
+                }
+            };
+            #[cfg(feature = "writing")]
+            context.danger.lock();
+            // whatever can't be restored is listed at the end, and the rest still is
+            let mut skipped = vec![];
+            context.options = snapshot.options;
+            // the snapshot's options are complete; a preset chosen before no longer applies
+            context.preset = None;
+            context.sets.clear();
+            if let Some(profile) = &context.profile {
+                if let Err(e) = profile.apply(&mut context.options) {
+                    print_error(&*e, context.options.progress_events);
+                }
+            }
+            context.card = CardView::restored(snapshot.free_blocks, snapshot.seqno);
+            if let Some(cwd) = &snapshot.cwd {
+                if let Err(e) = std::env::set_current_dir(cwd) {
+                    skipped.push(format!("working directory {cwd}: {e}"));
+                }
+            }
+
+            context.mounted = None;
+            if let Some(m) = &snapshot.mounted {
+                // the dump is still mounted if it's changed, but not without saying so
+                for problem in m.digests.iter().filter_map(FileDigest::problem) {
+                    eprintln!("Warning: {problem}");
+                }
+                #[cfg(feature = "writing")]
+                let mounted = if m.rw {
+                    MountedImage::load_rw(&m.nand, &m.spare)
+                } else {
+                    MountedImage::load(&m.nand, &m.spare)
+                };
+                #[cfg(not(feature = "writing"))]
+                let mounted = MountedImage::load(&m.nand, &m.spare);
+                match mounted {
+                    Ok(image) => {
+                        println!("Mounted {}", image.name);
+                        context.mounted = Some(image);
+                    }
+                    Err(e) => skipped.push(format!("mounted dump {}: {e}", m.nand)),
+                }
+            }
+
+            // device handles aren't saved; the same console is selected again if it's here
+            if let Some(bbid) = snapshot.bbid {
+                let current = context.player.as_ref().and_then(|p| p.reads().GetBBID().ok());
+                if current == Some(bbid) {
+                    println!("Console {bbid:08X} is already selected");
+                } else if context.player.is_some() {
+                    skipped.push(format!("console {bbid:08X}: another console is selected; use 'Q' first"));
+                } else if !context.usb.ready() {
+                    skipped.push(format!("console {bbid:08X}: the USB subsystem isn't available"));
+                } else {
+                    match reselect(bbid) {
+                        Ok(Some((player, selected))) => {
+                            let index = selected.index;
+                            match context.adopt(player, selected, false) {
+                                Ok(_) => println!("Selected player {index} (console {bbid:08X}); use 'B' to initialise it"),
+                                Err(e) => skipped.push(format!("console {bbid:08X}: {e}")),
+                            }
+                        }
+                        Ok(None) => skipped.push(format!("console {bbid:08X}: not connected")),
+                        Err(e) => skipped.push(format!("console {bbid:08X}: {e}")),
+                    }
+                }
+            }
+
+            // a transaction whose uploads have changed since they were queued waits for
+            // 'txn confirm' before it can be committed
+            if let Some(saved) = &snapshot.txn {
+                #[cfg(feature = "writing")]
+                {
+                    let restored = restore(saved, context.options.strict_writes);
+                    println!("Restored the open transaction, with {} operations", restored.ops.len());
+                    if !restored.flagged.is_empty() {
+                        eprintln!("Its files have changed since it was saved; check it with 'txn show', then use 'txn confirm' before committing it:");
+                        for f in &restored.flagged {
+                            eprintln!("  {f}");
+                        }
+                    }
+                    context.txn = Some(restored.ops);
+                    context.txn_unconfirmed = restored.flagged;
+                }
+                #[cfg(not(feature = "writing"))]
+                skipped.push(format!("the open transaction ({} operations): this version was built without support for writing", saved.len()));
+            }
+
+            println!("Restored the session saved by {}", snapshot.saved_by);
+            for s in skipped {
+                eprintln!("  Not restored: {s}");
+            }
+        }
+        (Some("read-only-attest"), None) => {
+            if context.attestation.is_some() {
+                eprintln!("This session is already attested");
+                return Flow::Continue;
+            }
+            if context.mounted.is_some() {
+                eprintln!("An attested session reads from a console, not a dump. Use 'unmount' to return to the console first.");
+                return Flow::Continue;
+            }
+            #[cfg(feature = "writing")]
+            if context.sandbox.is_some() || context.txn.is_some() {
+                eprintln!("Turn the sandbox off and close any open transaction before attesting the session");
+                return Flow::Continue;
+            }
+            let handle = match context.player.take() {
+                Some(Console::Open(handle)) if matches!(handle.initialised(), Ok(true)) => handle,
+                Some(other) => {
+                    context.player = Some(other);
+                    eprintln!("Initialise the connection with 'B' first; it can't be used once the session is attested");
+                    return Flow::Continue;
+                }
+                None => {
+                    eprintln!("No console selected. Have you used the 'l' and 's' commands to select a console?");
+                    return Flow::Continue;
+                }
+            };
+            let bbid = handle.GetBBID().ok();
+            context.player = Some(Console::Sealed(ReadOnly::seal(handle)));
+            context.attestation = Some(Attestation::start(bbid, Local::now().to_rfc3339()));
+            #[cfg(feature = "writing")]
+            context.danger.lock();
+            println!("The console can only be read from now, until {PROG_NAME} is quit: commands that could write to it are refused, and every command is logged. 'attest report file' saves the log.");
+        }
+        _ => eprintln!("'session' requires a subcommand, 'save' or 'load' and a file, or 'read-only-attest'. Type 'h' for a list of commands and their arguments."),
+    }
+    Flow::Continue
+}
+
+// 'attest': the log of an attested session
+pub(super) fn attest_report(context: &mut CliContext, command: &[&str]) -> Flow {
+    let Some(attestation) = &context.attestation else {
+        eprintln!("This session isn't attested; 'session read-only-attest' starts an attested one");
+        return Flow::Continue;
+    };
+    let (Some("report"), Some(path)) = (command.get(1).copied(), command.get(2)) else {
+        eprintln!("'attest' requires a subcommand, 'report', and a file. Type 'h' for a list of commands and their arguments.");
+        return Flow::Continue;
+    };
+    let saved = load_key(context.config.attest_key.as_deref())
+        .and_then(|key| {
+            report(
+                attestation,
+                &Local::now().to_rfc3339(),
+                key.as_ref().map(|(p, k)| (*p, k.as_slice())),
+            )
+        })
+        .and_then(|text| write_atomic(path, text.as_bytes()));
+    match saved {
+        Ok(()) => println!(
+            "Saved the log of {} commands to {path}",
+            attestation.entries.len()
+        ),
+        Err(e) => print_error(&*e, context.options.progress_events),
+    }
+    Flow::Continue
+}
+
+// 'q': quitting
+pub(super) fn quit(context: &mut CliContext, rl: &mut dyn Prompt) -> Flow {
+    #[cfg(feature = "writing")]
+    if keep_uncommitted(context, rl) {
+        return Flow::Continue;
+    }
+    if let Some(job) = context.jobs.holder() {
+        let answer = rl.readline(&format!(
+            "Job {} ({}) is still running. Stop it and quit? [y/N] ",
+            job.id,
+            job.status()
+        ));
+        if !matches!(answer.as_deref().map(str::trim), Ok("y" | "Y")) {
+            return Flow::Continue;
+        }
+        if let Some(console) = context.jobs.stop_all() {
+            context.player = Some(console);
+        }
+    }
+    if let Some(Console::Open(player)) =
+        context.player.as_mut().filter(|_| context.post_state.dirty)
+    {
+        let answer = rl.readline("The console's card was changed this session. Check it's safe to disconnect first? [Y/n] ");
+        if !matches!(answer.as_deref().map(str::trim), Ok("n" | "N")) {
+            match finish(player, &context.post_state) {
+                Ok(true) => {}
+                Ok(false) => {
+                    eprintln!("Not quitting; type 'q' again and answer 'n' to quit anyway");
+                    return Flow::Continue;
+                }
+                Err(e) => {
+                    eprintln!("{e}; not quitting, type 'q' again and answer 'n' to quit anyway");
+                    return Flow::Continue;
+                }
+            }
+        }
+    }
+    Flow::Quit
+}
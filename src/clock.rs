@@ -0,0 +1,226 @@
+use std::fmt;
+use std::time::Instant;
+
+// 'J' with no time sets the console's clock to the PC's, so a PC whose clock is wrong (a
+// recovery machine with a flat CMOS battery, say) passes it on, and limited-play tickets and save
+// timestamps go wrong with it. Before that, the PC's clock is checked against a few rules, and
+// any that fail are warned about.
+
+// the iQue Player went on sale in November 2003, so a PC clock from before then has been reset
+pub const LAUNCH_ERA: i64 = 1_067_644_800;
+
+// how far the PC may be from the console's clock as this session last set it
+pub const MAX_DRIFT_SECS: i64 = 5 * 60;
+
+// zones that are (at least part of the year) at UTC+0, so a PC in them legitimately shows no
+// offset; matched as prefixes of the zone's name
+const ZERO_OFFSET_ZONES: &[&str] = &[
+    "UTC",
+    "UCT",
+    "GMT",
+    "Etc/",
+    "Universal",
+    "Zulu",
+    "Greenwich",
+    "WET",
+    "Europe/London",
+    "Europe/Belfast",
+    "Europe/Dublin",
+    "Europe/Guernsey",
+    "Europe/Isle_of_Man",
+    "Europe/Jersey",
+    "Europe/Lisbon",
+    "Atlantic/",
+    "Africa/Abidjan",
+    "Africa/Accra",
+    "Africa/Bamako",
+    "Africa/Banjul",
+    "Africa/Bissau",
+    "Africa/Casablanca",
+    "Africa/Conakry",
+    "Africa/Dakar",
+    "Africa/El_Aaiun",
+    "Africa/Freetown",
+    "Africa/Lome",
+    "Africa/Monrovia",
+    "Africa/Nouakchott",
+    "Africa/Ouagadougou",
+    "Africa/Sao_Tome",
+    "America/Danmarkshavn",
+    "America/Scoresbysund",
+    "Antarctica/Troll",
+];
+
+// the PC's clock as it is now
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcClock<'a> {
+    // seconds since the Unix epoch
+    pub timestamp: i64,
+    pub utc_offset: i32,
+    // the zone the machine is configured for, if that can be found
+    pub zone: Option<&'a str>,
+}
+
+// the time this session last set the console's clock to, so where it should be now can be
+// worked out without asking the console (which can't be asked)
+#[derive(Debug, Clone, Copy)]
+pub struct ConsoleClock {
+    timestamp: i64,
+    at: Instant,
+}
+
+impl ConsoleClock {
+    pub fn set(timestamp: i64, at: Instant) -> Self {
+        Self { timestamp, at }
+    }
+
+    pub fn expected(&self, now: Instant) -> i64 {
+        self.timestamp + now.saturating_duration_since(self.at).as_secs() as i64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClockWarning {
+    BeforeLaunch,
+    // seconds the PC is ahead of the console (behind, if negative)
+    Drift(i64),
+    // the PC shows UTC, but is configured for this zone
+    UtcInZone(String),
+}
+
+impl fmt::Display for ClockWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BeforeLaunch => write!(
+                f,
+                "The PC's clock is set to before the iQue Player was released; its CMOS battery may be flat"
+            ),
+            Self::Drift(secs) => write!(
+                f,
+                "The PC's clock is {}m {}s {} the time this session set the console to",
+                secs.abs() / 60,
+                secs.abs() % 60,
+                if *secs > 0 { "ahead of" } else { "behind" }
+            ),
+            Self::UtcInZone(zone) => write!(
+                f,
+                "The PC's clock shows UTC, but the PC is configured for {zone}; its time zone may not have been applied"
+            ),
+        }
+    }
+}
+
+fn zero_offset_zone(zone: &str) -> bool {
+    ZERO_OFFSET_ZONES.iter().any(|z| zone.starts_with(z))
+}
+
+// everything that looks wrong about the PC's clock, given where the console's should be
+pub fn check(pc: &PcClock, console: Option<i64>) -> Vec<ClockWarning> {
+    let mut warnings = vec![];
+    if pc.timestamp < LAUNCH_ERA {
+        warnings.push(ClockWarning::BeforeLaunch);
+    }
+    if let Some(console) = console {
+        let drift = pc.timestamp - console;
+        if drift.abs() > MAX_DRIFT_SECS {
+            warnings.push(ClockWarning::Drift(drift));
+        }
+    }
+    match pc.zone {
+        Some(zone) if pc.utc_offset == 0 && !zero_offset_zone(zone) => {
+            warnings.push(ClockWarning::UtcInZone(zone.to_string()))
+        }
+        _ => {}
+    }
+    warnings
+}
+
+// the zone the machine is configured for: $TZ, or on Linux, /etc/timezone or where
+// /etc/localtime points
+pub fn configured_zone() -> Option<String> {
+    if let Ok(tz) = std::env::var("TZ") {
+        let tz = tz.trim_start_matches(':');
+        if !tz.is_empty() {
+            return Some(tz.to_string());
+        }
+    }
+    if let Ok(zone) = std::fs::read_to_string("/etc/timezone") {
+        if !zone.trim().is_empty() {
+            return Some(zone.trim().to_string());
+        }
+    }
+    let link = std::fs::read_link("/etc/localtime").ok()?;
+    let link = link.to_str()?;
+    link.split_once("zoneinfo/").map(|(_, z)| z.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Result};
+
+    #[test]
+    fn pc_clock() -> Result<()> {
+        // 2024-06-01T12:00:00Z
+        let now = 1_717_243_200;
+        let pc = |timestamp, utc_offset, zone| PcClock {
+            timestamp,
+            utc_offset,
+            zone,
+        };
+
+        let cases: [(PcClock, Option<i64>, &[ClockWarning]); 8] = [
+            (pc(now, 28800, Some("Asia/Shanghai")), None, &[]),
+            (pc(now, 0, Some("Etc/UTC")), Some(now - 30), &[]),
+            (pc(now, 0, Some("Europe/London")), None, &[]),
+            (pc(now, 0, None), None, &[]),
+            // a flat battery's 1 January 2000
+            (
+                pc(946_684_800, 28800, Some("Asia/Shanghai")),
+                None,
+                &[ClockWarning::BeforeLaunch],
+            ),
+            (
+                pc(now, 28800, Some("Asia/Shanghai")),
+                Some(now - 3600),
+                &[ClockWarning::Drift(3600)],
+            ),
+            (
+                pc(now, 0, Some("Asia/Shanghai")),
+                Some(now + MAX_DRIFT_SECS + 1),
+                &[
+                    ClockWarning::Drift(-MAX_DRIFT_SECS - 1),
+                    ClockWarning::UtcInZone("Asia/Shanghai".to_string()),
+                ],
+            ),
+            (
+                pc(0, 0, Some("America/New_York")),
+                Some(0),
+                &[
+                    ClockWarning::BeforeLaunch,
+                    ClockWarning::UtcInZone("America/New_York".to_string()),
+                ],
+            ),
+        ];
+        for (pc, console, expected) in cases {
+            if check(&pc, console) != expected {
+                bail!(
+                    "{pc:?} against {console:?} gave {:?}, not {expected:?}",
+                    check(&pc, console)
+                );
+            }
+        }
+
+        let start = Instant::now();
+        let console = ConsoleClock::set(now, start);
+        if console.expected(start + std::time::Duration::from_secs(90)) != now + 90 {
+            bail!("the console's clock wasn't kept in step");
+        }
+        if ClockWarning::Drift(-125).to_string()
+            != "The PC's clock is 2m 5s behind the time this session set the console to"
+        {
+            bail!("drift was described as: {}", ClockWarning::Drift(-125));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use std::borrow::Cow;
+use std::fs::read;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+
+// Dumps saved compressed, and compressed images written back, going by the file's name: one
+// ending in '.gz' is gzip and one ending in '.zst' is zstd, whatever comes before. Sizes and
+// hashes (manifests included) are always of the uncompressed data, so 'nand.bin.zst' checks out
+// against the same manifest as 'nand.bin'.
+
+// zstd's default; dumps are mostly erased blocks, which any level squeezes down to nothing
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Gzip,
+    Zstd,
+}
+
+impl Codec {
+    pub fn from_path(path: &str) -> Option<Self> {
+        let ext = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "gz" => Some(Self::Gzip),
+            "zst" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn ext(self) -> &'static str {
+        match self {
+            Self::Gzip => "gz",
+            Self::Zstd => "zst",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            }
+            Self::Zstd => zstd::encode_all(data, ZSTD_LEVEL)?,
+        })
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut out = vec![];
+        match self {
+            Self::Gzip => {
+                GzDecoder::new(data).read_to_end(&mut out)?;
+            }
+            Self::Zstd => out = zstd::decode_all(data)?,
+        }
+        Ok(out)
+    }
+}
+
+// what to save under `path`: `data` compressed if the name asks for it, as is otherwise
+pub fn encode_for<'a>(path: &str, data: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+    match Codec::from_path(path) {
+        None => Ok(Cow::Borrowed(data)),
+        Some(codec) => codec
+            .compress(data)
+            .map(Cow::Owned)
+            .map_err(|e| anyhow!("couldn't {} {path}: {e}", codec.name())),
+    }
+}
+
+// the name to save a dump under with 'set compress' on: the codec's extension added, unless the
+// name already asks for a codec of its own
+pub fn with_codec(path: &str, codec: Option<Codec>) -> Cow<'_, str> {
+    match codec {
+        Some(c) if Codec::from_path(path).is_none() => Cow::Owned(format!("{path}.{}", c.ext())),
+        _ => Cow::Borrowed(path),
+    }
+}
+
+// the contents of `path`, decompressed if its name says it's compressed
+pub fn read_input(path: &str) -> Result<Vec<u8>> {
+    let data = read(path).map_err(|e| anyhow!("{path}: {e}"))?;
+    match Codec::from_path(path) {
+        None => Ok(data),
+        Some(codec) => codec.decompress(&data).map_err(|e| {
+            anyhow!(
+                "{path} doesn't decompress as {}, as its name says it should: {e}",
+                codec.name()
+            )
+        }),
+    }
+}
+
+// a compressed file can't be appended to block by block, so a download into one is never resumed
+pub fn check_resumable(path: &str) -> Result<()> {
+    if let Some(codec) = Codec::from_path(path) {
+        bail!(
+            "'3 --continue' can't resume into {path}, which is saved {}-compressed; read it again without '--continue', or resume into the uncompressed name and compress it afterwards",
+            codec.name()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn compression() -> Result<()> {
+        // a dump-like image: erased blocks with a little data in between
+        let mut data = vec![0xFF; 0x10000];
+        for (i, b) in data[0x4000..0x4400].iter_mut().enumerate() {
+            *b = (i * 7 % 251) as u8;
+        }
+
+        for (name, codec) in [
+            ("nand.bin.gz", Some(Codec::Gzip)),
+            ("dumps/NAND.BIN.ZST", Some(Codec::Zstd)),
+            ("nand.bin", None),
+            ("nand.gz.bin", None),
+            ("dumps.zst/nand", None),
+        ] {
+            if Codec::from_path(name) != codec {
+                bail!("{name} was taken as {:?}", Codec::from_path(name));
+            }
+        }
+
+        // round trips through each format, and through files named for them
+        let dir = TestDir::new("compress")?;
+        for codec in [Codec::Gzip, Codec::Zstd] {
+            if codec.decompress(&codec.compress(&data)?)? != data {
+                bail!("{} didn't round-trip", codec.name());
+            }
+            if !codec.decompress(&codec.compress(&[])?)?.is_empty() {
+                bail!("{} didn't round-trip an empty file", codec.name());
+            }
+        }
+        for name in ["nand.bin", "nand.bin.gz", "nand.bin.zst"] {
+            let path = dir.join(name).to_string_lossy().into_owned();
+            crate::sink::write_atomic(&path, &encode_for(&path, &data)?)?;
+            let back = read_input(&path)?;
+            if back.len() != data.len() || back != data {
+                bail!("{name} read back as {:#X} different bytes", back.len());
+            }
+        }
+
+        for (name, codec, expected) in [
+            ("nand.bin", Some(Codec::Zstd), "nand.bin.zst"),
+            ("nand.bin", Some(Codec::Gzip), "nand.bin.gz"),
+            ("nand.bin.gz", Some(Codec::Zstd), "nand.bin.gz"),
+            ("nand.bin", None, "nand.bin"),
+        ] {
+            if with_codec(name, codec) != expected {
+                bail!(
+                    "{name} was saved as {} with {codec:?}",
+                    with_codec(name, codec)
+                );
+            }
+        }
+
+        if check_resumable("file.app").is_err()
+            || check_resumable("file.app.gz").is_ok()
+            || check_resumable("file.app.zst").is_ok()
+        {
+            bail!("resuming was allowed into the wrong files");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::acceptance::AcceptancePolicy;
+use crate::hooks::HookConfig;
+use crate::preset::Preset;
+use crate::profile::Profile;
+use crate::startup::AutoOpen;
+#[cfg(feature = "writing")]
+use crate::wear::WearPolicy;
+use crate::PROG_NAME;
+
+const CONFIG_FILE: &str = "config.toml";
+
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join(PROG_NAME))
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Config {
+    // the console to select at startup: "bbid:<hex>" or "serial:<USB serial number>"
+    pub preferred_console: Option<String>,
+    // open each connected console to read its BBID when looking for the preferred one
+    pub probe_for_preferred: bool,
+    // whether a console is opened at startup: 'never' (the default) only says what's connected,
+    // 'preferred' opens the preferred console, and 'single' that or else the only one connected
+    pub auto_open: AutoOpen,
+    // initialise the console selected at startup, or one selected when it's plugged in
+    pub auto_init: bool,
+    // say when consoles are plugged in or unplugged at the prompt (on if not set)
+    pub watch_hotplug: Option<bool>,
+    // record each console's card stats whenever 'C' is used, keeping this many records (1000 if not set)
+    pub stats_history: bool,
+    pub stats_history_limit: Option<usize>,
+    // how many ticket.sys backups to keep for each console (10 if not set)
+    #[cfg(feature = "writing")]
+    pub ticket_backups: Option<usize>,
+    // how many bytes of a file 'cat' reads and prints (4096 if not set)
+    pub cat_max_bytes: Option<usize>,
+    // options set at startup, over the preset's if one is chosen ('lint = "off"' and so on)
+    pub options: BTreeMap<String, String>,
+    // presets for 'preset' and '--preset', by name, as well as (or instead of) the built-in ones
+    pub presets: BTreeMap<String, Preset>,
+    // permission profiles for '--profile', by name
+    pub profiles: BTreeMap<String, Profile>,
+    // when '2' warns about wearing out the card
+    #[cfg(feature = "writing")]
+    pub wear: WearPolicy,
+    // which checks 'acceptance' runs at this station, and how strict they are
+    pub acceptance: AcceptancePolicy,
+    // commands run after operations, such as 'post_dump'
+    pub hooks: HookConfig,
+    // a file whose contents are the key 'attest report' signs with
+    pub attest_key: Option<String>,
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        let Some(path) = config_dir().map(|d| d.join(CONFIG_FILE)) else {
+            return Ok(Self::default());
+        };
+        match read_to_string(&path) {
+            Ok(s) => toml::from_str(&s).map_err(|e| anyhow!("{}: {e}", path.display())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(anyhow!("{}: {e}", path.display())),
+        }
+    }
+}
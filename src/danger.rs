@@ -0,0 +1,188 @@
+use std::io::{stdin, IsTerminal};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::fs::{FS_REGION_BLOCKS, SKSA_BLOCKS};
+use crate::player::Backend;
+use crate::prompt::Prompt;
+
+const UNLOCK_DURATION: Duration = Duration::from_secs(10 * 60);
+
+pub fn touches_protected(range: &Range<u16>, num_blocks: u16) -> bool {
+    let fs_start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    range.start < SKSA_BLOCKS || range.end > fs_start
+}
+
+// after one fully-confirmed dangerous operation, further ones only need a y/N for a while;
+// `now` is passed in everywhere so the rules don't depend on the real clock
+#[derive(Default)]
+pub struct DangerLock {
+    unlocked_until: Option<Instant>,
+}
+
+impl DangerLock {
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.unlocked_until
+            .and_then(|until| until.checked_duration_since(now))
+            .filter(|d| !d.is_zero())
+    }
+
+    pub fn unlock(&mut self, now: Instant) {
+        self.unlocked_until = Some(now + UNLOCK_DURATION);
+    }
+
+    pub fn lock(&mut self) -> bool {
+        self.unlocked_until.take().is_some()
+    }
+}
+
+pub fn parse_bbid(s: &str) -> Option<u32> {
+    u32::from_str_radix(s.trim().trim_start_matches("0x"), 16).ok()
+}
+
+// asks for confirmation of a dangerous operation; interactively, that means typing the BBID
+// (or just y/N while unlocked), and non-interactively an explicit '--confirm=<BBID>' argument
+pub fn confirm_dangerous(
+    rl: &mut dyn Prompt,
+    lock: &mut DangerLock,
+    player: &dyn Backend,
+    what: &str,
+    args: &[&str],
+) -> Result<()> {
+    let bbid = player.GetBBID()?;
+    confirm(
+        rl,
+        lock,
+        bbid,
+        what,
+        args,
+        stdin().is_terminal(),
+        Instant::now(),
+    )
+}
+
+fn confirm(
+    rl: &mut dyn Prompt,
+    lock: &mut DangerLock,
+    bbid: u32,
+    what: &str,
+    args: &[&str],
+    interactive: bool,
+    now: Instant,
+) -> Result<()> {
+    if !interactive {
+        let given = args.iter().find_map(|a| a.strip_prefix("--confirm="));
+        return match given.and_then(parse_bbid) {
+            Some(b) if b == bbid => Ok(()),
+            Some(_) => bail!("--confirm doesn't match this console's BBID; not continuing"),
+            None => bail!("{what} needs '--confirm=<BBID>' when not running interactively"),
+        };
+    }
+
+    if lock.remaining(now).is_some() {
+        let answer = rl.readline(&format!("{what}. Continue? [y/N] "))?;
+        return match answer.trim() {
+            "y" | "Y" => Ok(()),
+            _ => Err(anyhow!("Cancelled")),
+        };
+    }
+
+    println!("{what}. This can make the console unbootable.");
+    let answer = rl.readline(&format!(
+        "Type the console's BBID ({bbid:04X}) to continue: "
+    ))?;
+    if parse_bbid(&answer) != Some(bbid) {
+        bail!("BBID doesn't match; cancelled");
+    }
+    lock.unlock(now);
+    println!(
+        "dangerous-ops unlocked for {} minutes; use 'lock' to lock them again",
+        UNLOCK_DURATION.as_secs() / 60
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn protected() {
+        // the SKSA's the first 0x40 blocks, and the FS region the last 0x10
+        assert!(touches_protected(&(0x3F..0x41), 0x1000));
+        assert!(!touches_protected(&(0x40..0xFF0), 0x1000));
+        assert!(touches_protected(&(0xFEF..0xFF1), 0x1000));
+        assert!(!touches_protected(&(0x40..0x7F0), 0x800));
+        assert!(touches_protected(&(0x7F0..0x7F1), 0x800));
+    }
+
+    #[test]
+    fn bbids() {
+        assert_eq!(parse_bbid("1234abcd"), Some(0x1234ABCD));
+        assert_eq!(parse_bbid(" 0x1234ABCD\n"), Some(0x1234ABCD));
+        assert_eq!(parse_bbid("1234abcd5"), None);
+        assert_eq!(parse_bbid("BBID"), None);
+    }
+
+    // the BBID typed in full, then y/N until the unlock runs out or it's locked again;
+    // non-interactively, '--confirm' every time
+    #[test]
+    fn confirming() -> Result<()> {
+        let start = Instant::now();
+        let mut lock = DangerLock::default();
+        let mut answers = ["1234", "y", "12340000", "n", "y", "12340000"]
+            .map(str::to_string)
+            .into_iter()
+            .collect::<VecDeque<_>>();
+        let mut ask = |lock: &mut DangerLock, now| {
+            confirm(
+                &mut answers,
+                lock,
+                0x12340000,
+                "Writing the SKSA",
+                &[],
+                true,
+                now,
+            )
+        };
+
+        assert!(ask(&mut lock, start).is_err());
+        assert!(ask(&mut lock, start).is_err());
+        ask(&mut lock, start)?;
+        assert_eq!(lock.remaining(start), Some(UNLOCK_DURATION));
+        assert!(ask(&mut lock, start + Duration::from_secs(60)).is_err());
+        ask(&mut lock, start + Duration::from_secs(60))?;
+        // after ten minutes, the BBID again
+        ask(&mut lock, start + UNLOCK_DURATION)?;
+        assert!(lock.lock() && !lock.lock());
+
+        let batch = |args: &[&str]| {
+            confirm(
+                &mut VecDeque::new(),
+                &mut DangerLock::default(),
+                0x12340000,
+                "Writing the SKSA",
+                args,
+                false,
+                start,
+            )
+        };
+        batch(&["--confirm=12340000"])?;
+        let refused =
+            [batch(&["--confirm=1234"]), batch(&[])].map(|r| r.err().map(|e| e.to_string()));
+        assert_eq!(
+            refused,
+            [
+                Some("--confirm doesn't match this console's BBID; not continuing".to_string()),
+                Some(
+                    "Writing the SKSA needs '--confirm=<BBID>' when not running interactively"
+                        .to_string()
+                ),
+            ]
+        );
+        Ok(())
+    }
+}
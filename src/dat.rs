@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::fs::metadata;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+
+use crate::cancel::CancelToken;
+use crate::fs::FsBlock;
+use crate::hash_cache::HashCache;
+use crate::hashing::{HashAlgo, HashValue};
+use crate::offline::content_id;
+use crate::player::Player;
+use crate::sink::write_atomic;
+use crate::titles::TitleDb;
+use crate::PROG_NAME;
+
+// 'export dat': the files on a console (or a dump) as a Logiqx-style datafile, the XML that
+// clrmamepro, RomVault and No-Intro's tools read, with each file's size, CRC-32 and SHA-1. Each
+// file is a <game> of its own, named after the file, described by its title where titles.txt has
+// it. The same card always gives the same document, files in name order.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatEntry {
+    pub name: String,
+    pub size: u32,
+    pub crc32: HashValue,
+    pub sha1: HashValue,
+    pub title: Option<String>,
+}
+
+// what the header says the files are from; `date` is when they were read (or the dump was)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatHeader {
+    pub bbid: Option<u32>,
+    pub date: String,
+}
+
+pub fn xml_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out += "&amp;",
+            '<' => out += "&lt;",
+            '>' => out += "&gt;",
+            '"' => out += "&quot;",
+            '\'' => out += "&apos;",
+            // not allowed in XML 1.0 at all, even escaped
+            c if (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r') => out.push('\u{FFFD}'),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn render_dat(header: &DatHeader, entries: &[DatEntry]) -> String {
+    let console = match header.bbid {
+        Some(bbid) => format!("iQue Player {bbid:08X}"),
+        None => "iQue Player (unknown BBID)".to_string(),
+    };
+    let mut out = String::from(
+        "<?xml version=\"1.0\"?>\n\
+         <!DOCTYPE datafile PUBLIC \"-//Logiqx//DTD ROM Management Datafile//EN\" \
+         \"http://www.logiqx.com/Dats/datafile.dtd\">\n\
+         <datafile>\n\t<header>\n",
+    );
+    for (tag, value) in [
+        ("name", console.clone()),
+        (
+            "description",
+            format!("Files on {console}, {}", header.date),
+        ),
+        ("version", header.date.clone()),
+        ("date", header.date.clone()),
+        ("author", PROG_NAME.to_string()),
+    ] {
+        out += &format!("\t\t<{tag}>{}</{tag}>\n", xml_escape(&value));
+    }
+    out += "\t</header>\n";
+
+    let mut sorted = entries.iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+    for entry in sorted {
+        let name = xml_escape(&entry.name);
+        out += &format!("\t<game name=\"{name}\">\n");
+        out += &format!(
+            "\t\t<description>{}</description>\n",
+            xml_escape(entry.title.as_deref().unwrap_or(&entry.name))
+        );
+        out += &format!(
+            "\t\t<rom name=\"{name}\" size=\"{}\" crc=\"{}\" sha1=\"{}\"/>\n",
+            entry.size,
+            entry.crc32.hex(),
+            entry.sha1.hex()
+        );
+        out += "\t</game>\n";
+    }
+    out += "</datafile>\n";
+    out
+}
+
+// every file in `fs`, the player's current FS, hashed; a console's hashes come from the hash cache
+// where they can, but a dump without tickets has no BBID to key them by, so it's read through
+pub fn collect(
+    player: &dyn Player,
+    fs: &FsBlock,
+    titles: &TitleDb,
+    cancel: &CancelToken,
+) -> Result<(Option<u32>, Vec<DatEntry>)> {
+    let bbid = player.GetBBID().ok();
+    let names = fs
+        .entries
+        .iter()
+        .map(|e| e.name.as_str())
+        .collect::<Vec<_>>();
+    let hashes = match bbid {
+        Some(_) => {
+            let mut cache = HashCache::load();
+            let crc32 = cache.hashes(player, fs, &names, HashAlgo::Crc32, cancel)?;
+            let sha1 = cache.hashes(player, fs, &names, HashAlgo::Sha1, cancel)?;
+            if let Err(e) = cache.save() {
+                eprintln!("Couldn't save the hash cache: {e}");
+            }
+            names
+                .iter()
+                .map(|&n| (n.to_string(), (crc32[n].clone(), sha1[n].clone())))
+                .collect::<BTreeMap<_, _>>()
+        }
+        None => {
+            let mut hashes = BTreeMap::new();
+            for &name in &names {
+                cancel.check()?;
+                let data = player
+                    .ReadFile(name)?
+                    .ok_or_else(|| anyhow!("{name} disappeared while it was being read"))?;
+                let crc32 = HashAlgo::Crc32.digest(&data);
+                hashes.insert(name.to_string(), (crc32, HashAlgo::Sha1.digest(&data)));
+            }
+            hashes
+        }
+    };
+    let entries = fs
+        .entries
+        .iter()
+        .map(|e| {
+            let (crc32, sha1) = hashes[&e.name].clone();
+            DatEntry {
+                name: e.name.clone(),
+                size: e.size,
+                crc32,
+                sha1,
+                title: content_id(&e.name)
+                    .and_then(|(cid, _)| titles.get(cid))
+                    .map(str::to_string),
+            }
+        })
+        .collect();
+    Ok((bbid, entries))
+}
+
+// the date a dump was saved, or today's for a console (or a dump whose date can't be read)
+pub fn dump_date(path: Option<&str>) -> String {
+    let saved = path.and_then(|p| metadata(p).and_then(|m| m.modified()).ok());
+    saved
+        .map_or_else(Local::now, DateTime::<Local>::from)
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+// writes the datafile for the files in `fs`, returning how many there were
+pub fn export(
+    player: &dyn Player,
+    fs: &FsBlock,
+    date: String,
+    out: &str,
+    cancel: &CancelToken,
+) -> Result<usize> {
+    let (bbid, entries) = collect(player, fs, &TitleDb::load(), cancel)?;
+    write_atomic(
+        out,
+        render_dat(&DatHeader { bbid, date }, &entries).as_bytes(),
+    )?;
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dat_export() -> Result<()> {
+        use anyhow::bail;
+
+        let entry = |name: &str, data: &[u8], title: Option<&str>| DatEntry {
+            name: name.to_string(),
+            size: data.len() as u32,
+            crc32: HashAlgo::Crc32.digest(data),
+            sha1: HashAlgo::Sha1.digest(data),
+            title: title.map(str::to_string),
+        };
+
+        for (text, expected) in [
+            ("plain.app", "plain.app"),
+            ("a&b<c>", "a&amp;b&lt;c&gt;"),
+            ("say \"it's\"", "say &quot;it&apos;s&quot;"),
+            ("bell\u{7}", "bell\u{FFFD}"),
+            ("\u{5C0F}\u{9E21}", "\u{5C0F}\u{9E21}"),
+        ] {
+            if xml_escape(text) != expected {
+                bail!("'{text}' was escaped as '{}'", xml_escape(text));
+            }
+        }
+
+        // the fixture: files out of order, one with a title, one whose name needs escaping
+        let header = DatHeader {
+            bbid: Some(0x1234ABCD),
+            date: "2024-05-01".to_string(),
+        };
+        let entries = vec![
+            entry("ticket.sys", b"tickets", None),
+            entry("0012d687.app", b"game", Some("Dr. Mario & Co")),
+            entry("A<B>.rec", b"", None),
+        ];
+        let expected = "\
+    <?xml version=\"1.0\"?>
+<!DOCTYPE datafile PUBLIC \"-//Logiqx//DTD ROM Management Datafile//EN\" \"http://www.logiqx.com/Dats/datafile.dtd\">
+<datafile>
+\t<header>
+\t\t<name>iQue Player 1234ABCD</name>
+\t\t<description>Files on iQue Player 1234ABCD, 2024-05-01</description>
+\t\t<version>2024-05-01</version>
+\t\t<date>2024-05-01</date>
+\t\t<author>aulon2</author>
+\t</header>
+\t<game name=\"0012d687.app\">
+\t\t<description>Dr. Mario &amp; Co</description>
+\t\t<rom name=\"0012d687.app\" size=\"4\" crc=\"232b318c\" sha1=\"cda051c901386f0e24914b0eeb92ef4e380c159d\"/>
+\t</game>
+\t<game name=\"A&lt;B&gt;.rec\">
+\t\t<description>A&lt;B&gt;.rec</description>
+\t\t<rom name=\"A&lt;B&gt;.rec\" size=\"0\" crc=\"00000000\" sha1=\"da39a3ee5e6b4b0d3255bfef95601890afd80709\"/>
+\t</game>
+\t<game name=\"ticket.sys\">
+\t\t<description>ticket.sys</description>
+\t\t<rom name=\"ticket.sys\" size=\"7\" crc=\"54469df4\" sha1=\"d370d9cd5625a534b858b567de7a324d04573eaf\"/>
+\t</game>
+</datafile>
+";
+        let dat = render_dat(&header, &entries);
+        if dat != expected {
+            bail!("the datafile was\n{dat}");
+        }
+
+        // the same files in any order give the same document
+        let mut reversed = entries.clone();
+        reversed.reverse();
+        if render_dat(&header, &reversed) != dat {
+            bail!("the datafile depended on the order of the files");
+        }
+        let unknown = DatHeader {
+            bbid: None,
+            ..header
+        };
+        if !render_dat(&unknown, &[]).contains("<name>iQue Player (unknown BBID)</name>") {
+            bail!(
+                "a datafile without a BBID was\n{}",
+                render_dat(&unknown, &[])
+            );
+        }
+        Ok(())
+    }
+}
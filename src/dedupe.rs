@@ -0,0 +1,268 @@
+use std::collections::HashSet;
+use std::fs::{create_dir_all, read, read_dir, read_to_string, remove_file};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::fs::BLOCK_SIZE;
+use crate::hashing::{parse_sum_line, HashValue};
+use crate::provenance::manifest_path;
+use crate::sink::write_atomic;
+
+// A deduplicated archive is a directory holding:
+//
+//   blocks/<sha256>      one copy of each distinct block, named after the SHA-256 of its contents
+//   <dump>.dedupe        a JSON index for each original dump, listing its blocks' hashes in order
+//
+// Only dumps are deduplicated: files a whole number of blocks long that a manifest of their own
+// ('1 --manifest' writes <dump>.sha256) lists, with every digest there matching. Anything else,
+// spare files and unrelated files included, is left as it is. A damaged block is detected when
+// its contents no longer hash to its name, and a damaged or incomplete index by the whole-file
+// hash.
+
+const STORE_DIR: &str = "blocks";
+const INDEX_EXT: &str = "dedupe";
+const INDEX_SCHEMA: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct DedupeIndex {
+    pub schema: u32,
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    pub blocks: Vec<String>,
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn store_dir(archive: &Path) -> PathBuf {
+    archive.join(STORE_DIR)
+}
+
+// adds one dump's blocks to the store, returning its index; nothing is removed here
+fn dedupe_file(
+    store: &Path,
+    name: &str,
+    data: &[u8],
+    known: &mut HashSet<String>,
+) -> Result<(DedupeIndex, usize)> {
+    let mut added = 0;
+    let blocks = data
+        .chunks(BLOCK_SIZE)
+        .map(|block| {
+            let hash = sha256(block);
+            if known.insert(hash.clone()) {
+                write_atomic(store.join(&hash), block)?;
+                added += 1;
+            }
+            Ok(hash)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((
+        DedupeIndex {
+            schema: INDEX_SCHEMA,
+            name: name.to_string(),
+            size: data.len() as u64,
+            sha256: sha256(data),
+            blocks,
+        },
+        added,
+    ))
+}
+
+// rebuilds a dump from its index, checking every block and the result against their hashes
+fn rehydrate_index(store: &Path, index: &DedupeIndex) -> Result<Vec<u8>> {
+    if index.schema != INDEX_SCHEMA {
+        bail!("unsupported index version {}", index.schema);
+    }
+    let mut data = Vec::with_capacity(index.size as usize);
+    for (i, hash) in index.blocks.iter().enumerate() {
+        let block = read(store.join(hash)).map_err(|e| anyhow!("block {i:#X} ({hash}): {e}"))?;
+        if sha256(&block) != *hash {
+            bail!("block {i:#X} ({hash}) is corrupt in the store");
+        }
+        data.extend_from_slice(&block);
+    }
+    if data.len() as u64 != index.size || sha256(&data) != index.sha256 {
+        bail!(
+            "{} didn't rebuild to the original (wrong size or hash)",
+            index.name
+        );
+    }
+    Ok(data)
+}
+
+// the digests [name]'s own manifest in [archive] lists for it; none if there's no manifest, or it
+// doesn't list [name]
+fn listed_digests(archive: &Path, name: &str) -> Result<Vec<HashValue>> {
+    let path = archive.join(manifest_path(name));
+    if !path.is_file() {
+        return Ok(vec![]);
+    }
+    let mut digests = vec![];
+    for line in read_to_string(&path)?.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (hash, listed) =
+            parse_sum_line(line).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        if listed == name {
+            digests.push(hash);
+        }
+    }
+    Ok(digests)
+}
+
+pub fn dedupe_archive(dir: &str) -> Result<()> {
+    let archive = Path::new(dir);
+    let store = store_dir(archive);
+    create_dir_all(&store)?;
+
+    let mut known = read_dir(&store)?
+        .filter_map(|e| e.ok()?.file_name().into_string().ok())
+        .collect::<HashSet<_>>();
+    let (mut files, mut saved) = (0, 0u64);
+
+    let mut entries = read_dir(archive)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .map(|e| e.path())
+        .collect::<Vec<_>>();
+    entries.sort();
+    for path in entries {
+        let Some(name) = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        if path.extension().is_some_and(|e| e == INDEX_EXT) {
+            continue;
+        }
+        let digests = listed_digests(archive, &name)?;
+        if digests.is_empty() {
+            continue;
+        }
+        let data = read(&path)?;
+        if data.is_empty() || !data.len().is_multiple_of(BLOCK_SIZE) {
+            continue;
+        }
+        if digests.iter().any(|h| h.algo.digest(&data) != *h) {
+            println!("{name}: doesn't match its manifest; left as it is");
+            continue;
+        }
+
+        let (index, added) = dedupe_file(&store, &name, &data, &mut known)?;
+        // the original only goes once the index is known to rebuild it exactly
+        rehydrate_index(&store, &index)
+            .map_err(|e| anyhow!("{name}: {e}; keeping the original"))?;
+        let index_path = archive.join(format!("{name}.{INDEX_EXT}"));
+        write_atomic(&index_path, &serde_json::to_vec_pretty(&index)?)?;
+        remove_file(&path)?;
+
+        println!(
+            "{name}: {} blocks, {added} new to the store",
+            index.blocks.len()
+        );
+        files += 1;
+        saved += ((index.blocks.len() - added) * BLOCK_SIZE) as u64;
+    }
+    println!(
+        "Deduplicated {files} dumps; {saved:#X} bytes weren't stored again ({} blocks in the store)",
+        known.len()
+    );
+    Ok(())
+}
+
+pub fn rehydrate(index_path: &str, out: &str) -> Result<()> {
+    let index: DedupeIndex = serde_json::from_str(&read_to_string(index_path)?)
+        .map_err(|e| anyhow!("{index_path}: {e}"))?;
+    let archive = Path::new(index_path).parent().unwrap_or(Path::new("."));
+    let data = rehydrate_index(&store_dir(archive), &index)?;
+    write_atomic(out, &data)?;
+    println!("Rebuilt {} ({:#X} bytes) to {out}", index.name, data.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::HashAlgo;
+    use crate::provenance::dump_manifest;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let dir = TestDir::new("dedupe")?;
+        let block = |b: u8| vec![b; BLOCK_SIZE];
+        let first = [block(1), block(2), block(1), block(3)].concat();
+        let second = [block(2), block(3), block(4)].concat();
+        let spare = block(0xFF);
+        let other = block(5);
+        let tampered = block(6);
+
+        let write = |name: &str, data: &[u8]| write_atomic(dir.join(name), data);
+        write("nand.bin", &first)?;
+        write("spare.bin", &spare)?;
+        write(
+            &manifest_path("nand.bin"),
+            dump_manifest(
+                Some(0x1234),
+                None,
+                &[("nand.bin", &first), ("spare.bin", &spare)],
+                &[HashAlgo::Sha256, HashAlgo::Md5],
+            )
+            .as_bytes(),
+        )?;
+        write("second.bin", &second)?;
+        write(
+            &manifest_path("second.bin"),
+            dump_manifest(None, None, &[("second.bin", &second)], &[HashAlgo::Sha256]).as_bytes(),
+        )?;
+        write("other.bin", &other)?;
+        write("tampered.bin", &tampered)?;
+        write(
+            &manifest_path("tampered.bin"),
+            dump_manifest(None, None, &[("tampered.bin", &other)], &[HashAlgo::Sha256]).as_bytes(),
+        )?;
+
+        dedupe_archive(&dir.to_string_lossy())?;
+
+        // only the dumps with matching manifests went
+        for (name, data) in [
+            ("spare.bin", &spare),
+            ("other.bin", &other),
+            ("tampered.bin", &tampered),
+        ] {
+            assert_eq!(read(dir.join(name))?, *data, "{name} was changed");
+            assert!(
+                !dir.join(format!("{name}.{INDEX_EXT}")).exists(),
+                "{name} was indexed"
+            );
+        }
+        assert!(!dir.join("nand.bin").exists() && !dir.join("second.bin").exists());
+        assert_eq!(read_dir(store_dir(&dir))?.count(), 4);
+
+        for (name, data) in [("nand.bin", &first), ("second.bin", &second)] {
+            let out = dir.join(format!("{name}.out"));
+            rehydrate(
+                &dir.join(format!("{name}.{INDEX_EXT}")).to_string_lossy(),
+                &out.to_string_lossy(),
+            )?;
+            assert_eq!(read(&out)?, *data, "{name} didn't rebuild");
+        }
+
+        // a damaged block is caught rather than rebuilt
+        let index: DedupeIndex =
+            serde_json::from_str(&read_to_string(dir.join("nand.bin.dedupe"))?)?;
+        write_atomic(store_dir(&dir).join(&index.blocks[1]), &block(7))?;
+        assert!(rehydrate_index(&store_dir(&dir), &index).is_err());
+        Ok(())
+    }
+}
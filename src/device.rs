@@ -0,0 +1,636 @@
+use std::fmt::{self, Display};
+
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use bbrdb::{scan_devices, GlobalHandle};
+use rusb::{Device, DeviceDescriptor, DeviceHandle, GlobalContext, Speed};
+
+use crate::instance_lock::device_key;
+use crate::player::Backend;
+
+type ReadString = fn(&DeviceHandle<GlobalContext>, &DeviceDescriptor) -> rusb::Result<String>;
+
+// where the selected console was when it was selected; the address changes whenever a device
+// is reconnected, so a device at the same bus and address is the same one
+pub struct DeviceLocation {
+    pub index: usize,
+    bus: u8,
+    address: u8,
+    // the port path, which (unlike the address) survives a USB reset
+    ports: Vec<u8>,
+}
+
+impl DeviceLocation {
+    pub fn new(index: usize, device: &Device<GlobalContext>) -> Self {
+        Self {
+            index,
+            bus: device.bus_number(),
+            address: device.address(),
+            ports: device.port_numbers().unwrap_or_default(),
+        }
+    }
+
+    // whether `device` is the one that was selected
+    pub fn matches(&self, device: &Device<GlobalContext>) -> bool {
+        device.bus_number() == self.bus && device.address() == self.address
+    }
+
+    pub fn bus_address(&self) -> (u8, u8) {
+        (self.bus, self.address)
+    }
+
+    // the selected device as it is now, or None if it's gone
+    pub fn find(&self) -> Result<Option<Device<GlobalContext>>> {
+        Ok(scan_devices()?.into_iter().find(|d| self.matches(d)))
+    }
+
+    // the device after a reset, which can re-enumerate it at a new address on the same port
+    fn find_after_reset(&self) -> Result<Option<Device<GlobalContext>>> {
+        if let Some(d) = self.find()? {
+            return Ok(Some(d));
+        }
+        if self.ports.is_empty() {
+            return Ok(None);
+        }
+        Ok(scan_devices()?.into_iter().find(|d| {
+            d.bus_number() == self.bus && d.port_numbers().is_ok_and(|p| p == self.ports)
+        }))
+    }
+
+    // the selected device's USB serial number, if it's still there and has one
+    pub fn serial(&self) -> Option<String> {
+        DeviceInfo::query(&self.find().ok()??).ok()?.serial
+    }
+
+    // what its lock is named after: its serial number, or without one its port
+    pub fn lock_key(&self) -> String {
+        let port = if self.ports.is_empty() {
+            format!("{}-a{}", self.bus, self.address)
+        } else {
+            let ports = self.ports.iter().map(u8::to_string).collect::<Vec<_>>();
+            format!("{}-{}", self.bus, ports.join("."))
+        };
+        device_key(self.serial().as_deref(), &port)
+    }
+}
+
+// how long a console takes to come back after a reset
+const RESET_SETTLE: Duration = Duration::from_secs(1);
+
+// what libusb says (through rusb, or in bbrdb's own messages, which carry it as text) when the
+// console enumerated but doesn't answer on its endpoints
+const UNANSWERED: [&str; 5] = [
+    "timed out",
+    "timeout",
+    "pipe error",
+    "input/output error",
+    "i/o error",
+];
+
+// whether an Init failure looks like the ones a USB reset fixes: the console enumerated, but
+// doesn't answer on its endpoints. Anything in the error's chain can say so, whether it's a
+// rusb::Error or only a message
+pub fn reset_may_help(e: &anyhow::Error) -> bool {
+    e.chain().any(|c| {
+        if let Some(e) = c.downcast_ref::<rusb::Error>() {
+            return matches!(
+                e,
+                rusb::Error::Timeout | rusb::Error::Pipe | rusb::Error::Io
+            );
+        }
+        let message = c.to_string().to_lowercase();
+        UNANSWERED.iter().any(|m| message.contains(m))
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Initialised {
+    First,
+    AfterReset,
+}
+
+// what 'B' needs of a console to initialise it, resetting its USB port if that may help
+pub trait Resettable {
+    fn init(&mut self) -> Result<()>;
+    // closes the console, resets its USB port and opens it again, uninitialised
+    fn reset(&mut self) -> Result<()>;
+}
+
+// Init; if it fails, `on_failure` is given the error and whether a USB reset may help, and says
+// whether to reset the console and try once more. None if it failed and wasn't tried again
+pub fn init_with_reset<R: Resettable + ?Sized>(
+    console: &mut R,
+    on_failure: impl FnOnce(&anyhow::Error, bool) -> bool,
+) -> Result<Option<Initialised>> {
+    let e = match console.init() {
+        Ok(()) => return Ok(Some(Initialised::First)),
+        Err(e) => e,
+    };
+    if !on_failure(&e, reset_may_help(&e)) {
+        return Ok(None);
+    }
+    console.reset()?;
+    console.init()?;
+    Ok(Some(Initialised::AfterReset))
+}
+
+// the selected console, reset through where it was found
+pub struct SelectedConsole<'a> {
+    pub handle: &'a mut Box<dyn Backend>,
+    pub location: &'a mut Option<DeviceLocation>,
+}
+
+impl Resettable for SelectedConsole<'_> {
+    fn init(&mut self) -> Result<()> {
+        self.handle.Init()
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        let selected = self.location.as_ref().ok_or_else(|| {
+            anyhow!("Where the console was found isn't known, so it can't be reset")
+        })?;
+        let (handle, location) = reset_device(self.handle, selected)?;
+        *self.handle = Box::new(handle);
+        *self.location = Some(location);
+        Ok(())
+    }
+}
+
+// closes the handle, resets the console's USB port, and opens it again; the returned handle
+// hasn't been initialised
+pub fn reset_device(
+    player: &mut dyn Backend,
+    selected: &DeviceLocation,
+) -> Result<(GlobalHandle, DeviceLocation)> {
+    let _ = player.Close();
+    let device = selected.find()?.ok_or_else(|| {
+        anyhow!(
+            "Player {} has been disconnected since it was selected",
+            selected.index
+        )
+    })?;
+    // the device can disappear mid-reset as it re-enumerates, which libusb reports as NotFound
+    match device.open()?.reset() {
+        Ok(_) | Err(rusb::Error::NotFound) => {}
+        Err(e) => return Err(anyhow!("Couldn't reset the USB port: {e}")),
+    }
+    sleep(RESET_SETTLE);
+    let device = selected.find_after_reset()?.ok_or_else(|| {
+        anyhow!("The console didn't come back after the reset; use 'l' to rescan, then 's' to select it")
+    })?;
+    let handle = GlobalHandle::new(&device)?;
+    Ok((handle, DeviceLocation::new(selected.index, &device)))
+}
+
+// everything that can be found out about a device without talking to the console itself
+pub struct DeviceInfo {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub usb_version: String,
+    pub device_version: String,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial: Option<String>,
+    pub bus: u8,
+    pub address: u8,
+    pub ports: Vec<u8>,
+    pub speed: Speed,
+    // whether a kernel driver has claimed the console's interface, if that can be checked
+    pub kernel_driver: Option<bool>,
+}
+
+impl DeviceInfo {
+    pub fn query(device: &Device<GlobalContext>) -> Result<Self> {
+        let desc = device.device_descriptor()?;
+        // the strings need the device to be opened, but that doesn't send the console anything
+        let handle = device.open().ok();
+        let string = |index: Option<u8>, read: ReadString| {
+            index?;
+            read(handle.as_ref()?, &desc).ok()
+        };
+        Ok(Self {
+            vendor_id: desc.vendor_id(),
+            product_id: desc.product_id(),
+            usb_version: desc.usb_version().to_string(),
+            device_version: desc.device_version().to_string(),
+            manufacturer: string(
+                desc.manufacturer_string_index(),
+                DeviceHandle::read_manufacturer_string_ascii,
+            ),
+            product: string(
+                desc.product_string_index(),
+                DeviceHandle::read_product_string_ascii,
+            ),
+            serial: string(
+                desc.serial_number_string_index(),
+                DeviceHandle::read_serial_number_string_ascii,
+            ),
+            bus: device.bus_number(),
+            address: device.address(),
+            ports: device.port_numbers().unwrap_or_default(),
+            speed: device.speed(),
+            kernel_driver: handle.as_ref().and_then(|h| h.kernel_driver_active(0).ok()),
+        })
+    }
+}
+
+impl Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_unknown = |s: &Option<String>| s.clone().unwrap_or_else(|| "unknown".to_string());
+        writeln!(
+            f,
+            "USB ID:        {:04X}:{:04X}",
+            self.vendor_id, self.product_id
+        )?;
+        writeln!(f, "Manufacturer:  {}", or_unknown(&self.manufacturer))?;
+        writeln!(f, "Product:       {}", or_unknown(&self.product))?;
+        writeln!(f, "Serial:        {}", or_unknown(&self.serial))?;
+        writeln!(
+            f,
+            "USB version:   {} (device version {})",
+            self.usb_version, self.device_version
+        )?;
+        writeln!(
+            f,
+            "Location:      bus {} address {}, port path {}",
+            self.bus,
+            self.address,
+            if self.ports.is_empty() {
+                "unknown".to_string()
+            } else {
+                self.ports
+                    .iter()
+                    .map(u8::to_string)
+                    .collect::<Vec<_>>()
+                    .join(".")
+            }
+        )?;
+        writeln!(f, "Speed:         {:?}", self.speed)?;
+        write!(
+            f,
+            "Kernel driver: {}",
+            match self.kernel_driver {
+                Some(true) => "attached",
+                Some(false) => "none",
+                None => "unknown",
+            }
+        )
+    }
+}
+
+// a device as 'l' listed it: where it's plugged in, and what it said it was
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listed {
+    pub bus: u8,
+    pub ports: Vec<u8>,
+    pub address: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial: Option<String>,
+}
+
+impl Listed {
+    pub fn of(device: &Device<GlobalContext>) -> Self {
+        let desc = device.device_descriptor().ok();
+        Self {
+            bus: device.bus_number(),
+            ports: device.port_numbers().unwrap_or_default(),
+            address: device.address(),
+            vendor_id: desc.as_ref().map_or(0, |d| d.vendor_id()),
+            product_id: desc.as_ref().map_or(0, |d| d.product_id()),
+            serial: DeviceInfo::query(device).ok().and_then(|i| i.serial),
+        }
+    }
+
+    // where it is, as bus-port.port; by address where the ports can't be told
+    pub fn path(&self) -> String {
+        match self.ports.is_empty() {
+            true => format!("{}-a{}", self.bus, self.address),
+            false => {
+                let ports = self.ports.iter().map(u8::to_string).collect::<Vec<_>>();
+                format!("{}-{}", self.bus, ports.join("."))
+            }
+        }
+    }
+
+    // what devices are ordered by
+    pub fn key(&self) -> (u8, &[u8], u8) {
+        (self.bus, &self.ports, self.address)
+    }
+}
+
+// devices in the order of where they're plugged in, which stays the same from one scan to the
+// next, rather than the order they were found in, which needn't
+pub fn sort_by_path<T>(devices: &mut [(Listed, T)]) {
+    devices.sort_by(|(a, _), (b, _)| a.key().cmp(&b.key()));
+}
+
+// the connected devices, in that order, which is the order they're numbered in everywhere
+pub fn scan_sorted() -> Result<Vec<Device<GlobalContext>>> {
+    let mut devices = scan_devices()?;
+    devices.sort_by_cached_key(|d| {
+        (
+            d.bus_number(),
+            d.port_numbers().unwrap_or_default(),
+            d.address(),
+        )
+    });
+    Ok(devices)
+}
+
+// the same, with what 'l' lists about each
+pub fn scan_listed() -> Result<Vec<(Listed, Device<GlobalContext>)>> {
+    let mut devices = scan_sorted()?
+        .into_iter()
+        .map(|d| (Listed::of(&d), d))
+        .collect::<Vec<_>>();
+    sort_by_path(&mut devices);
+    Ok(devices)
+}
+
+// a device number as typed: only digits, so '-1', '+1' and '1.0' aren't taken for anything
+pub fn parse_index(arg: &str) -> Result<usize> {
+    if arg.is_empty() || !arg.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(anyhow!(
+            "'{arg}' isn't a device number; 'l' lists the devices with theirs"
+        ));
+    }
+    arg.parse()
+        .map_err(|_| anyhow!("There's no device {arg}; 'l' lists the devices with their numbers"))
+}
+
+// the devices as 'l' last listed them, so 's' selects the one that was looked at even if others
+// have come or gone since
+#[derive(Debug, Clone, Default)]
+pub struct DeviceList {
+    pub devices: Vec<Listed>,
+}
+
+impl DeviceList {
+    // where the device listed as `index` is in `now`, a fresh scan, as long as what's at its place
+    // is still the device that was listed there
+    pub fn pick(&self, index: usize, now: &[Listed]) -> Result<usize> {
+        let Some(listed) = self.devices.get(index) else {
+            return Err(match self.devices.len() {
+                0 => anyhow!("There's no device {index}; 'l' didn't find any"),
+                n => anyhow!(
+                    "There's no device {index}; 'l' listed {n}, numbered 0 to {}",
+                    n - 1
+                ),
+            });
+        };
+        let changed =
+            |what: String| anyhow!("The device list has changed since 'l' ({what}); run 'l' again");
+        let there = now.iter().position(|d| {
+            d.bus == listed.bus
+                && match listed.ports.is_empty() {
+                    true => d.address == listed.address,
+                    false => d.ports == listed.ports,
+                }
+        });
+        match there {
+            None => Err(changed(format!(
+                "device {index}, at {}, has gone",
+                listed.path()
+            ))),
+            // a device reconnected at the same place comes back at a new address
+            Some(i) if now[i] != *listed => Err(changed(format!(
+                "the device at {} isn't the one that was listed as {index}",
+                listed.path()
+            ))),
+            Some(i) => Ok(i),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_list() -> Result<()> {
+        use anyhow::bail;
+
+        let device = |bus: u8, ports: &[u8], address: u8, serial: Option<&str>| Listed {
+            bus,
+            ports: ports.to_vec(),
+            address,
+            vendor_id: 0x1527,
+            product_id: 0xBBDB,
+            serial: serial.map(str::to_string),
+        };
+        let a = device(1, &[2], 5, Some("BB001"));
+        let b = device(1, &[3, 1], 9, None);
+        let c = device(2, &[1], 4, Some("BB003"));
+
+        // the order is by place, whatever order they were found in
+        let mut found = vec![(c.clone(), 'c'), (b.clone(), 'b'), (a.clone(), 'a')];
+        sort_by_path(&mut found);
+        if found.iter().map(|(_, t)| *t).collect::<String>() != "abc" {
+            bail!("the devices were sorted as {found:?}");
+        }
+        if (a.path(), b.path(), device(3, &[], 7, None).path())
+            != ("1-2".to_string(), "1-3.1".to_string(), "3-a7".to_string())
+        {
+            bail!("the devices' paths were {} and {}", a.path(), b.path());
+        }
+
+        // a scan that's the same picks the device at the same place
+        let list = DeviceList {
+            devices: vec![a.clone(), b.clone(), c.clone()],
+        };
+        if list.pick(1, &list.devices)? != 1 {
+            bail!("an unchanged scan picked the wrong device");
+        }
+        // another device plugged in ahead of it moves it along, but it's still the one picked
+        let d = device(1, &[1], 12, None);
+        let now = vec![d.clone(), a.clone(), b.clone(), c.clone()];
+        if list.pick(1, &now)? != 2 || list.pick(2, &now)? != 3 {
+            bail!("a device that moved along in the scan wasn't followed");
+        }
+        // and one unplugged ahead of it doesn't make another be picked in its place
+        if list.pick(2, &[b.clone(), c.clone()])? != 1 {
+            bail!("a device that moved back in the scan wasn't followed");
+        }
+
+        let changed = |result: Result<usize>, what: &str| -> Result<()> {
+            match result {
+                Err(e)
+                    if e.to_string()
+                        .starts_with("The device list has changed since 'l'")
+                        && e.to_string().contains(what) =>
+                {
+                    Ok(())
+                }
+                other => bail!("a changed device list gave {other:?}, not one saying '{what}'"),
+            }
+        };
+        // gone, or replaced by another device (or the same one reconnected) at its place
+        changed(list.pick(0, &[b.clone(), c.clone()]), "at 1-2, has gone")?;
+        let replugged = device(1, &[2], 6, Some("BB001"));
+        changed(list.pick(0, &[replugged, b.clone()]), "isn't the one")?;
+        let other = device(1, &[2], 5, Some("BB002"));
+        changed(list.pick(0, &[other, b.clone()]), "isn't the one")?;
+
+        // numbers past the end of the list, and ones that aren't numbers
+        if list.pick(3, &list.devices).is_ok() || DeviceList::default().pick(0, &[]).is_ok() {
+            bail!("a device past the end of the list was picked");
+        }
+        for arg in [
+            "",
+            "-1",
+            "+1",
+            "1.0",
+            " 1",
+            "one",
+            "99999999999999999999999",
+        ] {
+            if parse_index(arg).is_ok() {
+                bail!("'{arg}' was taken as a device number");
+            }
+        }
+        if parse_index("0")? != 0 || parse_index("12")? != 12 {
+            bail!("a device number wasn't read");
+        }
+        Ok(())
+    }
+
+    // a console whose Init fails with each of `failures` in turn, then succeeds, for as long as
+    // it isn't reset; a reset makes it answer
+    struct Mock {
+        failures: Vec<anyhow::Error>,
+        calls: Vec<&'static str>,
+        reset_fails: bool,
+        reset: bool,
+    }
+
+    impl Mock {
+        fn new(failures: Vec<anyhow::Error>) -> Self {
+            Self {
+                failures,
+                calls: vec![],
+                reset_fails: false,
+                reset: false,
+            }
+        }
+    }
+
+    impl Resettable for Mock {
+        fn init(&mut self) -> Result<()> {
+            self.calls.push("init");
+            match self.reset || self.failures.is_empty() {
+                true => Ok(()),
+                false => Err(self.failures.remove(0)),
+            }
+        }
+
+        fn reset(&mut self) -> Result<()> {
+            self.calls.push("reset");
+            if self.reset_fails {
+                anyhow::bail!("Couldn't reset the USB port: Access denied");
+            }
+            self.reset = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn init_resets() -> Result<()> {
+        // what a reset helps with, however it's reported
+        for (e, helps) in [
+            (anyhow::Error::new(rusb::Error::Timeout), true),
+            (
+                anyhow::Error::new(rusb::Error::Pipe).context("Init failed"),
+                true,
+            ),
+            (anyhow::Error::new(rusb::Error::Access), false),
+            (anyhow!("libusb error: Operation timed out"), true),
+            (
+                anyhow!("Input/Output Error").context("reading the BBID"),
+                true,
+            ),
+            (
+                anyhow!("No such device (it may have been disconnected)"),
+                false,
+            ),
+            (anyhow!("bad response from the console"), false),
+        ] {
+            assert_eq!(reset_may_help(&e), helps, "{e:#}");
+        }
+
+        // fails, is reset, then succeeds
+        let mut console = Mock::new(vec![anyhow!("Operation timed out")]);
+        let mut told = None;
+        let result = init_with_reset(&mut console, |e, may_help| {
+            told = Some((e.to_string(), may_help));
+            true
+        })?;
+        assert_eq!(result, Some(Initialised::AfterReset));
+        assert_eq!(console.calls, ["init", "reset", "init"]);
+        assert_eq!(told, Some(("Operation timed out".to_string(), true)));
+
+        // succeeds first time, without being asked about
+        let mut console = Mock::new(vec![]);
+        assert_eq!(
+            init_with_reset(&mut console, |_, _| panic!("asked about a reset"))?,
+            Some(Initialised::First)
+        );
+        assert_eq!(console.calls, ["init"]);
+
+        // declined, or not one a reset helps
+        let mut console = Mock::new(vec![anyhow!("Pipe error")]);
+        assert_eq!(init_with_reset(&mut console, |_, _| false)?, None);
+        assert_eq!(console.calls, ["init"]);
+        let mut console = Mock::new(vec![anyhow!("bad response")]);
+        assert_eq!(init_with_reset(&mut console, |_, may_help| may_help)?, None);
+        assert_eq!(console.calls, ["init"]);
+
+        // the reset failing is the error, with no second Init
+        let mut console = Mock::new(vec![anyhow!("Operation timed out")]);
+        console.reset_fails = true;
+        let e = init_with_reset(&mut console, |_, _| true).unwrap_err();
+        assert!(e.to_string().starts_with("Couldn't reset"), "{e}");
+        assert_eq!(console.calls, ["init", "reset"]);
+        Ok(())
+    }
+
+    #[test]
+    fn details() {
+        let mut info = DeviceInfo {
+            vendor_id: 0x1527,
+            product_id: 0xBBDB,
+            usb_version: "2.0.0".to_string(),
+            device_version: "1.0.0".to_string(),
+            manufacturer: Some("iQue".to_string()),
+            product: None,
+            serial: Some("0123".to_string()),
+            bus: 1,
+            address: 7,
+            ports: vec![2, 4],
+            speed: Speed::High,
+            kernel_driver: Some(false),
+        };
+        assert_eq!(
+            info.to_string(),
+            "USB ID:        1527:BBDB
+Manufacturer:  iQue
+Product:       unknown
+Serial:        0123
+USB version:   2.0.0 (device version 1.0.0)
+Location:      bus 1 address 7, port path 2.4
+Speed:         High
+Kernel driver: none"
+        );
+
+        // what couldn't be found out is said to be unknown
+        info.ports.clear();
+        info.kernel_driver = None;
+        let text = info.to_string();
+        assert!(text.contains("port path unknown\n"), "{text}");
+        assert!(text.ends_with("Kernel driver: unknown"), "{text}");
+        info.kernel_driver = Some(true);
+        assert!(info.to_string().ends_with("Kernel driver: attached"));
+    }
+}
@@ -0,0 +1,566 @@
+use std::fs::{read, read_to_string, remove_file, write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+
+use crate::cancel::CancelToken;
+use crate::fs::{FsBlock, FsEntry, BLOCK_SIZE, SPARE_SIZE};
+use crate::player::Player;
+use crate::progress::Progress;
+use crate::throughput::TransferTimer;
+
+// what a '<name>.partial.info' sidecar records about an interrupted download
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialInfo {
+    // the file's size on the console, so a partial of a since-replaced file isn't resumed
+    pub size: u32,
+    // how many bytes at the start of '<name>.partial' are good
+    pub valid: usize,
+    pub failed_block: u16,
+}
+
+impl PartialInfo {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut size = None;
+        let mut valid = None;
+        let mut failed_block = None;
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("malformed line '{line}'"))?;
+            let value = value.trim();
+            match key.trim() {
+                "size" => size = Some(value.parse()?),
+                "valid" => valid = Some(value.parse()?),
+                "failed_block" => {
+                    failed_block = Some(u16::from_str_radix(value.trim_start_matches("0x"), 16)?)
+                }
+                _ => {}
+            }
+        }
+        match (size, valid, failed_block) {
+            (Some(size), Some(valid), Some(failed_block)) => Ok(Self {
+                size,
+                valid,
+                failed_block,
+            }),
+            _ => bail!("missing size, valid or failed_block"),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        format!(
+            "size={}\nvalid={}\nfailed_block={:#X}\n",
+            self.size, self.valid, self.failed_block
+        )
+    }
+
+    // only whole blocks are ever resumed, and never past the end of the file
+    pub fn resume_offset(&self) -> usize {
+        let valid = self.valid.min(self.size as usize);
+        valid - valid % BLOCK_SIZE
+    }
+}
+
+// the partial download of a file being read to 'out', and its sidecar
+fn partial_paths(out: &Path) -> (PathBuf, PathBuf) {
+    let with = |ext| {
+        let mut path = out.as_os_str().to_owned();
+        path.push(ext);
+        PathBuf::from(path)
+    };
+    (with(".partial"), with(".partial.info"))
+}
+
+// data already downloaded by an earlier, interrupted attempt at this file, if it can be used
+fn load_partial(name: &str, out: &Path, size: u32) -> Option<Vec<u8>> {
+    let (data_path, info_path) = partial_paths(out);
+    let info = match read_to_string(&info_path) {
+        Ok(text) => match PartialInfo::parse(&text) {
+            Ok(i) => i,
+            Err(e) => {
+                eprintln!("Ignoring {}: {e}", info_path.display());
+                return None;
+            }
+        },
+        Err(_) => {
+            println!("No partial download of {name} found; starting from the beginning");
+            return None;
+        }
+    };
+    if info.size != size {
+        println!("{name} has changed size since the partial download; starting from the beginning");
+        return None;
+    }
+    let mut data = match read(&data_path) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {e}", data_path.display());
+            return None;
+        }
+    };
+    data.truncate(
+        info.resume_offset()
+            .min(data.len() - data.len() % BLOCK_SIZE),
+    );
+    Some(data)
+}
+
+// a file's entry and the blocks holding it, in order
+fn locate(player: &dyn Player, name: &str) -> Result<(FsEntry, Vec<u16>)> {
+    let fs = FsBlock::parse(&player.DumpCurrentFS()?)?;
+    let Some(entry) = fs.find(name) else {
+        bail!("File {name} not found");
+    };
+    let chain = fs.chain(entry.start)?;
+    if chain.len() < entry.blocks() {
+        bail!(
+            "{name} is {} blocks long, but its FAT chain only has {}",
+            entry.blocks(),
+            chain.len()
+        );
+    }
+    Ok((entry.clone(), chain))
+}
+
+// reads a file from the console block by block, following its FAT chain; if a block fails or
+// it's cancelled between blocks, everything received so far is saved to '<name>.partial' so
+// that it can be resumed later
+pub fn download_file(
+    player: &dyn Player,
+    name: &str,
+    resume: bool,
+    events: bool,
+    cancel: &CancelToken,
+) -> Result<Vec<u8>> {
+    download_to(player, name, Path::new(name), resume, events, cancel)
+}
+
+// as 'download_file', keeping the partial download beside 'out'
+fn download_to(
+    player: &dyn Player,
+    name: &str,
+    out: &Path,
+    resume: bool,
+    events: bool,
+    cancel: &CancelToken,
+) -> Result<Vec<u8>> {
+    let (entry, chain) = locate(player, name)?;
+
+    let mut data = if resume {
+        load_partial(name, out, entry.size).unwrap_or_default()
+    } else {
+        vec![]
+    };
+    let skip = data.len() / BLOCK_SIZE;
+    if skip > 0 {
+        println!("Resuming {name} from offset {:#X}", data.len());
+    }
+
+    let remaining = (entry.blocks() - skip) as u64 * BLOCK_SIZE as u64;
+    let timer = TransferTimer::begin(player, name, remaining);
+    let mut progress = Progress::start("download", entry.blocks() as u64, BLOCK_SIZE, events);
+    progress.set_message(format!("Reading {name}"));
+    progress.inc(skip as u64);
+
+    for &blk in &chain[skip..entry.blocks()] {
+        match cancel
+            .check()
+            .and_then(|_| player.ReadSingleBlock(blk as u32))
+        {
+            Ok((n, _)) => {
+                data.extend_from_slice(&n);
+                progress.inc(1);
+            }
+            Err(e) => {
+                let (data_path, info_path) = partial_paths(out);
+                let info = PartialInfo {
+                    size: entry.size,
+                    valid: data.len(),
+                    failed_block: blk,
+                };
+                let saved = write(&data_path, &data)
+                    .and_then(|_| write(&info_path, info.to_text()))
+                    .map(|_| format!("; the first {:#X} bytes were saved to {}, use '3 --continue {name}' to resume", data.len(), data_path.display()))
+                    .unwrap_or_else(|e| format!("; couldn't save the partial download: {e}"));
+                let e = anyhow!(
+                    "Failed to read block {blk:#X} of {name} at offset {:#X}: {e}{saved}",
+                    data.len()
+                );
+                progress.fail(&e.to_string());
+                return Err(e);
+            }
+        }
+    }
+
+    progress.finish();
+    timer.complete();
+    data.truncate(entry.size as usize);
+    let (data_path, info_path) = partial_paths(out);
+    let _ = remove_file(data_path);
+    let _ = remove_file(info_path);
+    Ok(data)
+}
+
+// reads no more of a file than the blocks holding its first 'max' bytes, for a look at it
+// without pulling the whole thing; returns them with the file's full size
+pub fn read_head(
+    player: &dyn Player,
+    name: &str,
+    max: usize,
+    cancel: &CancelToken,
+) -> Result<(Vec<u8>, u32)> {
+    let (entry, chain) = locate(player, name)?;
+    let len = max.min(entry.size as usize);
+    let mut data = vec![];
+    for &blk in &chain[..len.div_ceil(BLOCK_SIZE)] {
+        let (n, _) = cancel
+            .check()
+            .and_then(|_| player.ReadSingleBlock(blk as u32))
+            .map_err(|e| anyhow!("Failed to read block {blk:#X} of {name}: {e}"))?;
+        data.extend_from_slice(&n);
+    }
+    data.truncate(len);
+    Ok((data, entry.size))
+}
+
+// a file as it lies on the card, for '3 --with-spare': its data, and the spare data of each of
+// its blocks, in chain order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawFile {
+    pub data: Vec<u8>,
+    pub spare: Vec<u8>,
+    pub chain: Vec<u16>,
+    pub size: u32,
+}
+
+// the '<out>.chain.json' sidecar, so the spare data can be matched up with the blocks it's from
+#[derive(Debug, Serialize)]
+struct ChainSidecar<'a> {
+    file: &'a str,
+    size: u32,
+    spare_size: usize,
+    blocks: &'a [u16],
+    // when the dump it was read from was made, in RFC 3339, kept whether or not the files could
+    // be dated then
+    dumped: Option<String>,
+}
+
+impl RawFile {
+    pub fn sidecar(&self, name: &str, dumped: Option<DateTime<FixedOffset>>) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&ChainSidecar {
+            file: name,
+            size: self.size,
+            spare_size: SPARE_SIZE,
+            blocks: &self.chain,
+            dumped: dumped.map(|d| d.to_rfc3339()),
+        })?)
+    }
+}
+
+fn read_raw(
+    player: &dyn Player,
+    name: &str,
+    entry: &FsEntry,
+    chain: &[u16],
+    cancel: &CancelToken,
+    mut progress: Option<&mut Progress>,
+) -> Result<RawFile> {
+    let chain = &chain[..entry.blocks()];
+    let mut raw = RawFile {
+        data: Vec::with_capacity(chain.len() * BLOCK_SIZE),
+        spare: Vec::with_capacity(chain.len() * SPARE_SIZE),
+        chain: chain.to_vec(),
+        size: entry.size,
+    };
+    for &blk in chain {
+        let (n, s) = cancel
+            .check()
+            .and_then(|_| player.ReadSingleBlock(blk as u32))
+            .map_err(|e| anyhow!("Failed to read block {blk:#X} of {name}: {e}"))?;
+        raw.data.extend_from_slice(&n);
+        raw.spare.extend_from_slice(&s);
+        if let Some(p) = progress.as_mut() {
+            p.inc(1);
+        }
+    }
+    // as '3' gives it, without the end of the last block
+    raw.data.truncate(entry.size as usize);
+    Ok(raw)
+}
+
+// reads a file block by block with each block's spare data, following its FAT chain
+pub fn download_with_spare(
+    player: &dyn Player,
+    name: &str,
+    events: bool,
+    cancel: &CancelToken,
+) -> Result<RawFile> {
+    let (entry, chain) = locate(player, name)?;
+    let timer = TransferTimer::begin(player, name, entry.blocks() as u64 * BLOCK_SIZE as u64);
+    let mut progress = Progress::start("download", entry.blocks() as u64, BLOCK_SIZE, events);
+    progress.set_message(format!("Reading {name} with its spare data"));
+    match read_raw(player, name, &entry, &chain, cancel, Some(&mut progress)) {
+        Ok(raw) => {
+            progress.finish();
+            timer.complete();
+            Ok(raw)
+        }
+        Err(e) => {
+            progress.fail(&e.to_string());
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genimage::{generate, FileSpec, Pattern, Spec};
+    use crate::image::NandImage;
+    use crate::mount::MountedImage;
+    use crate::test_dir::TestDir;
+    use bbrdb::CardStats;
+
+    // the card, but for one block that can't be read
+    struct Flaky<'a> {
+        card: &'a MountedImage,
+        bad: u16,
+    }
+
+    impl Player for Flaky<'_> {
+        fn GetBBID(&self) -> Result<u32> {
+            self.card.GetBBID()
+        }
+
+        fn SetLED(&self, value: u32) -> Result<()> {
+            self.card.SetLED(value)
+        }
+
+        fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+            self.card.ListFiles()
+        }
+
+        fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+            self.card.DumpCurrentFS()
+        }
+
+        fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+            self.card.ReadFile(name)
+        }
+
+        fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+            if blk == self.bad as u32 {
+                bail!("Operation timed out");
+            }
+            self.card.ReadSingleBlock(blk)
+        }
+
+        fn CardStats(&self) -> Result<CardStats> {
+            self.card.CardStats()
+        }
+    }
+
+    #[test]
+    fn partial_info() -> Result<()> {
+        let info = PartialInfo {
+            size: 0x9000,
+            valid: 0x4000,
+            failed_block: 0x1A3,
+        };
+        if info.to_text() != "size=36864\nvalid=16384\nfailed_block=0x1A3\n" {
+            bail!("the sidecar was written as {:?}", info.to_text());
+        }
+        assert_eq!(PartialInfo::parse(&info.to_text())?, info);
+        // unknown keys and blank lines are skipped, and the block may be given without '0x'
+        assert_eq!(
+            PartialInfo::parse("\nsize = 36864\nnote=x\nvalid=16384\nfailed_block=1a3\n")?,
+            info
+        );
+        for bad in [
+            "size=36864\nvalid=16384",
+            "size=36864\nvalid\nfailed_block=0x1",
+            "size=-1\nvalid=0\nfailed_block=0",
+        ] {
+            if PartialInfo::parse(bad).is_ok() {
+                bail!("{bad:?} was parsed");
+            }
+        }
+
+        // only whole blocks are resumed, and never past the end of the file
+        let at = |size, valid| {
+            PartialInfo {
+                size,
+                valid,
+                failed_block: 0,
+            }
+            .resume_offset()
+        };
+        assert_eq!(at(0x9000, 0x4000), 0x4000);
+        assert_eq!(at(0x9000, 0x7FFF), 0x4000);
+        assert_eq!(at(0x9000, 0x3FFF), 0);
+        assert_eq!(at(0x4000, 0x8000), 0x4000);
+        assert_eq!(at(0x6000, 0x8000), 0x4000);
+        Ok(())
+    }
+
+    // a read that fails partway keeps what it got, and '--continue' carries on from there
+    #[test]
+    fn resumes() -> Result<()> {
+        let name = "TEST.sys";
+        let spec = Spec {
+            seed: 8,
+            blocks: 0x80,
+            scatter: true,
+            files: vec![FileSpec {
+                name: name.into(),
+                pattern: Some(Pattern::Random),
+                size: Some(0x9000),
+                ..FileSpec::default()
+            }],
+            ..Spec::default()
+        };
+        let generated = generate(&spec, &|path| bail!("{path}: no local files"))?;
+        let chain = generated.manifest.files[0].chain.clone();
+        let card = MountedImage::from_image(
+            NandImage::new(generated.nand, generated.spare)?,
+            "mock",
+            "mock.spare",
+        )?;
+        let whole = card
+            .ReadFile(name)?
+            .ok_or_else(|| anyhow!("{name} isn't in the generated dump"))?;
+
+        let dir = TestDir::new("download")?;
+        let out = dir.join(name);
+        let (data_path, info_path) = partial_paths(&out);
+        let cancel = CancelToken::default();
+
+        // the second block fails
+        let link = Flaky {
+            card: &card,
+            bad: chain[1],
+        };
+        let e = match download_to(&link, name, &out, false, false, &cancel) {
+            Ok(_) => bail!("the read didn't fail"),
+            Err(e) => e.to_string(),
+        };
+        if !e.contains(&format!("block {:#X}", chain[1])) || !e.contains("3 --continue TEST.sys") {
+            bail!("the failure was reported as '{e}'");
+        }
+        let info = PartialInfo::parse(&read_to_string(&info_path)?)?;
+        assert_eq!(
+            info,
+            PartialInfo {
+                size: 0x9000,
+                valid: BLOCK_SIZE,
+                failed_block: chain[1],
+            }
+        );
+        if read(&data_path)? != whole[..BLOCK_SIZE] {
+            bail!("the partial download isn't the file's first block");
+        }
+
+        // resuming skips the block already read, and tidies up after itself
+        let link = Flaky {
+            card: &card,
+            bad: chain[0],
+        };
+        if download_to(&link, name, &out, true, false, &cancel)? != whole {
+            bail!("the resumed read differs from the file");
+        }
+        if data_path.exists() || info_path.exists() {
+            bail!("the partial download was left behind");
+        }
+
+        // a partial of a file that's since changed size isn't used
+        write(&data_path, vec![0; BLOCK_SIZE])?;
+        write(
+            &info_path,
+            PartialInfo {
+                size: 0x8000,
+                valid: BLOCK_SIZE,
+                failed_block: chain[1],
+            }
+            .to_text(),
+        )?;
+        if load_partial(name, &out, 0x9000).is_some() {
+            bail!("a partial of a different size was resumed");
+        }
+        if download_to(&card, name, &out, true, false, &cancel)? != whole {
+            bail!("the stale partial was used");
+        }
+
+        // a cancelled read is kept too
+        cancel.cancel();
+        if download_to(&card, name, &out, false, false, &cancel).is_ok() {
+            bail!("the cancelled read succeeded");
+        }
+        if !read(&data_path)?.is_empty() {
+            bail!("blocks were read after cancelling");
+        }
+        Ok(())
+    }
+
+    // reads a fragmented file from a generated dump in memory standing in for the card
+    #[test]
+    fn raw_downloads() -> Result<()> {
+        // scattered, as a file written to a well-used card is
+        let name = "TEST.sys";
+        let spec = Spec {
+            seed: 4,
+            blocks: 0x80,
+            scatter: true,
+            files: vec![FileSpec {
+                name: name.into(),
+                pattern: Some(Pattern::Random),
+                size: Some(0x6000),
+                ..FileSpec::default()
+            }],
+            ..Spec::default()
+        };
+        let generated = generate(&spec, &|path| bail!("{path}: no local files"))?;
+        let chain = generated.manifest.files[0].chain.clone();
+        if chain.is_sorted() {
+            bail!("the generated chain {chain:X?} is in order");
+        }
+        let in_chain = |data: &[u8], size| {
+            chain
+                .iter()
+                .flat_map(|&blk| &data[blk as usize * size..(blk as usize + 1) * size])
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        let (data, spare) = (
+            in_chain(&generated.nand, BLOCK_SIZE),
+            in_chain(&generated.spare, SPARE_SIZE),
+        );
+        let card = MountedImage::from_image(
+            NandImage::new(generated.nand, generated.spare)?,
+            "mock",
+            "mock.spare",
+        )?;
+
+        let (entry, found) = locate(&card, name)?;
+        if found != chain {
+            bail!("the chain was resolved as {found:X?}, not {chain:X?}");
+        }
+        let raw = read_raw(&card, name, &entry, &found, &CancelToken::default(), None)?;
+        if raw.data != data[..entry.size as usize] {
+            bail!("the file's data wasn't read in chain order");
+        }
+        if card.ReadFile(name)? != Some(raw.data.clone()) {
+            bail!("the file read block by block differs from the file read whole");
+        }
+        if raw.spare != spare {
+            bail!("the spare data wasn't read in chain order");
+        }
+        let sidecar: serde_json::Value = serde_json::from_str(&raw.sidecar(name, None)?)?;
+        if sidecar["blocks"] != serde_json::json!(chain) || sidecar["size"] != 0x6000 {
+            bail!("the chain sidecar was wrong: {sidecar}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+
+use crate::cancel::CancelToken;
+use crate::fs::BLOCK_SIZE;
+use crate::player::Player;
+#[cfg(feature = "writing")]
+use crate::player::PlayerWrite;
+#[cfg(feature = "writing")]
+use crate::prompt::Prompt;
+use crate::sizes::{format_size, format_total, total};
+#[cfg(feature = "writing")]
+use crate::ticket_backup::{backup_tickets, touches_tickets};
+
+// files on the card with identical contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DupeSet {
+    pub size: u32,
+    pub sha256: String,
+    pub names: Vec<String>,
+}
+
+impl DupeSet {
+    // files take up whole blocks, so that's what deleting all but one of them frees
+    pub fn reclaimable(&self) -> u64 {
+        let blocks = (self.size as u64).div_ceil(BLOCK_SIZE as u64).max(1);
+        blocks * BLOCK_SIZE as u64 * (self.names.len() as u64 - 1)
+    }
+}
+
+// groups (name, size, hash) by content, keeping only groups of more than one file, with the
+// most space to reclaim first
+pub fn group_duplicates(files: &[(String, u32, String)]) -> Vec<DupeSet> {
+    let mut groups: HashMap<(u32, &str), Vec<String>> = HashMap::new();
+    for (name, size, hash) in files {
+        groups
+            .entry((*size, hash.as_str()))
+            .or_default()
+            .push(name.clone());
+    }
+    let mut sets = groups
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+        .map(|((size, hash), mut names)| {
+            names.sort();
+            DupeSet {
+                size,
+                sha256: hash.to_string(),
+                names,
+            }
+        })
+        .collect::<Vec<_>>();
+    sets.sort_by(|a, b| {
+        b.reclaimable()
+            .cmp(&a.reclaimable())
+            .then_with(|| a.names.cmp(&b.names))
+    });
+    sets
+}
+
+// None if it's too much to add up, which only corrupted sizes could make it
+pub fn total_reclaimable(sets: &[DupeSet]) -> Option<u64> {
+    total(sets.iter().map(DupeSet::reclaimable))
+}
+
+// reads and hashes every file that shares its size with another; a file with a size of its
+// own can't have a duplicate, so isn't read at all
+// cancellable between files
+pub fn find_duplicates(player: &dyn Player, cancel: &CancelToken) -> Result<Vec<DupeSet>> {
+    let files = player.ListFiles()?;
+    let mut sizes: HashMap<u32, usize> = HashMap::new();
+    for (_, size) in &files {
+        *sizes.entry(*size).or_default() += 1;
+    }
+    let candidates = files
+        .into_iter()
+        .filter(|(_, size)| sizes[size] > 1)
+        .collect::<Vec<_>>();
+
+    let mut hashed = vec![];
+    let total = candidates.len();
+    for (i, (name, size)) in candidates.into_iter().enumerate() {
+        cancel.check()?;
+        println!("Hashing {name} ({}/{total})", i + 1);
+        let data = player
+            .ReadFile(&name)?
+            .ok_or_else(|| anyhow!("{name} disappeared while it was being read"))?;
+        hashed.push((name, size, format!("{:x}", Sha256::digest(&data))));
+    }
+    Ok(group_duplicates(&hashed))
+}
+
+pub fn print_dupes(sets: &[DupeSet]) {
+    if sets.is_empty() {
+        println!("No duplicate files found");
+        return;
+    }
+    for (i, set) in sets.iter().enumerate() {
+        println!(
+            "Set {}: {} copies of {} ({}), {} reclaimable",
+            i + 1,
+            set.names.len(),
+            format_size(set.size as u64, 0),
+            &set.sha256[..16],
+            format_size(set.reclaimable(), 0)
+        );
+        for (j, name) in set.names.iter().enumerate() {
+            println!("    {}. {name}", j + 1);
+        }
+    }
+    println!(
+        "{} reclaimable by keeping one copy of each",
+        format_total(total_reclaimable(sets), 0)
+    );
+}
+
+// asks which copies in each set to delete, never allowing all of them to go, and deletes them
+// the way '6' does, backing up the ticket file first if it's one of them
+#[cfg(feature = "writing")]
+pub fn delete_extras(
+    rl: &mut dyn Prompt,
+    player: &mut dyn PlayerWrite,
+    sets: &[DupeSet],
+    ticket_backups: Option<usize>,
+) -> Result<()> {
+    for (i, set) in sets.iter().enumerate() {
+        let answer = rl.readline(&format!(
+            "Set {}: numbers of the copies to delete, or Enter to skip: ",
+            i + 1
+        ))?;
+        let chosen = answer
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<usize>()
+                    .ok()
+                    .and_then(|n| set.names.get(n.checked_sub(1)?))
+                    .ok_or_else(|| anyhow!("'{s}' isn't one of the copies in set {}", i + 1))
+            })
+            .collect::<Result<Vec<_>>>();
+        let mut chosen = match chosen {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("{e}; skipping this set");
+                continue;
+            }
+        };
+        chosen.sort();
+        chosen.dedup();
+        if chosen.is_empty() {
+            continue;
+        }
+        if chosen.len() == set.names.len() {
+            eprintln!("That's every copy; keep at least one. Skipping this set");
+            continue;
+        }
+
+        let names = chosen.iter().map(|n| n.as_str()).collect::<Vec<_>>();
+        let answer = rl.readline(&format!("Delete {}? [y/N] ", names.join(", ")))?;
+        if !matches!(answer.trim(), "y" | "Y") {
+            println!("Skipped");
+            continue;
+        }
+        if touches_tickets(&names) {
+            if let Err(e) = backup_tickets(&*player, ticket_backups) {
+                eprintln!("{e}; not deleting");
+                continue;
+            }
+        }
+        for name in names {
+            match player.DeleteFile(name) {
+                Ok(_) => println!("Deleted {name}"),
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+
+    use anyhow::bail;
+    use bbrdb::CardStats;
+
+    // a card's files, recording which are read
+    #[derive(Default)]
+    struct Card {
+        files: BTreeMap<String, Vec<u8>>,
+        read: RefCell<Vec<String>>,
+    }
+
+    impl Player for Card {
+        fn GetBBID(&self) -> Result<u32> {
+            Ok(0x1234)
+        }
+        fn SetLED(&self, _: u32) -> Result<()> {
+            Ok(())
+        }
+        fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+            Ok(self
+                .files
+                .iter()
+                .map(|(n, d)| (n.clone(), d.len() as u32))
+                .collect())
+        }
+        fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+            bail!("no FS")
+        }
+        fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+            self.read.borrow_mut().push(name.to_string());
+            Ok(self.files.get(name).cloned())
+        }
+        fn ReadSingleBlock(&self, _: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+            bail!("no blocks")
+        }
+        fn CardStats(&self) -> Result<CardStats> {
+            bail!("no stats")
+        }
+    }
+
+    #[cfg(feature = "writing")]
+    impl PlayerWrite for Card {
+        fn WriteFile(&mut self, data: &[u8], name: &str) -> Result<()> {
+            self.files.insert(name.to_string(), data.to_vec());
+            Ok(())
+        }
+        fn DeleteFile(&mut self, name: &str) -> Result<()> {
+            self.files
+                .remove(name)
+                .map(|_| ())
+                .ok_or_else(|| anyhow!("{name} doesn't exist"))
+        }
+        fn RenameFile(&mut self, _: &str, _: &str) -> Result<()> {
+            bail!("dupes doesn't rename")
+        }
+    }
+
+    fn card(files: &[(&str, &[u8])]) -> Card {
+        Card {
+            files: files
+                .iter()
+                .map(|(n, d)| (n.to_string(), d.to_vec()))
+                .collect(),
+            ..Card::default()
+        }
+    }
+
+    // by whole blocks, never less than one, for every copy but one; the biggest saving first
+    #[test]
+    fn grouping() {
+        let file = |name: &str, size, hash: &str| (name.to_string(), size, hash.to_string());
+        let sets = group_duplicates(&[
+            file("a.sav", 0x200, "s"),
+            file("b.sav", 0x200, "s"),
+            file("c.sav", 0x200, "s"),
+            file("x.app", BLOCK_SIZE as u32 + 1, "g"),
+            file("y.app", BLOCK_SIZE as u32 + 1, "g"),
+            // the same size but not the same contents, or the other way round
+            file("other.sav", 0x200, "t"),
+            file("z.app", 0x200, "g"),
+            file("e1", 0, "e"),
+            file("e2", 0, "e"),
+        ]);
+        let names = sets.iter().map(|s| s.names.join(",")).collect::<Vec<_>>();
+        assert_eq!(names, ["a.sav,b.sav,c.sav", "x.app,y.app", "e1,e2"]);
+        let reclaimable = sets.iter().map(DupeSet::reclaimable).collect::<Vec<_>>();
+        assert_eq!(
+            reclaimable,
+            [
+                2 * BLOCK_SIZE as u64,
+                2 * BLOCK_SIZE as u64,
+                BLOCK_SIZE as u64
+            ]
+        );
+        assert_eq!(total_reclaimable(&sets), Some(5 * BLOCK_SIZE as u64));
+        assert!(group_duplicates(&[file("only", 1, "h")]).is_empty());
+    }
+
+    // only files sharing a size with another are read
+    #[test]
+    fn finds() -> Result<()> {
+        let card = card(&[
+            ("A.app", &[1; 0x40]),
+            ("B.app", &[1; 0x40]),
+            ("C.app", &[2; 0x40]),
+            ("lone.sav", &[1; 0x41]),
+        ]);
+        let sets = find_duplicates(&card, &CancelToken::default())?;
+        assert_eq!(sets.len(), 1);
+        assert_eq!(sets[0].names, ["A.app", "B.app"]);
+        assert_eq!(*card.read.borrow(), ["A.app", "B.app", "C.app"]);
+
+        let cancel = CancelToken::default();
+        cancel.cancel();
+        assert!(find_duplicates(&card, &cancel).is_err());
+        Ok(())
+    }
+
+    // never every copy, nothing without a yes, and bad numbers skip the set
+    #[cfg(feature = "writing")]
+    #[test]
+    fn deletes() -> Result<()> {
+        use std::collections::VecDeque;
+
+        let mut card = card(&[
+            ("a.sav", &[1; 8]),
+            ("b.sav", &[1; 8]),
+            ("c.sav", &[1; 8]),
+            ("x.app", &[2; 4]),
+            ("y.app", &[2; 4]),
+            ("p.rec", &[3; 2]),
+            ("q.rec", &[3; 2]),
+        ]);
+        let sets = find_duplicates(&card, &CancelToken::default())?;
+        let mut answers = [
+            // a, b and c: first every copy, which is skipped
+            "1,2,3", // x and y: declined
+            "2", "n", // p and q: not a copy
+            "3",
+        ]
+        .map(str::to_string)
+        .into_iter()
+        .collect::<VecDeque<_>>();
+        delete_extras(&mut answers, &mut card, &sets, None)?;
+        assert_eq!(card.files.len(), 7);
+
+        let mut answers = ["2 3, 3", "y", "", "1", "y"]
+            .map(str::to_string)
+            .into_iter()
+            .collect::<VecDeque<_>>();
+        delete_extras(&mut answers, &mut card, &sets, None)?;
+        assert!(answers.is_empty());
+        assert_eq!(
+            card.files.keys().collect::<Vec<_>>(),
+            ["a.sav", "p.rec", "q.rec", "y.app"]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{bail, Result};
+
+// SmartMedia-style Hamming ECC: 3 bytes for every 256 bytes of data, correcting one bit
+
+/// The bytes of data each 3 bytes of ECC cover.
+pub const ECC_CHUNK: usize = 0x100;
+
+fn parity(b: u8) -> u8 {
+    (b.count_ones() & 1) as u8
+}
+
+/// The ECC of a chunk of [`ECC_CHUNK`] bytes, as it's stored in the spare data; fails for data
+/// of any other size.
+pub fn ecc256(data: &[u8]) -> Result<[u8; 3]> {
+    if data.len() != ECC_CHUNK {
+        bail!(
+            "ECC is worked out {ECC_CHUNK:#X} bytes at a time, not {:#X}",
+            data.len()
+        );
+    }
+
+    // line parities: bit 2k covers bytes whose index has bit k clear, bit 2k + 1 those with it set
+    let mut lp = 0u16;
+    let mut all = 0u8;
+    for (i, &b) in data.iter().enumerate() {
+        all ^= b;
+        if parity(b) != 0 {
+            for k in 0..8 {
+                lp ^= 1 << (2 * k + ((i >> k) & 1));
+            }
+        }
+    }
+
+    // column parities, the same pattern applied to bit positions within a byte
+    let cp = [0x55, 0xAA, 0x33, 0xCC, 0x0F, 0xF0]
+        .iter()
+        .enumerate()
+        .fold(0u8, |cp, (k, mask)| cp | (parity(all & mask) << k));
+
+    // stored inverted, so an erased chunk has an all-0xFF ECC
+    Ok([!(lp as u8), !((lp >> 8) as u8), !(cp << 2)])
+}
+
+/// How data compares with the ECC stored for it, from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EccCheck {
+    /// The data matches its ECC.
+    Clean,
+    /// One bit flipped, in the data or the ECC itself.
+    Correctable,
+    /// More than one bit flipped.
+    Uncorrectable,
+}
+
+/// Compares a chunk with the ECC stored for it; fails for data that isn't [`ECC_CHUNK`] bytes.
+// a single flipped data bit flips exactly one bit of each of the 11 parity pairs, and a flipped
+// ECC bit is the only difference
+pub fn check256(data: &[u8], stored: [u8; 3]) -> Result<EccCheck> {
+    let ecc = ecc256(data)?;
+    let syndrome = (ecc[0] ^ stored[0]) as u32
+        | ((ecc[1] ^ stored[1]) as u32) << 8
+        | (((ecc[2] ^ stored[2]) >> 2) as u32) << 16;
+    Ok(match syndrome.count_ones() {
+        0 => EccCheck::Clean,
+        1 => EccCheck::Correctable,
+        11 if (0..11).all(|p| matches!((syndrome >> (2 * p)) & 3, 1 | 2)) => EccCheck::Correctable,
+        _ => EccCheck::Uncorrectable,
+    })
+}
+
+/// Where the ECC of the second chunk of a block's first page is kept in its spare data.
+pub const ECC_AREA_2: usize = 8;
+/// Where the ECC of the first chunk of a block's first page is kept in its spare data.
+pub const ECC_AREA_1: usize = 13;
+
+/// Writes the ECC of the first page of `page`, which may be the whole block, into `spare` where
+/// the console keeps it; fails if either is too short.
+pub fn page_ecc(page: &[u8], spare: &mut [u8]) -> Result<()> {
+    let (Some(first), Some(second)) = (page.get(..ECC_CHUNK), page.get(ECC_CHUNK..2 * ECC_CHUNK))
+    else {
+        bail!("{:#X} bytes is short of a page", page.len());
+    };
+    if spare.len() < ECC_AREA_1 + 3 {
+        bail!("{:#X} bytes is short of a block's spare data", spare.len());
+    }
+    spare[ECC_AREA_1..ECC_AREA_1 + 3].copy_from_slice(&ecc256(first)?);
+    spare[ECC_AREA_2..ECC_AREA_2 + 3].copy_from_slice(&ecc256(second)?);
+    Ok(())
+}
+
+pub(crate) fn self_test() -> Result<()> {
+    if ecc256(&[0xFF; ECC_CHUNK])? != [0xFF; 3] {
+        bail!("an erased chunk's ECC isn't all 0xFF");
+    }
+    // ECC of a zeroed chunk with the given (offset, value) bytes set
+    let check = |set: &[(usize, u8)], expected: [u8; 3]| -> Result<()> {
+        let mut chunk = [0; ECC_CHUNK];
+        for &(offset, value) in set {
+            chunk[offset] = value;
+        }
+        let ecc = ecc256(&chunk)?;
+        if ecc != expected {
+            bail!("{set:02X?}: got {ecc:02X?}, expected {expected:02X?}");
+        }
+        Ok(())
+    };
+    check(&[], [0xFF, 0xFF, 0xFF])?;
+    check(&[(0x00, 0x01)], [0xAA, 0xAA, 0xAB])?;
+    check(&[(0xFF, 0x80)], [0x55, 0x55, 0x57])?;
+    check(&[(0x00, 0x01), (0xFF, 0x80)], [0x00, 0x00, 0x03])?;
+
+    let mut chunk = [0x5A; ECC_CHUNK];
+    let ecc = ecc256(&chunk)?;
+    if check256(&chunk, ecc)? != EccCheck::Clean {
+        bail!("a chunk didn't match its own ECC");
+    }
+    if check256(&chunk, [ecc[0], ecc[1] ^ 0x10, ecc[2]])? != EccCheck::Correctable {
+        bail!("a flipped ECC bit wasn't correctable");
+    }
+    chunk[0x93] ^= 0x04;
+    if check256(&chunk, ecc)? != EccCheck::Correctable {
+        bail!("a flipped data bit wasn't correctable");
+    }
+    chunk[0x12] ^= 0x01;
+    if check256(&chunk, ecc)? != EccCheck::Uncorrectable {
+        bail!("two flipped data bits were taken as correctable");
+    }
+    // and data that isn't a chunk, or a page, is an error rather than a panic
+    if ecc256(&chunk[1..]).is_ok() || page_ecc(&[0; ECC_CHUNK], &mut [0xFF; 0x10]).is_ok() {
+        bail!("ECC was worked out for data of the wrong size");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn vectors() -> anyhow::Result<()> {
+        super::self_test()
+    }
+}
@@ -0,0 +1,219 @@
+use std::error::Error;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fs::FsError;
+use crate::known_errors::{self, identify};
+use crate::wrap::{stderr_width, wrap};
+
+// Printing a command's failure: every level of the error's chain on its own line, each with the
+// layer it came from where that can be told from its type, or as JSON when progress events are on.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layer {
+    #[serde(rename = "local io")]
+    LocalIo,
+    Usb,
+    // the console was there, but didn't answer as it should have
+    Protocol,
+    Parsing,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::LocalIo => "local io",
+            Self::Usb => "usb",
+            Self::Protocol => "protocol",
+            Self::Parsing => "parsing",
+        })
+    }
+}
+
+// these are matched exhaustively, so a new variant has to be given a layer before it builds
+fn usb_layer(e: &rusb::Error) -> Layer {
+    use rusb::Error::*;
+    match e {
+        Timeout | Pipe | Overflow => Layer::Protocol,
+        Io | InvalidParam | Access | NoDevice | NotFound | Busy | Interrupted | NoMem
+        | NotSupported | BadDescriptor | Other => Layer::Usb,
+    }
+}
+
+fn fs_layer(e: &FsError) -> Layer {
+    match e {
+        FsError::WrongSize(_) | FsError::BadMagic(_) | FsError::BadChecksum(_) => Layer::Parsing,
+    }
+}
+
+// the one place errors are given a layer; anything not listed here has none
+pub fn classify(e: &(dyn Error + 'static)) -> Option<Layer> {
+    if let Some(e) = e.downcast_ref::<rusb::Error>() {
+        Some(usb_layer(e))
+    } else if let Some(e) = e.downcast_ref::<FsError>() {
+        Some(fs_layer(e))
+    } else if e.is::<std::io::Error>() {
+        Some(Layer::LocalIo)
+    } else if e.is::<serde_json::Error>()
+        || e.is::<toml::de::Error>()
+        || e.is::<std::num::ParseIntError>()
+        || e.is::<std::str::Utf8Error>()
+        || e.is::<std::string::FromUtf8Error>()
+        || e.is::<chrono::ParseError>()
+    {
+        Some(Layer::Parsing)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Level {
+    pub layer: Option<Layer>,
+    pub message: String,
+}
+
+pub fn levels(e: &(dyn Error + 'static)) -> Vec<Level> {
+    let mut levels = vec![];
+    let mut next = Some(e);
+    while let Some(e) = next {
+        levels.push(Level {
+            layer: classify(e),
+            message: e.to_string(),
+        });
+        next = e.source();
+    }
+    levels
+}
+
+// the outermost message first, then what caused it, each indented a little further
+pub fn render(levels: &[Level]) -> String {
+    levels
+        .iter()
+        .enumerate()
+        .map(|(depth, level)| {
+            let indent = "  ".repeat(depth);
+            let lead = if depth == 0 { "" } else { "caused by: " };
+            match level.layer {
+                Some(layer) => format!("{indent}{lead}[{layer}] {}", level.message),
+                None => format!("{indent}{lead}{}", level.message),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Serialize)]
+struct ErrorEvent<'a> {
+    event: &'static str,
+    chain: &'a [Level],
+}
+
+pub fn render_json(levels: &[Level]) -> String {
+    serde_json::to_string(&ErrorEvent {
+        event: "error",
+        chain: levels,
+    })
+    .unwrap_or_default()
+}
+
+// prints a command's failure to stderr, each level wrapped to the terminal under its own indent,
+// then what to do about it if it's a known one; `json` is the progress-events option, whose JSON
+// lines this joins
+pub fn print_error(e: &(dyn Error + 'static), json: bool) {
+    let levels = levels(e);
+    let known = identify(&levels);
+    if json {
+        eprintln!("{}", render_json(&levels));
+        if let Some(signature) = known {
+            eprintln!("{}", known_errors::render_json(signature));
+        }
+        return;
+    }
+    let width = stderr_width();
+    for line in render(&levels).lines() {
+        let text = line.trim_start();
+        let indent = &line[..line.len() - text.len()];
+        eprintln!("{}", wrap(text, width, indent, &format!("{indent}  ")));
+    }
+    if let Some(signature) = known {
+        eprintln!(
+            "{}",
+            wrap(&format!("Try: {}", signature.remedy), width, "", "  ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, bail, Result};
+
+    #[test]
+    fn error_chains() -> Result<()> {
+        let check = |what: &str, found: String, expected: &str| -> Result<()> {
+            if found != expected {
+                bail!("{what} was rendered as\n{found}\nnot\n{expected}");
+            }
+            Ok(())
+        };
+
+        let usb = anyhow::Error::new(rusb::Error::Timeout)
+            .context("Couldn't read block 0x42")
+            .context("Dumping the NAND failed");
+        check(
+            "a USB timeout",
+            render(&levels(&*usb)),
+            "Dumping the NAND failed\n  caused by: Couldn't read block 0x42\n    caused by: [protocol] Operation timed out",
+        )?;
+        check(
+            "a USB timeout, as JSON",
+            render_json(&levels(&*usb)),
+            r#"{"event":"error","chain":[{"layer":null,"message":"Dumping the NAND failed"},{"layer":null,"message":"Couldn't read block 0x42"},{"layer":"protocol","message":"Operation timed out"}]}"#,
+        )?;
+
+        let io = anyhow::Error::new(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "Permission denied",
+        ))
+        .context("nand.bin");
+        check(
+            "a local file error",
+            render(&levels(&*io)),
+            "nand.bin\n  caused by: [local io] Permission denied",
+        )?;
+        check(
+            "a local file error, as JSON",
+            render_json(&levels(&*io)),
+            r#"{"event":"error","chain":[{"layer":null,"message":"nand.bin"},{"layer":"local io","message":"Permission denied"}]}"#,
+        )?;
+
+        let fs = anyhow::Error::new(FsError::BadMagic(*b"XXXX"));
+        check(
+            "a bad FS block",
+            render(&levels(&*fs)),
+            "[parsing] bad FS magic [58, 58, 58, 58]",
+        )?;
+        check(
+            "a bad number",
+            render(&levels(&"0x".parse::<u32>().unwrap_err())),
+            "[parsing] invalid digit found in string",
+        )?;
+        check(
+            "a device that's gone",
+            render(&levels(&rusb::Error::NoDevice)),
+            "[usb] No such device (it may have been disconnected)",
+        )?;
+
+        // an error of no known type is printed just as it always was
+        let plain = anyhow!("No such file on the console");
+        check(
+            "a plain message",
+            render(&levels(&*plain)),
+            "No such file on the console",
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use std::fs::read;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::HashAlgo;
+
+// The local files a saved session refers to (the mounted dump, and the files an open transaction
+// uploads), with what they held when it was saved. When it's loaded they're hashed again, so a
+// file that's been changed or deleted since is noticed instead of being used as if it were the
+// same one.
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct FileDigest {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    Unchanged,
+    Changed,
+    // with why it couldn't be read
+    Missing(String),
+}
+
+impl FileDigest {
+    pub fn of_data(path: &str, data: &[u8]) -> Self {
+        Self {
+            path: path.to_string(),
+            size: data.len() as u64,
+            sha256: HashAlgo::Sha256.digest(data).hex(),
+        }
+    }
+
+    pub fn of_file(path: &str) -> Result<Self> {
+        let data = read(path).map_err(|e| anyhow!("{path}: {e}"))?;
+        Ok(Self::of_data(path, &data))
+    }
+
+    pub fn check(&self) -> Drift {
+        match read(&self.path) {
+            Err(e) => Drift::Missing(e.to_string()),
+            Ok(data) if *self == Self::of_data(&self.path, &data) => Drift::Unchanged,
+            Ok(_) => Drift::Changed,
+        }
+    }
+
+    // what's wrong with the file, if anything
+    pub fn problem(&self) -> Option<String> {
+        match self.check() {
+            Drift::Unchanged => None,
+            Drift::Changed => Some(format!(
+                "{} has changed since the session was saved",
+                self.path
+            )),
+            Drift::Missing(e) => Some(format!("{} can't be read any more ({e})", self.path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn file_digests() -> Result<()> {
+        use anyhow::bail;
+
+        let dir = TestDir::new("digest")?;
+        let path = dir.join("nand.bin").to_string_lossy().into_owned();
+        std::fs::write(&path, [0xFF; 0x200])?;
+        let digest = FileDigest::of_file(&path)?;
+        if digest != FileDigest::of_data(&path, &[0xFF; 0x200])
+            || digest.check() != Drift::Unchanged
+        {
+            bail!(
+                "a file that hadn't changed was taken as {:?}",
+                digest.check()
+            );
+        }
+        if digest.problem().is_some() {
+            bail!("a file that hadn't changed had a problem");
+        }
+
+        // a change that keeps the size is still a change
+        let mut data = [0xFF; 0x200];
+        data[0x100] = 0;
+        std::fs::write(&path, data)?;
+        if digest.check() != Drift::Changed {
+            bail!("a file changed in place was taken as {:?}", digest.check());
+        }
+        std::fs::write(&path, [0xFF; 0x400])?;
+        if digest.check() != Drift::Changed
+            || !digest.problem().is_some_and(|p| p.contains("has changed"))
+        {
+            bail!("a file that grew was taken as {:?}", digest.check());
+        }
+
+        std::fs::remove_file(&path)?;
+        if !matches!(digest.check(), Drift::Missing(_)) || FileDigest::of_file(&path).is_ok() {
+            bail!("a deleted file was taken as {:?}", digest.check());
+        }
+        Ok(())
+    }
+}
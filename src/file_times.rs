@@ -0,0 +1,111 @@
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset};
+
+// The console's FS entries carry no times: an entry is an 8.3 name, the valid byte, the start
+// block, two unknown bytes and the size. The nearest thing to a file's time that's known is when
+// the dump it's read from was made, which '1 --manifest' records; a file read from a mounted dump
+// with one is dated then, on disk and in archives, rather than when it was read. A file read
+// from the console itself is as new as the read, so keeps the time it's written.
+
+// `time` as the platform keeps file times; None if it can't (Windows's start in 1601)
+pub fn to_system_time(time: DateTime<FixedOffset>) -> Option<SystemTime> {
+    let secs = time.timestamp();
+    let nanos = Duration::from_nanos(time.timestamp_subsec_nanos() as u64);
+    if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64) + nanos)
+    } else {
+        UNIX_EPOCH
+            .checked_sub(Duration::from_secs(secs.unsigned_abs()))?
+            .checked_add(nanos)
+    }
+}
+
+// `time` as a tar entry's, in whole seconds; None before 1970, which tar can't have
+pub fn to_tar_mtime(time: DateTime<FixedOffset>) -> Option<u64> {
+    u64::try_from(time.timestamp()).ok()
+}
+
+pub fn set_modified(path: impl AsRef<Path>, time: DateTime<FixedOffset>) -> Result<()> {
+    let system = to_system_time(time)
+        .ok_or_else(|| anyhow!("{time} is out of range for a file's time here"))?;
+    // Windows only sets a file's times through a handle opened for writing
+    File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(system)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use anyhow::bail;
+    use chrono::TimeZone;
+
+    #[test]
+    fn mapping() -> Result<()> {
+        let utc = FixedOffset::east_opt(0).ok_or_else(|| anyhow!("no UTC"))?;
+        let at = |secs, nanos| {
+            utc.timestamp_opt(secs, nanos)
+                .single()
+                .ok_or_else(|| anyhow!("no time at {secs}"))
+        };
+
+        // the epoch is the same to all of them
+        assert_eq!(to_system_time(at(0, 0)?), Some(UNIX_EPOCH));
+        assert_eq!(to_tar_mtime(at(0, 0)?), Some(0));
+
+        // fractions of a second are kept where they can be, and dropped in tar
+        let time = at(1_200_000_000, 250_000_000)?;
+        assert_eq!(
+            to_system_time(time),
+            Some(UNIX_EPOCH + Duration::from_millis(1_200_000_000_250))
+        );
+        assert_eq!(to_tar_mtime(time), Some(1_200_000_000));
+
+        // the zone makes no difference to the instant
+        let east = FixedOffset::east_opt(8 * 3600).ok_or_else(|| anyhow!("no UTC+8"))?;
+        assert_eq!(
+            to_system_time(time.with_timezone(&east)),
+            to_system_time(time)
+        );
+
+        // before 1970, tar can't have it, and a second and a half before is a second back and
+        // half forward
+        let before = at(-2, 500_000_000)?;
+        assert_eq!(to_tar_mtime(before), None);
+        #[cfg(unix)]
+        assert_eq!(
+            to_system_time(before),
+            Some(UNIX_EPOCH - Duration::from_millis(1_500))
+        );
+
+        // and the end of four-digit years is in range for both
+        let late = DateTime::parse_from_rfc3339("9999-12-31T23:59:59Z")?;
+        assert_eq!(to_tar_mtime(late), Some(253_402_300_799));
+        assert_eq!(
+            to_system_time(late),
+            Some(UNIX_EPOCH + Duration::from_secs(253_402_300_799))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sets() -> Result<()> {
+        let dir = TestDir::new("times")?;
+        let path = dir.join("dated.bin");
+        std::fs::write(&path, b"dated")?;
+        let dumped = DateTime::parse_from_rfc3339("2024-05-02T10:00:00+01:00")?;
+        set_modified(&path, dumped)?;
+        let modified = path.metadata()?.modified()?;
+        if Some(modified) != to_system_time(dumped) {
+            bail!("the file's time was set to {modified:?}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt::{self, Display};
+
+use anyhow::{bail, Result};
+
+use crate::fs::{BLOCK_SIZE, FS_REGION_BLOCKS, SPARE_SIZE};
+use crate::hashing::{HashAlgo, HashValue};
+use crate::spare::is_bad_block;
+
+// A fingerprint identifies the console a dump came from, so dumps can be compared by pasting a
+// line. Version 1 is the SHA-256 of, for each block before the FS region (the last 16 blocks)
+// in order, skipping blocks marked bad in their spare data and erased blocks (all 0xFF): the
+// block's number as 2 bytes big-endian, then its 0x4000 bytes of data. Skipping the FS region
+// means writing FS generations doesn't change it, and skipping erased blocks means the unused
+// part of a larger card doesn't either. Any change to these rules needs a new version. Another
+// algorithm can stand in for SHA-256, and says so in the short form, as 'v1-md5'.
+
+pub const FINGERPRINT_VERSION: u32 = 1;
+
+pub const RULES: &str = "the hash over (block number, 2 bytes big-endian; block data) for each \
+                         block before the FS region, skipping bad and erased blocks";
+
+pub struct Fingerprint {
+    // the full hash; SHA-256 unless another was asked for
+    pub digest: HashValue,
+    // how many blocks went into it
+    pub blocks: usize,
+}
+
+impl Fingerprint {
+    pub fn compute(nand: &[u8], spare: &[u8], algo: HashAlgo) -> Result<Self> {
+        if !nand.len().is_multiple_of(BLOCK_SIZE)
+            || spare.len() != nand.len() / BLOCK_SIZE * SPARE_SIZE
+        {
+            bail!("the NAND and spare data don't have the same number of whole blocks");
+        }
+        let num_blocks = nand.len() / BLOCK_SIZE;
+        let mut hasher = algo.hasher();
+        let mut blocks = 0;
+        for (blk, (data, spare)) in nand
+            .chunks_exact(BLOCK_SIZE)
+            .zip(spare.chunks_exact(SPARE_SIZE))
+            .take(num_blocks.saturating_sub(FS_REGION_BLOCKS))
+            .enumerate()
+        {
+            if is_bad_block(spare) || data.iter().all(|&b| b == 0xFF) {
+                continue;
+            }
+            hasher.update(&(blk as u16).to_be_bytes());
+            hasher.update(data);
+            blocks += 1;
+        }
+        Ok(Self {
+            digest: hasher.finalize(),
+            blocks,
+        })
+    }
+}
+
+// the short form: the version and the first 8 bytes of the digest (all of a CRC-32's 4), in
+// groups of 4 digits
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hex = self.digest.hex();
+        let short = hex.as_bytes()[..hex.len().min(16)]
+            .chunks(4)
+            .map(|c| String::from_utf8_lossy(c).into_owned())
+            .collect::<Vec<_>>()
+            .join("-");
+        let algo = match self.digest.algo {
+            HashAlgo::Sha256 => String::new(),
+            other => format!("-{other}"),
+        };
+        write!(
+            f,
+            "{}-fp-v{FINGERPRINT_VERSION}{algo}:{short}",
+            crate::PROG_NAME
+        )
+    }
+}
+
+pub fn print_fingerprint(fp: &Fingerprint) {
+    println!("{fp}");
+    println!("{}: {}", fp.digest.algo.label(), fp.digest.hex());
+    println!("({} blocks; v{FINGERPRINT_VERSION}: {RULES})", fp.blocks);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprints() -> Result<()> {
+        let blocks = 0x60;
+        let block = |fill: u8| vec![fill; BLOCK_SIZE];
+        let mut nand = vec![0xFF; blocks * BLOCK_SIZE];
+        let spare = vec![0xFF; blocks * SPARE_SIZE];
+        for (blk, fill) in [(0, 0x11), (1, 0x22), (0x40, 0x33), (0x41, 0x44)] {
+            nand[blk * BLOCK_SIZE..(blk + 1) * BLOCK_SIZE].copy_from_slice(&block(fill));
+        }
+        let before = Fingerprint::compute(&nand, &spare, HashAlgo::Sha256)?;
+        if before.blocks != 4 {
+            bail!("{} blocks were fingerprinted, expected 4", before.blocks);
+        }
+
+        // new FS generations, and a block marked bad with garbage in it, don't change it
+        let mut churned = nand.clone();
+        let mut churned_spare = spare.clone();
+        churned[(blocks - 3) * BLOCK_SIZE..(blocks - 2) * BLOCK_SIZE].copy_from_slice(&block(0x55));
+        churned[0x50 * BLOCK_SIZE..0x51 * BLOCK_SIZE].copy_from_slice(&block(0x66));
+        churned_spare[0x50 * SPARE_SIZE + 5] = 0;
+        if Fingerprint::compute(&churned, &churned_spare, HashAlgo::Sha256)?.digest != before.digest
+        {
+            bail!("FS churn changed the fingerprint");
+        }
+
+        // nor does the same data on a larger card, whose FS region is elsewhere
+        let mut larger = nand[..(blocks - FS_REGION_BLOCKS) * BLOCK_SIZE].to_vec();
+        larger.resize((blocks * 2) * BLOCK_SIZE, 0xFF);
+        let larger_spare = vec![0xFF; blocks * 2 * SPARE_SIZE];
+        if Fingerprint::compute(&larger, &larger_spare, HashAlgo::Sha256)?.digest != before.digest {
+            bail!("the card's size changed the fingerprint");
+        }
+
+        // a different console's data does, even if it's only moved to another block
+        let mut other = nand.clone();
+        other[BLOCK_SIZE + 7] ^= 1;
+        let mut moved = nand.clone();
+        moved.copy_within(0x41 * BLOCK_SIZE..0x42 * BLOCK_SIZE, 0x42 * BLOCK_SIZE);
+        moved[0x41 * BLOCK_SIZE..0x42 * BLOCK_SIZE].fill(0xFF);
+        for (what, data) in [
+            ("different data", &other),
+            ("data in another block", &moved),
+        ] {
+            if Fingerprint::compute(data, &spare, HashAlgo::Sha256)?.digest == before.digest {
+                bail!("{what} gave the same fingerprint");
+            }
+        }
+
+        let short = before.to_string();
+        if !short.starts_with(&format!("{}-fp-v1:", crate::PROG_NAME))
+            || short.len() != crate::PROG_NAME.len() + 7 + 19
+        {
+            bail!("the short form is {short}");
+        }
+
+        // another algorithm gives another fingerprint, marked as such, never SHA-256's
+        let crc = Fingerprint::compute(&nand, &spare, HashAlgo::Crc32)?;
+        let crc_short = crc.to_string();
+        if crc.digest.algo != HashAlgo::Crc32
+            || crc.digest == before.digest
+            || !crc_short.starts_with(&format!("{}-fp-v1-crc32:", crate::PROG_NAME))
+            || !crc_short.ends_with(&crc.digest.hex()[4..])
+        {
+            bail!("the CRC-32 fingerprint is {crc_short}");
+        }
+        Ok(())
+    }
+}
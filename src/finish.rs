@@ -0,0 +1,222 @@
+use std::io::{stdout, IsTerminal};
+
+use anyhow::{anyhow, Result};
+
+use crate::fs::{FsBlock, FS_REGION_BLOCKS};
+use crate::player::{Backend, Player};
+use crate::triage::fsck;
+
+// what the console's card should look like after this session's changes to it, so 'finish'
+// can check they all made it
+#[derive(Default)]
+pub struct PostState {
+    // whether anything has changed the card this session
+    pub dirty: bool,
+    // files that should be on the card (with their size, if known), and ones that shouldn't
+    present: Vec<(String, Option<u32>)>,
+    absent: Vec<String>,
+}
+
+impl PostState {
+    // a change that doesn't say anything about which files should be there
+    #[cfg(feature = "writing")]
+    pub fn changed(&mut self) {
+        self.dirty = true;
+    }
+
+    // raw block writes can replace the whole FS, so earlier expectations no longer hold
+    #[cfg(feature = "writing")]
+    pub fn wrote_blocks(&mut self) {
+        self.dirty = true;
+        self.present.clear();
+        self.absent.clear();
+    }
+
+    #[cfg(feature = "writing")]
+    fn forget(&mut self, name: &str) {
+        self.present.retain(|(n, _)| n != name);
+        self.absent.retain(|n| n != name);
+    }
+
+    #[cfg(feature = "writing")]
+    pub fn wrote_file(&mut self, name: &str, size: u32) {
+        self.dirty = true;
+        self.forget(name);
+        self.present.push((name.to_string(), Some(size)));
+    }
+
+    #[cfg(feature = "writing")]
+    pub fn deleted_file(&mut self, name: &str) {
+        self.dirty = true;
+        self.forget(name);
+        self.absent.push(name.to_string());
+    }
+
+    #[cfg(feature = "writing")]
+    pub fn renamed_file(&mut self, from: &str, to: &str) {
+        let size = self
+            .present
+            .iter()
+            .find(|(n, _)| n == from)
+            .and_then(|(_, s)| *s);
+        self.deleted_file(from);
+        self.forget(to);
+        self.present.push((to.to_string(), size));
+    }
+
+    // how the FS the console came back up with differs from what was expected; `newest_seqno`
+    // is the highest sequence number of any valid FS block in the FS region
+    pub fn problems(&self, fs: &FsBlock, newest_seqno: u32) -> Vec<String> {
+        let mut problems = vec![];
+        if fs.seqno != newest_seqno {
+            problems.push(format!(
+                "the console is using FS #{}, but the card has a newer one, #{newest_seqno}",
+                fs.seqno
+            ));
+        }
+        problems.extend(fsck(fs));
+        for (name, size) in &self.present {
+            match fs.find(name) {
+                None => problems.push(format!("{name} was written, but isn't on the card")),
+                Some(e) if size.is_some_and(|s| s != e.size) => problems.push(format!(
+                    "{name} was written with {} bytes, but the card has {}",
+                    size.unwrap_or_default(),
+                    e.size
+                )),
+                Some(_) => {}
+            }
+        }
+        for name in &self.absent {
+            if fs.find(name).is_some() {
+                problems.push(format!("{name} was deleted, but is still on the card"));
+            }
+        }
+        problems
+    }
+}
+
+// the highest sequence number of the valid FS blocks at the end of the card
+fn newest_seqno(player: &dyn Player) -> Result<u32> {
+    let stats = player.CardStats()?;
+    let num_blocks = stats.free + stats.used + stats.bad;
+    let start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u32);
+    (start..num_blocks)
+        .filter_map(|blk| FsBlock::parse(&player.ReadSingleBlock(blk).ok()?.0).ok())
+        .map(|fs| fs.seqno)
+        .max()
+        .ok_or_else(|| anyhow!("no valid FS blocks found on the card"))
+}
+
+// closes the connection so the console finishes writing, opens it again to read back the FS,
+// and closes it again if everything is as expected; returns whether it was
+pub fn finish(player: &mut dyn Backend, expected: &PostState) -> Result<bool> {
+    player.Close()?;
+    player.Init()?;
+    let fs = FsBlock::parse(&player.DumpCurrentFS()?)
+        .map_err(|e| anyhow!("the console's current FS doesn't parse: {e}"))?;
+    let problems = expected.problems(&fs, newest_seqno(player)?);
+    if !problems.is_empty() {
+        for p in &problems {
+            eprintln!("{p}");
+        }
+        eprintln!(
+            "The card isn't in the state this session left it in; the console is still open."
+        );
+        return Ok(false);
+    }
+    player.Close()?;
+    let message = format!("FS #{} checked; safe to disconnect", fs.seqno);
+    if stdout().is_terminal() {
+        println!("\x1b[32m{message}\x1b[0m");
+    } else {
+        println!("{message}");
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genimage::{generate, FileSpec, Pattern, Spec};
+    use crate::image::NandImage;
+    use crate::mount::MountedImage;
+    use anyhow::bail;
+
+    fn card(generations: usize) -> Result<MountedImage> {
+        let file = |name: &str| FileSpec {
+            name: name.into(),
+            pattern: Some(Pattern::Counting),
+            size: Some(0x100),
+            ..FileSpec::default()
+        };
+        let spec = Spec {
+            blocks: 0x100,
+            generations,
+            files: vec![file("GAME.app"), file("SAVE.sta")],
+            ..Spec::default()
+        };
+        let generated = generate(&spec, &|path| bail!("{path}: no local files"))?;
+        MountedImage::from_image(
+            NandImage::new(generated.nand, generated.spare)?,
+            "nand.bin",
+            "spare.bin",
+        )
+    }
+
+    #[test]
+    fn newest() -> Result<()> {
+        for generations in [1, 3] {
+            let card = card(generations)?;
+            let fs = FsBlock::parse(&card.DumpCurrentFS()?)?;
+            assert_eq!(newest_seqno(&card)?, fs.seqno);
+            assert!(PostState::default().problems(&fs, fs.seqno).is_empty());
+            assert_eq!(
+                PostState::default().problems(&fs, fs.seqno + 1),
+                [format!(
+                    "the console is using FS #{}, but the card has a newer one, #{}",
+                    fs.seqno,
+                    fs.seqno + 1
+                )]
+            );
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "writing")]
+    #[test]
+    fn expectations() -> Result<()> {
+        let card = card(1)?;
+        let fs = FsBlock::parse(&card.DumpCurrentFS()?)?;
+
+        // what this session did is all there
+        let mut state = PostState::default();
+        assert!(!state.dirty);
+        state.wrote_file("GAME.app", 0x100);
+        state.wrote_file("OLD.sta", 0x100);
+        state.renamed_file("OLD.sta", "SAVE.sta");
+        state.deleted_file("GONE.rec");
+        assert!(state.dirty);
+        assert!(state.problems(&fs, fs.seqno).is_empty());
+
+        // and what didn't make it is said
+        state.wrote_file("GAME.app", 0x200);
+        state.wrote_file("NEW.app", 0x100);
+        state.deleted_file("SAVE.sta");
+        assert_eq!(
+            state.problems(&fs, fs.seqno),
+            [
+                "GAME.app was written with 512 bytes, but the card has 256",
+                "NEW.app was written, but isn't on the card",
+                "SAVE.sta was deleted, but is still on the card",
+            ]
+        );
+
+        // a rename of a file of unknown size only expects the name; raw writes expect nothing
+        state.wrote_blocks();
+        state.renamed_file("UNKNOWN.app", "GAME.app");
+        assert!(state.problems(&fs, fs.seqno).is_empty());
+        state.wrote_blocks();
+        assert!(state.dirty && state.problems(&fs, fs.seqno).is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,425 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::fs::{
+    FsBlock, BLOCK_SIZE, FAT_BAD, FAT_FREE, FS_REGION_BLOCKS, SKSA_BLOCKS, SPARE_SIZE,
+};
+use crate::ranges::{block_ranges, format_range};
+use crate::relocate::{plan, Move};
+use crate::spare::{is_bad_block, synthesize_spare};
+
+// A dump of a card with bad blocks has its data laid out around them: its FAT marks them bad and
+// its files' chains skip them. Written as it is to a card whose bad blocks are elsewhere, files
+// land on that card's bad blocks, and its good ones stay marked bad. So before a write, the bad
+// blocks the image marks (in its spare data and its FAT) are compared with those the card's FAT
+// does, and if they differ, the write can leave out the blocks either marks bad, or move the
+// files off the card's bad blocks, using the image's FS to say where they went.
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Discrepancy {
+    // bad in the image, good on the card
+    pub source_only: Vec<u16>,
+    // good in the image, bad on the card
+    pub destination_only: Vec<u16>,
+}
+
+impl Discrepancy {
+    pub fn is_empty(&self) -> bool {
+        self.source_only.is_empty() && self.destination_only.is_empty()
+    }
+}
+
+pub struct Remap {
+    // what to write: the blocks asked for less the bad ones either way, and where data was moved
+    // to and the FS
+    pub ranges: Vec<Range<u16>>,
+    pub moves: Vec<Move>,
+    pub fs_block: u16,
+    pub seqno: u32,
+    // the image's bad blocks that are good on the card, free for files again
+    pub freed: Vec<u16>,
+}
+
+// the newest FS generation in an image, and the block it's in
+pub fn image_fs(nand: &[u8]) -> Option<(u16, FsBlock)> {
+    let num_blocks = nand.len() / BLOCK_SIZE;
+    (num_blocks.saturating_sub(FS_REGION_BLOCKS)..num_blocks)
+        .filter_map(|blk| {
+            let fs = FsBlock::parse(&nand[blk * BLOCK_SIZE..(blk + 1) * BLOCK_SIZE]).ok()?;
+            Some((blk as u16, fs))
+        })
+        .max_by_key(|(_, fs)| fs.seqno)
+}
+
+pub fn fat_bad(fs: &FsBlock, num_blocks: u16) -> BTreeSet<u16> {
+    (0..num_blocks.min(fs.fat.len() as u16))
+        .filter(|&b| fs.fat[b as usize] == FAT_BAD)
+        .collect()
+}
+
+// the blocks an image marks bad, in its FAT and in its spare data if it has any
+pub fn source_bad(nand: &[u8], spare: Option<&[u8]>) -> BTreeSet<u16> {
+    let num_blocks = (nand.len() / BLOCK_SIZE) as u16;
+    let mut bad = image_fs(nand)
+        .map(|(_, fs)| fat_bad(&fs, num_blocks))
+        .unwrap_or_default();
+    if let Some(spare) = spare {
+        bad.extend((0..num_blocks).filter(|&b| {
+            let b = b as usize;
+            spare
+                .get(b * SPARE_SIZE..(b + 1) * SPARE_SIZE)
+                .is_some_and(is_bad_block)
+        }));
+    }
+    bad
+}
+
+// the blocks in `ranges` that one side marks bad and the other doesn't
+pub fn compare_bad(
+    source: &BTreeSet<u16>,
+    destination: &BTreeSet<u16>,
+    ranges: &[Range<u16>],
+) -> Discrepancy {
+    let written = |b: &&u16| ranges.iter().any(|r| r.contains(*b));
+    Discrepancy {
+        source_only: source
+            .difference(destination)
+            .filter(written)
+            .copied()
+            .collect(),
+        destination_only: destination
+            .difference(source)
+            .filter(written)
+            .copied()
+            .collect(),
+    }
+}
+
+fn format_blocks(blocks: &[u16]) -> String {
+    block_ranges(blocks)
+        .iter()
+        .map(format_range)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// what the discrepancy would do to the card; `fs` is the image's, for which files are affected
+pub fn explain_bad(d: &Discrepancy, fs: Option<&FsBlock>) -> Vec<String> {
+    let mut lines = vec![
+        "The image's bad blocks aren't this card's, so its data is laid out around the wrong ones:"
+            .to_string(),
+    ];
+    if !d.source_only.is_empty() {
+        lines.push(format!(
+            "  bad in the image but good here ({}): they'd be written with whatever the image has in them, and stay marked bad",
+            format_blocks(&d.source_only)
+        ));
+    }
+    if !d.destination_only.is_empty() {
+        let files = fs.map_or(vec![], |fs| {
+            fs.entries
+                .iter()
+                .filter(|e| {
+                    fs.chain(e.start)
+                        .is_ok_and(|c| c.iter().any(|b| d.destination_only.contains(b)))
+                })
+                .map(|e| e.name.as_str())
+                .collect::<Vec<_>>()
+        });
+        lines.push(format!(
+            "  bad here but good in the image ({}): what's written to them would be lost{}",
+            format_blocks(&d.destination_only),
+            match files.is_empty() {
+                true => String::new(),
+                false => format!(", including part of {}", files.join(", ")),
+            }
+        ));
+    }
+    lines
+}
+
+// `ranges` without the blocks either side marks bad
+pub fn skip_bad(ranges: &[Range<u16>], d: &Discrepancy) -> Vec<Range<u16>> {
+    let blocks = ranges
+        .iter()
+        .flat_map(|r| r.clone())
+        .filter(|b| !d.source_only.contains(b) && !d.destination_only.contains(b))
+        .collect::<BTreeSet<_>>();
+    block_ranges(&blocks.into_iter().collect::<Vec<_>>())
+}
+
+// moves the image's files off the card's bad blocks, within the image (and its spare data, if
+// there is any), and writes the FS that results over the image's newest; the image's bad blocks
+// that are good on the card are freed for them to go to
+pub fn remap(
+    nand: &mut [u8],
+    mut spare: Option<&mut [u8]>,
+    d: &Discrepancy,
+    ranges: &[Range<u16>],
+) -> Result<Remap> {
+    let num_blocks = (nand.len() / BLOCK_SIZE) as u16;
+    let (fs_block, mut fs) = image_fs(nand)
+        .ok_or_else(|| anyhow!("the image has no valid FS to remap its files with"))?;
+    let data_area = SKSA_BLOCKS..num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    if let Some(blk) = d
+        .source_only
+        .iter()
+        .chain(&d.destination_only)
+        .find(|b| !data_area.contains(b))
+    {
+        bail!("block {blk:#X} is in the SKSA or FS region, which can't be remapped; use '--skip-foreign-bad' instead");
+    }
+
+    let mut freed = vec![];
+    for &blk in &d.source_only {
+        if fs.fat[blk as usize] == FAT_BAD {
+            fs.fat[blk as usize] = FAT_FREE;
+            freed.push(blk);
+        }
+    }
+    let planned = plan(&fs, &d.destination_only, num_blocks)?;
+    let block = |blk: u16| blk as usize * BLOCK_SIZE..(blk as usize + 1) * BLOCK_SIZE;
+    let spare_of = |blk: u16| blk as usize * SPARE_SIZE..(blk as usize + 1) * SPARE_SIZE;
+    for m in &planned.moves {
+        if let Some((_, to)) = &m.to {
+            nand.copy_within(block(m.from), block(*to).start);
+            if let Some(spare) = &mut spare {
+                spare.copy_within(spare_of(m.from), spare_of(*to).start);
+            }
+        }
+    }
+    let data = planned.fs.to_bytes()?;
+    nand[block(fs_block)].copy_from_slice(&data);
+    if let Some(spare) = &mut spare {
+        let fresh = synthesize_spare(&data, &spare[spare_of(fs_block)], false)?;
+        spare[spare_of(fs_block)].copy_from_slice(&fresh);
+    }
+
+    let mut blocks = skip_bad(ranges, d)
+        .into_iter()
+        .flatten()
+        .collect::<BTreeSet<_>>();
+    blocks.extend(
+        planned
+            .moves
+            .iter()
+            .filter_map(|m| m.to.as_ref().map(|(_, to)| *to)),
+    );
+    blocks.insert(fs_block);
+    Ok(Remap {
+        ranges: block_ranges(&blocks.into_iter().collect::<Vec<_>>()),
+        moves: planned.moves,
+        fs_block,
+        seqno: planned.fs.seqno,
+        freed,
+    })
+}
+
+pub fn describe_remap(remap: &Remap) -> Vec<String> {
+    let mut lines = remap
+        .moves
+        .iter()
+        .map(|m| match &m.to {
+            Some((file, to)) => format!("  {file}: block {:#X} moves to {to:#X}", m.from),
+            None => format!("  block {:#X} is marked bad", m.from),
+        })
+        .collect::<Vec<_>>();
+    if !remap.freed.is_empty() {
+        lines.push(format!(
+            "  {} are good here, so they're freed",
+            format_blocks(&remap.freed)
+        ));
+    }
+    lines.push(format!(
+        "  the FS is rewritten to match, as #{} in block {:#X}",
+        remap.seqno, remap.fs_block
+    ));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a dump of a card with bad blocks written to one with others, each way
+    #[test]
+    fn foreign_bad() -> Result<()> {
+        use crate::fs::FAT_END;
+        use crate::genimage::{generate, FileSpec, Pattern, Spec};
+        use crate::image::NandImage;
+        use crate::mount::MountedImage;
+        use crate::player::Player;
+        use crate::ranges::parse_ranges;
+        use crate::spare::ecc_matches;
+        use crate::triage::fsck;
+
+        let file = |name: &str, size| FileSpec {
+            name: name.into(),
+            pattern: Some(Pattern::Random),
+            size: Some(size),
+            ..FileSpec::default()
+        };
+        // GAME.app takes 0x40, 0x41 and 0x43, around the source's bad 0x42; SAVE.rec 0x44 and 0x45
+        let source = generate(
+            &Spec {
+                seed: 1,
+                blocks: 0x100,
+                bad_blocks: vec![0x42, 0x60],
+                files: vec![file("GAME.app", 0xA000), file("SAVE.rec", 0x5000)],
+                ..Spec::default()
+            },
+            &|path| bail!("{path}: no local files"),
+        )?;
+        let destination = generate(
+            &Spec {
+                seed: 2,
+                blocks: 0x100,
+                bad_blocks: vec![0x41, 0x60, 0x70],
+                ..Spec::default()
+            },
+            &|path| bail!("{path}: no local files"),
+        )?;
+        let game = source.manifest.files[0].chain.clone();
+        if game != [0x40, 0x41, 0x43] {
+            bail!("GAME.app was generated in {game:X?}");
+        }
+
+        // what each side marks bad, and where they differ
+        let (_, dest_fs) =
+            image_fs(&destination.nand).ok_or_else(|| anyhow!("no destination FS"))?;
+        let dest_bad = fat_bad(&dest_fs, 0x100);
+        let src_bad = source_bad(&source.nand, Some(&source.spare));
+        if src_bad != BTreeSet::from([0x42, 0x60])
+            || source_bad(&source.nand, None) != src_bad
+            || dest_bad != BTreeSet::from([0x41, 0x60, 0x70])
+        {
+            bail!("bad blocks were read as {src_bad:X?} and {dest_bad:X?}");
+        }
+        let all = std::iter::once(0..0x100).collect::<Vec<_>>();
+        let d = compare_bad(&src_bad, &dest_bad, &all);
+        if d.source_only != [0x42] || d.destination_only != [0x41, 0x70] {
+            bail!("the discrepancy was {d:X?}");
+        }
+        for (ranges, expected) in [
+            (
+                "0x50-0x80",
+                Discrepancy {
+                    source_only: vec![],
+                    destination_only: vec![0x70],
+                },
+            ),
+            ("0x60", Discrepancy::default()),
+            ("0-0x40,0x44-0x60", Discrepancy::default()),
+        ] {
+            let ranges = &parse_ranges(ranges, 0x100)?;
+            if compare_bad(&src_bad, &dest_bad, ranges) != expected {
+                bail!(
+                    "over {ranges:X?}, the discrepancy was {:X?}",
+                    compare_bad(&src_bad, &dest_bad, ranges)
+                );
+            }
+        }
+        let explained =
+            explain_bad(&d, image_fs(&source.nand).map(|(_, fs)| fs).as_ref()).join("\n");
+        if !explained.contains("(0x42)")
+            || !explained.contains("(0x41, 0x70)")
+            || !explained.contains("part of GAME.app")
+        {
+            bail!("the discrepancy was explained as\n{explained}");
+        }
+
+        // skipping leaves out the blocks either side marks bad, and nothing else
+        if skip_bad(&all, &d) != [0..0x41, 0x43..0x70, 0x71..0x100] {
+            bail!("skipping wrote {:X?}", skip_bad(&all, &d));
+        }
+
+        // remapping moves GAME.app off 0x41 into 0x42, which is good here, and marks 0x70 bad
+        let (mut nand, mut spare) = (source.nand.clone(), source.spare.clone());
+        let remapped = remap(&mut nand, Some(&mut spare), &d, &all)?;
+        let expected = [
+            Move {
+                from: 0x41,
+                to: Some(("GAME.app".to_string(), 0x42)),
+            },
+            Move {
+                from: 0x70,
+                to: None,
+            },
+        ];
+        if remapped.moves != expected || remapped.freed != [0x42] {
+            bail!(
+                "remapping planned {:X?}, freeing {:X?}",
+                remapped.moves,
+                remapped.freed
+            );
+        }
+        let written = remapped
+            .ranges
+            .iter()
+            .flat_map(|r| r.clone())
+            .collect::<BTreeSet<_>>();
+        if written.contains(&0x41)
+            || written.contains(&0x70)
+            || !written.contains(&0x42)
+            || !written.contains(&remapped.fs_block)
+        {
+            bail!("remapping wrote {:X?}", remapped.ranges);
+        }
+        if !ecc_matches(
+            &nand[0x42 * BLOCK_SIZE..0x43 * BLOCK_SIZE],
+            &spare[0x42 * SPARE_SIZE..0x43 * SPARE_SIZE],
+        ) {
+            bail!("the moved block's spare data doesn't match it");
+        }
+
+        // and written over the destination, the files read back whole, off its bad blocks
+        let (mut card, mut card_spare) = (destination.nand.clone(), destination.spare.clone());
+        for blk in &written {
+            let (b, s) = (*blk as usize * BLOCK_SIZE, *blk as usize * SPARE_SIZE);
+            card[b..b + BLOCK_SIZE].copy_from_slice(&nand[b..b + BLOCK_SIZE]);
+            card_spare[s..s + SPARE_SIZE].copy_from_slice(&spare[s..s + SPARE_SIZE]);
+        }
+        let original = MountedImage::from_image(
+            NandImage::new(source.nand.clone(), source.spare.clone())?,
+            "source",
+            "source.spare",
+        )?;
+        let image = NandImage::new(card, card_spare)?;
+        let (_, fs) = image
+            .current_fs()
+            .ok_or_else(|| anyhow!("the written card has no FS"))?;
+        let chain = fs.chain(
+            fs.find("GAME.app")
+                .ok_or_else(|| anyhow!("GAME.app is gone"))?
+                .start,
+        )?;
+        if chain != [0x40, 0x42, 0x43]
+            || !fsck(&fs).is_empty()
+            || fs.fat[0x41] != FAT_BAD
+            || fs.fat[0x70] != FAT_BAD
+            || fs.fat[0x43] != FAT_END
+        {
+            bail!("the written card's FS was {chain:X?}, {:?}", fsck(&fs));
+        }
+        let written_card = MountedImage::from_image(image, "card", "card.spare")?;
+        for name in ["GAME.app", "SAVE.rec"] {
+            if written_card.ReadFile(name)? != original.ReadFile(name)? {
+                bail!("{name} didn't read back from the written card as it was");
+            }
+        }
+
+        // the SKSA and FS region can't be remapped, nor an image without an FS
+        let sksa = Discrepancy {
+            source_only: vec![],
+            destination_only: vec![0x10],
+        };
+        if remap(&mut source.nand.clone(), None, &sksa, &all).is_ok()
+            || remap(&mut vec![0xFF; 0x100 * BLOCK_SIZE], None, &d, &all).is_ok()
+        {
+            bail!("an impossible remap was planned");
+        }
+        Ok(())
+    }
+}
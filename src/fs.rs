@@ -0,0 +1,535 @@
+use std::fmt::{self, Display};
+
+use anyhow::{anyhow, bail, Result};
+
+/// The data in a block: 32 pages of 512 bytes.
+pub const BLOCK_SIZE: usize = 0x4000;
+/// The spare data of a block, as the console's calls give it.
+pub const SPARE_SIZE: usize = 0x10;
+
+/// The SKSA occupies (at most) the first 64 blocks of the card.
+pub const SKSA_BLOCKS: u16 = 0x40;
+
+/// The filesystem lives in the last 16 blocks of the card, one generation per block.
+pub const FS_REGION_BLOCKS: usize = 0x10;
+
+/// The FAT's entries in an FS block, one per card block.
+pub const FAT_ENTRIES: usize = 0x1000;
+/// The largest card there is, 256 MiB.
+pub const MAX_CARD_BLOCKS: usize = 0x4000;
+/// The file entries in an FS block.
+pub const FS_FILE_COUNT: usize = 409;
+const FS_ENTRY_SIZE: usize = 0x14;
+const FS_ENTRIES_OFFSET: usize = FAT_ENTRIES * 2;
+const FS_FOOTER_OFFSET: usize = FS_ENTRIES_OFFSET + FS_FILE_COUNT * FS_ENTRY_SIZE;
+
+/// A FAT entry for a block no file uses.
+pub const FAT_FREE: u16 = 0x0000;
+/// A FAT entry for the last block of a file.
+pub const FAT_END: u16 = 0xFFFF;
+/// A FAT entry for a bad block.
+pub const FAT_BAD: u16 = 0xFFFE;
+/// A FAT entry for a block kept for the system (the SKSA's and the FS region's).
+pub const FAT_RESERVED: u16 = 0xFFFD;
+
+const FS_MAGIC: &[u8; 4] = b"BBFS";
+const FS_MAGIC_LINKED: &[u8; 4] = b"BBFL";
+const FS_CHECKSUM: u16 = 0xCAD7;
+
+/// A file's entry in an FS block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsEntry {
+    /// The 8.3 name, with its extension after a '.'.
+    pub name: String,
+    /// The file's first block; the FAT links it to the rest.
+    pub start: u16,
+    /// The file's size in bytes.
+    pub size: u32,
+    /// The rest of the entry, as it was read.
+    pub attrs: EntryAttrs,
+}
+
+/// The parts of an entry nothing here interprets, kept so that writing the entry back gives the
+/// same bytes: the 'valid' byte (only ever checked for zero), the two bytes after the start block,
+/// and whatever follows the NULs that end the name and extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryAttrs {
+    /// Nonzero for an entry in use.
+    pub valid: u8,
+    /// The two bytes after the start block.
+    pub reserved: [u8; 2],
+    /// What follows the NULs ending the name and extension, in the 11 bytes the two take.
+    pub slack: [u8; 11],
+}
+
+impl Default for EntryAttrs {
+    fn default() -> Self {
+        Self {
+            valid: 1,
+            reserved: [0; 2],
+            slack: [0; 11],
+        }
+    }
+}
+
+/// Where each of an entry's fields is, and what it is.
+pub const ENTRY_FIELDS: &[(usize, usize, &str)] = &[
+    (0x00, 8, "name"),
+    (0x08, 3, "extension"),
+    (0x0B, 1, "valid"),
+    (0x0C, 2, "start block"),
+    (0x0E, 2, "unknown"),
+    (0x10, 4, "size"),
+];
+
+impl FsEntry {
+    /// A new file's entry, with the attributes the console gives one.
+    pub fn new(name: &str, start: u16, size: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            start,
+            size,
+            attrs: EntryAttrs::default(),
+        }
+    }
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data[11] == 0 {
+            return None;
+        }
+        let name = trim_name(&data[0..8]);
+        let ext = trim_name(&data[8..11]);
+        let mut slack = [0; 11];
+        for (field, len) in [(0..8, name.len()), (8..11, ext.len())] {
+            let tail = field.start + len..field.end;
+            slack[tail.clone()].copy_from_slice(&data[tail]);
+        }
+        Some(Self {
+            name: if ext.is_empty() {
+                name
+            } else {
+                format!("{name}.{ext}")
+            },
+            start: u16::from_be_bytes([data[12], data[13]]),
+            size: u32::from_be_bytes([data[16], data[17], data[18], data[19]]),
+            attrs: EntryAttrs {
+                valid: data[11],
+                reserved: [data[14], data[15]],
+                slack,
+            },
+        })
+    }
+
+    /// How many blocks the file's data takes.
+    pub fn blocks(&self) -> usize {
+        (self.size as usize).div_ceil(BLOCK_SIZE)
+    }
+
+    /// Whether `name` can be an entry's: an 8.3 name.
+    pub fn fits(name: &str) -> bool {
+        let (name, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+        !name.is_empty() && name.len() <= 8 && ext.len() <= 3
+    }
+
+    fn write(&self, out: &mut [u8]) -> Result<()> {
+        if !Self::fits(&self.name) {
+            bail!("'{}' doesn't fit in an 8.3 name", self.name);
+        }
+        let (name, ext) = self.name.rsplit_once('.').unwrap_or((&self.name, ""));
+        out.fill(0);
+        out[..11].copy_from_slice(&self.attrs.slack);
+        for (field, text) in [(0..8, name), (8..11, ext)] {
+            out[field.start..field.start + text.len()].copy_from_slice(text.as_bytes());
+            // a renamed file's name can run into the old one's slack, which mustn't lengthen it
+            if text.len() < field.len() {
+                out[field.start + text.len()] = 0;
+            }
+        }
+        out[11] = self.attrs.valid;
+        out[12..14].copy_from_slice(&self.start.to_be_bytes());
+        out[14..16].copy_from_slice(&self.attrs.reserved);
+        out[16..20].copy_from_slice(&self.size.to_be_bytes());
+        Ok(())
+    }
+
+    /// The entry as it's stored in the FS block.
+    pub fn to_raw(&self) -> Result<[u8; FS_ENTRY_SIZE]> {
+        let mut raw = [0; FS_ENTRY_SIZE];
+        self.write(&mut raw)?;
+        Ok(raw)
+    }
+}
+
+/// Every field of an entry, known or not, with its offset and bytes, for 'stat'.
+pub fn stat_lines(entry: &FsEntry) -> Result<Vec<String>> {
+    let raw = entry.to_raw()?;
+    let mut lines = vec![entry.name.clone()];
+    for &(offset, len, field) in ENTRY_FIELDS {
+        let bytes = &raw[offset..offset + len];
+        let hex = bytes
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let meaning = match field {
+            "name" | "extension" => format!("{:?}", trim_name(bytes)),
+            "start block" => format!("{:#X}", entry.start),
+            "size" => format!("{} bytes, {} blocks", entry.size, entry.blocks()),
+            _ => String::new(),
+        };
+        let line = format!("  {offset:#04X}  {field:<11}  {hex:<23}  {meaning}");
+        lines.push(line.trim_end().to_string());
+    }
+    if entry.attrs.slack.iter().any(|&b| b != 0) {
+        lines.push("  (the name has bytes after its end, shown above)".to_string());
+    }
+    Ok(lines)
+}
+
+fn trim_name(raw: &[u8]) -> String {
+    let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..end]).into_owned()
+}
+
+/// One generation of the filesystem: a FAT and the files' entries.
+#[derive(Debug, Clone)]
+pub struct FsBlock {
+    /// The FAT: for each block, the next block of its file, or one of the `FAT_` values.
+    pub fat: Vec<u16>,
+    /// The entries in use.
+    pub entries: Vec<FsEntry>,
+    /// Whether the FAT goes on into further blocks ("BBFL" rather than "BBFS"), which aren't read.
+    pub linked: bool,
+    /// The generation's sequence number; the console uses the valid block with the highest.
+    pub seqno: u32,
+}
+
+/// Why a block isn't a valid FS block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    /// It isn't a block's size; the size it is.
+    WrongSize(usize),
+    /// Its footer doesn't start "BBFS" or "BBFL"; what it starts with.
+    BadMagic([u8; 4]),
+    /// Its 16-bit words don't add up to the checksum; what they add up to.
+    BadChecksum(u16),
+}
+
+impl Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongSize(s) => write!(f, "FS block is {s:#X} bytes, expected {BLOCK_SIZE:#X}"),
+            Self::BadMagic(m) => write!(f, "bad FS magic {m:02X?}"),
+            Self::BadChecksum(c) => {
+                write!(
+                    f,
+                    "bad FS checksum (sum {c:04X}, expected {FS_CHECKSUM:04X})"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for FsError {}
+
+impl FsBlock {
+    /// Reads an FS block, checking its size, magic and checksum.
+    pub fn parse(data: &[u8]) -> Result<Self, FsError> {
+        if data.len() != BLOCK_SIZE {
+            return Err(FsError::WrongSize(data.len()));
+        }
+
+        let footer = &data[FS_FOOTER_OFFSET..];
+        let magic = [footer[0], footer[1], footer[2], footer[3]];
+        let linked = match &magic {
+            FS_MAGIC => false,
+            FS_MAGIC_LINKED => true,
+            _ => return Err(FsError::BadMagic(magic)),
+        };
+
+        let sum = data.chunks_exact(2).fold(0u16, |acc, w| {
+            acc.wrapping_add(u16::from_be_bytes([w[0], w[1]]))
+        });
+        if sum != FS_CHECKSUM {
+            return Err(FsError::BadChecksum(sum));
+        }
+
+        let fat = data[..FS_ENTRIES_OFFSET]
+            .chunks_exact(2)
+            .map(|w| u16::from_be_bytes([w[0], w[1]]))
+            .collect();
+        let entries = data[FS_ENTRIES_OFFSET..FS_FOOTER_OFFSET]
+            .chunks_exact(FS_ENTRY_SIZE)
+            .filter_map(FsEntry::parse)
+            .collect();
+
+        Ok(Self {
+            fat,
+            entries,
+            linked,
+            seqno: u32::from_be_bytes([footer[4], footer[5], footer[6], footer[7]]),
+        })
+    }
+
+    /// The inverse of 'parse', always as a single unlinked block.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        if self.linked {
+            bail!("multi-block FATs aren't supported");
+        }
+        if self.entries.len() > FS_FILE_COUNT {
+            bail!(
+                "too many files ({}, at most {FS_FILE_COUNT})",
+                self.entries.len()
+            );
+        }
+
+        let mut data = vec![0; BLOCK_SIZE];
+        for (i, v) in self.fat.iter().enumerate() {
+            data[i * 2..i * 2 + 2].copy_from_slice(&v.to_be_bytes());
+        }
+        for (entry, out) in self
+            .entries
+            .iter()
+            .zip(data[FS_ENTRIES_OFFSET..FS_FOOTER_OFFSET].chunks_exact_mut(FS_ENTRY_SIZE))
+        {
+            entry.write(out)?;
+        }
+
+        let footer = &mut data[FS_FOOTER_OFFSET..];
+        footer[0..4].copy_from_slice(FS_MAGIC);
+        footer[4..8].copy_from_slice(&self.seqno.to_be_bytes());
+        let sum = data[..BLOCK_SIZE - 2].chunks_exact(2).fold(0u16, |acc, w| {
+            acc.wrapping_add(u16::from_be_bytes([w[0], w[1]]))
+        });
+        data[BLOCK_SIZE - 2..].copy_from_slice(&FS_CHECKSUM.wrapping_sub(sum).to_be_bytes());
+        Ok(data)
+    }
+
+    /// The entry for the file called `name`.
+    pub fn find(&self, name: &str) -> Option<&FsEntry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    /// The most a file could hold: every block the FAT covers, or for a linked FAT (only the first
+    /// block of which is parsed), the largest card.
+    pub fn capacity(&self) -> u64 {
+        let blocks = match self.linked {
+            true => MAX_CARD_BLOCKS,
+            false => self.fat.len(),
+        };
+        (blocks * BLOCK_SIZE) as u64
+    }
+
+    /// Entries claiming more than the card holds, which only a corrupted entry can.
+    pub fn suspect(&self) -> impl Iterator<Item = &FsEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.size as u64 > self.capacity())
+    }
+
+    /// Marks a file's blocks as free again.
+    #[cfg(feature = "writing")]
+    pub fn free_chain(&mut self, start: u16) -> Result<()> {
+        for blk in self.chain(start)? {
+            self.fat[blk as usize] = FAT_FREE;
+        }
+        Ok(())
+    }
+
+    /// Links `count` free blocks, chosen from those `usable` allows, into a new chain.
+    #[cfg(feature = "writing")]
+    pub fn allocate(&mut self, count: usize, usable: impl Fn(u16) -> bool) -> Result<Vec<u16>> {
+        let blocks = (0..self.fat.len() as u16)
+            .filter(|&b| self.fat[b as usize] == FAT_FREE && usable(b))
+            .take(count)
+            .collect::<Vec<_>>();
+        if blocks.len() < count {
+            bail!(
+                "needs {count} free blocks, but only {} are free",
+                blocks.len()
+            );
+        }
+        for (i, &blk) in blocks.iter().enumerate() {
+            self.fat[blk as usize] = blocks.get(i + 1).copied().unwrap_or(FAT_END);
+        }
+        Ok(blocks)
+    }
+
+    /// The blocks of the file starting at `start`, in order; fails if the chain leaves the FAT,
+    /// loops or runs into a block that isn't a file's.
+    pub fn chain(&self, start: u16) -> Result<Vec<u16>> {
+        let mut chain = vec![];
+        let mut block = start;
+        loop {
+            match self.fat.get(block as usize) {
+                None => bail!("chain leaves the FAT at block {block:#X}"),
+                Some(_) if chain.contains(&block) => bail!("chain loops at block {block:#X}"),
+                Some(&next) => {
+                    chain.push(block);
+                    match next {
+                        FAT_END => return Ok(chain),
+                        FAT_FREE | FAT_BAD | FAT_RESERVED => {
+                            return Err(anyhow!(
+                                "chain runs into a non-file block ({block:#X} -> {next:04X})"
+                            ))
+                        }
+                        _ => block = next,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A valid FS block holding one file, TEST.sys, for the self-tests.
+pub fn synthetic_block() -> Vec<u8> {
+    synthetic_chain(&[0x40, 0x41])
+}
+
+/// The same, with TEST.sys in the blocks of `chain`, in order.
+pub fn synthetic_chain(chain: &[u16]) -> Vec<u8> {
+    synthetic_generation(chain, 7)
+}
+
+/// The same again, as FS #`seqno`.
+pub fn synthetic_generation(chain: &[u16], seqno: u32) -> Vec<u8> {
+    let mut data = vec![0; BLOCK_SIZE];
+    let links = chain.iter().zip(chain[1..].iter().chain([&FAT_END]));
+    for (&blk, &next) in links.chain([(&0x10, &FAT_BAD)]) {
+        let blk = blk as usize;
+        data[blk * 2..blk * 2 + 2].copy_from_slice(&next.to_be_bytes());
+    }
+    let entry = &mut data[FS_ENTRIES_OFFSET..FS_ENTRIES_OFFSET + FS_ENTRY_SIZE];
+    entry[..4].copy_from_slice(b"TEST");
+    entry[8..11].copy_from_slice(b"sys");
+    entry[11] = 1;
+    entry[12..14].copy_from_slice(&chain[0].to_be_bytes());
+    entry[16..20].copy_from_slice(&0x6000u32.to_be_bytes());
+    data[FS_FOOTER_OFFSET..FS_FOOTER_OFFSET + 4].copy_from_slice(FS_MAGIC);
+    data[FS_FOOTER_OFFSET + 4..FS_FOOTER_OFFSET + 8].copy_from_slice(&seqno.to_be_bytes());
+    let sum = data.chunks_exact(2).fold(0u16, |acc, w| {
+        acc.wrapping_add(u16::from_be_bytes([w[0], w[1]]))
+    });
+    data[BLOCK_SIZE - 2..].copy_from_slice(&FS_CHECKSUM.wrapping_sub(sum).to_be_bytes());
+    data
+}
+
+/// Parses a synthetic block with known contents (and, where it can, writes it back out).
+pub(crate) fn self_test() -> Result<()> {
+    let mut data = synthetic_block();
+    let fs = FsBlock::parse(&data).map_err(|e| anyhow!("synthetic block: {e}"))?;
+    let expected = FsEntry::new("TEST.sys", 0x40, 0x6000);
+    if fs.entries != [expected] || fs.seqno != 7 || fs.linked {
+        bail!("synthetic block parsed incorrectly: {fs:?}");
+    }
+    if fs.chain(0x40)? != [0x40, 0x41] || fs.fat[0x10] != FAT_BAD {
+        bail!("synthetic block's FAT parsed incorrectly");
+    }
+    #[cfg(feature = "writing")]
+    if fs.to_bytes()? != data {
+        bail!("synthetic block didn't round-trip");
+    }
+
+    // entries' unknown bytes survive a change to another file: TEST.sys gets odd attributes and
+    // a neighbour, OTHER.dat, which is then renamed and moved
+    let mut odd = data.clone();
+    let test = FS_ENTRIES_OFFSET;
+    let other = FS_ENTRIES_OFFSET + FS_ENTRY_SIZE;
+    odd[test + 5] = 0x7E;
+    odd[test + 11] = 0x81;
+    odd[test + 14..test + 16].copy_from_slice(&[0xAB, 0xCD]);
+    odd[other..other + 5].copy_from_slice(b"OTHER");
+    odd[other + 8..other + 11].copy_from_slice(b"dat");
+    odd[other + 11] = 1;
+    odd[other + 12..other + 14].copy_from_slice(&0x42u16.to_be_bytes());
+    odd[other + 16..other + 20].copy_from_slice(&0x10u32.to_be_bytes());
+    odd[0x84..0x86].copy_from_slice(&FAT_END.to_be_bytes());
+    odd[BLOCK_SIZE - 2..].fill(0);
+    let sum = odd.chunks_exact(2).fold(0u16, |acc, w| {
+        acc.wrapping_add(u16::from_be_bytes([w[0], w[1]]))
+    });
+    odd[BLOCK_SIZE - 2..].copy_from_slice(&FS_CHECKSUM.wrapping_sub(sum).to_be_bytes());
+
+    let mut fs = FsBlock::parse(&odd).map_err(|e| anyhow!("odd block: {e}"))?;
+    let attrs = fs.entries[0].attrs;
+    if attrs.valid != 0x81 || attrs.reserved != [0xAB, 0xCD] || attrs.slack[5] != 0x7E {
+        bail!("an entry's attributes parsed as {attrs:?}");
+    }
+    if fs.to_bytes()? != odd {
+        bail!("a block with odd attributes didn't round-trip");
+    }
+    fs.entries[1].name = "MOVED.bin".to_string();
+    fs.entries[1].start = 0x43;
+    let changed = fs.to_bytes()?;
+    if changed[test..test + FS_ENTRY_SIZE] != odd[test..test + FS_ENTRY_SIZE] {
+        bail!("changing OTHER.dat changed TEST.sys's entry");
+    }
+    let reparsed = FsBlock::parse(&changed).map_err(|e| anyhow!("changed block: {e}"))?;
+    if reparsed.entries != fs.entries {
+        bail!("the changed block parsed as {:?}", reparsed.entries);
+    }
+
+    // a longer name over the slack isn't lengthened by it, and a shorter one is still ended
+    fs.entries[0].name = "TESTING.sys".to_string();
+    fs.entries[1].name = "M.bin".to_string();
+    let renamed = FsBlock::parse(&fs.to_bytes()?).map_err(|e| anyhow!("renamed block: {e}"))?;
+    let names = renamed
+        .entries
+        .iter()
+        .map(|e| e.name.as_str())
+        .collect::<Vec<_>>();
+    if names != ["TESTING.sys", "M.bin"] {
+        bail!("renamed entries read back as {names:?}");
+    }
+
+    let lines = stat_lines(&reparsed.entries[0])?;
+    let expected = [
+        "TEST.sys",
+        "  0x00  name         54 45 53 54 00 7E 00 00  \"TEST\"",
+        "  0x08  extension    73 79 73                 \"sys\"",
+        "  0x0B  valid        81",
+        "  0x0C  start block  00 40                    0x40",
+        "  0x0E  unknown      AB CD",
+        "  0x10  size         00 00 60 00              24576 bytes, 2 blocks",
+        "  (the name has bytes after its end, shown above)",
+    ];
+    if lines != expected {
+        bail!("'stat' printed {lines:#?}");
+    }
+
+    // an entry claiming more than any card holds parses, but as suspect, and fsck says why
+    // rather than complaining about its chain
+    let mut corrupt = synthetic_block();
+    corrupt[test + 16..test + 20].copy_from_slice(&u32::MAX.to_be_bytes());
+    corrupt[BLOCK_SIZE - 2..].fill(0);
+    let sum = corrupt.chunks_exact(2).fold(0u16, |acc, w| {
+        acc.wrapping_add(u16::from_be_bytes([w[0], w[1]]))
+    });
+    corrupt[BLOCK_SIZE - 2..].copy_from_slice(&FS_CHECKSUM.wrapping_sub(sum).to_be_bytes());
+    let corrupt = FsBlock::parse(&corrupt).map_err(|e| anyhow!("corrupt block: {e}"))?;
+    let suspect = corrupt
+        .suspect()
+        .map(|e| e.name.as_str())
+        .collect::<Vec<_>>();
+    let problems = crate::triage::fsck(&corrupt);
+    if suspect != ["TEST.sys"]
+        || problems != ["TEST.sys: claims 4294967295 bytes, more than the card's 64 MiB; the entry is corrupt"]
+        || fs.suspect().next().is_some()
+    {
+        bail!("the corrupt entry was suspected as {suspect:?}, and fsck found {problems:?}");
+    }
+
+    data[0] ^= 1;
+    if FsBlock::parse(&data).is_ok() {
+        bail!("a block with a bad checksum was accepted");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn vectors() -> anyhow::Result<()> {
+        super::self_test()
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::{anyhow, Result};
+
+use crate::fs::FsBlock;
+use crate::player::Player;
+
+// The console's FS block as last fetched, so listing the files doesn't mean fetching and parsing
+// the whole block every time. Every change to the card writes a new FS generation with a higher
+// sequence number, which CardStats reports cheaply, so the cached block is used for as long as
+// the card's number is the one it was parsed with. It's also dropped whenever this session
+// changes the card or picks another console, whose number could happen to match.
+
+#[derive(Default)]
+pub struct FsCache {
+    fs: Option<FsBlock>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl FsCache {
+    pub fn invalidate(&mut self) {
+        self.fs = None;
+    }
+
+    // the sequence number of the cached block, if there is one
+    pub fn seqno(&self) -> Option<u32> {
+        self.fs.as_ref().map(|fs| fs.seqno)
+    }
+
+    // the console's current FS, from the cache if the card's sequence number still matches it
+    pub fn fs(&mut self, player: &dyn Player) -> Result<&FsBlock> {
+        let seqno = player.CardStats()?.seqno;
+        if self.seqno() == Some(seqno) {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+            self.fs = None;
+            let fs = FsBlock::parse(&player.DumpCurrentFS()?)
+                .map_err(|e| anyhow!("the console's current FS doesn't parse: {e}"))?;
+            // keyed by the number in the block itself, in case the card changed in between
+            self.fs = Some(fs);
+        }
+        self.fs
+            .as_ref()
+            .ok_or_else(|| anyhow!("the FS cache is empty"))
+    }
+
+    // the names and sizes of the files, as ListFiles gives them
+    pub fn files(&mut self, player: &dyn Player) -> Result<Vec<(String, u32)>> {
+        Ok(self
+            .fs(player)?
+            .entries
+            .iter()
+            .map(|e| (e.name.clone(), e.size))
+            .collect())
+    }
+
+    // a mounted dump's FS is in memory already, so only a console's goes through the cache
+    pub fn current_fs(&mut self, player: &dyn Player, mounted: bool) -> Result<FsBlock> {
+        if mounted {
+            return Ok(FsBlock::parse(&player.DumpCurrentFS()?)?);
+        }
+        self.fs(player).cloned()
+    }
+
+    pub fn list_files(&mut self, player: &dyn Player, mounted: bool) -> Result<Vec<(String, u32)>> {
+        if mounted {
+            return player.ListFiles();
+        }
+        self.files(player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    #[test]
+    fn fs_cache() -> Result<()> {
+        use std::cell::{Cell, RefCell};
+
+        use bbrdb::CardStats;
+
+        use crate::fs::{synthetic_block, synthetic_generation};
+
+        // a console whose card's FS block and sequence number can be changed under the cache
+        struct Card {
+            stats_seqno: Cell<u32>,
+            block: RefCell<Vec<u8>>,
+            fetches: Cell<u32>,
+        }
+
+        impl Player for Card {
+            fn GetBBID(&self) -> Result<u32> {
+                Ok(1)
+            }
+            fn SetLED(&self, _value: u32) -> Result<()> {
+                Ok(())
+            }
+            fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+                bail!("the cache should list the files itself")
+            }
+            fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+                self.fetches.set(self.fetches.get() + 1);
+                Ok(self.block.borrow().clone())
+            }
+            fn ReadFile(&self, _name: &str) -> Result<Option<Vec<u8>>> {
+                Ok(None)
+            }
+            fn ReadSingleBlock(&self, _blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+                bail!("not used")
+            }
+            fn CardStats(&self) -> Result<CardStats> {
+                Ok(CardStats {
+                    free: 0,
+                    used: 0,
+                    bad: 0,
+                    seqno: self.stats_seqno.get(),
+                })
+            }
+        }
+
+        // the synthetic blocks are FS #7
+        let card = Card {
+            stats_seqno: Cell::new(7),
+            block: RefCell::new(synthetic_block()),
+            fetches: Cell::new(0),
+        };
+        let mut cache = FsCache::default();
+        let expected = vec![("TEST.sys".to_string(), 0x6000)];
+
+        if cache.files(&card)? != expected || cache.files(&card)? != expected {
+            bail!("the listing was wrong");
+        }
+        if card.fetches.get() != 1 || (cache.hits, cache.misses) != (1, 1) {
+            bail!(
+                "two listings of an unchanged card fetched the FS {} times ({} hits, {} misses)",
+                card.fetches.get(),
+                cache.hits,
+                cache.misses
+            );
+        }
+
+        // a new generation: the next listing fetches it
+        *card.block.borrow_mut() = synthetic_generation(&[0x50], 8);
+        card.stats_seqno.set(8);
+        let fs = cache.fs(&card)?;
+        if fs.chain(fs.entries[0].start)? != [0x50] || card.fetches.get() != 2 {
+            bail!("a card with a new sequence number was served from the cache");
+        }
+
+        // a different card (or an older generation) whose number doesn't match isn't served either,
+        // even though its number is lower
+        *card.block.borrow_mut() = synthetic_block();
+        card.stats_seqno.set(7);
+        cache.files(&card)?;
+        if card.fetches.get() != 3 {
+            bail!("a card whose sequence number went backwards was served from the cache");
+        }
+
+        // the block fetched is keyed by its own number: here the card changed between CardStats and
+        // the fetch, so the block's #7 doesn't match the #9 just seen, and the next call fetches again
+        card.stats_seqno.set(9);
+        cache.files(&card)?;
+        cache.files(&card)?;
+        if card.fetches.get() != 5 {
+            bail!("a block fetched as the card changed was cached under the wrong number");
+        }
+
+        // a local change drops the cache even though the number hasn't been seen to move
+        card.stats_seqno.set(7);
+        cache.files(&card)?;
+        let fetches = card.fetches.get();
+        cache.invalidate();
+        cache.files(&card)?;
+        if card.fetches.get() != fetches + 1 || cache.seqno() != Some(7) {
+            bail!("invalidating the cache didn't make the next listing fetch the FS");
+        }
+
+        // a block that doesn't parse isn't cached, so it isn't served later either
+        *card.block.borrow_mut() = vec![0; 0x10];
+        card.stats_seqno.set(10);
+        if cache.files(&card).is_ok() || cache.seqno().is_some() {
+            bail!("a block that doesn't parse was accepted");
+        }
+        Ok(())
+    }
+}
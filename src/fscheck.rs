@@ -0,0 +1,244 @@
+use std::ops::Range;
+
+use anyhow::{bail, Result};
+
+use crate::fs::{FsBlock, BLOCK_SIZE, FS_REGION_BLOCKS};
+use crate::player::Player;
+use crate::triage::fsck;
+
+// 'set post-write-fscheck': a write that reaches into the FS region can report success and still
+// leave an FS the console can't use, which is found out days later when the card won't boot. So
+// after one, the FS region is read back and its newest generation checked: that it parses, that
+// it's the generation the write put there when that's known, that fsck finds nothing wrong with
+// it, and that its files can be listed.
+
+fn fs_region(card_blocks: u16) -> Range<u16> {
+    card_blocks.saturating_sub(FS_REGION_BLOCKS as u16)..card_blocks
+}
+
+// whether a write of `ranges` touches the FS region of a card of `card_blocks` blocks
+pub fn overlaps_fs(ranges: &[Range<u16>], card_blocks: u16) -> bool {
+    let fs = fs_region(card_blocks);
+    ranges
+        .iter()
+        .any(|r| !r.is_empty() && r.start < fs.end && fs.start < r.end)
+}
+
+// the FS generation a write of `nand` over `ranges` leaves newest: only known if it wrote the
+// whole FS region, as otherwise a block it didn't write may hold a newer one
+pub fn expected_seqno(nand: &[u8], ranges: &[Range<u16>], card_blocks: u16) -> Option<u32> {
+    let fs = fs_region(card_blocks);
+    if !fs.clone().all(|b| ranges.iter().any(|r| r.contains(&b))) {
+        return None;
+    }
+    fs.filter_map(|b| {
+        let b = b as usize;
+        FsBlock::parse(nand.get(b * BLOCK_SIZE..(b + 1) * BLOCK_SIZE)?).ok()
+    })
+    .map(|fs| fs.seqno)
+    .max()
+}
+
+#[derive(Debug)]
+pub struct Checked {
+    pub seqno: u32,
+    pub files: usize,
+}
+
+// reads the FS region back from the card and checks its newest generation
+pub fn check_fs(
+    player: &dyn Player,
+    card_blocks: u16,
+    expected_seqno: Option<u32>,
+) -> Result<Checked> {
+    let mut unreadable = 0;
+    let newest = fs_region(card_blocks)
+        .filter_map(|blk| match player.ReadSingleBlock(blk as u32) {
+            Ok((data, _)) => FsBlock::parse(&data).ok(),
+            Err(_) => {
+                unreadable += 1;
+                None
+            }
+        })
+        .max_by_key(|fs| fs.seqno);
+    let Some(fs) = newest else {
+        bail!(
+            "no block of the FS region holds an FS that parses{}",
+            match unreadable {
+                0 => String::new(),
+                n => format!(" ({n} of them couldn't be read)"),
+            }
+        );
+    };
+    let mut problems = vec![];
+    if let Some(expected) = expected_seqno.filter(|&e| e != fs.seqno) {
+        problems.push(format!(
+            "the newest FS is #{}, not the #{expected} that was written",
+            fs.seqno
+        ));
+    }
+    problems.extend(fsck(&fs));
+    // the listing: every file's name, and the chain its size is read from
+    let files = fs
+        .entries
+        .iter()
+        .filter(|e| fs.chain(e.start).is_ok() && !e.name.is_empty())
+        .count();
+    if files != fs.entries.len() {
+        problems.push(format!(
+            "only {files} of its {} files could be listed",
+            fs.entries.len()
+        ));
+    }
+    if !problems.is_empty() {
+        bail!("FS #{}: {}", fs.seqno, problems.join("; "));
+    }
+    Ok(Checked {
+        seqno: fs.seqno,
+        files,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fs_write_check() -> Result<()> {
+        use std::cell::RefCell;
+
+        use bbrdb::CardStats;
+
+        use crate::fs::{FsEntry, FAT_END, FAT_ENTRIES, FAT_FREE, SPARE_SIZE};
+
+        // the trigger: ranges reaching into the last 16 blocks of each size of card, and no others
+        for card in [0x1000u16, 0x2000] {
+            let last = card - 1;
+            let cases = [
+                (0..card, true),
+                (0..0x40, false),
+                (0x40..card - 0x10, false),
+                (0x40..card - 0x0F, true),
+                (last..card, true),
+                (card - 0x10..card - 0x10, false),
+            ];
+            for (range, expected) in cases {
+                if overlaps_fs(std::slice::from_ref(&range), card) != expected {
+                    bail!("{range:X?} on a {card:#X}-block card overlapping the FS region wasn't {expected}");
+                }
+            }
+            if !overlaps_fs(&[0..0x40, card - 0x10..card - 0x0F], card) || overlaps_fs(&[], card) {
+                bail!(
+                    "a write of several ranges or none was checked wrongly against the FS region"
+                );
+            }
+        }
+        // a 64MiB card's FS region is in the middle of a 128MiB one's data
+        if overlaps_fs(std::slice::from_ref(&(0xFF0..0x1000)), 0x2000) {
+            bail!("the smaller card's FS region counted on the larger card");
+        }
+
+        // a card's FS region, with a file in blocks 0x40-0x41
+        let fs_at = |seqno| -> Result<Vec<u8>> {
+            let mut fat = vec![FAT_FREE; FAT_ENTRIES];
+            fat[0x40] = 0x41;
+            fat[0x41] = FAT_END;
+            FsBlock {
+                fat,
+                entries: vec![FsEntry::new("GAME.app", 0x40, 2 * BLOCK_SIZE as u32)],
+                linked: false,
+                seqno,
+            }
+            .to_bytes()
+        };
+        struct Card {
+            region: RefCell<Vec<Vec<u8>>>,
+        }
+        impl Card {
+            // a write to the card, which with `corrupt` doesn't all make it
+            fn write(&self, blk: usize, mut data: Vec<u8>, corrupt: bool) {
+                if corrupt {
+                    data[0x2000..0x2100].fill(0);
+                }
+                self.region.borrow_mut()[blk] = data;
+            }
+        }
+        impl Player for Card {
+            fn GetBBID(&self) -> Result<u32> {
+                Ok(0x1234)
+            }
+            fn SetLED(&self, _: u32) -> Result<()> {
+                Ok(())
+            }
+            fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+                bail!("the check should list the files from the FS it read")
+            }
+            fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+                bail!("the check should read the FS region, not the console's idea of it")
+            }
+            fn ReadFile(&self, _: &str) -> Result<Option<Vec<u8>>> {
+                Ok(None)
+            }
+            fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+                let Some(i) = (blk as usize).checked_sub(0xFF0) else {
+                    bail!("block {blk:#X} isn't in the FS region");
+                };
+                Ok((self.region.borrow()[i].clone(), vec![0xFF; SPARE_SIZE]))
+            }
+            fn CardStats(&self) -> Result<CardStats> {
+                Ok(CardStats {
+                    free: 0x1000 - 2,
+                    used: 2,
+                    bad: 0,
+                    seqno: 0,
+                })
+            }
+        }
+        let card = Card {
+            region: RefCell::new(vec![vec![0xFF; BLOCK_SIZE]; FS_REGION_BLOCKS]),
+        };
+        card.write(0, fs_at(5)?, false);
+
+        // a write that lands: its generation is the newest, and it's fine
+        card.write(1, fs_at(6)?, false);
+        let checked = check_fs(&card, 0x1000, Some(6))?;
+        if checked.seqno != 6 || checked.files != 1 {
+            bail!("a good write checked as {checked:?}");
+        }
+        // a write that corrupts the block it put the FS in: the console would fall back to the old
+        // generation, which isn't the one written
+        card.write(2, fs_at(7)?, true);
+        match check_fs(&card, 0x1000, Some(7)) {
+            Err(e) if e.to_string().contains("newest FS is #6, not the #7") => {}
+            other => bail!("a corrupted FS write checked as {other:?}"),
+        }
+        // an FS that parses but whose file's chain is broken
+        let mut broken = FsBlock::parse(&fs_at(8)?)?;
+        broken.fat[0x41] = FAT_FREE;
+        card.write(3, broken.to_bytes()?, false);
+        match check_fs(&card, 0x1000, Some(8)) {
+            Err(e) if e.to_string().contains("GAME.app") => {}
+            other => bail!("a broken chain checked as {other:?}"),
+        }
+        // nothing left that parses
+        for blk in 0..4 {
+            card.write(blk, fs_at(9)?, true);
+        }
+        match check_fs(&card, 0x1000, None) {
+            Err(e) if e.to_string().contains("no block of the FS region") => {}
+            other => bail!("an FS region with nothing that parses checked as {other:?}"),
+        }
+
+        // the generation a write is expected to leave is only known if it wrote the whole region
+        let mut nand = vec![0xFF; 0x1000 * BLOCK_SIZE];
+        nand[0xFF4 * BLOCK_SIZE..0xFF5 * BLOCK_SIZE].copy_from_slice(&fs_at(12)?);
+        nand[0xFF9 * BLOCK_SIZE..0xFFA * BLOCK_SIZE].copy_from_slice(&fs_at(11)?);
+        if expected_seqno(&nand, std::slice::from_ref(&(0..0x1000)), 0x1000) != Some(12)
+            || expected_seqno(&nand, &[0..0x40, 0xFF0..0x1000], 0x1000) != Some(12)
+            || expected_seqno(&nand, std::slice::from_ref(&(0xFF4..0x1000)), 0x1000).is_some()
+        {
+            bail!("the expected FS generation after a write was wrong");
+        }
+        Ok(())
+    }
+}
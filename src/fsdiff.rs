@@ -0,0 +1,262 @@
+use std::fs::read;
+
+use anyhow::{anyhow, Result};
+
+use crate::fs::{FsBlock, FsEntry, FAT_BAD, FAT_FREE};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(FsEntry),
+    Removed(FsEntry),
+    // same chain, different name (and possibly a different size too)
+    Renamed { old: FsEntry, new: FsEntry },
+    Resized { old: FsEntry, new: FsEntry },
+    // same name and size, different chain
+    Relocated { old: FsEntry, new: FsEntry },
+    // same name, but a different chain and size, so it could have been rewritten in place
+    // or deleted and created again; there's no way to tell which from the FS alone
+    Replaced { old: FsEntry, new: FsEntry },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsDiff {
+    pub changes: Vec<Change>,
+    pub free: (usize, usize),
+    pub bad: (usize, usize),
+    pub seqno: (u32, u32),
+}
+
+fn count(fs: &FsBlock, value: u16) -> usize {
+    fs.fat.iter().filter(|&&v| v == value).count()
+}
+
+// a file's chain, or just its first block if the chain is broken
+fn chain_of(fs: &FsBlock, entry: &FsEntry) -> Vec<u16> {
+    fs.chain(entry.start).unwrap_or_else(|_| vec![entry.start])
+}
+
+pub fn diff(old: &FsBlock, new: &FsBlock) -> FsDiff {
+    let mut changes = vec![];
+    let mut removed = vec![];
+    let mut added = vec![];
+
+    for o in &old.entries {
+        match new.find(&o.name) {
+            None => removed.push(o),
+            Some(n) => {
+                let same_chain = chain_of(old, o) == chain_of(new, n);
+                match (same_chain, o.size == n.size) {
+                    (true, true) => {}
+                    (true, false) => changes.push(Change::Resized {
+                        old: o.clone(),
+                        new: n.clone(),
+                    }),
+                    (false, true) => changes.push(Change::Relocated {
+                        old: o.clone(),
+                        new: n.clone(),
+                    }),
+                    (false, false) => changes.push(Change::Replaced {
+                        old: o.clone(),
+                        new: n.clone(),
+                    }),
+                }
+            }
+        }
+    }
+    for n in &new.entries {
+        if old.find(&n.name).is_none() {
+            added.push(n);
+        }
+    }
+
+    // a removed file and an added file with the same chain are a rename
+    for o in removed {
+        let chain = chain_of(old, o);
+        match added.iter().position(|n| chain_of(new, n) == chain) {
+            Some(i) => changes.push(Change::Renamed {
+                old: o.clone(),
+                new: added.remove(i).clone(),
+            }),
+            None => changes.push(Change::Removed(o.clone())),
+        }
+    }
+    changes.extend(added.into_iter().cloned().map(Change::Added));
+
+    FsDiff {
+        changes,
+        free: (count(old, FAT_FREE), count(new, FAT_FREE)),
+        bad: (count(old, FAT_BAD), count(new, FAT_BAD)),
+        seqno: (old.seqno, new.seqno),
+    }
+}
+
+impl FsDiff {
+    pub fn print(&self) {
+        let (old_seqno, new_seqno) = self.seqno;
+        println!(
+            "Sequence number: {old_seqno} -> {new_seqno} ({:+})",
+            new_seqno as i64 - old_seqno as i64
+        );
+        println!(
+            "Free blocks: {} -> {} ({:+})",
+            self.free.0,
+            self.free.1,
+            self.free.1 as i64 - self.free.0 as i64
+        );
+        println!(
+            "Bad blocks: {} -> {} ({:+})",
+            self.bad.0,
+            self.bad.1,
+            self.bad.1 as i64 - self.bad.0 as i64
+        );
+
+        if self.changes.is_empty() {
+            println!("No file changes");
+            return;
+        }
+        println!("File changes ({}):", self.changes.len());
+        for change in &self.changes {
+            match change {
+                Change::Added(n) => {
+                    println!("  added     {} ({:#X} bytes at {:#X})", n.name, n.size, n.start)
+                }
+                Change::Removed(o) => {
+                    println!("  removed   {} ({:#X} bytes at {:#X})", o.name, o.size, o.start)
+                }
+                Change::Renamed { old, new } if old.size == new.size => {
+                    println!("  renamed   {} -> {}", old.name, new.name)
+                }
+                Change::Renamed { old, new } => println!(
+                    "  renamed   {} -> {}, and resized {:#X} -> {:#X} bytes",
+                    old.name, new.name, old.size, new.size
+                ),
+                Change::Resized { old, new } => println!(
+                    "  resized   {} {:#X} -> {:#X} bytes",
+                    new.name, old.size, new.size
+                ),
+                Change::Relocated { old, new } => println!(
+                    "  relocated {} {:#X} -> {:#X}",
+                    new.name, old.start, new.start
+                ),
+                Change::Replaced { old, new } => println!(
+                    "  replaced  {} ({:#X} bytes at {:#X} -> {:#X} bytes at {:#X}; ambiguous: rewritten, or deleted and created again)",
+                    new.name, old.size, old.start, new.size, new.start
+                ),
+            }
+        }
+    }
+}
+
+fn load(filename: &str) -> Result<FsBlock> {
+    FsBlock::parse(&read(filename)?).map_err(|e| anyhow!("{filename}: {e}"))
+}
+
+pub fn fsdiff(old_filename: &str, new_filename: &str) -> Result<()> {
+    let old = load(old_filename)?;
+    let new = load(new_filename)?;
+    if old.linked || new.linked {
+        println!(
+            "Note: multi-block FATs aren't supported; only the first FS block of each is compared"
+        );
+    }
+    diff(&old, &new).print();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{FAT_END, FAT_ENTRIES};
+
+    // an FS with each file's blocks chained in the order given
+    fn fs(seqno: u32, files: &[(&str, &[u16], u32)], bad: &[u16]) -> FsBlock {
+        let mut fat = vec![FAT_FREE; FAT_ENTRIES];
+        for &(_, chain, _) in files {
+            for (i, &blk) in chain.iter().enumerate() {
+                fat[blk as usize] = chain.get(i + 1).copied().unwrap_or(FAT_END);
+            }
+        }
+        for &blk in bad {
+            fat[blk as usize] = FAT_BAD;
+        }
+        FsBlock {
+            fat,
+            entries: files
+                .iter()
+                .map(|&(name, chain, size)| FsEntry::new(name, chain[0], size))
+                .collect(),
+            linked: false,
+            seqno,
+        }
+    }
+
+    #[test]
+    fn changes() -> Result<()> {
+        let old = fs(
+            7,
+            &[
+                ("SAME.sys", &[0x100], 0x4000),
+                ("GROWN.sys", &[0x101, 0x102], 0x6000),
+                ("MOVED.app", &[0x103], 0x4000),
+                ("REDONE.rec", &[0x104], 0x4000),
+                ("OLD.sta", &[0x105, 0x106], 0x8000),
+                ("GONE.pak", &[0x107], 0x200),
+            ],
+            &[0x200],
+        );
+        let new = fs(
+            9,
+            &[
+                ("SAME.sys", &[0x100], 0x4000),
+                ("GROWN.sys", &[0x101, 0x102], 0x7000),
+                ("MOVED.app", &[0x110], 0x4000),
+                ("REDONE.rec", &[0x111, 0x112], 0x5000),
+                ("NEW.sta", &[0x105, 0x106], 0x8000),
+                ("ADDED.pak", &[0x113], 0x200),
+            ],
+            &[0x200, 0x201],
+        );
+
+        let d = diff(&old, &new);
+        let names = d
+            .changes
+            .iter()
+            .map(|c| match c {
+                Change::Added(n) => format!("+{}", n.name),
+                Change::Removed(o) => format!("-{}", o.name),
+                Change::Renamed { old, new } => format!("{}=>{}", old.name, new.name),
+                Change::Resized { new, .. } => format!("~{}", new.name),
+                Change::Relocated { new, .. } => format!(">{}", new.name),
+                Change::Replaced { new, .. } => format!("!{}", new.name),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "~GROWN.sys",
+                ">MOVED.app",
+                "!REDONE.rec",
+                "OLD.sta=>NEW.sta",
+                "-GONE.pak",
+                "+ADDED.pak"
+            ]
+        );
+        // 8 blocks in use and a bad one before, 9 and two bad ones after
+        assert_eq!(d.free, (FAT_ENTRIES - 9, FAT_ENTRIES - 11));
+        assert_eq!((d.bad, d.seqno), ((1, 2), (7, 9)));
+
+        // an FS against itself has nothing to say but the counts
+        let same = diff(&old, &old);
+        assert!(same.changes.is_empty());
+        assert_eq!(same.free, (d.free.0, d.free.0));
+
+        // a file whose chain is broken is compared by its first block alone
+        let mut broken = old.clone();
+        broken.fat[0x101] = FAT_FREE;
+        let d = diff(&old, &broken);
+        assert!(
+            matches!(&d.changes[..], [Change::Relocated { new, .. }] if new.name == "GROWN.sys")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,623 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::ecc::page_ecc;
+use crate::fs::{
+    FsBlock, FsEntry, BLOCK_SIZE, FAT_BAD, FAT_END, FAT_ENTRIES, FAT_FREE, FAT_RESERVED,
+    FS_FILE_COUNT, FS_REGION_BLOCKS, SKSA_BLOCKS, SPARE_SIZE,
+};
+use crate::hashing::HashAlgo;
+use crate::sink::write_atomic;
+use crate::spare::mark_bad;
+use crate::spotcheck::SampleRng;
+
+// Test images built to a spec, for 'genimage' and the self-tests: a card of so many blocks, with
+// files (copied from local ones or made up), bad blocks, a number of FS generations, and any
+// corruptions asked for. The same spec, seed included, always gives the same image byte for byte,
+// so an image can be regenerated from its spec rather than kept. The SKSA is left blank.
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Spec {
+    #[serde(default)]
+    pub seed: u64,
+    pub blocks: usize,
+    // each older one has fewer of the files, the oldest none
+    #[serde(default = "one")]
+    pub generations: usize,
+    #[serde(default)]
+    pub bad_blocks: Vec<u16>,
+    // whether files get free blocks in an order shuffled by the seed, as on a well-used card,
+    // rather than lowest first
+    #[serde(default)]
+    pub scatter: bool,
+    #[serde(default, rename = "file")]
+    pub files: Vec<FileSpec>,
+    #[serde(default, rename = "corrupt")]
+    pub corruptions: Vec<Corruption>,
+}
+
+fn one() -> usize {
+    1
+}
+
+impl Default for Spec {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            blocks: 0,
+            generations: one(),
+            bad_blocks: vec![],
+            scatter: false,
+            files: vec![],
+            corruptions: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileSpec {
+    pub name: String,
+    // a local file to copy, relative to the spec; or else `size` bytes of `pattern`
+    pub path: Option<String>,
+    pub pattern: Option<Pattern>,
+    pub size: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pattern {
+    Zeros,
+    Ones,
+    // each byte its offset, mod 256
+    Counting,
+    // from the seed
+    Random,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Corruption {
+    // the file's chain ends a block early in the newest generation
+    TruncatedChain { file: String },
+    // the newest generation's checksum is off, so it doesn't parse and the one before is current
+    BadChecksum,
+}
+
+// what was generated, saved beside the image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Manifest {
+    pub seed: u64,
+    pub blocks: usize,
+    pub bad_blocks: Vec<u16>,
+    pub generations: Vec<GenerationRecord>,
+    pub files: Vec<FileRecord>,
+    pub corruptions: Vec<Corruption>,
+    // whether fsck should find nothing wrong with the newest generation that parses
+    pub fsck_clean: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GenerationRecord {
+    pub block: usize,
+    pub seqno: u32,
+    pub files: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileRecord {
+    pub name: String,
+    pub size: u32,
+    pub source: String,
+    pub chain: Vec<u16>,
+    pub sha256: String,
+}
+
+pub struct Generated {
+    pub nand: Vec<u8>,
+    pub spare: Vec<u8>,
+    pub manifest: Manifest,
+}
+
+pub fn parse_spec(text: &str) -> Result<Spec> {
+    Ok(toml::from_str(text)?)
+}
+
+// the `index`th file's contents, and where they came from
+fn contents(
+    file: &FileSpec,
+    seed: u64,
+    index: usize,
+    read: &dyn Fn(&str) -> Result<Vec<u8>>,
+) -> Result<(Vec<u8>, String)> {
+    match (&file.path, file.pattern, file.size) {
+        (Some(path), None, None) => Ok((read(path)?, format!("path {path}"))),
+        (None, Some(pattern), Some(size)) => {
+            let size = size as usize;
+            let data = match pattern {
+                Pattern::Zeros => vec![0; size],
+                Pattern::Ones => vec![0xFF; size],
+                Pattern::Counting => (0..size).map(|i| i as u8).collect(),
+                Pattern::Random => {
+                    let mut rng = SampleRng::new(seed ^ (index as u64 + 1));
+                    (0..size).map(|_| rng.below(0x100) as u8).collect()
+                }
+            };
+            Ok((data, format!("pattern {pattern:?}").to_lowercase()))
+        }
+        _ => bail!("{} needs either 'path', or 'pattern' and 'size'", file.name),
+    }
+}
+
+// the image `spec` describes; `read` gets the contents of files given by path
+pub fn generate(spec: &Spec, read: &dyn Fn(&str) -> Result<Vec<u8>>) -> Result<Generated> {
+    let blocks = spec.blocks;
+    if blocks > FAT_ENTRIES {
+        bail!("{blocks:#X} blocks needs a linked FAT; cards of up to {FAT_ENTRIES:#X} blocks can be generated");
+    }
+    let data_area = SKSA_BLOCKS as usize..blocks.saturating_sub(FS_REGION_BLOCKS);
+    if data_area.is_empty() {
+        bail!("{blocks:#X} blocks leaves no room for files after the SKSA and the FS");
+    }
+    if !(1..=FS_REGION_BLOCKS).contains(&spec.generations) {
+        bail!(
+            "'generations' is {}, but the FS region holds 1 to {FS_REGION_BLOCKS}",
+            spec.generations
+        );
+    }
+    if spec.files.len() > FS_FILE_COUNT {
+        bail!(
+            "too many files ({}, at most {FS_FILE_COUNT})",
+            spec.files.len()
+        );
+    }
+
+    // the FAT with nothing on the card yet; blocks past its end are reserved, as the FS's own are
+    let mut base = vec![FAT_RESERVED; FAT_ENTRIES];
+    base[data_area.clone()].fill(FAT_FREE);
+    for &blk in &spec.bad_blocks {
+        if !data_area.contains(&(blk as usize)) {
+            bail!(
+                "bad block {blk:#X} isn't in the data area ({:#X} to {:#X})",
+                data_area.start,
+                data_area.end - 1
+            );
+        }
+        base[blk as usize] = FAT_BAD;
+    }
+    let mut free = data_area
+        .clone()
+        .filter(|&b| base[b] == FAT_FREE)
+        .map(|b| b as u16)
+        .collect::<Vec<_>>();
+    if spec.scatter {
+        let mut rng = SampleRng::new(spec.seed);
+        for i in (1..free.len()).rev() {
+            free.swap(i, rng.below(i + 1));
+        }
+    }
+
+    let mut nand = vec![0xFF; blocks * BLOCK_SIZE];
+    let mut written = vec![];
+    let mut free = free.into_iter();
+    let mut entries: Vec<FsEntry> = vec![];
+    let mut files = vec![];
+    for (i, file) in spec.files.iter().enumerate() {
+        if !FsEntry::fits(&file.name) {
+            bail!("'{}' doesn't fit in an 8.3 name", file.name);
+        }
+        if entries.iter().any(|e| e.name == file.name) {
+            bail!("{} is in the spec twice", file.name);
+        }
+        let (data, source) = contents(file, spec.seed, i, read)?;
+        let size = u32::try_from(data.len())
+            .map_err(|_| anyhow!("{} is too big for an FS entry", file.name))?;
+        // even an empty file has a block, for its entry to start at
+        let count = data.len().div_ceil(BLOCK_SIZE).max(1);
+        let chain = free.by_ref().take(count).collect::<Vec<_>>();
+        if chain.len() < count {
+            bail!("the files don't fit on a card of {blocks:#X} blocks");
+        }
+        for (&blk, chunk) in chain.iter().zip(data.chunks(BLOCK_SIZE)) {
+            let at = blk as usize * BLOCK_SIZE;
+            nand[at..at + chunk.len()].copy_from_slice(chunk);
+        }
+        written.extend(chain.iter().map(|&b| b as usize));
+        entries.push(FsEntry::new(&file.name, chain[0], size));
+        files.push(FileRecord {
+            name: file.name.clone(),
+            size,
+            source,
+            sha256: HashAlgo::Sha256.digest(&data).hex(),
+            chain,
+        });
+    }
+
+    // generation k of n has the first k/n of the files
+    let region = blocks - FS_REGION_BLOCKS;
+    let newest = spec.generations;
+    let mut generations = vec![];
+    for k in 1..=newest {
+        let held = files.len() * k / newest;
+        let mut fat = base.clone();
+        for file in &files[..held] {
+            for pair in file.chain.windows(2) {
+                fat[pair[0] as usize] = pair[1];
+            }
+            fat[*file.chain.last().unwrap() as usize] = FAT_END;
+        }
+        if k == newest {
+            for corruption in &spec.corruptions {
+                if let Corruption::TruncatedChain { file } = corruption {
+                    let chain = &files
+                        .iter()
+                        .find(|f| f.name == *file)
+                        .ok_or_else(|| anyhow!("{file} is to be truncated, but isn't in the spec"))?
+                        .chain;
+                    let [.., last_but_one, last] = chain[..] else {
+                        bail!("{file} has a single block, so its chain can't be truncated");
+                    };
+                    fat[last_but_one as usize] = FAT_END;
+                    fat[last as usize] = FAT_FREE;
+                }
+            }
+        }
+        let fs = FsBlock {
+            fat,
+            entries: entries[..held].to_vec(),
+            linked: false,
+            seqno: k as u32,
+        };
+        let mut data = fs.to_bytes()?;
+        if k == newest && spec.corruptions.contains(&Corruption::BadChecksum) {
+            data[BLOCK_SIZE - 1] ^= 1;
+        }
+        let blk = region + k - 1;
+        nand[blk * BLOCK_SIZE..(blk + 1) * BLOCK_SIZE].copy_from_slice(&data);
+        written.push(blk);
+        generations.push(GenerationRecord {
+            block: blk,
+            seqno: k as u32,
+            files: held,
+        });
+    }
+
+    // erased blocks' spare data is blank, written ones' has their ECC
+    let mut spare = vec![0xFF; blocks * SPARE_SIZE];
+    for blk in written {
+        page_ecc(
+            &nand[blk * BLOCK_SIZE..(blk + 1) * BLOCK_SIZE],
+            &mut spare[blk * SPARE_SIZE..(blk + 1) * SPARE_SIZE],
+        )?;
+    }
+    for &blk in &spec.bad_blocks {
+        let blk = blk as usize;
+        mark_bad(&mut spare[blk * SPARE_SIZE..(blk + 1) * SPARE_SIZE]);
+    }
+
+    let manifest = Manifest {
+        seed: spec.seed,
+        blocks,
+        bad_blocks: spec.bad_blocks.clone(),
+        generations,
+        files,
+        corruptions: spec.corruptions.clone(),
+        fsck_clean: spec.corruptions.is_empty(),
+    };
+    Ok(Generated {
+        nand,
+        spare,
+        manifest,
+    })
+}
+
+// generates the image `spec_path` describes into `nand_out` and `spare_out`, with the manifest
+// beside the NAND; paths in the spec are relative to it
+pub fn genimage(spec_path: &str, nand_out: &str, spare_out: &str) -> Result<Generated> {
+    let spec = read_to_string(spec_path)
+        .map_err(|e| anyhow!("{spec_path}: {e}"))
+        .and_then(|text| parse_spec(&text).map_err(|e| anyhow!("{spec_path}: {e}")))?;
+    let dir = Path::new(spec_path).parent().unwrap_or(Path::new(""));
+    let generated = generate(&spec, &|path| {
+        std::fs::read(dir.join(path)).map_err(|e| anyhow!("{path}: {e}"))
+    })?;
+    write_atomic(nand_out, &generated.nand)?;
+    write_atomic(spare_out, &generated.spare)?;
+    write_atomic(
+        format!("{nand_out}.manifest.json"),
+        serde_json::to_string_pretty(&generated.manifest)?.as_bytes(),
+    )?;
+    Ok(generated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_images() -> Result<()> {
+        use crate::image::NandImage;
+        use crate::mount::MountedImage;
+        use crate::player::Player;
+        use crate::spare::ecc_matches;
+        use crate::triage::fsck;
+
+        let local = vec![0x5A; 0x100];
+        let read = |path: &str| match path {
+            "d.dat" => Ok(local.clone()),
+            _ => bail!("{path}: no such file"),
+        };
+        let pattern = |name: &str, pattern, size| FileSpec {
+            name: name.into(),
+            pattern: Some(pattern),
+            size: Some(size),
+            ..FileSpec::default()
+        };
+        let spec = Spec {
+            seed: 7,
+            blocks: 0x100,
+            generations: 3,
+            bad_blocks: vec![0x45],
+            scatter: true,
+            files: vec![
+                pattern("A.app", Pattern::Counting, 0x6000),
+                pattern("B.rec", Pattern::Random, 0x8001),
+                pattern("C.sys", Pattern::Zeros, 0),
+                FileSpec {
+                    name: "D.dat".into(),
+                    path: Some("d.dat".into()),
+                    ..FileSpec::default()
+                },
+            ],
+            corruptions: vec![],
+        };
+        let clean = generate(&spec, &read)?;
+
+        // the same spec gives the same image; another seed, another
+        let again = generate(&spec, &read)?;
+        if again.nand != clean.nand
+            || again.spare != clean.spare
+            || again.manifest != clean.manifest
+        {
+            bail!("the same spec generated two different images");
+        }
+        let reseeded = generate(
+            &Spec {
+                seed: 8,
+                ..spec.clone()
+            },
+            &read,
+        )?;
+        if reseeded.nand == clean.nand {
+            bail!("a different seed generated the same image");
+        }
+
+        // the clean image is one the rest of the program reads as it was meant to be
+        let image = NandImage::new(clean.nand.clone(), clean.spare.clone())?;
+        let parsed = image
+            .fs_generations()
+            .into_iter()
+            .filter(|(_, fs)| fs.is_ok())
+            .count();
+        let (blk, current) = image
+            .current_fs()
+            .ok_or_else(|| anyhow!("the generated image has no FS"))?;
+        if parsed != 3 || current.seqno != 3 || blk != 0x100 - FS_REGION_BLOCKS + 2 {
+            bail!(
+                "the generated image's FS generations were {parsed}, newest #{} at {blk:#X}",
+                current.seqno
+            );
+        }
+        if !fsck(&current).is_empty() || image.bad_blocks() != [0x45] {
+            bail!("the clean image had problems: {:?}", fsck(&current));
+        }
+        let held = clean
+            .manifest
+            .generations
+            .iter()
+            .map(|g| g.files)
+            .collect::<Vec<_>>();
+        if held != [1, 2, 4] {
+            bail!("the generations held {held:?} files");
+        }
+        let card = MountedImage::from_image(image, "generated", "generated.spare")?;
+        let expected = [
+            (0..0x6000).map(|i| i as u8).collect(),
+            vec![],
+            local.clone(),
+        ];
+        for (name, data) in [
+            ("A.app", &expected[0]),
+            ("C.sys", &expected[1]),
+            ("D.dat", &expected[2]),
+        ] {
+            if card.ReadFile(name)?.as_ref() != Some(data) {
+                bail!("{name} didn't read back as generated");
+            }
+        }
+        let chains = clean
+            .manifest
+            .files
+            .iter()
+            .flat_map(|f| f.chain.clone())
+            .collect::<Vec<_>>();
+        if chains.contains(&0x45) || chains.is_sorted() {
+            bail!("the files' blocks were {chains:X?}");
+        }
+        let first = chains[0] as usize;
+        if !ecc_matches(
+            &clean.nand[first * BLOCK_SIZE..(first + 1) * BLOCK_SIZE],
+            &clean.spare[first * SPARE_SIZE..(first + 1) * SPARE_SIZE],
+        ) {
+            bail!("a generated block's ECC doesn't match it");
+        }
+
+        // each corruption fails fsck (or parsing) as it should, and nothing else does
+        let truncated = generate(
+            &Spec {
+                corruptions: vec![Corruption::TruncatedChain {
+                    file: "A.app".into(),
+                }],
+                ..spec.clone()
+            },
+            &read,
+        )?;
+        let (_, current) = NandImage::new(truncated.nand, truncated.spare)?
+            .current_fs()
+            .ok_or_else(|| anyhow!("the truncated image has no FS"))?;
+        if fsck(&current) != ["A.app: 24576 bytes needs 2 blocks, but its chain has 1"]
+            || truncated.manifest.fsck_clean
+        {
+            bail!("the truncated image's fsck found {:?}", fsck(&current));
+        }
+
+        let checksum = generate(
+            &Spec {
+                corruptions: vec![Corruption::BadChecksum],
+                ..spec.clone()
+            },
+            &read,
+        )?;
+        let image = NandImage::new(checksum.nand, checksum.spare)?;
+        let newest = image.fs_generations()[2].1.clone();
+        match (newest, image.current_fs()) {
+            (Err(crate::fs::FsError::BadChecksum(_)), Some((_, fs)))
+                if fs.seqno == 2 && fsck(&fs).is_empty() => {}
+            (newest, current) => bail!(
+                "the newest generation with a bad checksum parsed as {:?}, and #{:?} was current",
+                newest.map(|fs| fs.seqno),
+                current.map(|(_, fs)| fs.seqno)
+            ),
+        }
+
+        // and specs that can't be generated say why
+        for (what, bad) in [
+            (
+                "too big",
+                Spec {
+                    blocks: FAT_ENTRIES + 1,
+                    ..spec.clone()
+                },
+            ),
+            (
+                "too small",
+                Spec {
+                    blocks: SKSA_BLOCKS as usize + FS_REGION_BLOCKS,
+                    ..spec.clone()
+                },
+            ),
+            (
+                "no generations",
+                Spec {
+                    generations: 0,
+                    ..spec.clone()
+                },
+            ),
+            (
+                "bad FS block",
+                Spec {
+                    bad_blocks: vec![0xF8],
+                    ..spec.clone()
+                },
+            ),
+            (
+                "full",
+                Spec {
+                    blocks: 0x52,
+                    ..spec.clone()
+                },
+            ),
+            (
+                "duplicate",
+                Spec {
+                    files: vec![pattern("A.app", Pattern::Ones, 1); 2],
+                    ..spec.clone()
+                },
+            ),
+            (
+                "long name",
+                Spec {
+                    files: vec![pattern("LONGNAME1.app", Pattern::Ones, 1)],
+                    ..spec.clone()
+                },
+            ),
+            (
+                "no contents",
+                Spec {
+                    files: vec![FileSpec {
+                        name: "E.app".into(),
+                        ..FileSpec::default()
+                    }],
+                    ..spec.clone()
+                },
+            ),
+            (
+                "missing",
+                Spec {
+                    files: vec![FileSpec {
+                        name: "E.app".into(),
+                        path: Some("e.app".into()),
+                        ..FileSpec::default()
+                    }],
+                    ..spec.clone()
+                },
+            ),
+            (
+                "unknown",
+                Spec {
+                    corruptions: vec![Corruption::TruncatedChain {
+                        file: "E.app".into(),
+                    }],
+                    ..spec.clone()
+                },
+            ),
+            (
+                "one block",
+                Spec {
+                    corruptions: vec![Corruption::TruncatedChain {
+                        file: "D.dat".into(),
+                    }],
+                    ..spec.clone()
+                },
+            ),
+        ] {
+            if generate(&bad, &read).is_ok() {
+                bail!("the {what} spec was generated");
+            }
+        }
+
+        // specs are written in TOML
+        let parsed = parse_spec(
+            "seed = 7\nblocks = 0x100\ngenerations = 3\nbad_blocks = [0x45]\nscatter = true\n\
+             [[file]]\nname = \"A.app\"\npattern = \"counting\"\nsize = 0x6000\n\
+             [[file]]\nname = \"B.rec\"\npattern = \"random\"\nsize = 0x8001\n\
+             [[file]]\nname = \"C.sys\"\npattern = \"zeros\"\nsize = 0\n\
+             [[file]]\nname = \"D.dat\"\npath = \"d.dat\"\n\
+             [[corrupt]]\nkind = \"truncated-chain\"\nfile = \"A.app\"\n",
+        )?;
+        if parsed.corruptions
+            != [Corruption::TruncatedChain {
+                file: "A.app".into(),
+            }]
+            || generate(
+                &Spec {
+                    corruptions: vec![],
+                    ..parsed
+                },
+                &read,
+            )?
+            .manifest
+                != clean.manifest
+        {
+            bail!("the TOML spec didn't describe the same image");
+        }
+        if parse_spec("blocks = 0x100\nsectors = 4\n").is_ok() {
+            bail!("a spec with an unknown key was accepted");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use std::ops::Range;
+
+use anyhow::{bail, Result};
+
+use crate::fs::{FS_REGION_BLOCKS, SKSA_BLOCKS};
+
+// What can be restored from a dump of a card of a different size. The SKSA is at the start of
+// every card, but the FS region is the last 16 blocks, so it's somewhere else on each size of
+// card, and an FS only describes the blocks of the card it was made for.
+
+/// A part of the image that can be written to the card: image blocks `from` go to the card's
+/// blocks starting at `to`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestoreOption {
+    /// What to choose it by, with `--restore` or at the prompt.
+    pub name: &'static str,
+    /// What it holds, for the list of options.
+    pub description: &'static str,
+    /// The image's blocks.
+    pub from: Range<u16>,
+    /// The card block the first of them goes to.
+    pub to: u16,
+}
+
+impl RestoreOption {
+    /// The card's blocks it's written to.
+    pub fn destination(&self) -> Range<u16> {
+        self.to..self.to + self.from.len() as u16
+    }
+}
+
+fn fs_start(blocks: u16) -> u16 {
+    blocks.saturating_sub(FS_REGION_BLOCKS as u16)
+}
+
+fn size_mib(blocks: u16) -> u32 {
+    blocks as u32 / 64
+}
+
+/// Why an image of `image_blocks` blocks can't be written whole to a card of `card_blocks`.
+pub fn explain(image_blocks: u16, card_blocks: u16) -> String {
+    format!(
+        "The image has {image_blocks:#X} blocks ({}MiB), but the console's card has {card_blocks:#X} ({}MiB), \
+         so it was dumped from a different size of card (a 128MiB development unit's and a 64MiB retail \
+         console's, say). The FS is kept in the last {FS_REGION_BLOCKS} blocks of a card, so the image's FS \
+         would land in the middle of this card's data, and the console wouldn't find it; writing the whole \
+         image is refused.",
+        size_mib(image_blocks),
+        size_mib(card_blocks)
+    )
+}
+
+/// The parts of the image that can be written without leaving the card's FS pointing at blocks
+/// it doesn't have.
+pub fn restore_options(image_blocks: u16, card_blocks: u16) -> Vec<RestoreOption> {
+    let mut options = vec![];
+    if image_blocks >= SKSA_BLOCKS && card_blocks >= SKSA_BLOCKS + FS_REGION_BLOCKS as u16 {
+        options.push(RestoreOption {
+            name: "sksa",
+            description: "only the SKSA, which is in the same place on every card",
+            from: 0..SKSA_BLOCKS,
+            to: 0,
+        });
+    }
+    // an FS from a smaller card only refers to blocks this card has too, so the FS region and
+    // the data it describes can be written, with the FS region moved to the end of the card; the
+    // console will only use as much of the card as the image's FS describes
+    if image_blocks < card_blocks && image_blocks >= SKSA_BLOCKS + FS_REGION_BLOCKS as u16 {
+        if fs_start(image_blocks) > SKSA_BLOCKS {
+            options.push(RestoreOption {
+                name: "data",
+                description:
+                    "the file data, to the same blocks (write 'fs' too, or it won't be found)",
+                from: SKSA_BLOCKS..fs_start(image_blocks),
+                to: SKSA_BLOCKS,
+            });
+        }
+        options.push(RestoreOption {
+            name: "fs",
+            description: "the FS region, moved to the end of this card",
+            from: fs_start(image_blocks)..image_blocks,
+            to: fs_start(card_blocks),
+        });
+    }
+    options
+}
+
+/// Where ranges written straight across have to end: they have to be on both cards, and can't
+/// touch either card's FS region.
+pub fn fitting_end(image_blocks: u16, card_blocks: u16) -> u16 {
+    fs_start(image_blocks).min(fs_start(card_blocks))
+}
+
+/// Whether `range` can be written straight across, ending by [`fitting_end`].
+pub fn range_fits(range: &Range<u16>, image_blocks: u16, card_blocks: u16) -> bool {
+    range.end <= fitting_end(image_blocks, card_blocks)
+}
+
+/// Lays the chosen parts of the image out as they go on the card, in an image of the card's size;
+/// `unit` is the size of a block's data in `image` (a block or its spare data).
+pub fn lay_out(
+    image: &[u8],
+    unit: usize,
+    card_blocks: u16,
+    chosen: &[&RestoreOption],
+) -> Result<Vec<u8>> {
+    let mut out = vec![0xFF; card_blocks as usize * unit];
+    for option in chosen {
+        let from = option.from.start as usize * unit..option.from.end as usize * unit;
+        let to = option.to as usize * unit;
+        if from.end > image.len() || to + from.len() > out.len() {
+            bail!("'{}' doesn't fit", option.name);
+        }
+        out[to..to + from.len()].copy_from_slice(&image[from]);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn card_geometry() -> Result<()> {
+        // retail cards are 64MiB; development units have had 128MiB and larger
+        let capacities = [0x1000, 0x2000, 0x4000];
+        for image in capacities {
+            for card in capacities {
+                let options = restore_options(image, card);
+                for o in &options {
+                    let dest = o.destination();
+                    if o.from.end > image || dest.end > card {
+                        bail!(
+                            "'{}' from {image:#X} to {card:#X} blocks goes off the end",
+                            o.name
+                        );
+                    }
+                    // nothing but the FS may land in this card's FS region, and the FS only there
+                    let in_fs = dest.start >= fs_start(card);
+                    if (o.name == "fs") != in_fs || (in_fs && dest.end != card) {
+                        bail!(
+                            "'{}' from {image:#X} to {card:#X} blocks lands in the wrong place",
+                            o.name
+                        );
+                    }
+                }
+                let has_fs = options.iter().any(|o| o.name == "fs");
+                if has_fs != (image < card) {
+                    bail!(
+                        "the FS was {}offered from {image:#X} to {card:#X} blocks",
+                        if has_fs { "" } else { "not " }
+                    );
+                }
+                if !options.iter().any(|o| o.name == "sksa") {
+                    bail!("the SKSA wasn't offered from {image:#X} to {card:#X} blocks");
+                }
+
+                let limit = fitting_end(image, card);
+                if !range_fits(&(0..limit), image, card) || range_fits(&(0..limit + 1), image, card)
+                {
+                    bail!("ranges from {image:#X} to {card:#X} blocks are checked against the wrong limit");
+                }
+            }
+        }
+
+        let options = restore_options(4, 8);
+        if !options.is_empty() {
+            bail!("options were offered for cards too small to have an SKSA and FS region");
+        }
+
+        // blocks of one byte each: the FS region of a 0x50-block image, moved to a 0x60-block card
+        let image = (0..0x50).collect::<Vec<u8>>();
+        let options = restore_options(0x50, 0x60);
+        let Some(fs) = options.iter().find(|o| o.name == "fs") else {
+            bail!("the FS wasn't offered from 0x50 to 0x60 blocks");
+        };
+        let out = lay_out(&image, 1, 0x60, &[fs])?;
+        if out[0x50..] != image[0x40..] || out[..0x50].iter().any(|&b| b != 0xFF) {
+            bail!("the FS region was laid out wrongly");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_to_string, write};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::config::config_dir;
+use crate::fs::FsBlock;
+use crate::hashing::{HashAlgo, HashValue};
+use crate::player::Player;
+
+const CACHE_FILE: &str = "hash-cache.toml";
+
+// Hashes of files on consoles' cards, so comparing a card with something doesn't mean reading
+// every file every time. A hash is keyed by its algorithm, the console, the file's name and size,
+// and the blocks it's in: a file that's rewritten is written to free blocks before its old ones
+// are freed, so it's all but certain to miss the cache rather than match a stale hash. Each value
+// is stored as "algo:hex" too, and one whose algorithm isn't the one asked for is never used, so
+// a cache written by an older version, whose keys had no algorithm, is simply missed.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct HashCache {
+    pub hashes: BTreeMap<String, String>,
+}
+
+fn key(algo: HashAlgo, bbid: u32, name: &str, size: u32, chain: &[u16]) -> String {
+    let blocks = chain
+        .iter()
+        .map(|b| format!("{b:X}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{algo} {bbid:08X} {name} {size} {blocks}")
+}
+
+impl HashCache {
+    // a missing or unreadable file just means nothing's cached
+    pub fn load() -> Self {
+        config_dir()
+            .and_then(|d| read_to_string(d.join(CACHE_FILE)).ok())
+            .and_then(|s| toml::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let dir = config_dir().ok_or_else(|| anyhow!("no config directory"))?;
+        create_dir_all(&dir)?;
+        write(dir.join(CACHE_FILE), toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    // the `algo` hashes of the named files on the console, whose current FS is `fs`, reading only
+    // those the cache doesn't have; cancellable between files
+    pub fn hashes(
+        &mut self,
+        player: &dyn Player,
+        fs: &FsBlock,
+        names: &[&str],
+        algo: HashAlgo,
+        cancel: &CancelToken,
+    ) -> Result<BTreeMap<String, HashValue>> {
+        let bbid = player.GetBBID()?;
+        let mut hashes = BTreeMap::new();
+        for &name in names {
+            let entry = fs
+                .find(name)
+                .ok_or_else(|| anyhow!("{name} isn't on the card"))?;
+            let key = key(algo, bbid, name, entry.size, &fs.chain(entry.start)?);
+            let cached = self
+                .hashes
+                .get(&key)
+                .and_then(|h| h.parse::<HashValue>().ok())
+                .filter(|h| h.algo == algo);
+            let hash = match cached {
+                Some(h) => h,
+                None => {
+                    cancel.check()?;
+                    println!("Hashing {name}");
+                    let data = player
+                        .ReadFile(name)?
+                        .ok_or_else(|| anyhow!("{name} disappeared while it was being read"))?;
+                    let hash = algo.digest(&data);
+                    self.hashes.insert(key, hash.to_string());
+                    hash
+                }
+            };
+            hashes.insert(name.to_string(), hash);
+        }
+        Ok(hashes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    #[test]
+    fn hash_cache() -> Result<()> {
+        use std::cell::Cell;
+
+        use bbrdb::CardStats;
+
+        use crate::fs::synthetic_block;
+
+        // a console with one file, counting how often it's read
+        struct Card {
+            reads: Cell<u32>,
+        }
+
+        impl Player for Card {
+            fn GetBBID(&self) -> Result<u32> {
+                Ok(0x1234)
+            }
+            fn SetLED(&self, _value: u32) -> Result<()> {
+                Ok(())
+            }
+            fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+                Ok(vec![])
+            }
+            fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+                Ok(synthetic_block())
+            }
+            fn ReadFile(&self, _name: &str) -> Result<Option<Vec<u8>>> {
+                self.reads.set(self.reads.get() + 1);
+                Ok(Some(vec![0x42; 0x6000]))
+            }
+            fn ReadSingleBlock(&self, _blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+                bail!("not used")
+            }
+            fn CardStats(&self) -> Result<CardStats> {
+                bail!("not used")
+            }
+        }
+
+        let card = Card {
+            reads: Cell::new(0),
+        };
+        let fs = FsBlock::parse(&synthetic_block())?;
+        let cancel = CancelToken::default();
+        let mut cache = HashCache::default();
+        let hash = |cache: &mut HashCache, algo| -> Result<HashValue> {
+            Ok(cache.hashes(&card, &fs, &["TEST.sys"], algo, &cancel)?["TEST.sys"].clone())
+        };
+        let data = vec![0x42; 0x6000];
+
+        let sha = hash(&mut cache, HashAlgo::Sha256)?;
+        if sha != HashAlgo::Sha256.digest(&data) || hash(&mut cache, HashAlgo::Sha256)? != sha {
+            bail!("the cached SHA-256 wasn't the file's");
+        }
+        if card.reads.get() != 1 {
+            bail!("a cached hash was read again");
+        }
+
+        // another algorithm misses the cache rather than being handed the SHA-256
+        let crc = hash(&mut cache, HashAlgo::Crc32)?;
+        if crc.algo != HashAlgo::Crc32
+            || crc != HashAlgo::Crc32.digest(&data)
+            || card.reads.get() != 2
+        {
+            bail!("a CRC-32 was served from a cache holding the SHA-256");
+        }
+
+        // an entry whose value is another algorithm's, such as a hand-edited or corrupted cache, is
+        // ignored, as is one keyed the way older versions keyed them
+        let chain = fs.chain(fs.find("TEST.sys").unwrap().start)?;
+        cache.hashes.insert(
+            key(HashAlgo::Md5, 0x1234, "TEST.sys", 0x6000, &chain),
+            sha.to_string(),
+        );
+        cache
+            .hashes
+            .insert("00001234 TEST.sys 24576 40,41".to_string(), sha.hex());
+        let md5 = hash(&mut cache, HashAlgo::Md5)?;
+        if md5.algo != HashAlgo::Md5 || md5 != HashAlgo::Md5.digest(&data) || card.reads.get() != 3
+        {
+            bail!("an MD5 lookup was answered with {md5}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+// The digests files and dumps can be hashed with, for whatever downstream wants them: MD5 for old
+// dat files, SHA-1 for community databases, SHA-256 for new archives and CRC-32 for quick checks.
+// Everything that hashes goes through `HashAlgo`, so a new algorithm is added here and nowhere
+// else. A `HashValue` carries its algorithm, and two values are only equal if their algorithms
+// are, so an MD5 can never be taken as matching (or not matching) a SHA-256 of the same file.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgo {
+    Md5,
+    Sha1,
+    Sha256,
+    Crc32,
+}
+
+impl HashAlgo {
+    pub const ALL: [Self; 4] = [Self::Md5, Self::Sha1, Self::Sha256, Self::Crc32];
+
+    // as typed at the prompt and written in caches
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Sha1 => "sha1",
+            Self::Sha256 => "sha256",
+            Self::Crc32 => "crc32",
+        }
+    }
+
+    // as BSD-style checksum lines ('cksum --tag') name it
+    pub fn tag(self) -> &'static str {
+        match self {
+            Self::Md5 => "MD5",
+            Self::Sha1 => "SHA1",
+            Self::Sha256 => "SHA256",
+            Self::Crc32 => "CRC32",
+        }
+    }
+
+    // for messages
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Md5 => "MD5",
+            Self::Sha1 => "SHA-1",
+            Self::Sha256 => "SHA-256",
+            Self::Crc32 => "CRC-32",
+        }
+    }
+
+    // the digest's length in bytes
+    pub fn digest_len(self) -> usize {
+        match self {
+            Self::Md5 => 16,
+            Self::Sha1 => 20,
+            Self::Sha256 => 32,
+            Self::Crc32 => 4,
+        }
+    }
+
+    // an untagged hex digest is taken to be whichever algorithm gives digests of its length
+    pub fn from_hex_len(len: usize) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.digest_len() * 2 == len)
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::Md5 => Hasher::Md5(Md5::new()),
+            Self::Sha1 => Hasher::Sha1(Sha1::new()),
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+            Self::Crc32 => Hasher::Crc32(!0),
+        }
+    }
+
+    pub fn digest(self, data: &[u8]) -> HashValue {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+impl Display for HashAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for HashAlgo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim().to_ascii_lowercase().replace('-', "");
+        Self::ALL
+            .into_iter()
+            .find(|a| a.name() == s)
+            .ok_or_else(|| {
+                let names = Self::ALL.map(|a| a.name());
+                anyhow!("'{s}' isn't a hash algorithm; use {}", names.join(", "))
+            })
+    }
+}
+
+// a comma-separated list, as given to 'set hash-algos' and '--algo'; each algorithm once
+pub fn parse_algos(list: &str) -> Result<Vec<HashAlgo>> {
+    let mut algos = vec![];
+    for name in list.split(',') {
+        let algo = name.parse()?;
+        if algos.contains(&algo) {
+            bail!("'{list}' gives {algo} twice");
+        }
+        algos.push(algo);
+    }
+    Ok(algos)
+}
+
+// the algorithms a command uses: its '--algo' if given, or else the session's 'set hash-algos',
+// or else the command's own default
+pub fn choose(
+    flag: Option<&str>,
+    session: Option<&[HashAlgo]>,
+    default: HashAlgo,
+) -> Result<Vec<HashAlgo>> {
+    match (flag, session) {
+        (Some(list), _) => parse_algos(list),
+        (None, Some(algos)) if !algos.is_empty() => Ok(algos.to_vec()),
+        _ => Ok(vec![default]),
+    }
+}
+
+// the CRC-32 (IEEE) register after `data`; start from !0 and invert at the end
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| {
+        (0..8).fold(crc ^ b as u32, |c, _| {
+            if c & 1 != 0 {
+                (c >> 1) ^ 0xEDB88320
+            } else {
+                c >> 1
+            }
+        })
+    })
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+pub enum Hasher {
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Crc32(u32),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Md5(h) => h.update(data),
+            Self::Sha1(h) => h.update(data),
+            Self::Sha256(h) => h.update(data),
+            Self::Crc32(crc) => *crc = crc32_update(*crc, data),
+        }
+    }
+
+    pub fn finalize(self) -> HashValue {
+        let (algo, bytes) = match self {
+            Self::Md5(h) => (HashAlgo::Md5, h.finalize().to_vec()),
+            Self::Sha1(h) => (HashAlgo::Sha1, h.finalize().to_vec()),
+            Self::Sha256(h) => (HashAlgo::Sha256, h.finalize().to_vec()),
+            Self::Crc32(crc) => (HashAlgo::Crc32, (!crc).to_be_bytes().to_vec()),
+        };
+        HashValue { algo, bytes }
+    }
+}
+
+// a digest and the algorithm that made it; written "algo:hex" where it's stored
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HashValue {
+    pub algo: HashAlgo,
+    pub bytes: Vec<u8>,
+}
+
+impl HashValue {
+    pub fn from_hex(algo: HashAlgo, hex: &str) -> Result<Self> {
+        let hex = hex.trim();
+        if hex.len() != algo.digest_len() * 2 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("'{hex}' isn't {} {}", article(algo), algo.label());
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { algo, bytes })
+    }
+
+    pub fn hex(&self) -> String {
+        self.bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+}
+
+// HMAC (RFC 2104) with any of the digests other than CRC-32, all of which take 64-byte blocks
+pub fn hmac(algo: HashAlgo, key: &[u8], message: &[u8]) -> HashValue {
+    const BLOCK: usize = 64;
+    let mut block = match key.len() > BLOCK {
+        true => algo.digest(key).bytes,
+        false => key.to_vec(),
+    };
+    block.resize(BLOCK, 0);
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<_>>();
+    let inner = algo.digest(&[pad(0x36), message.to_vec()].concat());
+    algo.digest(&[pad(0x5C), inner.bytes].concat())
+}
+
+fn article(algo: HashAlgo) -> &'static str {
+    match algo {
+        HashAlgo::Md5 => "an",
+        _ => "a",
+    }
+}
+
+impl Display for HashValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algo, self.hex())
+    }
+}
+
+impl FromStr for HashValue {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (algo, hex) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("'{s}' doesn't say which algorithm it's from"))?;
+        Self::from_hex(algo.parse()?, hex)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashFormat {
+    // the digests alone, one to a line, in the order the algorithms were given
+    Hex,
+    // what sha256sum and friends write: "<hex>  <name>", or with several algorithms, BSD-style
+    // "SHA256 (<name>) = <hex>" lines, which 'cksum --check' reads
+    Sum,
+    // one object per file: {"file": name, "<algo>": hex, ...}
+    Json,
+}
+
+impl FromStr for HashFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "hex" => Ok(Self::Hex),
+            "sum" => Ok(Self::Sum),
+            "json" => Ok(Self::Json),
+            _ => bail!("'{s}' isn't a hash format; use hex, sum or json"),
+        }
+    }
+}
+
+// a file's hashes, as lines in `format`
+pub fn format_hashes(format: HashFormat, name: &str, hashes: &[HashValue]) -> String {
+    match format {
+        HashFormat::Hex => hashes
+            .iter()
+            .map(HashValue::hex)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        HashFormat::Sum if hashes.len() == 1 => format!("{}  {name}", hashes[0].hex()),
+        HashFormat::Sum => hashes
+            .iter()
+            .map(|h| format!("{} ({name}) = {}", h.algo.tag(), h.hex()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        HashFormat::Json => {
+            let mut object = BTreeMap::from([("file".to_string(), name.to_string())]);
+            object.extend(hashes.iter().map(|h| (h.algo.to_string(), h.hex())));
+            serde_json::to_string(&object).unwrap_or_default()
+        }
+    }
+}
+
+// one line of a checksum file, either "<hex>  <name>" (the algorithm going by the digest's
+// length, and a '*' before the name marking binary mode) or BSD-style "ALGO (<name>) = <hex>"
+pub fn parse_sum_line(line: &str) -> Result<(HashValue, String)> {
+    if let Some((tag, rest)) = line.split_once(" (") {
+        if let Some((name, hex)) = rest.rsplit_once(") = ") {
+            let algo = HashAlgo::ALL
+                .into_iter()
+                .find(|a| a.tag().eq_ignore_ascii_case(tag.trim()))
+                .ok_or_else(|| anyhow!("'{tag}' isn't a hash algorithm"))?;
+            return Ok((HashValue::from_hex(algo, hex)?, name.to_string()));
+        }
+    }
+    let (hex, name) = line
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| anyhow!("expected '<hash>  <file>' in '{line}'"))?;
+    let name = name.trim_start().trim_start_matches('*');
+    let algo = HashAlgo::from_hex_len(hex.len())
+        .ok_or_else(|| anyhow!("'{hex}' isn't a hash of any length this knows"))?;
+    Ok((HashValue::from_hex(algo, hex)?, name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashing() -> Result<()> {
+        // the CRC check value, whole and in pieces
+        if crc32(b"123456789") != 0xCBF43926
+            || !crc32_update(crc32_update(!0, b"1234"), b"56789") != 0xCBF43926
+        {
+            bail!("CRC-32 of '123456789' isn't CBF43926");
+        }
+        let crc = HashAlgo::Crc32.digest(b"123456789");
+        if crc.hex() != "cbf43926" || crc.to_string() != "crc32:cbf43926" {
+            bail!("the CRC-32 value was written as {crc}");
+        }
+
+        // every algorithm hashes in pieces as it does whole, gives digests of its length, and
+        // round-trips through its stored form
+        for algo in HashAlgo::ALL {
+            let mut hasher = algo.hasher();
+            hasher.update(b"hello, ");
+            hasher.update(b"world");
+            let value = algo.digest(b"hello, world");
+            if hasher.finalize() != value || value.bytes.len() != algo.digest_len() {
+                bail!("{algo} gave different digests whole and in pieces");
+            }
+            if value.to_string().parse::<HashValue>()? != value
+                || HashAlgo::from_hex_len(value.hex().len()) != Some(algo)
+            {
+                bail!("{value} didn't round-trip");
+            }
+        }
+
+        // the same bytes under two algorithms are two different values
+        let bytes = vec![0xAB; 4];
+        let as_crc = HashValue {
+            algo: HashAlgo::Crc32,
+            bytes: bytes.clone(),
+        };
+        let mislabelled = HashValue {
+            algo: HashAlgo::Sha256,
+            bytes,
+        };
+        if as_crc == mislabelled || as_crc.hex() != mislabelled.hex() {
+            bail!("values from different algorithms compared equal");
+        }
+        if "sha256:abababab".parse::<HashValue>().is_ok() || "abababab".parse::<HashValue>().is_ok()
+        {
+            bail!("a digest of the wrong length, or with no algorithm, was accepted");
+        }
+
+        if parse_algos("sha256,CRC32,sha-1")? != [HashAlgo::Sha256, HashAlgo::Crc32, HashAlgo::Sha1]
+        {
+            bail!("the algorithm list was parsed wrong");
+        }
+        for bad in ["sha512", "md5,md5", ""] {
+            if parse_algos(bad).is_ok() {
+                bail!("'{bad}' was accepted as a list of algorithms");
+            }
+        }
+        let session = [HashAlgo::Md5];
+        if choose(Some("crc32"), Some(&session), HashAlgo::Sha256)? != [HashAlgo::Crc32]
+            || choose(None, Some(&session), HashAlgo::Sha256)? != [HashAlgo::Md5]
+            || choose(None, None, HashAlgo::Sha256)? != [HashAlgo::Sha256]
+        {
+            bail!("the algorithms weren't chosen flag first, then session, then default");
+        }
+
+        let crc = HashValue::from_hex(HashAlgo::Crc32, "cbf43926")?;
+        let md5 = HashValue::from_hex(HashAlgo::Md5, &"0".repeat(32))?;
+        if format_hashes(HashFormat::Sum, "a b.bin", std::slice::from_ref(&crc))
+            != "cbf43926  a b.bin"
+            || format_hashes(HashFormat::Sum, "a.bin", &[crc.clone(), md5.clone()])
+                != format!("CRC32 (a.bin) = cbf43926\nMD5 (a.bin) = {}", "0".repeat(32))
+            || format_hashes(HashFormat::Hex, "a.bin", &[crc.clone(), md5.clone()])
+                != format!("cbf43926\n{}", "0".repeat(32))
+        {
+            bail!("the hashes were rendered wrong");
+        }
+        for line in [
+            "cbf43926  a b.bin",
+            "cbf43926 *a b.bin",
+            "CRC32 (a b.bin) = cbf43926",
+        ] {
+            if parse_sum_line(line)? != (crc.clone(), "a b.bin".to_string()) {
+                bail!("'{line}' was parsed as {:?}", parse_sum_line(line)?);
+            }
+        }
+        if parse_sum_line("cbf4392  a.bin").is_ok()
+            || parse_sum_line("SHA256 (a.bin) = cbf43926").is_ok()
+        {
+            bail!("a malformed checksum line was accepted");
+        }
+        Ok(())
+    }
+}
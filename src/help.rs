@@ -0,0 +1,1073 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use unicode_width::UnicodeWidthStr;
+
+use crate::similar::distance;
+use crate::wrap::wrap;
+use crate::{PROG_NAME, PROG_VER};
+
+// The commands' help, for 'h'. Each line of a description is a paragraph, re-flowed to the
+// terminal's width by `render`; "{PROG_NAME}" in one stands for the program's name. Commands that
+// need an optional feature are marked with it, so builds without it list them apart, don't
+// suggest them, and say so in 'caps'.
+
+pub enum Help {
+    // a command's usage and what it does
+    Command(&'static str, &'static str),
+    // a command only some builds have
+    Gated(Feature, &'static str, &'static str),
+    // a note between the commands
+    Note(&'static str),
+    // a blank line between groups of commands
+    Gap,
+}
+
+use Help::*;
+
+// the optional parts of the program that commands can need
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Feature {
+    Writing,
+    Tui,
+}
+
+use Feature::*;
+
+impl Feature {
+    pub const ALL: [Feature; 2] = [Writing, Tui];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Writing => "writing",
+            Tui => "tui",
+        }
+    }
+
+    // whether this build has it
+    pub fn built(self) -> bool {
+        match self {
+            Writing => cfg!(feature = "writing"),
+            Tui => cfg!(feature = "tui"),
+        }
+    }
+}
+
+impl Help {
+    fn usage(&self) -> Option<&'static str> {
+        match self {
+            Command(usage, _) | Gated(_, usage, _) => Some(usage),
+            Note(_) | Gap => None,
+        }
+    }
+
+    // the command's name, the first word of its usage
+    pub fn name(&self) -> Option<&'static str> {
+        self.usage()?.split_whitespace().next()
+    }
+
+    // the feature it needs that `built` says the build doesn't have
+    fn missing(&self, built: &impl Fn(Feature) -> bool) -> Option<Feature> {
+        match self {
+            Gated(feature, ..) if !built(*feature) => Some(*feature),
+            _ => None,
+        }
+    }
+}
+
+// the usage column, and the indent of a description's later lines
+const USAGE_WIDTH: usize = 25;
+const HANG: &str = "                    ";
+// narrower than this, a description starts on the line after its usage, less indented
+const MIN_DESCRIPTION: usize = 24;
+const NARROW_HANG: &str = "        ";
+
+pub const HELP: &[Help] = &[
+    Command(
+        "l",
+        "List available BB Players by number, in order of the USB port they're plugged into, and \
+         which are in use by another copy of {PROG_NAME}. Consoles plugged in or unplugged at \
+         the prompt are announced as they come and go, and the list kept up to date (see \
+         'watch_hotplug' in the config file); with 'auto_open' set, one plugged in while none is \
+         selected can be selected by pressing Enter at an empty prompt. The selected console is \
+         reopened if it's unplugged and plugged back into the same port",
+    ),
+    Command(
+        "s device",
+        "Select BB Player <device>, unless another copy of {PROG_NAME} has it selected; a console \
+         opened at startup (see 'auto_open' in the config file) is released first, even if \
+         initialised. The number is the one 'l' last showed; if that device has gone or been \
+         replaced since, nothing is selected, and 'l' has to be run again",
+    ),
+    Gap,
+    Command(
+        "device",
+        "Print what's known about the selected player without initialising it: \
+         USB descriptors, port path, speed and driver state",
+    ),
+    Command(
+        "survey [--csv out] [--json out]",
+        "Open each connected console in turn (the selected one through its own connection) and \
+         list its BBID, card stats and the SHA-256 of its SKSA, one row per console; one that can't \
+         be read, or that another copy of {PROG_NAME} has selected, gets a row saying why. \
+         '--csv' and '--json' also save the rows to [out]",
+    ),
+    Command(
+        "B",
+        "Initialise USB connection to the selected console",
+    ),
+    Command(
+        "I",
+        "Request the console's unique BBID",
+    ),
+    Command(
+        "H value",
+        "Set LED (0, 1 = off; 2 = on; 3 = flashing)",
+    ),
+    Command(
+        "H --during command...",
+        "Run [command] with the LED flashing, then put the LED back how it was",
+    ),
+    Command(
+        ";S hash_file",
+        "Sign the SHA-1 hash in [hash_file] using ECDSA",
+    ),
+    Command(
+        "S --local key hash_file [sig_file]",
+        "Sign the SHA-1 hash in [hash_file] on the PC with the sect233r1 key in [key] (SEC1 or \
+         PKCS#8 PEM, or the private scalar in hex), writing the same 64-byte signature as the console \
+         (r then s) to [sig_file], or [hash_file].sig; the hash file holds the 20 bytes, or them in hex",
+    ),
+    Command(
+        "S --verify sig_file pubkey hash_file",
+        "Check that [sig_file] is a signature of the hash in [hash_file] by the key in [pubkey] (a PEM \
+         public or private key, or the public point in hex), however it was made",
+    ),
+    Command(
+        "J [time]",
+        "Set console clock to PC's current time, or [time] if given (note: RFC3339 format); the PC's \
+         time is checked first, and if it's from before the iQue Player, far from the time last set \
+         this session, or UTC on a PC configured for another zone, it's only used once confirmed",
+    ),
+    Command(
+        "L [--porcelain]",
+        "List all games currently on the console (as for '5')",
+    ),
+    Command(
+        "F file",
+        "Dump the current filesystem block to [file]",
+    ),
+    Command(
+        "K [file] [--raw-region|--both]",
+        "Dump the SKSA to [file] (or 'sksa') with the console's ReadSKSA call, warning if it isn't \
+         the size of the SKSA region; with '--raw-region', read the region's 64 blocks one at a time \
+         instead, with their spare data to [file].spare; with '--both', do both, keeping the region \
+         as [file].region, and compare them block by block",
+    ),
+    Command(
+        "X blkno nand spare",
+        "Read one block and its spare data from the console to [nand] and [spare]; with '--heroic', a \
+         block that fails its ECC is read up to 8 more times and each bit voted on, and the vote is \
+         kept if it passes the ECC",
+    ),
+    Command(
+        "page blk page [out]",
+        "Print page [page] (0 to 31) of block [blk] as a hex dump, with the block's spare data \
+         decoded and checked against page 0; or save it to [out], and for page 0 the spare data to \
+         [out].spare. The console only reads whole blocks, so the page is cut from a read of its \
+         block, and only the first page's spare data (and ECC) is kept",
+    ),
+    Gated(
+        Writing,
+        "Y blkno nand spare",
+        "Write one block and its spare data from [nand] and [spare] to the console; \
+         refused if the spare data's SA marker doesn't match the block, unless '--force' is given; \
+         [nand] and [spare] can be .hex or .srec images addressed as in the whole NAND or spare file",
+    ),
+    Command(
+        "C",
+        "Print statistics about the console's NAND. Commands that change the card first check that \
+         nothing else has changed it since (by its FS sequence number), and ask before going ahead if so",
+    ),
+    Command(
+        "Q",
+        "Close USB connection to the console",
+    ),
+    Command(
+        "stats history [--graph]",
+        "Print the card stats recorded by 'C' for this console (with 'stats_history = true' in the \
+         config file), or graph free, used and bad blocks over time",
+    ),
+    Gap,
+    Command(
+        "1 [nand, spare]",
+        "Dump the console's NAND to 'nand.bin' and 'spare.bin', or [nand] and [spare] if both are provided; \
+         add '--crcs' to also save a CRC of each block to [nand].crcs, for 'spotcheck', and '--heroic' to \
+         read blocks that fail their ECC again and vote on them, as 'X --heroic' does; '--manifest' saves \
+         [nand].sha256 with the files' hashes and the console's BBID, which '2' checks against; \
+         '--algo list' hashes both with those algorithms rather than CRC-32 and SHA-256\n\
+         Names ending in '.gz' or '.zst' are saved gzip- or zstd-compressed (as are those given to \
+         'F', 'K' and '3'); the hashes, CRCs and sizes are of the uncompressed data\n\
+         The config file's 'hooks.post_dump' commands are run afterwards, one by one, with {nand}, \
+         {spare}, {crcs}, {manifest}, {bbid} and {timestamp} filled in; see 'hooks' in the config file \
+         for their timeout and whether one failing fails the dump",
+    ),
+    Gated(
+        Writing,
+        "2 [nand, spare], [ranges]",
+        "Write the console's NAND from 'nand.bin' and 'spare.bin', or [nand] and [spare] if both are provided\n\
+         [ranges] can optionally be specified, to only write certain blocks or ranges of blocks; \
+         e.g. \"2 0-0x100,4075\" writes blocks 0 - 0x100 (exclusive, i.e. not including block 0x100 itself), \
+         and block 4075. Make sure to prefix hexadecimal block numbers with '0x'!\n\
+         Ranged writes show per-range progress and a summary; add '--verify' to read back each block, \
+         and '--report path' with it to save the results as JSON\n\
+         For a nand-only image, give '-' as [spare] (or add '--no-spare' and leave [spare] out): spare data is generated from \
+         each block, keeping bad block markers from the console; this is refused if the generated ECC \
+         doesn't match what's already on the console's card\n\
+         Blocks whose spare data's SA marker doesn't match what they hold (SKSA or not) are refused; \
+         add '--force' to write them anyway\n\
+         With 'set strict-writes on', '--manifest file' (in sha256sum's format) must list the files' \
+         hashes, '--bbid BBID' must match the console's, and [ranges] must be given\n\
+         A dump from another console (by its manifest, or [nand].sha256 from '1 --manifest', or else by the \
+         BBID in its tickets) is warned about, or with strict-writes refused; add '--cross-console' if \
+         that's deliberate\n\
+         A whole image whose FS blocks only check out byte-swapped (16-bit words) is refused; \
+         add '--byteswap' to swap [nand] back as it's written\n\
+         An image from a different size of card is refused, with what can be written instead: the \
+         SKSA, and from a smaller card the file data and FS region (moved to the end of the card); \
+         choose with '--restore sksa,data,fs' or at the prompt, or give [ranges] that fit both cards\n\
+         Add '--interactive' instead of [ranges] to build the selection from a map of the image, \
+         by ranges and by region ('sksa', 'fs'), confirming it (and its [ranges] form) at the end\n\
+         A write covering more than half the card (see '[wear]' in the config file) asks again first; \
+         add '--prescan' to compare with the console and be offered the [ranges] that differ, or \
+         '--accept-wear' to skip the question\n\
+         A write of 16 MiB or more (see 'set preflight') checks the link first, as 'preflight' \
+         does, and asks again if it scores badly (with strict-writes, refuses); add \
+         '--accept-link' to skip the question\n\
+         An image whose bad blocks (by its spare data and FAT) aren't those the console's FAT marks is \
+         refused, saying which differ; add '--skip-foreign-bad' to leave out the blocks either marks \
+         bad, or '--remap' to move files off the console's bad blocks, rewriting the image's FS to match\n\
+         [nand] and [spare] can be .hex or .srec images with [ranges]; records outside them are refused, \
+         or '.gz' or '.zst' files, which are decompressed first",
+    ),
+    Command(
+        "preflight",
+        "Check the link to the console: read a few dozen blocks from across the card, timing them \
+         and retrying any that don't read cleanly, and ask for its stats twice, then give the link \
+         a score out of 100, with what cost it points and what to do about them",
+    ),
+    Command(
+        "triage [--save dir]",
+        "Check a console's card without writing to it: FS generations and consistency, the SKSA, \
+         bad blocks and read stability, with a conclusion; --save keeps the FS region and SKSA in [dir]",
+    ),
+    Gated(
+        Writing,
+        "relocate blkno...",
+        "Move the data off failing blocks: each one that's part of a file is read (retrying until its \
+         ECC matches), copied to a free block and swapped into the file's chain in a new FS generation, \
+         then marked bad; blocks not in a file are just marked bad. Each step is logged to the write \
+         journal (write-journal.log in the config directory). With '--heroic', a block that never reads \
+         cleanly is rebuilt by voting across its reads, if the vote passes the ECC",
+    ),
+    Command(
+        "badblocks audit [ranges] [--test]",
+        "Check the blocks marked bad in their spare data (in [ranges], or the whole card): each is read \
+         several times and its marker called confirmed, if anything about it is off, or suspicious, if it \
+         reads cleanly and the same every time. With '--test' (needs writing), a suspicious block also \
+         has test patterns written to it and its contents put back. The findings are kept per console \
+         in the config directory",
+    ),
+    Command(
+        "badblocks list",
+        "Show the last audit's findings for this console, and which blocks are treated as good",
+    ),
+    Command(
+        "badblocks trust|untrust ranges",
+        "Treat blocks the audit found suspicious as good (or stop doing so): writes, relocation and \
+         transactions then place data on them, and write them without the marker",
+    ),
+    Command(
+        "history [nand]",
+        "Show what changed between each of the FS generations kept in the FS region, oldest first, \
+         from the console (or mounted dump) or the NAND dump [nand]",
+    ),
+    Command(
+        "status [--debug]",
+        "Show the selected console or mounted dump, what's known about its card, and any open \
+         transaction, and whether the console was opened at startup; '--debug' adds how often file listings were served from the cached FS block",
+    ),
+    Command(
+        "session save file",
+        "Save the session's options, mounted dump, open transaction, working directory and \
+         selected console to [file], with the digests of the local files they use",
+    ),
+    Command(
+        "session load file",
+        "Restore a saved session; the console is selected again if it's connected, but writes \
+         to protected regions need unlocking again. A mounted dump that's changed since it was \
+         saved is pointed out, and an open transaction whose files have changed needs 'txn \
+         confirm' before it's committed (with strict-writes on, those uploads are left out)",
+    ),
+    Command(
+        "session read-only-attest",
+        "Seal the console for the rest of the session, for gathering evidence: only commands that \
+         read from it can be used, and every command typed is logged, each entry hashed with the \
+         one before it. The connection has to be initialised ('B') first",
+    ),
+    Command(
+        "attest report file",
+        "Save the attested session's log to [file], with a statement that nothing could have \
+         written to the console, signed with the key in the file 'attest_key' in the config file \
+         names, if it's set",
+    ),
+    Command(
+        "report save file",
+        "Save the last error, recent commands and their outcomes, and the session's options to \
+         [file] for a bug report (no file contents are included, nor the command notify-command \
+         runs), with the known error it matched if it's one of them.\n\
+         Known errors are printed with what usually fixes them; more can be added, with their \
+         remedies, in 'signatures.toml' in the config directory",
+    ),
+    Command(
+        "note set text",
+        "Keep a note about this console (by BBID) in the config directory's 'notes' folder",
+    ),
+    Command(
+        "note tag key [value]",
+        "Set a tag on this console's note, or remove it if no value is given",
+    ),
+    Command(
+        "note show",
+        "Print this console's note; 'l', 'device' and startup selection show it too",
+    ),
+    Command(
+        "note list",
+        "List every console with a note",
+    ),
+    Command(
+        "dupes [--interactive]",
+        "Find files on the card with identical contents and how much space deleting the extra copies \
+         would free; --interactive offers to delete chosen copies from each set",
+    ),
+    Command(
+        "verify [nand] [ranges]",
+        "Compare the console's NAND (or [ranges] of it) with 'nand.bin', or [nand] (decompressed first \
+         if it ends in '.gz' or '.zst'), without writing; \
+         add '--report path' to save the results, hashes, card stats and BBID as JSON",
+    ),
+    Command(
+        "acceptance imagedir",
+        "Check a freshly flashed console against [imagedir]/nand.bin: verify the whole card, fsck its \
+         FS, compare the SKSA's hash, and compare its files with [imagedir]/files.txt (a '5 --porcelain' \
+         listing) or else the image's own; prints one verdict, saves a report named by BBID and time, \
+         and makes {PROG_NAME} exit non-zero at the end if any console failed. Set which checks run, \
+         'max_mismatches', 'extra_files_ok' and 'report_dir' in the config's [acceptance] section",
+    ),
+    Command(
+        "spotcheck crcs",
+        "Read a random sample of blocks and compare them with a [crcs] file saved by '1 --crcs'; \
+         '--blocks N' sets the sample size (default 64), '--seed S' repeats an earlier sample; \
+         '--algo list' only checks the file's columns for those algorithms (as does 'set hash-algos')",
+    ),
+    Command(
+        "scrub [--report path]",
+        "Read every block the FS marks free (retrying those that don't read cleanly), without writing, \
+         and list those that needed a retry or correction or couldn't be read, with what to do about them; \
+         '--report path' saves the results as JSON, and blocks that couldn't be read can be marked bad \
+         (with 'relocate') at the end",
+    ),
+    Command(
+        "jobs",
+        "List the background jobs: end 'verify' or 'scrub' with ' &' to run it in the background, \
+         holding the console until it's done while commands that don't need the console (calc, \
+         status, note list and the like) carry on. What a job says is shown when it finishes",
+    ),
+    Command(
+        "fg [id]",
+        "Wait for background job [id] (or the newest), following its progress; Ctrl+C stops it",
+    ),
+    Command(
+        "3 [--continue] file",
+        "Read [file] from the console; if it fails partway, what was read is kept in [file].partial, \
+         and --continue resumes from there (not into a compressed '.gz' or '.zst' name); the config file's 'hooks.post_read' commands are run \
+         afterwards with {file} filled in. The console keeps no file times, but one read from a \
+         mounted dump whose manifest says when it was dumped is dated then",
+    ),
+    Command(
+        "3 --with-spare file out spareout",
+        "Read [file] block by block into [out], with the spare data of each of its blocks in \
+         [spareout] and its block chain in [out].chain.json, for forensics; from a dated dump, \
+         the files are dated as '3' does, and the sidecar records the time",
+    ),
+    Command(
+        "cat file",
+        "Print the start of [file] from the console: as text if it is (with control characters escaped), \
+         or as a hex dump; only the blocks needed are read. '--max-bytes N' sets how much (default 4096, \
+         or 'cat_max_bytes' in the config file), and '--strings' lists its runs of printable characters",
+    ),
+    Gated(
+        Writing,
+        "4 [--blocks] [--continue] file",
+        "Write [file] to the console, after checking it as 'lint' does (unless 'set lint off'); \
+         a large file has the link checked first, as '2' does, and it's read back to check it. \
+         WriteFile sends it in one go, so one that fails has to start again; with --blocks it's \
+         written a block at a time, and trying again after it fails partway offers to carry on \
+         from there (--continue does without asking), as long as neither [file] nor the card's FS \
+         has changed. An empty [file] is refused unless '--allow-empty' is added. The card's FS keeps \
+         no times, so '--preserve-times' is refused rather than dropping [file]'s",
+    ),
+    Gated(
+        Writing,
+        "patch file patchfile",
+        "Apply an IPS or BPS patch to [file] on the console (or a dump mounted with --rw): it's read, \
+         patched in memory and written back, then read again to check it; BPS patches are checked \
+         against the file they're for and the result they give. The patched file is written beside \
+         the old one and swapped in once it checks out, so it must fit in the free blocks",
+    ),
+    Command(
+        "patch --local in patchfile out",
+        "Apply a patch to the local file [in], writing the result to [out]",
+    ),
+    Gated(
+        Writing,
+        "patch --blocks range patchfile",
+        "Apply a patch to [range] of blocks (e.g. 0x100-0x120) of a dump mounted with --rw, \
+         keeping their size; their spare data isn't changed",
+    ),
+    Command(
+        "5 [--porcelain]",
+        "List all files currently on the console. When the output isn't a terminal, or with \
+         '--porcelain', it's a stable format for scripts: a line per file, sorted bytewise by name, \
+         of the name, a tab, and the size in bytes, each ending in LF",
+    ),
+    Gated(
+        Writing,
+        "6 file",
+        "Delete [file] from the console",
+    ),
+    Gated(
+        Writing,
+        "7 from to",
+        "Rename [from] to [to]",
+    ),
+    Gated(
+        Writing,
+        "txn begin",
+        "Start gathering file operations to carry out together, all or none",
+    ),
+    Gated(
+        Writing,
+        "txn add command",
+        "Add an operation: '4 file', '6 file' or '7 from to', as the commands themselves take",
+    ),
+    Gated(
+        Writing,
+        "txn show",
+        "List the operations added so far",
+    ),
+    Gated(
+        Writing,
+        "txn confirm",
+        "Allow a transaction restored by 'session load' to be committed, though its files have \
+         changed since it was saved",
+    ),
+    Gated(
+        Writing,
+        "txn commit",
+        "Check the operations together (names, space, system files) and carry them out: the new \
+         files' data is written to free blocks, then a single new FS generation, so a failure before \
+         that last write leaves the card as it was. Blocks freed by the transaction's own deletes \
+         can't be used until it's committed. On a console, each step goes in the write journal",
+    ),
+    Gated(
+        Writing,
+        "txn abort",
+        "Abandon the transaction without changing anything",
+    ),
+    Gated(
+        Writing,
+        "sandbox on|diff|off",
+        "Try out changes without making them: after 'sandbox on', changes to the console's files (by \
+         '4', '6', '7', 'patch', 'txn commit', 'provision apply' and the rest) are only made in memory, \
+         where later commands see them, and anything they haven't changed is read from the console for \
+         as long as its card stays as it was. 'sandbox diff' lists what would have changed on the card, \
+         and 'sandbox off' discards it all. Commands that change the console other than through its \
+         files ('Y', '2', 'J', 'relocate'), or switch to another console or a dump, are refused until then",
+    ),
+    Gated(
+        Writing,
+        "clean",
+        "Offer to clean up the temporary files {PROG_NAME} left on the card when an operation was \
+         interrupted (which 'B' also checks for), going by the write journal: deleted, or put in \
+         place of the file they were replacing if that's gone; then list empty and duplicate files, \
+         which may be junk, without touching them",
+    ),
+    Command(
+        "hash file",
+        "Print the SHA-256 of [file] on the console, as sha256sum does; '--algo list' hashes with \
+         others (md5, sha1, sha256, crc32, comma-separated) instead, and '--format hex|sum|json' \
+         prints the bare digests, sha256sum-style lines (BSD-style ones for several algorithms), \
+         or a JSON object",
+    ),
+    Command(
+        "stat file",
+        "Print every field of [file]'s FS entry, with its offset and bytes, including the ones \
+         nothing knows the meaning of yet (shown as 'unknown')",
+    ),
+    Gated(
+        Tui,
+        "browse",
+        "Open a full-screen list of the console's files, with their sizes and titles, to download (d), \
+         upload (u, choosing from the current directory), delete (x), rename (r) and hash (h) them \
+         with the keys, as '3', '4', '6', '7' and 'hash' would; Esc goes back to the prompt",
+    ),
+    Gap,
+    Command(
+        "provision check dir",
+        "Compare the console's files with those in [dir]: which are missing, extra or different \
+         (going by SHA-256, or the first of '--algo list', with hashes of the console's files \
+         cached between runs)",
+    ),
+    Gated(
+        Writing,
+        "provision apply dir",
+        "Make the console's files exactly those in [dir], with the fewest uploads and deletes; \
+         system files (*.sys) are never touched. The plan is shown first and carried out once \
+         confirmed, or straight away with '--yes' (for scripts); '--dry-run' only shows it. The \
+         result is checked against [dir] at the end",
+    ),
+    Gap,
+    Command(
+        "lint files...",
+        "Check that [files] can be uploaded to the console: names, sizes, and formats where known \
+         (.app files named for a content ID, and not a plain ROM or blank); free space is checked \
+         against the last 'C', or the card as it was left by the last change this session made",
+    ),
+    Command(
+        "calc expr",
+        "Work out an offset or size: numbers (0x for hex) with units (bytes, pages, blocks, KiB, \
+         MiB) before or after them, + - * / and parentheses, and 'to <unit>' or 'offset' at the \
+         end, e.g. 'calc 0x1F4000 to blocks' or 'calc block 500 offset'; the result is given in \
+         every unit, with where it lands on the card (the console's, the mounted dump's, or else a \
+         64 MiB one, as it says)",
+    ),
+    Command(
+        "organize [dir]",
+        "Move the dumps in [dir] (or the current directory) into archive/<BBID>/<date>/, pairing each \
+         NAND with its spare by manifest or by the spare's ECC, and asking which console a dump \
+         without a manifest is from; anything that can't be paired for certain is left. 'organize \
+         --undo [dir]' puts back what the last run moved",
+    ),
+    Command(
+        "genimage spec nand spare",
+        "Generate a test NAND image and its spare data from [spec], a TOML file giving the card's \
+         'blocks', the files ([[file]] with a 'name', and a 'path' or a 'pattern' (zeros, ones, \
+         counting or random) and 'size'), 'bad_blocks', how many FS 'generations', whether to \
+         'scatter' the files' blocks, and any [[corrupt]]ions (kind 'truncated-chain' with a \
+         'file', or 'bad-checksum'). The same spec and 'seed' always give the same image. What's \
+         on it is saved to <nand>.manifest.json",
+    ),
+    Gap,
+    Command(
+        "ticket backups",
+        "List the backups of the console's ticket.sys, which is backed up whenever it's about to change",
+    ),
+    Gated(
+        Writing,
+        "ticket restore timestamp",
+        "Put the backup of ticket.sys from [timestamp] back on the console",
+    ),
+    Gap,
+    Command(
+        "fingerprint [nand spare]",
+        "Print an identity for the console's NAND (read in full), or a dump's, for comparing dumps: \
+         versioned, and the same whatever the FS region holds, bad blocks and the card's size; \
+         '--algo list' gives one for each algorithm instead of SHA-256's",
+    ),
+    Command(
+        "dumpinfo nand spare",
+        "List the games, tickets and saves in an offline dump, without a console",
+    ),
+    Command(
+        "export dat file [nand spare]",
+        "Save a datafile (Logiqx XML, for ROM managers) of the console's files, or a dump's: each \
+         file's size, CRC-32, SHA-1 and title, with the BBID and the date it was read",
+    ),
+    Command(
+        "mount [--rw] nand spare",
+        "Use an offline dump in place of the console for I, L, F, X, C, 1, 3 and 5; \
+         with --rw, 4, 6 and 7 change the dump in memory too",
+    ),
+    Command(
+        "commit",
+        "Write the changes to a dump mounted with --rw back to its files; the first commit keeps the \
+         originals as .bak (or .bak1 and so on, so an earlier backup isn't replaced)",
+    ),
+    Command(
+        "unmount",
+        "Go back to using the console, discarding uncommitted changes after asking",
+    ),
+    Gap,
+    Command(
+        "convert in out [--base a]",
+        "Convert between a raw binary and an Intel HEX (.hex) or SREC (.srec) image, going by the \
+         extensions; a binary made from records starts at address 0, and --base sets the address \
+         records made from a binary start at; '--byteswap' swaps the bytes of each 16-bit word of \
+         the binary, and with it both files can be raw binaries",
+    ),
+    Command(
+        "fsdiff old new",
+        "Compare two FS blocks dumped with 'F': files added, removed, renamed, resized and moved",
+    ),
+    Gap,
+    Command(
+        "backup incremental dir",
+        "Read the whole card and store it in [dir] as a new generation: the first is a full dump, and \
+         each after it holds only the blocks changed since the one before",
+    ),
+    Command(
+        "backup restore dir [nand] [spare]",
+        "Rebuild the newest generation in [dir] (or '--generation N') as 'nand.bin' and \
+         'spare.bin', or [nand] and [spare], checking every block against its recorded hash",
+    ),
+    Command(
+        "backup prune dir --keep N",
+        "Remove all but the newest N generations from [dir], first making the oldest one kept a full dump",
+    ),
+    Command(
+        "backup list dir",
+        "List the generations in [dir]",
+    ),
+    Gap,
+    Command(
+        "kit create dir",
+        "Save a recovery kit of the console to [dir] (or into the sink's archive): its SKSA, its FS \
+         region, its system files (*.sys), the card's stats and the console's BBID and serial \
+         number, with a manifest of their hashes. Games aren't included, so it's small and quick",
+    ),
+    Gated(
+        Writing,
+        "kit restore dir [items...]",
+        "Put a recovery kit back on the console: the SKSA, then the FS region, then the files, or \
+         only the [items] named ('sksa', 'fs' or a file's name), asking before each and checking \
+         each as it's written. Nothing is written unless every part asked for matches the manifest",
+    ),
+    Gap,
+    Command(
+        "dedupe-archive dir",
+        "Replace the NAND dumps in [dir] with indexes into a shared store of their blocks, so blocks \
+         that are the same across dumps are only kept once. Only dumps with a manifest of their own \
+         (from '1 --manifest') that they still match are taken; spare files and anything else are \
+         left alone, and each dump is checked before it's removed",
+    ),
+    Command(
+        "rehydrate index out",
+        "Rebuild the dump described by [index] (a .dedupe file) to [out], checking every block",
+    ),
+    Gap,
+    Command(
+        "set [option value]",
+        "Set a session option, or list the current options if none is given\n\
+         progress-events on|off: report progress, and errors, as JSON lines on stderr instead of progress bars\n\
+         led-feedback on|off: flash the LED during dumps, writes and reads, leaving it on if they fail\n\
+         lint on|off: check files with 'lint' before uploading them with '4'\n\
+         keepalive secs|off: while waiting at the prompt, ping the console every [secs], reconnecting it \
+         if it doesn't answer\n\
+         sink files|tar:<archive>: save downloaded files as plain files, or into a .tar.gz\n\
+         notify-command command|off: run [command] when a dump, write, verify or read taking at least \
+         notify-threshold seconds (default 60) finishes, with AULON2_OPERATION, AULON2_BBID, \
+         AULON2_DURATION_SECS, AULON2_STATUS, AULON2_OUTPUTS and AULON2_ERROR set\n\
+         auto-clean on|off: after 'B', clean up the temporary files interrupted operations left on the \
+         card without asking\n\
+         hash-algos list|default: what 'hash', 'fingerprint', 'provision', 'spotcheck' and '1''s \
+         [nand].crcs and [nand].sha256 hash with (md5, sha1, sha256, crc32, comma-separated) when a \
+         command isn't given '--algo'; 'default' leaves each its own\n\
+         manifest on|off: have '1' write [nand].sha256 as if given '--manifest'\n\
+         compress gz|zst|off: have '1' save the dump compressed, adding .gz or .zst to its names\n\
+         verify-writes on|off: have '2' read back what it writes as if given '--verify'\n\
+         strict-writes on|off: refuse anything that writes to the card ('2', 'Y', '4', '6', '7', \
+         'patch', 'relocate', 'clean', 'dupes --interactive', 'txn commit', 'ticket restore', \
+         'provision apply', 'kit restore' and 'badblocks audit --test') unless '--bbid BBID' matches \
+         the console's, '--manifest file' lists the files it writes from with matching hashes, and \
+         '2', 'kit restore' and 'badblocks audit' are given what to write\n\
+         dry-run trace|off: make none of the calls of the console that 'I', 'L', '5', 'F', 'X', \
+         'C', '1', '3', 'cat', 'hash', 'stat', '4', '6' and '7' would, but list them after the command, \
+         with the made-up replies it carried on with marked as placeholders; nothing is saved, and \
+         other commands that use the console are refused\n\
+         preflight MiB|off: check the link before writes of at least this many MiB (16 to start \
+         with)\n\
+         preflight-score N: the score out of 100 the link needs for those writes to go ahead \
+         without asking (70 to start with)\n\
+         require-backup hours|off: refuse '2' over the whole card, or its SKSA or FS region, unless \
+         a dump of the same console from the last [hours] hours, with a manifest its files still \
+         match, is in backup-dir; '2' offers to make one first\n\
+         backup-dir dir|default: where require-backup looks for dumps, and the folders below it \
+         (the current directory by default)\n\
+         post-write-fscheck on|off: after a write that reaches into the FS region, read the region \
+         back and check that its newest FS parses, is the one written, and lists its files; a \
+         failed check fails the write (on to start with)",
+    ),
+    Command(
+        "preset [name|off|show name]",
+        "Use a preset, a named set of options for a kind of work: 'archive' (manifest, zstd \
+         compression, SHA-256 and MD5), 'debug' (none of that) and 'station' (strict-writes and \
+         verify-writes), and any in the config file's [presets]; with no name, list them. Options \
+         come from the defaults, then the preset, then the config file's [options], then this \
+         session's 'set' commands, each over the last. 'show' lists the options a preset would \
+         give; '--preset name' at startup uses one from the start",
+    ),
+    Gap,
+    Command(
+        "reset-usb",
+        "Reset the selected console's USB port and reopen it, as if it had been replugged; with \
+         'set auto-reset on', 'B' does this itself when Init fails in a way a reset may fix",
+    ),
+    Command(
+        "retry-usb",
+        "Try to initialise the USB subsystem again, if it failed at startup",
+    ),
+    Gap,
+    Command(
+        "lock",
+        "Require the full BBID confirmation again for writes to the SKSA or FS region. Each \
+         such confirmation comes after a preview of what the write changes: the FS's entries, \
+         the SKSA's components, the tickets added and removed, or a block's changed bytes",
+    ),
+    Gap,
+    Command(
+        "notify test",
+        "Run the notify-command with a test event, to check it works",
+    ),
+    Command(
+        "caps [--json]",
+        "List the optional features this build has, and the commands it doesn't have because of them; \
+         '--json' lists every command's usage with whether it's available and what it needs",
+    ),
+    Command(
+        "selftest",
+        "Check the offline logic (ECC, FS parsing, block ranges, sizes, byte order) against built-in \
+         test vectors; run '{PROG_NAME} --selftest' to do this and exit non-zero on failure",
+    ),
+    Note("('{PROG_NAME} --profile name' limits the commands to those allowed by the config file's [profiles.name], and pins the options it sets so 'set' can't change them)"),
+    Command(
+        "h [command]",
+        "Print this help, or only the part about [command]",
+    ),
+    Command(
+        "?",
+        "Print copyright and licensing information",
+    ),
+    Command(
+        "finish",
+        "Close the connection, then reopen it to check the FS the console comes back up with is the \
+         newest on the card, is consistent, and has this session's changes, before disconnecting",
+    ),
+    Command(
+        "q",
+        "Quit {PROG_NAME}, offering to run 'finish' first if the console's card was changed, and \
+         asking first if a dump mounted with --rw has changes that haven't been committed",
+    ),
+];
+
+fn render_item(item: &Help, width: Option<usize>) -> String {
+    match item {
+        Command(usage, text) | Gated(_, usage, text) => {
+            let text = text.replace("{PROG_NAME}", PROG_NAME);
+            let lead = format!("    {usage:<USAGE_WIDTH$} - ");
+            match width {
+                Some(w) if lead.width() + MIN_DESCRIPTION > w => format!(
+                    "    {usage}\n{}",
+                    wrap(&text, width, NARROW_HANG, NARROW_HANG)
+                ),
+                _ => wrap(&text, width, &lead, HANG),
+            }
+        }
+        Note(text) => wrap(
+            &text.replace("{PROG_NAME}", PROG_NAME),
+            width,
+            "    ",
+            "    ",
+        ),
+        Gap => String::new(),
+    }
+}
+
+fn unavailable_note(feature: Feature) -> String {
+    format!(
+        "Not available in this build; rebuild with `-F {}` to use these:",
+        feature.name()
+    )
+}
+
+// the help, as a build with the features `built` says it has would show it: the commands it
+// doesn't have are left out of the list and named in a section of their own at the end
+fn render_for(width: Option<usize>, built: impl Fn(Feature) -> bool) -> String {
+    let mut help = "Commands:\n\n".to_string();
+    for item in HELP.iter().filter(|item| item.missing(&built).is_none()) {
+        help += &render_item(item, width);
+        help.push('\n');
+    }
+    for feature in Feature::ALL {
+        let usages = HELP
+            .iter()
+            .filter(|item| item.missing(&built) == Some(feature))
+            .filter_map(Help::usage)
+            .collect::<Vec<_>>();
+        if !usages.is_empty() {
+            help += &format!("\n{}\n", wrap(&unavailable_note(feature), width, "", ""));
+            help += &wrap(&usages.join(",\n"), width, "    ", "    ");
+            help.push('\n');
+        }
+    }
+    help
+}
+
+// the whole of the help
+pub fn render(width: Option<usize>) -> String {
+    render_for(width, Feature::built)
+}
+
+fn entries_for(
+    name: &str,
+    width: Option<usize>,
+    built: impl Fn(Feature) -> bool,
+) -> Option<String> {
+    let entries = HELP
+        .iter()
+        .filter(|item| item.name() == Some(name))
+        .map(|item| match item.missing(&built) {
+            None => render_item(item, width) + "\n",
+            Some(feature) => format!(
+                "{}\n{}\n",
+                render_item(item, width),
+                wrap(
+                    &format!(
+                        "(not available in this build; rebuild with `-F {}` to use it)",
+                        feature.name()
+                    ),
+                    width,
+                    HANG,
+                    HANG
+                )
+            ),
+        })
+        .collect::<String>();
+    (!entries.is_empty()).then_some(entries)
+}
+
+// the help for the commands named `name` (there may be several forms), or None if there's no
+// such command
+pub fn entries(name: &str, width: Option<usize>) -> Option<String> {
+    entries_for(name, width, Feature::built)
+}
+
+// the command `typed` was most likely meant to be, among those the build has; single letters
+// are too close to each other to guess at, except by case
+fn suggest_for(typed: &str, built: impl Fn(Feature) -> bool) -> Option<&'static str> {
+    HELP.iter()
+        .filter(|item| item.missing(&built).is_none())
+        .filter_map(Help::name)
+        .filter(|name| *name != typed)
+        .map(|name| (distance(&typed.to_lowercase(), &name.to_lowercase()), name))
+        .filter(|&(d, name)| d == 0 || (typed.len() >= 3 && name.len() >= 3 && d <= 2))
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, name)| name)
+}
+
+pub fn suggest(typed: &str) -> Option<&'static str> {
+    suggest_for(typed, Feature::built)
+}
+
+// a command's form, and whether this build has it
+#[derive(Serialize)]
+pub struct Capability {
+    pub usage: &'static str,
+    pub available: bool,
+    pub needs: Option<Feature>,
+}
+
+#[derive(Serialize)]
+pub struct Capabilities {
+    pub version: &'static str,
+    pub features: BTreeMap<&'static str, bool>,
+    pub commands: Vec<Capability>,
+}
+
+// what 'caps' reports: the build's features and every command's availability
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        version: PROG_VER,
+        features: Feature::ALL.map(|f| (f.name(), f.built())).into(),
+        commands: HELP
+            .iter()
+            .filter_map(|item| {
+                let needs = match item {
+                    Gated(feature, ..) => Some(*feature),
+                    _ => None,
+                };
+                Some(Capability {
+                    usage: item.usage()?,
+                    available: needs.is_none_or(Feature::built),
+                    needs,
+                })
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Result};
+
+    #[test]
+    fn help() -> Result<()> {
+        let lines = |s: &str| s.lines().map(str::to_string).collect::<Vec<_>>();
+        let everything = |_| true;
+        let entries = |name, width| entries_for(name, width, everything);
+
+        // as the help has always looked in a wide terminal
+        let wide = entries("Q", Some(120)).unwrap_or_default();
+        if wide != "    Q                         - Close USB connection to the console\n" {
+            bail!("'Q' was rendered as {wide:?}");
+        }
+        let mount = lines(&entries("mount", Some(100)).unwrap_or_default());
+        if mount
+            != [
+                "    mount [--rw] nand spare   - Use an offline dump in place of the console for I, L, F, X, C, 1, 3",
+                "                    and 5; with --rw, 4, 6 and 7 change the dump in memory too",
+            ]
+        {
+            bail!("'mount' was rendered as {mount:?}");
+        }
+
+        // every line fits an 80-column terminal, unless it's a single word that can't
+        for width in [80, 60, 40] {
+            for line in render_for(Some(width), everything).lines() {
+                if line.width() > width && line.split_whitespace().count() > 1 {
+                    bail!("at {width} columns, this line is too long:\n{line}");
+                }
+            }
+        }
+
+        // in a narrow pane, the description goes under the usage
+        let narrow = lines(&entries("6", Some(40)).unwrap_or_default());
+        if narrow != ["    6 file", "        Delete [file] from the console"] {
+            bail!("'6' was rendered at 40 columns as {narrow:?}");
+        }
+
+        // piped, each paragraph is one line; '2' has one for each thing it can do
+        let piped = entries("2", None).unwrap_or_default();
+        if !piped.starts_with("    2 [nand, spare], [ranges] - Write the console's NAND")
+            || piped.lines().count() != 14
+        {
+            bail!("'2' was rendered unwrapped as\n{piped}");
+        }
+        let full = render_for(None, everything);
+        if !full.contains(&format!("Quit {PROG_NAME},")) || full.contains("{PROG_NAME}") {
+            bail!("the program's name wasn't filled in");
+        }
+
+        // commands with more than one form have them all shown
+        if entries("txn", None).map(|e| e.lines().count()) != Some(6)
+            || entries("nope", None).is_some()
+        {
+            bail!("looking up commands by name didn't find the right entries");
+        }
+
+        // a build without writing lists those commands apart, after everything it can do
+        let read_only = |f| f != Writing;
+        let help = render_for(None, read_only);
+        let section = help
+            .find(&unavailable_note(Writing))
+            .ok_or_else(|| anyhow::anyhow!("the help without writing has no section for it"))?;
+        let (usable, unusable) = help.split_at(section);
+        for usage in [
+            "4 [--blocks] [--continue] file",
+            "2 [nand, spare], [ranges]",
+            "txn commit",
+            "provision apply dir",
+        ] {
+            if usable.contains(&format!("    {usage} ")) || !unusable.contains(usage) {
+                bail!("without writing, '{usage}' wasn't in the unavailable section");
+            }
+        }
+        for usage in [
+            "5 [--porcelain]",
+            "provision check dir",
+            "browse",
+            "patch --local in patchfile out",
+        ] {
+            if !usable.contains(&format!("    {usage} ")) || unusable.contains(usage) {
+                bail!("without writing, '{usage}' wasn't listed as usable");
+            }
+        }
+        if full.contains("Not available")
+            || render_for(None, |f| f != Tui)
+                .matches("Not available")
+                .count()
+                != 1
+        {
+            bail!("the unavailable sections weren't where they should be");
+        }
+        let four = entries_for("4", None, read_only).unwrap_or_default();
+        if !four.contains("Write [file] to the console")
+            || !four.contains("rebuild with `-F writing`")
+        {
+            bail!("'h 4' without writing gave\n{four}");
+        }
+
+        // suggestions: typos, case, and only commands the build has
+        let suggested = |typed, built: fn(Feature) -> bool| suggest_for(typed, built);
+        if suggested("provison", everything) != Some("provision")
+            || suggested("finsh", everything) != Some("finish")
+            || suggested("b", everything) != Some("B")
+            || suggested("z", everything).is_some()
+            || suggested("frobnicate", everything).is_some()
+        {
+            bail!("commands weren't suggested as they should be");
+        }
+        if suggested("relocat", everything) != Some("relocate")
+            || suggested("relocat", read_only).is_some()
+        {
+            bail!("a command the build doesn't have was suggested");
+        }
+        if suggested("browze", |f| f != Tui).is_some() {
+            bail!("'browse' was suggested without the file browser");
+        }
+
+        // and what this build says about itself, compiled both ways
+        let caps = capabilities();
+        let four_available = caps
+            .commands
+            .iter()
+            .find(|c| c.usage == "4 [--blocks] [--continue] file")
+            .map(|c| c.available);
+        #[cfg(feature = "writing")]
+        if four_available != Some(true)
+            || caps.features.get("writing") != Some(&true)
+            || render(None).contains(&unavailable_note(Writing))
+            || suggest("relocat") != Some("relocate")
+        {
+            bail!("a build with writing didn't offer its commands");
+        }
+        #[cfg(not(feature = "writing"))]
+        if four_available != Some(false)
+            || caps.features.get("writing") != Some(&false)
+            || !render(None).contains(&unavailable_note(Writing))
+            || suggest("relocat").is_some()
+        {
+            bail!("a build without writing offered commands it doesn't have");
+        }
+        if caps
+            .commands
+            .iter()
+            .any(|c| c.needs.is_none() && !c.available)
+        {
+            bail!("a command that needs nothing was unavailable");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::{bail, Result};
+
+use crate::cancel::CancelToken;
+use crate::ecc::EccCheck;
+use crate::player::Player;
+use crate::spare::ecc_check;
+
+// '--heroic' reads: a marginal block often comes back with different bits wrong each time, so
+// when a read fails its ECC the block is read again several times and each bit taken as what
+// most of the reads say. The vote is only used if it then passes the ECC in its (also voted)
+// spare data. The ECC only covers the first page, so that's all the check can prove; the rest
+// of the block is the vote's best guess.
+
+// reads after the first, when it fails its ECC
+pub const HEROIC_READS: usize = 8;
+
+// fewer reads than this can't outvote a bad one
+const MIN_VOTERS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadOutcome {
+    // the first read (or a retry) passed its ECC
+    Clean,
+    // the vote across this many reads passed its ECC, differing from the first read in this many
+    // bits
+    Rescued { reads: usize, bits: u32 },
+    // nothing passed its ECC; the data is the first read that succeeded
+    Unrescued { reads: usize },
+}
+
+// each bit as most of `reads` have it; a tie goes to the first read
+pub fn majority(reads: &[&[u8]]) -> Vec<u8> {
+    let Some(first) = reads.first() else {
+        return vec![];
+    };
+    (0..first.len())
+        .map(|i| {
+            (0..8).fold(0u8, |byte, bit| {
+                let ones = reads.iter().filter(|r| r[i] >> bit & 1 != 0).count();
+                let set = match (ones * 2).cmp(&reads.len()) {
+                    std::cmp::Ordering::Greater => true,
+                    std::cmp::Ordering::Less => false,
+                    std::cmp::Ordering::Equal => first[i] >> bit & 1 != 0,
+                };
+                byte | (set as u8) << bit
+            })
+        })
+        .collect()
+}
+
+pub fn differing_bits(a: &[u8], b: &[u8]) -> u32 {
+    a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+}
+
+// votes over the reads of a block (data and spare data), returning the result if it passes its
+// ECC, with how many bits it differs from the first read
+pub fn vote(reads: &[(Vec<u8>, Vec<u8>)]) -> Option<(Vec<u8>, Vec<u8>, u32)> {
+    if reads.len() < MIN_VOTERS {
+        return None;
+    }
+    let data = majority(&reads.iter().map(|(d, _)| &d[..]).collect::<Vec<_>>());
+    let spare = majority(&reads.iter().map(|(_, s)| &s[..]).collect::<Vec<_>>());
+    if ecc_check(&data, &spare) != EccCheck::Clean {
+        return None;
+    }
+    let bits = differing_bits(&data, &reads[0].0) + differing_bits(&spare, &reads[0].1);
+    Some((data, spare, bits))
+}
+
+// votes over reads already made, for callers with their own retry loop
+pub fn rescue(reads: Vec<(Vec<u8>, Vec<u8>)>) -> (Vec<u8>, Vec<u8>, ReadOutcome) {
+    let count = reads.len();
+    match vote(&reads) {
+        Some((data, spare, bits)) => (data, spare, ReadOutcome::Rescued { reads: count, bits }),
+        None => {
+            let (data, spare) = reads.into_iter().next().unwrap_or_default();
+            (data, spare, ReadOutcome::Unrescued { reads: count })
+        }
+    }
+}
+
+// reads a block, and if it doesn't pass its ECC (or the read fails), up to HEROIC_READS more
+// times, stopping at a clean read, then votes; cancellable between reads
+pub fn heroic_read(
+    player: &dyn Player,
+    blk: u32,
+    cancel: &CancelToken,
+) -> Result<(Vec<u8>, Vec<u8>, ReadOutcome)> {
+    let mut reads = vec![];
+    let mut last_error = None;
+    for _ in 0..=HEROIC_READS {
+        match cancel.check().and_then(|_| player.ReadSingleBlock(blk)) {
+            Ok((data, spare)) if ecc_check(&data, &spare) == EccCheck::Clean => {
+                return Ok((data, spare, ReadOutcome::Clean));
+            }
+            Ok(read) => reads.push(read),
+            Err(e) if cancel.check().is_err() => return Err(e),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    match last_error {
+        Some(e) if reads.is_empty() => bail!("{e} (in {} attempts)", HEROIC_READS + 1),
+        _ => Ok(rescue(reads)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heroic_reads() -> Result<()> {
+        use crate::ecc::page_ecc;
+        use crate::fs::{BLOCK_SIZE, SPARE_SIZE};
+
+        if majority(&[&[0b1100], &[0b1010], &[0b0110]]) != [0b1110] {
+            bail!("the majority of three reads was wrong");
+        }
+        // two against two: the first read decides
+        if majority(&[&[0b01], &[0b10], &[0b01], &[0b10]]) != [0b01] {
+            bail!("a tied vote didn't go to the first read");
+        }
+
+        // a block with varied contents and the spare data its ECC gives
+        let data = (0..BLOCK_SIZE)
+            .map(|i| (i * 7 + i / 256) as u8)
+            .collect::<Vec<_>>();
+        let mut spare = vec![0xFF; SPARE_SIZE];
+        page_ecc(&data, &mut spare)?;
+
+        // each read has a different handful of bits wrong, some in the page the ECC covers; a simple
+        // generator keeps it repeatable
+        let mut seed = 0x2545F491u32;
+        let mut noisy = || {
+            let mut d = data.clone();
+            for _ in 0..6 {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                // half the flips land in the first page
+                let at = if seed & 1 == 0 {
+                    seed as usize % 0x200
+                } else {
+                    seed as usize % BLOCK_SIZE
+                };
+                d[at] ^= 1 << (seed >> 8 & 7);
+            }
+            (d, spare.clone())
+        };
+        let reads = (0..5).map(|_| noisy()).collect::<Vec<_>>();
+        if reads
+            .iter()
+            .all(|(d, s)| ecc_check(d, s) != EccCheck::Uncorrectable)
+        {
+            bail!("the synthetic noise didn't make any read fail its ECC");
+        }
+        let Some((voted, voted_spare, bits)) = vote(&reads) else {
+            bail!("five noisy reads didn't vote to a block that passes its ECC");
+        };
+        if voted != data || voted_spare != spare {
+            bail!("the vote didn't recover the original block");
+        }
+        if bits != differing_bits(&reads[0].0, &data) || bits == 0 {
+            bail!("{bits} bits were reported rescued");
+        }
+
+        if vote(&reads[..2]).is_some() {
+            bail!("two reads were voted on");
+        }
+        // the same bits wrong in most reads are voted in, and the ECC catches them
+        let mut stuck = reads.clone();
+        for (d, _) in &mut stuck[..3] {
+            d[0x10] = data[0x10] ^ 0x01;
+            d[0x30] = data[0x30] ^ 0x04;
+        }
+        if vote(&stuck).is_some() {
+            bail!("a vote that's still wrong was accepted");
+        }
+        let first = stuck[0].0.clone();
+        match rescue(stuck) {
+            (d, _, ReadOutcome::Unrescued { reads: 5 }) if d == first => {}
+            (_, _, outcome) => bail!("an unrescued block came back as {outcome:?}"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,354 @@
+use std::fs::read_to_string;
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::byteswap::swap16;
+use crate::compress::read_input;
+
+// Intel HEX and Motorola SREC images: text files of records, each holding a few bytes and the
+// address they go at. Addresses are NAND byte addresses (block * 0x4000 + offset) for NAND data,
+// and spare byte addresses (block * 0x10 + offset) for spare data.
+
+// bytes per data record when writing
+const RECORD_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A text format of address records.
+pub enum RecordFormat {
+    /// Intel HEX (`:` records, with extended address records for addresses past 64KiB).
+    IntelHex,
+    /// Motorola SREC (S1, S2 and S3 data records).
+    Srec,
+}
+
+impl RecordFormat {
+    /// The format a file's extension names, if it names one.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let ext = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "hex" | "ihex" | "ihx" => Some(Self::IntelHex),
+            "srec" | "s19" | "s28" | "s37" | "mot" => Some(Self::Srec),
+            _ => None,
+        }
+    }
+}
+
+/// (address, data) of each data record, in file order.
+pub type Records = Vec<(u32, Vec<u8>)>;
+
+fn hex_bytes(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        bail!("odd number of hex digits");
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(s.get(i..i + 2).unwrap_or("?"), 16)
+                .map_err(|_| anyhow!("'{}' isn't hex", &s[i..(i + 2).min(s.len())]))
+        })
+        .collect()
+}
+
+/// Reads Intel HEX records, checking each line's length and checksum.
+pub fn parse_ihex(text: &str) -> Result<Records> {
+    let mut records = vec![];
+    // set by extended segment (02) and extended linear (04) address records
+    let mut base = 0u32;
+    for (n, line) in text.lines().enumerate().map(|(n, l)| (n + 1, l.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+        let at = |e: anyhow::Error| anyhow!("line {n}: {e}");
+        let body = line
+            .strip_prefix(':')
+            .ok_or_else(|| anyhow!("line {n}: doesn't start with ':'"))?;
+        let bytes = hex_bytes(body).map_err(at)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            bail!("line {n}: record length doesn't match its byte count");
+        }
+        if bytes.iter().fold(0u8, |a, &b| a.wrapping_add(b)) != 0 {
+            bail!("line {n}: bad checksum");
+        }
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0x00 => records.push((base + address, data.to_vec())),
+            0x01 => break,
+            0x02 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            0x04 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            // start addresses mean nothing for block data
+            0x03 | 0x05 => {}
+            t => bail!("line {n}: unsupported record type {t:02X}"),
+        }
+    }
+    Ok(records)
+}
+
+/// Reads SREC data records, checking each line's length and checksum and skipping the others.
+pub fn parse_srec(text: &str) -> Result<Records> {
+    let mut records = vec![];
+    for (n, line) in text.lines().enumerate().map(|(n, l)| (n + 1, l.trim())) {
+        if line.is_empty() {
+            continue;
+        }
+        let at = |e: anyhow::Error| anyhow!("line {n}: {e}");
+        let (kind, body) = match line.as_bytes() {
+            [b'S', kind, ..] => (*kind, &line[2..]),
+            _ => bail!("line {n}: doesn't start with 'S'"),
+        };
+        let bytes = hex_bytes(body).map_err(at)?;
+        if bytes.is_empty() || bytes.len() != bytes[0] as usize + 1 {
+            bail!("line {n}: record length doesn't match its byte count");
+        }
+        let sum = bytes[..bytes.len() - 1]
+            .iter()
+            .fold(0u8, |a, &b| a.wrapping_add(b));
+        if !sum != bytes[bytes.len() - 1] {
+            bail!("line {n}: bad checksum");
+        }
+        let address_len = match kind {
+            b'1' => 2,
+            b'2' => 3,
+            b'3' => 4,
+            // the header, record counts and start addresses
+            b'0' | b'5' | b'6' | b'7' | b'8' | b'9' => continue,
+            _ => bail!("line {n}: unsupported record type S{}", kind as char),
+        };
+        if bytes.len() < address_len + 2 {
+            bail!("line {n}: record too short for its address");
+        }
+        let address = bytes[1..1 + address_len]
+            .iter()
+            .fold(0u32, |a, &b| (a << 8) | b as u32);
+        records.push((address, bytes[1 + address_len..bytes.len() - 1].to_vec()));
+    }
+    Ok(records)
+}
+
+/// Reads the records of a file in `format`.
+pub fn parse_records(format: RecordFormat, text: &str) -> Result<Records> {
+    match format {
+        RecordFormat::IntelHex => parse_ihex(text),
+        RecordFormat::Srec => parse_srec(text),
+    }
+}
+
+/// Lays records out over `base..base + len`, filling gaps with 0xFF; every record must be inside
+/// that (and one of `allowed`, if given), and no two may overlap.
+pub fn place_records(
+    records: &Records,
+    base: usize,
+    len: usize,
+    allowed: Option<&[Range<usize>]>,
+) -> Result<Vec<u8>> {
+    let mut spans = records
+        .iter()
+        .map(|(address, data)| *address as usize..*address as usize + data.len())
+        .collect::<Vec<_>>();
+    spans.sort_by_key(|s| (s.start, s.end));
+    for pair in spans.windows(2) {
+        if pair[1].start < pair[0].end {
+            bail!(
+                "records at {:#X}-{:#X} and {:#X}-{:#X} overlap",
+                pair[0].start,
+                pair[0].end,
+                pair[1].start,
+                pair[1].end
+            );
+        }
+    }
+
+    let mut image = vec![0xFF; len];
+    for (address, data) in records {
+        let span = *address as usize..*address as usize + data.len();
+        let covered = allowed.is_none_or(|allowed| {
+            allowed
+                .iter()
+                .map(|a| span.end.min(a.end).saturating_sub(span.start.max(a.start)))
+                .sum::<usize>()
+                == span.len()
+        });
+        if !covered || span.start < base || span.end > base + len {
+            bail!(
+                "a record at {:#X}-{:#X} is outside the blocks being written",
+                span.start,
+                span.end
+            );
+        }
+        image[span.start - base..span.end - base].copy_from_slice(data);
+    }
+    Ok(image)
+}
+
+/// A NAND or spare input: raw files are read as they are, and HEX/SREC files are laid out over
+/// `base..base + len`, with their records restricted to `allowed` if it's given.
+#[cfg(feature = "writing")]
+pub fn load_input(
+    path: &str,
+    base: usize,
+    len: usize,
+    allowed: Option<&[Range<usize>]>,
+) -> Result<Vec<u8>> {
+    match RecordFormat::from_path(path) {
+        None => read_input(path),
+        Some(format) => {
+            let records = parse_records(format, &read_to_string(path)?)
+                .map_err(|e| anyhow!("{path}: {e}"))?;
+            place_records(&records, base, len, allowed).map_err(|e| anyhow!("{path}: {e}"))
+        }
+    }
+}
+
+/// Block ranges as byte ranges, for blocks of `unit` bytes.
+#[cfg(feature = "writing")]
+pub fn byte_ranges(ranges: &[Range<u16>], unit: usize) -> Vec<Range<usize>> {
+    ranges
+        .iter()
+        .map(|r| r.start as usize * unit..r.end as usize * unit)
+        .collect()
+}
+
+/// Writes `data` as Intel HEX, starting at address `base`.
+pub fn to_ihex(data: &[u8], base: u32) -> String {
+    let line = |kind: u8, address: u16, data: &[u8]| {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend_from_slice(&address.to_be_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(data);
+        let sum = bytes.iter().fold(0u8, |a, &b| a.wrapping_add(b));
+        bytes.push(sum.wrapping_neg());
+        format!(":{}\n", hex(&bytes))
+    };
+    let mut text = String::new();
+    let mut upper = None;
+    let mut offset = 0;
+    while offset < data.len() {
+        let address = base + offset as u32;
+        if upper != Some(address >> 16) {
+            upper = Some(address >> 16);
+            text += &line(0x04, 0, &((address >> 16) as u16).to_be_bytes());
+        }
+        // a record's address can't carry into the upper 16 bits, so none may cross 64KiB
+        let len = RECORD_LEN
+            .min(data.len() - offset)
+            .min(0x10000 - (address & 0xFFFF) as usize);
+        text += &line(0x00, address as u16, &data[offset..offset + len]);
+        offset += len;
+    }
+    text + &line(0x01, 0, &[])
+}
+
+/// Writes `data` as SREC S3 records, starting at address `base`.
+pub fn to_srec(data: &[u8], base: u32) -> String {
+    let line = |kind: char, address: u32, data: &[u8]| {
+        let mut bytes = vec![(4 + data.len() + 1) as u8];
+        bytes.extend_from_slice(&address.to_be_bytes());
+        bytes.extend_from_slice(data);
+        let sum = bytes.iter().fold(0u8, |a, &b| a.wrapping_add(b));
+        bytes.push(!sum);
+        format!("S{kind}{}\n", hex(&bytes))
+    };
+    let mut text = String::new();
+    for (i, chunk) in data.chunks(RECORD_LEN).enumerate() {
+        text += &line('3', base + (i * RECORD_LEN) as u32, chunk);
+    }
+    text + &line('7', 0, &[])
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+/// Converts between raw binaries and HEX/SREC, going by the file extensions; a raw binary made
+/// from records starts at address 0. `byteswap` swaps each 16-bit word of the raw side, which is
+/// all that's done when both sides are raw.
+pub fn convert(input: &str, output: &str, base: u32, byteswap: bool) -> Result<Vec<u8>> {
+    let read_raw = |path: &str| -> Result<Vec<u8>> {
+        let mut data = read_input(path)?;
+        if byteswap {
+            swap16(&mut data);
+        }
+        Ok(data)
+    };
+    match (
+        RecordFormat::from_path(input),
+        RecordFormat::from_path(output),
+    ) {
+        (Some(format), None) => {
+            let records = parse_records(format, &read_to_string(input)?)
+                .map_err(|e| anyhow!("{input}: {e}"))?;
+            let end = records
+                .iter()
+                .map(|(a, d)| *a as usize + d.len())
+                .max()
+                .unwrap_or(0);
+            let mut data = place_records(&records, 0, end, None)?;
+            if byteswap {
+                swap16(&mut data);
+            }
+            Ok(data)
+        }
+        (None, Some(RecordFormat::IntelHex)) => Ok(to_ihex(&read_raw(input)?, base).into_bytes()),
+        (None, Some(RecordFormat::Srec)) => Ok(to_srec(&read_raw(input)?, base).into_bytes()),
+        (Some(_), Some(_)) => bail!("one of the files must be a raw binary"),
+        (None, None) if byteswap => read_raw(input),
+        (None, None) => bail!("one of the files must be .hex or .srec (or add '--byteswap')"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_srec() -> Result<()> {
+        // two data records with a gap, an extended linear address, and an ignored start address
+        let ihex =
+            ":0400000001020304F2\n:02000004000AF0\n:0200100055AAEF\n:0400000500000000F7\n:00000001FF\n";
+        let records = parse_ihex(ihex)?;
+        if records != vec![(0, vec![1, 2, 3, 4]), (0xA0010, vec![0x55, 0xAA])] {
+            bail!("Intel HEX parsed as {records:X?}");
+        }
+        if parse_ihex(":0400000001020304F3\n").is_ok() {
+            bail!("an Intel HEX record with a bad checksum was accepted");
+        }
+
+        let srec = "S00600004844521B\nS1070000DEADBEEFC0\nS2060000100102E6\nS9030000FC\n";
+        let records = parse_srec(srec)?;
+        if records != vec![(0, vec![0xDE, 0xAD, 0xBE, 0xEF]), (0x10, vec![1, 2])] {
+            bail!("SREC parsed as {records:X?}");
+        }
+        if parse_srec("S1070000DEADBEEFC1\n").is_ok() {
+            bail!("an SREC record with a bad checksum was accepted");
+        }
+
+        let image = place_records(&records, 0, 0x20, None)?;
+        if image[..4] != [0xDE, 0xAD, 0xBE, 0xEF] || image[4] != 0xFF || image[0x10..0x12] != [1, 2]
+        {
+            bail!("records laid out incorrectly");
+        }
+        let allowed = [0..4, 0x8..0x10];
+        if place_records(&records, 0, 0x20, Some(&allowed)).is_ok() {
+            bail!("a record outside the allowed range was accepted");
+        }
+        let overlapping = vec![(0, vec![0; 4]), (2, vec![0; 4])];
+        if place_records(&overlapping, 0, 0x10, None).is_ok() {
+            bail!("overlapping records were accepted");
+        }
+
+        // both writers round-trip through their parsers, across a 64KiB boundary for HEX
+        let data = (0..40u8).collect::<Vec<_>>();
+        let base = 0xFFF8;
+        for records in [
+            parse_ihex(&to_ihex(&data, base))?,
+            parse_srec(&to_srec(&data, base))?,
+        ] {
+            if place_records(&records, base as usize, data.len(), None)? != data {
+                bail!("written records didn't read back the same");
+            }
+        }
+        Ok(())
+    }
+}
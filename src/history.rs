@@ -0,0 +1,247 @@
+use std::fs::read;
+
+use anyhow::{bail, Result};
+
+use crate::fs::{FsBlock, BLOCK_SIZE, FS_REGION_BLOCKS};
+use crate::fsdiff::{diff, Change, FsDiff};
+use crate::player::Player;
+use crate::sizes;
+
+// what one block of the FS region holds
+pub enum Slot {
+    Blank,
+    Invalid(String),
+    Valid(FsBlock),
+}
+
+impl Slot {
+    pub fn classify(data: &[u8]) -> Self {
+        if data.iter().all(|&b| b == 0xFF) {
+            return Self::Blank;
+        }
+        match FsBlock::parse(data) {
+            Ok(fs) => Self::Valid(fs),
+            Err(e) => Self::Invalid(e.to_string()),
+        }
+    }
+}
+
+// from one generation to the next; `diff` is None if they hold the same files and FAT, in which
+// case `from` to `to` can span several generations, `generations` of them
+pub struct Step {
+    pub from: u32,
+    pub to: u32,
+    pub diff: Option<FsDiff>,
+    pub generations: usize,
+}
+
+pub struct Timeline {
+    // the oldest valid generation and how many files it has
+    pub oldest: Option<(u32, usize)>,
+    pub steps: Vec<Step>,
+    pub invalid: Vec<(u16, String)>,
+    pub blank: usize,
+}
+
+fn same_content(a: &FsBlock, b: &FsBlock) -> bool {
+    a.entries == b.entries && a.fat == b.fat
+}
+
+// orders the valid generations by sequence number and works out what changed between each
+pub fn timeline(region: Vec<(u16, Slot)>) -> Timeline {
+    let mut valid = vec![];
+    let mut invalid = vec![];
+    let mut blank = 0;
+    for (blk, slot) in region {
+        match slot {
+            Slot::Blank => blank += 1,
+            Slot::Invalid(e) => invalid.push((blk, e)),
+            Slot::Valid(fs) => valid.push(fs),
+        }
+    }
+    valid.sort_by_key(|fs| fs.seqno);
+
+    let mut steps: Vec<Step> = vec![];
+    for pair in valid.windows(2) {
+        let (old, new) = (&pair[0], &pair[1]);
+        if !same_content(old, new) {
+            steps.push(Step {
+                from: old.seqno,
+                to: new.seqno,
+                diff: Some(diff(old, new)),
+                generations: 2,
+            });
+            continue;
+        }
+        match steps.last_mut() {
+            Some(s) if s.diff.is_none() && s.to == old.seqno => {
+                s.to = new.seqno;
+                s.generations += 1;
+            }
+            _ => steps.push(Step {
+                from: old.seqno,
+                to: new.seqno,
+                diff: None,
+                generations: 2,
+            }),
+        }
+    }
+
+    Timeline {
+        oldest: valid.first().map(|fs| (fs.seqno, fs.entries.len())),
+        steps,
+        invalid,
+        blank,
+    }
+}
+
+fn format_size(bytes: u32) -> String {
+    sizes::format_size(bytes as u64, 1)
+}
+
+fn describe(change: &Change) -> String {
+    match change {
+        Change::Added(n) => format!("+{} ({})", n.name, format_size(n.size)),
+        Change::Removed(o) => format!("-{}", o.name),
+        Change::Renamed { old, new } => format!("{}→{}", old.name, new.name),
+        Change::Resized { old, new } => format!(
+            "~{} ({}→{})",
+            new.name,
+            format_size(old.size),
+            format_size(new.size)
+        ),
+        Change::Relocated { new, .. } => format!("{} moved", new.name),
+        Change::Replaced { new, .. } => {
+            format!("{} rewritten ({})", new.name, format_size(new.size))
+        }
+    }
+}
+
+impl Step {
+    pub fn summary(&self) -> String {
+        let Some(d) = &self.diff else {
+            return if self.generations > 2 {
+                format!(
+                    "gen {}→{}: no changes ({} generations)",
+                    self.from, self.to, self.generations
+                )
+            } else {
+                format!("gen {}→{}: no changes", self.from, self.to)
+            };
+        };
+        let mut parts = d.changes.iter().map(describe).collect::<Vec<_>>();
+        if d.bad.0 != d.bad.1 {
+            parts.push(format!("bad blocks {}→{}", d.bad.0, d.bad.1));
+        }
+        if parts.is_empty() {
+            // only the FAT changed, e.g. blocks freed or reserved without any file changing
+            parts.push(format!("free blocks {}→{}", d.free.0, d.free.1));
+        }
+        format!("gen {}→{}: {}", self.from, self.to, parts.join(", "))
+    }
+}
+
+impl Timeline {
+    pub fn print(&self) {
+        match self.oldest {
+            Some((seqno, files)) => println!("gen {seqno}: oldest generation, {files} files"),
+            None => println!("No valid generations"),
+        }
+        for step in &self.steps {
+            println!("{}", step.summary());
+        }
+        for (blk, e) in &self.invalid {
+            println!("Block {blk:#X}: invalid generation ({e})");
+        }
+        if self.blank > 0 {
+            println!("{} blocks of the FS region are blank", self.blank);
+        }
+    }
+}
+
+pub fn read_region(player: &dyn Player) -> Result<Vec<(u16, Slot)>> {
+    let stats = player.CardStats()?;
+    let num_blocks = (stats.free + stats.used + stats.bad) as u16;
+    let start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    Ok((start..num_blocks)
+        .map(|blk| {
+            let slot = match player.ReadSingleBlock(blk as u32) {
+                Ok((data, _)) => Slot::classify(&data),
+                Err(e) => Slot::Invalid(format!("couldn't be read: {e}")),
+            };
+            (blk, slot)
+        })
+        .collect())
+}
+
+// the FS region of a raw NAND dump
+pub fn read_region_file(filename: &str) -> Result<Vec<(u16, Slot)>> {
+    let nand = read(filename)?;
+    if nand.len() % BLOCK_SIZE != 0 || nand.len() < FS_REGION_BLOCKS * BLOCK_SIZE {
+        bail!("{filename} isn't a whole number of blocks, or is too small to have an FS region");
+    }
+    let num_blocks = nand.len() / BLOCK_SIZE;
+    let start = num_blocks - FS_REGION_BLOCKS;
+    Ok(nand[start * BLOCK_SIZE..]
+        .chunks_exact(BLOCK_SIZE)
+        .enumerate()
+        .map(|(i, data)| ((start + i) as u16, Slot::classify(data)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{FsEntry, FAT_END, FAT_ENTRIES, FAT_FREE};
+
+    #[test]
+    fn fs_history() -> Result<()> {
+        let file = FsEntry::new;
+        let generation = |seqno: u32, entries: Vec<FsEntry>| {
+            let mut fat = vec![FAT_FREE; FAT_ENTRIES];
+            for e in &entries {
+                fat[e.start as usize] = FAT_END;
+            }
+            Slot::Valid(FsBlock {
+                fat,
+                entries,
+                linked: false,
+                seqno,
+            })
+        };
+        let game = file("GAME1.app", 0x40, 0x3000);
+        let temp = file("temp.tmp", 0x41, 0x4000);
+        // out of order, as they would be in the region once it's wrapped around
+        let region = vec![
+            (0xFF0, generation(44, vec![game.clone(), temp.clone()])),
+            (0xFF1, generation(45, vec![game.clone()])),
+            (0xFF2, Slot::Invalid("bad FS checksum".to_string())),
+            (0xFF3, Slot::Blank),
+            (0xFFD, generation(41, vec![])),
+            (0xFFE, generation(42, vec![game.clone(), temp.clone()])),
+            (0xFFF, generation(43, vec![game, temp])),
+        ];
+        let t = timeline(region);
+        let summaries = t.steps.iter().map(Step::summary).collect::<Vec<_>>();
+        let expected = [
+            format!(
+                "gen 41→42: +GAME1.app ({}), +temp.tmp ({})",
+                format_size(0x3000),
+                format_size(0x4000)
+            ),
+            "gen 42→44: no changes (3 generations)".to_string(),
+            "gen 44→45: -temp.tmp".to_string(),
+        ];
+        if summaries != expected {
+            bail!("timeline came out as {summaries:?}");
+        }
+        if t.oldest != Some((41, 0))
+            || t.invalid.len() != 1
+            || t.invalid[0].0 != 0xFF2
+            || t.blank != 1
+        {
+            bail!("invalid or blank generations weren't noted");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use std::io::{BufRead, BufReader, Read};
+use std::process::Stdio;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+
+use crate::notify::shell;
+
+// Commands run after an operation completes, for pipelines that compress, checksum or upload
+// what it produced. They're set per operation in the config file:
+//
+//   [hooks]
+//   post_dump = ["zstd -19 {nand}", "rsync {nand}.zst nas:/bb/"]
+//
+// and run one after another, stopping at the first that fails or runs out of time. Placeholders
+// are replaced with the operation's values, quoted for the shell, so a path with spaces or quotes
+// in it is still one argument; '{{' and '}}' are literal braces. Unlike 'notify-command', which
+// only hears about operations, these are part of the command that ran them.
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct HookConfig {
+    // after '1': {nand} and {spare}, and {crcs} and {manifest} if they were saved
+    pub post_dump: Vec<String>,
+    // after '3': {file}
+    pub post_read: Vec<String>,
+    // how long each hook may run before it's killed
+    pub timeout_secs: u64,
+    // whether a hook failing (or being refused) fails the command it ran after
+    pub fail_command: bool,
+}
+
+impl Default for HookConfig {
+    fn default() -> Self {
+        Self {
+            post_dump: vec![],
+            post_read: vec![],
+            timeout_secs: 300,
+            fail_command: true,
+        }
+    }
+}
+
+// what stops hooks from running at all
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Guard {
+    // 'set strict-writes on', where nothing is run that the command line didn't ask for
+    pub strict_writes: bool,
+    // the permission profile in use, if any: a shared station shouldn't run shell commands
+    pub profile: Option<String>,
+    // the outputs went into an archive, so there are no files for the hooks to work on
+    pub to_archive: bool,
+}
+
+// every placeholder's value: the operation's files, and the console and time
+pub fn values(
+    bbid: Option<u32>,
+    finished: &DateTime<Local>,
+    files: &[(&'static str, &str)],
+) -> Vec<(&'static str, String)> {
+    let mut values = vec![
+        ("bbid", bbid.map(|b| format!("{b:08X}")).unwrap_or_default()),
+        ("timestamp", finished.format("%Y%m%d-%H%M%S").to_string()),
+    ];
+    values.extend(files.iter().map(|(k, v)| (*k, v.to_string())));
+    values
+}
+
+// one argument to the shell, whatever it holds
+pub fn quote(value: &str, windows: bool) -> String {
+    if windows {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+// `template` with its placeholders filled in; fails on one it doesn't have a value for, or a
+// brace that isn't part of one
+pub fn substitute(
+    template: &str,
+    values: &[(&'static str, String)],
+    windows: bool,
+) -> Result<String> {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                out.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                out.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => bail!("'{template}' has a '{{' that isn't closed; write '{{{{' for a literal one"),
+                    }
+                }
+                let value = values.iter().find(|(k, _)| *k == name).ok_or_else(|| {
+                    let known = values
+                        .iter()
+                        .map(|(k, _)| format!("{{{k}}}"))
+                        .collect::<Vec<_>>();
+                    anyhow!(
+                        "'{template}' uses {{{name}}}, but only {} can be used here",
+                        known.join(", ")
+                    )
+                })?;
+                out += &quote(&value.1, windows);
+            }
+            '}' => bail!("'{template}' has a '}}' that doesn't close a placeholder; write '}}}}' for a literal one"),
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+// the commands to run, filled in; nothing if there are no hooks, and an error (before any of
+// them has run) if they mustn't be run or can't all be filled in
+pub fn plan(
+    hooks: &[String],
+    values: &[(&'static str, String)],
+    guard: &Guard,
+    windows: bool,
+) -> Result<Vec<String>> {
+    if hooks.is_empty() {
+        return Ok(vec![]);
+    }
+    if guard.strict_writes {
+        bail!("hooks aren't run with strict-writes on");
+    }
+    if let Some(profile) = &guard.profile {
+        bail!("hooks aren't run under a permission profile ('{profile}')");
+    }
+    if guard.to_archive {
+        bail!("the outputs went into an archive, so there are no files for hooks to work on");
+    }
+    hooks
+        .iter()
+        .map(|h| substitute(h, values, windows))
+        .collect()
+}
+
+// prints each line of a hook's output as it comes
+fn relay(stream: impl Read + Send + 'static, label: String) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines().map_while(Result::ok) {
+            eprintln!("hook ({label}): {line}");
+        }
+    })
+}
+
+// runs one hook to completion, killing it if it takes longer than `timeout`
+pub fn run_hook(command: &str, timeout: Duration, label: &str) -> Result<()> {
+    let mut child = shell(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow!("couldn't run '{command}': {e}"))?;
+    let relays = [
+        child.stdout.take().map(|s| relay(s, label.to_string())),
+        child.stderr.take().map(|s| relay(s, label.to_string())),
+    ];
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if started.elapsed() >= timeout {
+            // killing the shell may leave what it started holding the pipes, so the output
+            // relays aren't waited for
+            let _ = child.kill();
+            let _ = child.wait();
+            break None;
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    let Some(status) = status else {
+        bail!(
+            "'{command}' was still running after {}s, and was killed",
+            timeout.as_secs_f64()
+        );
+    };
+    for relay in relays.into_iter().flatten() {
+        let _ = relay.join();
+    }
+    if !status.success() {
+        bail!("'{command}' exited with {status}");
+    }
+    Ok(())
+}
+
+// runs the hooks one after another, stopping at the first that fails
+pub fn run_all(commands: &[String], timeout: Duration, label: &str) -> Result<()> {
+    for command in commands {
+        println!("Running hook: {command}");
+        run_hook(command, timeout, label)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn hooks() -> Result<()> {
+        let values = vec![
+            ("nand", "dumps/my nand.bin".to_string()),
+            ("bbid", "0123ABCD".to_string()),
+            ("odd", "it's $HOME; `x`".to_string()),
+        ];
+
+        // each value is one argument, whatever's in it
+        let filled = substitute(
+            "zstd -19 {nand} && rsync {nand}.zst nas:/bb/{bbid}/",
+            &values,
+            false,
+        )?;
+        if filled
+            != "zstd -19 'dumps/my nand.bin' && rsync 'dumps/my nand.bin'.zst nas:/bb/'0123ABCD'/"
+        {
+            bail!("the placeholders were filled in as {filled}");
+        }
+        if substitute("echo {odd}", &values, false)? != r"echo 'it'\''s $HOME; `x`'"
+            || substitute("echo {odd}", &values, true)? != "echo \"it's $HOME; `x`\""
+            || quote("say \"hi\"", true) != "\"say \"\"hi\"\"\""
+        {
+            bail!("awkward values weren't quoted as they should be");
+        }
+        if substitute("awk '{{print $1}}' {bbid}", &values, false)? != "awk '{print $1}' '0123ABCD'"
+        {
+            bail!("doubled braces weren't made literal");
+        }
+        for bad in ["echo {spare}", "echo {nand", "echo }", "echo {}"] {
+            if substitute(bad, &values, false).is_ok() {
+                bail!("'{bad}' was filled in");
+            }
+        }
+        match substitute("cp {spare} /tmp", &values, false) {
+            Err(e)
+                if e.to_string()
+                    .contains("only {nand}, {bbid}, {odd} can be used") => {}
+            other => bail!("an unknown placeholder gave {other:?}"),
+        }
+
+        // when hooks are run, refused, or there's nothing to do
+        let hooks = vec!["gzip {nand}".to_string(), "ls {nand}.gz".to_string()];
+        if plan(&hooks, &values, &Guard::default(), false)?
+            != ["gzip 'dumps/my nand.bin'", "ls 'dumps/my nand.bin'.gz"]
+        {
+            bail!("the hooks weren't planned as written");
+        }
+        let refused = [
+            Guard {
+                strict_writes: true,
+                ..Guard::default()
+            },
+            Guard {
+                profile: Some("meetup".to_string()),
+                ..Guard::default()
+            },
+            Guard {
+                to_archive: true,
+                ..Guard::default()
+            },
+        ];
+        for guard in &refused {
+            if plan(&hooks, &values, guard, false).is_ok() {
+                bail!("hooks were planned despite {guard:?}");
+            }
+            if !plan(&[], &values, guard, false)?.is_empty() {
+                bail!("no hooks gave something to run");
+            }
+        }
+        let one_bad = vec!["gzip {nand}".to_string(), "ls {spare}".to_string()];
+        if plan(&one_bad, &values, &Guard::default(), false).is_ok() {
+            bail!("hooks were planned though one of them can't be filled in");
+        }
+
+        // running them, with trivial commands
+        if cfg!(unix) {
+            let dir = TestDir::new("hook")?;
+            let second = dir.join("second");
+            let touch = format!("touch {}", quote(&second.to_string_lossy(), false));
+            let short = Duration::from_secs(5);
+            run_hook("true", short, "test")?;
+            if run_hook("exit 3", short, "test").is_ok() {
+                bail!("a hook that failed was taken as succeeding");
+            }
+            match run_hook("sleep 5", Duration::from_millis(200), "test") {
+                Err(e) if e.to_string().contains("killed") => {}
+                other => bail!("a hook that ran too long gave {other:?}"),
+            }
+            if run_all(&["false".to_string(), touch.clone()], short, "test").is_ok()
+                || second.exists()
+            {
+                bail!("the hooks carried on after one failed");
+            }
+            run_all(&["echo hello".to_string(), touch], short, "test")?;
+            if !second.exists() {
+                bail!("the second hook didn't run");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,396 @@
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+use anyhow::Result;
+use rusb::{has_hotplug, Device, GlobalContext, Hotplug, HotplugBuilder, UsbContext};
+
+use crate::device::{scan_sorted, Listed};
+use crate::instance_lock::DeviceLock;
+use crate::startup::{choose_startup_device, AutoOpen, Candidate, PreferredConsole, StartupChoice};
+
+// Consoles plugged in or unplugged while the prompt is waiting. A thread watches for them, with
+// libusb's hotplug events where it has them and by rescanning every POLL_INTERVAL where it
+// doesn't, and says so above the prompt as it happens. What that means for the session (selecting
+// the new console, or losing the open one) is left to the main thread to act on before the next
+// command, as a command may be using the console in between.
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Arrived(Listed),
+    Left(Listed),
+}
+
+// what the session has to do before its next command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reaction {
+    // the open console was unplugged
+    Lost(Listed),
+    // and has been plugged back in at the same place, with a new address
+    Returned(Listed),
+}
+
+// the open console after it was unplugged, kept so it can be reopened as it was if it's plugged
+// back in; its lock is held meanwhile, so another copy of the program can't take it
+pub struct Unplugged {
+    pub index: usize,
+    pub lock: DeviceLock,
+    pub initialised: bool,
+    // whether the session had sealed it
+    pub sealed: bool,
+}
+
+// the consoles connected as the watcher last saw them, and what it's told the session about
+#[derive(Debug, Default)]
+pub struct Tracker {
+    // in the order they're numbered in
+    pub devices: Vec<Listed>,
+    // the open console's bus and address, if there is one
+    pub open: Option<(u8, u8)>,
+    // whether a console that's plugged in is offered for selecting, as auto_open would have
+    // opened it at startup
+    pub auto_open: AutoOpen,
+    pub preferred: Option<PreferredConsole>,
+    // the console offered for selecting with Enter at an empty prompt, by number, if any
+    pub offer: Option<(usize, Listed)>,
+    // the open console after it was unplugged, until it's back
+    lost: Option<Listed>,
+    reactions: Vec<Reaction>,
+}
+
+// the same device: a device plugged back in gets a new address
+fn same_device(a: &Listed, b: &Listed) -> bool {
+    a.bus == b.bus && a.address == b.address
+}
+
+// the same console plugged back in where it was; by address where the ports can't be told
+fn same_place(was: &Listed, now: &Listed) -> bool {
+    was.bus == now.bus
+        && match was.ports.is_empty() {
+            true => was.address == now.address,
+            false => was.ports == now.ports,
+        }
+        && (was.serial.is_none() || now.serial.is_none() || was.serial == now.serial)
+}
+
+// what came and went between two scans: what's gone first, then what's new
+pub fn diff(before: &[Listed], now: &[Listed]) -> Vec<Event> {
+    let left = before
+        .iter()
+        .filter(|b| !now.iter().any(|n| same_device(b, n)))
+        .map(|b| Event::Left(b.clone()));
+    let arrived = now
+        .iter()
+        .filter(|n| !before.iter().any(|b| same_device(b, n)))
+        .map(|n| Event::Arrived(n.clone()));
+    left.chain(arrived).collect()
+}
+
+impl Tracker {
+    pub fn new(devices: Vec<Listed>) -> Self {
+        Self {
+            devices,
+            ..Default::default()
+        }
+    }
+
+    fn offers(&self, index: usize, listed: &Listed) -> bool {
+        let candidate = Candidate {
+            index,
+            serial: listed.serial.clone(),
+            bbid: None,
+        };
+        self.open.is_none()
+            && self.lost.is_none()
+            && choose_startup_device(&[candidate], self.preferred.as_ref(), self.auto_open)
+                != StartupChoice::Manual
+    }
+
+    // the offer's number, after devices ahead of it have come or gone
+    fn renumber(&mut self) {
+        if let Some((index, offered)) = &mut self.offer {
+            match self.devices.iter().position(|d| same_device(d, offered)) {
+                Some(i) => *index = i,
+                None => self.offer = None,
+            }
+        }
+    }
+
+    // takes in an event, returning what to say about it, or None if it's one already seen
+    pub fn apply(&mut self, event: Event) -> Option<String> {
+        match event {
+            Event::Arrived(listed) => {
+                if self.devices.iter().any(|d| same_device(d, &listed)) {
+                    return None;
+                }
+                self.devices.push(listed.clone());
+                self.devices.sort_by(|a, b| a.key().cmp(&b.key()));
+                self.renumber();
+                let index = self.devices.iter().position(|d| *d == listed)?;
+                if self.lost.as_ref().is_some_and(|l| same_place(l, &listed)) {
+                    self.lost = None;
+                    self.reactions.push(Reaction::Returned(listed.clone()));
+                    return Some(format!(
+                        "The selected console is back at {}, as player {index}; it'll be reopened before the next command",
+                        listed.path()
+                    ));
+                }
+                if self.offers(index, &listed) {
+                    self.offer = Some((index, listed.clone()));
+                    return Some(format!(
+                        "BB Player connected at {}, as player {index}; press Enter at an empty prompt to select it",
+                        listed.path()
+                    ));
+                }
+                Some(format!(
+                    "BB Player connected at {}, as player {index}",
+                    listed.path()
+                ))
+            }
+            Event::Left(listed) => {
+                let index = self.devices.iter().position(|d| same_device(d, &listed))?;
+                let gone = self.devices.remove(index);
+                self.renumber();
+                if self.open == Some((gone.bus, gone.address)) {
+                    self.open = None;
+                    self.lost = Some(gone.clone());
+                    self.reactions.push(Reaction::Lost(gone.clone()));
+                    return Some(format!(
+                        "The selected console (player {index}, at {}) was disconnected",
+                        gone.path()
+                    ));
+                }
+                Some(format!(
+                    "BB Player at {} (player {index}) disconnected",
+                    gone.path()
+                ))
+            }
+        }
+    }
+
+    // what the session has to do since it last looked, in order
+    pub fn take(&mut self) -> Vec<Reaction> {
+        std::mem::take(&mut self.reactions)
+    }
+
+    // stops waiting for the lost console to come back, as another one was selected
+    pub fn forget_lost(&mut self) {
+        self.lost = None;
+    }
+}
+
+// wakes the watcher when libusb sees a device come or go; what changed is found by rescanning,
+// the same as without hotplug events
+struct Wake(Sender<()>);
+
+impl Hotplug<GlobalContext> for Wake {
+    fn device_arrived(&mut self, _device: Device<GlobalContext>) {
+        let _ = self.0.send(());
+    }
+
+    fn device_left(&mut self, _device: Device<GlobalContext>) {
+        let _ = self.0.send(());
+    }
+}
+
+// the consoles connected now; the ones already known aren't opened again for their serial, as
+// the session may be using one
+fn rescan(known: &[Listed]) -> Result<Vec<Listed>> {
+    let mut now = scan_sorted()?
+        .iter()
+        .map(|d| {
+            known
+                .iter()
+                .find(|k| k.bus == d.bus_number() && k.address == d.address())
+                .cloned()
+                .unwrap_or_else(|| Listed::of(d))
+        })
+        .collect::<Vec<_>>();
+    now.sort_by(|a, b| a.key().cmp(&b.key()));
+    Ok(now)
+}
+
+// starts watching for consoles coming and going, passing what to say about each to `print`,
+// which puts it above the prompt; the tracker is shared with the session
+pub fn watch(tracker: Arc<Mutex<Tracker>>, mut print: Box<dyn FnMut(String) + Send>) {
+    let (wake, woken) = channel();
+    let registration = match has_hotplug() {
+        true => {
+            let mut builder = HotplugBuilder::new();
+            builder.enumerate(false);
+            builder
+                .register(
+                    GlobalContext::default(),
+                    Box::new(Wake(wake)) as Box<dyn Hotplug<GlobalContext>>,
+                )
+                .ok()
+        }
+        false => None,
+    };
+    spawn(move || loop {
+        match &registration {
+            // libusb only calls back while something's handling its events
+            Some(_) => {
+                let _ = GlobalContext::default().handle_events(Some(POLL_INTERVAL));
+                if woken.try_iter().count() == 0 {
+                    continue;
+                }
+            }
+            None => sleep(POLL_INTERVAL),
+        }
+        let known = tracker
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .devices
+            .clone();
+        let Ok(now) = rescan(&known) else {
+            continue;
+        };
+        let mut tracker = tracker.lock().unwrap_or_else(PoisonError::into_inner);
+        for event in diff(&known, &now) {
+            if let Some(notice) = tracker.apply(event) {
+                print(notice);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    #[test]
+    fn hotplug() -> Result<()> {
+        let device = |ports: &[u8], address: u8, serial: Option<&str>| Listed {
+            bus: 1,
+            ports: ports.to_vec(),
+            address,
+            vendor_id: 0x1527,
+            product_id: 0xBBDB,
+            serial: serial.map(str::to_string),
+        };
+        let a = device(&[2], 5, Some("BB001"));
+        let b = device(&[3], 9, None);
+        let c = device(&[4], 11, Some("BB003"));
+
+        // scans differ by what's gone and what's new, whatever order they're in
+        if diff(&[a.clone(), b.clone()], &[b.clone(), c.clone()])
+            != [Event::Left(a.clone()), Event::Arrived(c.clone())]
+            || !diff(&[a.clone(), b.clone()], &[b.clone(), a.clone()]).is_empty()
+        {
+            bail!("the scans' differences were wrong");
+        }
+
+        // with auto_open 'never', arrivals are only reported, and numbered where they're plugged in
+        let mut tracker = Tracker::new(vec![a.clone(), c.clone()]);
+        let notice = tracker.apply(Event::Arrived(b.clone()));
+        if notice.as_deref() != Some("BB Player connected at 1-3, as player 1")
+            || tracker.offer.is_some()
+        {
+            bail!(
+                "an arrival with auto_open 'never' gave {notice:?} and {:?}",
+                tracker.offer
+            );
+        }
+        // an event already seen (by hotplug and a rescan, say) is let go
+        if tracker.apply(Event::Arrived(b.clone())).is_some() || tracker.devices.len() != 3 {
+            bail!("an arrival was taken in twice");
+        }
+        if tracker.apply(Event::Left(b.clone())).is_none()
+            || tracker.apply(Event::Left(b.clone())).is_some()
+        {
+            bail!("a departure wasn't taken in just once");
+        }
+
+        // with 'single', one is offered while nothing's open, and its number follows the others
+        tracker.auto_open = AutoOpen::Single;
+        let notice = tracker.apply(Event::Arrived(b.clone())).unwrap_or_default();
+        if tracker.offer != Some((1, b.clone()))
+            || !notice.ends_with("press Enter at an empty prompt to select it")
+        {
+            bail!(
+                "an arrival with auto_open 'single' gave '{notice}' and {:?}",
+                tracker.offer
+            );
+        }
+        tracker.apply(Event::Left(a.clone()));
+        if tracker.offer != Some((0, b.clone())) {
+            bail!(
+                "the offer became {:?} when a device ahead of it left",
+                tracker.offer
+            );
+        }
+        tracker.apply(Event::Left(b.clone()));
+        if tracker.offer.is_some() {
+            bail!("a device that left was still offered");
+        }
+
+        // with 'preferred', only the preferred console is, as far as its serial tells
+        tracker.auto_open = AutoOpen::Preferred;
+        tracker.preferred = Some(PreferredConsole::Serial("BB001".to_string()));
+        tracker.apply(Event::Arrived(b.clone()));
+        if tracker.offer.is_some() {
+            bail!("a console that isn't the preferred one was offered");
+        }
+        tracker.apply(Event::Arrived(a.clone()));
+        if tracker.offer != Some((0, a.clone())) {
+            bail!(
+                "the preferred console wasn't offered, but {:?}",
+                tracker.offer
+            );
+        }
+
+        // nothing's offered while a console is open
+        let mut tracker = Tracker::new(vec![a.clone()]);
+        tracker.auto_open = AutoOpen::Single;
+        tracker.open = Some((a.bus, a.address));
+        tracker.apply(Event::Arrived(b.clone()));
+        if tracker.offer.is_some() || !tracker.take().is_empty() {
+            bail!("a console was offered with another one open");
+        }
+
+        // the open console going is lost, and coming back at the same place (at a new address) is
+        // reopened; another console turning up in between is neither, nor offered in its place
+        let notice = tracker.apply(Event::Left(a.clone())).unwrap_or_default();
+        if tracker.take() != [Reaction::Lost(a.clone())]
+            || tracker.open.is_some()
+            || !notice.starts_with("The selected console")
+        {
+            bail!("losing the open console gave '{notice}'");
+        }
+        tracker.apply(Event::Arrived(c.clone()));
+        let impostor = device(&[2], 13, Some("BB009"));
+        tracker.apply(Event::Arrived(impostor.clone()));
+        if !tracker.take().is_empty() || tracker.offer.is_some() {
+            bail!("another console was taken for the one that was lost");
+        }
+        tracker.apply(Event::Left(impostor));
+        let replugged = device(&[2], 14, Some("BB001"));
+        tracker.apply(Event::Arrived(replugged.clone()));
+        if tracker.take() != [Reaction::Returned(replugged.clone())] {
+            bail!("the lost console wasn't reopened when it came back");
+        }
+        // and only once
+        tracker.apply(Event::Left(replugged.clone()));
+        tracker.apply(Event::Arrived(replugged.clone()));
+        if !tracker.take().is_empty() {
+            bail!("a console that was no longer open was reopened");
+        }
+
+        // once another console is selected, the lost one isn't waited for
+        let mut tracker = Tracker::new(vec![a.clone(), b.clone()]);
+        tracker.open = Some((a.bus, a.address));
+        tracker.apply(Event::Left(a.clone()));
+        tracker.forget_lost();
+        tracker.open = Some((b.bus, b.address));
+        tracker.apply(Event::Arrived(a.clone()));
+        if tracker.take() != [Reaction::Lost(a.clone())] {
+            bail!("a console was reopened after another was selected");
+        }
+        Ok(())
+    }
+}
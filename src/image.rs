@@ -0,0 +1,106 @@
+use std::fs::read;
+use std::ops::Range;
+
+use anyhow::{bail, Result};
+
+use crate::fs::{FsBlock, FsEntry, FsError, BLOCK_SIZE, FS_REGION_BLOCKS, SPARE_SIZE};
+use crate::spare::is_bad_block;
+
+pub struct NandImage {
+    nand: Vec<u8>,
+    spare: Vec<u8>,
+}
+
+impl NandImage {
+    pub fn new(nand: Vec<u8>, spare: Vec<u8>) -> Result<Self> {
+        if nand.is_empty() || !nand.len().is_multiple_of(BLOCK_SIZE) {
+            bail!(
+                "NAND image is {:#X} bytes, which isn't a whole number of {BLOCK_SIZE:#X}-byte blocks",
+                nand.len()
+            );
+        }
+        let blocks = nand.len() / BLOCK_SIZE;
+        if spare.len() != blocks * SPARE_SIZE {
+            bail!(
+                "spare data is {:#X} bytes, expected {:#X} for {blocks} blocks",
+                spare.len(),
+                blocks * SPARE_SIZE
+            );
+        }
+        Ok(Self { nand, spare })
+    }
+
+    pub fn load(nand_filename: &str, spare_filename: &str) -> Result<Self> {
+        Self::new(read(nand_filename)?, read(spare_filename)?)
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.nand.len() / BLOCK_SIZE
+    }
+
+    pub fn block(&self, blk: usize) -> &[u8] {
+        &self.nand[blk * BLOCK_SIZE..(blk + 1) * BLOCK_SIZE]
+    }
+
+    #[cfg(feature = "writing")]
+    pub fn block_mut(&mut self, blk: usize) -> &mut [u8] {
+        &mut self.nand[blk * BLOCK_SIZE..(blk + 1) * BLOCK_SIZE]
+    }
+
+    #[cfg(feature = "writing")]
+    pub fn data(&self) -> (&[u8], &[u8]) {
+        (&self.nand, &self.spare)
+    }
+
+    pub fn spare(&self, blk: usize) -> &[u8] {
+        &self.spare[blk * SPARE_SIZE..(blk + 1) * SPARE_SIZE]
+    }
+
+    pub fn bad_blocks(&self) -> Vec<usize> {
+        (0..self.num_blocks())
+            .filter(|&blk| is_bad_block(self.spare(blk)))
+            .collect()
+    }
+
+    pub fn fs_region(&self) -> Range<usize> {
+        self.num_blocks().saturating_sub(FS_REGION_BLOCKS)..self.num_blocks()
+    }
+
+    pub fn fs_generations(&self) -> Vec<(usize, Result<FsBlock, FsError>)> {
+        self.fs_region()
+            .map(|blk| (blk, FsBlock::parse(self.block(blk))))
+            .collect()
+    }
+
+    pub fn current_fs(&self) -> Option<(usize, FsBlock)> {
+        self.fs_generations()
+            .into_iter()
+            .filter_map(|(blk, fs)| fs.ok().map(|fs| (blk, fs)))
+            .max_by_key(|(_, fs)| fs.seqno)
+    }
+
+    pub fn read_file(&self, fs: &FsBlock, entry: &FsEntry) -> Result<Vec<u8>> {
+        let chain = fs.chain(entry.start)?;
+        if chain.len() < entry.blocks() {
+            bail!(
+                "chain for {} is {} blocks long, but its size needs {}",
+                entry.name,
+                chain.len(),
+                entry.blocks()
+            );
+        }
+
+        let mut data = Vec::with_capacity(chain.len() * BLOCK_SIZE);
+        for blk in chain {
+            if blk as usize >= self.num_blocks() {
+                bail!(
+                    "chain for {} points past the end of the image ({blk:#X})",
+                    entry.name
+                );
+            }
+            data.extend_from_slice(self.block(blk as usize));
+        }
+        data.truncate(entry.size as usize);
+        Ok(data)
+    }
+}
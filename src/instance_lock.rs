@@ -0,0 +1,250 @@
+use std::fs::{create_dir_all, hard_link, read_to_string, remove_file, write};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+use anyhow::{bail, Result};
+
+use crate::PROG_NAME;
+
+// Two copies of the program mustn't drive the same console: they fight over claiming it, and
+// their commands interleave. The first to select a console writes a lock file for it, named after
+// its USB serial number (or its port, without one), holding its PID, to a per-user directory; a
+// second copy finding the file says who has the console. A lock whose process has gone is stale,
+// and is removed when it's found.
+
+// the locks this process holds, for exits that skip destructors
+static HELD: Mutex<Vec<PathBuf>> = Mutex::new(vec![]);
+
+/// The process holding a console's lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Holder {
+    /// Its process ID.
+    pub pid: u32,
+}
+
+/// Held while a console is selected; dropping it removes the lock file.
+#[derive(Debug)]
+pub struct DeviceLock {
+    path: PathBuf,
+}
+
+/// The name a console's lock goes by: its USB serial number, or the port it's plugged into if it
+/// hasn't one.
+pub fn device_key(serial: Option<&str>, port: &str) -> String {
+    match serial {
+        Some(s) => format!("serial-{s}"),
+        None => format!("port-{port}"),
+    }
+}
+
+fn lock_dir() -> PathBuf {
+    match dirs::runtime_dir() {
+        Some(d) => d.join(PROG_NAME),
+        // outside Linux there's no per-user runtime directory, so the user goes in the name
+        None => {
+            let user = std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .unwrap_or_default();
+            std::env::temp_dir().join(format!("{PROG_NAME}-{user}"))
+        }
+    }
+}
+
+fn lock_path(dir: &Path, key: &str) -> PathBuf {
+    let name = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    dir.join(format!("{name}.lock"))
+}
+
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> bool {
+    Path::new(&format!("/proc/{pid}")).exists()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_alive(pid: u32) -> bool {
+    process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(process::Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {pid}"), "/NH"])
+        .output()
+        .is_ok_and(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .split_whitespace()
+                .any(|w| w == pid.to_string())
+        })
+}
+
+// with no way to tell, a lock is never taken as stale
+#[cfg(not(any(unix, windows)))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    read_to_string(path)
+        .ok()?
+        .lines()
+        .find_map(|l| l.strip_prefix("pid="))?
+        .trim()
+        .parse()
+        .ok()
+}
+
+// the live process holding the lock at `path`, removing the lock if its process is gone (or it
+// can't be read, as what's left of one that was never finished)
+fn holder_at(path: &Path) -> Option<Holder> {
+    if !path.exists() {
+        return None;
+    }
+    match read_pid(path) {
+        Some(pid) if pid == process::id() || process_alive(pid) => Some(Holder { pid }),
+        _ => {
+            let _ = remove_file(path);
+            None
+        }
+    }
+}
+
+/// Another live process holding the console with this key, as 'l' says; a lock left by a process
+/// that's gone is removed.
+pub fn holder(key: &str) -> Option<Holder> {
+    holder_at(&lock_path(&lock_dir(), key)).filter(|h| h.pid != process::id())
+}
+
+fn release(path: &Path) {
+    HELD.lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|p| p != path);
+    // only ever another process's if this one's was taken as stale, which it can't be while
+    // this process is running
+    if read_pid(path) == Some(process::id()) {
+        let _ = remove_file(path);
+    }
+}
+
+fn release_where(which: impl Fn(&Path) -> bool) {
+    let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+    let (released, kept) = held.drain(..).partition::<Vec<_>, _>(|p| which(p));
+    *held = kept;
+    for path in released {
+        if read_pid(&path) == Some(process::id()) {
+            let _ = remove_file(path);
+        }
+    }
+}
+
+/// Removes every lock this process holds, for exiting without running destructors: from a
+/// panic hook, or before `std::process::exit`.
+pub fn release_all() {
+    release_where(|_| true);
+}
+
+impl DeviceLock {
+    /// Takes the lock on the console with this key; fails if another live process has it, saying
+    /// which.
+    pub fn acquire(key: &str) -> Result<Self> {
+        Self::acquire_in(&lock_dir(), key)
+    }
+
+    fn acquire_in(dir: &Path, key: &str) -> Result<Self> {
+        create_dir_all(dir)?;
+        let path = lock_path(dir, key);
+        // written in full under another name and then linked into place, so the lock is never
+        // seen without its PID
+        let temp = path.with_extension(format!("{}.tmp", process::id()));
+        write(&temp, format!("pid={}\n", process::id()))?;
+        let linked = Self::link(&temp, &path, key);
+        let _ = remove_file(&temp);
+        linked?;
+        HELD.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(path.clone());
+        Ok(Self { path })
+    }
+
+    fn link(temp: &Path, path: &Path, key: &str) -> Result<()> {
+        // a second try, after clearing a stale lock
+        for _ in 0..2 {
+            match hard_link(temp, path) {
+                Ok(_) => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    if let Some(holder) = holder_at(path) {
+                        bail!(
+                            "This console is in use by another {PROG_NAME} (PID {}); close it there with 'Q' first",
+                            holder.pid
+                        );
+                    }
+                }
+                Err(e) => bail!("{}: {e}", path.display()),
+            }
+        }
+        bail!(
+            "couldn't lock {key}: {} keeps being recreated",
+            path.display()
+        )
+    }
+}
+
+impl Drop for DeviceLock {
+    fn drop(&mut self) {
+        release(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn console_locks() -> Result<()> {
+        let dir = TestDir::new("lock")?;
+        let key = device_key(Some("BB/0001"), "1-2");
+        let lock = DeviceLock::acquire_in(&dir, &key)?;
+        let path = lock_path(&dir, &key);
+        if path.file_name() != Some("serial-BB_0001.lock".as_ref()) {
+            bail!("the lock was named {}", path.display());
+        }
+        if DeviceLock::acquire_in(&dir, &key).is_ok() {
+            bail!("a held lock was taken again");
+        }
+        drop(lock);
+        if path.exists() {
+            bail!("dropping the lock didn't remove it");
+        }
+
+        // left by a process that's gone; no PID gets that high
+        std::fs::write(&path, "pid=4294967295\n")?;
+        let lock = DeviceLock::acquire_in(&dir, &key)?;
+        if holder_at(&path) != Some(Holder { pid: process::id() }) {
+            bail!("the stale lock wasn't replaced");
+        }
+
+        let other = DeviceLock::acquire_in(&dir, &device_key(None, "1-2"))?;
+        // only this test's, not the session's
+        release_where(|p| p.starts_with(&dir));
+        if path.exists() || lock_path(&dir, &device_key(None, "1-2")).exists() {
+            bail!("releasing every lock left some behind");
+        }
+        drop((lock, other));
+        Ok(())
+    }
+}
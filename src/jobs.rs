@@ -0,0 +1,532 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::cancel::CancelToken;
+
+// Background jobs: a long read-only command given with '&' at the end ('verify nand.bin &') runs
+// on a worker thread that takes the console with it, so nothing else can reach the console until
+// it's done, and the prompt comes straight back for commands that don't need it. A job's progress
+// goes to its meter rather than the terminal, and what it would have printed is kept for when it
+// finishes, to be shown above the prompt or by 'fg'. Nothing that changes the console is ever run
+// this way.
+
+// how often 'fg' looks at a job
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// how far a job has got; on the job's thread, progress bars and anything else said go here
+#[derive(Default)]
+pub struct Meter {
+    done: AtomicU64,
+    total: AtomicU64,
+    lines: Mutex<Vec<String>>,
+}
+
+impl Meter {
+    pub fn start(&self, total: u64) {
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.done.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    // None until the job has said how much there is to do
+    pub fn percent(&self) -> Option<u64> {
+        match self.total() {
+            0 => None,
+            total => Some(self.done().min(total) * 100 / total),
+        }
+    }
+
+    fn say(&self, line: String) {
+        self.lines
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(line);
+    }
+
+    fn take_lines(&self) -> Vec<String> {
+        std::mem::take(&mut *self.lines.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+thread_local! {
+    static METER: RefCell<Option<Arc<Meter>>> = const { RefCell::new(None) };
+}
+
+// the meter of the job running on this thread, if this is a job's thread
+pub fn meter() -> Option<Arc<Meter>> {
+    METER.with(|m| m.borrow().clone())
+}
+
+// prints a line, or on a job's thread keeps it for when the job's done
+pub fn say(line: String) {
+    match meter() {
+        Some(meter) => meter.say(line),
+        None => println!("{line}"),
+    }
+}
+
+// what a job's work ends with: what it has to say, and the error that failed it, if one did
+pub struct Finished {
+    pub output: String,
+    pub error: Option<String>,
+}
+
+// what a job does with the device, as given to 'spawn' boxed
+pub type Work<D> = Box<dyn FnOnce(&D, &CancelToken) -> Finished + Send>;
+
+type Announce = Arc<Mutex<Box<dyn FnMut(String) + Send>>>;
+
+// what's said when a job finishes: how it went, then what it had to say
+fn notice(id: usize, command: &str, finished: &Finished, took: Duration) -> String {
+    let mut notice = match &finished.error {
+        None => format!("[{id}] Done: {command} ({}s)", took.as_secs()),
+        Some(e) => format!("[{id}] Failed: {command} ({}s): {e}", took.as_secs()),
+    };
+    if !finished.output.is_empty() {
+        notice.push('\n');
+        notice += finished.output.trim_end();
+    }
+    notice
+}
+
+pub struct Job<D> {
+    pub id: usize,
+    pub command: String,
+    pub meter: Arc<Meter>,
+    cancel: CancelToken,
+    started: Instant,
+    running: Option<JoinHandle<(D, Finished)>>,
+    // what it ended with, and how long it took
+    finished: Option<(Finished, Duration)>,
+    // whether its finishing has been said already
+    announced: Arc<AtomicBool>,
+}
+
+impl<D> Job<D> {
+    pub fn name(&self) -> &str {
+        self.command.split(' ').next().unwrap_or_default()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    // e.g. "verify, 43%"
+    pub fn status(&self) -> String {
+        match self.meter.percent() {
+            Some(percent) => format!("{}, {percent}%", self.name()),
+            None => format!("{}, starting", self.name()),
+        }
+    }
+
+    // its line in 'jobs'
+    pub fn line(&self) -> String {
+        match &self.finished {
+            None => format!(
+                "[{}] running  {}  {}% ({}/{} blocks), {}s so far",
+                self.id,
+                self.command,
+                self.meter.percent().unwrap_or(0),
+                self.meter.done(),
+                self.meter.total(),
+                self.started.elapsed().as_secs()
+            ),
+            Some((finished, took)) => format!(
+                "[{}] {}  {}  after {}s{}",
+                self.id,
+                if finished.error.is_some() {
+                    "failed "
+                } else {
+                    "done   "
+                },
+                self.command,
+                took.as_secs(),
+                finished
+                    .error
+                    .as_ref()
+                    .map(|e| format!(": {e}"))
+                    .unwrap_or_default()
+            ),
+        }
+    }
+
+    // what's said when it finishes
+    pub fn notice(&self) -> Option<String> {
+        let (finished, took) = self.finished.as_ref()?;
+        Some(notice(self.id, &self.command, finished, *took))
+    }
+
+    // stops it at its next cancellation point
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    // if it's finished, takes the device back from its thread
+    fn collect(&mut self) -> Option<D> {
+        if !self.running.as_ref()?.is_finished() {
+            return None;
+        }
+        let took = self.started.elapsed();
+        match self.running.take()?.join() {
+            Ok((device, finished)) => {
+                self.finished = Some((finished, took));
+                Some(device)
+            }
+            // the device went down with the thread
+            Err(_) => {
+                self.finished = Some((
+                    Finished {
+                        output: String::new(),
+                        error: Some("the job's thread panicked".to_string()),
+                    },
+                    took,
+                ));
+                None
+            }
+        }
+    }
+}
+
+// the session's jobs, numbered from 1; `D` is what a job takes with it, the console
+pub struct Jobs<D> {
+    jobs: Vec<Job<D>>,
+    announce: Option<Announce>,
+}
+
+impl<D> Default for Jobs<D> {
+    fn default() -> Self {
+        Self {
+            jobs: vec![],
+            announce: None,
+        }
+    }
+}
+
+impl<D: Send + 'static> Jobs<D> {
+    // jobs finishing are said with `print` as they finish, rather than at the next command
+    pub fn announce_with(&mut self, print: Box<dyn FnMut(String) + Send>) {
+        self.announce = Some(Arc::new(Mutex::new(print)));
+    }
+
+    // starts `work` on its own thread with `device`, which it has to itself until it's done;
+    // returns the job's number
+    pub fn spawn(
+        &mut self,
+        command: &str,
+        device: D,
+        work: impl FnOnce(&D, &CancelToken) -> Finished + Send + 'static,
+    ) -> usize {
+        let id = self.jobs.len() + 1;
+        let meter = Arc::new(Meter::default());
+        let cancel = CancelToken::default();
+        let announced = Arc::new(AtomicBool::new(false));
+        let started = Instant::now();
+        let running = {
+            let (meter, cancel, announced) = (meter.clone(), cancel.clone(), announced.clone());
+            let (command, announce) = (command.to_string(), self.announce.clone());
+            thread::spawn(move || {
+                METER.with(|m| *m.borrow_mut() = Some(meter.clone()));
+                let mut finished = work(&device, &cancel);
+                let lines = meter.take_lines();
+                if !lines.is_empty() {
+                    finished.output = format!("{}\n{}", lines.join("\n"), finished.output);
+                }
+                if let Some(announce) = announce {
+                    let said = notice(id, &command, &finished, started.elapsed());
+                    (announce.lock().unwrap_or_else(PoisonError::into_inner))(said);
+                    announced.store(true, Ordering::Relaxed);
+                }
+                (device, finished)
+            })
+        };
+        self.jobs.push(Job {
+            id,
+            command: command.to_string(),
+            meter,
+            cancel,
+            started,
+            running: Some(running),
+            finished: None,
+            announced,
+        });
+        id
+    }
+
+    // the running job that has the device, if one does
+    pub fn holder(&self) -> Option<&Job<D>> {
+        self.jobs.iter().find(|j| j.is_running())
+    }
+
+    // what's said to a command that needs the device while a job has it
+    pub fn busy(&self) -> Option<String> {
+        self.holder().map(|job| {
+            format!(
+                "The console is busy with job {} ({}). Use 'fg {}' to wait for it, or 'jobs' to see how it's doing.",
+                job.id,
+                job.status(),
+                job.id
+            )
+        })
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, id: usize) -> Option<&Job<D>> {
+        self.jobs.iter().find(|j| j.id == id)
+    }
+
+    pub fn list(&self) -> &[Job<D>] {
+        &self.jobs
+    }
+
+    // collects the jobs that have finished: the device back, if one had it, and what's to be
+    // said about each that hasn't been said already
+    pub fn reap(&mut self) -> (Option<D>, Vec<String>) {
+        let mut device = None;
+        let mut notices = vec![];
+        for job in &mut self.jobs {
+            if let Some(d) = job.collect() {
+                device = Some(d);
+            }
+            if job.finished.is_some() && !job.announced.swap(true, Ordering::Relaxed) {
+                notices.extend(job.notice());
+            }
+        }
+        (device, notices)
+    }
+
+    // waits for job `id` to finish, calling `tick` with it every POLL_INTERVAL while it runs;
+    // when `tick` returns false, the job is cancelled and waited for. Returns the device back,
+    // and the job's notice, which is taken as said.
+    pub fn wait(
+        &mut self,
+        id: usize,
+        tick: &mut dyn FnMut(&Job<D>) -> bool,
+    ) -> Result<(Option<D>, String)> {
+        let job = self
+            .jobs
+            .iter_mut()
+            .find(|j| j.id == id)
+            .ok_or_else(|| anyhow!("There's no job {id}; 'jobs' lists them"))?;
+        let mut device = None;
+        while job.is_running() {
+            if !tick(job) {
+                job.cancel();
+            }
+            thread::sleep(POLL_INTERVAL);
+            device = job.collect();
+        }
+        job.announced.store(true, Ordering::Relaxed);
+        let Some(notice) = job.notice() else {
+            bail!("Job {id} hasn't finished");
+        };
+        Ok((device, notice))
+    }
+
+    // stops every running job and waits for them, for quitting; the device back, if a job had it
+    pub fn stop_all(&mut self) -> Option<D> {
+        let running = self
+            .jobs
+            .iter()
+            .filter(|j| j.is_running())
+            .map(|j| j.id)
+            .collect::<Vec<_>>();
+        let mut device = None;
+        for id in running {
+            if let Ok((Some(d), _)) = self.wait(id, &mut |_| false) {
+                device = Some(d);
+            }
+        }
+        device
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs() -> Result<()> {
+        use std::sync::atomic::AtomicU32;
+
+        use bbrdb::CardStats;
+
+        use crate::fs::{BLOCK_SIZE, SPARE_SIZE};
+        use crate::player::Player;
+        use crate::summary::RangeSummary;
+        use crate::verify::verify_ranges;
+
+        // a console that takes a while over each block, and counts its reads
+        struct Slow {
+            id: u32,
+            delay: Duration,
+            reads: AtomicU32,
+        }
+
+        impl Player for Slow {
+            fn GetBBID(&self) -> Result<u32> {
+                Ok(self.id)
+            }
+
+            fn SetLED(&self, _value: u32) -> Result<()> {
+                Ok(())
+            }
+
+            fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+                Ok(vec![])
+            }
+
+            fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+                Ok(vec![0xFF; BLOCK_SIZE])
+            }
+
+            fn ReadFile(&self, _name: &str) -> Result<Option<Vec<u8>>> {
+                Ok(None)
+            }
+
+            fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+                thread::sleep(self.delay);
+                self.reads.fetch_add(1, Ordering::Relaxed);
+                Ok((vec![blk as u8; BLOCK_SIZE], vec![0xFF; SPARE_SIZE]))
+            }
+
+            fn CardStats(&self) -> Result<CardStats> {
+                Ok(CardStats {
+                    free: 0x40,
+                    used: 0,
+                    bad: 0,
+                    seqno: 1,
+                })
+            }
+        }
+
+        let slow = |id, millis| Slow {
+            id,
+            delay: Duration::from_millis(millis),
+            reads: AtomicU32::new(0),
+        };
+        // a verify of 0x40 blocks, as '&' runs it; block 0x3F of the file doesn't match
+        let verify = |player: &Slow, cancel: &CancelToken| {
+            let mut nand = (0..0x40u8)
+                .flat_map(|b| vec![b; BLOCK_SIZE])
+                .collect::<Vec<_>>();
+            nand[0x3F * BLOCK_SIZE] ^= 1;
+            let mut summary = RangeSummary::default();
+            let ranges = std::iter::once(0..0x40).collect::<Vec<_>>();
+            let result = verify_ranges(player, &nand, &ranges, false, &mut summary, cancel);
+            Finished {
+                output: summary.render(),
+                error: match result {
+                    Ok(_) => Some(format!(
+                        "{} blocks didn't match",
+                        summary.total_mismatches()
+                    )),
+                    Err(e) => Some(e.to_string()),
+                },
+            }
+        };
+
+        // the device goes with the job, and anything needing it is told what has it
+        let mut jobs = Jobs::default();
+        let id = jobs.spawn("verify nand.bin", slow(1, 5), verify);
+        if id != 1
+            || !jobs
+                .busy()
+                .is_some_and(|b| b.contains("busy with job 1 (verify, "))
+        {
+            bail!("a running job was described as {:?}", jobs.busy());
+        }
+        let (device, notices) = jobs.reap();
+        if device.is_some() || !notices.is_empty() {
+            bail!("the device came back while the job was still running");
+        }
+        // its progress can be seen partway through
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while jobs.get(1).is_some_and(|j| j.meter.done() < 4) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        let busy = jobs.busy().unwrap_or_default();
+        let percent = jobs.get(1).and_then(|j| j.meter.percent());
+        if !percent.is_some_and(|p| p > 0 && p < 100)
+            || !busy.contains(&format!("{}%", percent.unwrap_or(0)))
+        {
+            bail!("partway through, the job was at {percent:?}% and said '{busy}'");
+        }
+        // waiting for it gives back the device and what it said, and that isn't said again
+        let mut ticks = 0;
+        let (device, notice) = jobs.wait(1, &mut |_| {
+            ticks += 1;
+            true
+        })?;
+        if !device.is_some_and(|d| d.id == 1 && d.reads.load(Ordering::Relaxed) == 0x40)
+            || ticks == 0
+            || !notice.starts_with("[1] Failed: verify nand.bin")
+            || !notice.contains("1 blocks didn't match")
+            || !notice.contains("1 mismatched: 0x3F")
+        {
+            bail!("waiting for the job gave:\n{notice}");
+        }
+        if jobs.busy().is_some() || !jobs.reap().1.is_empty() {
+            bail!("a finished job still had the device, or was said to have finished twice");
+        }
+
+        // with somewhere to say it, a job's finishing is said as it finishes, and only there
+        let said = Arc::new(Mutex::new(vec![]));
+        let sink = said.clone();
+        jobs.announce_with(Box::new(move |n| sink.lock().unwrap().push(n)));
+        jobs.spawn("verify nand.bin", slow(2, 0), verify);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let device = loop {
+            match jobs.reap() {
+                (Some(device), notices) if notices.is_empty() => break device,
+                (Some(_), notices) => bail!("a job's finishing was said again: {notices:?}"),
+                (None, _) if Instant::now() > deadline => bail!("the second job never finished"),
+                (None, _) => thread::sleep(Duration::from_millis(5)),
+            }
+        };
+        let said = said.lock().unwrap().clone();
+        if device.id != 2 || said.len() != 1 || !said[0].starts_with("[2] Failed: verify") {
+            bail!("the second job's finishing was said as {said:?}");
+        }
+
+        // one that's cancelled stops at its next block, and still gives the device back
+        jobs.spawn("verify nand.bin", slow(3, 5), verify);
+        let (device, notice) = jobs.wait(3, &mut |_| false)?;
+        if !device.is_some_and(|d| d.reads.load(Ordering::Relaxed) < 0x40)
+            || !notice.contains("Cancelled")
+        {
+            bail!("the cancelled job gave:\n{notice}");
+        }
+        let lines = jobs.list().iter().map(Job::line).collect::<Vec<_>>();
+        if lines.len() != 3 || !lines[2].starts_with("[3] failed ") || jobs.stop_all().is_some() {
+            bail!("the jobs were listed as {lines:?}");
+        }
+        // a job's thread keeps what would have been printed, and nothing else's does
+        let keeps = thread::spawn(|| {
+            METER.with(|m| *m.borrow_mut() = Some(Arc::new(Meter::default())));
+            say("kept".to_string());
+            meter().map(|m| m.take_lines())
+        })
+        .join()
+        .map_err(|_| anyhow!("the thread panicked"))?;
+        if keeps != Some(vec!["kept".to_string()]) || meter().is_some() {
+            bail!("a job's thread kept {keeps:?}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use std::fs::{create_dir_all, read_to_string, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use chrono::Local;
+
+use crate::config::config_dir;
+
+const JOURNAL_FILE: &str = "write-journal.log";
+
+pub fn journal_path() -> Result<PathBuf> {
+    let dir =
+        config_dir().ok_or_else(|| anyhow!("no config directory to keep the write journal in"))?;
+    create_dir_all(&dir)?;
+    Ok(dir.join(JOURNAL_FILE))
+}
+
+// an append-only log of the steps of a multi-step change to a console's card, each synced to
+// disk before the next step starts, so an interrupted change can be pieced back together
+pub struct Journal {
+    file: File,
+    // which console and operation the lines are about
+    prefix: String,
+}
+
+impl Journal {
+    pub fn open(bbid: u32, operation: &str) -> Result<Self> {
+        let path = journal_path()?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| anyhow!("{}: {e}", path.display()))?;
+        Ok(Self {
+            file,
+            prefix: format!("{bbid:08X} {operation}"),
+        })
+    }
+
+    pub fn record(&mut self, step: &str) -> Result<()> {
+        writeln!(
+            self.file,
+            "{} {}: {step}",
+            Local::now().to_rfc3339(),
+            self.prefix
+        )?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+// a line of the journal, as `Journal::record` wrote it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub bbid: u32,
+    pub operation: String,
+    pub step: String,
+}
+
+pub fn parse_line(line: &str) -> Option<Entry> {
+    let (_time, rest) = line.split_once(' ')?;
+    let (bbid, rest) = rest.split_once(' ')?;
+    let (operation, step) = rest.split_once(": ")?;
+    Some(Entry {
+        bbid: u32::from_str_radix(bbid, 16).ok()?,
+        operation: operation.to_string(),
+        step: step.to_string(),
+    })
+}
+
+// every line of the journal, oldest first; lines that don't parse (a write cut short) are skipped
+pub fn read_entries() -> Result<Vec<Entry>> {
+    let path = journal_path()?;
+    match read_to_string(&path) {
+        Ok(text) => Ok(text.lines().filter_map(parse_line).collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(anyhow!("{}: {e}", path.display())),
+    }
+}
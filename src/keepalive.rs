@@ -0,0 +1,236 @@
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::player::Backend;
+
+// The console's link tends to go stale after a long idle period, which makes the next command
+// fail. While the prompt waits for a command, the console is handed to a thread that pings it
+// with a cheap query whenever it's been quiet for the interval, and reconnects it if the ping
+// fails. The thread owns the console until the next command takes it back, so a ping can never
+// run alongside a command; what happened while idle is said then.
+
+// what the keep-alive needs of a console
+pub trait Link: Send {
+    // a harmless query
+    fn ping(&mut self) -> Result<()>;
+    fn reconnect(&mut self) -> Result<()>;
+}
+
+impl Link for Box<dyn Backend> {
+    fn ping(&mut self) -> Result<()> {
+        self.CardStats().map(|_| ())
+    }
+
+    fn reconnect(&mut self) -> Result<()> {
+        let _ = self.Close();
+        self.Init()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Health {
+    Fine,
+    // it stopped answering and was reconnected
+    Reconnected(String),
+    // it stopped answering and couldn't be reconnected, so may not answer the next command
+    Suspect(String),
+}
+
+// when to ping, and how it's gone; driven by whatever clock it's given
+pub struct Pinger {
+    interval: Duration,
+    // when the link was last used
+    last: Instant,
+    pub health: Health,
+    pub pings: usize,
+}
+
+impl Pinger {
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            last: now,
+            health: Health::Fine,
+            pings: 0,
+        }
+    }
+
+    // how long until the next ping's due
+    pub fn until_due(&self, now: Instant) -> Duration {
+        (self.last + self.interval).saturating_duration_since(now)
+    }
+
+    // pings if the link's been quiet for the interval, reconnecting if it doesn't answer; a
+    // suspect link is reconnected rather than pinged
+    pub fn tick<L: Link + ?Sized>(&mut self, link: &mut L, now: Instant) {
+        if !self.until_due(now).is_zero() {
+            return;
+        }
+        self.last = now;
+        let failed = match &self.health {
+            Health::Suspect(why) => why.clone(),
+            _ => {
+                self.pings += 1;
+                match link.ping() {
+                    Ok(()) => return,
+                    Err(e) => e.to_string(),
+                }
+            }
+        };
+        self.health = match link.reconnect() {
+            Ok(()) => Health::Reconnected(failed),
+            Err(e) => Health::Suspect(format!("{failed}; reconnecting failed: {e}")),
+        };
+    }
+}
+
+struct Worker<L> {
+    stop: Sender<()>,
+    thread: JoinHandle<(L, Pinger)>,
+}
+
+// the console, while it's with the keep-alive thread
+pub struct KeepAlive<L = Box<dyn Backend>> {
+    worker: Option<Worker<L>>,
+}
+
+impl<L> Default for KeepAlive<L> {
+    fn default() -> Self {
+        Self { worker: None }
+    }
+}
+
+impl<L: Link + 'static> KeepAlive<L> {
+    // hands `link` to a thread that keeps it warm until 'resume'
+    pub fn idle(&mut self, link: L, interval: Duration) {
+        let (stop, stopped) = channel();
+        let thread = thread::spawn(move || {
+            let mut link = link;
+            let mut pinger = Pinger::new(interval, Instant::now());
+            while let Err(RecvTimeoutError::Timeout) =
+                stopped.recv_timeout(pinger.until_due(Instant::now()))
+            {
+                pinger.tick(&mut link, Instant::now());
+            }
+            (link, pinger)
+        });
+        self.worker = Some(Worker { stop, thread });
+    }
+
+    // takes the console back, waiting for a ping under way to finish, with how it went while
+    // idle; None if it wasn't idle (or the thread panicked, which takes the console with it)
+    pub fn resume(&mut self) -> Option<(L, Health)> {
+        let worker = self.worker.take()?;
+        let _ = worker.stop.send(());
+        let (link, pinger) = worker.thread.join().ok()?;
+        Some((link, pinger.health))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    // a console that fails the next `failing_pings` pings and `failing_reconnects` reconnects
+    #[derive(Default)]
+    struct Mock {
+        calls: Vec<&'static str>,
+        failing_pings: usize,
+        failing_reconnects: usize,
+    }
+
+    impl Link for Mock {
+        fn ping(&mut self) -> Result<()> {
+            self.calls.push("ping");
+            if self.failing_pings > 0 {
+                self.failing_pings -= 1;
+                return Err(anyhow!("Operation timed out"));
+            }
+            Ok(())
+        }
+
+        fn reconnect(&mut self) -> Result<()> {
+            self.calls.push("reconnect");
+            if self.failing_reconnects > 0 {
+                self.failing_reconnects -= 1;
+                return Err(anyhow!("No such device"));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn pings() -> Result<()> {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut pinger = Pinger::new(Duration::from_secs(60), start);
+        let mut link = Mock::default();
+
+        // only once it's been quiet for the interval, and then not again for another
+        pinger.tick(&mut link, at(30));
+        assert!(link.calls.is_empty());
+        assert_eq!(pinger.until_due(at(30)), Duration::from_secs(30));
+        pinger.tick(&mut link, at(60));
+        pinger.tick(&mut link, at(90));
+        assert_eq!(link.calls, ["ping"]);
+        pinger.tick(&mut link, at(150));
+        assert_eq!((pinger.pings, &pinger.health), (2, &Health::Fine));
+
+        // a failed ping reconnects
+        link = Mock {
+            failing_pings: 1,
+            ..Mock::default()
+        };
+        pinger.tick(&mut link, at(210));
+        assert_eq!(link.calls, ["ping", "reconnect"]);
+        assert_eq!(
+            pinger.health,
+            Health::Reconnected("Operation timed out".to_string())
+        );
+
+        // and if that fails, the link's suspect, and the next tick tries reconnecting again
+        let mut pinger = Pinger::new(Duration::from_secs(60), start);
+        link = Mock {
+            failing_pings: 1,
+            failing_reconnects: 1,
+            ..Mock::default()
+        };
+        pinger.tick(&mut link, at(60));
+        assert_eq!(
+            pinger.health,
+            Health::Suspect("Operation timed out; reconnecting failed: No such device".to_string())
+        );
+        pinger.tick(&mut link, at(120));
+        assert_eq!(link.calls, ["ping", "reconnect", "reconnect"]);
+        assert!(matches!(pinger.health, Health::Reconnected(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn idles() -> Result<()> {
+        let mut keepalive = KeepAlive::default();
+        assert!(keepalive.resume().is_none());
+
+        // the console goes with the thread, and comes back with what it did
+        keepalive.idle(Mock::default(), Duration::from_millis(5));
+        thread::sleep(Duration::from_millis(50));
+        let (link, health) = keepalive
+            .resume()
+            .ok_or_else(|| anyhow!("no console back"))?;
+        assert!(keepalive.resume().is_none());
+        assert_eq!(health, Health::Fine);
+        assert!(!link.calls.is_empty() && link.calls.iter().all(|&c| c == "ping"));
+
+        // taken back before it's due, it isn't pinged at all
+        keepalive.idle(Mock::default(), Duration::from_secs(3600));
+        let (link, _) = keepalive
+            .resume()
+            .ok_or_else(|| anyhow!("no console back"))?;
+        assert!(link.calls.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,488 @@
+#[cfg(feature = "writing")]
+use std::fmt::{self, Display};
+#[cfg(feature = "writing")]
+use std::ops::Range;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::cancel::CancelToken;
+#[cfg(feature = "writing")]
+use crate::fs::{BLOCK_SIZE, FS_REGION_BLOCKS, SKSA_BLOCKS, SPARE_SIZE};
+use crate::hashing::HashAlgo;
+use crate::player::Player;
+use crate::provenance::dump_manifest;
+use crate::provision::is_protected;
+#[cfg(feature = "writing")]
+use crate::strict::Manifest;
+
+// 'kit create <dir>': what it takes to put a console back as it was, short of its games, small
+// enough to make before any experiment. A kit is a directory of:
+//
+//   kit.txt                    which console it's from (BBID, serial number), the card's size and
+//                              stats, when it was made and which system files it holds
+//   sksa.bin, sksa.spare.bin   the SKSA's blocks and their spare data
+//   fs.bin, fs.spare.bin       the FS region's blocks (the card's last 16) and their spare data
+//   <name>.sys                 each of the console's system files, such as ticket.sys
+//   kit.sha256                 a manifest of all of the above, with the console and when
+//
+// 'kit restore <dir>' checks all of a kit (or the parts of it asked for) against its manifest
+// before anything is written, then puts each part back in the order it depends on: the SKSA,
+// then the FS region, then the files, asking about each.
+
+pub const INFO_FILE: &str = "kit.txt";
+pub const MANIFEST_FILE: &str = "kit.sha256";
+
+const KIT_VERSION: u32 = 1;
+const VERSION_LINE: &str = "# recovery kit, version";
+
+// what a kit holds, in the order it's restored in
+#[cfg(feature = "writing")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Sksa,
+    Fs,
+    File(String),
+}
+
+#[cfg(feature = "writing")]
+impl Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sksa => f.write_str("the SKSA"),
+            Self::Fs => f.write_str("the FS region"),
+            Self::File(name) => f.write_str(name),
+        }
+    }
+}
+
+#[cfg(feature = "writing")]
+impl Item {
+    // what it's called on the command line
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Sksa => "sksa",
+            Self::Fs => "fs",
+            Self::File(name) => name,
+        }
+    }
+
+    // the kit's files holding it, and how long each must be, if it has a set length
+    fn parts(&self) -> Vec<(String, Option<usize>)> {
+        let region = |name: &str, count: usize| {
+            vec![
+                (format!("{name}.bin"), Some(count * BLOCK_SIZE)),
+                (format!("{name}.spare.bin"), Some(count * SPARE_SIZE)),
+            ]
+        };
+        match self {
+            Self::Sksa => region("sksa", SKSA_BLOCKS as usize),
+            Self::Fs => region("fs", FS_REGION_BLOCKS),
+            Self::File(name) => vec![(name.clone(), None)],
+        }
+    }
+
+    // the blocks it's written to, on a card of `blocks` blocks
+    pub fn blocks(&self, blocks: u16) -> Option<Range<u16>> {
+        match self {
+            Self::Sksa => Some(0..SKSA_BLOCKS),
+            Self::Fs => Some(blocks.saturating_sub(FS_REGION_BLOCKS as u16)..blocks),
+            Self::File(_) => None,
+        }
+    }
+}
+
+// what kit.txt says
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KitInfo {
+    pub bbid: u32,
+    pub serial: Option<String>,
+    pub blocks: u32,
+    pub stats: String,
+    pub made: String,
+    pub files: Vec<String>,
+}
+
+impl KitInfo {
+    pub fn to_text(&self) -> String {
+        format!(
+            "{VERSION_LINE} {KIT_VERSION}\nbbid: {:08X}\nserial: {}\nblocks: {:#X}\nstats: {}\nmade: {}\nfiles: {}\n",
+            self.bbid,
+            self.serial.as_deref().unwrap_or("unknown"),
+            self.blocks,
+            self.stats,
+            self.made,
+            self.files.join(", ")
+        )
+    }
+
+    #[cfg(feature = "writing")]
+    pub fn parse(text: &str) -> Result<Self> {
+        let version = text
+            .lines()
+            .find_map(|l| l.trim().strip_prefix(VERSION_LINE))
+            .ok_or_else(|| anyhow!("{INFO_FILE} doesn't say it's a recovery kit"))?;
+        if version.trim() != KIT_VERSION.to_string() {
+            bail!("{INFO_FILE} is from version {} of the kit format; this reads version {KIT_VERSION}", version.trim());
+        }
+        let field = |key: &str| {
+            text.lines()
+                .find_map(|l| l.trim().strip_prefix(key)?.strip_prefix(':'))
+                .map(str::trim)
+                .ok_or_else(|| anyhow!("{INFO_FILE} has no '{key}' line"))
+        };
+        let bbid = field("bbid")?;
+        let blocks = field("blocks")?;
+        Ok(Self {
+            bbid: u32::from_str_radix(bbid, 16).map_err(|_| anyhow!("'{bbid}' isn't a BBID"))?,
+            serial: Some(field("serial")?.to_string()).filter(|s| s != "unknown"),
+            blocks: parse_int::parse(blocks)
+                .map_err(|_| anyhow!("'{blocks}' isn't a number of blocks"))?,
+            stats: field("stats").unwrap_or_default().to_string(),
+            made: field("made").unwrap_or_default().to_string(),
+            files: field("files")?
+                .split(',')
+                .map(str::trim)
+                .filter(|f| !f.is_empty())
+                .map(str::to_string)
+                .collect(),
+        })
+    }
+
+    // everything the kit holds, in restore order
+    #[cfg(feature = "writing")]
+    pub fn items(&self) -> Vec<Item> {
+        [Item::Sksa, Item::Fs]
+            .into_iter()
+            .chain(self.files.iter().cloned().map(Item::File))
+            .collect()
+    }
+}
+
+// the kit's files, named as they go in its directory: the parts, kit.txt and the manifest of
+// them all
+pub fn build(
+    info: &KitInfo,
+    sksa: (Vec<u8>, Vec<u8>),
+    fs: (Vec<u8>, Vec<u8>),
+    files: Vec<(String, Vec<u8>)>,
+    algos: &[HashAlgo],
+) -> Vec<(String, Vec<u8>)> {
+    let mut out = vec![
+        (INFO_FILE.to_string(), info.to_text().into_bytes()),
+        ("sksa.bin".to_string(), sksa.0),
+        ("sksa.spare.bin".to_string(), sksa.1),
+        ("fs.bin".to_string(), fs.0),
+        ("fs.spare.bin".to_string(), fs.1),
+    ];
+    out.extend(files);
+    let listed = out
+        .iter()
+        .map(|(n, d)| (n.as_str(), d.as_slice()))
+        .collect::<Vec<_>>();
+    let manifest = dump_manifest(Some(info.bbid), Some(&info.made), &listed, algos);
+    out.push((MANIFEST_FILE.to_string(), manifest.into_bytes()));
+    out
+}
+
+// the data and spare data of each block in `blocks`, read one at a time
+pub fn read_blocks(
+    player: &dyn Player,
+    blocks: impl Iterator<Item = u32>,
+    cancel: &CancelToken,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let (mut nand, mut spare) = (vec![], vec![]);
+    for blk in blocks {
+        cancel.check()?;
+        let (data, s) = player
+            .ReadSingleBlock(blk)
+            .map_err(|e| anyhow!("reading block {blk:#X}: {e}"))?;
+        nand.extend(data);
+        spare.extend(s);
+    }
+    Ok((nand, spare))
+}
+
+// the console's system files, by name
+pub fn system_files(player: &dyn Player) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut files = vec![];
+    for (name, _) in player.ListFiles()? {
+        if !is_protected(&name) {
+            continue;
+        }
+        match player.ReadFile(&name)? {
+            Some(data) => files.push((name, data)),
+            None => bail!("{name} is listed, but couldn't be read"),
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// an item checked and ready to be restored: its data, and for a region its spare data
+#[cfg(feature = "writing")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Restore {
+    pub item: Item,
+    pub data: Vec<u8>,
+    pub spare: Vec<u8>,
+}
+
+// the items of the kit asked for (all of them if none are), each checked against the manifest,
+// in restore order; every missing or corrupt part is reported together, before anything is
+// written. `read` gets one of the kit's files by name.
+#[cfg(feature = "writing")]
+pub fn plan(
+    manifest: &str,
+    read: &mut dyn FnMut(&str) -> Result<Vec<u8>>,
+    chosen: &[&str],
+) -> Result<(KitInfo, Vec<Restore>)> {
+    let manifest = Manifest::parse(manifest).map_err(|e| anyhow!("{MANIFEST_FILE}: {e}"))?;
+    let mut problems = vec![];
+    let mut check =
+        |problems: &mut Vec<String>, name: &str, len: Option<usize>| -> Option<Vec<u8>> {
+            let expected = manifest
+                .entries()
+                .iter()
+                .filter(|(_, n)| n == name)
+                .map(|(h, _)| h)
+                .collect::<Vec<_>>();
+            if expected.is_empty() {
+                problems.push(format!("{name} isn't listed in {MANIFEST_FILE}"));
+                return None;
+            }
+            let data = match read(name) {
+                Ok(d) => d,
+                Err(e) => {
+                    problems.push(format!("{name} is missing ({e})"));
+                    return None;
+                }
+            };
+            if expected.iter().any(|h| h.algo.digest(&data) != **h) {
+                problems.push(format!("{name} doesn't match its hash in {MANIFEST_FILE}"));
+                return None;
+            }
+            if let Some(len) = len.filter(|&l| l != data.len()) {
+                problems.push(format!("{name} is {:#X} bytes, not {len:#X}", data.len()));
+                return None;
+            }
+            Some(data)
+        };
+
+    let info = match check(&mut problems, INFO_FILE, None) {
+        Some(text) => KitInfo::parse(&String::from_utf8_lossy(&text))?,
+        None => bail!("The kit can't be used: {}", problems.join("; ")),
+    };
+    let items = info.items();
+    if let Some(unknown) = chosen
+        .iter()
+        .find(|c| !items.iter().any(|i| i.key() == **c))
+    {
+        let keys = items.iter().map(Item::key).collect::<Vec<_>>();
+        bail!("The kit holds no '{unknown}'; it holds {}", keys.join(", "));
+    }
+
+    let mut restores = vec![];
+    for item in items {
+        if !chosen.is_empty() && !chosen.contains(&item.key()) {
+            continue;
+        }
+        let mut parts = item
+            .parts()
+            .into_iter()
+            .map(|(name, len)| check(&mut problems, &name, len))
+            .collect::<Vec<_>>();
+        if parts.iter().all(Option::is_some) {
+            let spare = match parts.len() {
+                2 => parts.pop().flatten().unwrap_or_default(),
+                _ => vec![],
+            };
+            restores.push(Restore {
+                item,
+                data: parts.pop().flatten().unwrap_or_default(),
+                spare,
+            });
+        }
+    }
+    if !problems.is_empty() {
+        bail!(
+            "The kit can't be used, so nothing was written:\n  {}",
+            problems.join("\n  ")
+        );
+    }
+    Ok((info, restores))
+}
+
+// why restoring `restores` from the kit to a console with `blocks` blocks can't go ahead: the FS
+// region is at the end of the card, so a kit's can only go back on a card of the same size
+#[cfg(feature = "writing")]
+pub fn check_fit(info: &KitInfo, restores: &[Restore], blocks: u32) -> Result<()> {
+    if blocks != info.blocks && restores.iter().any(|r| r.item == Item::Fs) {
+        bail!(
+            "The kit's FS region is from a card of {:#X} blocks, and this one has {blocks:#X}",
+            info.blocks
+        );
+    }
+    Ok(())
+}
+
+// a region's data and spare data placed at its blocks, in an image long enough for write_ranges
+#[cfg(feature = "writing")]
+pub fn place(range: &Range<u16>, data: &[u8], spare: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut nand = vec![0xFF; range.end as usize * BLOCK_SIZE];
+    let mut spares = vec![0xFF; range.end as usize * SPARE_SIZE];
+    nand[range.start as usize * BLOCK_SIZE..].copy_from_slice(data);
+    spares[range.start as usize * SPARE_SIZE..].copy_from_slice(spare);
+    (nand, spares)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{BLOCK_SIZE, FS_REGION_BLOCKS, SKSA_BLOCKS, SPARE_SIZE};
+
+    #[test]
+    fn recovery_kits() -> Result<()> {
+        let info = KitInfo {
+            bbid: 0x1234ABCD,
+            serial: None,
+            blocks: 0x1000,
+            stats: "4016 free, 80 used, 0 bad, FS sequence 7".to_string(),
+            made: "2024-05-02T10:00:00+01:00".to_string(),
+            files: vec!["sig.sys".to_string(), "ticket.sys".to_string()],
+        };
+        // a kit of patterned regions and files; its manifest lists every other file in it
+        let region = |count: usize, fill: u8| {
+            (
+                vec![fill; count * BLOCK_SIZE],
+                vec![fill; count * SPARE_SIZE],
+            )
+        };
+        let files = vec![
+            ("sig.sys".to_string(), b"signatures".to_vec()),
+            ("ticket.sys".to_string(), b"tickets".to_vec()),
+        ];
+        let kit = build(
+            &info,
+            region(SKSA_BLOCKS as usize, 0x5A),
+            region(FS_REGION_BLOCKS, 0xA5),
+            files,
+            &[HashAlgo::Crc32],
+        );
+        let names = kit.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+        let manifest = String::from_utf8_lossy(&kit.last().unwrap().1).into_owned();
+        if names.last() != Some(&MANIFEST_FILE)
+            || names[..names.len() - 1]
+                .iter()
+                .any(|n| !manifest.lines().any(|l| l.ends_with(&format!("  {n}"))))
+            || !manifest.contains("# source-bbid: 1234ABCD")
+        {
+            bail!("the kit was {names:?}, with the manifest:\n{manifest}");
+        }
+
+        #[cfg(feature = "writing")]
+        {
+            use std::collections::BTreeMap;
+
+            if KitInfo::parse(&info.to_text())? != info {
+                bail!("kit.txt didn't read back as written:\n{}", info.to_text());
+            }
+            if KitInfo::parse("bbid: 1234ABCD\nblocks: 0x1000\nfiles:\n").is_ok() {
+                bail!("a kit.txt without its version line was read");
+            }
+            let keys = info
+                .items()
+                .iter()
+                .map(|i| i.key().to_string())
+                .collect::<Vec<_>>();
+            if keys != ["sksa", "fs", "sig.sys", "ticket.sys"] {
+                bail!("the kit's items were {keys:?}");
+            }
+
+            let stored = kit.into_iter().collect::<BTreeMap<_, _>>();
+            let reader = |stored: &BTreeMap<String, Vec<u8>>| {
+                let stored = stored.clone();
+                move |name: &str| {
+                    stored
+                        .get(name)
+                        .cloned()
+                        .ok_or_else(|| anyhow!("no such file"))
+                }
+            };
+            let order = |restores: &[Restore]| {
+                restores
+                    .iter()
+                    .map(|r| r.item.key().to_string())
+                    .collect::<Vec<_>>()
+            };
+
+            // everything, or what's asked for, comes back in the order it depends on
+            let (read_info, all) = plan(&manifest, &mut reader(&stored), &[])?;
+            if read_info != info || order(&all) != keys {
+                bail!("the whole kit was planned as {:?}", order(&all));
+            }
+            if all[0].data.len() != SKSA_BLOCKS as usize * BLOCK_SIZE
+                || all[1].spare != vec![0xA5; FS_REGION_BLOCKS * SPARE_SIZE]
+                || all[3].data != b"tickets"
+                || !all[3].spare.is_empty()
+            {
+                bail!("the kit's items were read back wrong");
+            }
+            let (_, some) = plan(
+                &manifest,
+                &mut reader(&stored),
+                &["ticket.sys", "fs", "sksa"],
+            )?;
+            if order(&some) != ["sksa", "fs", "ticket.sys"] {
+                bail!("ticket.sys, fs, sksa was planned as {:?}", order(&some));
+            }
+            match plan(&manifest, &mut reader(&stored), &["game.app"]) {
+                Err(e) if e.to_string().contains("holds no 'game.app'") => {}
+                other => bail!(
+                    "asking for something the kit doesn't hold gave {:?}",
+                    other.map(|p| order(&p.1))
+                ),
+            }
+
+            // every missing or corrupt part is reported, before anything is written
+            let mut damaged = stored.clone();
+            damaged.remove("fs.spare.bin");
+            damaged.insert("ticket.sys".to_string(), b"tickets!".to_vec());
+            match plan(&manifest, &mut reader(&damaged), &[]) {
+                Err(e)
+                    if e.to_string().contains("fs.spare.bin is missing")
+                        && e.to_string().contains("ticket.sys doesn't match")
+                        && e.to_string().contains("nothing was written") => {}
+                other => bail!("a damaged kit gave {:?}", other.map(|p| order(&p.1))),
+            }
+            // only what's asked for has to be sound
+            if order(&plan(&manifest, &mut reader(&damaged), &["sksa", "sig.sys"])?.1)
+                != ["sksa", "sig.sys"]
+            {
+                bail!("a sound part of a damaged kit couldn't be restored");
+            }
+            let mut unsigned = stored.clone();
+            unsigned.remove(INFO_FILE);
+            if plan(&manifest, &mut reader(&unsigned), &["sksa"]).is_ok() {
+                bail!("a kit without its kit.txt was used");
+            }
+
+            // the FS region only goes back on a card of the same size, and lands at its end
+            if check_fit(&info, &some, 0x2000).is_ok()
+                || check_fit(&info, &some[..1], 0x2000).is_err()
+            {
+                bail!("the FS region's fit to the card was judged wrong");
+            }
+            let range = Item::Fs.blocks(0x1000).unwrap();
+            let (nand, spare) = place(&range, &some[1].data, &some[1].spare);
+            if range != (0xFF0..0x1000)
+                || nand.len() != 0x1000 * BLOCK_SIZE
+                || nand[0xFF0 * BLOCK_SIZE - 1] != 0xFF
+                || nand[0xFF0 * BLOCK_SIZE] != 0xA5
+                || spare[0xFF0 * SPARE_SIZE] != 0xA5
+            {
+                bail!("the FS region was placed at the wrong blocks");
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+use std::fs::read_to_string;
+use std::io::ErrorKind;
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::config_dir;
+use crate::error_chain::{Layer, Level};
+
+// Known failures: signatures of the errors people run into most, each with what usually fixes
+// it, which is printed under the error when one matches. More can be added in signatures.toml in
+// the config directory, and a shipped one replaced by giving its id:
+//
+//   [[signature]]
+//   id = "bench-3-hub"
+//   contains = ["timed out"]
+//   remedy = "The hub on bench 3 drops out; plug the console into the PC directly"
+//
+// A signature matches when each of its strings appears (ignoring case) somewhere in the error's
+// chain and, if it gives a layer, some level of the chain is in that layer. An error that
+// matches nothing gets nothing extra.
+
+const SIGNATURES_FILE: &str = "signatures.toml";
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub id: String,
+    pub contains: Vec<String>,
+    #[serde(default)]
+    pub layer: Option<Layer>,
+    pub remedy: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SignatureFile {
+    signature: Vec<Signature>,
+}
+
+fn signature(id: &str, contains: &[&str], layer: Option<Layer>, remedy: &str) -> Signature {
+    Signature {
+        id: id.to_string(),
+        contains: contains.iter().map(|c| c.to_string()).collect(),
+        layer,
+        remedy: remedy.to_string(),
+    }
+}
+
+// the ones that come with the program; most errors lose their type on the way up (a message
+// formatted from another), so these go by the text, and only by the layer where the type survives
+pub fn builtin() -> Vec<Signature> {
+    vec![
+        signature(
+            "usb-busy",
+            &["resource busy"],
+            None,
+            "Something else has claimed the console's USB interface, usually another program \
+             that's still running; close it, or unplug and replug the console, then select it \
+             again with 's'",
+        ),
+        signature(
+            "usb-access",
+            &["access denied"],
+            None,
+            "This user isn't allowed to open the console's USB device: on Linux, add a udev \
+             rule for it (or run as root); on Windows, install the WinUSB driver for it",
+        ),
+        signature(
+            "disconnected",
+            &["no such device"],
+            None,
+            "The console was unplugged, lost power or reset itself; check its cable and power, \
+             then find it again with 'l' and 's'",
+        ),
+        signature(
+            "timeout-mid-transfer",
+            &["timed out", "block"],
+            None,
+            "A timeout partway through usually means a failing cable or a USB hub dropping out: \
+             try another cable, or a port on the PC itself, then 'reset-usb' and run the command \
+             again",
+        ),
+        signature(
+            "stalled",
+            &["pipe error"],
+            None,
+            "The console stopped accepting requests; 'reset-usb' (or replugging it) and then 'B' \
+             usually bring it back",
+        ),
+        signature(
+            "failing-block",
+            &["couldn't be read cleanly"],
+            None,
+            "The block keeps failing its ECC, so it's wearing out. '--heroic' can often recover \
+             its data by voting across reads; once that's safe, 'relocate' moves the data off it \
+             and marks it bad",
+        ),
+        signature(
+            "fs-corrupt",
+            &["bad fs checksum"],
+            Some(Layer::Parsing),
+            "This FS generation is damaged; 'triage' shows whether an older one is intact, and \
+             what can be recovered",
+        ),
+    ]
+}
+
+// the config directory's signatures, then the shipped ones that it doesn't replace
+pub fn merge(extra: Vec<Signature>) -> Vec<Signature> {
+    let mut signatures = builtin();
+    signatures.retain(|s| !extra.iter().any(|e| e.id == s.id));
+    extra.into_iter().chain(signatures).collect()
+}
+
+pub fn parse(text: &str) -> Result<Vec<Signature>> {
+    let file: SignatureFile = toml::from_str(text)?;
+    if let Some(s) = file.signature.iter().find(|s| s.contains.is_empty()) {
+        return Err(anyhow!(
+            "signature '{}' has nothing in 'contains', so it would match every error",
+            s.id
+        ));
+    }
+    Ok(file.signature)
+}
+
+fn load() -> Result<Vec<Signature>> {
+    let Some(path) = config_dir().map(|d| d.join(SIGNATURES_FILE)) else {
+        return Ok(vec![]);
+    };
+    match read_to_string(&path) {
+        Ok(text) => parse(&text).map_err(|e| anyhow!("{}: {e}", path.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(anyhow!("{}: {e}", path.display())),
+    }
+}
+
+// loaded the first time an error is printed; a file that can't be used is said so once, and the
+// shipped signatures are used without it
+fn signatures() -> &'static [Signature] {
+    static SIGNATURES: OnceLock<Vec<Signature>> = OnceLock::new();
+    SIGNATURES.get_or_init(|| {
+        merge(load().unwrap_or_else(|e| {
+            eprintln!("{e}");
+            vec![]
+        }))
+    })
+}
+
+pub fn matches(signature: &Signature, levels: &[Level]) -> bool {
+    let text = levels
+        .iter()
+        .map(|l| l.message.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("\n");
+    !signature.contains.is_empty()
+        && signature
+            .contains
+            .iter()
+            .all(|c| text.contains(&c.to_lowercase()))
+        && signature
+            .layer
+            .is_none_or(|layer| levels.iter().any(|l| l.layer == Some(layer)))
+}
+
+pub fn find<'a>(signatures: &'a [Signature], levels: &[Level]) -> Option<&'a Signature> {
+    signatures.iter().find(|s| matches(s, levels))
+}
+
+// the signature the last error printed matched, for 'report save'
+static LAST_MATCH: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn last_match() -> Option<String> {
+    LAST_MATCH.lock().ok()?.clone()
+}
+
+// the known signature `levels` matches, if there is one, remembered for 'report save'
+pub fn identify(levels: &[Level]) -> Option<&'static Signature> {
+    let found = find(signatures(), levels);
+    if let Ok(mut last) = LAST_MATCH.lock() {
+        *last = found.map(|s| s.id.clone());
+    }
+    found
+}
+
+#[derive(Serialize)]
+struct RemedyEvent<'a> {
+    event: &'static str,
+    signature: &'a str,
+    remedy: &'a str,
+}
+
+pub fn render_json(signature: &Signature) -> String {
+    serde_json::to_string(&RemedyEvent {
+        event: "known_error",
+        signature: &signature.id,
+        remedy: &signature.remedy,
+    })
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_errors() -> Result<()> {
+        use anyhow::bail;
+
+        use crate::error_chain::levels;
+
+        let shipped = builtin();
+        let id = |e: &anyhow::Error| find(&shipped, &levels(&**e)).map(|s| s.id.as_str());
+
+        // the failures the table is for, as they reach the prompt
+        for (error, expected) in [
+            (
+                anyhow::Error::new(rusb::Error::Busy).context("Couldn't open player 0"),
+                "usb-busy",
+            ),
+            (anyhow::Error::new(rusb::Error::Access), "usb-access"),
+            (
+                anyhow::Error::new(rusb::Error::NoDevice).context("Init failed"),
+                "disconnected",
+            ),
+            (
+                anyhow!("Failed to read block 0x1A2: Operation timed out"),
+                "timeout-mid-transfer",
+            ),
+            (
+                anyhow::Error::new(rusb::Error::Timeout).context("Couldn't read block 0x42"),
+                "timeout-mid-transfer",
+            ),
+            (
+                anyhow!("block 0x123 couldn't be read cleanly in 16 attempts (last: the data didn't match its ECC)"),
+                "failing-block",
+            ),
+            (
+                anyhow::Error::new(crate::fs::FsError::BadChecksum(0x1234)),
+                "fs-corrupt",
+            ),
+        ] {
+            if id(&error) != Some(expected) {
+                bail!("'{error:#}' matched {:?}, not {expected}", id(&error));
+            }
+        }
+
+        // near misses match nothing, so they print nothing extra
+        for error in [
+            // a timeout that isn't partway through a transfer
+            anyhow::Error::new(rusb::Error::Timeout).context("Init failed"),
+            // a local file, not the console
+            anyhow::Error::new(std::io::Error::new(
+                ErrorKind::PermissionDenied,
+                "Permission denied",
+            ))
+            .context("nand.bin"),
+            anyhow!("File busy.app not found"),
+            anyhow!("'hook.sh' was still running after 300s, and was killed"),
+            // the right words, but not from parsing an FS block
+            anyhow!("the dump's bad FS checksum count was 3"),
+            anyhow!("No such file on the console"),
+        ] {
+            if let Some(found) = id(&error) {
+                bail!("'{error:#}' wrongly matched {found}");
+            }
+        }
+
+        // every string has to be there, and the layer too when one is given
+        let both = signature("both", &["Timed Out", "BLOCK"], None, "");
+        let plain = |m: &str| levels(&*anyhow!("{m}"));
+        if !matches(&both, &plain("block 1: timed out")) || matches(&both, &plain("timed out")) {
+            bail!("a signature's strings weren't all required");
+        }
+        let usb = signature("usb", &["resource busy"], Some(Layer::Usb), "");
+        if matches(&usb, &plain("Resource busy"))
+            || !matches(&usb, &levels(&rusb::Error::Busy))
+            || matches(&signature("none", &[], None, ""), &plain("anything"))
+        {
+            bail!("a signature's layer wasn't checked");
+        }
+
+        // signatures in the config directory come first, and replace shipped ones with their ids
+        let extra = vec![
+            signature("bench-3-hub", &["timed out"], None, "Plug it into the PC"),
+            signature("stalled", &["pipe error"], None, "Replug it"),
+        ];
+        let merged = merge(extra.clone());
+        if merged.len() != shipped.len() + 1
+            || merged[..2] != extra[..]
+            || merged.iter().filter(|s| s.id == "stalled").count() != 1
+        {
+            bail!("the extra signatures were merged as {merged:?}");
+        }
+        let timeout = levels(&*anyhow!("Failed to read block 0x1A2: Operation timed out"));
+        if find(&merged, &timeout).map(|s| s.id.as_str()) != Some("bench-3-hub") {
+            bail!("a signature from the config directory didn't take precedence");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use crate::player::Player;
+
+pub const LED_ON: u32 = 2;
+pub const LED_FLASHING: u32 = 3;
+
+// the console can't report its LED state, so this tracks what it was last set to
+#[derive(Default)]
+pub struct LedState {
+    // the value last set with 'H'
+    pub last: u32,
+    // set after a failed operation, which leaves the LED solid until the next command
+    pub restore_on_next_command: bool,
+    // set by 'H --during', which restores the LED as soon as the wrapped command finishes
+    pub restore_after_command: bool,
+}
+
+impl LedState {
+    pub fn restore(&mut self, player: Option<&dyn Player>) {
+        if let Some(player) = player {
+            if let Err(e) = player.SetLED(self.last) {
+                eprintln!("Couldn't restore the LED: {e}");
+            }
+        }
+        self.restore_on_next_command = false;
+        self.restore_after_command = false;
+    }
+
+    pub fn flash(&mut self, player: &dyn Player) {
+        if let Err(e) = player.SetLED(LED_FLASHING) {
+            eprintln!("Couldn't set the LED: {e}");
+        }
+    }
+}
+
+// flashes the LED for as long as it's alive; 'succeed' puts the LED back how it was, and
+// dropping it any other way (an error, or an early return) leaves the LED solid instead
+pub struct LedGuard<'a> {
+    player: &'a dyn Player,
+    state: &'a mut LedState,
+    enabled: bool,
+}
+
+impl<'a> LedGuard<'a> {
+    pub fn start(player: &'a dyn Player, state: &'a mut LedState, enabled: bool) -> Self {
+        if enabled {
+            state.flash(player);
+        }
+        Self {
+            player,
+            state,
+            enabled,
+        }
+    }
+
+    pub fn succeed(mut self) {
+        if self.enabled {
+            self.state.restore(Some(self.player));
+            self.enabled = false;
+        }
+    }
+}
+
+impl Drop for LedGuard<'_> {
+    fn drop(&mut self) {
+        if self.enabled {
+            let _ = self.player.SetLED(LED_ON);
+            self.state.restore_on_next_command = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use bbrdb::CardStats;
+    use std::cell::RefCell;
+
+    // remembers what the LED was set to
+    #[derive(Default)]
+    struct Console {
+        led: RefCell<Vec<u32>>,
+    }
+
+    impl Player for Console {
+        fn GetBBID(&self) -> Result<u32> {
+            Ok(0x1234)
+        }
+
+        fn SetLED(&self, value: u32) -> Result<()> {
+            self.led.borrow_mut().push(value);
+            Ok(())
+        }
+
+        fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+            Ok(vec![])
+        }
+
+        fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+            Ok(vec![])
+        }
+
+        fn ReadFile(&self, _name: &str) -> Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn ReadSingleBlock(&self, _blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+            Ok((vec![], vec![]))
+        }
+
+        fn CardStats(&self) -> Result<CardStats> {
+            Ok(CardStats {
+                free: 0,
+                used: 0,
+                bad: 0,
+                seqno: 0,
+            })
+        }
+    }
+
+    #[test]
+    fn feedback() {
+        let console = Console::default();
+        let mut state = LedState {
+            last: 1,
+            ..LedState::default()
+        };
+
+        // flashes while it runs, then goes back to how 'H' left it
+        LedGuard::start(&console, &mut state, true).succeed();
+        assert_eq!(*console.led.borrow(), [LED_FLASHING, 1]);
+        assert!(!state.restore_on_next_command);
+
+        // a failure leaves it solid until the next command
+        drop(LedGuard::start(&console, &mut state, true));
+        assert_eq!(console.led.borrow()[2..], [LED_FLASHING, LED_ON]);
+        assert!(state.restore_on_next_command);
+        state.restore(Some(&console));
+        assert_eq!(console.led.borrow()[4..], [1]);
+        assert!(!state.restore_on_next_command);
+
+        // and with the feedback turned off, it's left alone either way
+        LedGuard::start(&console, &mut state, false).succeed();
+        drop(LedGuard::start(&console, &mut state, false));
+        assert_eq!(console.led.borrow().len(), 5);
+        assert!(!state.restore_on_next_command);
+
+        // restoring with no console still clears what was pending
+        state.restore_after_command = true;
+        state.restore(None);
+        assert!(!state.restore_after_command);
+        assert_eq!(console.led.borrow().len(), 5);
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+
+use crate::journal::{Entry, Journal};
+use crate::player::PlayerWrite;
+
+// A file that's replaced in several steps (ticket.sys by 'ticket restore', a file by 'patch') is
+// first written to a temporary file, checked, and only then swapped in, so an interruption never
+// leaves it half-written. An interruption can leave the temporary file behind, though, so they
+// all have names of one form, and each step is written to the journal: a leftover can be told
+// apart from anything else on the card, and one that was complete when its target was deleted
+// can be put in its place rather than thrown away.
+
+// "a2" and six hex digits, with this extension: an 8.3 name no console software uses
+const TEMP_PREFIX: &str = "a2";
+const TEMP_EXT: &str = ".tmp";
+
+// the journal steps, each followed by the temporary file's name
+const CREATED: &str = "temp created: ";
+const VERIFIED: &str = "temp verified: ";
+const REMOVED: &str = "temp removed: ";
+const GONE: &str = "temp gone: ";
+// after CREATED's name, the file it's standing in for
+const FOR: &str = " for ";
+
+pub fn temp_name(seed: u32) -> String {
+    format!("{TEMP_PREFIX}{:06x}{TEMP_EXT}", seed & 0xFF_FFFF)
+}
+
+pub fn is_temp_name(name: &str) -> bool {
+    name.strip_prefix(TEMP_PREFIX)
+        .and_then(|n| n.strip_suffix(TEMP_EXT))
+        .is_some_and(|hex| {
+            hex.len() == 6 && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cleanup {
+    Delete,
+    // the file it stood in for was deleted after it was verified, so it's the only copy
+    Finish(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leftover {
+    pub name: String,
+    pub size: u32,
+    // the operation the journal says made it, if it says
+    pub operation: Option<String>,
+    pub cleanup: Cleanup,
+}
+
+impl fmt::Display for Leftover {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let from = match &self.operation {
+            Some(op) => format!("left by an interrupted '{op}'"),
+            None => "not in the journal".to_string(),
+        };
+        match &self.cleanup {
+            Cleanup::Delete => write!(f, "{} ({} bytes, {from}): delete", self.name, self.size),
+            Cleanup::Finish(target) => write!(
+                f,
+                "{} ({} bytes, {from}): rename to {target}, which it was replacing",
+                self.name, self.size
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Scan {
+    pub leftovers: Vec<Leftover>,
+    // temporary files the journal says weren't removed, but that aren't on the card
+    pub gone: Vec<String>,
+}
+
+// a temporary file the journal hasn't seen the end of
+struct Open {
+    operation: String,
+    target: Option<String>,
+    verified: bool,
+}
+
+// the temporary files in `files` (the card's listing), matched against what the journal says
+// about this console
+pub fn scan(files: &[(String, u32)], journal: &[Entry], bbid: u32) -> Scan {
+    let mut open: HashMap<&str, Open> = HashMap::new();
+    for entry in journal.iter().filter(|e| e.bbid == bbid) {
+        let step = entry.step.as_str();
+        if let Some(rest) = step.strip_prefix(CREATED) {
+            let (name, target) = match rest.split_once(FOR) {
+                Some((n, t)) => (n, Some(t.to_string())),
+                None => (rest, None),
+            };
+            open.insert(
+                name,
+                Open {
+                    operation: entry.operation.clone(),
+                    target,
+                    verified: false,
+                },
+            );
+        } else if let Some(name) = step.strip_prefix(VERIFIED) {
+            if let Some(o) = open.get_mut(name) {
+                o.verified = true;
+            }
+        } else if let Some(name) = step
+            .strip_prefix(REMOVED)
+            .or_else(|| step.strip_prefix(GONE))
+        {
+            open.remove(name);
+        }
+    }
+
+    let present = |name: &str| files.iter().any(|(n, _)| n == name);
+    let leftovers = files
+        .iter()
+        .filter(|(name, _)| is_temp_name(name))
+        .map(|(name, size)| {
+            let known = open.get(name.as_str());
+            let cleanup = match known {
+                Some(Open {
+                    target: Some(t),
+                    verified: true,
+                    ..
+                }) if !present(t) => Cleanup::Finish(t.clone()),
+                _ => Cleanup::Delete,
+            };
+            Leftover {
+                name: name.clone(),
+                size: *size,
+                operation: known.map(|o| o.operation.clone()),
+                cleanup,
+            }
+        })
+        .collect();
+    let mut gone = open
+        .keys()
+        .filter(|name| !present(name))
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    gone.sort();
+    Scan { leftovers, gone }
+}
+
+// files that may be junk, for the user to decide about: empty ones (leaving out the temporary
+// files, which are dealt with separately)
+pub fn empty_files(files: &[(String, u32)]) -> Vec<String> {
+    files
+        .iter()
+        .filter(|(name, size)| *size == 0 && !is_temp_name(name))
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+// deletes the leftovers (or puts them in place), and closes the journal's entries for them and for
+// those already gone
+pub fn clean(player: &mut dyn PlayerWrite, scan: &Scan) -> Result<()> {
+    let mut journal = Journal::open(player.GetBBID()?, "clean")?;
+    for leftover in &scan.leftovers {
+        match &leftover.cleanup {
+            Cleanup::Delete => player.DeleteFile(&leftover.name)?,
+            Cleanup::Finish(target) => player.RenameFile(&leftover.name, target)?,
+        }
+        journal.record(&format!("{REMOVED}{}", leftover.name))?;
+        println!("Cleaned up {}", leftover.name);
+    }
+    for name in &scan.gone {
+        journal.record(&format!("{GONE}{name}"))?;
+    }
+    Ok(())
+}
+
+// replaces `name` with `data` by way of a temporary file, so that it's never left half-written
+pub fn replace_file(
+    player: &mut dyn PlayerWrite,
+    name: &str,
+    data: &[u8],
+    operation: &str,
+) -> Result<()> {
+    let files = player.ListFiles()?;
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos())
+        ^ std::process::id();
+    let temp = (0..)
+        .map(|i| temp_name(seed.wrapping_add(i)))
+        .find(|t| files.iter().all(|(n, _)| n != t))
+        .unwrap_or_default();
+
+    let mut journal = Journal::open(player.GetBBID()?, operation)?;
+    journal.record(&format!("{CREATED}{temp}{FOR}{name}"))?;
+    player.WriteFile(data, &temp)?;
+    if player.ReadFile(&temp)?.as_deref() != Some(data) {
+        bail!("{temp} didn't read back as it was written, so {name} was left as it was; 'clean' will remove it");
+    }
+    journal.record(&format!("{VERIFIED}{temp}"))?;
+    if files.iter().any(|(n, _)| n == name) {
+        player.DeleteFile(name)?;
+    }
+    player.RenameFile(&temp, name)?;
+    journal.record(&format!("{REMOVED}{temp}"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::parse_line;
+
+    #[test]
+    fn leftover_temps() -> Result<()> {
+        let entry = |bbid, step: &str| Entry {
+            bbid,
+            operation: "patch".to_string(),
+            step: step.to_string(),
+        };
+        let file = |name: &str, size| (name.to_string(), size);
+
+        let line = "2024-06-01T12:00:00+08:00 0000ABCD ticket restore: temp created: a2000001.tmp for ticket.sys";
+        let expected = Entry {
+            bbid: 0xABCD,
+            operation: "ticket restore".to_string(),
+            step: "temp created: a2000001.tmp for ticket.sys".to_string(),
+        };
+        if parse_line(line) != Some(expected)
+            || parse_line("2024-06-01T12:00:00+08:00 0000AB").is_some()
+        {
+            bail!("the journal line was read as {:?}", parse_line(line));
+        }
+
+        if !is_temp_name(&temp_name(0x1234_5678)) || temp_name(0x1234_5678) != "a2345678.tmp" {
+            bail!("the temporary file name was {}", temp_name(0x1234_5678));
+        }
+        for name in ["a2abcdef.tmp", "a2000000.tmp"] {
+            if !is_temp_name(name) {
+                bail!("{name} wasn't taken as a temporary file");
+            }
+        }
+        for name in [
+            "a2abcdeg.tmp",
+            "a2ABCDEF.tmp",
+            "a21234567.tmp",
+            "00a2b3c4.tmp",
+            "a2123456.app",
+            "temp.tmp",
+        ] {
+            if is_temp_name(name) {
+                bail!("{name} was taken as a temporary file");
+            }
+        }
+
+        let journal = [
+            // finished: not a leftover
+            entry(1, "temp created: a2000001.tmp for ticket.sys"),
+            entry(1, "temp verified: a2000001.tmp"),
+            entry(1, "temp removed: a2000001.tmp"),
+            // cut short while being written
+            entry(1, "temp created: a2000002.tmp for game.app"),
+            // verified, and its target deleted, but not renamed
+            entry(1, "temp created: a2000003.tmp for ticket.sys"),
+            entry(1, "temp verified: a2000003.tmp"),
+            // verified, but its target is still there
+            entry(1, "temp created: a2000004.tmp for save.sta"),
+            entry(1, "temp verified: a2000004.tmp"),
+            // the journal says it's left over, but it's gone from the card
+            entry(1, "temp created: a2000005.tmp for other.app"),
+            // another console's
+            entry(2, "temp created: a2000006.tmp for ticket.sys"),
+            entry(2, "temp verified: a2000006.tmp"),
+        ];
+        let files = [
+            file("game.app", 0x4000),
+            file("save.sta", 0x200),
+            file("a2000002.tmp", 0x1000),
+            file("a2000003.tmp", 0x2000),
+            file("a2000004.tmp", 0x200),
+            // left by a session whose journal is gone
+            file("a2000006.tmp", 0x40),
+            file("a2fffff0.tmp", 0x40),
+            file("empty.sys", 0),
+        ];
+        let result = scan(&files, &journal, 1);
+        let leftover = |name: &str, size, op: Option<&str>, cleanup| Leftover {
+            name: name.to_string(),
+            size,
+            operation: op.map(str::to_string),
+            cleanup,
+        };
+        let expected = [
+            leftover("a2000002.tmp", 0x1000, Some("patch"), Cleanup::Delete),
+            leftover(
+                "a2000003.tmp",
+                0x2000,
+                Some("patch"),
+                Cleanup::Finish("ticket.sys".to_string()),
+            ),
+            leftover("a2000004.tmp", 0x200, Some("patch"), Cleanup::Delete),
+            leftover("a2000006.tmp", 0x40, None, Cleanup::Delete),
+            leftover("a2fffff0.tmp", 0x40, None, Cleanup::Delete),
+        ];
+        if result.leftovers != expected {
+            bail!("the leftovers found were {:#?}", result.leftovers);
+        }
+        if result.gone != ["a2000005.tmp"] {
+            bail!("the journalled files already gone were {:?}", result.gone);
+        }
+        if empty_files(&files) != ["empty.sys"]
+            || !empty_files(&[file("a2000007.tmp", 0)]).is_empty()
+        {
+            bail!("the wrong files were found empty");
+        }
+
+        // once cleaned, nothing is reported again, even though the journal entries are still there
+        let mut closed = journal.to_vec();
+        closed.extend(
+            ["a2000002.tmp", "a2000003.tmp", "a2000004.tmp"]
+                .map(|n| entry(1, &format!("temp removed: {n}"))),
+        );
+        closed.push(entry(1, "temp gone: a2000005.tmp"));
+        let cleaned = files
+            .iter()
+            .filter(|(n, _)| !is_temp_name(n))
+            .cloned()
+            .collect::<Vec<_>>();
+        if scan(&cleaned, &closed, 1) != Scan::default() {
+            bail!(
+                "a cleaned card was still reported: {:?}",
+                scan(&cleaned, &closed, 1)
+            );
+        }
+        // with no journal at all, every temporary file is still found
+        if scan(&files, &[], 1)
+            .leftovers
+            .iter()
+            .any(|l| l.operation.is_some() || l.cleanup != Cleanup::Delete)
+            || scan(&files, &[], 1).leftovers.len() != 5
+        {
+            bail!("leftovers without a journal weren't all found for deleting");
+        }
+        Ok(())
+    }
+}
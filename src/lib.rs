@@ -0,0 +1,174 @@
+#![feature(let_chains)]
+
+//! The console-independent parts of aulon2, for driving it from other programs: the command
+//! dispatcher, the [`player::Player`] trait that lets commands run against a dump instead of a
+//! console, and the FS, spare data, ECC and block range code. Without the `cli` feature it
+//! doesn't depend on rustyline or anything else that needs a terminal.
+//!
+//! A session can be driven without a console by mounting a dump and dispatching lines to it:
+//!
+//! ```no_run
+//! use std::collections::VecDeque;
+//!
+//! use aulon2::cli::{dispatch, CliContext};
+//!
+//! let mut context = CliContext::default();
+//! let mut answers = VecDeque::new();
+//! for line in ["mount nand.bin spare.bin", "L", "unmount"] {
+//!     dispatch(&mut context, &mut answers, line);
+//! }
+//! ```
+//!
+//! Or with a console of the program's own, such as one simulated in memory, by implementing
+//! [`player::Backend`] for it and selecting it with [`cli::CliContext::select_player`].
+
+mod acceptance;
+mod attest;
+mod backup;
+mod badblocks;
+#[cfg(feature = "tui")]
+mod browse;
+mod byteswap;
+mod calc;
+mod call_trace;
+/// Stopping long operations between blocks.
+pub mod cancel;
+/// The command line: the session state and the dispatcher that runs a line of input.
+pub mod cli;
+mod clock;
+mod compress;
+mod config;
+#[cfg(feature = "writing")]
+mod danger;
+mod dat;
+mod dedupe;
+mod device;
+mod download;
+mod dupes;
+/// The NAND's per-page ECC.
+pub mod ecc;
+mod error_chain;
+mod file_digest;
+mod file_times;
+mod fingerprint;
+mod finish;
+#[cfg(feature = "writing")]
+mod foreign_bad;
+/// Parsing and building FS blocks, and the card layout constants.
+pub mod fs;
+mod fs_cache;
+#[cfg(feature = "writing")]
+mod fscheck;
+mod fsdiff;
+mod genimage;
+/// What can be restored from a dump of a different size of card.
+#[cfg(feature = "writing")]
+pub mod geometry;
+mod hash_cache;
+mod hashing;
+mod help;
+mod heroic;
+/// Intel HEX and Motorola S-record conversion.
+pub mod hexfile;
+mod history;
+mod hooks;
+mod hotplug;
+mod image;
+/// Keeping a second copy of the program off a console the first has selected.
+pub mod instance_lock;
+mod jobs;
+#[cfg(feature = "writing")]
+mod journal;
+mod keepalive;
+mod kit;
+mod known_errors;
+mod led;
+#[cfg(feature = "writing")]
+mod leftovers;
+mod lint;
+mod listing;
+/// Offline dumps that stand in for a console.
+pub mod mount;
+mod nand_read;
+#[cfg(feature = "writing")]
+mod nand_write;
+mod notes;
+mod notify;
+mod offline;
+mod oplog;
+/// Session options, as changed with 'set'.
+pub mod options;
+mod organize;
+mod page;
+mod patch;
+mod paths;
+/// The operations commands need from a console, so they can run against a dump instead.
+pub mod player;
+mod preflight;
+mod preset;
+mod preview;
+mod profile;
+mod progress;
+/// Where commands get answers to questions from.
+pub mod prompt;
+mod provenance;
+mod provision;
+#[cfg(feature = "writing")]
+mod range_builder;
+/// Parsing and formatting block ranges.
+pub mod ranges;
+#[cfg(feature = "writing")]
+mod relocate;
+mod report;
+#[cfg(feature = "writing")]
+mod require_backup;
+#[cfg(feature = "writing")]
+mod roles;
+#[cfg(feature = "writing")]
+mod sandbox;
+mod scrub;
+/// Built-in checks of the offline logic.
+pub mod selftest;
+mod session;
+mod sign;
+mod similar;
+mod sink;
+mod sizes;
+mod sksa;
+#[cfg(feature = "devtools")]
+mod slowlink;
+/// Spare data: bad block markers and ECC.
+pub mod spare;
+mod spotcheck;
+mod staleness;
+mod startup;
+mod stats_history;
+#[cfg(feature = "writing")]
+mod strict;
+mod summary;
+mod survey;
+#[cfg(test)]
+mod test_dir;
+mod throughput;
+mod ticket;
+mod ticket_backup;
+mod titles;
+mod triage;
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(feature = "writing")]
+mod txn;
+#[cfg(feature = "writing")]
+mod upload;
+mod usb;
+mod verify;
+#[cfg(feature = "writing")]
+mod wear;
+mod wrap;
+#[cfg(feature = "writing")]
+mod write_preview;
+
+/// The name the program goes by in messages and file names.
+pub const PROG_NAME: &str = "aulon2";
+/// The program's version, as shown at startup.
+pub const PROG_VER: &str = "0.0.1";
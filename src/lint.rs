@@ -0,0 +1,303 @@
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::fs::read;
+use std::path::Path;
+
+use crate::fs::BLOCK_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub level: Level,
+    pub message: String,
+}
+
+impl Issue {
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            level: Level::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        Self {
+            level: Level::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.level {
+            Level::Warning => write!(f, "warning: {}", self.message),
+            Level::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+// the filesystem stores 8.3 names
+pub fn check_name(name: &str) -> Vec<Issue> {
+    let mut issues = vec![];
+    let (stem, ext) = name.split_once('.').unwrap_or((name, ""));
+    if stem.is_empty() {
+        issues.push(Issue::error("name is empty"));
+    }
+    if stem.len() > 8 {
+        issues.push(Issue::error(format!(
+            "name '{stem}' is longer than 8 characters"
+        )));
+    }
+    if ext.contains('.') {
+        issues.push(Issue::error("name contains more than one '.'"));
+    } else if ext.len() > 3 {
+        issues.push(Issue::error(format!(
+            "extension '{ext}' is longer than 3 characters"
+        )));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !c.is_ascii_graphic() || matches!(c, '/' | '\\'))
+    {
+        issues.push(Issue::error(format!("name contains {c:?}")));
+    }
+    if name.chars().any(|c| c.is_ascii_uppercase()) {
+        issues.push(Issue::warning(
+            "name contains uppercase letters; the console's own files are all lowercase",
+        ));
+    }
+    issues
+}
+
+pub fn blocks_needed(size: usize) -> usize {
+    size.div_ceil(BLOCK_SIZE)
+}
+
+pub fn check_size(size: usize, free_blocks: Option<u32>) -> Vec<Issue> {
+    let mut issues = vec![];
+    if size == 0 {
+        issues.push(Issue::error("file is empty"));
+    }
+    if let Some(free) = free_blocks {
+        if blocks_needed(size) > free as usize {
+            issues.push(Issue::error(format!(
+                "needs {} blocks, but only {free} are free",
+                blocks_needed(size)
+            )));
+        }
+    }
+    issues
+}
+
+// an N64 ROM's first word, in each byte order ROMs are passed around in
+const ROM_MAGICS: [[u8; 4]; 3] = [
+    [0x80, 0x37, 0x12, 0x40],
+    [0x37, 0x80, 0x40, 0x12],
+    [0x40, 0x12, 0x37, 0x80],
+];
+
+// content is encrypted, so its header can't be read without keys, but a plaintext ROM or a blank
+// file plainly isn't content; and the console finds content by the ID it's named for
+pub fn check_app(name: &str, data: &[u8]) -> Vec<Issue> {
+    let mut issues = vec![];
+    let stem = name.strip_suffix(".app").unwrap_or(name);
+    if stem.len() != 8 || !stem.chars().all(|c| c.is_ascii_hexdigit()) {
+        issues.push(Issue::warning(
+            "content is named for its content ID in hex (e.g. 0012abcd.app); the console won't find it under any other name",
+        ));
+    }
+    if ROM_MAGICS.iter().any(|m| data.starts_with(m)) {
+        issues.push(Issue::error(
+            "this is an unencrypted N64 ROM, not content for the console",
+        ));
+    } else if let Some(&b) = data
+        .first()
+        .filter(|&&b| b == 0x00 || b == 0xFF)
+        .filter(|&&b| data[..data.len().min(BLOCK_SIZE)].iter().all(|&x| x == b))
+    {
+        issues.push(Issue::error(format!(
+            "its first block is all {b:#04X}; encrypted content never is, so it's blank or corrupt"
+        )));
+    }
+    issues
+}
+
+// only the parts of each format that can be checked without any keys
+pub fn check_format(name: &str, data: &[u8]) -> Vec<Issue> {
+    let ext = name.rsplit_once('.').map(|(_, e)| e).unwrap_or("");
+    let expected_save = |sizes: &[usize]| {
+        if sizes.contains(&data.len()) {
+            vec![]
+        } else {
+            vec![Issue::error(format!(
+                "{ext} saves are {} bytes, but this is {:#X}",
+                sizes
+                    .iter()
+                    .map(|s| format!("{s:#X}"))
+                    .collect::<Vec<_>>()
+                    .join(" or "),
+                data.len()
+            ))]
+        }
+    };
+    let mut issues = match ext {
+        // content is AES-CBC encrypted, so it's always a whole number of AES blocks
+        "app" | "rec" if !data.len().is_multiple_of(0x10) => vec![Issue::error(format!(
+            "size {:#X} isn't a multiple of 0x10, so this can't be encrypted content",
+            data.len()
+        ))],
+        "app" | "rec" if !data.len().is_multiple_of(BLOCK_SIZE) => vec![Issue::warning(format!(
+            "size {:#X} isn't a whole number of blocks",
+            data.len()
+        ))],
+        "eep" => expected_save(&[0x200, 0x800]),
+        "fla" => expected_save(&[0x20000]),
+        "pak" => expected_save(&[0x8000]),
+        _ => vec![],
+    };
+    if ext == "app" {
+        issues.extend(check_app(name, data));
+    }
+    issues
+}
+
+pub fn duplicate_names<'a>(names: &[&'a str]) -> Vec<&'a str> {
+    let mut seen = HashSet::new();
+    let mut dups = vec![];
+    for &name in names {
+        if !seen.insert(name) && !dups.contains(&name) {
+            dups.push(name);
+        }
+    }
+    dups
+}
+
+// checks each file and prints what's wrong with it; returns whether there were no errors.
+// `free_blocks` comes from the last 'C', so linting never talks to the console
+pub fn lint_files(paths: &[&str], free_blocks: Option<u32>) -> bool {
+    let names = paths
+        .iter()
+        .map(|p| {
+            Path::new(p)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or(p)
+        })
+        .collect::<Vec<_>>();
+    let dups = duplicate_names(&names);
+
+    let mut errors = 0;
+    let mut warnings = 0;
+    let mut total_blocks = 0;
+    for (path, name) in paths.iter().zip(&names) {
+        let mut issues = check_name(name);
+        if dups.contains(name) {
+            issues.push(Issue::error("another file in this batch has the same name"));
+        }
+        match read(path) {
+            Ok(data) => {
+                total_blocks += blocks_needed(data.len());
+                issues.extend(check_size(data.len(), free_blocks));
+                issues.extend(check_format(name, &data));
+            }
+            Err(e) => issues.push(Issue::error(format!("couldn't read it: {e}"))),
+        }
+
+        if issues.is_empty() {
+            println!("{path}: ok");
+        } else {
+            println!("{path}:");
+            for issue in &issues {
+                println!("    {issue}");
+            }
+        }
+        errors += issues.iter().filter(|i| i.level == Level::Error).count();
+        warnings += issues.iter().filter(|i| i.level == Level::Warning).count();
+    }
+
+    match free_blocks {
+        Some(free) if paths.len() > 1 && total_blocks > free as usize => {
+            println!("error: together these need {total_blocks} blocks, but only {free} are free");
+            errors += 1;
+        }
+        None => println!("Free space wasn't checked; use 'C' first to check it"),
+        _ => {}
+    }
+
+    println!(
+        "{}: {errors} errors, {warnings} warnings",
+        if errors == 0 { "Pass" } else { "Fail" }
+    );
+    errors == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn levels(issues: &[Issue]) -> Vec<Level> {
+        issues.iter().map(|i| i.level).collect()
+    }
+
+    #[test]
+    fn checks() -> Result<()> {
+        use Level::*;
+
+        for (name, expected) in [
+            ("0012abcd.app", vec![]),
+            ("ticket.sys", vec![]),
+            ("toolongname.app", vec![Error]),
+            ("a.b.c", vec![Error]),
+            ("file.text", vec![Error]),
+            ("my file", vec![Error]),
+            ("a/b", vec![Error]),
+            (".app", vec![Error]),
+            ("Game.app", vec![Warning]),
+        ] {
+            assert_eq!(levels(&check_name(name)), expected, "{name}");
+        }
+
+        assert_eq!(levels(&check_size(0, None)), [Error]);
+        assert_eq!(levels(&check_size(1, Some(1))), []);
+        assert_eq!(levels(&check_size(BLOCK_SIZE + 1, Some(1))), [Error]);
+        assert_eq!(blocks_needed(BLOCK_SIZE * 2), 2);
+
+        let content = (0..BLOCK_SIZE * 2)
+            .map(|i| (i * 7) as u8 | 1)
+            .collect::<Vec<_>>();
+        let rom = [&[0x80, 0x37, 0x12, 0x40][..], &content[4..]].concat();
+        let swapped = [&[0x37, 0x80, 0x40, 0x12][..], &content[4..]].concat();
+        for (name, data, expected) in [
+            ("0012abcd.app", content.clone(), vec![]),
+            ("game.app", content.clone(), vec![Warning]),
+            ("0012abcd.app", content[..0x10].to_vec(), vec![Warning]),
+            ("0012abcd.app", content[..0x11].to_vec(), vec![Error]),
+            ("0012abcd.app", rom, vec![Error]),
+            ("0012abcd.app", swapped, vec![Error]),
+            ("0012abcd.app", vec![0xFF; BLOCK_SIZE * 2], vec![Error]),
+            ("0012abcd.app", vec![0; BLOCK_SIZE], vec![Error]),
+            ("0012abcd.eep", vec![0; 0x200], vec![]),
+            ("0012abcd.eep", vec![0; 0x300], vec![Error]),
+            ("0012abcd.fla", vec![0; 0x20000], vec![]),
+            ("0012abcd.pak", vec![0; 0x100], vec![Error]),
+        ] {
+            assert_eq!(
+                levels(&check_format(name, &data)),
+                expected,
+                "{name} ({:#X} bytes)",
+                data.len()
+            );
+        }
+
+        assert_eq!(duplicate_names(&["a", "b", "a", "c", "a", "b"]), ["a", "b"]);
+        assert!(duplicate_names(&["a", "b"]).is_empty());
+        Ok(())
+    }
+}
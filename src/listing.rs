@@ -0,0 +1,103 @@
+use std::io::{stdout, IsTerminal};
+
+use crate::sizes::{format_size, plausible, suspect_note};
+
+// The file listings of '5' and 'L'. The table is for people; the porcelain form is for scripts,
+// and must never change: one line per file, sorted bytewise by name, with the name and the size
+// in bytes separated by a tab, and each line ending in LF. It's used when stdout isn't a
+// terminal, or with '--porcelain'.
+
+pub fn games(files: Vec<(String, u32)>) -> Vec<(String, u32)> {
+    files
+        .into_iter()
+        .filter(|(name, _)| name.ends_with(".rec") || name.ends_with(".app"))
+        .collect()
+}
+
+// in the console's order, with sizes in the most fitting unit, and a note under it if any of
+// them can't be right
+pub fn table(files: &[(String, u32)]) -> String {
+    let mut table = files
+        .iter()
+        .map(|(name, size)| format!("{name:>12}: {:>7}\n", format_size(*size as u64, 0)))
+        .collect::<String>();
+    if files.iter().any(|(_, size)| !plausible(*size as u64)) {
+        table += &format!("{}\n", suspect_note());
+    }
+    table
+}
+
+pub fn porcelain(files: &[(String, u32)]) -> String {
+    let mut files = files.iter().collect::<Vec<_>>();
+    // String's ordering is bytewise, whatever the locale
+    files.sort_by(|(a, _), (b, _)| a.cmp(b));
+    files
+        .iter()
+        .map(|(name, size)| format!("{name}\t{size}\n"))
+        .collect()
+}
+
+pub fn render(files: &[(String, u32)], args: &[&str]) -> String {
+    if args.contains(&"--porcelain") || !stdout().is_terminal() {
+        porcelain(files)
+    } else {
+        table(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Result};
+
+    #[test]
+    fn file_listings() -> Result<()> {
+        let files = [
+            ("ticket.sys", 0x4000),
+            ("00bbc0de.app", 0x123456),
+            ("B.rec", 0),
+            ("a.rec", 1),
+            ("0000ffff.sta", 0x4000),
+        ]
+        .map(|(n, s)| (n.to_string(), s))
+        .to_vec();
+
+        let expected =
+            "0000ffff.sta\t16384\n00bbc0de.app\t1193046\nB.rec\t0\na.rec\t1\nticket.sys\t16384\n";
+        if porcelain(&files) != expected {
+            bail!("'5 --porcelain' output changed:\n{}", porcelain(&files));
+        }
+        let expected = "00bbc0de.app\t1193046\nB.rec\t0\na.rec\t1\n";
+        if porcelain(&games(files.clone())) != expected {
+            bail!(
+                "'L --porcelain' output changed:\n{}",
+                porcelain(&games(files))
+            );
+        }
+        if !porcelain(&[]).is_empty() {
+            bail!("an empty card's porcelain listing isn't empty");
+        }
+
+        // a corrupted entry's size is flagged in the table, but given as it is to scripts
+        let corrupt = [("a.rec", 1), ("BAD.app", u32::MAX), ("c.rec", u32::MAX)]
+            .map(|(n, s)| (n.to_string(), s))
+            .to_vec();
+        let table = table(&corrupt);
+        let lines = table.lines().collect::<Vec<_>>();
+        if lines.len() != 4
+            || lines[1] != "     BAD.app: >256 MiB (!)"
+            || lines[0].contains("(!)")
+            || lines[3] != suspect_note()
+            || self::table(&files).contains("(!)")
+        {
+            bail!("the listing with corrupt sizes was:\n{table}");
+        }
+        if porcelain(&corrupt) != "BAD.app\t4294967295\na.rec\t1\nc.rec\t4294967295\n" {
+            bail!(
+                "the porcelain listing with corrupt sizes was:\n{}",
+                porcelain(&corrupt)
+            );
+        }
+        Ok(())
+    }
+}
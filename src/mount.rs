@@ -0,0 +1,663 @@
+#![allow(non_snake_case)]
+
+#[cfg(feature = "writing")]
+use std::fs::copy;
+use std::ops::Deref;
+#[cfg(feature = "writing")]
+use std::ops::{DerefMut, Range};
+#[cfg(feature = "writing")]
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use bbrdb::CardStats;
+use chrono::{DateTime, FixedOffset};
+
+use crate::call_trace::{self, Tracer};
+use crate::fs::{FsBlock, FAT_BAD, FAT_FREE};
+#[cfg(feature = "writing")]
+use crate::fs::{FsEntry, BLOCK_SIZE};
+use crate::image::NandImage;
+#[cfg(feature = "writing")]
+use crate::lint::{check_name, Level};
+#[cfg(feature = "writing")]
+use crate::player::PlayerWrite;
+use crate::player::{Console, Player};
+use crate::provenance::dumped_at;
+#[cfg(feature = "writing")]
+use crate::sandbox::{Overlay, OverlayMut, Sandbox};
+#[cfg(feature = "writing")]
+use crate::sink::write_atomic;
+#[cfg(feature = "devtools")]
+use crate::slowlink::{simulation, SlowLink};
+use crate::ticket::{parse_tickets, TICKET_FILE};
+#[cfg(feature = "writing")]
+use crate::txn::{apply, Applied, Op};
+
+/// A build without writing has nothing for a sandbox to hold back, so never has one.
+#[cfg(not(feature = "writing"))]
+pub type Sandbox = std::convert::Infallible;
+
+/// An offline dump standing in for a console through its newest valid FS block; changes made
+/// with 'mount --rw' stay in memory until 'commit'.
+pub struct MountedImage {
+    /// The NAND file it was mounted from.
+    pub name: String,
+    image: NandImage,
+    fs_blk: usize,
+    fs: FsBlock,
+    spare_name: String,
+    // when the dump was made, if its manifest says; forgotten once it's changed
+    dumped: Option<DateTime<FixedOffset>>,
+    #[cfg(feature = "writing")]
+    writable: bool,
+    /// Whether it's been changed since it was mounted or last committed.
+    #[cfg(feature = "writing")]
+    pub dirty: bool,
+    // where the first 'commit' kept the files as they were mounted; later ones don't back up the
+    // files again, so these stay the originals
+    #[cfg(feature = "writing")]
+    backups: Vec<String>,
+}
+
+impl MountedImage {
+    /// Mounts a dump read-only, from its NAND and spare files.
+    pub fn load(nand_filename: &str, spare_filename: &str) -> Result<Self> {
+        Self::from_image(
+            NandImage::load(nand_filename, spare_filename)?,
+            nand_filename,
+            spare_filename,
+        )
+    }
+
+    /// An image already in memory, under the names of the files it would be committed to.
+    pub fn from_image(image: NandImage, nand_filename: &str, spare_filename: &str) -> Result<Self> {
+        let (fs_blk, fs) = image
+            .current_fs()
+            .ok_or_else(|| anyhow!("{nand_filename} has no valid FS block"))?;
+        Ok(Self {
+            name: nand_filename.to_string(),
+            image,
+            fs_blk,
+            fs,
+            spare_name: spare_filename.to_string(),
+            dumped: dumped_at(nand_filename),
+            #[cfg(feature = "writing")]
+            writable: false,
+            #[cfg(feature = "writing")]
+            dirty: false,
+            #[cfg(feature = "writing")]
+            backups: vec![],
+        })
+    }
+
+    /// The files it was mounted from, and whether it was mounted with --rw.
+    pub fn source_files(&self) -> (&str, &str, bool) {
+        #[cfg(feature = "writing")]
+        let rw = self.writable;
+        #[cfg(not(feature = "writing"))]
+        let rw = false;
+        (&self.name, &self.spare_name, rw)
+    }
+
+    /// When the files read from it date from: when it was dumped, until it's changed.
+    pub fn dumped(&self) -> Option<DateTime<FixedOffset>> {
+        self.dumped
+    }
+}
+
+#[cfg(feature = "writing")]
+impl MountedImage {
+    /// Mounts a dump for changing, as 'mount --rw' does; a multi-block FAT is refused.
+    pub fn load_rw(nand_filename: &str, spare_filename: &str) -> Result<Self> {
+        let mut mounted = Self::load(nand_filename, spare_filename)?;
+        if mounted.fs.linked {
+            bail!("{nand_filename} has a multi-block FAT, which can't be changed");
+        }
+        mounted.writable = true;
+        Ok(mounted)
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if !self.writable {
+            bail!("The dump is mounted read-only; use 'mount --rw' to change it");
+        }
+        Ok(())
+    }
+
+    fn check_name(name: &str) -> Result<()> {
+        match check_name(name)
+            .into_iter()
+            .find(|i| i.level == Level::Error)
+        {
+            Some(issue) => bail!("Can't use '{name}': {}", issue.message),
+            None => Ok(()),
+        }
+    }
+
+    // like the console, writes the changed FS to the next good block of the FS region
+    // with the sequence number bumped, leaving the previous generation intact
+    fn replace_fs(&mut self, mut fs: FsBlock) -> Result<()> {
+        fs.seqno += 1;
+        self.install_fs(fs)
+    }
+
+    // writes `fs`, whose sequence number has already been bumped, as the next generation
+    fn install_fs(&mut self, fs: FsBlock) -> Result<()> {
+        let data = fs.to_bytes()?;
+        let region = self.image.fs_region();
+        let bad = self.image.bad_blocks();
+        let next = (1..=region.len())
+            .map(|i| region.start + (self.fs_blk - region.start + i) % region.len())
+            .find(|b| !bad.contains(b))
+            .ok_or_else(|| anyhow!("every block of the FS region is bad"))?;
+        self.image.block_mut(next).copy_from_slice(&data);
+        self.fs = fs;
+        self.fs_blk = next;
+        self.changed();
+        Ok(())
+    }
+
+    /// Carries out a transaction's operations as a single change: the new files' blocks are
+    /// written, and then one new FS generation.
+    pub fn commit_txn(&mut self, ops: &[Op]) -> Result<Applied> {
+        self.check_writable()?;
+        let fs_start = self.image.fs_region().start;
+        let bad = self.image.bad_blocks();
+        let applied = apply(&self.fs, ops, |b| {
+            (b as usize) < fs_start && !bad.contains(&(b as usize))
+        })?;
+        for (blk, data) in &applied.writes {
+            self.image.block_mut(*blk as usize).copy_from_slice(data);
+        }
+        self.install_fs(applied.fs.clone())?;
+        Ok(applied)
+    }
+
+    /// How many blocks the dump has.
+    pub fn num_blocks(&self) -> u16 {
+        self.image.num_blocks() as u16
+    }
+
+    /// The data of whole blocks, for changing in place with 'patch --blocks'.
+    pub fn read_blocks(&self, blocks: Range<u16>) -> Result<Vec<u8>> {
+        self.check_blocks(&blocks)?;
+        Ok(blocks
+            .flat_map(|b| self.image.block(b as usize).to_vec())
+            .collect())
+    }
+
+    /// Overwrites whole blocks; their spare data (and so their ECC) is left as it was.
+    pub fn write_blocks(&mut self, blocks: Range<u16>, data: &[u8]) -> Result<()> {
+        self.check_writable()?;
+        self.check_blocks(&blocks)?;
+        if data.len() != blocks.len() * BLOCK_SIZE {
+            bail!(
+                "blocks {blocks:X?} hold {:#X} bytes, not {:#X}; they can only be overwritten with the same amount",
+                blocks.len() * BLOCK_SIZE,
+                data.len()
+            );
+        }
+        for (b, chunk) in blocks.zip(data.chunks(BLOCK_SIZE)) {
+            self.image.block_mut(b as usize).copy_from_slice(chunk);
+        }
+        self.changed();
+        Ok(())
+    }
+
+    // a changed dump no longer dates from when it was made, even once it's committed
+    fn changed(&mut self) {
+        self.dirty = true;
+        self.dumped = None;
+    }
+
+    // the FS region is only changed through the FS, so the mounted FS stays the dump's
+    fn check_blocks(&self, blocks: &Range<u16>) -> Result<()> {
+        let fs_start = self.image.fs_region().start;
+        if blocks.end as usize > fs_start {
+            bail!(
+                "blocks {blocks:X?} run into the FS region (from {fs_start:#X}) or past the end of the dump"
+            );
+        }
+        Ok(())
+    }
+
+    /// Writes the changed image back over the files it was mounted from; spare data is written
+    /// back as it was. The first commit keeps the originals as '.bak' (or '.bak1' and so on, if
+    /// that's taken), and returns where.
+    pub fn commit(&mut self) -> Result<Vec<String>> {
+        self.check_writable()?;
+        let mut made = vec![];
+        if self.backups.is_empty() {
+            for filename in [&self.name, &self.spare_name] {
+                let backup = unused_backup(filename);
+                copy(filename, &backup)?;
+                made.push(backup);
+            }
+            self.backups = made.clone();
+        }
+        let (nand, spare) = self.image.data();
+        for (filename, data) in [(&self.name, nand), (&self.spare_name, spare)] {
+            write_atomic(filename, data)?;
+        }
+        self.dirty = false;
+        Ok(made)
+    }
+}
+
+// a backup of [filename] that doesn't replace an earlier one
+#[cfg(feature = "writing")]
+fn unused_backup(filename: &str) -> String {
+    (0..)
+        .map(|n| match n {
+            0 => format!("{filename}.bak"),
+            n => format!("{filename}.bak{n}"),
+        })
+        .find(|b| !Path::new(b).exists())
+        .unwrap()
+}
+
+#[cfg(feature = "writing")]
+impl PlayerWrite for MountedImage {
+    fn WriteFile(&mut self, data: &[u8], name: &str) -> Result<()> {
+        self.check_writable()?;
+        Self::check_name(name)?;
+        if data.is_empty() {
+            bail!("Can't write an empty file");
+        }
+
+        let mut fs = self.fs.clone();
+        let old = fs.find(name).cloned();
+        if let Some(old) = &old {
+            fs.free_chain(old.start)?;
+            fs.entries.retain(|e| e.name != name);
+        }
+        let fs_start = self.image.fs_region().start;
+        let bad = self.image.bad_blocks();
+        let chain = fs.allocate(data.len().div_ceil(BLOCK_SIZE), |b| {
+            (b as usize) < fs_start && !bad.contains(&(b as usize))
+        })?;
+        for (&blk, chunk) in chain.iter().zip(data.chunks(BLOCK_SIZE)) {
+            let out = self.image.block_mut(blk as usize);
+            out.fill(0);
+            out[..chunk.len()].copy_from_slice(chunk);
+        }
+        // a file that's replaced keeps its attributes
+        fs.entries.push(FsEntry {
+            attrs: old.map_or_else(Default::default, |o| o.attrs),
+            ..FsEntry::new(name, chain[0], data.len() as u32)
+        });
+        self.replace_fs(fs)
+    }
+
+    fn DeleteFile(&mut self, name: &str) -> Result<()> {
+        self.check_writable()?;
+        let mut fs = self.fs.clone();
+        let Some(entry) = fs.find(name).cloned() else {
+            bail!("File {name} not found");
+        };
+        fs.free_chain(entry.start)?;
+        fs.entries.retain(|e| e.name != name);
+        self.replace_fs(fs)
+    }
+
+    fn RenameFile(&mut self, from: &str, to: &str) -> Result<()> {
+        self.check_writable()?;
+        Self::check_name(to)?;
+        let mut fs = self.fs.clone();
+        if fs.find(to).is_some() {
+            bail!("File {to} already exists");
+        }
+        match fs.entries.iter_mut().find(|e| e.name == from) {
+            Some(entry) => entry.name = to.to_string(),
+            None => bail!("File {from} not found"),
+        }
+        self.replace_fs(fs)
+    }
+}
+
+impl Player for MountedImage {
+    // a dump doesn't store the BBID anywhere itself, but its tickets are issued to it
+    fn GetBBID(&self) -> Result<u32> {
+        let data = self.ReadFile(TICKET_FILE)?.ok_or_else(|| {
+            anyhow!("The mounted dump has no {TICKET_FILE} to take its BBID from")
+        })?;
+        let tickets = parse_tickets(&data)?;
+        match tickets.tickets.first() {
+            Some(t) => Ok(t.bbid),
+            None => bail!("The mounted dump's {TICKET_FILE} has no tickets to take its BBID from"),
+        }
+    }
+
+    fn SetLED(&self, _value: u32) -> Result<()> {
+        Ok(())
+    }
+
+    fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+        Ok(self
+            .fs
+            .entries
+            .iter()
+            .map(|e| (e.name.clone(), e.size))
+            .collect())
+    }
+
+    fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+        Ok(self.image.block(self.fs_blk).to_vec())
+    }
+
+    fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        self.fs
+            .find(name)
+            .map(|entry| self.image.read_file(&self.fs, entry))
+            .transpose()
+    }
+
+    fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        let b = blk as usize;
+        if b >= self.image.num_blocks() {
+            bail!(
+                "Block {blk:#X} is past the end of the mounted dump ({:#X} blocks)",
+                self.image.num_blocks()
+            );
+        }
+        Ok((self.image.block(b).to_vec(), self.image.spare(b).to_vec()))
+    }
+
+    fn CardStats(&self) -> Result<CardStats> {
+        let fat = &self.fs.fat[..self.image.num_blocks().min(self.fs.fat.len())];
+        let free = fat.iter().filter(|&&v| v == FAT_FREE).count() as u32;
+        let bad = fat.iter().filter(|&&v| v == FAT_BAD).count() as u32;
+        Ok(CardStats {
+            free,
+            used: self.image.num_blocks() as u32 - free - bad,
+            bad,
+            seqno: self.fs.seqno,
+        })
+    }
+}
+
+// the dump or console that 'source' picked; in builds with devtools, behind the slow link
+// simulation
+#[cfg(not(feature = "devtools"))]
+type Base<'a> = &'a dyn Player;
+#[cfg(feature = "devtools")]
+type Base<'a> = SlowLink<'static, &'a dyn Player>;
+
+#[cfg(all(feature = "writing", not(feature = "devtools")))]
+type BaseMut<'a> = &'a mut dyn PlayerWrite;
+#[cfg(all(feature = "writing", feature = "devtools"))]
+type BaseMut<'a> = SlowLink<'static, &'a mut dyn PlayerWrite>;
+
+// and while 'sandbox on' is in effect, seen through the sandbox; or, under 'set dry-run trace',
+// neither, with only the calls written down
+enum View<'a> {
+    Direct(Base<'a>),
+    #[cfg(feature = "writing")]
+    Sandboxed(Overlay<'a, Base<'a>>),
+    Traced(Tracer),
+}
+
+/// A [`Player`] to read from, as [`source`] chooses it.
+pub struct Source<'a> {
+    view: View<'a>,
+}
+
+impl<'a> Deref for Source<'a> {
+    type Target = dyn Player + 'a;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.view {
+            View::Direct(player) => player,
+            #[cfg(feature = "writing")]
+            View::Sandboxed(overlay) => overlay,
+            View::Traced(tracer) => tracer,
+        }
+    }
+}
+
+#[cfg(feature = "writing")]
+enum ViewMut<'a> {
+    Direct(BaseMut<'a>),
+    Sandboxed(OverlayMut<'a, Base<'a>>),
+    Traced(Tracer),
+}
+
+/// A [`PlayerWrite`] to change files through, as [`source_mut`] chooses it.
+#[cfg(feature = "writing")]
+pub struct SourceMut<'a> {
+    view: ViewMut<'a>,
+}
+
+#[cfg(feature = "writing")]
+impl<'a> Deref for SourceMut<'a> {
+    type Target = dyn PlayerWrite + 'a;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.view {
+            ViewMut::Direct(player) => player,
+            ViewMut::Sandboxed(overlay) => overlay,
+            ViewMut::Traced(tracer) => tracer,
+        }
+    }
+}
+
+#[cfg(feature = "writing")]
+impl DerefMut for SourceMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match &mut self.view {
+            ViewMut::Direct(player) => player,
+            ViewMut::Sandboxed(overlay) => overlay,
+            ViewMut::Traced(tracer) => tracer,
+        }
+    }
+}
+
+/// The mounted dump if there is one, otherwise the selected console (through the sandbox, if
+/// it's on); while a dry run is being traced, the tracer, whether there's a console or not.
+#[cfg_attr(not(feature = "writing"), allow(unused_variables))]
+pub fn source<'a>(
+    mounted: &'a Option<MountedImage>,
+    sandbox: &'a Option<Sandbox>,
+    player: &'a Option<Console>,
+) -> Option<Source<'a>> {
+    if call_trace::active() {
+        return Some(Source {
+            view: View::Traced(Tracer),
+        });
+    }
+    let base: &'a dyn Player = match (mounted, player) {
+        (Some(m), _) => m,
+        (None, Some(p)) => p.reads(),
+        (None, None) => return None,
+    };
+    #[cfg(feature = "devtools")]
+    let base = SlowLink::new(base, simulation());
+    #[cfg(feature = "writing")]
+    if let (None, Some(sandbox)) = (mounted, sandbox) {
+        return Some(Source {
+            view: View::Sandboxed(Overlay::new(base, sandbox)),
+        });
+    }
+    Some(Source {
+        view: View::Direct(base),
+    })
+}
+
+/// The same, for changing files; in the sandbox, only the sandbox's copies of them change.
+#[cfg(feature = "writing")]
+pub fn source_mut<'a>(
+    mounted: &'a mut Option<MountedImage>,
+    sandbox: &'a mut Option<Sandbox>,
+    player: &'a mut Option<Console>,
+) -> Option<SourceMut<'a>> {
+    if call_trace::active() {
+        return Some(SourceMut {
+            view: ViewMut::Traced(Tracer),
+        });
+    }
+    if let (None, Some(sandbox)) = (&*mounted, sandbox) {
+        let base: &'a dyn Player = player.as_ref()?.reads();
+        #[cfg(feature = "devtools")]
+        let base = SlowLink::new(base, simulation());
+        return Some(SourceMut {
+            view: ViewMut::Sandboxed(OverlayMut::new(base, sandbox)),
+        });
+    }
+    // a sealed console has nothing that writes to offer
+    let player: &'a mut dyn PlayerWrite = match (mounted, player) {
+        (Some(m), _) => m,
+        (None, Some(Console::Open(p))) => p,
+        (None, Some(Console::Sealed(_)) | None) => return None,
+    };
+    #[cfg(feature = "devtools")]
+    let player = SlowLink::new(player, simulation());
+    Some(SourceMut {
+        view: ViewMut::Direct(player),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::BLOCK_SIZE;
+    use crate::genimage::{generate, FileSpec, Pattern, Spec};
+    #[cfg(feature = "writing")]
+    use crate::test_dir::TestDir;
+    use crate::ticket::{TICKET_BBID, TICKET_SIZE};
+
+    // a read-only mount answers as a console would, from the dump
+    #[test]
+    fn reads() -> Result<()> {
+        let file = |name: &str, path: Option<&str>, size| FileSpec {
+            name: name.into(),
+            path: path.map(str::to_string),
+            pattern: path.is_none().then_some(Pattern::Counting),
+            size: path.is_none().then_some(size),
+        };
+        let spec = |files| Spec {
+            blocks: 0x100,
+            bad_blocks: vec![0x90],
+            files,
+            ..Spec::default()
+        };
+        let mut tickets = 1u32.to_be_bytes().to_vec();
+        tickets.resize(4 + TICKET_SIZE, 0);
+        tickets[4 + TICKET_BBID..8 + TICKET_BBID].copy_from_slice(&0x1234ABCDu32.to_be_bytes());
+        let load = |path: &str| match path {
+            "tickets" => Ok(tickets.clone()),
+            _ => bail!("{path}: no local files"),
+        };
+
+        let generated = generate(
+            &spec(vec![
+                file("GAME.app", None, 0x9000),
+                file(TICKET_FILE, Some("tickets"), 0),
+            ]),
+            &load,
+        )?;
+        let mounted = MountedImage::from_image(
+            NandImage::new(generated.nand.clone(), generated.spare.clone())?,
+            "nand.bin",
+            "spare.bin",
+        )?;
+        assert_eq!(mounted.source_files(), ("nand.bin", "spare.bin", false));
+        assert_eq!(mounted.dumped(), None);
+        assert_eq!(mounted.GetBBID()?, 0x1234ABCD);
+        mounted.SetLED(3)?;
+        assert_eq!(
+            mounted.ListFiles()?,
+            [
+                ("GAME.app".to_string(), 0x9000),
+                (TICKET_FILE.to_string(), tickets.len() as u32)
+            ]
+        );
+        let counting = (0..0x9000).map(|i| i as u8).collect::<Vec<_>>();
+        assert_eq!(mounted.ReadFile("GAME.app")?, Some(counting));
+        assert_eq!(mounted.ReadFile("MISSING.app")?, None);
+        let fs = FsBlock::parse(&mounted.DumpCurrentFS()?)?;
+        assert_eq!(fs.entries.len(), 2);
+
+        assert_eq!(
+            mounted.ReadSingleBlock(0x20)?,
+            (
+                generated.nand[0x20 * BLOCK_SIZE..0x21 * BLOCK_SIZE].to_vec(),
+                generated.spare[0x20 * 0x10..0x21 * 0x10].to_vec()
+            )
+        );
+        if mounted.ReadSingleBlock(0x100).is_ok() {
+            bail!("a block past the end of the dump was read");
+        }
+
+        // 3 blocks of the game and 1 of tickets, a bad one, and what the system area and FS use
+        let stats = mounted.CardStats()?;
+        assert_eq!((stats.bad, stats.seqno), (1, fs.seqno));
+        assert_eq!(stats.free + stats.used + stats.bad, 0x100);
+        assert_eq!(
+            stats.free as usize,
+            fs.fat[..0x100].iter().filter(|&&v| v == FAT_FREE).count()
+        );
+
+        // without tickets, there's no BBID to give
+        let generated = generate(&spec(vec![file("GAME.app", None, 0x100)]), &load)?;
+        let mounted = MountedImage::from_image(
+            NandImage::new(generated.nand, generated.spare)?,
+            "nand.bin",
+            "spare.bin",
+        )?;
+        if mounted.GetBBID().is_ok() {
+            bail!("a dump without tickets gave a BBID");
+        }
+        Ok(())
+    }
+
+    // the first commit keeps the files as mounted, later ones leave that backup alone, and a later
+    // mount's backup goes beside it
+    #[cfg(feature = "writing")]
+    #[test]
+    fn commit_backups() -> Result<()> {
+        use crate::provenance::{dump_manifest, manifest_path};
+        use std::fs::read;
+
+        let spec = Spec {
+            blocks: 0x80,
+            ..Spec::default()
+        };
+        let generated = generate(&spec, &|path| bail!("{path}: no local files"))?;
+        let dir = TestDir::new("mount")?;
+        let nand = dir.join("nand.bin").to_string_lossy().into_owned();
+        let spare = dir.join("spare.bin").to_string_lossy().into_owned();
+        write_atomic(&nand, &generated.nand)?;
+        write_atomic(&spare, &generated.spare)?;
+
+        // the manifest beside it dates the dump, until it's changed
+        let manifest = dump_manifest(None, Some("2024-05-02T10:00:00+01:00"), &[], &[]);
+        write_atomic(manifest_path(&nand), manifest.as_bytes())?;
+        let mut mounted = MountedImage::load_rw(&nand, &spare)?;
+        assert_eq!(mounted.dumped().map(|d| d.timestamp()), Some(1_714_640_400));
+        mounted.WriteFile(&[1; 0x100], "one.bin")?;
+        assert!(mounted.dirty);
+        assert_eq!(mounted.dumped(), None);
+        assert_eq!(
+            mounted.commit()?,
+            [format!("{nand}.bak"), format!("{spare}.bak")]
+        );
+        assert!(!mounted.dirty);
+        let first = read(&nand)?;
+
+        mounted.WriteFile(&[2; 0x100], "two.bin")?;
+        assert!(mounted.commit()?.is_empty());
+        assert_eq!(read(format!("{nand}.bak"))?, generated.nand);
+        assert_eq!(read(format!("{spare}.bak"))?, generated.spare);
+
+        let mut again = MountedImage::load_rw(&nand, &spare)?;
+        again.WriteFile(&[3; 0x100], "three.bin")?;
+        assert_eq!(
+            again.commit()?,
+            [format!("{nand}.bak1"), format!("{spare}.bak1")]
+        );
+        assert_eq!(read(format!("{nand}.bak"))?, generated.nand);
+        assert_ne!(read(format!("{nand}.bak1"))?, first);
+        assert_eq!(again.ReadFile("two.bin")?, Some(vec![2; 0x100]));
+        Ok(())
+    }
+}
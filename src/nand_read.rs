@@ -0,0 +1,64 @@
+use anyhow::{anyhow, Result};
+
+use crate::cancel::CancelToken;
+use crate::fs::BLOCK_SIZE;
+use crate::heroic::{heroic_read, ReadOutcome};
+use crate::player::Player;
+use crate::progress::Progress;
+use crate::throughput::TransferTimer;
+
+// the card's size in blocks, as every block is either free, used or bad
+pub fn card_blocks(player: &dyn Player) -> Result<u32> {
+    let stats = player.CardStats()?;
+    Ok(stats.free + stats.used + stats.bad)
+}
+
+// cancellable between blocks; with `heroic`, a block that fails its ECC is read again and voted on
+pub fn dump_nand(
+    player: &dyn Player,
+    events: bool,
+    heroic: bool,
+    cancel: &CancelToken,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let blocks = card_blocks(player)?;
+    let timer = TransferTimer::begin(player, "Dump", blocks as u64 * BLOCK_SIZE as u64);
+    let mut progress = Progress::start("dump", blocks as u64, BLOCK_SIZE, events);
+    progress.set_message("Dumping NAND".to_string());
+
+    let mut nand = Vec::with_capacity(blocks as usize * BLOCK_SIZE);
+    let mut spare = vec![];
+    for blk in 0..blocks {
+        let read = if heroic {
+            heroic_read(player, blk, cancel).map(|(n, s, outcome)| {
+                match outcome {
+                    ReadOutcome::Clean => {}
+                    ReadOutcome::Rescued { reads, bits } => progress.println(format!(
+                        "Block {blk:#X}: rescued {bits} bits by voting across {reads} reads"
+                    )),
+                    ReadOutcome::Unrescued { reads } => progress.println(format!(
+                        "Block {blk:#X}: still fails its ECC after {reads} reads; keeping the first"
+                    )),
+                }
+                (n, s)
+            })
+        } else {
+            cancel.check().and_then(|_| player.ReadSingleBlock(blk))
+        };
+        match read {
+            Ok((n, s)) => {
+                nand.extend_from_slice(&n);
+                spare.extend_from_slice(&s);
+                progress.inc(1);
+            }
+            Err(e) => {
+                let e = anyhow!("Failed to read block {blk:#X}: {e}");
+                progress.fail(&e.to_string());
+                return Err(e);
+            }
+        }
+    }
+
+    progress.finish();
+    timer.complete();
+    Ok((nand, spare))
+}
@@ -0,0 +1,413 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha256};
+
+use crate::badblocks::treat_as_bad;
+use crate::cancel::CancelToken;
+use crate::fs::{BLOCK_SIZE, SKSA_BLOCKS, SPARE_SIZE};
+use crate::player::{Player, PlayerBlockWrite};
+use crate::progress::Progress;
+use crate::ranges::format_range;
+use crate::spare::{clear_bad, ecc_matches, is_bad_block, synthesize_spare};
+use crate::summary::{RangeOutcome, RangeSummary};
+use crate::throughput::TransferTimer;
+
+// '2's positional arguments, [nand spare] [ranges], or with '--no-spare', [nand] [ranges]; the
+// files and the ranges, if given, or None if there are too many
+pub fn write_args<'a>(
+    args: &[&'a str],
+    no_spare: bool,
+) -> Option<(&'a str, &'a str, Option<&'a str>)> {
+    match (no_spare, args) {
+        (false, []) => Some(("nand.bin", "spare.bin", None)),
+        (false, [ranges]) => Some(("nand.bin", "spare.bin", Some(ranges))),
+        (false, [nand, spare]) => Some((nand, spare, None)),
+        (false, [nand, spare, ranges]) => Some((nand, spare, Some(ranges))),
+        (true, []) => Some(("nand.bin", "-", None)),
+        (true, [nand]) => Some((nand, "-", None)),
+        (true, [nand, ranges]) => Some((nand, "-", Some(ranges))),
+        _ => None,
+    }
+}
+
+// writes (and optionally reads back) each range one block at a time, recording per-range
+// results in `summary` so that a partial summary is available even when a block fails or it's
+// cancelled between blocks
+#[allow(clippy::too_many_arguments)]
+pub fn write_ranges<P: PlayerBlockWrite>(
+    player: &P,
+    nand: &[u8],
+    spare: &[u8],
+    ranges: &[Range<u16>],
+    verify: bool,
+    events: bool,
+    summary: &mut RangeSummary,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let end = ranges.iter().map(|r| r.end as usize).max().unwrap_or(0);
+    if nand.len() < end * BLOCK_SIZE || spare.len() < end * SPARE_SIZE {
+        bail!("NAND or spare data is too short for the selected blocks (up to {end:#X})");
+    }
+
+    let total = ranges.iter().map(|r| r.len() as u64).sum::<u64>();
+    // verification reads every block back, doubling the amount transferred
+    let bytes = total * BLOCK_SIZE as u64 * if verify { 2 } else { 1 };
+    let timer = TransferTimer::begin(
+        player,
+        if verify { "Write and verify" } else { "Write" },
+        bytes,
+    );
+    let mut progress = Progress::start("write", total, BLOCK_SIZE, events);
+
+    for (i, range) in ranges.iter().enumerate() {
+        progress.set_message(format!(
+            "Range {}/{} ({})",
+            i + 1,
+            ranges.len(),
+            format_range(range)
+        ));
+
+        let start = Instant::now();
+        let mut outcome = RangeOutcome {
+            range: range.clone(),
+            done: 0,
+            elapsed: Duration::ZERO,
+            mismatches: verify.then(Vec::new),
+            hashes: None,
+        };
+        let mut hashers = verify.then(|| (Sha256::new(), Sha256::new()));
+
+        for blk in range.clone() {
+            let b = blk as usize;
+            let data = &nand[b * BLOCK_SIZE..(b + 1) * BLOCK_SIZE];
+            let result = cancel
+                .check()
+                .and_then(|_| {
+                    player.WriteSingleBlock(
+                        blk as u32,
+                        data,
+                        &spare[b * SPARE_SIZE..(b + 1) * SPARE_SIZE],
+                    )
+                })
+                .and_then(|_| {
+                    if let (Some(mismatches), Some((local, console))) =
+                        (&mut outcome.mismatches, &mut hashers)
+                    {
+                        let (read_back, _) = player.ReadSingleBlock(blk as u32)?;
+                        local.update(data);
+                        console.update(&read_back);
+                        if read_back != data {
+                            mismatches.push(blk);
+                        }
+                    }
+                    Ok(())
+                });
+
+            if let Err(e) = result {
+                outcome.elapsed = start.elapsed();
+                summary.outcomes.push(outcome);
+                let e = anyhow!(
+                    "Failed at block {blk:#X} of range {}: {e}",
+                    format_range(range)
+                );
+                progress.fail(&e.to_string());
+                return Err(e);
+            }
+            outcome.done += 1;
+            progress.inc(1);
+        }
+
+        outcome.elapsed = start.elapsed();
+        outcome.hashes = hashers.map(|(local, console)| {
+            (
+                format!("{:x}", local.finalize()),
+                format!("{:x}", console.finalize()),
+            )
+        });
+        progress.println(format!(
+            "Range {} done: {} blocks in {:.1}s",
+            format_range(range),
+            outcome.done,
+            outcome.elapsed.as_secs_f64()
+        ));
+        summary.outcomes.push(outcome);
+    }
+
+    progress.finish();
+    timer.complete();
+    Ok(())
+}
+
+// builds spare data for writing a nand-only image, reading each block's current spare data from
+// the console; the ECC generator is checked against every block on the console that has data,
+// and if it doesn't reproduce their ECC (a card it hasn't been validated on), nothing is written;
+// blocks marked bad keep their spare data as it is, unless they're in `trusted`
+pub fn synthesize_spares(
+    player: &dyn Player,
+    nand: &[u8],
+    ranges: &[Range<u16>],
+    trusted: &BTreeSet<u16>,
+    events: bool,
+) -> Result<Vec<u8>> {
+    let end = ranges.iter().map(|r| r.end as usize).max().unwrap_or(0);
+    if nand.len() < end * BLOCK_SIZE {
+        bail!("NAND data is too short for the selected blocks (up to {end:#X})");
+    }
+
+    let mut spare = vec![0xFF; nand.len() / BLOCK_SIZE * SPARE_SIZE];
+    let (mut checked, mut mismatched) = (0, 0);
+    let total = ranges.iter().map(|r| r.len() as u64).sum::<u64>();
+    let mut progress = Progress::start("read-spare", total, BLOCK_SIZE, events);
+    progress.set_message("Reading the console's spare data".to_string());
+
+    for blk in ranges.iter().flat_map(|r| r.clone()) {
+        let b = blk as usize;
+        let (current, mut existing) = match player.ReadSingleBlock(blk as u32) {
+            Ok(ns) => ns,
+            Err(e) => {
+                let e = anyhow!("Failed to read block {blk:#X}: {e}");
+                progress.fail(&e.to_string());
+                return Err(e);
+            }
+        };
+        let out = &mut spare[b * SPARE_SIZE..(b + 1) * SPARE_SIZE];
+        if treat_as_bad(blk, &existing, trusted) {
+            out.copy_from_slice(&existing[..SPARE_SIZE]);
+        } else {
+            if is_bad_block(&existing) {
+                // the audit found its marker suspicious and the user trusts it, so it's written as
+                // a good block; its old data says nothing about the ECC generator
+                clear_bad(&mut existing);
+            } else if current.iter().any(|&x| x != 0xFF) {
+                checked += 1;
+                if !ecc_matches(&current, &existing) {
+                    mismatched += 1;
+                }
+            }
+            match synthesize_spare(
+                &nand[b * BLOCK_SIZE..(b + 1) * BLOCK_SIZE],
+                &existing,
+                blk < SKSA_BLOCKS,
+            ) {
+                Ok(fresh) => out.copy_from_slice(&fresh),
+                Err(e) => {
+                    progress.fail(&e.to_string());
+                    return Err(e);
+                }
+            }
+        }
+        progress.inc(1);
+    }
+    progress.finish();
+
+    if checked == 0 {
+        bail!("None of the selected blocks has data on the console to check the ECC generator against, so spare data can't be synthesized safely");
+    }
+    if mismatched > 0 {
+        bail!("The ECC generator doesn't match the ECC on this console's card ({mismatched} of {checked} blocks differ), so spare data can't be synthesized for it");
+    }
+    Ok(spare)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    use bbrdb::CardStats;
+
+    // a console's NAND in memory, recording the blocks written; writes to `failing` fail, and
+    // reads of `flipped` come back with a bit flipped
+    #[derive(Default)]
+    struct Card {
+        nand: RefCell<Vec<u8>>,
+        written: RefCell<Vec<u32>>,
+        failing: Option<u32>,
+        flipped: Option<u32>,
+    }
+
+    impl Player for Card {
+        fn GetBBID(&self) -> Result<u32> {
+            bail!("no BBID")
+        }
+        fn SetLED(&self, _: u32) -> Result<()> {
+            Ok(())
+        }
+        fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+            bail!("no files")
+        }
+        fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+            bail!("no FS")
+        }
+        fn ReadFile(&self, _: &str) -> Result<Option<Vec<u8>>> {
+            bail!("no files")
+        }
+        fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+            let b = blk as usize;
+            let mut data = self.nand.borrow()[b * BLOCK_SIZE..(b + 1) * BLOCK_SIZE].to_vec();
+            if self.flipped == Some(blk) {
+                data[0] ^= 1;
+            }
+            Ok((data, vec![0xFF; SPARE_SIZE]))
+        }
+        fn CardStats(&self) -> Result<CardStats> {
+            bail!("no stats")
+        }
+    }
+
+    impl PlayerBlockWrite for Card {
+        fn WriteSingleBlock(&self, blk: u32, nand: &[u8], _: &[u8]) -> Result<()> {
+            if self.failing == Some(blk) {
+                bail!("Operation timed out");
+            }
+            let b = blk as usize;
+            self.nand.borrow_mut()[b * BLOCK_SIZE..(b + 1) * BLOCK_SIZE].copy_from_slice(nand);
+            self.written.borrow_mut().push(blk);
+            Ok(())
+        }
+        fn Close(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn Init(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // each range block by block, in the order given, with a summary of each even when one fails
+    #[test]
+    fn ranges() -> Result<()> {
+        let blocks = 0x10;
+        let nand = (0..blocks * BLOCK_SIZE)
+            .map(|i| (i / BLOCK_SIZE) as u8)
+            .collect::<Vec<_>>();
+        let spare = vec![0xFF; blocks * SPARE_SIZE];
+        let card = |failing, flipped| Card {
+            nand: RefCell::new(vec![0xFF; blocks * BLOCK_SIZE]),
+            failing,
+            flipped,
+            ..Card::default()
+        };
+        let cancel = CancelToken::default();
+
+        let console = card(None, Some(9));
+        let mut summary = RangeSummary::default();
+        let ranges = [8..0xB, 2..4];
+        write_ranges(
+            &console,
+            &nand,
+            &spare,
+            &ranges,
+            true,
+            false,
+            &mut summary,
+            &cancel,
+        )?;
+        assert_eq!(*console.written.borrow(), [8, 9, 0xA, 2, 3]);
+        assert_eq!(
+            console.nand.borrow()[2 * BLOCK_SIZE..4 * BLOCK_SIZE],
+            nand[2 * BLOCK_SIZE..4 * BLOCK_SIZE]
+        );
+        let outcomes = summary
+            .outcomes
+            .iter()
+            .map(|o| (o.range.clone(), o.done, o.mismatches.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            [(8..0xB, 3, Some(vec![9])), (2..4, 2, Some(vec![]))]
+        );
+        let (local, read_back) = summary.outcomes[1].hashes.clone().unwrap_or_default();
+        assert_eq!(local, read_back);
+
+        // a failure stops the write where it was, saying where
+        let console = card(Some(3), None);
+        let mut summary = RangeSummary::default();
+        let e = write_ranges(
+            &console,
+            &nand,
+            &spare,
+            &[0..2, 2..5],
+            false,
+            false,
+            &mut summary,
+            &cancel,
+        )
+        .err()
+        .map(|e| e.to_string());
+        assert_eq!(
+            e.as_deref(),
+            Some("Failed at block 0x3 of range 0x2-0x5: Operation timed out")
+        );
+        assert_eq!(*console.written.borrow(), [0, 1, 2]);
+        assert_eq!(summary.total_blocks(), 3);
+        assert!(summary.outcomes.iter().all(|o| o.mismatches.is_none()));
+
+        // as does cancelling it, before anything's written; and data too short isn't written at all
+        cancel.cancel();
+        let console = card(None, None);
+        let mut summary = RangeSummary::default();
+        assert!(write_ranges(
+            &console,
+            &nand,
+            &spare,
+            &[0..1, 1..2],
+            false,
+            false,
+            &mut summary,
+            &cancel
+        )
+        .is_err());
+        cancel.reset();
+        assert!(write_ranges(
+            &console,
+            &nand,
+            &spare,
+            &[0..8, 8..0x11],
+            false,
+            false,
+            &mut summary,
+            &cancel
+        )
+        .is_err());
+        assert!(console.written.borrow().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn args() -> Result<()> {
+        for (args, no_spare, expected) in [
+            (&[][..], false, Some(("nand.bin", "spare.bin", None))),
+            (
+                &["0-0x10"],
+                false,
+                Some(("nand.bin", "spare.bin", Some("0-0x10"))),
+            ),
+            (&["a.bin", "b.bin"], false, Some(("a.bin", "b.bin", None))),
+            (
+                &["a.bin", "-", "4075"],
+                false,
+                Some(("a.bin", "-", Some("4075"))),
+            ),
+            (&["a.bin", "b.bin", "4075", "x"], false, None),
+            (&[], true, Some(("nand.bin", "-", None))),
+            (&["a.bin"], true, Some(("a.bin", "-", None))),
+            (
+                &["a.bin", "0-0x10"],
+                true,
+                Some(("a.bin", "-", Some("0-0x10"))),
+            ),
+            // a spare file alongside '--no-spare' isn't taken as the ranges
+            (&["a.bin", "b.bin", "0-0x10"], true, None),
+        ] {
+            assert_eq!(
+                write_args(args, no_spare),
+                expected,
+                "{args:?} with no_spare {no_spare}"
+            );
+        }
+        Ok(())
+    }
+}
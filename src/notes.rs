@@ -0,0 +1,372 @@
+use std::fmt::{self, Display};
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_file};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+
+use crate::config::config_dir;
+use crate::sink::write_atomic;
+
+// Notes live in <config dir>/notes, one plain text file per console:
+//
+//   <BBID>.txt           a console whose BBID is known
+//   serial-<serial>.txt  one only known by its USB serial number
+//   serials              "<serial> <BBID>" lines, for finding a console's BBID without opening it
+//
+// In a note, lines like "@key = value" are tags and everything else is the note's text.
+
+const SERIALS_FILE: &str = "serials";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsoleKey {
+    Bbid(u32),
+    Serial(String),
+}
+
+impl ConsoleKey {
+    fn file_name(&self) -> String {
+        match self {
+            Self::Bbid(bbid) => format!("{bbid:08X}.txt"),
+            Self::Serial(serial) => format!("serial-{}.txt", sanitise(serial)),
+        }
+    }
+
+    fn from_file_name(name: &str) -> Option<Self> {
+        let stem = name.strip_suffix(".txt")?;
+        match stem.strip_prefix("serial-") {
+            Some(serial) => Some(Self::Serial(serial.to_string())),
+            None => u32::from_str_radix(stem, 16).ok().map(Self::Bbid),
+        }
+    }
+}
+
+impl Display for ConsoleKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bbid(bbid) => write!(f, "BBID {bbid:08X}"),
+            Self::Serial(serial) => write!(f, "serial {serial}"),
+        }
+    }
+}
+
+// serial numbers come from the device, so keep them to something that's safe in a file name
+fn sanitise(serial: &str) -> String {
+    serial
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Note {
+    pub text: String,
+    pub tags: Vec<(String, String)>,
+}
+
+impl Note {
+    pub fn parse(file: &str) -> Self {
+        let mut note = Self::default();
+        let mut text = vec![];
+        for line in file.lines() {
+            match line.strip_prefix('@').and_then(|t| t.split_once('=')) {
+                Some((key, value)) => note.set_tag(key.trim(), value.trim()),
+                None => text.push(line),
+            }
+        }
+        note.text = text.join("\n").trim().to_string();
+        note
+    }
+
+    pub fn to_file(&self) -> String {
+        let mut file = String::new();
+        for (key, value) in &self.tags {
+            file += &format!("@{key} = {value}\n");
+        }
+        if !self.text.is_empty() {
+            file += &self.text;
+            file += "\n";
+        }
+        file
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.tags.is_empty()
+    }
+
+    // an empty value removes the tag
+    pub fn set_tag(&mut self, key: &str, value: &str) {
+        match self.tags.iter_mut().find(|(k, _)| k == key) {
+            Some(tag) if !value.is_empty() => tag.1 = value.to_string(),
+            Some(_) => self.tags.retain(|(k, _)| k != key),
+            None if !value.is_empty() => self.tags.push((key.to_string(), value.to_string())),
+            None => {}
+        }
+    }
+
+    // combines a console's BBID-keyed note with one written while only its serial was known;
+    // the BBID-keyed note's tags win, and text that's in both is only kept once
+    pub fn merge(self, other: Note) -> Note {
+        let mut merged = self;
+        for (key, value) in other.tags {
+            if !merged.tags.iter().any(|(k, _)| *k == key) {
+                merged.tags.push((key, value));
+            }
+        }
+        if merged.text.is_empty() {
+            merged.text = other.text;
+        } else if !other.text.is_empty() && !merged.text.contains(&other.text) {
+            merged.text = format!("{}\n{}", merged.text, other.text);
+        }
+        merged
+    }
+
+    // the first line of the text, or the tags if there's no text
+    pub fn summary(&self) -> String {
+        match self.text.lines().next() {
+            Some(line) => line.to_string(),
+            None => self
+                .tags
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+}
+
+impl Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.tags {
+            writeln!(f, "{key}: {value}")?;
+        }
+        if !self.text.is_empty() {
+            writeln!(f, "{}", self.text)?;
+        }
+        Ok(())
+    }
+}
+
+// "<serial> <BBID>" lines; a later line for the same serial replaces an earlier one
+pub fn parse_serials(file: &str) -> Vec<(String, u32)> {
+    let mut serials: Vec<(String, u32)> = vec![];
+    for line in file.lines() {
+        let Some((serial, bbid)) = line.trim().rsplit_once(' ') else {
+            continue;
+        };
+        let Ok(bbid) = u32::from_str_radix(bbid.trim(), 16) else {
+            continue;
+        };
+        serials.retain(|(s, _)| s != serial.trim());
+        serials.push((serial.trim().to_string(), bbid));
+    }
+    serials
+}
+
+// the keys a console's notes could be under, BBID-keyed first: the BBID if it's known, else the
+// one cached for its serial, then the serial itself
+pub fn keys_for(
+    bbid: Option<u32>,
+    serial: Option<&str>,
+    serials: &[(String, u32)],
+) -> Vec<ConsoleKey> {
+    let bbid = bbid.or_else(|| {
+        let serial = serial?;
+        serials.iter().find(|(s, _)| s == serial).map(|(_, b)| *b)
+    });
+    bbid.map(ConsoleKey::Bbid)
+        .into_iter()
+        .chain(serial.map(|s| ConsoleKey::Serial(s.to_string())))
+        .collect()
+}
+
+fn notes_dir() -> Result<PathBuf> {
+    let dir = config_dir()
+        .ok_or_else(|| anyhow!("no config directory to keep notes in"))?
+        .join("notes");
+    create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn read_optional(path: PathBuf) -> Result<Option<String>> {
+    match read_to_string(&path) {
+        Ok(s) => Ok(Some(s)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(anyhow!("{}: {e}", path.display())),
+    }
+}
+
+fn load_serials() -> Result<Vec<(String, u32)>> {
+    Ok(read_optional(notes_dir()?.join(SERIALS_FILE))?
+        .map(|s| parse_serials(&s))
+        .unwrap_or_default())
+}
+
+// records which BBID a serial number belongs to, so 'l' can show notes without opening consoles
+pub fn remember_serial(serial: &str, bbid: u32) -> Result<()> {
+    let mut serials = load_serials()?;
+    if serials.iter().any(|(s, b)| s == serial && *b == bbid) {
+        return Ok(());
+    }
+    serials.retain(|(s, _)| s != serial);
+    serials.push((serial.to_string(), bbid));
+    let file = serials
+        .iter()
+        .map(|(s, b)| format!("{s} {b:08X}\n"))
+        .collect::<String>();
+    write_atomic(notes_dir()?.join(SERIALS_FILE), file.as_bytes())
+}
+
+fn load(key: &ConsoleKey) -> Result<Option<Note>> {
+    Ok(read_optional(notes_dir()?.join(key.file_name()))?.map(|s| Note::parse(&s)))
+}
+
+// saves a console's note under its BBID if it's known, else under its serial; once the BBID is
+// known, a note kept under the serial has been merged into it by 'lookup', so that goes
+pub fn save(bbid: Option<u32>, serial: Option<&str>, note: &Note) -> Result<()> {
+    let dir = notes_dir()?;
+    let (Some(bbid), serial) = (bbid, serial) else {
+        let serial = serial.ok_or_else(|| {
+            anyhow!("Couldn't read the console's BBID or USB serial number to keep its note under")
+        })?;
+        let key = ConsoleKey::Serial(serial.to_string());
+        return write_atomic(dir.join(key.file_name()), note.to_file().as_bytes());
+    };
+    write_atomic(
+        dir.join(ConsoleKey::Bbid(bbid).file_name()),
+        note.to_file().as_bytes(),
+    )?;
+    if let Some(serial) = serial {
+        remember_serial(serial, bbid)?;
+        let old = dir.join(ConsoleKey::Serial(serial.to_string()).file_name());
+        match remove_file(&old) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(anyhow!("{}: {e}", old.display()))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// a console's note, merged from everywhere it could be
+pub fn lookup(bbid: Option<u32>, serial: Option<&str>) -> Result<Option<Note>> {
+    let mut found: Option<Note> = None;
+    for key in keys_for(bbid, serial, &load_serials()?) {
+        if let Some(note) = load(&key)? {
+            found = Some(match found {
+                Some(f) => f.merge(note),
+                None => note,
+            });
+        }
+    }
+    Ok(found.filter(|n| !n.is_empty()))
+}
+
+pub fn list() -> Result<Vec<(ConsoleKey, Note)>> {
+    let mut notes = vec![];
+    for entry in read_dir(notes_dir()?)? {
+        let name = entry?.file_name();
+        let Some(key) = name.to_str().and_then(ConsoleKey::from_file_name) else {
+            continue;
+        };
+        if let Some(note) = load(&key)?.filter(|n| !n.is_empty()) {
+            notes.push((key, note));
+        }
+    }
+    notes.sort_by_key(|(key, _)| key.file_name());
+    Ok(notes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notes() {
+        let note = Note::parse(
+            "@owner = Sam\n\nBad left trigger.\n@slot=2\nResoldered 2025.\n@owner = Alex\n",
+        );
+        assert_eq!(note.text, "Bad left trigger.\nResoldered 2025.");
+        assert_eq!(
+            note.tags,
+            [
+                ("owner".to_string(), "Alex".to_string()),
+                ("slot".to_string(), "2".to_string())
+            ]
+        );
+        assert_eq!(Note::parse(&note.to_file()), note);
+        assert_eq!(note.summary(), "Bad left trigger.");
+        assert_eq!(
+            note.to_string(),
+            "owner: Alex\nslot: 2\nBad left trigger.\nResoldered 2025.\n"
+        );
+
+        // an empty value removes a tag, and a note with only tags is summed up by them
+        let mut tags = Note::default();
+        assert!(tags.is_empty());
+        tags.set_tag("slot", "3");
+        tags.set_tag("shelf", "B");
+        tags.set_tag("slot", "");
+        tags.set_tag("missing", "");
+        assert_eq!(tags.summary(), "shelf=B");
+        assert_eq!(tags.to_file(), "@shelf = B\n");
+
+        // the BBID-keyed note's tags win, and shared text isn't repeated
+        let by_serial = Note::parse("@owner = Sam\n@shelf = C\nResoldered 2025.");
+        let merged = note.clone().merge(by_serial);
+        assert_eq!(merged.text, note.text);
+        assert_eq!(merged.tags.len(), 3);
+        assert_eq!(merged.tags[0].1, "Alex");
+        assert_eq!(merged.tags[2], ("shelf".to_string(), "C".to_string()));
+        let merged = Note::default().merge(Note::parse("Found in a box."));
+        assert_eq!(merged.text, "Found in a box.");
+        assert_eq!(
+            Note::parse("One.").merge(Note::parse("Two.")).text,
+            "One.\nTwo."
+        );
+    }
+
+    #[test]
+    fn keys() {
+        let serials =
+            parse_serials("ABC123 1234ABCD\nbad line\nXYZ notabbid\n\nABC123 0000BEEF\nQ 9\n");
+        assert_eq!(
+            serials,
+            [("ABC123".to_string(), 0xBEEF), ("Q".to_string(), 9)]
+        );
+
+        // a known BBID first, then the one cached for the serial, then the serial itself
+        let serial = |s: &str| ConsoleKey::Serial(s.to_string());
+        assert_eq!(
+            keys_for(Some(1), Some("ABC123"), &serials),
+            [ConsoleKey::Bbid(1), serial("ABC123")]
+        );
+        assert_eq!(
+            keys_for(None, Some("ABC123"), &serials),
+            [ConsoleKey::Bbid(0xBEEF), serial("ABC123")]
+        );
+        assert_eq!(keys_for(None, Some("NEW"), &serials), [serial("NEW")]);
+        assert_eq!(keys_for(None, None, &serials), []);
+
+        // serials are kept to what's safe in a file name
+        for (key, file) in [
+            (ConsoleKey::Bbid(0xBEEF), "0000BEEF.txt"),
+            (serial("A/../b c"), "serial-A____b_c.txt"),
+        ] {
+            assert_eq!(key.file_name(), file);
+        }
+        assert_eq!(
+            ConsoleKey::from_file_name("0000BEEF.txt"),
+            Some(ConsoleKey::Bbid(0xBEEF))
+        );
+        assert_eq!(
+            ConsoleKey::from_file_name("serial-ABC123.txt"),
+            Some(serial("ABC123"))
+        );
+        assert_eq!(ConsoleKey::from_file_name("serials"), None);
+        assert_eq!(ConsoleKey::from_file_name("notes.txt"), None);
+        assert_eq!(serial("ABC123").to_string(), "serial ABC123");
+        assert_eq!(ConsoleKey::Bbid(0xBEEF).to_string(), "BBID 0000BEEF");
+    }
+}
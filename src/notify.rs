@@ -0,0 +1,209 @@
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::options::Options;
+use crate::player::Player;
+
+// how a long operation ended, for 'notify-command'
+pub struct Outcome {
+    pub operation: String,
+    pub bbid: Option<u32>,
+    pub duration: Duration,
+    // None if it succeeded
+    pub error: Option<String>,
+    pub outputs: Vec<String>,
+}
+
+// the environment the hook runs with; outputs are separated by newlines, since paths may
+// contain anything else
+pub fn hook_env(outcome: &Outcome) -> Vec<(&'static str, String)> {
+    vec![
+        ("AULON2_OPERATION", outcome.operation.clone()),
+        (
+            "AULON2_BBID",
+            outcome.bbid.map(|b| format!("{b:08X}")).unwrap_or_default(),
+        ),
+        (
+            "AULON2_DURATION_SECS",
+            format!("{:.1}", outcome.duration.as_secs_f64()),
+        ),
+        (
+            "AULON2_STATUS",
+            if outcome.error.is_none() {
+                "success"
+            } else {
+                "failure"
+            }
+            .to_string(),
+        ),
+        ("AULON2_OUTPUTS", outcome.outputs.join("\n")),
+        ("AULON2_ERROR", outcome.error.clone().unwrap_or_default()),
+    ]
+}
+
+pub fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.args(["/C", command]);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.args(["-c", command]);
+        c
+    }
+}
+
+// runs the hook in the background; nothing it does (or fails to do) affects the operation
+pub fn fire(command: &str, outcome: &Outcome) {
+    let mut cmd = shell(command);
+    cmd.envs(hook_env(outcome));
+    let operation = outcome.operation.clone();
+    thread::spawn(move || match cmd.output() {
+        Ok(output) => {
+            for line in String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .chain(String::from_utf8_lossy(&output.stderr).lines())
+            {
+                eprintln!("notify ({operation}): {line}");
+            }
+            if !output.status.success() {
+                eprintln!(
+                    "notify ({operation}): the hook exited with {}",
+                    output.status
+                );
+            }
+        }
+        Err(e) => eprintln!("notify ({operation}): couldn't run the hook: {e}"),
+    });
+}
+
+// the hook to run after an operation that took `duration`, if one's set and it took long enough
+fn due(options: &Options, duration: Duration) -> Option<&str> {
+    options
+        .notify_command
+        .as_deref()
+        .filter(|_| duration.as_secs() >= options.notify_threshold)
+}
+
+// fires the hook if one is set and the operation took long enough to be worth hearing about
+pub fn notify(
+    options: &Options,
+    operation: &str,
+    player: &dyn Player,
+    started: Instant,
+    error: Option<String>,
+    outputs: &[&str],
+) {
+    let duration = started.elapsed();
+    let Some(command) = due(options, duration) else {
+        return;
+    };
+    fire(
+        command,
+        &Outcome {
+            operation: operation.to_string(),
+            bbid: player.GetBBID().ok(),
+            duration,
+            error,
+            outputs: outputs.iter().map(|o| o.to_string()).collect(),
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use anyhow::{bail, Result};
+
+    #[test]
+    fn env() -> Result<()> {
+        let mut outcome = Outcome {
+            operation: "dump".to_string(),
+            bbid: Some(0xABC),
+            duration: Duration::from_millis(61_250),
+            error: None,
+            outputs: vec!["out/nand.bin".to_string(), "out/spare bin".to_string()],
+        };
+        assert_eq!(
+            hook_env(&outcome),
+            [
+                ("AULON2_OPERATION", "dump".to_string()),
+                ("AULON2_BBID", "00000ABC".to_string()),
+                ("AULON2_DURATION_SECS", "61.2".to_string()),
+                ("AULON2_STATUS", "success".to_string()),
+                ("AULON2_OUTPUTS", "out/nand.bin\nout/spare bin".to_string()),
+                ("AULON2_ERROR", String::new()),
+            ]
+        );
+
+        // a failure, from a console whose BBID couldn't be read, with nothing saved
+        outcome.bbid = None;
+        outcome.error = Some("Operation timed out".to_string());
+        outcome.outputs.clear();
+        let env = hook_env(&outcome);
+        let var = |name| {
+            env.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.as_str())
+        };
+        if var("AULON2_BBID") != Some("")
+            || var("AULON2_STATUS") != Some("failure")
+            || var("AULON2_OUTPUTS") != Some("")
+            || var("AULON2_ERROR") != Some("Operation timed out")
+        {
+            bail!("a failure's hook environment was {env:?}");
+        }
+        Ok(())
+    }
+
+    // only with a hook set, and only for operations that took at least the threshold
+    #[test]
+    fn threshold() {
+        let mut options = Options::default();
+        assert_eq!(due(&options, Duration::from_secs(3600)), None);
+        options.notify_command = Some("notify-send done".to_string());
+        options.notify_threshold = 60;
+        assert_eq!(due(&options, Duration::from_millis(59_999)), None);
+        assert_eq!(
+            due(&options, Duration::from_secs(60)),
+            Some("notify-send done")
+        );
+        options.notify_threshold = 0;
+        assert_eq!(due(&options, Duration::ZERO), Some("notify-send done"));
+    }
+
+    // the hook gets the environment through the shell
+    #[cfg(unix)]
+    #[test]
+    fn fires() -> Result<()> {
+        let dir = TestDir::new("notify")?;
+        let out = dir.join("out");
+        let command = format!(
+            "echo \"$AULON2_OPERATION $AULON2_STATUS $AULON2_BBID\" > '{}'",
+            out.display()
+        );
+        fire(
+            &command,
+            &Outcome {
+                operation: "write".to_string(),
+                bbid: Some(0x1234),
+                duration: Duration::from_secs(90),
+                error: None,
+                outputs: vec![],
+            },
+        );
+        // it runs in the background
+        let mut written = String::new();
+        for _ in 0..100 {
+            written = std::fs::read_to_string(&out).unwrap_or_default();
+            if written.ends_with('\n') {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(written, "write success 00001234\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+use anyhow::Result;
+
+use crate::fs::{FsBlock, FsEntry, BLOCK_SIZE, FS_REGION_BLOCKS};
+use crate::image::NandImage;
+use crate::sizes::format_size;
+use crate::ticket::{parse_tickets, Ticket, TICKET_FILE};
+use crate::titles::TitleDb;
+
+const CONTENT_EXTS: [&str; 2] = ["app", "rec"];
+const SAVE_EXTS: [&str; 4] = ["sta", "eep", "fla", "pak"];
+
+// splits "0012d687.app" into (0x0012D687, "app")
+pub fn content_id(name: &str) -> Option<(u32, &str)> {
+    let (stem, ext) = name.rsplit_once('.')?;
+    if stem.len() != 8 {
+        return None;
+    }
+    Some((u32::from_str_radix(stem, 16).ok()?, ext))
+}
+
+// the card's files, sorted out against its tickets
+#[derive(Debug, Default)]
+struct Contents<'a> {
+    installed: Vec<(&'a FsEntry, &'a Ticket)>,
+    // apps with no ticket, and tickets with no app
+    orphaned: Vec<&'a FsEntry>,
+    missing: Vec<&'a Ticket>,
+    // with the content ID of the game each is for
+    saves: Vec<(&'a FsEntry, u32)>,
+}
+
+fn sort_out<'a>(fs: &'a FsBlock, tickets: &'a [Ticket]) -> Contents<'a> {
+    let mut contents = Contents::default();
+    for entry in &fs.entries {
+        match content_id(&entry.name) {
+            Some((cid, ext)) if CONTENT_EXTS.contains(&ext) => {
+                match tickets.iter().find(|t| t.content_id == cid) {
+                    Some(t) => contents.installed.push((entry, t)),
+                    None => contents.orphaned.push(entry),
+                }
+            }
+            Some((cid, ext)) if SAVE_EXTS.contains(&ext) => contents.saves.push((entry, cid)),
+            _ => {}
+        }
+    }
+    contents.missing = tickets
+        .iter()
+        .filter(|t| {
+            !contents
+                .installed
+                .iter()
+                .any(|(_, i)| i.content_id == t.content_id)
+        })
+        .collect();
+    contents
+}
+
+pub fn dumpinfo(nand_filename: &str, spare_filename: &str) -> Result<()> {
+    let image = NandImage::load(nand_filename, spare_filename)?;
+    let titles = TitleDb::load();
+    let mut problems = vec![];
+
+    println!(
+        "Image: {} blocks ({}), {} marked bad in spare",
+        image.num_blocks(),
+        format_size((image.num_blocks() * BLOCK_SIZE) as u64, 2),
+        image.bad_blocks().len()
+    );
+
+    let generations = image.fs_generations();
+    for (blk, fs) in &generations {
+        match fs {
+            Ok(fs) if fs.linked => problems.push(format!(
+                "FS block {blk:#X} is part of a multi-block FAT, which isn't supported; only its first block was read"
+            )),
+            Ok(_) => {}
+            Err(e) => problems.push(format!("FS block {blk:#X}: {e}")),
+        }
+    }
+
+    let Some((fs_blk, fs)) = image.current_fs() else {
+        println!("No valid FS block found in the last {FS_REGION_BLOCKS} blocks; nothing more can be decoded.");
+        for p in problems {
+            println!("  {p}");
+        }
+        return Ok(());
+    };
+    println!(
+        "FS: using block {fs_blk:#X} (sequence number {}), {} of {} FS blocks valid",
+        fs.seqno,
+        generations.iter().filter(|(_, fs)| fs.is_ok()).count(),
+        generations.len()
+    );
+
+    let tickets = match fs.find(TICKET_FILE) {
+        Some(entry) => match image.read_file(&fs, entry).and_then(|d| parse_tickets(&d)) {
+            Ok(t) => {
+                if let Some(count) = t.truncated {
+                    problems.push(format!(
+                        "{TICKET_FILE} claims {count} tickets but only {} fit in the file",
+                        t.tickets.len()
+                    ));
+                }
+                if let Some(first) = t.tickets.first() {
+                    println!(
+                        "{TICKET_FILE}: {} tickets for BBID {:08X}",
+                        t.tickets.len(),
+                        first.bbid
+                    );
+                }
+                t.tickets
+            }
+            Err(e) => {
+                problems.push(format!("{TICKET_FILE}: {e}"));
+                vec![]
+            }
+        },
+        None => {
+            problems.push(format!("{TICKET_FILE} not found in the FS"));
+            vec![]
+        }
+    };
+
+    for entry in fs.suspect() {
+        problems.push(format!(
+            "{}: claims {} bytes, more than the card holds",
+            entry.name, entry.size
+        ));
+    }
+    for entry in &fs.entries {
+        if let Err(e) = fs.chain(entry.start) {
+            problems.push(format!("{}: {e}", entry.name));
+        }
+    }
+    let Contents {
+        installed,
+        orphaned,
+        missing,
+        saves,
+    } = sort_out(&fs, &tickets);
+
+    println!("\nInstalled games ({}):", installed.len());
+    for (entry, ticket) in installed {
+        println!(
+            "{:>12}: {:>7}  {:<24} {}",
+            entry.name,
+            format_size(entry.size as u64, 0),
+            ticket.kind,
+            titles.lookup(ticket.content_id)
+        );
+    }
+
+    println!("\nApps with no ticket ({}):", orphaned.len());
+    for entry in orphaned {
+        let cid = content_id(&entry.name).map_or(0, |(cid, _)| cid);
+        println!(
+            "{:>12}: {:>7}  {}",
+            entry.name,
+            format_size(entry.size as u64, 0),
+            titles.lookup(cid)
+        );
+    }
+
+    println!("\nTickets with no app ({}):", missing.len());
+    for ticket in missing {
+        println!(
+            "{:>12}: {:>7}  {:<24} {}",
+            ticket.app_name(),
+            format_size(ticket.content_size as u64, 0),
+            ticket.kind,
+            titles.lookup(ticket.content_id)
+        );
+    }
+
+    println!("\nSaves ({}):", saves.len());
+    for (entry, cid) in saves {
+        println!(
+            "{:>12}: {:>7}  {}",
+            entry.name,
+            format_size(entry.size as u64, 0),
+            titles.lookup(cid)
+        );
+    }
+
+    if !problems.is_empty() {
+        println!("\nCould not decode:");
+        for p in problems {
+            println!("  {p}");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, bail};
+
+    use crate::genimage::{generate, FileSpec, Pattern, Spec};
+    use crate::ticket::TicketKind;
+
+    #[test]
+    fn content_ids() {
+        assert_eq!(content_id("0012d687.app"), Some((0x0012D687, "app")));
+        assert_eq!(content_id("0012D687.sta"), Some((0x0012D687, "sta")));
+        assert_eq!(content_id("12d687.app"), None);
+        assert_eq!(content_id("0012g687.app"), None);
+        assert_eq!(content_id("ticket.sys"), None);
+        assert_eq!(content_id("0012d687"), None);
+    }
+
+    // games with tickets, apps without, tickets without apps, and saves, from a dump's FS
+    #[test]
+    fn sorting_out() -> Result<()> {
+        let file = |name: &str| FileSpec {
+            name: name.to_string(),
+            pattern: Some(Pattern::Counting),
+            size: Some(0x100),
+            ..FileSpec::default()
+        };
+        let generated = generate(
+            &Spec {
+                blocks: 0x80,
+                files: ["0012d687.app", "0012d687.sta", "11111111.rec", "readme.txt"]
+                    .map(file)
+                    .to_vec(),
+                ..Spec::default()
+            },
+            &|path| bail!("{path}: no local files"),
+        )?;
+        let image = NandImage::new(generated.nand, generated.spare)?;
+        let (_, fs) = image.current_fs().ok_or_else(|| anyhow!("no FS"))?;
+        let ticket = |content_id, kind| Ticket {
+            content_id,
+            content_size: 0x100,
+            bbid: 0x1234,
+            kind,
+        };
+        let tickets = [
+            ticket(0x0012D687, TicketKind::Permanent),
+            ticket(0x00ABCDEF, TicketKind::Limited { code: 1, limit: 5 }),
+        ];
+
+        let contents = sort_out(&fs, &tickets);
+        let names =
+            |entries: Vec<&FsEntry>| entries.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+        assert_eq!(
+            names(contents.installed.iter().map(|(e, _)| *e).collect()),
+            ["0012d687.app"]
+        );
+        assert_eq!(contents.installed[0].1, &tickets[0]);
+        assert_eq!(names(contents.orphaned), ["11111111.rec"]);
+        assert_eq!(contents.missing, [&tickets[1]]);
+        assert_eq!(
+            contents
+                .saves
+                .iter()
+                .map(|(e, cid)| (e.name.as_str(), *cid))
+                .collect::<Vec<_>>(),
+            [("0012d687.sta", 0x0012D687)]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::Local;
+
+use crate::sink::write_atomic;
+
+// how many commands are kept for 'report save'
+const CAPACITY: usize = 32;
+// the hint about 'report save' is printed at most this often
+const HINT_INTERVAL: Duration = Duration::from_secs(60);
+// options whose values are commands to run, which can carry tokens or passwords; a report says
+// whether they're set, but not what to
+const COMMAND_OPTIONS: [&str; 1] = ["notify-command"];
+
+// a command as typed (file names, block numbers and the like, but never file contents)
+pub struct OpRecord {
+    pub time: String,
+    pub command: String,
+    // None if the command didn't report an outcome
+    pub outcome: Option<Result<(), String>>,
+}
+
+// the last few commands and how they went, for bug reports
+#[derive(Default)]
+pub struct OpLog {
+    records: VecDeque<OpRecord>,
+    last_error: Option<String>,
+    last_hint: Option<Instant>,
+}
+
+impl OpLog {
+    pub fn begin(&mut self, command: &str) {
+        if self.records.len() == CAPACITY {
+            self.records.pop_front();
+        }
+        self.records.push_back(OpRecord {
+            time: Local::now().to_rfc3339(),
+            command: command.to_string(),
+            outcome: None,
+        });
+    }
+
+    pub fn succeed(&mut self) {
+        if let Some(r) = self.records.back_mut() {
+            r.outcome = Some(Ok(()));
+        }
+    }
+
+    // records a failure, printing where it happened (if that's known) and, now and then, how to
+    // save a report about it
+    pub fn fail(&mut self, error: &str, context: Option<String>, now: Instant) {
+        let error = match &context {
+            Some(c) => {
+                eprintln!("  ({c})");
+                format!("{error} ({c})")
+            }
+            None => error.to_string(),
+        };
+        if let Some(r) = self.records.back_mut() {
+            r.outcome = Some(Err(error.clone()));
+        }
+        self.last_error = Some(error);
+        if self
+            .last_hint
+            .is_none_or(|t| now.duration_since(t) >= HINT_INTERVAL)
+        {
+            self.last_hint = Some(now);
+            eprintln!(
+                "Type 'report save <file>' to save the details of this error for a bug report."
+            );
+        }
+    }
+
+    // records how a command went, given its error if it failed
+    pub fn record(&mut self, error: Option<&str>, context: Option<String>) {
+        match error {
+            None => self.succeed(),
+            Some(e) => self.fail(e, context, Instant::now()),
+        }
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &OpRecord> {
+        self.records.iter()
+    }
+
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    // `signature` is the known error signature the last error printed matched, if any
+    pub fn save(
+        &self,
+        path: &str,
+        version: &str,
+        options: &[String],
+        signature: Option<&str>,
+    ) -> Result<()> {
+        write_atomic(
+            path,
+            format_report(
+                version,
+                options,
+                self.records(),
+                self.last_error(),
+                signature,
+            )
+            .as_bytes(),
+        )
+    }
+}
+
+// an option as 'set' lists it, with a command-valued one's value left out
+fn redact_option(line: &str) -> String {
+    match line.split_once(": ") {
+        Some((option, value)) if COMMAND_OPTIONS.contains(&option) && value != "off" => {
+            format!("{option}: (set; not included)")
+        }
+        _ => line.to_string(),
+    }
+}
+
+// a command as typed, with the value of a command-valued option it sets left out
+fn redact_command(command: &str) -> String {
+    let words = command.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+        ["set", option, value, ..] if COMMAND_OPTIONS.contains(option) && *value != "off" => {
+            format!("set {option} (not included)")
+        }
+        _ => command.to_string(),
+    }
+}
+
+pub fn format_report<'a>(
+    version: &str,
+    options: &[String],
+    records: impl Iterator<Item = &'a OpRecord>,
+    last_error: Option<&str>,
+    signature: Option<&str>,
+) -> String {
+    let mut report = format!("{version}\n");
+    report += &format!("OS: {} {}\n", std::env::consts::OS, std::env::consts::ARCH);
+    report += &format!("Saved: {}\n\nOptions:\n", Local::now().to_rfc3339());
+    for o in options {
+        report += &format!("  {}\n", redact_option(o));
+    }
+    report += "\nLast error:\n";
+    report += &format!("  {}\n", last_error.unwrap_or("none"));
+    if let Some(id) = signature {
+        report += &format!("  (matched known signature: {id})\n");
+    }
+    report += "\nRecent commands (oldest first):\n";
+    for r in records {
+        let outcome = match &r.outcome {
+            None => "-".to_string(),
+            Some(Ok(())) => "ok".to_string(),
+            Some(Err(e)) => format!("failed: {e}"),
+        };
+        report += &format!(
+            "  {}  {}  => {outcome}\n",
+            r.time,
+            redact_command(&r.command)
+        );
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report() -> Result<()> {
+        let mut log = OpLog::default();
+        log.begin("set notify-command curl -H 'Authorization: Bearer secret' https://example.com");
+        log.succeed();
+        log.begin("set notify-command off");
+        log.succeed();
+        log.begin("4 secret.app");
+        log.fail("upload failed", None, Instant::now());
+
+        let report = format_report(
+            "test v0",
+            &[
+                "notify-command: curl -H 'Authorization: Bearer secret' https://example.com"
+                    .to_string(),
+                "lint: on".to_string(),
+            ],
+            log.records(),
+            log.last_error(),
+            None,
+        );
+        assert!(!report.contains("Bearer"), "{report}");
+        for line in [
+            "  notify-command: (set; not included)\n",
+            "  lint: on\n",
+            "  set notify-command (not included)  => ok\n",
+            "  set notify-command off  => ok\n",
+            "  4 secret.app  => failed: upload failed\n",
+            "Last error:\n  upload failed\n",
+        ] {
+            assert!(report.contains(line), "no {line:?} in {report}");
+        }
+        assert_eq!(redact_option("notify-command: off"), "notify-command: off");
+
+        // only the last few commands are kept
+        for i in 0..CAPACITY + 5 {
+            log.begin(&format!("C {i}"));
+        }
+        assert_eq!(log.records().count(), CAPACITY);
+        assert_eq!(
+            log.records().next().map(|r| r.command.as_str()),
+            Some("C 5")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::compress::Codec;
+use crate::hashing::{parse_algos, HashAlgo};
+
+/// Session options, changed at the prompt with `set <option> <value>`.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    /// Whether progress is emitted as newline-delimited JSON events on stderr instead of progress
+    /// bars, and errors as JSON too.
+    pub progress_events: bool,
+    /// Whether files are checked before they're uploaded.
+    pub lint: bool,
+    /// Whether the console's LED flashes during long operations.
+    pub led_feedback: bool,
+    /// How often, in seconds, the console is pinged while the prompt waits.
+    pub keepalive: Option<u64>,
+    /// What's run after an operation taking at least `notify_threshold` seconds finishes.
+    pub notify_command: Option<String>,
+    /// How long, in seconds, an operation takes before `notify_command` is run for it.
+    pub notify_threshold: u64,
+    /// Whether writes that don't give a manifest, the console's BBID and explicit ranges are
+    /// refused.
+    pub strict_writes: bool,
+    /// Whether the console's USB port is reset and 'B' retried when it fails in a way that
+    /// suggests it needs one.
+    pub auto_reset: bool,
+    /// Whether temporary files left by interrupted operations are cleaned up on connecting,
+    /// without asking.
+    pub auto_clean: bool,
+    /// What hashing commands hash with when not given '--algo'; None leaves each its own default.
+    pub hash_algos: Option<Vec<HashAlgo>>,
+    /// Whether '1' writes a manifest without being asked.
+    pub manifest: bool,
+    /// What '1' saves the dump compressed with, without being asked.
+    pub compress: Option<Codec>,
+    /// Whether '2' reads back what it wrote, as with '--verify'.
+    pub verify_writes: bool,
+    /// Whether commands that use the console list the calls they'd make of it instead of making
+    /// them.
+    pub dry_run_trace: bool,
+    /// The size, in MiB, from which writes check the link first.
+    pub preflight_above: Option<u64>,
+    /// The link check score that lets a write go ahead.
+    pub preflight_score: u8,
+    /// How recent, in hours, the verified dump of the console that writes over the whole card (or
+    /// its SKSA or FS region) need has to be.
+    pub require_backup: Option<u64>,
+    /// Where that dump is looked for, instead of the current directory.
+    pub backup_dir: Option<String>,
+    /// Whether a write that reaches into the FS region reads the region back and checks its FS.
+    pub post_write_fscheck: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            progress_events: false,
+            lint: true,
+            led_feedback: false,
+            keepalive: None,
+            notify_command: None,
+            notify_threshold: 60,
+            strict_writes: false,
+            auto_reset: false,
+            auto_clean: false,
+            hash_algos: None,
+            manifest: false,
+            compress: None,
+            verify_writes: false,
+            dry_run_trace: false,
+            preflight_above: Some(16),
+            preflight_score: 70,
+            require_backup: None,
+            backup_dir: None,
+            post_write_fscheck: true,
+        }
+    }
+}
+
+/// Reads an on/off value: 'on', 'true', 'yes' or '1', or 'off', 'false', 'no' or '0'.
+pub fn parse_bool(value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "true" | "yes" | "1" => Ok(true),
+        "off" | "false" | "no" | "0" => Ok(false),
+        _ => Err(anyhow!("'{value}' isn't a valid on/off value")),
+    }
+}
+
+/// Removes `<flag> <value>` from a command's arguments, returning the value; fails if the flag
+/// is there without a value.
+pub fn take_flag_value<'a>(args: &mut Vec<&'a str>, flag: &str) -> Result<Option<&'a str>> {
+    let Some(i) = args.iter().position(|a| *a == flag) else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        bail!(
+            "'{flag}' requires an argument. Type 'h' for a list of commands and their arguments."
+        );
+    }
+    let value = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(value))
+}
+
+impl Options {
+    /// Sets an option by the name 'set' knows it by.
+    pub fn set(&mut self, option: &str, value: &str) -> Result<()> {
+        match option {
+            "progress-events" => self.progress_events = parse_bool(value)?,
+            "lint" => self.lint = parse_bool(value)?,
+            "led-feedback" => self.led_feedback = parse_bool(value)?,
+            "keepalive" => {
+                self.keepalive =
+                    match value {
+                        "off" | "0" => None,
+                        _ => Some(value.parse().map_err(|_| {
+                            anyhow!("'{value}' isn't a number of seconds, or 'off'")
+                        })?),
+                    }
+            }
+            "notify-command" => self.notify_command = (value != "off").then(|| value.to_string()),
+            "notify-threshold" => {
+                self.notify_threshold = value
+                    .parse()
+                    .map_err(|_| anyhow!("'{value}' isn't a number of seconds"))?
+            }
+            "strict-writes" => self.strict_writes = parse_bool(value)?,
+            "auto-reset" => self.auto_reset = parse_bool(value)?,
+            "auto-clean" => self.auto_clean = parse_bool(value)?,
+            "hash-algos" => {
+                self.hash_algos = match value {
+                    "default" => None,
+                    _ => Some(parse_algos(value)?),
+                }
+            }
+            "manifest" => self.manifest = parse_bool(value)?,
+            "compress" => {
+                self.compress = match value {
+                    "off" => None,
+                    "gz" | "gzip" => Some(Codec::Gzip),
+                    "zst" | "zstd" => Some(Codec::Zstd),
+                    _ => bail!("'{value}' isn't a compression format; use 'gz', 'zst' or 'off'"),
+                }
+            }
+            "verify-writes" => self.verify_writes = parse_bool(value)?,
+            "dry-run" => {
+                self.dry_run_trace = match value {
+                    "trace" => true,
+                    "off" => false,
+                    _ => bail!("'{value}' isn't a dry run mode; use 'trace' or 'off'"),
+                }
+            }
+            "preflight" => {
+                self.preflight_above = match value {
+                    "off" => None,
+                    _ => Some(
+                        value
+                            .parse()
+                            .map_err(|_| anyhow!("'{value}' isn't a number of MiB, or 'off'"))?,
+                    ),
+                }
+            }
+            "preflight-score" => {
+                self.preflight_score = value
+                    .parse()
+                    .ok()
+                    .filter(|&s| s <= 100)
+                    .ok_or_else(|| anyhow!("'{value}' isn't a score from 0 to 100"))?
+            }
+            "require-backup" => {
+                self.require_backup = match value {
+                    "off" => None,
+                    _ => Some(
+                        value
+                            .parse()
+                            .map_err(|_| anyhow!("'{value}' isn't a number of hours, or 'off'"))?,
+                    ),
+                }
+            }
+            "backup-dir" => self.backup_dir = (value != "default").then(|| value.to_string()),
+            "post-write-fscheck" => self.post_write_fscheck = parse_bool(value)?,
+            _ => bail!("Unknown option '{option}'. Type 'set' to list the available options."),
+        }
+        Ok(())
+    }
+
+    /// Each option's name and value, as 'set' lists them.
+    pub fn lines(&self) -> Vec<String> {
+        let on_off = |b: bool| if b { "on" } else { "off" };
+        vec![
+            format!("progress-events: {}", on_off(self.progress_events)),
+            format!("lint: {}", on_off(self.lint)),
+            format!("led-feedback: {}", on_off(self.led_feedback)),
+            match self.keepalive {
+                Some(secs) => format!("keepalive: {secs}s"),
+                None => "keepalive: off".to_string(),
+            },
+            format!(
+                "notify-command: {}",
+                self.notify_command.as_deref().unwrap_or("off")
+            ),
+            format!("notify-threshold: {}s", self.notify_threshold),
+            format!("strict-writes: {}", on_off(self.strict_writes)),
+            format!("auto-reset: {}", on_off(self.auto_reset)),
+            format!("auto-clean: {}", on_off(self.auto_clean)),
+            match &self.hash_algos {
+                Some(algos) => format!(
+                    "hash-algos: {}",
+                    algos.iter().map(|a| a.name()).collect::<Vec<_>>().join(",")
+                ),
+                None => "hash-algos: default".to_string(),
+            },
+            format!("manifest: {}", on_off(self.manifest)),
+            format!(
+                "compress: {}",
+                self.compress.map(Codec::ext).unwrap_or("off")
+            ),
+            format!("verify-writes: {}", on_off(self.verify_writes)),
+            format!(
+                "dry-run: {}",
+                if self.dry_run_trace { "trace" } else { "off" }
+            ),
+            match self.preflight_above {
+                Some(mib) => format!("preflight: writes of {mib} MiB or more"),
+                None => "preflight: off".to_string(),
+            },
+            format!("preflight-score: {}", self.preflight_score),
+            match self.require_backup {
+                Some(hours) => format!("require-backup: a dump from the last {hours} hours"),
+                None => "require-backup: off".to_string(),
+            },
+            format!(
+                "backup-dir: {}",
+                self.backup_dir.as_deref().unwrap_or("default")
+            ),
+            format!("post-write-fscheck: {}", on_off(self.post_write_fscheck)),
+        ]
+    }
+
+    /// Prints [`Options::lines`].
+    pub fn print(&self) {
+        for line in self.lines() {
+            println!("{line}");
+        }
+    }
+}
@@ -0,0 +1,646 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{create_dir_all, read, read_dir, read_to_string, rename, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local};
+
+use crate::ecc::EccCheck;
+use crate::fs::{BLOCK_SIZE, SPARE_SIZE};
+use crate::hashing::parse_sum_line;
+use crate::provenance::{MANIFEST_EXT, SOURCE_BBID_TAG};
+use crate::spare::ecc_check;
+
+// 'organize': tidies the dumps in a directory into archive/<BBID>/<date>/. Files are taken for
+// NANDs and spares by their sizes (for the sizes of card there are), and a NAND is paired with
+// its spare by a manifest listing both, or else by the spare's ECC matching the NAND's data. The
+// console comes from the manifest (or is asked for), and the date from the manifest or the NAND.
+// Anything whose pairing isn't certain is left where it is, and said so.
+//
+// Every move is logged in the directory, in MOVE_LOG, before the next is made, so that
+// 'organize --undo' can put the files back.
+
+pub const ARCHIVE_DIR: &str = "archive";
+pub const MOVE_LOG: &str = ".aulon2-moves.log";
+
+// the sizes of card there are, in blocks
+const CARD_BLOCKS: [usize; 3] = [0x1000, 0x2000, 0x4000];
+
+// blocks compared when pairing by ECC; enough that two different dumps won't agree on them all
+const ECC_SAMPLE: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalFile {
+    pub name: String,
+    pub size: u64,
+    // when it was last changed, as YYYY-MM-DD
+    pub date: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    // with the card's size in blocks
+    Nand(usize),
+    Spare(usize),
+    Manifest,
+    Other,
+}
+
+pub fn classify(file: &LocalFile) -> Kind {
+    if file.name.ends_with(&format!(".{MANIFEST_EXT}")) {
+        return Kind::Manifest;
+    }
+    let size = file.size as usize;
+    match CARD_BLOCKS
+        .iter()
+        .find(|&&b| size == b * BLOCK_SIZE || size == b * SPARE_SIZE)
+    {
+        Some(&b) if size == b * BLOCK_SIZE => Kind::Nand(b),
+        Some(&b) => Kind::Spare(b),
+        None => Kind::Other,
+    }
+}
+
+// what a manifest says: the files it lists, and the console they came from
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ManifestInfo {
+    pub names: Vec<String>,
+    pub bbid: Option<u32>,
+}
+
+impl ManifestInfo {
+    pub fn parse(text: &str) -> Result<Self> {
+        let bbid = text
+            .lines()
+            .find_map(|l| l.trim().strip_prefix(SOURCE_BBID_TAG))
+            .map(|b| {
+                u32::from_str_radix(b.trim().trim_start_matches("0x"), 16)
+                    .map_err(|_| anyhow!("'{}' isn't a BBID", b.trim()))
+            })
+            .transpose()?;
+        let names = text
+            .lines()
+            .filter(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+            .map(|l| {
+                let (_, name) = parse_sum_line(l)?;
+                Ok(Path::new(&name)
+                    .file_name()
+                    .map_or(name.clone(), |n| n.to_string_lossy().into_owned()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { names, bbid })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pairing {
+    Manifest,
+    Ecc,
+    // a manifest lists the NAND alone, so there's no spare to look for
+    NandOnly,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dump {
+    pub nand: String,
+    pub spare: Option<String>,
+    // the manifest and CRC sidecar that go with it
+    pub sidecars: Vec<String>,
+    pub bbid: Option<u32>,
+    pub date: String,
+    pub pairing: Pairing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Survey {
+    pub dumps: Vec<Dump>,
+    // files left where they are, with why
+    pub left: Vec<(String, String)>,
+}
+
+// pairs the NANDs in `files` with their spares: by the manifests first (`manifests` is what each
+// one in `files` says), then by `ecc_matches`, which says whether a spare's ECC fits a NAND's data
+pub fn survey(
+    files: &[LocalFile],
+    manifests: &BTreeMap<String, ManifestInfo>,
+    ecc_matches: &dyn Fn(&str, &str) -> bool,
+) -> Survey {
+    let kinds = files
+        .iter()
+        .map(|f| (f.name.as_str(), (classify(f), f)))
+        .collect::<BTreeMap<_, _>>();
+    let nands = kinds
+        .iter()
+        .filter_map(|(&n, (k, _))| matches!(k, Kind::Nand(_)).then_some(n))
+        .collect::<Vec<_>>();
+    let spares = kinds
+        .iter()
+        .filter_map(|(&n, (k, _))| matches!(k, Kind::Spare(_)).then_some(n))
+        .collect::<Vec<_>>();
+    let blocks = |name: &str| match kinds[name].0 {
+        Kind::Nand(b) | Kind::Spare(b) => b,
+        _ => 0,
+    };
+
+    let mut survey = Survey::default();
+    let mut claimed = BTreeSet::new();
+
+    // what each manifest pairs, and which manifests mention each file
+    let mut mentions = BTreeMap::<&str, Vec<&str>>::new();
+    for (manifest, info) in manifests {
+        for name in info.names.iter().filter(|n| kinds.contains_key(n.as_str())) {
+            mentions.entry(name).or_default().push(manifest);
+        }
+    }
+    for (manifest, info) in manifests {
+        let listed = |wanted: fn(&Kind) -> bool| {
+            info.names
+                .iter()
+                .filter(|n| kinds.get(n.as_str()).is_some_and(|(k, _)| wanted(k)))
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+        };
+        let listed_nands = listed(|k| matches!(k, Kind::Nand(_)));
+        let listed_spares = listed(|k| matches!(k, Kind::Spare(_)));
+        let (nand, spare) = match (&listed_nands[..], &listed_spares[..]) {
+            ([], _) => continue,
+            (&[nand], []) => (nand, None),
+            (&[nand], &[spare]) => (nand, Some(spare)),
+            _ => {
+                for name in listed_nands.iter().chain(&listed_spares) {
+                    claimed.insert(name.to_string());
+                    leave(
+                        &mut survey,
+                        name,
+                        format!("{manifest} lists more than one dump"),
+                    );
+                }
+                continue;
+            }
+        };
+        let shared = [Some(nand), spare]
+            .into_iter()
+            .flatten()
+            .find(|n| mentions[n].len() > 1);
+        if let Some(shared) = shared {
+            for name in [Some(nand), spare].into_iter().flatten() {
+                if claimed.insert(name.to_string()) {
+                    leave(
+                        &mut survey,
+                        name,
+                        format!("more than one manifest lists {shared}"),
+                    );
+                }
+            }
+            continue;
+        }
+        if spare.is_some_and(|s| blocks(s) != blocks(nand)) {
+            for name in [Some(nand), spare].into_iter().flatten() {
+                claimed.insert(name.to_string());
+                leave(
+                    &mut survey,
+                    name,
+                    format!("{manifest} pairs a NAND and spare of different sizes"),
+                );
+            }
+            continue;
+        }
+        claimed.insert(nand.to_string());
+        claimed.extend(spare.map(str::to_string));
+        let mut sidecars = vec![manifest.clone()];
+        sidecars.extend(crcs_of(nand, &kinds));
+        survey.dumps.push(Dump {
+            nand: nand.to_string(),
+            spare: spare.map(str::to_string),
+            sidecars,
+            bbid: info.bbid,
+            date: kinds
+                .get(manifest.as_str())
+                .map_or(&kinds[nand].1.date, |(_, f)| &f.date)
+                .clone(),
+            pairing: if spare.is_some() {
+                Pairing::Manifest
+            } else {
+                Pairing::NandOnly
+            },
+        });
+    }
+
+    // the rest by ECC, where exactly one spare fits a NAND and no other NAND
+    let (nands, spares) = (unclaimed(&nands, &claimed), unclaimed(&spares, &claimed));
+    let fits = |nand: &str| {
+        spares
+            .iter()
+            .copied()
+            .filter(|s| blocks(s) == blocks(nand) && ecc_matches(nand, s))
+            .collect::<Vec<_>>()
+    };
+    let candidates = nands.iter().map(|&n| (n, fits(n))).collect::<Vec<_>>();
+    for (nand, fitting) in &candidates {
+        match fitting[..] {
+            [] => leave(&mut survey, nand, "no spare's ECC matches it".to_string()),
+            [spare]
+                if candidates
+                    .iter()
+                    .filter(|(_, f)| f.contains(&spare))
+                    .count()
+                    > 1 =>
+            {
+                leave(
+                    &mut survey,
+                    nand,
+                    format!("{spare} matches it, but other NANDs too"),
+                )
+            }
+            [spare] => {
+                claimed.insert(spare.to_string());
+                survey.dumps.push(Dump {
+                    nand: nand.to_string(),
+                    spare: Some(spare.to_string()),
+                    sidecars: crcs_of(nand, &kinds),
+                    bbid: None,
+                    date: kinds[nand].1.date.clone(),
+                    pairing: Pairing::Ecc,
+                });
+            }
+            _ => leave(
+                &mut survey,
+                nand,
+                format!("the ECC of {} spares matches it", fitting.len()),
+            ),
+        }
+    }
+    for spare in spares.iter().filter(|s| !claimed.contains(**s)) {
+        let why = if candidates.iter().any(|(_, f)| f.contains(spare)) {
+            "it can't be told which NAND it goes with"
+        } else {
+            "no NAND's data matches its ECC"
+        };
+        leave(&mut survey, spare, why.to_string());
+    }
+    survey.dumps.sort_by(|a, b| a.nand.cmp(&b.nand));
+    survey.left.sort();
+    survey
+}
+
+fn unclaimed<'a>(names: &[&'a str], claimed: &BTreeSet<String>) -> Vec<&'a str> {
+    names
+        .iter()
+        .copied()
+        .filter(|n| !claimed.contains(*n))
+        .collect()
+}
+
+fn leave(survey: &mut Survey, name: &str, why: String) {
+    survey.left.push((name.to_string(), why));
+}
+
+fn crcs_of(nand: &str, kinds: &BTreeMap<&str, (Kind, &LocalFile)>) -> Vec<String> {
+    let crcs = format!("{nand}.crcs");
+    kinds
+        .contains_key(crcs.as_str())
+        .then_some(crcs)
+        .into_iter()
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Move {
+    pub from: String,
+    pub to: String,
+}
+
+// where each dump's files go, relative to the directory: archive/<BBID>/<date>/, or <date>-2 and
+// so on if that's taken (by `exists`, or by an earlier dump); dumps without a BBID aren't moved
+pub fn layout(dumps: &[Dump], exists: &dyn Fn(&str) -> bool) -> Vec<Move> {
+    let mut moves = vec![];
+    let mut planned = BTreeSet::new();
+    for dump in dumps {
+        let Some(bbid) = dump.bbid else {
+            continue;
+        };
+        let files = [Some(&dump.nand), dump.spare.as_ref()]
+            .into_iter()
+            .flatten()
+            .chain(&dump.sidecars)
+            .collect::<Vec<_>>();
+        let dir = (1..)
+            .map(|n| match n {
+                1 => format!("{ARCHIVE_DIR}/{bbid:08X}/{}", dump.date),
+                n => format!("{ARCHIVE_DIR}/{bbid:08X}/{}-{n}", dump.date),
+            })
+            .find(|dir| !planned.contains(dir) && !exists(dir))
+            .unwrap_or_default();
+        for file in files {
+            moves.push(Move {
+                from: file.clone(),
+                to: format!("{dir}/{file}"),
+            });
+        }
+        planned.insert(dir);
+    }
+    moves
+}
+
+// whether the spare's ECC fits the NAND's data, over a sample of the blocks that hold any
+pub fn ecc_fits(nand: &[u8], spare: &[u8]) -> bool {
+    let blocks = nand
+        .chunks_exact(BLOCK_SIZE)
+        .zip(spare.chunks_exact(SPARE_SIZE))
+        .filter(|(data, _)| data[..0x200].iter().any(|&b| b != 0xFF))
+        .collect::<Vec<_>>();
+    let step = (blocks.len() / ECC_SAMPLE).max(1);
+    !blocks.is_empty()
+        && blocks
+            .iter()
+            .step_by(step)
+            .all(|(data, spare)| ecc_check(data, spare) == EccCheck::Clean)
+}
+
+// the files directly in `dir`
+pub fn list(dir: &Path) -> Result<Vec<LocalFile>> {
+    let mut files = vec![];
+    for entry in read_dir(dir).map_err(|e| anyhow!("{}: {e}", dir.display()))? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if !meta.is_file() {
+            continue;
+        }
+        files.push(LocalFile {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            size: meta.len(),
+            date: meta
+                .modified()
+                .map(|t| DateTime::<Local>::from(t).format("%Y-%m-%d").to_string())
+                .unwrap_or_else(|_| Local::now().format("%Y-%m-%d").to_string()),
+        });
+    }
+    Ok(files)
+}
+
+// surveys `dir` as it is on disk
+pub fn survey_dir(dir: &Path) -> Result<Survey> {
+    let files = list(dir)?;
+    let mut manifests = BTreeMap::new();
+    for file in files.iter().filter(|f| classify(f) == Kind::Manifest) {
+        let path = dir.join(&file.name);
+        // a manifest that can't be read just doesn't pair anything
+        if let Ok(info) = read_to_string(&path)
+            .map_err(Into::into)
+            .and_then(|t| ManifestInfo::parse(&t))
+        {
+            manifests.insert(file.name.clone(), info);
+        }
+    }
+    let ecc_matches = |nand: &str, spare: &str| match (read(dir.join(nand)), read(dir.join(spare)))
+    {
+        (Ok(n), Ok(s)) => ecc_fits(&n, &s),
+        _ => false,
+    };
+    Ok(survey(&files, &manifests, &ecc_matches))
+}
+
+// moves a file within `dir`, never over another, logging it first so it can be undone
+pub fn move_file(dir: &Path, mv: &Move) -> Result<()> {
+    let (from, to) = (dir.join(&mv.from), dir.join(&mv.to));
+    if to.exists() {
+        bail!("{} is already there; not moving {} over it", mv.to, mv.from);
+    }
+    if let Some(parent) = to.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(MOVE_LOG))?;
+    writeln!(log, "{}\t{}\t{}", Local::now().to_rfc3339(), mv.from, mv.to)?;
+    log.sync_data()?;
+    rename(&from, &to).map_err(|e| anyhow!("{} -> {}: {e}", mv.from, mv.to))
+}
+
+// the moves the log records, oldest first
+pub fn logged_moves(dir: &Path) -> Result<Vec<Move>> {
+    let text = match read_to_string(dir.join(MOVE_LOG)) {
+        Ok(t) => t,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(text
+        .lines()
+        .filter_map(|l| {
+            let mut fields = l.split('\t').skip(1);
+            Some(Move {
+                from: fields.next()?.to_string(),
+                to: fields.next()?.to_string(),
+            })
+        })
+        .collect())
+}
+
+// puts back everything the log records, newest first, and clears the log; a file that's no
+// longer where it was moved to, or whose old name has been taken since, is left
+pub fn undo(dir: &Path) -> Result<(usize, Vec<String>)> {
+    let mut undone = 0;
+    let mut skipped = vec![];
+    for mv in logged_moves(dir)?.iter().rev() {
+        let (from, to) = (dir.join(&mv.from), dir.join(&mv.to));
+        if !to.exists() || from.exists() {
+            skipped.push(mv.to.clone());
+            continue;
+        }
+        rename(&to, &from).map_err(|e| anyhow!("{} -> {}: {e}", mv.to, mv.from))?;
+        undone += 1;
+    }
+    std::fs::remove_file(dir.join(MOVE_LOG))?;
+    Ok((undone, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn organize() -> Result<()> {
+        let file = |name: &str, size: usize, date: &str| LocalFile {
+            name: name.to_string(),
+            size: size as u64,
+            date: date.to_string(),
+        };
+        let nand = 0x1000 * BLOCK_SIZE;
+        let spare = 0x1000 * SPARE_SIZE;
+        let big_spare = 0x2000 * SPARE_SIZE;
+
+        if classify(&file("nand.bin", nand, "")) != Kind::Nand(0x1000)
+            || classify(&file("x", big_spare, "")) != Kind::Spare(0x2000)
+            || classify(&file("nand.bin.sha256", 100, "")) != Kind::Manifest
+            || classify(&file("notes.txt", 100, "")) != Kind::Other
+        {
+            bail!("files were classified wrongly");
+        }
+
+        let manifest = ManifestInfo::parse(&format!(
+            "{SOURCE_BBID_TAG} 1234ABCD\n{}  dumps/nand.bin\n{}  spare.bin\n",
+            "0".repeat(64),
+            "1".repeat(64)
+        ))?;
+        if manifest.bbid != Some(0x1234ABCD) || manifest.names != ["nand.bin", "spare.bin"] {
+            bail!("the manifest was read as {manifest:?}");
+        }
+
+        // a day's dumping: one with a manifest, two pairs only ECC can match (one of them without
+        // a manifest for its console), a spare two NANDs fit, and leftovers
+        let files = [
+            file("nand.bin", nand, "2024-05-01"),
+            file("spare.bin", spare, "2024-05-01"),
+            file("nand.bin.sha256", 200, "2024-05-02"),
+            file("nand.bin.crcs", 300, "2024-05-01"),
+            file("nand(1).bin", nand, "2024-05-03"),
+            file("spare(1).bin", spare, "2024-05-03"),
+            file("a.bin", nand, "2024-05-03"),
+            file("b.bin", nand, "2024-05-03"),
+            file("ab-spare.bin", spare, "2024-05-03"),
+            file("lonely-spare.bin", spare, "2024-05-03"),
+            file("dev-nand.bin", 0x2000 * BLOCK_SIZE, "2024-05-04"),
+            file("notes.txt", 10, "2024-05-04"),
+        ];
+        let manifests = BTreeMap::from([(
+            "nand.bin.sha256".to_string(),
+            ManifestInfo {
+                names: vec!["nand.bin".to_string(), "spare.bin".to_string()],
+                bbid: Some(0x1234ABCD),
+            },
+        )]);
+        // the ECC fits of each NAND; the spare with the manifest fits everything, as a blank card's would
+        let fitting = [
+            ("nand(1).bin", "spare(1).bin"),
+            ("a.bin", "ab-spare.bin"),
+            ("b.bin", "ab-spare.bin"),
+        ];
+        let ecc = |n: &str, s: &str| s == "spare.bin" || fitting.contains(&(n, s));
+        let found = survey(&files, &manifests, &ecc);
+
+        let expected = [
+            Dump {
+                nand: "nand(1).bin".to_string(),
+                spare: Some("spare(1).bin".to_string()),
+                sidecars: vec![],
+                bbid: None,
+                date: "2024-05-03".to_string(),
+                pairing: Pairing::Ecc,
+            },
+            Dump {
+                nand: "nand.bin".to_string(),
+                spare: Some("spare.bin".to_string()),
+                sidecars: vec!["nand.bin.sha256".to_string(), "nand.bin.crcs".to_string()],
+                bbid: Some(0x1234ABCD),
+                date: "2024-05-02".to_string(),
+                pairing: Pairing::Manifest,
+            },
+        ];
+        if found.dumps != expected {
+            bail!("the dumps were paired as {:#?}", found.dumps);
+        }
+        let left = found
+            .left
+            .iter()
+            .map(|(n, _)| n.as_str())
+            .collect::<Vec<_>>();
+        if left
+            != [
+                "a.bin",
+                "ab-spare.bin",
+                "b.bin",
+                "dev-nand.bin",
+                "lonely-spare.bin",
+            ]
+        {
+            bail!("the files left were {:?}", found.left);
+        }
+
+        // a file two manifests claim is left, as are both pairings
+        let mut twice = manifests.clone();
+        twice.insert(
+            "other.sha256".to_string(),
+            manifests["nand.bin.sha256"].clone(),
+        );
+        let found = survey(&files[..4], &twice, &|_, _| false);
+        if !found.dumps.is_empty() || found.left.len() != 2 {
+            bail!("a NAND two manifests list was paired: {found:?}");
+        }
+
+        // a second dump of the same console on the same day goes beside the first
+        let mut again = expected[1].clone();
+        again.nand = "nand2.bin".to_string();
+        again.spare = None;
+        again.sidecars = vec![];
+        let dumps = [expected[0].clone(), expected[1].clone(), again];
+        let moves = layout(&dumps, &|d| d == "archive/1234ABCD/2024-05-02");
+        let to = moves
+            .iter()
+            .map(|m| (m.from.as_str(), m.to.as_str()))
+            .collect::<Vec<_>>();
+        if to
+            != [
+                ("nand.bin", "archive/1234ABCD/2024-05-02-2/nand.bin"),
+                ("spare.bin", "archive/1234ABCD/2024-05-02-2/spare.bin"),
+                (
+                    "nand.bin.sha256",
+                    "archive/1234ABCD/2024-05-02-2/nand.bin.sha256",
+                ),
+                (
+                    "nand.bin.crcs",
+                    "archive/1234ABCD/2024-05-02-2/nand.bin.crcs",
+                ),
+                ("nand2.bin", "archive/1234ABCD/2024-05-02-3/nand2.bin"),
+            ]
+        {
+            bail!("the dumps were laid out as {to:#?}");
+        }
+
+        // moves never go over a file, and are put back from the log
+        let dir = TestDir::new("organize")?;
+        std::fs::write(dir.join("nand.bin"), b"nand")?;
+        std::fs::write(dir.join("spare.bin"), b"spare")?;
+        let mv = |from: &str, to: &str| Move {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        move_file(&dir, &mv("nand.bin", "archive/X/nand.bin"))?;
+        if move_file(&dir, &mv("spare.bin", "archive/X/nand.bin")).is_ok() {
+            bail!("a move went over another file");
+        }
+        move_file(&dir, &mv("spare.bin", "archive/X/spare.bin"))?;
+        if logged_moves(&dir)?.len() != 2 || dir.join("nand.bin").exists() {
+            bail!("the moves weren't made and logged");
+        }
+        let (undone, skipped) = undo(&dir)?;
+        if undone != 2
+            || !skipped.is_empty()
+            || read(dir.join("nand.bin"))? != b"nand"
+            || read(dir.join("spare.bin"))? != b"spare"
+            || dir.join(MOVE_LOG).exists()
+        {
+            bail!("undoing the moves gave {undone} undone, {skipped:?} skipped");
+        }
+
+        // a spare's ECC fits the data it was made for, and not other data or a blank card
+        let mut data = vec![0xFF; 4 * BLOCK_SIZE];
+        data[BLOCK_SIZE..BLOCK_SIZE + 4].copy_from_slice(b"SKSA");
+        let mut spares = vec![0xFF; 4 * SPARE_SIZE];
+        crate::ecc::page_ecc(
+            &data[BLOCK_SIZE..BLOCK_SIZE + 0x200],
+            &mut spares[SPARE_SIZE..],
+        )?;
+        let mut other = data.clone();
+        other[BLOCK_SIZE] = b'X';
+        if !ecc_fits(&data, &spares)
+            || ecc_fits(&other, &spares)
+            || ecc_fits(&[0xFF; BLOCK_SIZE], &spares)
+        {
+            bail!("spares were matched to NANDs by ECC wrongly");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::calc::PAGE_SIZE;
+use crate::ecc::EccCheck;
+use crate::fs::BLOCK_SIZE;
+use crate::preview::hex_dump_at;
+use crate::spare::{describe_spare, page_ecc_check};
+
+// 'page': one page of a block, for questions a block is too coarse for ("is only the first page
+// of this block corrupt?"). The console only reads whole blocks, so a page is cut out of a read
+// of its block, and labelled as such; and a block's spare data only holds the first page's ECC,
+// so that's the only page that can be checked.
+
+pub const PAGES_PER_BLOCK: usize = BLOCK_SIZE / PAGE_SIZE;
+
+// a block and page as typed (decimal, or hex with 0x), checked against a card of `card_blocks`
+pub fn parse_page(block: &str, page: &str, card_blocks: u32) -> Result<(u32, usize)> {
+    let block =
+        parse_int::parse::<u32>(block).map_err(|_| anyhow!("'{block}' isn't a block number"))?;
+    let page =
+        parse_int::parse::<usize>(page).map_err(|_| anyhow!("'{page}' isn't a page number"))?;
+    if block >= card_blocks {
+        bail!(
+            "There's no block {block:#X}; the card has {card_blocks:#X} blocks, numbered 0 to {:#X}",
+            card_blocks.saturating_sub(1)
+        );
+    }
+    if page >= PAGES_PER_BLOCK {
+        bail!(
+            "There's no page {page}; a block has {PAGES_PER_BLOCK}, numbered 0 to {}",
+            PAGES_PER_BLOCK - 1
+        );
+    }
+    Ok((block, page))
+}
+
+// page `page` of a block's data
+pub fn slice_page(block: &[u8], page: usize) -> &[u8] {
+    &block[page * PAGE_SIZE..(page + 1) * PAGE_SIZE]
+}
+
+// where page `page` of block `block` starts in a NAND dump
+pub fn page_offset(block: u32, page: usize) -> usize {
+    block as usize * BLOCK_SIZE + page * PAGE_SIZE
+}
+
+// what 'page' prints: the page as a hex dump at its place in the NAND, and what the spare data
+// says about it
+pub fn render_page(block: u32, page: usize, data: &[u8], spare: &[u8]) -> String {
+    let offset = page_offset(block, page);
+    let mut out = format!(
+        "Block {block:#X}, page {page} of {PAGES_PER_BLOCK}, at NAND offset {offset:#X}; cut from a read of the whole block, as the console has no page reads\n"
+    );
+    out += &hex_dump_at(slice_page(data, page), offset);
+    match page_ecc_check(page, slice_page(data, page), spare) {
+        Some(check) => {
+            out += "Spare data (the block's, which is the first page's):\n";
+            for line in describe_spare(spare) {
+                let _ = writeln!(out, "  {line}");
+            }
+            let _ = writeln!(
+                out,
+                "  ECC check    {}",
+                match check {
+                    EccCheck::Clean => "clean",
+                    EccCheck::Correctable => "one bit off, correctable",
+                    EccCheck::Uncorrectable => "fails, uncorrectable",
+                }
+            );
+        }
+        None => out += "Spare data: only the first page's is kept, in the block's, so this page has no ECC to check\n",
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages() -> Result<()> {
+        use crate::ecc::page_ecc;
+
+        // pages are checked against the size of card, for both sizes of card
+        for (card, last) in [(0x1000, "0xFFF"), (0x2000, "0x1FFF")] {
+            if parse_page(last, "31", card)? != (card - 1, 31)
+                || parse_page("0", "0x1F", card)? != (0, 31)
+            {
+                bail!("the last block and page of a {card:#X}-block card weren't taken");
+            }
+            let past = format!("{card:#X}");
+            match parse_page(&past, "0", card) {
+                Err(e) if e.to_string().contains(&format!("numbered 0 to {last}")) => {}
+                other => bail!("block {past} of a {card:#X}-block card gave {other:?}"),
+            }
+            if parse_page("0", "32", card).is_ok()
+                || parse_page("x", "0", card).is_ok()
+                || parse_page("0", "-1", card).is_ok()
+            {
+                bail!("a page past the end of a block, or a bad number, was taken");
+            }
+        }
+        if parse_page("0x1000", "0", 0x2000).is_err() {
+            bail!("a block only the larger card has wasn't taken on it");
+        }
+
+        // each page is its own 0x200 bytes, at its place in the NAND
+        let mut block = (0..BLOCK_SIZE)
+            .map(|i| (i / PAGE_SIZE) as u8)
+            .collect::<Vec<_>>();
+        if slice_page(&block, 31) != [31; PAGE_SIZE] || page_offset(0x10, 5) != 0x40A00 {
+            bail!(
+                "page 31 was cut out wrong, or page 5 of block 0x10 placed at {:#X}",
+                page_offset(0x10, 5)
+            );
+        }
+        let mut spare = [0xFF; 0x10];
+        page_ecc(&block, &mut spare)?;
+        let shown = render_page(0x10, 0, &block, &spare);
+        if !shown.contains("cut from a read of the whole block")
+            || !shown.contains("\n00040000  00 00")
+            || !shown.contains("ECC check    clean")
+        {
+            bail!("page 0 was shown as:\n{shown}");
+        }
+        // only the first page can be checked: a bit off in it is caught, and no other page is checked
+        block[3] ^= 0x10;
+        if !render_page(0x10, 0, &block, &spare).contains("one bit off") {
+            bail!("a bit off in page 0 wasn't caught");
+        }
+        let shown = render_page(0x10, 5, &block, &spare);
+        if !shown.contains("\n00040a00  05 05")
+            || !shown.contains("no ECC to check")
+            || shown.contains("ECC check")
+        {
+            bail!("page 5 was shown as:\n{shown}");
+        }
+        // the spare data's fields
+        spare[5] = 0;
+        spare[0] = 0x12;
+        let fields = describe_spare(&spare);
+        if !fields[0].contains("12 ff ff (in the SA chain)")
+            || !fields[1].contains("00 (marked bad)")
+        {
+            bail!("the spare data was described as {fields:?}");
+        }
+        Ok(())
+    }
+}
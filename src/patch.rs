@@ -0,0 +1,393 @@
+use std::fs::read;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::hashing::crc32;
+
+// Binary patches, as fixes are passed around: IPS, which is just records of bytes to write (with
+// no way of telling whether it's being applied to the right file), and BPS, which copies from the
+// source and the target as it goes and carries CRCs of both, so a patch applied to the wrong file
+// or giving the wrong result is caught.
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: u32 = 0x454F46;
+const BPS_MAGIC: &[u8] = b"BPS1";
+// the source, target and patch CRCs
+const BPS_FOOTER: usize = 12;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpsRecord {
+    Data { offset: u32, data: Vec<u8> },
+    Fill { offset: u32, len: u16, value: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BpsAction {
+    SourceRead(usize),
+    TargetRead(Vec<u8>),
+    SourceCopy { len: usize, offset: i64 },
+    TargetCopy { len: usize, offset: i64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bps {
+    pub source_size: usize,
+    pub target_size: usize,
+    pub metadata: Vec<u8>,
+    pub actions: Vec<BpsAction>,
+    pub source_crc: u32,
+    pub target_crc: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Patch {
+    Ips {
+        records: Vec<IpsRecord>,
+        // the length to cut the result to, from the extension some tools write after the end
+        truncate: Option<u32>,
+    },
+    Bps(Bps),
+}
+
+// reads a big-endian number of `len` bytes from the front of `data`
+fn take_be(data: &mut &[u8], len: usize) -> Result<u32> {
+    if data.len() < len {
+        bail!("the patch ends in the middle of a record");
+    }
+    let (bytes, rest) = data.split_at(len);
+    *data = rest;
+    Ok(bytes.iter().fold(0, |n, &b| n << 8 | b as u32))
+}
+
+fn parse_ips(mut data: &[u8]) -> Result<Patch> {
+    let mut records = vec![];
+    loop {
+        let offset = take_be(&mut data, 3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        match take_be(&mut data, 2)? {
+            0 => records.push(IpsRecord::Fill {
+                offset,
+                len: take_be(&mut data, 2)? as u16,
+                value: take_be(&mut data, 1)? as u8,
+            }),
+            len => {
+                let len = len as usize;
+                if data.len() < len {
+                    bail!("the patch ends in the middle of a record");
+                }
+                records.push(IpsRecord::Data {
+                    offset,
+                    data: data[..len].to_vec(),
+                });
+                data = &data[len..];
+            }
+        }
+    }
+    let truncate = match data.len() {
+        0 => None,
+        3 => Some(take_be(&mut data, 3)?),
+        n => bail!("{n} unexpected bytes after the end of the patch"),
+    };
+    Ok(Patch::Ips { records, truncate })
+}
+
+// BPS's variable-length numbers: 7 bits a byte, least significant first, with the top bit
+// marking the last byte
+fn take_varint(data: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    let mut shift = 1u64;
+    loop {
+        let (&b, rest) = data
+            .split_first()
+            .ok_or_else(|| anyhow!("the patch ends in the middle of a number"))?;
+        *data = rest;
+        value = (b as u64 & 0x7F)
+            .checked_mul(shift)
+            .and_then(|v| v.checked_add(value))
+            .ok_or_else(|| anyhow!("a number in the patch is too large"))?;
+        if b & 0x80 != 0 {
+            return Ok(value);
+        }
+        shift = shift
+            .checked_shl(7)
+            .filter(|&s| s != 0)
+            .ok_or_else(|| anyhow!("a number in the patch is too large"))?;
+        value += shift;
+    }
+}
+
+fn take_offset(data: &mut &[u8]) -> Result<i64> {
+    let n = take_varint(data)?;
+    let magnitude = (n >> 1) as i64;
+    Ok(if n & 1 != 0 { -magnitude } else { magnitude })
+}
+
+fn parse_bps(patch: &[u8]) -> Result<Patch> {
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER {
+        bail!("the patch is too short to be BPS");
+    }
+    let (body, footer) = patch.split_at(patch.len() - BPS_FOOTER);
+    let crc = |i: usize| u32::from_le_bytes(footer[i * 4..i * 4 + 4].try_into().unwrap());
+    if crc32(&patch[..patch.len() - 4]) != crc(2) {
+        bail!("the patch is damaged (its own CRC doesn't match)");
+    }
+
+    let mut data = &body[BPS_MAGIC.len()..];
+    let source_size = take_varint(&mut data)? as usize;
+    let target_size = take_varint(&mut data)? as usize;
+    let metadata_size = take_varint(&mut data)? as usize;
+    if data.len() < metadata_size {
+        bail!("the patch's metadata runs past its end");
+    }
+    let metadata = data[..metadata_size].to_vec();
+    data = &data[metadata_size..];
+
+    let mut actions = vec![];
+    while !data.is_empty() {
+        let n = take_varint(&mut data)?;
+        let len = (n >> 2) as usize + 1;
+        actions.push(match n & 3 {
+            0 => BpsAction::SourceRead(len),
+            1 => {
+                if data.len() < len {
+                    bail!("the patch ends in the middle of its data");
+                }
+                let bytes = data[..len].to_vec();
+                data = &data[len..];
+                BpsAction::TargetRead(bytes)
+            }
+            2 => BpsAction::SourceCopy {
+                len,
+                offset: take_offset(&mut data)?,
+            },
+            _ => BpsAction::TargetCopy {
+                len,
+                offset: take_offset(&mut data)?,
+            },
+        });
+    }
+    Ok(Patch::Bps(Bps {
+        source_size,
+        target_size,
+        metadata,
+        actions,
+        source_crc: crc(0),
+        target_crc: crc(1),
+    }))
+}
+
+fn apply_ips(records: &[IpsRecord], truncate: Option<u32>, source: &[u8]) -> Vec<u8> {
+    let mut target = source.to_vec();
+    // writing past the end grows the file, with zeroes in any gap
+    let mut put = |offset: u32, bytes: &mut dyn Iterator<Item = u8>| {
+        for (i, b) in bytes.enumerate() {
+            let at = offset as usize + i;
+            if at >= target.len() {
+                target.resize(at + 1, 0);
+            }
+            target[at] = b;
+        }
+    };
+    for record in records {
+        match record {
+            IpsRecord::Data { offset, data } => put(*offset, &mut data.iter().copied()),
+            IpsRecord::Fill { offset, len, value } => {
+                put(*offset, &mut std::iter::repeat_n(*value, *len as usize))
+            }
+        }
+    }
+    if let Some(len) = truncate {
+        target.truncate(len as usize);
+    }
+    target
+}
+
+// moves a relative offset, refusing one that goes outside `0..limit`
+fn seek(position: usize, offset: i64, limit: usize) -> Result<usize> {
+    position
+        .checked_add_signed(offset as isize)
+        .filter(|&p| p < limit)
+        .ok_or_else(|| anyhow!("the patch copies from outside the file"))
+}
+
+fn apply_bps(bps: &Bps, source: &[u8]) -> Result<Vec<u8>> {
+    if source.len() != bps.source_size || crc32(source) != bps.source_crc {
+        bail!(
+            "the file isn't the one the patch is for: it expects {:#X} bytes with CRC {:08X}, not {:#X} bytes with CRC {:08X}",
+            bps.source_size,
+            bps.source_crc,
+            source.len(),
+            crc32(source)
+        );
+    }
+    let mut target: Vec<u8> = Vec::with_capacity(bps.target_size);
+    let (mut source_at, mut target_at) = (0usize, 0usize);
+    for action in &bps.actions {
+        match action {
+            BpsAction::SourceRead(len) => {
+                let at = target.len();
+                let bytes = source
+                    .get(at..at + len)
+                    .ok_or_else(|| anyhow!("the patch reads past the end of the file"))?;
+                target.extend_from_slice(bytes);
+            }
+            BpsAction::TargetRead(bytes) => target.extend_from_slice(bytes),
+            BpsAction::SourceCopy { len, offset } => {
+                source_at = seek(source_at, *offset, source.len())?;
+                let bytes = source
+                    .get(source_at..source_at + len)
+                    .ok_or_else(|| anyhow!("the patch copies from past the end of the file"))?;
+                target.extend_from_slice(bytes);
+                source_at += len;
+            }
+            // byte by byte, as the copy can overlap what it's writing
+            BpsAction::TargetCopy { len, offset } => {
+                target_at = seek(target_at, *offset, target.len())?;
+                for _ in 0..*len {
+                    let b = *target
+                        .get(target_at)
+                        .ok_or_else(|| anyhow!("the patch copies from past what it's written"))?;
+                    target.push(b);
+                    target_at += 1;
+                }
+            }
+        }
+        if target.len() > bps.target_size {
+            bail!("the patch writes past the size it gives for the result");
+        }
+    }
+    if target.len() != bps.target_size || crc32(&target) != bps.target_crc {
+        bail!("the patched file isn't what the patch says it should be (its size or CRC is wrong)");
+    }
+    Ok(target)
+}
+
+impl Patch {
+    pub fn load(path: &str) -> Result<Self> {
+        read(path)
+            .map_err(Into::into)
+            .and_then(|data| Self::parse(&data))
+            .map_err(|e| anyhow!("{path}: {e}"))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if let Some(rest) = data.strip_prefix(IPS_MAGIC) {
+            parse_ips(rest)
+        } else if data.starts_with(BPS_MAGIC) {
+            parse_bps(data)
+        } else {
+            bail!("not an IPS or BPS patch")
+        }
+    }
+
+    pub fn format(&self) -> &'static str {
+        match self {
+            Patch::Ips { .. } => "IPS",
+            Patch::Bps(_) => "BPS",
+        }
+    }
+
+    // whether applying it checks that it's being applied to the right file
+    pub fn checked(&self) -> bool {
+        matches!(self, Patch::Bps(_))
+    }
+
+    pub fn apply(&self, source: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Patch::Ips { records, truncate } => Ok(apply_ips(records, *truncate, source)),
+            Patch::Bps(bps) => apply_bps(bps, source),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_patches() -> Result<()> {
+        let source = b"The quick brown fox jumps over the lazy dog".to_vec();
+
+        // overwrites "quick", fills past the end (growing the file, with a gap) and adds a record
+        let ips = [
+            b"PATCH".as_slice(),
+            &[0x00, 0x00, 0x04, 0x00, 0x05],
+            b"sleek",
+            &[0x00, 0x00, 0x2D, 0x00, 0x00, 0x00, 0x03, b'!'],
+            b"EOF",
+        ]
+        .concat();
+        let mut expected = b"The sleek brown fox jumps over the lazy dog\0\0!!!".to_vec();
+        let patched = Patch::parse(&ips)?.apply(&source)?;
+        if patched != expected {
+            bail!("the IPS patch gave {:?}", String::from_utf8_lossy(&patched));
+        }
+        // with the truncation extension
+        let truncated = [ips.as_slice(), &[0x00, 0x00, 0x09]].concat();
+        expected.truncate(9);
+        if Patch::parse(&truncated)?.apply(&source)? != expected {
+            bail!("the IPS patch wasn't truncated");
+        }
+        if Patch::parse(&ips[..ips.len() - 4]).is_ok() {
+            bail!("a cut-off IPS patch was accepted");
+        }
+
+        // copies "The ", writes "lazy ", copies "dog" from the source's end, then doubles the last
+        // three bytes with an overlapping target copy
+        let target = b"The lazy dogdogdog".to_vec();
+        let varint = |mut n: u64| {
+            let mut out = vec![];
+            loop {
+                let x = (n & 0x7F) as u8;
+                n >>= 7;
+                if n == 0 {
+                    out.push(0x80 | x);
+                    return out;
+                }
+                out.push(x);
+                n -= 1;
+            }
+        };
+        let action = |kind: u64, len: u64| varint((len - 1) << 2 | kind);
+        let mut bps = [
+            b"BPS1".as_slice(),
+            &varint(source.len() as u64),
+            &varint(target.len() as u64),
+            &varint(0),
+            &action(0, 4),
+            &action(1, 5),
+            b"lazy ",
+            &action(2, 3),
+            &varint(40 << 1),
+            &action(3, 6),
+            &varint(9 << 1),
+            &crc32(&source).to_le_bytes(),
+            &crc32(&target).to_le_bytes(),
+        ]
+        .concat();
+        bps.extend_from_slice(&crc32(&bps).to_le_bytes());
+
+        let patch = Patch::parse(&bps)?;
+        let patched = patch.apply(&source)?;
+        if patched != target {
+            bail!("the BPS patch gave {:?}", String::from_utf8_lossy(&patched));
+        }
+        if patch
+            .apply(b"The quick brown fox jumps over the lazy cat")
+            .is_ok()
+        {
+            bail!("a BPS patch was applied to the wrong file");
+        }
+        let mut damaged = bps.clone();
+        damaged[10] ^= 1;
+        if Patch::parse(&damaged).is_ok() {
+            bail!("a damaged BPS patch was accepted");
+        }
+        if Patch::parse(b"NOT A PATCH").is_ok() {
+            bail!("something that isn't a patch was accepted");
+        }
+        Ok(())
+    }
+}
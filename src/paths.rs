@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+// a path in a form that can be compared with others, whether or not the file exists yet;
+// if it doesn't, its directory is resolved instead, so symlinks and '..' still compare equal
+fn comparable(path: &str) -> PathBuf {
+    let p = Path::new(path);
+    let resolved = p.canonicalize().ok().or_else(|| {
+        let dir = match p.parent() {
+            Some(d) if !d.as_os_str().is_empty() => d,
+            _ => Path::new("."),
+        };
+        Some(dir.canonicalize().ok()?.join(p.file_name()?))
+    });
+    let resolved = resolved.unwrap_or_else(|| p.to_path_buf());
+    if cfg!(windows) {
+        PathBuf::from(resolved.to_string_lossy().to_lowercase())
+    } else {
+        resolved
+    }
+}
+
+// fails if any two of the (argument name, path) pairs refer to the same file
+pub fn check_distinct(paths: &[(&str, &str)]) -> Result<()> {
+    let resolved = paths.iter().map(|(_, p)| comparable(p)).collect::<Vec<_>>();
+    for (i, a) in resolved.iter().enumerate() {
+        if let Some(j) = resolved[i + 1..].iter().position(|b| b == a) {
+            bail!(
+                "'{}' and '{}' are the same file ({}); use a different file for each",
+                paths[i].0,
+                paths[i + 1 + j].0,
+                paths[i].1
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn distinct() -> Result<()> {
+        let dir = TestDir::new("paths")?;
+        std::fs::create_dir_all(dir.join("sub"))?;
+        let path = |p: &str| dir.join(p).to_string_lossy().into_owned();
+        std::fs::write(path("nand.bin"), [])?;
+
+        check_distinct(&[("nand", &path("nand.bin")), ("spare", &path("spare.bin"))])?;
+        check_distinct(&[])?;
+
+        // the same file however it's written, whether or not it exists yet
+        for (a, b) in [
+            ("nand.bin", "nand.bin"),
+            ("nand.bin", "sub/../nand.bin"),
+            ("spare.bin", "./sub/../spare.bin"),
+        ] {
+            match check_distinct(&[("nand", &path(a)), ("x", "other"), ("spare", &path(b))]) {
+                Ok(()) => bail!("{a} and {b} were taken as different files"),
+                Err(e)
+                    if e.to_string()
+                        .starts_with("'nand' and 'spare' are the same file") => {}
+                Err(e) => bail!("{a} and {b} were refused with '{e}'"),
+            }
+        }
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(path("nand.bin"), path("sub/link.bin"))?;
+            if check_distinct(&[
+                ("nand", &path("nand.bin")),
+                ("spare", &path("sub/link.bin")),
+            ])
+            .is_ok()
+            {
+                bail!("a link to the nand file was taken as a different file");
+            }
+        }
+        Ok(())
+    }
+}
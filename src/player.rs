@@ -0,0 +1,424 @@
+#![allow(non_snake_case)]
+
+use anyhow::Result;
+use bbrdb::GlobalHandle;
+use chrono::{DateTime, FixedOffset};
+
+/// The card's block counts and the sequence number of its current FS, as [`Player::CardStats`]
+/// gives them.
+pub use bbrdb::CardStats;
+
+/// The read-only operations that work the same on a real console and on a mounted dump;
+/// named after (and delegating to) the console's own calls.
+pub trait Player {
+    /// The console's BBID.
+    fn GetBBID(&self) -> Result<u32>;
+    /// Sets the console's LED.
+    fn SetLED(&self, value: u32) -> Result<()>;
+    /// The name and size of each file on the card.
+    fn ListFiles(&self) -> Result<Vec<(String, u32)>>;
+    /// The current FS block.
+    fn DumpCurrentFS(&self) -> Result<Vec<u8>>;
+    /// A file's contents, or None if there's no such file.
+    fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>>;
+    /// A block's data and spare data.
+    fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)>;
+    /// The card's block counts and the current FS's sequence number.
+    fn CardStats(&self) -> Result<CardStats>;
+}
+
+impl Player for GlobalHandle {
+    fn GetBBID(&self) -> Result<u32> {
+        GlobalHandle::GetBBID(self)
+    }
+
+    fn SetLED(&self, value: u32) -> Result<()> {
+        GlobalHandle::SetLED(self, value)
+    }
+
+    fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+        GlobalHandle::ListFiles(self)
+    }
+
+    fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+        GlobalHandle::DumpCurrentFS(self)
+    }
+
+    fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        GlobalHandle::ReadFile(self, name)
+    }
+
+    fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        GlobalHandle::ReadSingleBlock(self, blk)
+    }
+
+    fn CardStats(&self) -> Result<CardStats> {
+        GlobalHandle::CardStats(self)
+    }
+}
+
+// a borrowed player is one too, so a source can hold either a console (or dump) or a view over one
+impl<P: Player + ?Sized> Player for &P {
+    fn GetBBID(&self) -> Result<u32> {
+        (**self).GetBBID()
+    }
+
+    fn SetLED(&self, value: u32) -> Result<()> {
+        (**self).SetLED(value)
+    }
+
+    fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+        (**self).ListFiles()
+    }
+
+    fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+        (**self).DumpCurrentFS()
+    }
+
+    fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        (**self).ReadFile(name)
+    }
+
+    fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        (**self).ReadSingleBlock(blk)
+    }
+
+    fn CardStats(&self) -> Result<CardStats> {
+        (**self).CardStats()
+    }
+}
+
+#[cfg(feature = "writing")]
+impl<P: Player + ?Sized> Player for &mut P {
+    fn GetBBID(&self) -> Result<u32> {
+        (**self).GetBBID()
+    }
+
+    fn SetLED(&self, value: u32) -> Result<()> {
+        (**self).SetLED(value)
+    }
+
+    fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+        (**self).ListFiles()
+    }
+
+    fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+        (**self).DumpCurrentFS()
+    }
+
+    fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        (**self).ReadFile(name)
+    }
+
+    fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        (**self).ReadSingleBlock(blk)
+    }
+
+    fn CardStats(&self) -> Result<CardStats> {
+        (**self).CardStats()
+    }
+}
+
+// a boxed one too, so the session can hold whichever console it was given
+impl<P: Player + ?Sized> Player for Box<P> {
+    fn GetBBID(&self) -> Result<u32> {
+        (**self).GetBBID()
+    }
+
+    fn SetLED(&self, value: u32) -> Result<()> {
+        (**self).SetLED(value)
+    }
+
+    fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+        (**self).ListFiles()
+    }
+
+    fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+        (**self).DumpCurrentFS()
+    }
+
+    fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        (**self).ReadFile(name)
+    }
+
+    fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        (**self).ReadSingleBlock(blk)
+    }
+
+    fn CardStats(&self) -> Result<CardStats> {
+        (**self).CardStats()
+    }
+}
+
+/// The operations that change files, on a console or on a dump mounted with 'mount --rw'.
+#[cfg(feature = "writing")]
+pub trait PlayerWrite: Player {
+    /// Writes a file, replacing any of the same name.
+    fn WriteFile(&mut self, data: &[u8], name: &str) -> Result<()>;
+    /// Deletes a file.
+    fn DeleteFile(&mut self, name: &str) -> Result<()>;
+    /// Renames a file.
+    fn RenameFile(&mut self, from: &str, to: &str) -> Result<()>;
+}
+
+#[cfg(feature = "writing")]
+impl PlayerWrite for GlobalHandle {
+    fn WriteFile(&mut self, data: &[u8], name: &str) -> Result<()> {
+        GlobalHandle::WriteFile(self, data, name)
+    }
+
+    fn DeleteFile(&mut self, name: &str) -> Result<()> {
+        GlobalHandle::DeleteFile(self, name)
+    }
+
+    fn RenameFile(&mut self, from: &str, to: &str) -> Result<()> {
+        GlobalHandle::RenameFile(self, from, to)
+    }
+}
+
+#[cfg(feature = "writing")]
+impl<P: PlayerWrite + ?Sized> PlayerWrite for &mut P {
+    fn WriteFile(&mut self, data: &[u8], name: &str) -> Result<()> {
+        (**self).WriteFile(data, name)
+    }
+
+    fn DeleteFile(&mut self, name: &str) -> Result<()> {
+        (**self).DeleteFile(name)
+    }
+
+    fn RenameFile(&mut self, from: &str, to: &str) -> Result<()> {
+        (**self).RenameFile(from, to)
+    }
+}
+
+#[cfg(feature = "writing")]
+impl<P: PlayerWrite + ?Sized> PlayerWrite for Box<P> {
+    fn WriteFile(&mut self, data: &[u8], name: &str) -> Result<()> {
+        (**self).WriteFile(data, name)
+    }
+
+    fn DeleteFile(&mut self, name: &str) -> Result<()> {
+        (**self).DeleteFile(name)
+    }
+
+    fn RenameFile(&mut self, from: &str, to: &str) -> Result<()> {
+        (**self).RenameFile(from, to)
+    }
+}
+
+/// Writing a single block, which a console can do and a mounted dump or the sandbox can't; how
+/// '4 --blocks' sends a file a block at a time. The console reads its FS when the connection is
+/// initialised and keeps it, so after writing an FS block itself the connection is closed and
+/// initialised again for the console to see it.
+#[cfg(feature = "writing")]
+pub trait PlayerBlockWrite: Player {
+    /// Writes a block's data and spare data.
+    fn WriteSingleBlock(&self, blk: u32, nand: &[u8], spare: &[u8]) -> Result<()>;
+    /// Closes the connection.
+    fn Close(&mut self) -> Result<()>;
+    /// Initialises the connection, after which the console has read its card's FS again.
+    fn Init(&mut self) -> Result<()>;
+}
+
+#[cfg(feature = "writing")]
+impl PlayerBlockWrite for GlobalHandle {
+    fn WriteSingleBlock(&self, blk: u32, nand: &[u8], spare: &[u8]) -> Result<()> {
+        GlobalHandle::WriteSingleBlock(self, blk, nand, spare)
+    }
+
+    fn Close(&mut self) -> Result<()> {
+        GlobalHandle::Close(self)
+    }
+
+    fn Init(&mut self) -> Result<()> {
+        GlobalHandle::Init(self)
+    }
+}
+
+#[cfg(feature = "writing")]
+impl<P: PlayerBlockWrite + ?Sized> PlayerBlockWrite for Box<P> {
+    fn WriteSingleBlock(&self, blk: u32, nand: &[u8], spare: &[u8]) -> Result<()> {
+        (**self).WriteSingleBlock(blk, nand, spare)
+    }
+
+    fn Close(&mut self) -> Result<()> {
+        (**self).Close()
+    }
+
+    fn Init(&mut self) -> Result<()> {
+        (**self).Init()
+    }
+}
+
+/// A console the session can select: a [`Player`] that, with the `writing` feature, is also a
+/// [`PlayerWrite`] and a [`PlayerBlockWrite`], and has the calls only a console connection has.
+/// [`GlobalHandle`] is the real one; anything else can stand in for it, through
+/// [`CliContext::select_player`](crate::cli::CliContext::select_player).
+#[cfg(feature = "writing")]
+pub trait Backend: PlayerWrite + PlayerBlockWrite + Send {
+    /// Whether the connection has been initialised; asking mustn't touch the console.
+    fn initialised(&self) -> Result<bool>;
+    /// Sets the console's clock.
+    fn SetTime(&self, time: DateTime<FixedOffset>) -> Result<()>;
+    /// The SKSA as the console gives it back, which needn't be the size of the SKSA region.
+    fn ReadSKSA(&self) -> Result<Vec<u8>>;
+    /// Writes blocks of a whole card's data and spare data, `nand` and `spare`: only the
+    /// `blocks` listed, or all of them if there's no list.
+    fn WriteNANDSpare(&self, nand: &[u8], spare: &[u8], blocks: Option<Vec<u16>>) -> Result<()>;
+}
+
+/// A console the session can select: a [`Player`] with the calls only a console connection has.
+/// [`GlobalHandle`] is the real one; anything else can stand in for it, through
+/// [`CliContext::select_player`](crate::cli::CliContext::select_player).
+#[cfg(not(feature = "writing"))]
+pub trait Backend: Player + Send {
+    /// Whether the connection has been initialised; asking mustn't touch the console.
+    fn initialised(&self) -> Result<bool>;
+    /// Initialises the connection, after which the console has read its card's FS.
+    fn Init(&mut self) -> Result<()>;
+    /// Closes the connection, which finishes any writes the console has in hand.
+    fn Close(&mut self) -> Result<()>;
+    /// Sets the console's clock.
+    fn SetTime(&self, time: DateTime<FixedOffset>) -> Result<()>;
+    /// The SKSA as the console gives it back, which needn't be the size of the SKSA region.
+    fn ReadSKSA(&self) -> Result<Vec<u8>>;
+}
+
+impl Backend for GlobalHandle {
+    fn initialised(&self) -> Result<bool> {
+        GlobalHandle::initialised(self)
+    }
+
+    #[cfg(not(feature = "writing"))]
+    fn Init(&mut self) -> Result<()> {
+        GlobalHandle::Init(self)
+    }
+
+    #[cfg(not(feature = "writing"))]
+    fn Close(&mut self) -> Result<()> {
+        GlobalHandle::Close(self)
+    }
+
+    fn SetTime(&self, time: DateTime<FixedOffset>) -> Result<()> {
+        GlobalHandle::SetTime(self, time)
+    }
+
+    fn ReadSKSA(&self) -> Result<Vec<u8>> {
+        GlobalHandle::ReadSKSA(self)
+    }
+
+    #[cfg(feature = "writing")]
+    fn WriteNANDSpare(&self, nand: &[u8], spare: &[u8], blocks: Option<Vec<u16>>) -> Result<()> {
+        GlobalHandle::WriteNANDSpare(self, nand, spare, blocks)
+    }
+}
+
+// a boxed one too, so it can be handed on as one
+impl<B: Backend + ?Sized> Backend for Box<B> {
+    fn initialised(&self) -> Result<bool> {
+        (**self).initialised()
+    }
+
+    #[cfg(not(feature = "writing"))]
+    fn Init(&mut self) -> Result<()> {
+        (**self).Init()
+    }
+
+    #[cfg(not(feature = "writing"))]
+    fn Close(&mut self) -> Result<()> {
+        (**self).Close()
+    }
+
+    fn SetTime(&self, time: DateTime<FixedOffset>) -> Result<()> {
+        (**self).SetTime(time)
+    }
+
+    fn ReadSKSA(&self) -> Result<Vec<u8>> {
+        (**self).ReadSKSA()
+    }
+
+    #[cfg(feature = "writing")]
+    fn WriteNANDSpare(&self, nand: &[u8], spare: &[u8], blocks: Option<Vec<u16>>) -> Result<()> {
+        (**self).WriteNANDSpare(nand, spare, blocks)
+    }
+}
+
+/// A player that can only be read from: it's a Player and nothing else, and there's no way back to
+/// what it wraps, so it can't be handed to anything that writes; an attested session's console.
+pub struct ReadOnly<P>(P);
+
+impl<P: Player> ReadOnly<P> {
+    /// Wraps `player`, for good.
+    pub fn seal(player: P) -> Self {
+        Self(player)
+    }
+}
+
+impl<P: Player> Player for ReadOnly<P> {
+    fn GetBBID(&self) -> Result<u32> {
+        self.0.GetBBID()
+    }
+
+    fn SetLED(&self, value: u32) -> Result<()> {
+        self.0.SetLED(value)
+    }
+
+    fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+        self.0.ListFiles()
+    }
+
+    fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+        self.0.DumpCurrentFS()
+    }
+
+    fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        self.0.ReadFile(name)
+    }
+
+    fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        self.0.ReadSingleBlock(blk)
+    }
+
+    fn CardStats(&self) -> Result<CardStats> {
+        self.0.CardStats()
+    }
+}
+
+// a ReadOnly is never a PlayerWrite: if it were, `check` would be ambiguous and this wouldn't build
+#[cfg(feature = "writing")]
+const _: fn() = || {
+    trait Writable<A> {
+        fn check() {}
+    }
+    impl<T: ?Sized> Writable<()> for T {}
+    struct Yes;
+    impl<T: ?Sized + PlayerWrite> Writable<Yes> for T {}
+    let _ = <ReadOnly<Box<dyn Backend>> as Writable<_>>::check;
+};
+
+/// The selected console: open to anything, or sealed once the session is attested, after which
+/// only reads can reach it.
+pub enum Console {
+    /// A console that can be written to.
+    Open(Box<dyn Backend>),
+    /// An attested session's console, which can only be read from.
+    Sealed(ReadOnly<Box<dyn Backend>>),
+}
+
+impl Console {
+    /// The console, for reading.
+    pub fn reads(&self) -> &dyn Player {
+        match self {
+            Console::Open(player) => player,
+            Console::Sealed(player) => player,
+        }
+    }
+
+    /// Whether this session initialised the connection; asking doesn't touch the console.
+    pub fn initialised(&self) -> Result<bool> {
+        match self {
+            Console::Open(player) => player.initialised(),
+            Console::Sealed(ReadOnly(player)) => player.initialised(),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use bbrdb::CardStats;
+
+use crate::cancel::CancelToken;
+use crate::ecc::EccCheck;
+use crate::fs::BLOCK_SIZE;
+use crate::nand_read::card_blocks;
+use crate::player::Player;
+use crate::scrub::{classify, Health, READ_ATTEMPTS};
+use crate::spare::ecc_check;
+use crate::spotcheck::{sample_blocks, SampleRng};
+
+// A pre-flight check of the link to the console, run before a long write (or on demand with
+// 'preflight'): a few dozen blocks from across the card are read, retrying as 'scrub' does, and
+// timed, and the card's stats are asked for before and after. What's measured is boiled down to
+// a score out of 100; a write the score is under 'set preflight-score' needs confirming, or with
+// strict-writes on is refused.
+
+pub const SAMPLE_BLOCKS: usize = 32;
+
+// a healthy link moves a block in well under this
+const SLOW_LINK: f64 = 256.0 * 1024.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    pub reads: usize,
+    // blocks that never read cleanly, and those that only did after a retry or correction
+    pub failed: usize,
+    pub retried: usize,
+    // how long each block's first read took
+    pub latencies: Vec<Duration>,
+    pub bytes_per_sec: f64,
+    // whether the card's stats were the same both times they were asked for
+    pub stats_stable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assessment {
+    pub score: u8,
+    // what cost the link points, worst first
+    pub reasons: Vec<String>,
+}
+
+// how far the latencies spread, as a fraction of their mean
+pub fn jitter(latencies: &[Duration]) -> f64 {
+    if latencies.len() < 2 {
+        return 0.0;
+    }
+    let secs = latencies
+        .iter()
+        .map(Duration::as_secs_f64)
+        .collect::<Vec<_>>();
+    let mean = secs.iter().sum::<f64>() / secs.len() as f64;
+    if mean == 0.0 {
+        return 0.0;
+    }
+    let variance = secs.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / secs.len() as f64;
+    variance.sqrt() / mean
+}
+
+pub fn score(metrics: &Metrics) -> Assessment {
+    if metrics.reads == 0 {
+        return Assessment {
+            score: 0,
+            reasons: vec!["no blocks could be read".to_string()],
+        };
+    }
+    let reads = metrics.reads as f64;
+    let jitter = jitter(&metrics.latencies);
+    let mut penalties = vec![
+        (
+            (metrics.failed as f64 / reads * 600.0).min(60.0),
+            format!(
+                "{} of {} blocks couldn't be read",
+                metrics.failed, metrics.reads
+            ),
+        ),
+        (
+            (metrics.retried as f64 / reads * 150.0).min(30.0),
+            format!(
+                "{} of {} blocks needed a retry or correction",
+                metrics.retried, metrics.reads
+            ),
+        ),
+        (
+            ((jitter - 0.25) * 40.0).clamp(0.0, 20.0),
+            format!(
+                "read times varied by {:.0}% of their average",
+                jitter * 100.0
+            ),
+        ),
+        (
+            ((1.0 - metrics.bytes_per_sec / SLOW_LINK) * 20.0).clamp(0.0, 20.0),
+            format!("reads ran at {:.0} KiB/s", metrics.bytes_per_sec / 1024.0),
+        ),
+        (
+            if metrics.stats_stable { 0.0 } else { 40.0 },
+            "the card's stats changed between two reads of them".to_string(),
+        ),
+    ];
+    penalties.retain(|(p, _)| *p >= 1.0);
+    penalties.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let lost = penalties.iter().map(|(p, _)| p).sum::<f64>();
+    Assessment {
+        score: (100.0 - lost).clamp(0.0, 100.0).round() as u8,
+        reasons: penalties.into_iter().map(|(_, r)| r).collect(),
+    }
+}
+
+pub fn recommendation(assessment: &Assessment, min_score: u8) -> &'static str {
+    match assessment.score {
+        s if s < min_score => {
+            "The link isn't sound enough for a long write: reseat or replace the cable, plug the \
+             console into the PC rather than a hub, and run 'preflight' again"
+        }
+        s if s < 90 => "The link is usable, but not perfect; a long write should be watched",
+        _ => "The link looks solid",
+    }
+}
+
+pub fn report(assessment: &Assessment, min_score: u8) -> Vec<String> {
+    let mut lines = vec![format!("Link health: {}/100", assessment.score)];
+    lines.extend(assessment.reasons.iter().map(|r| format!("  - {r}")));
+    lines.push(recommendation(assessment, min_score).to_string());
+    lines
+}
+
+fn same_stats(a: &CardStats, b: &CardStats) -> bool {
+    (a.free, a.used, a.bad, a.seqno) == (b.free, b.used, b.bad, b.seqno)
+}
+
+// reads `count` blocks from across the card, only reading; cancellable between blocks
+pub fn measure(player: &dyn Player, count: usize, cancel: &CancelToken) -> Result<Metrics> {
+    let before = player.CardStats()?;
+    let total = card_blocks(player)? as usize;
+    let blocks = sample_blocks(total, count, &mut SampleRng::new(SampleRng::from_time()));
+
+    let mut metrics = Metrics {
+        reads: 0,
+        failed: 0,
+        retried: 0,
+        latencies: vec![],
+        bytes_per_sec: 0.0,
+        stats_stable: false,
+    };
+    let started = Instant::now();
+    for blk in blocks {
+        cancel
+            .check()
+            .map_err(|e| anyhow!("Stopped before block {blk:#X}: {e}"))?;
+        let mut attempts = vec![];
+        while attempts.len() < READ_ATTEMPTS && attempts.last() != Some(&Some(EccCheck::Clean)) {
+            let read = Instant::now();
+            let result = player.ReadSingleBlock(blk).ok();
+            if attempts.is_empty() {
+                metrics.latencies.push(read.elapsed());
+            }
+            attempts.push(result.map(|(data, spare)| ecc_check(&data, &spare)));
+        }
+        metrics.reads += 1;
+        match classify(&attempts) {
+            Health::Ok => {}
+            Health::Correctable => metrics.retried += 1,
+            Health::Failed => metrics.failed += 1,
+        }
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+    if elapsed > 0.0 {
+        metrics.bytes_per_sec = (metrics.reads * BLOCK_SIZE) as f64 / elapsed;
+    }
+    metrics.stats_stable = same_stats(&before, &player.CardStats()?);
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_preflight() -> Result<()> {
+        use anyhow::bail;
+
+        let ms = |times: &[u64]| {
+            times
+                .iter()
+                .map(|&t| Duration::from_millis(t))
+                .collect::<Vec<_>>()
+        };
+        let steady = ms(&[16; 32]);
+        let healthy = Metrics {
+            reads: 32,
+            failed: 0,
+            retried: 0,
+            latencies: steady.clone(),
+            bytes_per_sec: 1024.0 * 1024.0,
+            stats_stable: true,
+        };
+
+        // a sound link scores full marks, with nothing to say against it
+        let assessment = score(&healthy);
+        if assessment.score != 100 || !assessment.reasons.is_empty() {
+            bail!("a healthy link was assessed as {assessment:?}");
+        }
+
+        // the metric sets each failure gives, and the range its score should land in
+        let mut jittery = healthy.clone();
+        jittery.latencies = ms(&[8, 8, 8, 8, 40, 8, 8, 60, 8, 8, 8, 30, 8, 8, 8, 8]);
+        let mut slow = healthy.clone();
+        slow.bytes_per_sec = 100.0 * 1024.0;
+        let mut one_retry = healthy.clone();
+        one_retry.retried = 1;
+        let mut flaky_cable = healthy.clone();
+        flaky_cable.retried = 8;
+        flaky_cable.latencies = ms(&[16, 16, 40, 16, 90, 16, 16, 16, 70, 16]);
+        let mut failing = healthy.clone();
+        failing.failed = 4;
+        failing.retried = 3;
+        let mut resetting = healthy.clone();
+        resetting.stats_stable = false;
+        let nothing = Metrics {
+            reads: 0,
+            latencies: vec![],
+            ..healthy.clone()
+        };
+        let cases = [
+            ("one retry", &one_retry, 90..=99),
+            ("jittery", &jittery, 75..=95),
+            ("slow", &slow, 80..=95),
+            ("flaky cable", &flaky_cable, 40..=69),
+            ("resetting", &resetting, 50..=69),
+            ("failing", &failing, 0..=29),
+            ("nothing", &nothing, 0..=0),
+        ];
+        for (name, metrics, range) in cases {
+            let assessment = score(metrics);
+            if !range.contains(&assessment.score) || assessment.reasons.is_empty() {
+                bail!("the {name} link was assessed as {assessment:?}, not in {range:?}");
+            }
+        }
+
+        // the worst problem is given first
+        let reasons = score(&failing).reasons;
+        if !reasons[0].contains("couldn't be read") || reasons.len() != 2 {
+            bail!("the failing link's reasons were {reasons:?}");
+        }
+
+        // the recommendation follows the threshold
+        if !recommendation(&score(&flaky_cable), 70).starts_with("The link isn't sound")
+            || recommendation(&score(&flaky_cable), 40).starts_with("The link isn't sound")
+            || recommendation(&assessment, 70) != "The link looks solid"
+        {
+            bail!("the recommendations didn't follow the threshold");
+        }
+
+        if jitter(&steady) > 1e-9
+            || jitter(&[]) != 0.0
+            || (jitter(&ms(&[10, 30])) - 0.5).abs() > 1e-9
+        {
+            bail!("jitter was worked out wrongly");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+
+use crate::options::Options;
+
+// Presets: named sets of options for common workflows, chosen with 'preset <name>' at the prompt
+// or '--preset <name>' at startup. A few are built in; the config file can add more, or replace a
+// built-in one, under [presets]:
+//
+//   [presets.archive]
+//   manifest = "on"
+//   compress = "zst"
+//   hash-algos = "sha256,md5"
+//
+// Options are worked out in a fixed order, each layer over the one before: the defaults, the
+// preset, the config file's [options], then this session's 'set' commands. A profile's pins go
+// over all of them.
+
+pub type Preset = BTreeMap<String, String>;
+
+fn preset(options: &[(&str, &str)]) -> Preset {
+    options
+        .iter()
+        .map(|(o, v)| (o.to_string(), v.to_string()))
+        .collect()
+}
+
+pub fn builtin() -> BTreeMap<String, Preset> {
+    BTreeMap::from([
+        // dumps kept for the long term: checkable, and small
+        (
+            "archive".to_string(),
+            preset(&[
+                ("manifest", "on"),
+                ("compress", "zst"),
+                ("hash-algos", "sha256,md5"),
+            ]),
+        ),
+        // quick looks while debugging: nothing extra
+        (
+            "debug".to_string(),
+            preset(&[
+                ("manifest", "off"),
+                ("compress", "off"),
+                ("hash-algos", "default"),
+                ("strict-writes", "off"),
+                ("verify-writes", "off"),
+            ]),
+        ),
+        // flashing consoles at a station: nothing written that isn't checked before and after
+        (
+            "station".to_string(),
+            preset(&[
+                ("strict-writes", "on"),
+                ("verify-writes", "on"),
+                ("lint", "on"),
+            ]),
+        ),
+    ])
+}
+
+// the built-in presets, with the config file's over them
+pub fn all(config: &BTreeMap<String, Preset>) -> BTreeMap<String, Preset> {
+    let mut presets = builtin();
+    presets.extend(config.iter().map(|(n, p)| (n.clone(), p.clone())));
+    presets
+}
+
+pub fn find<'a>(presets: &'a BTreeMap<String, Preset>, name: &str) -> Result<&'a Preset> {
+    presets.get(name).ok_or_else(|| {
+        anyhow!(
+            "There's no preset called '{name}'; there are {}",
+            presets.keys().cloned().collect::<Vec<_>>().join(", ")
+        )
+    })
+}
+
+// the options from each layer in turn; `preset` is a name and its options
+pub fn resolve(
+    preset: Option<(&str, &Preset)>,
+    config: &BTreeMap<String, String>,
+    sets: &[(String, String)],
+) -> Result<Options> {
+    let mut options = Options::default();
+    if let Some((name, preset)) = preset {
+        for (option, value) in preset {
+            options
+                .set(option, value)
+                .map_err(|e| anyhow!("preset '{name}': {e}"))?;
+        }
+    }
+    for (option, value) in config {
+        options
+            .set(option, value)
+            .map_err(|e| anyhow!("the config file's [options]: {e}"))?;
+    }
+    for (option, value) in sets {
+        options.set(option, value)?;
+    }
+    Ok(options)
+}
+
+// the value of '--preset <name>' in the command line arguments, if it's there
+pub fn preset_arg(args: &[String]) -> Result<Option<&str>> {
+    let Some(i) = args.iter().position(|a| a == "--preset") else {
+        return Ok(None);
+    };
+    args.get(i + 1)
+        .map(|name| Some(name.as_str()))
+        .ok_or_else(|| anyhow!("'--preset' requires an argument, the name of a preset"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets() -> Result<()> {
+        use anyhow::bail;
+
+        use crate::compress::Codec;
+        use crate::hashing::HashAlgo;
+
+        // every built-in preset sets only options that exist
+        for (name, p) in builtin() {
+            resolve(Some((&name, &p)), &BTreeMap::new(), &[])?;
+        }
+
+        // a preset from the config file replaces the built-in one of the same name
+        let config_presets = BTreeMap::from([
+            ("archive".to_string(), preset(&[("compress", "gz")])),
+            ("mine".to_string(), preset(&[("lint", "off")])),
+        ]);
+        let presets = all(&config_presets);
+        if presets.len() != 4 || find(&presets, "archive")? != &config_presets["archive"] {
+            bail!("the config file's presets were merged as {presets:?}");
+        }
+        if find(&presets, "none").is_ok() {
+            bail!("a preset that doesn't exist was found");
+        }
+
+        // each layer goes over the one before: defaults < preset < config < 'set'
+        let archive = builtin()["archive"].clone();
+        let options = resolve(Some(("archive", &archive)), &BTreeMap::new(), &[])?;
+        if !options.manifest
+            || options.compress != Some(Codec::Zstd)
+            || options.hash_algos != Some(vec![HashAlgo::Sha256, HashAlgo::Md5])
+            || !options.lint
+        {
+            bail!("the archive preset came out as {:?}", options.lines());
+        }
+        let config = BTreeMap::from([
+            ("compress".to_string(), "gz".to_string()),
+            ("lint".to_string(), "off".to_string()),
+        ]);
+        let options = resolve(Some(("archive", &archive)), &config, &[])?;
+        if options.compress != Some(Codec::Gzip) || options.lint || !options.manifest {
+            bail!(
+                "the config file's options didn't go over the preset's: {:?}",
+                options.lines()
+            );
+        }
+        let sets = [
+            ("compress".to_string(), "off".to_string()),
+            ("manifest".to_string(), "off".to_string()),
+            ("manifest".to_string(), "on".to_string()),
+        ];
+        let options = resolve(Some(("archive", &archive)), &config, &sets)?;
+        if options.compress.is_some() || !options.manifest || options.lint {
+            bail!(
+                "'set' didn't go over the config file and preset: {:?}",
+                options.lines()
+            );
+        }
+        // without a preset, only the other layers apply
+        let options = resolve(None, &BTreeMap::new(), &sets[..1])?;
+        if options.manifest || options.compress.is_some() || options.hash_algos.is_some() {
+            bail!("options without a preset came out as {:?}", options.lines());
+        }
+
+        // a bad value says which layer it's in
+        let bad = preset(&[("compress", "rar")]);
+        match resolve(Some(("bad", &bad)), &BTreeMap::new(), &[]) {
+            Err(e) if e.to_string().starts_with("preset 'bad': ") => {}
+            other => bail!("a bad preset gave {:?}", other.map(|o| o.lines())),
+        }
+        let unknown = BTreeMap::from([("nope".to_string(), "on".to_string())]);
+        match resolve(None, &unknown, &[]) {
+            Err(e) if e.to_string().starts_with("the config file's [options]: ") => {}
+            other => bail!("a bad config option gave {:?}", other.map(|o| o.lines())),
+        }
+
+        let args = ["aulon2", "--preset", "archive"].map(String::from);
+        if preset_arg(&args)? != Some("archive")
+            || preset_arg(&args[..2]).is_ok()
+            || preset_arg(&args[..1])?.is_some()
+        {
+            bail!("'--preset' was parsed wrongly");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt::Write;
+
+// 'cat': a quick look at a file on the card without downloading it. Text is printed as it is,
+// with control characters escaped so they can't upset the terminal; anything else is shown as a
+// hex dump, or with '--strings', as the runs of printable characters in it.
+
+// how much of a file 'cat' reads and prints, unless the config file or '--max-bytes' says otherwise
+pub const DEFAULT_MAX_BYTES: usize = 0x1000;
+
+// runs of printable characters shorter than this aren't shown by '--strings', as for strings(1)
+const MIN_STRING: usize = 4;
+
+// the longest prefix that's text, or None if it isn't text; a multi-byte character cut off by
+// the end of what was read doesn't count against it
+pub fn as_text(data: &[u8]) -> Option<&str> {
+    let text = match std::str::from_utf8(data) {
+        Ok(t) => t,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&data[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    // a few stray control characters still leave it text, but a NUL never does
+    let control = text
+        .chars()
+        .filter(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+        .count();
+    if text.contains('\0') || control * 32 > text.chars().count() {
+        return None;
+    }
+    Some(text)
+}
+
+// escapes everything that would be interpreted by the terminal, keeping line breaks and tabs
+pub fn escape_text(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\n' | '\t' => c.to_string(),
+            c if c.is_control() => c.escape_default().to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+// offset, 16 bytes in hex and the same as ASCII, as in 'hexdump -C'
+pub fn hex_dump(data: &[u8]) -> String {
+    hex_dump_at(data, 0)
+}
+
+// the same, with offsets from `base`, for a piece of something larger (a page of the NAND)
+pub fn hex_dump_at(data: &[u8], base: usize) -> String {
+    let mut out = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x} ", base + i * 16);
+        for j in 0..16 {
+            if j == 8 {
+                out.push(' ');
+            }
+            match line.get(j) {
+                Some(b) => {
+                    let _ = write!(out, " {b:02x}");
+                }
+                None => out.push_str("   "),
+            }
+        }
+        let ascii = line
+            .iter()
+            .map(|&b| match b {
+                0x20..=0x7E => b as char,
+                _ => '.',
+            })
+            .collect::<String>();
+        let _ = writeln!(out, "  |{ascii}|");
+    }
+    out
+}
+
+// the runs of at least MIN_STRING printable ASCII characters (and tabs), like strings(1)
+pub fn strings(data: &[u8]) -> Vec<String> {
+    data.split(|&b| !matches!(b, 0x20..=0x7E | b'\t'))
+        .filter(|run| run.len() >= MIN_STRING)
+        .map(|run| String::from_utf8_lossy(run).into_owned())
+        .collect()
+}
+
+// what 'cat' prints for the first bytes of a file 'size' bytes long
+pub fn render(data: &[u8], size: u32, as_strings: bool) -> String {
+    let mut out = if as_strings {
+        strings(data).into_iter().map(|s| s + "\n").collect()
+    } else if let Some(text) = as_text(data) {
+        let mut text = escape_text(text);
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text
+    } else {
+        hex_dump(data)
+    };
+    if data.len() < size as usize {
+        let _ = writeln!(
+            out,
+            "... (the first {:#X} of {size:#X} bytes; '--max-bytes' shows more)",
+            data.len()
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Result};
+
+    #[test]
+    fn file_preview() -> Result<()> {
+        if as_text(b"volume=3\r\nname=\"BB\"\n") != Some("volume=3\r\nname=\"BB\"\n") {
+            bail!("a settings file wasn't taken as text");
+        }
+        // 'é' cut in half by the end of the read
+        if as_text(b"caf\xC3\xA9 caf\xC3") != Some("caf\u{e9} caf") {
+            bail!("text cut off mid-character wasn't taken as text");
+        }
+        if as_text(b"ab\0cd").is_some() || as_text(&[0x89, b'P', b'N', b'G']).is_some() {
+            bail!("binary data was taken as text");
+        }
+        if escape_text("a\x1b[2Jb\n\tc\r") != "a\\u{1b}[2Jb\n\tc\\r" {
+            bail!(
+                "control characters weren't escaped: {}",
+                escape_text("a\x1b[2Jb\n\tc\r")
+            );
+        }
+
+        let dump = hex_dump(b"0123456789abcdef\x00\xFFxyz");
+        let expected =
+            "00000000  30 31 32 33 34 35 36 37  38 39 61 62 63 64 65 66  |0123456789abcdef|\n\
+                        00000010  00 ff 78 79 7a                                    |..xyz|\n";
+        if dump != expected {
+            bail!("the hex dump was wrong:\n{dump}");
+        }
+        let dump = hex_dump_at(b"xyz", 0x4200);
+        if dump != "00004200  78 79 7a                                          |xyz|\n" {
+            bail!("the hex dump from 0x4200 was wrong:\n{dump}");
+        }
+
+        if strings(b"\0\0GAME\x01ab\x02LONGER NAME\tX\xFF") != ["GAME", "LONGER NAME\tX"] {
+            bail!("the wrong strings were found");
+        }
+
+        let shown = render(b"hello", 0x20, false);
+        if shown != "hello\n... (the first 0x5 of 0x20 bytes; '--max-bytes' shows more)\n" {
+            bail!("a truncated file was shown as:\n{shown}");
+        }
+        if render(b"hello\n", 6, false) != "hello\n" {
+            bail!("a whole file was marked as truncated");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+
+use crate::options::Options;
+
+// Permission profiles, for a station shared by people who shouldn't all be writing to consoles.
+// They're named tables in the config file, chosen with '--profile <name>' at startup:
+//
+//   [profiles.meetup]
+//   allow = ["l", "s", "B", "I", "C", "L", "5", "1", "device"]
+//   pin = { strict-writes = "on" }
+//
+// There's no authentication: anyone can start without '--profile', and this only stops mistakes.
+
+// usable under any profile, so nobody is stuck at the prompt
+const ALWAYS_ALLOWED: [&str; 3] = ["", "h", "q"];
+
+#[derive(Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct Profile {
+    // if given, only these commands can be used
+    pub allow: Option<Vec<String>>,
+    // these commands can't be used, even if they're in 'allow'
+    pub deny: Vec<String>,
+    // options set when the profile is chosen, which 'set' and 'session load' can't change
+    pub pin: BTreeMap<String, String>,
+}
+
+// the profile in use, with its name for messages
+pub struct ActiveProfile {
+    pub name: String,
+    pub profile: Profile,
+}
+
+impl ActiveProfile {
+    pub fn select(profiles: &BTreeMap<String, Profile>, name: &str) -> Result<Self> {
+        let profile = profiles.get(name).cloned().ok_or_else(|| {
+            anyhow!("There's no profile called '{name}' in the config file's [profiles]")
+        })?;
+        let active = Self {
+            name: name.to_string(),
+            profile,
+        };
+        // a pin that doesn't apply would look like protection that isn't there
+        active.apply(&mut Options::default())?;
+        Ok(active)
+    }
+
+    // the error to print if `command` can't be used under this profile
+    pub fn check(&self, command: &str) -> Result<()> {
+        if ALWAYS_ALLOWED.contains(&command) {
+            return Ok(());
+        }
+        let allowed = self
+            .profile
+            .allow
+            .as_ref()
+            .is_none_or(|a| a.iter().any(|c| c == command));
+        if !allowed || self.profile.deny.iter().any(|c| c == command) {
+            bail!("'{command}' isn't allowed by the '{}' profile", self.name);
+        }
+        Ok(())
+    }
+
+    // sets the pinned options, over whatever they were
+    pub fn apply(&self, options: &mut Options) -> Result<()> {
+        for (option, value) in &self.profile.pin {
+            options
+                .set(option, value)
+                .map_err(|e| anyhow!("profile '{}': {e}", self.name))?;
+        }
+        Ok(())
+    }
+
+    pub fn check_set(&self, option: &str) -> Result<()> {
+        match self.profile.pin.get(option) {
+            Some(value) => bail!(
+                "'{option}' is pinned to '{value}' by the '{}' profile",
+                self.name
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+// the value of '--profile <name>' in the command line arguments, if it's there
+pub fn profile_arg(args: &[String]) -> Result<Option<&str>> {
+    let Some(i) = args.iter().position(|a| a == "--profile") else {
+        return Ok(None);
+    };
+    args.get(i + 1)
+        .map(|name| Some(name.as_str()))
+        .ok_or_else(|| anyhow!("'--profile' requires an argument, the name of a profile"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles() -> Result<()> {
+        let profiles: BTreeMap<String, Profile> = toml::from_str(
+            "[meetup]\nallow = [\"l\", \"s\", \"1\", \"2\"]\ndeny = [\"2\"]\npin = { strict-writes = \"on\" }\n",
+        )?;
+        let expected = Profile {
+            allow: Some(["l", "s", "1", "2"].map(String::from).to_vec()),
+            deny: vec!["2".to_string()],
+            pin: BTreeMap::from([("strict-writes".to_string(), "on".to_string())]),
+        };
+        if profiles.get("meetup") != Some(&expected) {
+            bail!("profile parsed as {profiles:?}");
+        }
+        if ActiveProfile::select(&profiles, "other").is_ok() {
+            bail!("a profile that isn't in the config was selected");
+        }
+        let active = ActiveProfile::select(&profiles, "meetup")?;
+
+        // deny wins over allow, and commands outside 'allow' are refused
+        for (command, allowed) in [("1", true), ("2", false), ("6", false), ("q", true)] {
+            if active.check(command).is_ok() != allowed {
+                bail!(
+                    "'{command}' was wrongly {}",
+                    if allowed { "refused" } else { "allowed" }
+                );
+            }
+        }
+
+        // pins win over earlier options, and can't be changed afterwards
+        let mut options = Options {
+            strict_writes: false,
+            ..Default::default()
+        };
+        active.apply(&mut options)?;
+        if !options.strict_writes {
+            bail!("a pinned option wasn't applied");
+        }
+        if active.check_set("strict-writes").is_ok() || active.check_set("lint").is_err() {
+            bail!("'set' was checked against the wrong pins");
+        }
+
+        let bad = BTreeMap::from([(
+            "bad".to_string(),
+            Profile {
+                pin: BTreeMap::from([("no-such-option".to_string(), "on".to_string())]),
+                ..Default::default()
+            },
+        )]);
+        if ActiveProfile::select(&bad, "bad").is_ok() {
+            bail!("a profile pinning an unknown option was selected");
+        }
+
+        let args = ["aulon2", "--profile", "meetup"].map(String::from);
+        if profile_arg(&args)? != Some("meetup") || profile_arg(&args[..2]).is_ok() {
+            bail!("'--profile' was parsed wrongly");
+        }
+        Ok(())
+    }
+}
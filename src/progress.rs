@@ -0,0 +1,278 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+
+use crate::jobs::{meter, say, Meter};
+
+static NEXT_OP_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+enum Event<'a> {
+    Start {
+        op: &'a str,
+        id: u64,
+        total: u64,
+    },
+    Progress {
+        op: &'a str,
+        id: u64,
+        done: u64,
+        total: u64,
+        bytes_per_sec: u64,
+        // what the bar would be showing, on the first event after it changes
+        #[serde(skip_serializing_if = "Option::is_none")]
+        label: Option<&'a str>,
+    },
+    Finish {
+        op: &'a str,
+        id: u64,
+        done: u64,
+        total: u64,
+        elapsed_secs: f64,
+    },
+    Error {
+        op: &'a str,
+        id: u64,
+        done: u64,
+        total: u64,
+        message: &'a str,
+    },
+}
+
+fn emit(event: &Event) {
+    if let Ok(line) = serde_json::to_string(event) {
+        eprintln!("{line}");
+    }
+}
+
+// progress of a long block-based operation, rendered either as a progress bar or as
+// JSON events on stderr; both renderers are driven from the same calls. In a background job,
+// neither: it goes to the job's meter, for 'jobs' and 'fg'
+pub struct Progress {
+    op: &'static str,
+    id: u64,
+    total: u64,
+    done: u64,
+    unit_bytes: u64,
+    started: Instant,
+    bar: Option<ProgressBar>,
+    meter: Option<Arc<Meter>>,
+    // a label set since the last progress event, for the next one
+    label: Option<String>,
+}
+
+impl Progress {
+    pub fn start(op: &'static str, total: u64, unit_bytes: usize, events: bool) -> Self {
+        let id = NEXT_OP_ID.fetch_add(1, Ordering::Relaxed);
+        let meter = meter();
+        let bar = if let Some(meter) = &meter {
+            meter.start(total);
+            None
+        } else if events {
+            emit(&Event::Start { op, id, total });
+            None
+        } else {
+            let bar = ProgressBar::new(total);
+            if let Ok(style) = ProgressStyle::with_template(
+                "{msg} [{wide_bar}] {pos}/{len} blocks ({elapsed}, ETA {eta})",
+            ) {
+                bar.set_style(style.progress_chars("=> "));
+            }
+            Some(bar)
+        };
+        Self {
+            op,
+            id,
+            total,
+            done: 0,
+            unit_bytes: unit_bytes as u64,
+            started: Instant::now(),
+            bar,
+            meter,
+            label: None,
+        }
+    }
+
+    pub fn set_message(&mut self, msg: String) {
+        match &self.bar {
+            Some(bar) => bar.set_message(msg),
+            None => self.label = Some(msg),
+        }
+    }
+
+    pub fn inc(&mut self, n: u64) {
+        self.done += n;
+        if let Some(meter) = &self.meter {
+            meter.add(n);
+            return;
+        }
+        match &self.bar {
+            Some(bar) => bar.inc(n),
+            None => {
+                let secs = self.started.elapsed().as_secs_f64();
+                emit(&Event::Progress {
+                    op: self.op,
+                    id: self.id,
+                    done: self.done,
+                    total: self.total,
+                    bytes_per_sec: if secs > 0.0 {
+                        ((self.done * self.unit_bytes) as f64 / secs) as u64
+                    } else {
+                        0
+                    },
+                    label: self.label.take().as_deref(),
+                })
+            }
+        }
+    }
+
+    // prints a line without disturbing the progress bar
+    pub fn println(&self, line: String) {
+        match &self.bar {
+            Some(bar) => bar.println(line),
+            None => say(line),
+        }
+    }
+
+    pub fn finish(self) {
+        if self.meter.is_some() {
+            return;
+        }
+        match &self.bar {
+            Some(bar) => bar.finish_and_clear(),
+            None => emit(&Event::Finish {
+                op: self.op,
+                id: self.id,
+                done: self.done,
+                total: self.total,
+                elapsed_secs: self.started.elapsed().as_secs_f64(),
+            }),
+        }
+    }
+
+    pub fn fail(self, message: &str) {
+        if self.meter.is_some() {
+            return;
+        }
+        match &self.bar {
+            Some(bar) => bar.abandon(),
+            None => emit(&Event::Error {
+                op: self.op,
+                id: self.id,
+                done: self.done,
+                total: self.total,
+                message,
+            }),
+        }
+    }
+}
+
+// a progress bar following a background job's meter, for 'fg'; with progress events on, 'fg'
+// just waits
+pub struct Follow {
+    bar: Option<ProgressBar>,
+}
+
+impl Follow {
+    pub fn start(events: bool) -> Self {
+        let bar = (!events).then(|| {
+            let bar = ProgressBar::new(0);
+            if let Ok(style) = ProgressStyle::with_template("{msg} [{wide_bar}] {pos}/{len} blocks")
+            {
+                bar.set_style(style.progress_chars("=> "));
+            }
+            bar
+        });
+        Self { bar }
+    }
+
+    pub fn update(&mut self, meter: &Meter, label: &str) {
+        if let Some(bar) = &self.bar {
+            bar.set_length(meter.total());
+            bar.set_position(meter.done());
+            bar.set_message(label.to_string());
+        }
+    }
+
+    pub fn finish(self) {
+        if let Some(bar) = self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    // the lines scripts read: one JSON object each, told apart by "event"
+    #[test]
+    fn events() -> Result<()> {
+        let lines = [
+            Event::Start {
+                op: "dump",
+                id: 1,
+                total: 0x1000,
+            },
+            Event::Progress {
+                op: "dump",
+                id: 1,
+                done: 0x10,
+                total: 0x1000,
+                bytes_per_sec: 350_000,
+                label: Some("Range 1/2 (0x0-0x7FF)"),
+            },
+            Event::Progress {
+                op: "dump",
+                id: 1,
+                done: 0x11,
+                total: 0x1000,
+                bytes_per_sec: 350_000,
+                label: None,
+            },
+            Event::Finish {
+                op: "dump",
+                id: 1,
+                done: 0x1000,
+                total: 0x1000,
+                elapsed_secs: 12.5,
+            },
+            Event::Error {
+                op: "write",
+                id: 2,
+                done: 3,
+                total: 5,
+                message: "Failed at block 0x3: \"timed out\"",
+            },
+        ]
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            lines,
+            [
+                r#"{"event":"start","op":"dump","id":1,"total":4096}"#,
+                r#"{"event":"progress","op":"dump","id":1,"done":16,"total":4096,"bytes_per_sec":350000,"label":"Range 1/2 (0x0-0x7FF)"}"#,
+                r#"{"event":"progress","op":"dump","id":1,"done":17,"total":4096,"bytes_per_sec":350000}"#,
+                r#"{"event":"finish","op":"dump","id":1,"done":4096,"total":4096,"elapsed_secs":12.5}"#,
+                r#"{"event":"error","op":"write","id":2,"done":3,"total":5,"message":"Failed at block 0x3: \"timed out\""}"#,
+            ]
+        );
+        Ok(())
+    }
+
+    // each operation gets an ID of its own, so interleaved events can be told apart
+    #[test]
+    fn ids() {
+        let first = Progress::start("verify", 2, 0x4000, true);
+        let second = Progress::start("verify", 2, 0x4000, true);
+        assert!(second.id > first.id);
+        first.finish();
+        second.fail("cancelled");
+    }
+}
@@ -0,0 +1,25 @@
+use std::collections::VecDeque;
+
+use anyhow::{anyhow, Result};
+
+/// Where commands that ask questions (confirmations, choices) get their answers from.
+pub trait Prompt {
+    /// Shows `prompt` and returns the line that was typed, without its line ending.
+    fn readline(&mut self, prompt: &str) -> Result<String>;
+}
+
+#[cfg(feature = "cli")]
+impl Prompt for rustyline::DefaultEditor {
+    fn readline(&mut self, prompt: &str) -> Result<String> {
+        Ok(rustyline::Editor::readline(self, prompt)?)
+    }
+}
+
+/// Answers prompts from a list of lines, in order; running out is an error, as if the input had
+/// ended.
+impl Prompt for VecDeque<String> {
+    fn readline(&mut self, prompt: &str) -> Result<String> {
+        self.pop_front()
+            .ok_or_else(|| anyhow!("no answer left for '{}'", prompt.trim()))
+    }
+}
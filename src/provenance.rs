@@ -0,0 +1,322 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::hashing::{format_hashes, HashAlgo, HashFormat};
+
+#[cfg(feature = "writing")]
+use crate::image::NandImage;
+#[cfg(feature = "writing")]
+use crate::ticket::{parse_tickets, TICKET_FILE};
+
+// A dump holds things tied to the console it came from (its tickets, above all), so writing
+// console A's dump to console B is usually a mistake. '1 --manifest' saves a manifest beside the
+// dump recording which console it came from; '2' compares that with the console it's writing to,
+// or without one, the BBID in the dump's tickets, which is weaker evidence: tickets can be
+// copied between cards.
+
+// the manifest '1 --manifest' writes, and '2' looks for, beside [nand]; it keeps the name with
+// other algorithms, as '2' looks for it by name and reads whichever it holds
+pub const MANIFEST_EXT: &str = "sha256";
+
+// the comment line in a manifest recording the console a dump came from; sha256sum skips it
+pub const SOURCE_BBID_TAG: &str = "# source-bbid:";
+
+// the comment line recording when the dump was made, in RFC 3339, for 'set require-backup'
+pub const DUMPED_TAG: &str = "# dumped:";
+
+pub fn manifest_path(nand_filename: &str) -> String {
+    format!("{nand_filename}.{MANIFEST_EXT}")
+}
+
+// a manifest in sha256sum's format (or BSD-style lines, for several algorithms), with the
+// console the files came from and when
+pub fn dump_manifest(
+    bbid: Option<u32>,
+    dumped: Option<&str>,
+    files: &[(&str, &[u8])],
+    algos: &[HashAlgo],
+) -> String {
+    let mut text = match bbid {
+        Some(b) => format!("{SOURCE_BBID_TAG} {b:08X}\n"),
+        None => String::new(),
+    };
+    if let Some(dumped) = dumped {
+        text += &format!("{DUMPED_TAG} {dumped}\n");
+    }
+    for (path, data) in files {
+        let name = Path::new(path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(path);
+        let hashes = algos.iter().map(|a| a.digest(data)).collect::<Vec<_>>();
+        text += &format_hashes(HashFormat::Sum, name, &hashes);
+        text.push('\n');
+    }
+    text
+}
+
+// when a manifest says its dump was made, if it does
+pub fn parse_dumped(text: &str) -> Option<DateTime<FixedOffset>> {
+    let dumped = text
+        .lines()
+        .find_map(|l| l.trim().strip_prefix(DUMPED_TAG))?;
+    DateTime::parse_from_rfc3339(dumped.trim()).ok()
+}
+
+// when the dump [nand] was made, from the manifest beside it
+pub fn dumped_at(nand_filename: &str) -> Option<DateTime<FixedOffset>> {
+    parse_dumped(&read_to_string(manifest_path(nand_filename)).ok()?)
+}
+
+#[cfg(feature = "writing")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confidence {
+    // recorded in the dump's manifest
+    Certain,
+    // every ticket in the dump is for the same console
+    Likely,
+    // the dump's tickets are for several consoles; this one has the most
+    Weak,
+}
+
+// the console a dump came from, as far as can be told
+#[cfg(feature = "writing")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Origin {
+    pub bbid: u32,
+    pub confidence: Confidence,
+}
+
+#[cfg(feature = "writing")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Proceed,
+    // said, but the write goes ahead
+    Warn(String),
+    Refuse(String),
+}
+
+// the console most of the tickets are for
+#[cfg(feature = "writing")]
+pub fn origin_from_tickets(bbids: &[u32]) -> Option<Origin> {
+    let mut counts: Vec<(u32, usize)> = vec![];
+    for &b in bbids {
+        match counts.iter_mut().find(|(c, _)| *c == b) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((b, 1)),
+        }
+    }
+    // ties go to the first seen, for a repeatable answer
+    let &(bbid, n) = counts.iter().rev().max_by_key(|(_, n)| *n)?;
+    Some(Origin {
+        bbid,
+        confidence: if n == bbids.len() {
+            Confidence::Likely
+        } else {
+            Confidence::Weak
+        },
+    })
+}
+
+// the BBIDs of the tickets in a dump's ticket.sys, if its FS can be read
+#[cfg(feature = "writing")]
+pub fn ticket_bbids(nand: &[u8], spare: Option<&[u8]>) -> Vec<u32> {
+    // the spare data only matters for bad block markers, which reading a file doesn't need
+    let spare = spare
+        .map(<[u8]>::to_vec)
+        .unwrap_or_else(|| vec![0xFF; nand.len() / crate::fs::BLOCK_SIZE * crate::fs::SPARE_SIZE]);
+    let Ok(image) = NandImage::new(nand.to_vec(), spare) else {
+        return vec![];
+    };
+    image
+        .current_fs()
+        .and_then(|(_, fs)| image.read_file(&fs, fs.find(TICKET_FILE)?).ok())
+        .and_then(|data| parse_tickets(&data).ok())
+        .map(|t| t.tickets.iter().map(|t| t.bbid).collect())
+        .unwrap_or_default()
+}
+
+// whether a dump from `origin` may be written to the console with BBID `console`
+#[cfg(feature = "writing")]
+pub fn policy(
+    origin: Option<Origin>,
+    console: Option<u32>,
+    strict: bool,
+    acknowledged: bool,
+) -> Verdict {
+    let Some(origin) = origin else {
+        return Verdict::Proceed;
+    };
+    let Some(console) = console.filter(|&c| c != origin.bbid) else {
+        return match console {
+            Some(_) => Verdict::Proceed,
+            None => Verdict::Warn(format!(
+                "The dump is from console {:08X}, but this console's BBID couldn't be read to compare",
+                origin.bbid
+            )),
+        };
+    };
+    let evidence = match origin.confidence {
+        Confidence::Certain => "its manifest says",
+        Confidence::Likely => "going by its tickets",
+        Confidence::Weak => "going by most of its tickets, though some are for other consoles",
+    };
+    let what = format!(
+        "This dump is from console {:08X} ({evidence}), not this one ({console:08X})",
+        origin.bbid
+    );
+    match (acknowledged, strict) {
+        (true, _) => Verdict::Warn(format!("{what}; writing it anyway, as '--cross-console' was given")),
+        (false, true) => Verdict::Refuse(format!(
+            "strict-writes: {what}; add '--cross-console' if that's deliberate"
+        )),
+        (false, false) => Verdict::Warn(format!(
+            "{what}. Writing another console's dump is usually a mistake; add '--cross-console' to say it's deliberate"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Result};
+
+    #[test]
+    fn dump_origins() -> Result<()> {
+        let manifest = dump_manifest(
+            Some(0x1234ABCD),
+            Some("2024-05-02T10:00:00+01:00"),
+            &[("dumps/nand.bin", b""), ("spare.bin", b"")],
+            &[HashAlgo::Sha256],
+        );
+        let empty = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        if manifest
+            != format!("# source-bbid: 1234ABCD\n# dumped: 2024-05-02T10:00:00+01:00\n{empty}  nand.bin\n{empty}  spare.bin\n")
+        {
+            bail!("the dump manifest was:\n{manifest}");
+        }
+        // the time it records is read back as the instant it names, and one that isn't a time,
+        // or none at all, dates nothing
+        let dumped = parse_dumped(&manifest).map(|d| d.timestamp());
+        assert_eq!(dumped, Some(1_714_640_400));
+        assert_eq!(parse_dumped("# dumped: yesterday\n"), None);
+        assert_eq!(parse_dumped(&format!("{empty}  nand.bin\n")), None);
+
+        // with other algorithms, each file gets a line per algorithm, and reading the manifest back
+        // checks each against its own algorithm's hash
+        let nand = vec![0x5A; 0x100];
+        let mixed = dump_manifest(
+            None,
+            None,
+            &[("nand.bin", &nand), ("spare.bin", b"")],
+            &[HashAlgo::Md5, HashAlgo::Crc32],
+        );
+        let crc = HashAlgo::Crc32.digest(&nand).hex();
+        if mixed.lines().count() != 4 || !mixed.contains(&format!("CRC32 (nand.bin) = {crc}\n")) {
+            bail!("the MD5 and CRC-32 manifest was:\n{mixed}");
+        }
+        #[cfg(feature = "writing")]
+        {
+            use crate::strict::{check_strict, Manifest, WriteRequest};
+            let request = |data: &[u8]| {
+                check_strict(
+                    &WriteRequest {
+                        files: vec![("dumps/nand.bin", data)],
+                        expected_bbid: Some(1),
+                        ranges_given: true,
+                    },
+                    Some(&Manifest::parse(&mixed)?),
+                    Some(1),
+                )
+            };
+            request(&nand)?;
+            let mut changed = nand.clone();
+            changed[7] ^= 1;
+            match request(&changed) {
+                Err(e) if e.to_string().contains("has MD5") => {}
+                other => bail!("a changed file against the MD5 and CRC-32 manifest gave {other:?}"),
+            }
+            // an untagged line is read as the algorithm its length says, not as SHA-256
+            let untagged = format!("{crc}  nand.bin\n");
+            let manifest = Manifest::parse(&untagged)?;
+            let check = |data: &[u8]| {
+                check_strict(
+                    &WriteRequest {
+                        files: vec![("nand.bin", data)],
+                        expected_bbid: Some(1),
+                        ranges_given: true,
+                    },
+                    Some(&manifest),
+                    Some(1),
+                )
+            };
+            if check(&nand).is_err() || check(&changed).is_ok() {
+                bail!("an untagged CRC-32 manifest wasn't checked as CRC-32");
+            }
+        }
+
+        #[cfg(feature = "writing")]
+        {
+            use Confidence::*;
+            if origin_from_tickets(&[]).is_some()
+                || origin_from_tickets(&[7, 7, 7])
+                    != Some(Origin {
+                        bbid: 7,
+                        confidence: Likely,
+                    })
+                || origin_from_tickets(&[5, 7, 7, 5, 7])
+                    != Some(Origin {
+                        bbid: 7,
+                        confidence: Weak,
+                    })
+                || origin_from_tickets(&[5, 7]).map(|o| o.bbid) != Some(5)
+            {
+                bail!("the wrong console was taken from the tickets");
+            }
+
+            let from = |confidence| {
+                Some(Origin {
+                    bbid: 1,
+                    confidence,
+                })
+            };
+            let is = |v: &Verdict, kind: &str| {
+                matches!(
+                    (v, kind),
+                    (Verdict::Proceed, "proceed")
+                        | (Verdict::Warn(_), "warn")
+                        | (Verdict::Refuse(_), "refuse")
+                )
+            };
+            // (origin, console, strict, --cross-console, expected)
+            let cases = [
+                (None, Some(2), false, false, "proceed"),
+                (None, Some(2), true, false, "proceed"),
+                (from(Certain), Some(1), false, false, "proceed"),
+                (from(Certain), Some(1), true, false, "proceed"),
+                (from(Likely), Some(1), true, false, "proceed"),
+                (from(Certain), Some(2), false, false, "warn"),
+                (from(Certain), Some(2), true, false, "refuse"),
+                (from(Certain), Some(2), true, true, "warn"),
+                (from(Certain), Some(2), false, true, "warn"),
+                (from(Likely), Some(2), false, false, "warn"),
+                (from(Likely), Some(2), true, false, "refuse"),
+                (from(Weak), Some(2), true, true, "warn"),
+                (from(Certain), None, true, false, "warn"),
+            ];
+            for (origin, console, strict, acknowledged, expected) in cases {
+                let verdict = policy(origin, console, strict, acknowledged);
+                if !is(&verdict, expected) {
+                    bail!("{origin:?} onto {console:?} (strict {strict}, --cross-console {acknowledged}) gave {verdict:?}, not {expected}");
+                }
+            }
+            match policy(from(Weak), Some(2), false, false) {
+                Verdict::Warn(w) if w.contains("some are for other consoles") => {}
+                v => bail!("weak evidence wasn't described as such: {v:?}"),
+            }
+        }
+        Ok(())
+    }
+}
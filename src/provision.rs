@@ -0,0 +1,399 @@
+use std::collections::BTreeMap;
+use std::fs::{read, read_dir};
+#[cfg(feature = "writing")]
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::cancel::CancelToken;
+use crate::fs::FsBlock;
+#[cfg(feature = "writing")]
+use crate::fs::BLOCK_SIZE;
+use crate::hash_cache::HashCache;
+use crate::hashing::{HashAlgo, HashValue};
+use crate::lint::{check_name, Level};
+use crate::player::Player;
+
+// 'provision': bringing a console's card to exactly the files in a directory, for demo consoles
+// that are all set up the same. The console's system files (*.sys, such as ticket.sys) are never
+// deleted or replaced, whatever the directory holds.
+
+pub fn is_protected(name: &str) -> bool {
+    name.ends_with(".sys")
+}
+
+// a file in the directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wanted {
+    pub name: String,
+    pub size: u32,
+    pub hash: HashValue,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Diff {
+    // in the directory, not on the console
+    pub missing: Vec<String>,
+    // on the console, not in the directory
+    pub extra: Vec<String>,
+    // on both, with different contents
+    pub different: Vec<String>,
+    // system files that differ from the directory's copy, or that only the directory has; they're
+    // left alone
+    pub protected: Vec<String>,
+}
+
+impl Diff {
+    pub fn in_sync(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.different.is_empty()
+    }
+}
+
+// the console's files that have to be hashed to compare them: those the directory has a copy of
+// the same size of (a different size is already a difference)
+pub fn to_hash<'a>(wanted: &[Wanted], present: &'a [(String, u32)]) -> Vec<&'a str> {
+    present
+        .iter()
+        .filter(|(name, size)| wanted.iter().any(|w| w.name == *name && w.size == *size))
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
+
+// `hashes` needs the console's files named by `to_hash`
+pub fn diff(
+    wanted: &[Wanted],
+    present: &[(String, u32)],
+    hashes: &BTreeMap<String, HashValue>,
+) -> Diff {
+    let mut diff = Diff::default();
+    for w in wanted {
+        let on_card = present.iter().find(|(name, _)| *name == w.name);
+        let same = on_card
+            .is_some_and(|(name, size)| *size == w.size && hashes.get(name) == Some(&w.hash));
+        match (on_card, same) {
+            (_, true) => {}
+            _ if is_protected(&w.name) => diff.protected.push(w.name.clone()),
+            (None, _) => diff.missing.push(w.name.clone()),
+            (Some(_), false) => diff.different.push(w.name.clone()),
+        }
+    }
+    for (name, _) in present {
+        if !wanted.iter().any(|w| w.name == *name) && !is_protected(name) {
+            diff.extra.push(name.clone());
+        }
+    }
+    for list in [
+        &mut diff.missing,
+        &mut diff.extra,
+        &mut diff.different,
+        &mut diff.protected,
+    ] {
+        list.sort();
+    }
+    diff
+}
+
+#[cfg(feature = "writing")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Delete(String),
+    Upload(String),
+}
+
+#[cfg(feature = "writing")]
+impl Step {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Delete(name) | Self::Upload(name) => name,
+        }
+    }
+}
+
+#[cfg(feature = "writing")]
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Delete(name) => write!(f, "delete {name}"),
+            Self::Upload(name) => write!(f, "upload {name}"),
+        }
+    }
+}
+
+// files take whole blocks
+#[cfg(feature = "writing")]
+fn blocks(size: u32) -> usize {
+    (size as usize).div_ceil(BLOCK_SIZE).max(1)
+}
+
+// the uploads and deletes that turn the console's files into the directory's, in an order that
+// fits in `free` blocks: uploads first while there's room for them, so an interrupted run leaves
+// as much in place as it can, and deletes first when there isn't. A different file is deleted
+// just before its new copy is uploaded. Fails, with the blocks needed and available, if the
+// directory won't fit even once everything's deleted.
+#[cfg(feature = "writing")]
+pub fn plan(
+    diff: &Diff,
+    wanted: &[Wanted],
+    present: &[(String, u32)],
+    free: usize,
+) -> Result<Vec<Step>> {
+    let size_of = |name: &String, files: &[(String, u32)]| {
+        files.iter().find(|(n, _)| n == name).map_or(0, |(_, s)| *s)
+    };
+    let wanted_sizes = wanted
+        .iter()
+        .map(|w| (w.name.clone(), w.size))
+        .collect::<Vec<_>>();
+    let needed = diff
+        .missing
+        .iter()
+        .chain(&diff.different)
+        .map(|n| blocks(size_of(n, &wanted_sizes)))
+        .sum::<usize>();
+    let freed = diff
+        .extra
+        .iter()
+        .chain(&diff.different)
+        .map(|n| blocks(size_of(n, present)))
+        .sum::<usize>();
+    if needed > free + freed {
+        bail!(
+            "the directory's files need {needed} blocks, but only {} would be free even after deleting the files it doesn't have",
+            free + freed
+        );
+    }
+
+    let mut steps = vec![];
+    let replace = diff
+        .different
+        .iter()
+        .flat_map(|n| [Step::Delete(n.clone()), Step::Upload(n.clone())]);
+    let uploads = diff.missing.iter().map(|n| Step::Upload(n.clone()));
+    let deletes = diff.extra.iter().map(|n| Step::Delete(n.clone()));
+    if needed <= free {
+        steps.extend(uploads);
+        steps.extend(replace);
+        steps.extend(deletes);
+    } else {
+        steps.extend(deletes);
+        steps.extend(diff.different.iter().map(|n| Step::Delete(n.clone())));
+        steps.extend(uploads);
+        steps.extend(diff.different.iter().map(|n| Step::Upload(n.clone())));
+    }
+    Ok(steps)
+}
+
+// the files in `dir`, hashed with `algo`, which must all have names the console can take
+pub fn read_wanted(dir: &str, algo: HashAlgo) -> Result<Vec<Wanted>> {
+    let mut wanted = vec![];
+    let mut bad = vec![];
+    for entry in read_dir(dir).map_err(|e| anyhow!("{dir}: {e}"))? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let Ok(name) = entry.file_name().into_string() else {
+            bad.push(entry.file_name().to_string_lossy().into_owned());
+            continue;
+        };
+        if check_name(&name).iter().any(|i| i.level == Level::Error) {
+            bad.push(name);
+            continue;
+        }
+        let data = read(entry.path())?;
+        wanted.push(Wanted {
+            hash: algo.digest(&data),
+            size: data.len() as u32,
+            name,
+        });
+    }
+    if !bad.is_empty() {
+        bad.sort();
+        bail!(
+            "{dir} has files whose names the console can't take ('lint' says why): {}",
+            bad.join(", ")
+        );
+    }
+    wanted.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(wanted)
+}
+
+#[cfg_attr(not(feature = "writing"), allow(dead_code))]
+pub struct Comparison {
+    pub wanted: Vec<Wanted>,
+    // the console's files and their sizes
+    pub present: Vec<(String, u32)>,
+    pub diff: Diff,
+}
+
+// the directory, the console's files (as of its current FS, `fs`), and how they differ, both
+// sides hashed with `algo`; the console's hashes come from the cache where they can
+pub fn compare(
+    player: &dyn Player,
+    fs: &FsBlock,
+    dir: &str,
+    algo: HashAlgo,
+    cancel: &CancelToken,
+) -> Result<Comparison> {
+    let wanted = read_wanted(dir, algo)?;
+    let present = fs
+        .entries
+        .iter()
+        .map(|e| (e.name.clone(), e.size))
+        .collect::<Vec<_>>();
+    let mut cache = HashCache::load();
+    let hashes = cache.hashes(player, fs, &to_hash(&wanted, &present), algo, cancel)?;
+    if let Err(e) = cache.save() {
+        eprintln!("Couldn't save the hash cache: {e}");
+    }
+    let diff = diff(&wanted, &present, &hashes);
+    Ok(Comparison {
+        wanted,
+        present,
+        diff,
+    })
+}
+
+pub fn print_diff(diff: &Diff, dir: &str) {
+    if diff.in_sync() {
+        println!("The console's files match {dir}");
+    }
+    for (label, names) in [
+        ("Missing from the console", &diff.missing),
+        ("On the console but not in the directory", &diff.extra),
+        ("Different", &diff.different),
+        ("System files left alone", &diff.protected),
+    ] {
+        if !names.is_empty() {
+            println!("{label} ({}): {}", names.len(), names.join(", "));
+        }
+    }
+}
+
+// a file in the directory, for uploading
+#[cfg(feature = "writing")]
+pub fn read_local(dir: &str, name: &str) -> Result<Vec<u8>> {
+    let path = Path::new(dir).join(name);
+    read(&path).map_err(|e| anyhow!("{}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provisioning() -> Result<()> {
+        let hash = |sha: &str| HashValue {
+            algo: HashAlgo::Sha256,
+            bytes: sha.as_bytes().to_vec(),
+        };
+        let want = |name: &str, size, sha: &str| Wanted {
+            name: name.to_string(),
+            size,
+            hash: hash(sha),
+        };
+        let on = |name: &str, size| (name.to_string(), size);
+        let names = |n: &[&str]| n.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        let wanted = [
+            want("same.app", 0x8000, "aa"),
+            want("changed.app", 0x8000, "bb"),
+            want("resized.app", 0xC000, "cc"),
+            want("new.app", 0x10000, "dd"),
+            want("ticket.sys", 0x4000, "ee"),
+        ];
+        let present = [
+            on("same.app", 0x8000),
+            on("changed.app", 0x8000),
+            on("resized.app", 0x4000),
+            on("old.app", 0x14000),
+            on("ticket.sys", 0x4000),
+            on("crl.sys", 0x400),
+        ];
+        if to_hash(&wanted, &present) != ["same.app", "changed.app", "ticket.sys"] {
+            bail!("the files to hash were {:?}", to_hash(&wanted, &present));
+        }
+        let hashes = BTreeMap::from(
+            [
+                ("same.app", "aa"),
+                ("changed.app", "b0"),
+                ("ticket.sys", "e0"),
+            ]
+            .map(|(n, h)| (n.to_string(), hash(h))),
+        );
+        let found = diff(&wanted, &present, &hashes);
+        let expected = Diff {
+            missing: names(&["new.app"]),
+            extra: names(&["old.app"]),
+            different: names(&["changed.app", "resized.app"]),
+            protected: names(&["ticket.sys"]),
+        };
+        if found != expected {
+            bail!("the differences were {found:?}");
+        }
+
+        // the console's hash of a file from another algorithm never matches, even with the same digits
+        let mut crc_hashes = hashes.clone();
+        crc_hashes.get_mut("same.app").unwrap().algo = HashAlgo::Crc32;
+        if !diff(&wanted, &present, &crc_hashes)
+            .different
+            .contains(&"same.app".to_string())
+        {
+            bail!("a CRC-32 from the console was taken as matching the directory's SHA-256");
+        }
+
+        #[cfg(feature = "writing")]
+        {
+            use Step::*;
+            let step = |s: &str| {
+                let (verb, name) = s.split_once(' ').unwrap();
+                match verb {
+                    "delete" => Delete(name.to_string()),
+                    _ => Upload(name.to_string()),
+                }
+            };
+            let steps = |s: &[&str]| s.iter().map(|s| step(s)).collect::<Vec<_>>();
+
+            // needed: new 4 + changed 2 + resized 3 = 9 blocks; freed: old 5 + changed 2 + resized 1 = 8
+            let roomy = plan(&found, &wanted, &present, 9)?;
+            let uploads_first = steps(&[
+                "upload new.app",
+                "delete changed.app",
+                "upload changed.app",
+                "delete resized.app",
+                "upload resized.app",
+                "delete old.app",
+            ]);
+            if roomy != uploads_first {
+                bail!("with room to spare, the plan was {roomy:?}");
+            }
+            let tight = plan(&found, &wanted, &present, 1)?;
+            let deletes_first = steps(&[
+                "delete old.app",
+                "delete changed.app",
+                "delete resized.app",
+                "upload new.app",
+                "upload changed.app",
+                "upload resized.app",
+            ]);
+            if tight != deletes_first {
+                bail!("short of space, the plan was {tight:?}");
+            }
+            match plan(&found, &wanted, &present, 0) {
+                Err(e) if e.to_string().contains("need 9 blocks, but only 8") => {}
+                other => bail!("a directory that can't fit gave {other:?}"),
+            }
+            if !plan(&Diff::default(), &wanted, &present, 0)?.is_empty() {
+                bail!("a console that already matches was given steps");
+            }
+            if tight
+                .iter()
+                .chain(&roomy)
+                .any(|s| matches!(s, Delete(n) | Upload(n) if is_protected(n)))
+            {
+                bail!("a system file was touched");
+            }
+        }
+        Ok(())
+    }
+}
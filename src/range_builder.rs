@@ -0,0 +1,238 @@
+use std::ops::Range;
+
+use anyhow::{anyhow, Result};
+
+use crate::fs::{BLOCK_SIZE, FS_REGION_BLOCKS, SKSA_BLOCKS};
+use crate::ranges::{format_range, parse_ranges};
+use crate::throughput::ThroughputStats;
+
+// The dialog behind '2 --interactive': the user builds up a selection of blocks a line at a
+// time, and confirms it before anything is written. It only deals in lines of text, so it can be
+// driven by a script as well as by the prompt.
+
+const HELP: &str = "Type ranges to add (e.g. '0x40-0x100,0x200'), 'sksa' or 'fs' to add those regions,
+'remove <ranges>' to take blocks out, 'clear', 'map' to show the image, 'done' to finish, or 'cancel'.";
+
+// columns of the block map
+const MAP_WIDTH: usize = 64;
+
+pub enum Step {
+    // keep going, after printing this
+    Continue(String),
+    Done(Vec<Range<u16>>),
+    Cancelled,
+}
+
+pub struct RangeBuilder {
+    num_blocks: u16,
+    // whether each block of the image is all 0xFF
+    erased: Vec<bool>,
+    // one flag per block, so ranges can be added and removed in any order
+    selected: Vec<bool>,
+    confirming: bool,
+    stats: ThroughputStats,
+    bbid: Option<u32>,
+}
+
+impl RangeBuilder {
+    pub fn new(image: &[u8], stats: ThroughputStats, bbid: Option<u32>) -> Self {
+        let erased = image
+            .chunks(BLOCK_SIZE)
+            .map(|b| b.iter().all(|&x| x == 0xFF))
+            .collect::<Vec<_>>();
+        Self {
+            num_blocks: erased.len() as u16,
+            selected: vec![false; erased.len()],
+            erased,
+            confirming: false,
+            stats,
+            bbid,
+        }
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.confirming {
+            "Write these blocks? [y/N] "
+        } else {
+            "ranges> "
+        }
+    }
+
+    // the selection as the fewest ranges, in order
+    pub fn ranges(&self) -> Vec<Range<u16>> {
+        let mut ranges: Vec<Range<u16>> = vec![];
+        for (blk, _) in self.selected.iter().enumerate().filter(|(_, &s)| s) {
+            let blk = blk as u16;
+            match ranges.last_mut() {
+                Some(r) if r.end == blk => r.end += 1,
+                _ => ranges.push(blk..blk + 1),
+            }
+        }
+        ranges
+    }
+
+    // the selection in the syntax '2' takes
+    pub fn canonical(&self) -> String {
+        self.ranges()
+            .iter()
+            .map(format_range)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    fn named(&self, name: &str) -> Option<Range<u16>> {
+        match name {
+            "sksa" => Some(0..SKSA_BLOCKS.min(self.num_blocks)),
+            "fs" => Some(self.num_blocks.saturating_sub(FS_REGION_BLOCKS as u16)..self.num_blocks),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, selection: &str, value: bool) -> Result<()> {
+        let ranges = match self.named(selection) {
+            Some(r) => vec![r],
+            None => parse_ranges(selection, self.num_blocks)?,
+        };
+        for r in ranges {
+            self.selected[r.start as usize..r.end as usize].fill(value);
+        }
+        Ok(())
+    }
+
+    pub fn summary(&self) -> String {
+        let count = self.selected.iter().filter(|&&s| s).count();
+        if count == 0 {
+            return "Nothing selected".to_string();
+        }
+        let estimate = self.stats.estimate(self.bbid, (count * BLOCK_SIZE) as u64);
+        format!(
+            "Selected: {} ({count} blocks, {estimate})",
+            self.canonical()
+        )
+    }
+
+    // a line per MAP_WIDTH columns, each column standing for a few blocks: '*' if any of them are
+    // selected, else '#' if any of them hold data, else '.'
+    pub fn map(&self) -> String {
+        let per_column = (self.num_blocks as usize).div_ceil(MAP_WIDTH * 4).max(1);
+        let columns = self
+            .selected
+            .chunks(per_column)
+            .zip(self.erased.chunks(per_column))
+            .map(|(selected, erased)| {
+                if selected.iter().any(|&s| s) {
+                    '*'
+                } else if erased.iter().any(|&e| !e) {
+                    '#'
+                } else {
+                    '.'
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut map =
+            format!("Image map ({per_column} blocks a character; * selected, # data, . erased):\n");
+        for (i, line) in columns.chunks(MAP_WIDTH).enumerate() {
+            map += &format!(
+                "{:#06X} {}\n",
+                i * MAP_WIDTH * per_column,
+                line.iter().collect::<String>()
+            );
+        }
+        map
+    }
+
+    pub fn start(&self) -> String {
+        format!("{}{HELP}", self.map())
+    }
+
+    pub fn handle(&mut self, input: &str) -> Step {
+        let input = input.trim();
+        if self.confirming {
+            self.confirming = false;
+            return match input {
+                "y" | "Y" => Step::Done(self.ranges()),
+                _ => Step::Continue("Not confirmed; carry on changing the selection".to_string()),
+            };
+        }
+        if input.is_empty() {
+            return Step::Continue(self.summary());
+        }
+        let result = match input.split_once(' ') {
+            Some(("remove", selection)) => self.set(selection.trim(), false),
+            _ => match input {
+                "cancel" | "q" => return Step::Cancelled,
+                "help" | "h" => return Step::Continue(HELP.to_string()),
+                "map" => return Step::Continue(self.map()),
+                "clear" => {
+                    self.selected.fill(false);
+                    Ok(())
+                }
+                "done" if self.selected.iter().any(|&s| s) => {
+                    self.confirming = true;
+                    return Step::Continue(format!(
+                        "{}\nThe same selection can be given to '2' as: {}",
+                        self.summary(),
+                        self.canonical()
+                    ));
+                }
+                "done" => Err(anyhow!("Nothing is selected yet")),
+                selection => self.set(selection, true),
+            },
+        };
+        match result {
+            Ok(_) => Step::Continue(self.summary()),
+            Err(e) => Step::Continue(format!("{e}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    #[test]
+    fn range_builder() -> Result<()> {
+        let mut image = vec![0xFF; 0x100 * BLOCK_SIZE];
+        image[..BLOCK_SIZE].fill(0);
+        let mut builder = RangeBuilder::new(&image, ThroughputStats::default(), None);
+
+        // (input, the canonical selection after it, or None if it finishes the dialog)
+        let script: [(&str, Option<&str>); 9] = [
+            ("0x40-0x80", Some("0x40-0x80")),
+            ("fs", Some("0x40-0x80,0xF0-0x100")),
+            ("remove 0x50-0x60", Some("0x40-0x50,0x60-0x80,0xF0-0x100")),
+            ("0x50-0x60,0x80", Some("0x40-0x81,0xF0-0x100")),
+            ("0x200", Some("0x40-0x81,0xF0-0x100")),
+            ("done", Some("0x40-0x81,0xF0-0x100")),
+            ("n", Some("0x40-0x81,0xF0-0x100")),
+            ("done", Some("0x40-0x81,0xF0-0x100")),
+            ("y", None),
+        ];
+        for (input, expected) in script {
+            match (builder.handle(input), expected) {
+                (Step::Continue(_), Some(e)) if builder.canonical() == e => {}
+                (Step::Done(ranges), None) if ranges == [0x40..0x81, 0xF0..0x100] => {}
+                _ => bail!("'{input}' left the selection as {}", builder.canonical()),
+            }
+        }
+
+        let mut builder = RangeBuilder::new(&image, ThroughputStats::default(), None);
+        for (input, done) in [("done", false), ("sksa", false), ("cancel", true)] {
+            match builder.handle(input) {
+                Step::Continue(_) if !done => {}
+                Step::Cancelled if done => {}
+                _ => bail!("'{input}' didn't do what was expected"),
+            }
+        }
+        if !builder
+            .map()
+            .lines()
+            .nth(1)
+            .is_some_and(|l| l.ends_with(&"*".repeat(64)))
+        {
+            bail!("the block map doesn't show the selection");
+        }
+        Ok(())
+    }
+}
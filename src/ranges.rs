@@ -0,0 +1,116 @@
+use std::ops::Range;
+
+use anyhow::{anyhow, bail, Result};
+use parse_int::parse;
+
+/// Parses a block selection like "0-0x100,4075" into ranges, in the order given; "a-b" is
+/// exclusive of b, and either end may be left off to mean the start or end of a card of
+/// `num_blocks` blocks. Fails on a range that's empty or goes past the end.
+pub fn parse_ranges(selection: &str, num_blocks: u16) -> Result<Vec<Range<u16>>> {
+    let parse_block = |s: &str| parse::<u16>(s).map_err(|e| anyhow!("'{s}': {e}"));
+
+    let mut ranges = vec![];
+    for sect in selection.split(',') {
+        let range = match sect.split('-').collect::<Vec<_>>()[..] {
+            [single] => {
+                let blk = parse_block(single)?;
+                blk..blk.saturating_add(1)
+            }
+            [start, end] => {
+                let start = if start.is_empty() {
+                    0
+                } else {
+                    parse_block(start)?
+                };
+                let end = if end.is_empty() {
+                    num_blocks
+                } else {
+                    parse_block(end)?
+                };
+                start..end
+            }
+            _ => bail!("Invalid block range selection '{sect}'"),
+        };
+        if range.is_empty() || range.end > num_blocks {
+            bail!("Invalid block range selection '{sect}' (the image has {num_blocks:#X} blocks)");
+        }
+        ranges.push(range);
+    }
+    Ok(ranges)
+}
+
+/// Sorted blocks as the fewest ranges; block 0xFFFF, which no range can end after, is left out,
+/// as no card has that many.
+pub fn block_ranges(blocks: &[u16]) -> Vec<Range<u16>> {
+    let mut ranges: Vec<Range<u16>> = vec![];
+    for &blk in blocks {
+        let Some(end) = blk.checked_add(1) else {
+            continue;
+        };
+        match ranges.last_mut() {
+            Some(r) if r.end == blk => r.end = end,
+            _ => ranges.push(blk..end),
+        }
+    }
+    ranges
+}
+
+/// A range as a selection would give it: "0x10-0x20", or "0x5" for a single block.
+pub fn format_range(range: &Range<u16>) -> String {
+    if range.len() == 1 {
+        format!("{:#X}", range.start)
+    } else {
+        format!("{:#X}-{:#X}", range.start, range.end)
+    }
+}
+
+pub(crate) fn self_test() -> Result<()> {
+    // (selection, expected (start, end) pairs)
+    let cases: [(&str, &[(u16, u16)]); 4] = [
+        ("0-0x100,4075", &[(0, 0x100), (4075, 4076)]),
+        ("-0x40", &[(0, 0x40)]),
+        ("0xFF0-", &[(0xFF0, 0x1000)]),
+        ("7", &[(7, 8)]),
+    ];
+    for (selection, expected) in cases {
+        let ranges = parse_ranges(selection, 0x1000)?;
+        if !ranges
+            .iter()
+            .map(|r| (r.start, r.end))
+            .eq(expected.iter().copied())
+        {
+            bail!("'{selection}' parsed as {ranges:?}, not {expected:X?}");
+        }
+    }
+    for bad in ["0x10-0x10", "0-0x1001", "1-2-3", "x"] {
+        if parse_ranges(bad, 0x1000).is_ok() {
+            bail!("'{bad}' was accepted");
+        }
+    }
+    if format_range(&(0x10..0x20)) != "0x10-0x20" || format_range(&(5..6)) != "0x5" {
+        bail!("ranges formatted incorrectly");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn vectors() -> anyhow::Result<()> {
+        super::self_test()
+    }
+
+    #[test]
+    fn runs() {
+        assert_eq!(
+            super::block_ranges(&[1, 2, 3, 7, 9, 10]),
+            [1..4, 7..8, 9..11]
+        );
+        // a run up to the last block there could be stops short of it, rather than overflowing
+        assert_eq!(
+            super::block_ranges(&[0xFFF0, 0xFFFD, 0xFFFE, 0xFFFF]),
+            [0xFFF0..0xFFF1, 0xFFFD..0xFFFF]
+        );
+        assert_eq!(super::block_ranges(&[0xFFFF]), []);
+    }
+}
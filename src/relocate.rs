@@ -0,0 +1,336 @@
+use std::collections::BTreeSet;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::badblocks::{spare_for, treat_as_bad, Overrides};
+use crate::fs::{
+    FsBlock, BLOCK_SIZE, FAT_BAD, FAT_RESERVED, FS_REGION_BLOCKS, SKSA_BLOCKS, SPARE_SIZE,
+};
+use crate::heroic::vote;
+use crate::journal::Journal;
+use crate::nand_read::card_blocks;
+use crate::player::{Backend, Player, PlayerBlockWrite};
+use crate::spare::{ecc_matches, is_bad_block, mark_bad};
+
+// a failing block often reads cleanly now and then, so it's read this many times before giving up
+const READ_ATTEMPTS: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Move {
+    pub from: u16,
+    // the file the block belongs to and the block its data goes to; None if no file owns it, so
+    // it's only marked bad
+    pub to: Option<(String, u16)>,
+}
+
+pub struct Plan {
+    // the FS generation to write once the data has been copied
+    pub fs: FsBlock,
+    pub moves: Vec<Move>,
+}
+
+// the file whose chain `blk` is in, and where in the chain it is
+fn owner(fs: &FsBlock, blk: u16) -> Option<(usize, usize)> {
+    fs.entries.iter().enumerate().find_map(|(i, e)| {
+        let position = fs.chain(e.start).ok()?.iter().position(|&b| b == blk)?;
+        Some((i, position))
+    })
+}
+
+// works out where each block's data goes and the FS that results: each block that's part of a
+// file is swapped out of its chain for a free block, and every block is marked bad in the FAT
+pub fn plan(current: &FsBlock, blocks: &[u16], num_blocks: u16) -> Result<Plan> {
+    let fs_start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    let usable = |b: u16| (SKSA_BLOCKS..fs_start).contains(&b) && !blocks.contains(&b);
+    let mut fs = current.clone();
+    let mut moves = vec![];
+    for &blk in blocks {
+        if !(SKSA_BLOCKS..fs_start).contains(&blk) {
+            bail!("block {blk:#X} is in the SKSA or FS region, or past the end of the card, so it can't be relocated");
+        }
+        match fs.fat.get(blk as usize) {
+            None => bail!("block {blk:#X} is past the end of the FAT"),
+            Some(&FAT_BAD) => bail!("block {blk:#X} is already marked bad"),
+            Some(&FAT_RESERVED) => bail!("block {blk:#X} is reserved"),
+            Some(_) => {}
+        }
+        let Some((entry, position)) = owner(&fs, blk) else {
+            fs.fat[blk as usize] = FAT_BAD;
+            moves.push(Move {
+                from: blk,
+                to: None,
+            });
+            continue;
+        };
+        let chain = fs.chain(fs.entries[entry].start)?;
+        let to = fs.allocate(1, usable)?[0];
+        fs.fat[to as usize] = fs.fat[blk as usize];
+        fs.fat[blk as usize] = FAT_BAD;
+        match position {
+            0 => fs.entries[entry].start = to,
+            p => fs.fat[chain[p - 1] as usize] = to,
+        }
+        moves.push(Move {
+            from: blk,
+            to: Some((fs.entries[entry].name.clone(), to)),
+        });
+    }
+    fs.seqno += 1;
+    Ok(Plan { fs, moves })
+}
+
+// reads a block until its data matches the ECC in its spare data; with `heroic`, failing that,
+// votes across the reads
+fn read_clean(player: &dyn Backend, blk: u16, heroic: bool) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut last = String::new();
+    let mut reads = vec![];
+    for _ in 0..READ_ATTEMPTS {
+        match player.ReadSingleBlock(blk as u32) {
+            Ok((data, spare)) if ecc_matches(&data, &spare) => return Ok((data, spare)),
+            Ok(read) => {
+                last = "the data didn't match its ECC".to_string();
+                reads.push(read);
+            }
+            Err(e) => last = e.to_string(),
+        }
+    }
+    if heroic {
+        if let Some((data, spare, bits)) = vote(&reads) {
+            println!(
+                "Block {blk:#X}: rescued {bits} bits by voting across {} reads",
+                reads.len()
+            );
+            return Ok((data, spare));
+        }
+    }
+    bail!("block {blk:#X} couldn't be read cleanly in {READ_ATTEMPTS} attempts (last: {last})")
+}
+
+pub fn write_verified(
+    player: &dyn PlayerBlockWrite,
+    blk: u16,
+    data: &[u8],
+    spare: &[u8],
+) -> Result<()> {
+    player.WriteSingleBlock(blk as u32, data, spare)?;
+    let (read_back, _) = player.ReadSingleBlock(blk as u32)?;
+    if read_back != data {
+        bail!("block {blk:#X} didn't read back as written");
+    }
+    Ok(())
+}
+
+// the newest valid FS generation in the FS region, and the block it's in
+pub fn newest_fs(player: &dyn Player, num_blocks: u16) -> Result<(u16, FsBlock)> {
+    let start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    (start..num_blocks)
+        .filter_map(|blk| {
+            Some((
+                blk,
+                FsBlock::parse(&player.ReadSingleBlock(blk as u32).ok()?.0).ok()?,
+            ))
+        })
+        .max_by_key(|(_, fs)| fs.seqno)
+        .ok_or_else(|| anyhow!("no valid FS blocks found on the card"))
+}
+
+// like the console, the next good block of the FS region after the current generation's; a
+// marked block counts as good if it's in `trusted`
+pub fn next_fs_block(
+    player: &dyn Player,
+    current: u16,
+    num_blocks: u16,
+    trusted: &BTreeSet<u16>,
+) -> Result<u16> {
+    let start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    let len = num_blocks - start;
+    (1..=len)
+        .map(|i| start + (current - start + i) % len)
+        .find(|&blk| {
+            player
+                .ReadSingleBlock(blk as u32)
+                .is_ok_and(|(_, spare)| !treat_as_bad(blk, &spare, trusted))
+        })
+        .ok_or_else(|| anyhow!("every block of the FS region is bad"))
+}
+
+// copies the data off each block, writes the FS generation that points at the copies, and
+// then marks the old blocks bad; the copies come first so that an interruption at any point
+// leaves a card whose newest FS is consistent
+fn run(
+    player: &dyn Backend,
+    blocks: &[u16],
+    heroic: bool,
+    trusted: &BTreeSet<u16>,
+    journal: &mut Journal,
+) -> Result<Plan> {
+    let num_blocks = card_blocks(player)? as u16;
+    let (fs_blk, current) = newest_fs(player, num_blocks)?;
+    if current.linked {
+        bail!("multi-block FATs aren't supported");
+    }
+    let plan = plan(&current, blocks, num_blocks)?;
+    for m in &plan.moves {
+        journal.record(&match &m.to {
+            Some((name, to)) => format!("plan: move block {:#X} of {name} to {to:#X}", m.from),
+            None => format!("plan: mark block {:#X} bad (not part of a file)", m.from),
+        })?;
+    }
+
+    let mut old = vec![];
+    for m in &plan.moves {
+        let Some((name, to)) = &m.to else {
+            let (data, spare) = player
+                .ReadSingleBlock(m.from as u32)
+                .unwrap_or_else(|_| (vec![0xFF; BLOCK_SIZE], vec![0xFF; SPARE_SIZE]));
+            old.push((m.from, data, spare));
+            continue;
+        };
+        let (data, spare) = read_clean(player, m.from, heroic)?;
+        let (_, existing) = player.ReadSingleBlock(*to as u32)?;
+        write_verified(
+            player,
+            *to,
+            &data,
+            &spare_for(*to, &data, &existing, trusted)?,
+        )?;
+        journal.record(&format!(
+            "copied block {:#X} of {name} to {to:#X}; verified",
+            m.from
+        ))?;
+        old.push((m.from, data, spare));
+    }
+
+    let next = next_fs_block(player, fs_blk, num_blocks, trusted)?;
+    let data = plan.fs.to_bytes()?;
+    let (_, existing) = player.ReadSingleBlock(next as u32)?;
+    write_verified(
+        player,
+        next,
+        &data,
+        &spare_for(next, &data, &existing, trusted)?,
+    )?;
+    journal.record(&format!(
+        "wrote FS #{} to block {next:#X}; verified",
+        plan.fs.seqno
+    ))?;
+
+    // the FAT already says these are bad, so a block too far gone to take the marker isn't fatal
+    for (blk, data, mut spare) in old {
+        mark_bad(&mut spare);
+        let marked = player
+            .WriteSingleBlock(blk as u32, &data, &spare)
+            .and_then(|_| player.ReadSingleBlock(blk as u32))
+            .map(|(_, spare)| is_bad_block(&spare));
+        let step = match marked {
+            Ok(true) => format!("marked block {blk:#X} bad in its spare data; verified"),
+            Ok(false) => format!("block {blk:#X} didn't keep the bad block marker; the FAT still marks it bad"),
+            Err(e) => format!("couldn't mark block {blk:#X} bad in its spare data ({e}); the FAT still marks it bad"),
+        };
+        println!("{step}");
+        journal.record(&step)?;
+    }
+    Ok(plan)
+}
+
+// returns the sequence number of the FS generation it wrote
+pub fn relocate(player: &dyn Backend, blocks: &[u16], heroic: bool) -> Result<u32> {
+    let bbid = player.GetBBID()?;
+    let mut journal = Journal::open(bbid, "relocate")?;
+    let mut overrides = Overrides::load(bbid).unwrap_or_else(|e| {
+        eprintln!("Note: couldn't read the console's bad block overrides, so every marked block is treated as bad: {e}");
+        Overrides::default()
+    });
+    let list = blocks
+        .iter()
+        .map(|b| format!("{b:#X}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    journal.record(&format!("begin: {list}"))?;
+    match run(player, blocks, heroic, &overrides.trusted(), &mut journal) {
+        Ok(plan) => {
+            for m in &plan.moves {
+                if let Some((name, to)) = &m.to {
+                    println!("Moved block {:#X} of {name} to {to:#X}", m.from);
+                }
+            }
+            // they're bad on purpose now, whatever an audit said of them before
+            if overrides.forget(blocks) {
+                if let Err(e) = overrides.save(bbid) {
+                    eprintln!("Couldn't update the console's bad block overrides: {e}");
+                }
+            }
+            journal.record("done")?;
+            Ok(plan.fs.seqno)
+        }
+        Err(e) => {
+            // the error matters more than whether it could be journaled
+            let _ = journal.record(&format!("failed: {e}"));
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecc::page_ecc;
+    use crate::fs::{FsEntry, FAT_END, FAT_ENTRIES, FAT_FREE};
+
+    #[test]
+    fn relocation() -> Result<()> {
+        let mut fat = vec![FAT_FREE; FAT_ENTRIES];
+        // GAME.app is 0x40 -> 0x41 -> 0x42; copies go to the first free blocks, 0x43 and on
+        fat[0x40] = 0x41;
+        fat[0x41] = 0x42;
+        fat[0x42] = FAT_END;
+        let fs = FsBlock {
+            fat,
+            entries: vec![FsEntry::new("GAME.app", 0x40, 3 * BLOCK_SIZE as u32)],
+            linked: false,
+            seqno: 9,
+        };
+
+        let moved = plan(&fs, &[0x41, 0x40, 0x50], 0x1000)?;
+        let expected = [
+            Move {
+                from: 0x41,
+                to: Some(("GAME.app".to_string(), 0x43)),
+            },
+            Move {
+                from: 0x40,
+                to: Some(("GAME.app".to_string(), 0x44)),
+            },
+            Move {
+                from: 0x50,
+                to: None,
+            },
+        ];
+        if moved.moves != expected {
+            bail!("planned {:X?}", moved.moves);
+        }
+        if moved.fs.chain(moved.fs.entries[0].start)? != [0x44, 0x43, 0x42] || moved.fs.seqno != 10
+        {
+            bail!("the relocated chain or sequence number is wrong");
+        }
+        if [0x40, 0x41, 0x50]
+            .iter()
+            .any(|&b| moved.fs.fat[b] != FAT_BAD)
+        {
+            bail!("relocated blocks weren't marked bad");
+        }
+
+        for blocks in [&[0x10][..], &[0xFF8], &[0x50, 0x50]] {
+            if plan(&fs, blocks, 0x1000).is_ok() {
+                bail!("relocating {blocks:X?} was allowed");
+            }
+        }
+        let mut spare = [0xFF; SPARE_SIZE];
+        page_ecc(&[0; BLOCK_SIZE], &mut spare)?;
+        mark_bad(&mut spare);
+        if !is_bad_block(&spare) {
+            bail!("a block marked bad doesn't read as bad");
+        }
+        Ok(())
+    }
+}
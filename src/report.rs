@@ -0,0 +1,201 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::acceptance::CheckResult;
+use crate::jobs::say;
+use crate::player::Player;
+use crate::scrub::{recommend, BlockResult, Health};
+use crate::sink::write_atomic;
+use crate::summary::RangeSummary;
+use crate::{PROG_NAME, PROG_VER};
+
+// bumped whenever a field is removed or changes meaning; adding fields doesn't bump it
+pub const REPORT_SCHEMA: u32 = 1;
+
+#[derive(Serialize)]
+pub struct CardReport {
+    pub free: u32,
+    pub used: u32,
+    pub bad: u32,
+    pub seqno: u32,
+}
+
+impl CardReport {
+    fn read(player: &dyn Player) -> Option<Self> {
+        player.CardStats().ok().map(|c| Self {
+            free: c.free,
+            used: c.used,
+            bad: c.bad,
+            seqno: c.seqno,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct RangeReport {
+    pub start: u16,
+    pub end: u16,
+    pub blocks_checked: usize,
+    pub mismatches: Vec<u16>,
+    pub local_sha256: Option<String>,
+    pub console_sha256: Option<String>,
+}
+
+// a durable record of a verification ('verify' or '2 --verify'), written as JSON
+#[derive(Serialize)]
+pub struct VerifyReport {
+    pub schema: u32,
+    pub tool: &'static str,
+    pub version: &'static str,
+    pub operation: &'static str,
+    pub bbid: Option<String>,
+    pub card: Option<CardReport>,
+    pub started: String,
+    pub finished: String,
+    pub complete: bool,
+    pub total_mismatches: usize,
+    pub ranges: Vec<RangeReport>,
+}
+
+impl VerifyReport {
+    pub fn new(
+        operation: &'static str,
+        player: &dyn Player,
+        summary: &RangeSummary,
+        started: DateTime<Local>,
+        complete: bool,
+    ) -> Self {
+        Self {
+            schema: REPORT_SCHEMA,
+            tool: PROG_NAME,
+            version: PROG_VER,
+            operation,
+            bbid: player.GetBBID().ok().map(|b| format!("{b:08X}")),
+            card: CardReport::read(player),
+            started: started.to_rfc3339(),
+            finished: Local::now().to_rfc3339(),
+            complete,
+            total_mismatches: summary.total_mismatches(),
+            ranges: summary
+                .outcomes
+                .iter()
+                .map(|o| RangeReport {
+                    start: o.range.start,
+                    end: o.range.end,
+                    blocks_checked: o.done,
+                    mismatches: o.mismatches.clone().unwrap_or_default(),
+                    local_sha256: o.hashes.as_ref().map(|h| h.0.clone()),
+                    console_sha256: o.hashes.as_ref().map(|h| h.1.clone()),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn save(&self, path: &str) {
+        save(self, "verification", path);
+    }
+}
+
+// the results of a 'scrub', written as JSON; blocks that read cleanly aren't listed
+#[derive(Serialize)]
+pub struct ScrubReport {
+    pub schema: u32,
+    pub tool: &'static str,
+    pub version: &'static str,
+    pub operation: &'static str,
+    pub bbid: Option<String>,
+    pub card: Option<CardReport>,
+    pub started: String,
+    pub finished: String,
+    pub complete: bool,
+    pub blocks_scrubbed: usize,
+    pub problems: Vec<BlockResult>,
+    pub recommendations: Vec<String>,
+}
+
+impl ScrubReport {
+    pub fn new(
+        player: &dyn Player,
+        results: &[BlockResult],
+        started: DateTime<Local>,
+        complete: bool,
+    ) -> Self {
+        Self {
+            schema: REPORT_SCHEMA,
+            tool: PROG_NAME,
+            version: PROG_VER,
+            operation: "scrub",
+            bbid: player.GetBBID().ok().map(|b| format!("{b:08X}")),
+            card: CardReport::read(player),
+            started: started.to_rfc3339(),
+            finished: Local::now().to_rfc3339(),
+            complete,
+            blocks_scrubbed: results.len(),
+            problems: results
+                .iter()
+                .filter(|r| r.health != Health::Ok)
+                .cloned()
+                .collect(),
+            recommendations: recommend(results),
+        }
+    }
+
+    pub fn save(&self, path: &str) {
+        save(self, "scrub", path);
+    }
+}
+
+// the verdict of an 'acceptance' run and what each check found, written as JSON
+#[derive(Serialize)]
+pub struct AcceptanceReport {
+    pub schema: u32,
+    pub tool: &'static str,
+    pub version: &'static str,
+    pub operation: &'static str,
+    pub bbid: Option<String>,
+    pub card: Option<CardReport>,
+    pub started: String,
+    pub finished: String,
+    pub image_dir: String,
+    pub passed: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+impl AcceptanceReport {
+    pub fn new(
+        player: &dyn Player,
+        image_dir: &str,
+        results: Vec<CheckResult>,
+        passed: bool,
+        started: DateTime<Local>,
+        finished: DateTime<Local>,
+    ) -> Self {
+        Self {
+            schema: REPORT_SCHEMA,
+            tool: PROG_NAME,
+            version: PROG_VER,
+            operation: "acceptance",
+            bbid: player.GetBBID().ok().map(|b| format!("{b:08X}")),
+            card: CardReport::read(player),
+            started: started.to_rfc3339(),
+            finished: finished.to_rfc3339(),
+            image_dir: image_dir.to_string(),
+            passed,
+            checks: results,
+        }
+    }
+
+    pub fn save(&self, path: &str) {
+        save(self, "acceptance", path);
+    }
+}
+
+fn save(report: &impl Serialize, what: &str, path: &str) {
+    match serde_json::to_vec_pretty(report)
+        .map_err(anyhow::Error::from)
+        .and_then(|data| write_atomic(path, &data))
+    {
+        Ok(_) => say(format!("Wrote {what} report to {path}")),
+        Err(e) => eprintln!("Couldn't write the {what} report to {path}: {e}"),
+    }
+}
@@ -0,0 +1,269 @@
+use std::fs::{read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+use chrono::Local;
+
+use crate::compress::read_input;
+use crate::provenance::{parse_dumped, DUMPED_TAG, MANIFEST_EXT};
+use crate::strict::Manifest;
+
+// 'set require-backup <hours>': a write over the whole card, or its SKSA or FS region, needs a
+// dump of the same console from the last <hours> hours whose files still match its manifest.
+// Dumps are found by the manifests '1 --manifest' writes, which record the console they came from
+// and when, anywhere under the backup directory; one from before the time was recorded goes by
+// when the manifest was last changed.
+
+// how far down the backup directory manifests are looked for; 'organize' puts them three down,
+// in archive/<BBID>/<date>/
+const SEARCH_DEPTH: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    pub manifest: PathBuf,
+    pub bbid: Option<u32>,
+    // when it was dumped, in seconds since the Unix epoch
+    pub dumped: Option<i64>,
+}
+
+// how long ago `secs` seconds was, roughly
+fn age(secs: i64) -> String {
+    match secs {
+        s if s < 2 * 3600 => format!("{} minutes", s.max(0) / 60),
+        s if s < 48 * 3600 => format!("{} hours", s / 3600),
+        s => format!("{} days", s / 86400),
+    }
+}
+
+// the newest of `backups` that's of console `bbid`, from the last `max_age` seconds before
+// `now`, and passes `verify` (which is only asked about those, newest first, until one does); or
+// why there's none
+pub fn evaluate<'a>(
+    backups: &'a [Backup],
+    bbid: u32,
+    now: i64,
+    max_age: i64,
+    verify: &mut dyn FnMut(&Backup) -> Result<()>,
+) -> Result<&'a Backup> {
+    let mut own = backups
+        .iter()
+        .filter(|b| b.bbid == Some(bbid))
+        .collect::<Vec<_>>();
+    if own.is_empty() {
+        bail!("there's no dump of console {bbid:08X} with a manifest recording it");
+    }
+    own.sort_by_key(|b| std::cmp::Reverse(b.dumped));
+    let recent = own
+        .iter()
+        .filter(|b| b.dumped.is_some_and(|d| now - d <= max_age))
+        .collect::<Vec<_>>();
+    let Some(newest) = own[0].dumped else {
+        bail!(
+            "no dump of console {bbid:08X} records when it was made ({} doesn't)",
+            own[0].manifest.display()
+        );
+    };
+    if recent.is_empty() {
+        bail!(
+            "the newest dump of console {bbid:08X} ({}) is {} old, older than require-backup allows",
+            own[0].manifest.display(),
+            age(now - newest)
+        );
+    }
+    let mut failures = vec![];
+    for backup in recent {
+        match verify(backup) {
+            Ok(()) => return Ok(backup),
+            Err(e) => failures.push(format!("{}: {e}", backup.manifest.display())),
+        }
+    }
+    Err(anyhow!(
+        "no recent dump of console {bbid:08X} still matches its manifest ({})",
+        failures.join("; ")
+    ))
+}
+
+// what a manifest says about its dump, or None if it isn't one
+fn read_backup(path: &Path) -> Option<Backup> {
+    let text = read_to_string(path).ok()?;
+    let manifest = Manifest::parse(&text).ok()?;
+    let dumped = if text.lines().any(|l| l.trim().starts_with(DUMPED_TAG)) {
+        parse_dumped(&text).map(|d| d.timestamp())
+    } else {
+        path.metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+    };
+    Some(Backup {
+        manifest: path.to_path_buf(),
+        bbid: manifest.source_bbid,
+        dumped,
+    })
+}
+
+// every dump manifest in `dir` and the directories below it
+pub fn find_backups(dir: &Path) -> Vec<Backup> {
+    let mut found = vec![];
+    let mut dirs = vec![(dir.to_path_buf(), 0)];
+    while let Some((dir, depth)) = dirs.pop() {
+        let Ok(entries) = read_dir(&dir) else {
+            continue;
+        };
+        for path in entries.flatten().map(|e| e.path()) {
+            if path.is_dir() {
+                if depth < SEARCH_DEPTH {
+                    dirs.push((path, depth + 1));
+                }
+            } else if path.extension().is_some_and(|e| e == MANIFEST_EXT) {
+                found.extend(read_backup(&path));
+            }
+        }
+    }
+    found.sort_by(|a, b| a.manifest.cmp(&b.manifest));
+    found
+}
+
+// whether the files a manifest lists, beside it, still have the hashes it gives
+pub fn verify_backup(backup: &Backup) -> Result<()> {
+    let manifest = Manifest::load(&backup.manifest.to_string_lossy())?;
+    if manifest.entries().is_empty() {
+        bail!("it lists no files");
+    }
+    let dir = backup.manifest.parent().unwrap_or(Path::new("."));
+    for (expected, name) in manifest.entries() {
+        let data = read_input(&dir.join(name).to_string_lossy())?;
+        if expected.algo.digest(&data) != *expected {
+            bail!("{name} has changed since it was dumped");
+        }
+    }
+    Ok(())
+}
+
+// the newest dump of console `bbid` under `dir` in the last `hours` hours whose files still
+// match it
+pub fn recent_backup(dir: &Path, bbid: u32, hours: u64) -> Result<Backup> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let backups = find_backups(dir);
+    let found = evaluate(&backups, bbid, now, hours as i64 * 3600, &mut verify_backup)?;
+    Ok(found.clone())
+}
+
+// names for a dump made so a write can go ahead: the console, then the time, in `dir`
+pub fn dump_names(dir: &str, bbid: u32) -> (String, String) {
+    let stamp = Local::now().format("%Y%m%d-%H%M%S").to_string();
+    let name = |part: &str| {
+        Path::new(dir)
+            .join(format!("{bbid:08X}-{stamp}-{part}.bin"))
+            .to_string_lossy()
+            .into_owned()
+    };
+    (name("nand"), name("spare"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn backup_policy() -> Result<()> {
+        use crate::hashing::HashAlgo;
+        use crate::provenance::dump_manifest;
+
+        let backup = |name: &str, bbid: u32, dumped: Option<i64>| Backup {
+            manifest: PathBuf::from(name),
+            bbid: Some(bbid),
+            dumped,
+        };
+        let now = 100 * 3600;
+        let day = 24 * 3600;
+        let backups = [
+            backup("old.sha256", 0xA, Some(now - 3 * day)),
+            backup("new.sha256", 0xA, Some(now - 3600)),
+            backup("newer.sha256", 0xA, Some(now - 600)),
+            backup("other.sha256", 0xB, Some(now - 60)),
+            backup("undated.sha256", 0xC, None),
+        ];
+        let mut verified = vec![];
+        let mut all_good = |b: &Backup| {
+            verified.push(b.manifest.clone());
+            Ok(())
+        };
+
+        // the newest recent one of the right console, and only it is verified
+        let found = evaluate(&backups, 0xA, now, day, &mut all_good)?;
+        if found.manifest != Path::new("newer.sha256") || verified.len() != 1 {
+            bail!("{found:?} was chosen, verifying {verified:?}");
+        }
+        // one that no longer verifies is passed over for the next newest
+        let mut newest_bad = |b: &Backup| match b.manifest == Path::new("newer.sha256") {
+            true => bail!("nand.bin has changed since it was dumped"),
+            false => Ok(()),
+        };
+        if evaluate(&backups, 0xA, now, day, &mut newest_bad)?.manifest != Path::new("new.sha256") {
+            bail!("a dump that didn't verify was chosen");
+        }
+
+        let refused = |result: Result<&Backup>, what: &str| -> Result<()> {
+            match result {
+                Err(e) if e.to_string().contains(what) => Ok(()),
+                other => bail!("expected a refusal saying '{what}', got {other:?}"),
+            }
+        };
+        let mut all_bad = |_: &Backup| bail!("spare.bin has changed since it was dumped");
+        refused(
+            evaluate(&backups, 0xA, now, day, &mut all_bad),
+            "still matches its manifest (new",
+        )?;
+        // too old, another console's, or with no time recorded
+        refused(
+            evaluate(&backups, 0xA, now + 2 * day, day, &mut |_| Ok(())),
+            "is 2 days old",
+        )?;
+        refused(
+            evaluate(&backups, 0xD, now, day, &mut |_| Ok(())),
+            "no dump of console 0000000D",
+        )?;
+        refused(
+            evaluate(&backups, 0xC, now, day, &mut |_| Ok(())),
+            "records when it was made",
+        )?;
+        if evaluate(&backups, 0xA, now, 0, &mut |_| Ok(())).is_ok() {
+            bail!("a dump was accepted with no age allowed");
+        }
+
+        // manifests are found below the directory, and their files checked against them
+        let dir = TestDir::new("backup")?;
+        let dated = dir.join("archive").join("0000000A").join("2024-05-02");
+        std::fs::create_dir_all(&dated)?;
+        let nand = vec![0x5A; 0x200];
+        let text = dump_manifest(Some(0xA), None, &[("nand.bin", &nand)], &[HashAlgo::Sha256]);
+        std::fs::write(dated.join("nand.bin"), &nand)?;
+        std::fs::write(dated.join("nand.bin.sha256"), &text)?;
+        std::fs::write(dir.join("notes.sha256"), "not a manifest")?;
+        let found = find_backups(&dir);
+        if found.len() != 1 || found[0].bbid != Some(0xA) || found[0].dumped.is_none() {
+            bail!("the archive's manifests were found as {found:?}");
+        }
+        verify_backup(&found[0])?;
+        std::fs::write(dated.join("nand.bin"), [0u8; 0x200])?;
+        if verify_backup(&found[0]).is_ok() {
+            bail!("a changed dump still verified");
+        }
+
+        // names made for a dump now, so the write can go ahead
+        let (nand, spare) = dump_names("dumps", 0x1234ABCD);
+        if !nand.starts_with("dumps")
+            || !nand.contains("1234ABCD-")
+            || !spare.ends_with("-spare.bin")
+        {
+            bail!("the dump was named {nand} and {spare}");
+        }
+        Ok(())
+    }
+}
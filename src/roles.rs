@@ -0,0 +1,225 @@
+use std::collections::BTreeSet;
+use std::fmt::{self, Display};
+
+use crate::fs::{BLOCK_SIZE, FS_REGION_BLOCKS, SKSA_BLOCKS, SPARE_SIZE};
+use crate::spare::{is_bad_block, sa_link, sa_marked};
+
+// the SK comes first in the SKSA, and isn't linked into the SA chain
+pub const SK_BLOCKS: u16 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockRole {
+    Sksa,
+    UserData,
+    Fs,
+    // nothing is being put in the block, so it doesn't matter what it's marked as
+    Erased,
+}
+
+impl Display for BlockRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sksa => "SKSA",
+            Self::UserData => "user data",
+            Self::Fs => "filesystem",
+            Self::Erased => "erased",
+        })
+    }
+}
+
+// what a block is for, going by where it is and what's being written to it
+pub fn intended_role(blk: u16, num_blocks: u16, data: &[u8]) -> BlockRole {
+    if data.iter().all(|&b| b == 0xFF) {
+        BlockRole::Erased
+    } else if blk < SKSA_BLOCKS {
+        BlockRole::Sksa
+    } else if blk >= num_blocks.saturating_sub(FS_REGION_BLOCKS as u16) {
+        BlockRole::Fs
+    } else {
+        BlockRole::UserData
+    }
+}
+
+// the blocks the SA chain links, from `spares`, the spare data of the card from block 0: it starts
+// at the first good block after the SK and follows each block's link bytes until one without them
+// (the last), or a link that leaves the SKSA or goes back into the chain
+pub fn sa_chain(spares: &[u8]) -> BTreeSet<u16> {
+    let spare = |blk: u16| spares.get(blk as usize * SPARE_SIZE..(blk as usize + 1) * SPARE_SIZE);
+    let mut chain = BTreeSet::new();
+    let mut next = (SK_BLOCKS..SKSA_BLOCKS).find(|&b| spare(b).is_some_and(|s| !is_bad_block(s)));
+    while let Some(blk) = next.filter(|b| *b < SKSA_BLOCKS && chain.insert(*b)) {
+        next = spare(blk).and_then(sa_link);
+    }
+    chain
+}
+
+// why writing `data` with `spare` to `blk` would leave a block the boot ROM disagrees with, if it
+// would: nothing outside the SKSA may carry the SA link bytes, and SKSA data past the SK has to be
+// somewhere `chain` (the SA chain, as it'll be after the write) reaches. The SK and the last block
+// of the chain have no link bytes, so a block without them isn't wrong in itself
+pub fn role_conflict(
+    blk: u16,
+    num_blocks: u16,
+    data: &[u8],
+    spare: &[u8],
+    chain: &BTreeSet<u16>,
+) -> Option<String> {
+    if is_bad_block(spare) {
+        return None;
+    }
+    match (intended_role(blk, num_blocks, data), sa_marked(spare)) {
+        (BlockRole::Sksa, false) if blk >= SK_BLOCKS && !chain.contains(&blk) => Some(format!(
+            "block {blk:#X} is SKSA data, but it isn't marked as part of the SA and the SA chain doesn't reach it"
+        )),
+        (role @ (BlockRole::UserData | BlockRole::Fs), true) => Some(format!(
+            "block {blk:#X} is {role}, but its spare data marks it as part of the SA"
+        )),
+        _ => None,
+    }
+}
+
+// conflicts for every block of the given ranges in a nand/spare image, with the SA chain as the
+// image's spare data has it
+pub fn role_conflicts(
+    blocks: impl Iterator<Item = u16>,
+    num_blocks: u16,
+    nand: &[u8],
+    spare: &[u8],
+) -> Vec<String> {
+    let chain = sa_chain(spare);
+    blocks
+        .filter_map(|blk| {
+            let b = blk as usize;
+            role_conflict(
+                blk,
+                num_blocks,
+                nand.get(b * BLOCK_SIZE..(b + 1) * BLOCK_SIZE)?,
+                spare.get(b * SPARE_SIZE..(b + 1) * SPARE_SIZE)?,
+                &chain,
+            )
+        })
+        .collect()
+}
+
+// prints the conflicts, and whether the write should go ahead
+pub fn allow_conflicts(conflicts: &[String], force: bool) -> bool {
+    if conflicts.is_empty() {
+        return true;
+    }
+    for c in conflicts.iter().take(8) {
+        eprintln!("Warning: {c}");
+    }
+    if conflicts.len() > 8 {
+        eprintln!("Warning: ...and {} more", conflicts.len() - 8);
+    }
+    if force {
+        eprintln!("Writing anyway, as '--force' was given");
+        true
+    } else {
+        eprintln!("A card written like this may not boot. Add '--force' to write it anyway.");
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // spare data with the given link bytes (0xFF for none), good or marked bad
+    fn spare(link: u8, bad: bool) -> Vec<u8> {
+        let mut spare = vec![0xFF; SPARE_SIZE];
+        spare[0] = link;
+        if bad {
+            spare[5] = 0;
+        }
+        spare
+    }
+
+    // an SKSA whose chain runs 4 -> 5 -> 7 -> 8, skipping 6, which is bad
+    fn sksa_spares() -> Vec<u8> {
+        let mut spares = vec![0xFF; SKSA_BLOCKS as usize * SPARE_SIZE];
+        for (blk, link, bad) in [(4, 5, false), (5, 7, false), (6, 0xFF, true), (7, 8, false)] {
+            spares[blk * SPARE_SIZE..(blk + 1) * SPARE_SIZE].copy_from_slice(&spare(link, bad));
+        }
+        spares
+    }
+
+    #[test]
+    fn chain() {
+        assert_eq!(sa_chain(&sksa_spares()), BTreeSet::from([4, 5, 7, 8]));
+        // a link back into the chain ends it, as does one out of the SKSA
+        let mut looped = sksa_spares();
+        looped[8 * SPARE_SIZE] = 4;
+        assert_eq!(sa_chain(&looped), BTreeSet::from([4, 5, 7, 8]));
+        let mut escaped = sksa_spares();
+        escaped[8 * SPARE_SIZE] = 0x40;
+        assert_eq!(sa_chain(&escaped), BTreeSet::from([4, 5, 7, 8]));
+        // a bad first block after the SK moves the start along
+        let mut moved = vec![0xFF; SKSA_BLOCKS as usize * SPARE_SIZE];
+        moved[4 * SPARE_SIZE..5 * SPARE_SIZE].copy_from_slice(&spare(0xFF, true));
+        assert_eq!(sa_chain(&moved), BTreeSet::from([5]));
+        assert!(sa_chain(&[]).is_empty());
+    }
+
+    #[test]
+    fn conflicts() {
+        let chain = sa_chain(&sksa_spares());
+        let data = vec![0x5A; BLOCK_SIZE];
+        let erased = vec![0xFF; BLOCK_SIZE];
+        let (linked, unlinked) = (spare(0x12, false), spare(0xFF, false));
+        // (block, data, spare, conflict expected)
+        let table: &[(u16, &[u8], &[u8], bool)] = &[
+            // the SK carries no link bytes, and may carry some
+            (0, &data, &unlinked, false),
+            (3, &data, &unlinked, false),
+            (2, &data, &linked, false),
+            // SA blocks the chain links, marked or (the last) not
+            (4, &data, &linked, false),
+            (5, &data, &linked, false),
+            (8, &data, &unlinked, false),
+            // SKSA data the chain doesn't reach
+            (9, &data, &unlinked, true),
+            (0x3F, &data, &unlinked, true),
+            // nothing being put there, or a bad block
+            (9, &erased, &unlinked, false),
+            (6, &data, &spare(0xFF, true), false),
+            // user data and the FS, which must never carry link bytes
+            (0x40, &data, &unlinked, false),
+            (0x40, &data, &linked, true),
+            (0x800, &data, &linked, true),
+            (0x800, &erased, &linked, false),
+            (0xFF0, &data, &unlinked, false),
+            (0xFF0, &data, &linked, true),
+            (0x123, &data, &spare(0x12, true), false),
+        ];
+        for &(blk, data, spare, expected) in table {
+            let conflict = role_conflict(blk, 0x1000, data, spare, &chain);
+            assert_eq!(
+                conflict.is_some(),
+                expected,
+                "block {blk:#X}, spare {spare:02X?}: {conflict:?}"
+            );
+        }
+        assert_eq!(
+            role_conflict(0xFF0, 0x1000, &data, &linked, &chain).as_deref(),
+            Some("block 0xFF0 is filesystem, but its spare data marks it as part of the SA")
+        );
+    }
+
+    #[test]
+    fn image_conflicts() {
+        // a whole image: the chain comes from its own spare data
+        let blocks = 0x1000usize;
+        let mut nand = vec![0xFF; 0x50 * BLOCK_SIZE];
+        let mut spares = sksa_spares();
+        spares.resize(blocks * SPARE_SIZE, 0xFF);
+        for blk in [0, 4, 5, 7, 8, 9, 0x41] {
+            nand[blk * BLOCK_SIZE..(blk + 1) * BLOCK_SIZE].fill(0x5A);
+        }
+        spares[0x41 * SPARE_SIZE] = 0x20;
+        let conflicts = role_conflicts(0..0x50, blocks as u16, &nand, &spares);
+        assert_eq!(conflicts.len(), 2, "{conflicts:?}");
+        assert!(conflicts[0].starts_with("block 0x9 is SKSA data"));
+        assert!(conflicts[1].starts_with("block 0x41 is user data"));
+    }
+}
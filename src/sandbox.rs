@@ -0,0 +1,564 @@
+#![allow(non_snake_case)]
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Result};
+use bbrdb::CardStats;
+
+use crate::fs::{FsBlock, FAT_FREE, FS_REGION_BLOCKS, SKSA_BLOCKS};
+use crate::fsdiff::{diff, FsDiff};
+use crate::nand_read::card_blocks;
+use crate::player::{Player, PlayerWrite};
+use crate::spare::synthesize_spare;
+use crate::txn::{apply, Applied, Op};
+
+// 'sandbox on': the console's card as it was then, with every change made since kept in memory
+// and never written to it. The views over the sandbox only ever hold the console as a `Player`,
+// which has no methods that change anything, and the changes themselves are planned exactly as
+// 'txn' plans them, against the sandbox's own copy of the FS, with the new files' blocks kept
+// here; so nothing done in the sandbox can reach the card. A file the sandbox hasn't changed is
+// read from the console, under the name it had when the sandbox was started, and only while the
+// card is still on the FS generation it was on then, so what's read always matches the listing.
+
+pub struct Sandbox {
+    // the card's FS and stats when the sandbox was started
+    snapshot: FsBlock,
+    stats: CardStats,
+    num_blocks: u16,
+    // the FS as the changes since have left it
+    fs: FsBlock,
+    // what the changes wrote to the blocks they took
+    blocks: BTreeMap<u16, Vec<u8>>,
+}
+
+impl Sandbox {
+    pub fn start(player: &dyn Player) -> Result<Self> {
+        let stats = player.CardStats()?;
+        let snapshot = FsBlock::parse(&player.DumpCurrentFS()?)
+            .map_err(|e| anyhow!("the console's current FS doesn't parse: {e}"))?;
+        if snapshot.linked {
+            bail!("the console's card has a multi-block FAT, which the sandbox can't lay changes over");
+        }
+        if snapshot.seqno != stats.seqno {
+            bail!("the console's card changed while the sandbox was being started; try again");
+        }
+        Ok(Self {
+            fs: snapshot.clone(),
+            snapshot,
+            stats,
+            num_blocks: card_blocks(player)? as u16,
+            blocks: BTreeMap::new(),
+        })
+    }
+
+    // the sequence number of the FS the sandbox was started on
+    pub fn seqno(&self) -> u32 {
+        self.snapshot.seqno
+    }
+
+    // what the changes would have done to the card
+    pub fn diff(&self) -> FsDiff {
+        diff(&self.snapshot, &self.fs)
+    }
+
+    // the blocks of file data the changes would have written that are still in use
+    pub fn blocks_written(&self) -> usize {
+        self.blocks
+            .keys()
+            .filter(|&&b| self.fs.fat[b as usize] != FAT_FREE)
+            .count()
+    }
+
+    // carries out `ops` on the sandbox's FS as 'txn commit' would on the card: all of them or
+    // none, with each new generation's sequence number bumped
+    pub fn commit_txn(&mut self, ops: &[Op]) -> Result<Applied> {
+        let fs_start = self.num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+        let applied = apply(&self.fs, ops, |b| (SKSA_BLOCKS..fs_start).contains(&b))?;
+        self.blocks.extend(applied.writes.iter().cloned());
+        self.fs = applied.fs.clone();
+        Ok(applied)
+    }
+
+    // the console, for what the sandbox hasn't changed, as long as its card hasn't moved on
+    fn check_card(&self, player: &dyn Player) -> Result<()> {
+        let seqno = player.CardStats()?.seqno;
+        if seqno != self.snapshot.seqno {
+            bail!(
+                "The console's card has changed since the sandbox was started (FS #{} then, #{seqno} now), so it no longer matches the sandbox; use 'sandbox off' and start again",
+                self.snapshot.seqno
+            );
+        }
+        Ok(())
+    }
+
+    fn read_file(&self, player: &dyn Player, name: &str) -> Result<Option<Vec<u8>>> {
+        let Some(entry) = self.fs.find(name) else {
+            return Ok(None);
+        };
+        // a file the sandbox wrote takes blocks that were free, so never starts where one of
+        // the card's files still does
+        if self.blocks.contains_key(&entry.start) {
+            let mut data = vec![];
+            for blk in self.fs.chain(entry.start)? {
+                let block = self.blocks.get(&blk).ok_or_else(|| {
+                    anyhow!("{name} runs into block {blk:#X}, which the sandbox didn't write")
+                })?;
+                data.extend_from_slice(block);
+            }
+            data.truncate(entry.size as usize);
+            return Ok(Some(data));
+        }
+        let original = self
+            .snapshot
+            .entries
+            .iter()
+            .find(|e| e.start == entry.start)
+            .ok_or_else(|| anyhow!("{name} is neither in the sandbox nor on the card"))?;
+        self.check_card(player)?;
+        player.ReadFile(&original.name)
+    }
+
+    // raw blocks are the card's as it is, apart from those the sandbox wrote, which get spare
+    // data to match
+    fn read_block(&self, player: &dyn Player, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        let (data, spare) = player.ReadSingleBlock(blk)?;
+        match u16::try_from(blk).ok().and_then(|b| self.blocks.get(&b)) {
+            Some(written) => Ok((
+                written.clone(),
+                synthesize_spare(written, &spare, false)?.to_vec(),
+            )),
+            None => Ok((data, spare)),
+        }
+    }
+
+    // the card's stats when the sandbox was started, less what the changes took
+    fn card_stats(&self) -> CardStats {
+        let free = |fs: &FsBlock| {
+            fs.fat[..fs.fat.len().min(self.num_blocks as usize)]
+                .iter()
+                .filter(|&&v| v == FAT_FREE)
+                .count() as i64
+        };
+        let change = free(&self.fs) - free(&self.snapshot);
+        CardStats {
+            free: (self.stats.free as i64 + change) as u32,
+            used: (self.stats.used as i64 - change) as u32,
+            bad: self.stats.bad,
+            seqno: self.fs.seqno,
+        }
+    }
+}
+
+// the console seen through the sandbox
+pub struct Overlay<'a, P> {
+    player: P,
+    sandbox: &'a Sandbox,
+}
+
+impl<'a, P: Player> Overlay<'a, P> {
+    pub fn new(player: P, sandbox: &'a Sandbox) -> Self {
+        Self { player, sandbox }
+    }
+}
+
+impl<P: Player> Player for Overlay<'_, P> {
+    fn GetBBID(&self) -> Result<u32> {
+        self.player.GetBBID()
+    }
+
+    // the LED isn't the card
+    fn SetLED(&self, value: u32) -> Result<()> {
+        self.player.SetLED(value)
+    }
+
+    fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+        Ok(self
+            .sandbox
+            .fs
+            .entries
+            .iter()
+            .map(|e| (e.name.clone(), e.size))
+            .collect())
+    }
+
+    fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+        self.sandbox.fs.to_bytes()
+    }
+
+    fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        self.sandbox.read_file(&self.player, name)
+    }
+
+    fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        self.sandbox.read_block(&self.player, blk)
+    }
+
+    fn CardStats(&self) -> Result<CardStats> {
+        Ok(self.sandbox.card_stats())
+    }
+}
+
+// the same, for changing files: only ever the sandbox's, as `player` can't be changed through
+pub struct OverlayMut<'a, P> {
+    player: P,
+    sandbox: &'a mut Sandbox,
+}
+
+impl<'a, P: Player> OverlayMut<'a, P> {
+    pub fn new(player: P, sandbox: &'a mut Sandbox) -> Self {
+        Self { player, sandbox }
+    }
+
+    fn view(&self) -> Overlay<'_, &P> {
+        Overlay::new(&self.player, self.sandbox)
+    }
+}
+
+impl<P: Player> Player for OverlayMut<'_, P> {
+    fn GetBBID(&self) -> Result<u32> {
+        self.view().GetBBID()
+    }
+
+    fn SetLED(&self, value: u32) -> Result<()> {
+        self.view().SetLED(value)
+    }
+
+    fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+        self.view().ListFiles()
+    }
+
+    fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+        self.view().DumpCurrentFS()
+    }
+
+    fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        self.view().ReadFile(name)
+    }
+
+    fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        self.view().ReadSingleBlock(blk)
+    }
+
+    fn CardStats(&self) -> Result<CardStats> {
+        self.view().CardStats()
+    }
+}
+
+impl<P: Player> PlayerWrite for OverlayMut<'_, P> {
+    fn WriteFile(&mut self, data: &[u8], name: &str) -> Result<()> {
+        self.sandbox
+            .commit_txn(&[Op::Upload {
+                name: name.to_string(),
+                data: data.to_vec(),
+                source: None,
+            }])
+            .map(|_| ())
+    }
+
+    fn DeleteFile(&mut self, name: &str) -> Result<()> {
+        self.sandbox
+            .commit_txn(&[Op::Delete(name.to_string())])
+            .map(|_| ())
+    }
+
+    fn RenameFile(&mut self, from: &str, to: &str) -> Result<()> {
+        self.sandbox
+            .commit_txn(&[Op::Rename {
+                from: from.to_string(),
+                to: to.to_string(),
+            }])
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sandbox() -> Result<()> {
+        use std::cell::{Cell, RefCell};
+
+        use crate::fs::{synthetic_block, BLOCK_SIZE, FAT_BAD, SPARE_SIZE};
+        use crate::fs_cache::FsCache;
+        use crate::fsdiff::Change;
+        use crate::spare::ecc_matches;
+
+        // a console that can only be read, which records everything asked of it; there's no
+        // PlayerWrite for it, so the sandbox being able to work over it at all shows it never
+        // needs to change the card
+        struct Card {
+            block: Vec<u8>,
+            seqno: Cell<u32>,
+            calls: RefCell<Vec<String>>,
+        }
+
+        impl Card {
+            fn call(&self, call: String) {
+                self.calls.borrow_mut().push(call);
+            }
+        }
+
+        impl Player for Card {
+            fn GetBBID(&self) -> Result<u32> {
+                self.call("GetBBID".to_string());
+                Ok(0x1234)
+            }
+            fn SetLED(&self, value: u32) -> Result<()> {
+                self.call(format!("SetLED {value}"));
+                Ok(())
+            }
+            fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+                bail!("the sandbox should list the files itself")
+            }
+            fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+                self.call("DumpCurrentFS".to_string());
+                Ok(self.block.clone())
+            }
+            fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+                self.call(format!("ReadFile {name}"));
+                Ok((name == "TEST.app").then(|| vec![0x5A; 0x6000]))
+            }
+            fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+                self.call(format!("ReadSingleBlock {blk:#X}"));
+                Ok((vec![blk as u8; BLOCK_SIZE], vec![0xFF; SPARE_SIZE]))
+            }
+            fn CardStats(&self) -> Result<CardStats> {
+                self.call("CardStats".to_string());
+                // the synthetic FAT's two blocks of TEST.app and one bad block
+                Ok(CardStats {
+                    free: 0x1000 - 3,
+                    used: 2,
+                    bad: 1,
+                    seqno: self.seqno.get(),
+                })
+            }
+        }
+
+        // the synthetic card, with TEST.sys (which nothing may delete or rename) as TEST.app
+        let mut fs = FsBlock::parse(&synthetic_block())?;
+        fs.entries[0].name = "TEST.app".to_string();
+        let card = Card {
+            block: fs.to_bytes()?,
+            seqno: Cell::new(7),
+            calls: RefCell::new(vec![]),
+        };
+        let mut sandbox = Sandbox::start(&card)?;
+        let stats = |sandbox: &Sandbox| {
+            let s = Overlay::new(&card, sandbox).CardStats().unwrap();
+            (s.free, s.used, s.bad, s.seqno)
+        };
+        let files = |sandbox: &Sandbox| Overlay::new(&card, sandbox).ListFiles().unwrap();
+
+        // to start with, the sandbox is the card
+        if files(&sandbox) != [("TEST.app".to_string(), 0x6000)]
+            || stats(&sandbox) != (0x1000 - 3, 2, 1, 7)
+            || Overlay::new(&card, &sandbox).ReadFile("TEST.app")? != Some(vec![0x5A; 0x6000])
+        {
+            bail!("a fresh sandbox didn't show the card as it is");
+        }
+
+        // read-your-writes: an upload is listed, read back and counted, without the card being asked
+        let new = (0..0x5000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        card.calls.borrow_mut().clear();
+        OverlayMut::new(&card, &mut sandbox).WriteFile(&new, "NEW.app")?;
+        let overlay = Overlay::new(&card, &sandbox);
+        if overlay.ReadFile("NEW.app")? != Some(new.clone())
+            || card
+                .calls
+                .borrow()
+                .iter()
+                .any(|c| c.starts_with("ReadFile"))
+        {
+            bail!("an upload in the sandbox didn't read back from the sandbox");
+        }
+        if files(&sandbox)
+            != [
+                ("TEST.app".to_string(), 0x6000),
+                ("NEW.app".to_string(), 0x5000),
+            ]
+        {
+            bail!("the upload was listed as {:?}", files(&sandbox));
+        }
+        if stats(&sandbox) != (0x1000 - 5, 4, 1, 8) {
+            bail!(
+                "after a 2-block upload, the stats were {:?}",
+                stats(&sandbox)
+            );
+        }
+
+        // the FS the sandbox gives out is the one it lists, through the FS cache too
+        let current = FsBlock::parse(&overlay.DumpCurrentFS()?)?;
+        let mut cache = FsCache::default();
+        if current.seqno != 8
+            || current.find("NEW.app").map(|e| e.size) != Some(0x5000)
+            || cache.files(&overlay)? != files(&sandbox)
+        {
+            bail!("the sandbox's FS block didn't match its listing");
+        }
+
+        // its blocks read back as written, with spare data to match; others come from the card
+        let first = current.find("NEW.app").map(|e| e.start).unwrap_or_default();
+        let (data, spare) = overlay.ReadSingleBlock(first as u32)?;
+        if data[..] != new[..BLOCK_SIZE] || !ecc_matches(&data, &spare) {
+            bail!("the sandbox's block {first:#X} didn't read back as written");
+        }
+        if overlay.ReadSingleBlock(0x20)?.0 != vec![0x20; BLOCK_SIZE] {
+            bail!("a block the sandbox didn't write wasn't the card's");
+        }
+
+        // a renamed file is still read from the card, under its old name; the old name is gone
+        card.calls.borrow_mut().clear();
+        OverlayMut::new(&card, &mut sandbox).RenameFile("TEST.app", "MOVED.app")?;
+        let overlay = Overlay::new(&card, &sandbox);
+        if overlay.ReadFile("MOVED.app")? != Some(vec![0x5A; 0x6000])
+            || overlay.ReadFile("TEST.app")?.is_some()
+            || !card
+                .calls
+                .borrow()
+                .contains(&"ReadFile TEST.app".to_string())
+            || card
+                .calls
+                .borrow()
+                .iter()
+                .filter(|c| c.starts_with("ReadFile"))
+                .count()
+                != 1
+        {
+            bail!("a renamed file wasn't read from the card under its old name");
+        }
+
+        // overwriting one of the card's files takes new blocks and is read from the sandbox after
+        let changed = vec![0xA5; 0x4100];
+        OverlayMut::new(&card, &mut sandbox).WriteFile(&changed, "MOVED.app")?;
+        card.calls.borrow_mut().clear();
+        if Overlay::new(&card, &sandbox).ReadFile("MOVED.app")? != Some(changed)
+            || card
+                .calls
+                .borrow()
+                .iter()
+                .any(|c| c.starts_with("ReadFile"))
+        {
+            bail!("an overwritten file was read from the card");
+        }
+        if stats(&sandbox) != (0x1000 - 5, 4, 1, 10) {
+            bail!(
+                "after replacing 2 blocks with 2, the stats were {:?}",
+                stats(&sandbox)
+            );
+        }
+
+        // deleting gives the blocks back
+        OverlayMut::new(&card, &mut sandbox).DeleteFile("NEW.app")?;
+        if Overlay::new(&card, &sandbox).ReadFile("NEW.app")?.is_some()
+            || files(&sandbox) != [("MOVED.app".to_string(), 0x4100)]
+            || stats(&sandbox) != (0x1000 - 3, 2, 1, 11)
+            || sandbox.blocks_written() != 2
+        {
+            bail!("a deleted file was still there, or its blocks weren't given back");
+        }
+
+        // what's refused on the card is refused here, and leaves the sandbox as it was
+        let before = (files(&sandbox), stats(&sandbox));
+        let mut overlay = OverlayMut::new(&card, &mut sandbox);
+        for (what, result) in [
+            ("deleting a missing file", overlay.DeleteFile("NONE.app")),
+            ("renaming onto a file", {
+                overlay.WriteFile(&[1], "ONE.app")?;
+                overlay.RenameFile("ONE.app", "MOVED.app")
+            }),
+            (
+                "renaming a system file",
+                overlay.RenameFile("X.sys", "Y.app"),
+            ),
+            ("an empty upload", overlay.WriteFile(&[], "EMPTY.app")),
+            (
+                "an upload too big for the card",
+                overlay.WriteFile(&vec![0; 0x1000 * BLOCK_SIZE], "BIG.app"),
+            ),
+        ] {
+            if result.is_ok() {
+                bail!("{what} was allowed in the sandbox");
+            }
+        }
+        overlay.DeleteFile("ONE.app")?;
+        if (files(&sandbox), stats(&sandbox).0) != (before.0, before.1 .0) {
+            bail!("a refused change left the sandbox changed");
+        }
+
+        // a transaction is all or nothing here as on the card
+        let seqno = stats(&sandbox).3;
+        let ops = [
+            Op::Upload {
+                name: "TXN.app".to_string(),
+                data: vec![7; 0x10],
+                source: None,
+            },
+            Op::Delete("NONE.app".to_string()),
+        ];
+        if sandbox.commit_txn(&ops).is_ok()
+            || files(&sandbox).len() != 1
+            || stats(&sandbox).3 != seqno
+        {
+            bail!("a failed transaction changed the sandbox");
+        }
+        sandbox.commit_txn(&ops[..1])?;
+        if Overlay::new(&card, &sandbox).ReadFile("TXN.app")? != Some(vec![7; 0x10]) {
+            bail!("a transaction's upload didn't read back");
+        }
+
+        // the diff is against the card as it was
+        let diff = sandbox.diff();
+        let mut seen = diff
+            .changes
+            .iter()
+            .map(|c| match c {
+                Change::Added(n) => format!("+{}", n.name),
+                Change::Removed(o) => format!("-{}", o.name),
+                other => format!("{other:?}"),
+            })
+            .collect::<Vec<_>>();
+        seen.sort();
+        if seen != ["+MOVED.app", "+TXN.app", "-TEST.app"] || diff.seqno != (7, stats(&sandbox).3) {
+            bail!("the sandbox's diff was {seen:?}, FS {:?}", diff.seqno);
+        }
+
+        // once the card moves on, the sandbox won't mix its files with the card's new ones, though
+        // what it holds itself can still be read
+        let mut sandbox = Sandbox::start(&card)?;
+        OverlayMut::new(&card, &mut sandbox).WriteFile(&[3; 8], "MINE.app")?;
+        card.seqno.set(9);
+        let overlay = Overlay::new(&card, &sandbox);
+        if overlay.ReadFile("TEST.app").is_ok() || overlay.ReadFile("MINE.app")? != Some(vec![3; 8])
+        {
+            bail!("the sandbox read from a card that had changed under it");
+        }
+
+        // a card whose FS and stats disagree can't be started on
+        if Sandbox::start(&card).is_ok() {
+            bail!("a sandbox was started on a card that was changing");
+        }
+
+        // nothing asked of the card was anything but a read
+        let reads = [
+            "GetBBID",
+            "SetLED",
+            "DumpCurrentFS",
+            "ReadFile",
+            "ReadSingleBlock",
+            "CardStats",
+        ];
+        if let Some(call) = card
+            .calls
+            .borrow()
+            .iter()
+            .find(|c| !reads.iter().any(|r| c.starts_with(r)))
+        {
+            bail!("the sandbox asked the card for '{call}'");
+        }
+        if sandbox.fs.fat[0x10] != FAT_BAD {
+            bail!("the card's bad block wasn't kept");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::{anyhow, bail, Result};
+use serde::Serialize;
+
+use crate::cancel::CancelToken;
+use crate::ecc::EccCheck;
+use crate::fs::{FsBlock, BLOCK_SIZE, FAT_FREE, FS_REGION_BLOCKS, SKSA_BLOCKS};
+use crate::nand_read::card_blocks;
+use crate::player::Player;
+use crate::progress::Progress;
+use crate::ranges::{block_ranges, format_range};
+use crate::spare::ecc_check;
+use crate::throughput::TransferTimer;
+
+// Free blocks are never read in normal use, so a block going bad isn't noticed until a file is
+// written to it. 'scrub' reads each of them, only reading, and lists those that needed retrying
+// or correcting or couldn't be read at all, as candidates for marking bad before anything is
+// stored in them.
+
+// a healthy block reads cleanly the first time, so a few tries tell a weak block from a dead one
+pub const READ_ATTEMPTS: usize = 4;
+
+// more than this fraction of the free blocks failing points at the card rather than at blocks
+const FAILING_CARD_FRACTION: f64 = 0.02;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Health {
+    Ok,
+    // read cleanly in the end, but only after retrying or with a bit corrected
+    Correctable,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockResult {
+    pub block: u16,
+    pub health: Health,
+    pub attempts: usize,
+}
+
+// from what each attempt gave, in order (None if the read itself failed); attempts stop at the
+// first clean read
+pub fn classify(attempts: &[Option<EccCheck>]) -> Health {
+    match attempts {
+        [Some(EccCheck::Clean)] => Health::Ok,
+        a if a
+            .iter()
+            .any(|r| matches!(r, Some(EccCheck::Clean | EccCheck::Correctable))) =>
+        {
+            Health::Correctable
+        }
+        _ => Health::Failed,
+    }
+}
+
+fn blocks_with(results: &[BlockResult], health: Health) -> Vec<u16> {
+    results
+        .iter()
+        .filter(|r| r.health == health)
+        .map(|r| r.block)
+        .collect()
+}
+
+fn block_list(blocks: &[u16]) -> String {
+    blocks
+        .iter()
+        .map(|b| format!("{b:#X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// what to do about the blocks found, for the end of the scrub
+pub fn recommend(results: &[BlockResult]) -> Vec<String> {
+    let failed = blocks_with(results, Health::Failed);
+    let correctable = blocks_with(results, Health::Correctable);
+    let mut advice = vec![];
+    if failed.is_empty() && correctable.is_empty() {
+        advice.push(format!(
+            "All {} free blocks read cleanly; nothing needs doing.",
+            results.len()
+        ));
+        return advice;
+    }
+    if failed.len() as f64 > FAILING_CARD_FRACTION * results.len() as f64 {
+        advice.push(format!(
+            "{} of {} free blocks couldn't be read: the card itself may be failing, so dump it with '1' while it still reads.",
+            failed.len(),
+            results.len()
+        ));
+    }
+    if !failed.is_empty() {
+        advice.push(format!(
+            "Mark the blocks that couldn't be read as bad before a file is written to them: relocate {}",
+            block_list(&failed)
+        ));
+    }
+    if !correctable.is_empty() {
+        let ranges = block_ranges(&correctable)
+            .iter()
+            .map(format_range)
+            .collect::<Vec<_>>()
+            .join(",");
+        advice.push(format!(
+            "{} blocks ({ranges}) only read cleanly after a retry or correction; scrub again later, and mark any that are still weak with: relocate {}",
+            correctable.len(),
+            block_list(&correctable)
+        ));
+    }
+    advice
+}
+
+// the free blocks of the current FS, outside the SKSA and FS region
+pub fn free_blocks(fs: &FsBlock, num_blocks: u16) -> Vec<u16> {
+    let fs_start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    (SKSA_BLOCKS..fs_start)
+        .filter(|&b| fs.fat.get(b as usize) == Some(&FAT_FREE))
+        .collect()
+}
+
+// reads every free block, retrying those that don't read cleanly, into `results` (so what was
+// scrubbed is there even if it fails or is cancelled); cancellable between blocks
+pub fn scrub(
+    player: &dyn Player,
+    events: bool,
+    results: &mut Vec<BlockResult>,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let fs = FsBlock::parse(&player.DumpCurrentFS()?)?;
+    let num_blocks = card_blocks(player)? as u16;
+    let blocks = free_blocks(&fs, num_blocks);
+    if blocks.is_empty() {
+        bail!("the card has no free blocks to scrub");
+    }
+
+    let timer = TransferTimer::begin(player, "Scrub", blocks.len() as u64 * BLOCK_SIZE as u64);
+    let mut progress = Progress::start("scrub", blocks.len() as u64, BLOCK_SIZE, events);
+    progress.set_message(format!("Scrubbing {} free blocks", blocks.len()));
+
+    for blk in blocks {
+        if let Err(e) = cancel.check() {
+            let e = anyhow!("Stopped before block {blk:#X}: {e}");
+            progress.fail(&e.to_string());
+            return Err(e);
+        }
+        let mut attempts = vec![];
+        while attempts.len() < READ_ATTEMPTS && attempts.last() != Some(&Some(EccCheck::Clean)) {
+            attempts.push(
+                player
+                    .ReadSingleBlock(blk as u32)
+                    .ok()
+                    .map(|(data, spare)| ecc_check(&data, &spare)),
+            );
+        }
+        let health = classify(&attempts);
+        if health != Health::Ok {
+            progress.println(format!(
+                "Block {blk:#X}: {health:?} after {} reads",
+                attempts.len()
+            ));
+        }
+        results.push(BlockResult {
+            block: blk,
+            health,
+            attempts: attempts.len(),
+        });
+        progress.inc(1);
+    }
+
+    progress.finish();
+    timer.complete();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_scrub() -> Result<()> {
+        use EccCheck::*;
+        let cases: [(&[Option<EccCheck>], Health); 6] = [
+            (&[Some(Clean)], Health::Ok),
+            (&[Some(Correctable), Some(Clean)], Health::Correctable),
+            (&[None, Some(Clean)], Health::Correctable),
+            (&[Some(Correctable); 4], Health::Correctable),
+            (
+                &[Some(Uncorrectable), None, Some(Uncorrectable), None],
+                Health::Failed,
+            ),
+            (&[None; 4], Health::Failed),
+        ];
+        for (attempts, expected) in cases {
+            if classify(attempts) != expected {
+                bail!(
+                    "{attempts:?} was classified as {:?}, not {expected:?}",
+                    classify(attempts)
+                );
+            }
+        }
+
+        let result = |block, health| BlockResult {
+            block,
+            health,
+            attempts: 1,
+        };
+        let mut results = (0x100..0x200)
+            .map(|b| result(b, Health::Ok))
+            .collect::<Vec<_>>();
+        if recommend(&results).len() != 1 || !recommend(&results)[0].contains("nothing needs doing")
+        {
+            bail!("a clean scrub recommended {:?}", recommend(&results));
+        }
+
+        results[0x10].health = Health::Failed;
+        results[0x20].health = Health::Correctable;
+        results[0x21].health = Health::Correctable;
+        let advice = recommend(&results);
+        if advice.len() != 2
+            || !advice[0].ends_with("relocate 0x110")
+            || !advice[1].contains("(0x120-0x122)")
+            || !advice[1].ends_with("relocate 0x120 0x121")
+        {
+            bail!("the wrong follow-up was recommended: {advice:?}");
+        }
+
+        for r in &mut results[..8] {
+            r.health = Health::Failed;
+        }
+        if !recommend(&results)[0].contains("the card itself may be failing") {
+            bail!("many unreadable blocks didn't point at the card");
+        }
+
+        let mut fs = FsBlock::parse(&crate::fs::synthetic_block())?;
+        fs.fat[SKSA_BLOCKS as usize - 1] = FAT_FREE;
+        let free = free_blocks(&fs, 0x1000);
+        if free.first() != Some(&0x42)
+            || free.contains(&(SKSA_BLOCKS - 1))
+            || free.contains(&0x10)
+            || free.last() != Some(&(0x1000 - FS_REGION_BLOCKS as u16 - 1))
+        {
+            bail!("the wrong blocks were taken as free");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+use anyhow::Result;
+
+type SelfTest = fn() -> Result<()>;
+
+// checks of the offline logic against vectors built into the binary, so a miscompiled build is
+// caught before it's trusted with a console
+const SUBSYSTEMS: &[(&str, SelfTest)] = &[
+    ("ECC", crate::ecc::self_test),
+    ("FS block", crate::fs::self_test),
+    ("block ranges", crate::ranges::self_test),
+    ("sizes", crate::sizes::self_test),
+    ("byte order", crate::byteswap::self_test),
+];
+
+/// Checks the offline logic (ECC, FS blocks, block ranges, sizes and byte order) against vectors
+/// built into the binary, printing a line for each; whether they all passed.
+pub fn selftest() -> bool {
+    let mut passed = true;
+    for (name, test) in SUBSYSTEMS {
+        match test() {
+            Ok(_) => println!("{name:<14} pass"),
+            Err(e) => {
+                println!("{name:<14} FAIL: {e}");
+                passed = false;
+            }
+        }
+    }
+    passed
+}
@@ -0,0 +1,87 @@
+use std::fs::read_to_string;
+
+use anyhow::{anyhow, bail, Result};
+use bbrdb::GlobalHandle;
+use serde::{Deserialize, Serialize};
+
+use crate::device::{scan_sorted, DeviceLocation};
+use crate::file_digest::FileDigest;
+use crate::options::Options;
+use crate::sink::write_atomic;
+use crate::startup::probe_bbid;
+
+// bumped when a field changes meaning; fields added later just take their defaults when an older
+// snapshot is loaded
+pub const SESSION_SCHEMA: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct MountSnapshot {
+    pub nand: String,
+    pub spare: String,
+    pub rw: bool,
+    // the dump's files as they were when it was saved
+    #[serde(default)]
+    pub digests: Vec<FileDigest>,
+}
+
+// an operation of the open transaction, as the 'txn add' arguments that queue it again; an
+// upload has the digest of the data it queued
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct SavedOp {
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub digest: Option<FileDigest>,
+}
+
+// the parts of a session that can be saved; device handles and the write unlock never are
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Snapshot {
+    pub schema: u32,
+    // the version that saved it, for messages only
+    pub saved_by: String,
+    pub options: Options,
+    pub cwd: Option<String>,
+    pub mounted: Option<MountSnapshot>,
+    // the selected console, so it can be selected again if it's connected
+    pub bbid: Option<u32>,
+    pub free_blocks: Option<u32>,
+    // the FS sequence number they were read at, so changes made to the card since are noticed
+    pub seqno: Option<u32>,
+    // the transaction that was open, if one was
+    pub txn: Option<Vec<SavedOp>>,
+}
+
+impl Snapshot {
+    pub fn parse(text: &str) -> Result<Self> {
+        let snapshot: Self = serde_json::from_str(text)?;
+        match snapshot.schema {
+            0 => bail!("not a session snapshot"),
+            s if s > SESSION_SCHEMA => bail!(
+                "saved by a newer version ({}, format {s}); this version only reads up to format {SESSION_SCHEMA}",
+                snapshot.saved_by
+            ),
+            _ => Ok(snapshot),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        Self::parse(&read_to_string(path)?).map_err(|e| anyhow!("{path}: {e}"))
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        write_atomic(path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+}
+
+// finds the connected console with this BBID, opening each in turn to ask; the returned handle
+// hasn't been initialised
+pub fn reselect(bbid: u32) -> Result<Option<(GlobalHandle, DeviceLocation)>> {
+    for (index, device) in scan_sorted()?.iter().enumerate() {
+        if probe_bbid(device).is_ok_and(|b| b == bbid) {
+            let handle = GlobalHandle::new(device)?;
+            return Ok(Some((handle, DeviceLocation::new(index, device))));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,158 @@
+// Names close to one that wasn't found, for 'did you mean'. How close counts depends on the
+// name's length: names of a few letters are all close to each other, so those only match ignoring
+// case.
+
+pub const MAX_SUGGESTIONS: usize = 3;
+
+// the number of single-character edits between `a` and `b`
+pub fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let next = (row[j + 1] + 1)
+                .min(row[j] + 1)
+                .min(diagonal + usize::from(ca != *cb));
+            diagonal = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+// the most edits (ignoring case) a name `len` characters long can be off by and still suggested
+fn tolerance(len: usize) -> usize {
+    match len {
+        0..=2 => 0,
+        3..=5 => 1,
+        6..=9 => 2,
+        _ => 3,
+    }
+}
+
+// up to MAX_SUGGESTIONS of `names` close to `typed`, closest first; stray spaces around `typed`
+// are ignored, and names as close as each other ignoring case go by case, then alphabetically
+pub fn similar_names<'a>(typed: &str, names: &[&'a str]) -> Vec<&'a str> {
+    let trimmed = typed.trim();
+    let lower = trimmed.to_lowercase();
+    let allowed = tolerance(trimmed.chars().count());
+    let mut close = names
+        .iter()
+        .filter(|name| **name != typed)
+        .map(|name| {
+            (
+                distance(&lower, &name.to_lowercase()),
+                distance(trimmed, name),
+                *name,
+            )
+        })
+        .filter(|&(d, _, _)| d <= allowed)
+        .collect::<Vec<_>>();
+    close.sort();
+    close.dedup_by_key(|&mut (_, _, name)| name);
+    close
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, _, name)| name)
+        .collect()
+}
+
+// "a", "a or b", "a, b or c"
+pub fn either(names: &[&str]) -> String {
+    match names {
+        [] => String::new(),
+        [only] => only.to_string(),
+        [rest @ .., last] => format!("{} or {last}", rest.join(", ")),
+    }
+}
+
+// which of `close` to run the command again with, if any: the closest, if this isn't already a
+// retry and `ask` says to; a retry that also fails goes no further
+pub fn retry_with<'a>(
+    close: &[&'a str],
+    retrying: bool,
+    ask: &mut dyn FnMut(&str) -> bool,
+) -> Option<&'a str> {
+    match (close.first(), retrying) {
+        (Some(top), false) if ask(top) => Some(top),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Result};
+
+    #[test]
+    fn close_names() -> Result<()> {
+        let card = [
+            "0000B1C4.app",
+            "0000B1C5.app",
+            "0000b1c4.rec",
+            "ticket.sys",
+            "ticket.sha",
+            "a",
+            "B",
+            "ab.c",
+            "GAME1.app",
+            "GAME3.app",
+            "GAME2.app",
+            "GAME4.app",
+        ];
+        for (typed, expected) in [
+            // case, and stray spaces, are what's most often wrong
+            (
+                "0000b1c4.app",
+                &["0000B1C4.app", "0000B1C5.app", "0000b1c4.rec"][..],
+            ),
+            (
+                " 0000B1C4.app ",
+                &["0000B1C4.app", "0000B1C5.app", "0000b1c4.rec"],
+            ),
+            ("TICKET.SYS", &["ticket.sys", "ticket.sha"]),
+            // ties go alphabetically, however the names were listed, and stop at three
+            ("GAME.app", &["GAME1.app", "GAME2.app", "GAME3.app"]),
+            // short names only match by case, or by a single edit from three letters on
+            ("b", &["B"]),
+            ("A", &["a"]),
+            ("x", &[]),
+            ("ab.d", &["ab.c"]),
+            ("xy.z", &[]),
+            ("", &[]),
+            ("save.rec", &[]),
+        ] {
+            let close = similar_names(typed, &card);
+            if close != expected {
+                bail!("'{typed}' suggested {close:?}, not {expected:?}");
+            }
+        }
+
+        if either(&["a"]) != "a"
+            || either(&["a", "b"]) != "a or b"
+            || either(&["a", "b", "c"]) != "a, b or c"
+        {
+            bail!("suggestions were joined as '{}'", either(&["a", "b", "c"]));
+        }
+
+        // a retry is only offered once, so one whose suggestion also fails doesn't loop
+        let mut asked = 0;
+        let mut yes = |_: &str| {
+            asked += 1;
+            true
+        };
+        if retry_with(&["GAME1.app", "GAME2.app"], false, &mut yes) != Some("GAME1.app")
+            || retry_with(&["GAME1.app"], true, &mut yes).is_some()
+            || retry_with(&[], false, &mut yes).is_some()
+            || retry_with(&["GAME1.app"], false, &mut |_: &str| false).is_some()
+        {
+            bail!("a retry was offered when it shouldn't have been, or not when it should");
+        }
+        if asked != 1 {
+            bail!("asked {asked} times to retry");
+        }
+        Ok(())
+    }
+}
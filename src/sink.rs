@@ -0,0 +1,227 @@
+use std::fmt::{self, Display};
+use std::fs::{rename, File};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tar::{Builder, Header};
+
+use crate::compress::encode_for;
+use crate::file_times::{set_modified, to_tar_mtime};
+
+// writes to a temporary file next to `path` and renames it into place, so that an interrupted
+// write never leaves a truncated file under the real name
+pub fn write_atomic(path: impl AsRef<Path>, data: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    rename(&tmp, path)?;
+    Ok(())
+}
+
+// where downloaded data ends up; selected with 'set sink files' or 'set sink tar:<archive>'
+#[derive(Default)]
+pub enum OutputSink {
+    #[default]
+    Files,
+    Tar {
+        path: String,
+        builder: Builder<GzEncoder<File>>,
+    },
+    // while a dry run is traced: nothing is saved, only said
+    Discard,
+}
+
+impl OutputSink {
+    pub fn open(spec: &str) -> Result<Self> {
+        match spec.split_once(':') {
+            None if spec == "files" => Ok(Self::Files),
+            Some(("tar", path)) if !path.is_empty() => {
+                let file = File::create(path)?;
+                Ok(Self::Tar {
+                    path: path.to_string(),
+                    builder: Builder::new(GzEncoder::new(file, Compression::default())),
+                })
+            }
+            _ => Err(anyhow!(
+                "'{spec}' isn't a valid sink; use 'files' or 'tar:<archive>'"
+            )),
+        }
+    }
+
+    // only complete data is ever put, so a failed download never leaves an entry in an archive;
+    // a name ending in '.gz' or '.zst' is saved compressed
+    pub fn put(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        self.put_dated(name, data, None)
+    }
+
+    // the same, dated `modified` rather than now if given; a time the file or archive can't take
+    // is said instead, so it isn't lost
+    pub fn put_dated(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        modified: Option<DateTime<FixedOffset>>,
+    ) -> Result<()> {
+        let data = encode_for(name, data)?;
+        match self {
+            Self::Files => {
+                write_atomic(name, &data)?;
+                if let Some(time) = modified {
+                    if let Err(e) = set_modified(name, time) {
+                        println!("Note: {name} dates from {time}, but couldn't be dated then: {e}");
+                    }
+                }
+                Ok(())
+            }
+            Self::Discard => {
+                println!("Not saving {name} ({:#X} bytes) in a dry run", data.len());
+                Ok(())
+            }
+            Self::Tar { builder, .. } => {
+                let mtime = modified.and_then(|time| {
+                    let mtime = to_tar_mtime(time);
+                    if mtime.is_none() {
+                        println!(
+                            "Note: {name} dates from {time}, which is too early for a tar entry"
+                        );
+                    }
+                    mtime
+                });
+                let mut header = Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(mtime.unwrap_or_else(|| {
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or(0)
+                }));
+                header.set_cksum();
+                builder.append_data(&mut header, name, &*data)?;
+                Ok(())
+            }
+        }
+    }
+
+    // writes the end of the archive; nothing needs doing for plain files
+    pub fn finish(self) -> Result<()> {
+        if let Self::Tar { path, builder } = self {
+            builder
+                .into_inner()
+                .and_then(|gz| gz.finish())
+                .map_err(|e| anyhow!("{path}: {e}"))?;
+            println!("Finished writing {path}");
+        }
+        Ok(())
+    }
+}
+
+impl Display for OutputSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Files => write!(f, "files"),
+            Self::Tar { path, .. } => write!(f, "tar:{path}"),
+            Self::Discard => write!(f, "none (dry run)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::Codec;
+    use crate::file_times::to_system_time;
+    use crate::test_dir::TestDir;
+    use anyhow::bail;
+    use flate2::read::GzDecoder;
+    use std::fs::{read, read_dir};
+    use std::io::Read;
+
+    #[test]
+    fn sinks() -> Result<()> {
+        for bad in ["", "file", "tar", "tar:", "zip:out.zip"] {
+            if OutputSink::open(bad).is_ok() {
+                bail!("'{bad}' was taken as a sink");
+            }
+        }
+        assert_eq!(OutputSink::open("files")?.to_string(), "files");
+        assert_eq!(OutputSink::Discard.to_string(), "none (dry run)");
+
+        let dir = TestDir::new("sink")?;
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let data = vec![0x5A; 0x4100];
+
+        // plain files are put whole, with nothing left beside them
+        let mut files = OutputSink::open("files")?;
+        files.put(&path("GAME.app"), &data)?;
+        files.put(&path("nand.bin.gz"), &data)?;
+        // and one from a dump is dated when the dump was made
+        let dumped = DateTime::parse_from_rfc3339("2024-05-02T10:00:00+01:00")?;
+        files.put_dated(&path("OLD.sys"), b"old", Some(dumped))?;
+        files.finish()?;
+        if Some(std::fs::metadata(path("OLD.sys"))?.modified()?) != to_system_time(dumped) {
+            bail!("the file from a dump wasn't dated when it was dumped");
+        }
+        if read(path("GAME.app"))? != data {
+            bail!("the file wasn't saved as it was read");
+        }
+        if Codec::Gzip.decompress(&read(path("nand.bin.gz"))?)? != data {
+            bail!("the '.gz' file wasn't saved compressed");
+        }
+        let mut names = read_dir(&dir)?
+            .map(|e| Ok(e?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        assert_eq!(names, ["GAME.app", "OLD.sys", "nand.bin.gz"]);
+
+        // an archive gets each file as an entry, and is only finished on 'finish'
+        let archive = path("out.tar.gz");
+        let mut tar = OutputSink::open(&format!("tar:{archive}"))?;
+        assert_eq!(tar.to_string(), format!("tar:{archive}"));
+        tar.put("GAME.app", &data)?;
+        tar.put("TEST.sys", b"test")?;
+        tar.put_dated("OLD.sys", b"old", Some(dumped))?;
+        // one from before 1970 can't be, so is dated now
+        let early = DateTime::parse_from_rfc3339("1969-12-31T23:00:00Z")?;
+        tar.put_dated("EARLY.sys", b"early", Some(early))?;
+        tar.finish()?;
+        let gz = read(&archive)?;
+        let mut entries = vec![];
+        let mut mtimes = vec![];
+        for entry in tar::Archive::new(GzDecoder::new(&*gz)).entries()? {
+            let mut entry = entry?;
+            let mut contents = vec![];
+            entry.read_to_end(&mut contents)?;
+            mtimes.push(entry.header().mtime()?);
+            entries.push((entry.path()?.to_string_lossy().into_owned(), contents));
+        }
+        if entries
+            != [
+                ("GAME.app".into(), data),
+                ("TEST.sys".into(), b"test".to_vec()),
+                ("OLD.sys".into(), b"old".to_vec()),
+                ("EARLY.sys".into(), b"early".to_vec()),
+            ]
+        {
+            bail!(
+                "the archive held {:?}",
+                entries
+                    .iter()
+                    .map(|(n, d)| (n, d.len()))
+                    .collect::<Vec<_>>()
+            );
+        }
+        if mtimes[2] != 1_714_640_400 || mtimes[3] < mtimes[0] {
+            bail!("the entries were dated {mtimes:?}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use anyhow::{bail, Result};
+use byte_unit::Byte;
+
+use crate::fs::{BLOCK_SIZE, MAX_CARD_BLOCKS};
+
+// Sizes as they're shown to people. A corrupted FS entry can claim a size no card could hold (up
+// to 4 GiB), which is shown capped at the largest card's and marked with SUSPECT, rather than as
+// whatever it comes to; totals are added up checked, and say so when they don't fit.
+
+pub const MAX_PLAUSIBLE: u64 = (MAX_CARD_BLOCKS * BLOCK_SIZE) as u64;
+pub const SUSPECT: &str = "(!)";
+
+pub fn plausible(bytes: u64) -> bool {
+    bytes <= MAX_PLAUSIBLE
+}
+
+// in the most fitting unit, to `decimals` places
+pub fn format_size(bytes: u64, decimals: usize) -> String {
+    if !plausible(bytes) {
+        return format!(">{} {SUSPECT}", format_size(MAX_PLAUSIBLE, 0));
+    }
+    Byte::from_bytes(bytes as u128)
+        .get_appropriate_unit(true)
+        .format(decimals)
+}
+
+// None if the sizes add up to more than a u64 holds
+pub fn total(sizes: impl IntoIterator<Item = u64>) -> Option<u64> {
+    sizes.into_iter().try_fold(0u64, u64::checked_add)
+}
+
+pub fn format_total(total: Option<u64>, decimals: usize) -> String {
+    match total {
+        Some(bytes) => format_size(bytes, decimals),
+        None => format!("too much to add up {SUSPECT}"),
+    }
+}
+
+// the note under a listing that has suspect sizes in it
+pub fn suspect_note() -> String {
+    format!(
+        "{SUSPECT} more than any card holds, so the FS entry is likely corrupt; 'triage' checks the FS"
+    )
+}
+
+pub fn self_test() -> Result<()> {
+    for (bytes, expected) in [
+        (0, "0 B"),
+        (0x4000, "16 KiB"),
+        (MAX_PLAUSIBLE, "256 MiB"),
+        (MAX_PLAUSIBLE + 1, ">256 MiB (!)"),
+        (u32::MAX as u64, ">256 MiB (!)"),
+        (u64::MAX, ">256 MiB (!)"),
+    ] {
+        if format_size(bytes, 0) != expected {
+            bail!("{bytes} bytes was shown as '{}'", format_size(bytes, 0));
+        }
+    }
+
+    // totals that would overflow added up naively are reported, not wrapped
+    let maximal = [u32::MAX as u64; 409];
+    if total(maximal) != Some(409 * u32::MAX as u64) {
+        bail!("409 maximal sizes added up to {:?}", total(maximal));
+    }
+    for sizes in [
+        vec![u64::MAX, 1],
+        vec![u64::MAX / 2 + 1, u64::MAX / 2 + 1],
+        vec![1; 3].into_iter().chain([u64::MAX - 1]).collect(),
+    ] {
+        if total(sizes.iter().copied()).is_some() {
+            bail!("{sizes:?} didn't overflow");
+        }
+    }
+    if format_total(None, 0) != "too much to add up (!)"
+        || format_total(Some(0x4000), 0) != "16 KiB"
+    {
+        bail!("totals were shown as '{}'", format_total(None, 0));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn vectors() -> anyhow::Result<()> {
+        super::self_test()
+    }
+}
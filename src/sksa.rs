@@ -0,0 +1,265 @@
+use std::cmp::Ordering;
+
+use anyhow::{anyhow, Result};
+
+use crate::cancel::CancelToken;
+use crate::fs::{BLOCK_SIZE, SKSA_BLOCKS, SPARE_SIZE};
+use crate::player::{Backend, Player};
+use crate::progress::Progress;
+use crate::ranges::{block_ranges, format_range};
+use crate::spare::is_bad_block;
+use crate::throughput::TransferTimer;
+
+// 'K': the SKSA, through the console's ReadSKSA call, which gives back whatever it gives back in
+// one go, or with '--raw-region' as the first SKSA_BLOCKS blocks read one at a time, spare data
+// and all, as a cross-check for when the call misbehaves. Either is checked against the size of
+// the SKSA region, and when both are read they're compared block by block.
+
+// the size of the SKSA region on a card of `card_blocks` blocks
+pub fn expected_len(card_blocks: u32) -> usize {
+    (SKSA_BLOCKS as usize).min(card_blocks as usize) * BLOCK_SIZE
+}
+
+// a warning if ReadSKSA's result isn't the size of the SKSA region
+pub fn check_length(len: usize, expected: usize) -> Option<String> {
+    if len == 0 {
+        return Some(
+            "ReadSKSA returned nothing; 'K --raw-region' reads the SKSA region block by block instead"
+                .to_string(),
+        );
+    }
+    let blocks = match len % BLOCK_SIZE {
+        0 => format!("{} blocks", len / BLOCK_SIZE),
+        _ => format!(
+            "{:.2} blocks, not a whole number",
+            len as f64 / BLOCK_SIZE as f64
+        ),
+    };
+    match len.cmp(&expected) {
+        Ordering::Less => Some(format!(
+            "ReadSKSA returned {len:#X} bytes ({blocks}), short of the {expected:#X}-byte SKSA region; 'K --both' reads the region block by block as well and compares them"
+        )),
+        Ordering::Greater => Some(format!(
+            "ReadSKSA returned {len:#X} bytes ({blocks}), more than the {expected:#X}-byte SKSA region; the rest isn't from the SKSA"
+        )),
+        Ordering::Equal => None,
+    }
+}
+
+// ReadSKSA is one call with nothing to report partway, so its progress is all or nothing
+pub fn read_call(player: &dyn Backend, card_blocks: u32, events: bool) -> Result<Vec<u8>> {
+    let mut progress = Progress::start("read-sksa", 1, expected_len(card_blocks), events);
+    progress.set_message("Reading the SKSA".to_string());
+    match player.ReadSKSA() {
+        Ok(sksa) => {
+            progress.inc(1);
+            progress.finish();
+            Ok(sksa)
+        }
+        Err(e) => {
+            progress.fail(&e.to_string());
+            Err(e)
+        }
+    }
+}
+
+// the SKSA region's blocks and their spare data; cancellable between blocks
+pub fn read_region(
+    player: &dyn Player,
+    card_blocks: u32,
+    events: bool,
+    cancel: &CancelToken,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let blocks = (expected_len(card_blocks) / BLOCK_SIZE) as u32;
+    let timer = TransferTimer::begin(player, "SKSA region", blocks as u64 * BLOCK_SIZE as u64);
+    let mut progress = Progress::start("read-sksa", blocks as u64, BLOCK_SIZE, events);
+    progress.set_message("Reading the SKSA region".to_string());
+    let (mut nand, mut spare) = (vec![], vec![]);
+    for blk in 0..blocks {
+        match cancel.check().and_then(|_| player.ReadSingleBlock(blk)) {
+            Ok((n, s)) => {
+                nand.extend_from_slice(&n);
+                spare.extend_from_slice(&s);
+                progress.inc(1);
+            }
+            Err(e) => {
+                let e = anyhow!("Failed to read block {blk:#X}: {e}");
+                progress.fail(&e.to_string());
+                return Err(e);
+            }
+        }
+    }
+    progress.finish();
+    timer.complete();
+    Ok((nand, spare))
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Comparison {
+    // blocks both have, and those of them that differ
+    pub compared: usize,
+    pub differing: Vec<u16>,
+    // blocks of the region past the end of ReadSKSA's result that hold data
+    pub missing: Vec<u16>,
+    // bytes of ReadSKSA's result past the end of the region
+    pub extra: usize,
+    // blocks of the region marked bad, which ReadSKSA may have skipped
+    pub bad: Vec<u16>,
+}
+
+impl Comparison {
+    pub fn agrees(&self) -> bool {
+        self.differing.is_empty() && self.missing.is_empty() && self.extra == 0
+    }
+}
+
+// ReadSKSA's result against the region read block by block; a partial last block of the result
+// is compared as far as it goes
+pub fn compare(call: &[u8], region: &[u8], region_spare: &[u8]) -> Comparison {
+    let mut c = Comparison {
+        extra: call.len().saturating_sub(region.len()),
+        ..Default::default()
+    };
+    for (blk, block) in region.chunks(BLOCK_SIZE).enumerate() {
+        let blk16 = blk as u16;
+        if region_spare
+            .get(blk * SPARE_SIZE..(blk + 1) * SPARE_SIZE)
+            .is_some_and(is_bad_block)
+        {
+            c.bad.push(blk16);
+        }
+        match call.get(blk * BLOCK_SIZE..) {
+            Some(rest) if !rest.is_empty() => {
+                let theirs = &rest[..rest.len().min(BLOCK_SIZE)];
+                c.compared += 1;
+                if theirs != &block[..theirs.len()] {
+                    c.differing.push(blk16);
+                }
+            }
+            _ => {
+                if block.iter().any(|&b| b != 0xFF) {
+                    c.missing.push(blk16);
+                }
+            }
+        }
+    }
+    c
+}
+
+fn format_blocks(blocks: &[u16]) -> String {
+    block_ranges(blocks)
+        .iter()
+        .map(format_range)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn describe(c: &Comparison) -> Vec<String> {
+    if c.agrees() {
+        return vec![format!(
+            "ReadSKSA and the SKSA region agree over the {} blocks ReadSKSA returned",
+            c.compared
+        )];
+    }
+    let mut lines = vec!["ReadSKSA and the SKSA region read block by block disagree:".to_string()];
+    if !c.differing.is_empty() {
+        lines.push(format!(
+            "  {} of {} blocks differ: {}",
+            c.differing.len(),
+            c.compared,
+            format_blocks(&c.differing)
+        ));
+    }
+    if !c.missing.is_empty() {
+        lines.push(format!(
+            "  blocks {} hold data, but are past the end of what ReadSKSA returned",
+            format_blocks(&c.missing)
+        ));
+    }
+    if c.extra > 0 {
+        lines.push(format!(
+            "  ReadSKSA returned {:#X} bytes past the end of the region",
+            c.extra
+        ));
+    }
+    if !c.bad.is_empty() {
+        lines.push(format!(
+            "  blocks {} are marked bad; if ReadSKSA skips them, everything after the first is shifted",
+            format_blocks(&c.bad)
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    #[test]
+    fn sksa_reads() -> Result<()> {
+        use crate::spare::mark_bad;
+
+        // the region is the same size on both sizes of card, and only a full one passes
+        let full = expected_len(0x1000);
+        if full != 0x100000 || expected_len(0x2000) != full {
+            bail!("the SKSA region was {full:#X} bytes");
+        }
+        if check_length(full, full).is_some() {
+            bail!("a full SKSA region was warned about");
+        }
+        for (len, expected) in [
+            (0, "returned nothing"),
+            (
+                0x4000,
+                "0x4000 bytes (1 blocks), short of the 0x100000-byte",
+            ),
+            (0x104000, "more than the 0x100000-byte"),
+            (0x6000, "1.50 blocks, not a whole number"),
+        ] {
+            match check_length(len, full) {
+                Some(w) if w.contains(expected) => {}
+                other => bail!("{len:#X} bytes gave {other:?}"),
+            }
+        }
+
+        // a region of four blocks, each its own byte, the last erased
+        let mut region = (0..4 * BLOCK_SIZE)
+            .map(|i| (i / BLOCK_SIZE) as u8)
+            .collect::<Vec<_>>();
+        region[3 * BLOCK_SIZE..].fill(0xFF);
+        let spare = vec![0xFF; 4 * SPARE_SIZE];
+        let same = compare(&region, &region, &spare);
+        if !same.agrees() || same.compared != 4 || !describe(&same)[0].contains("agree over the 4")
+        {
+            bail!("identical reads compared as {same:?}");
+        }
+        // a call that stops early is fine if what it left out is erased, not if it holds data
+        if !compare(&region[..3 * BLOCK_SIZE], &region, &spare).agrees() {
+            bail!("an erased block past the end of ReadSKSA's result counted against it");
+        }
+        let short = compare(&region[..BLOCK_SIZE + 0x100], &region, &spare);
+        if short.missing != [2] || short.compared != 2 || !short.differing.is_empty() {
+            bail!("a short ReadSKSA compared as {short:?}");
+        }
+
+        // a changed byte, extra bytes, and a block marked bad are all reported
+        let mut call = region.clone();
+        call[BLOCK_SIZE + 5] ^= 1;
+        call.extend_from_slice(&[0; 0x10]);
+        let mut spare = spare;
+        mark_bad(&mut spare[SPARE_SIZE..2 * SPARE_SIZE]);
+        let c = compare(&call, &region, &spare);
+        let lines = describe(&c).join("\n");
+        if c.differing != [1]
+            || c.extra != 0x10
+            || c.bad != [1]
+            || !lines.contains("1 of 4 blocks differ: 0x1")
+            || !lines.contains("0x10 bytes past the end")
+            || !lines.contains("blocks 0x1 are marked bad")
+        {
+            bail!("a differing ReadSKSA compared as {c:?}:\n{lines}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+#![allow(non_snake_case)]
+
+use std::ops::Deref;
+#[cfg(feature = "writing")]
+use std::ops::DerefMut;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use bbrdb::CardStats;
+
+use crate::fs::BLOCK_SIZE;
+use crate::player::Player;
+#[cfg(feature = "writing")]
+use crate::player::PlayerWrite;
+
+// A developer aid: a proxy for the active console or dump that takes as long as a real USB link
+// to move each block, and can fail once at a chosen block, so progress bars, cancellation and the
+// retry and salvage paths can be tried out against a mounted dump. Only block transfers are
+// slowed; commands that only ask the console a question aren't.
+
+#[derive(Debug, Default)]
+pub struct Simulation {
+    pub per_block: Duration,
+    pub fail_at: Option<u32>,
+    // the failure only happens once, so that retrying gets past it
+    failed: AtomicBool,
+}
+
+impl Simulation {
+    pub fn new(per_block: Duration, fail_at: Option<u32>) -> Self {
+        Self {
+            per_block,
+            fail_at,
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    fn transfer(&self, blocks: usize) {
+        sleep(self.per_block * blocks as u32);
+    }
+
+    fn check(&self, blk: u32) -> Result<()> {
+        if self.fail_at == Some(blk) && !self.failed.swap(true, Ordering::Relaxed) {
+            bail!("simulated failure at block {blk:#X}");
+        }
+        Ok(())
+    }
+}
+
+static SIMULATION: OnceLock<Simulation> = OnceLock::new();
+
+// the simulation chosen at startup; with none, the proxy passes everything straight through
+pub fn simulation() -> &'static Simulation {
+    SIMULATION.get_or_init(Simulation::default)
+}
+
+// reads '--simulate-latency <ms-per-block>' and '--simulate-fail-at <blkno>' from the command line
+pub fn simulation_args(args: &[String]) -> Result<Option<Simulation>> {
+    let value = |flag: &str| -> Result<Option<u32>> {
+        let Some(i) = args.iter().position(|a| a == flag) else {
+            return Ok(None);
+        };
+        let value = args
+            .get(i + 1)
+            .ok_or_else(|| anyhow!("'{flag}' requires an argument"))?;
+        parse_int::parse::<u32>(value)
+            .map(Some)
+            .map_err(|e| anyhow!("'{flag} {value}': {e}"))
+    };
+    let latency = value("--simulate-latency")?;
+    let fail_at = value("--simulate-fail-at")?;
+    if latency.is_none() && fail_at.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(Simulation::new(
+        Duration::from_millis(latency.unwrap_or(0) as u64),
+        fail_at,
+    )))
+}
+
+pub fn start_simulation(simulation: Simulation) -> Result<()> {
+    SIMULATION
+        .set(simulation)
+        .map_err(|_| anyhow!("the link simulation has already started"))
+}
+
+pub struct SlowLink<'s, P> {
+    inner: P,
+    simulation: &'s Simulation,
+}
+
+impl<'s, P> SlowLink<'s, P> {
+    pub fn new(inner: P, simulation: &'s Simulation) -> Self {
+        Self { inner, simulation }
+    }
+}
+
+fn blocks(len: usize) -> usize {
+    len.div_ceil(BLOCK_SIZE)
+}
+
+impl<P> Player for SlowLink<'_, P>
+where
+    P: Deref,
+    P::Target: Player,
+{
+    fn GetBBID(&self) -> Result<u32> {
+        self.inner.GetBBID()
+    }
+
+    fn SetLED(&self, value: u32) -> Result<()> {
+        self.inner.SetLED(value)
+    }
+
+    fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+        self.inner.ListFiles()
+    }
+
+    fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+        self.simulation.transfer(1);
+        self.inner.DumpCurrentFS()
+    }
+
+    fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let data = self.inner.ReadFile(name)?;
+        self.simulation
+            .transfer(data.as_ref().map_or(0, |d| blocks(d.len())));
+        Ok(data)
+    }
+
+    fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        self.simulation.transfer(1);
+        self.simulation.check(blk)?;
+        self.inner.ReadSingleBlock(blk)
+    }
+
+    fn CardStats(&self) -> Result<CardStats> {
+        self.inner.CardStats()
+    }
+}
+
+// each change ends with writing a new FS block, which takes a block of its own
+#[cfg(feature = "writing")]
+impl<P> PlayerWrite for SlowLink<'_, P>
+where
+    P: DerefMut,
+    P::Target: PlayerWrite,
+{
+    fn WriteFile(&mut self, data: &[u8], name: &str) -> Result<()> {
+        self.simulation.transfer(blocks(data.len()) + 1);
+        self.inner.WriteFile(data, name)
+    }
+
+    fn DeleteFile(&mut self, name: &str) -> Result<()> {
+        self.simulation.transfer(1);
+        self.inner.DeleteFile(name)
+    }
+
+    fn RenameFile(&mut self, from: &str, to: &str) -> Result<()> {
+        self.simulation.transfer(1);
+        self.inner.RenameFile(from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_link() -> Result<()> {
+        struct Card;
+
+        impl Player for Card {
+            fn GetBBID(&self) -> Result<u32> {
+                Ok(0x1234)
+            }
+
+            fn SetLED(&self, _value: u32) -> Result<()> {
+                Ok(())
+            }
+
+            fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+                Ok(vec![("GAME.app".to_string(), 0x8000)])
+            }
+
+            fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+                Ok(vec![0xAA; BLOCK_SIZE])
+            }
+
+            fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+                Ok((name == "GAME.app").then(|| vec![0x55; 0x8000]))
+            }
+
+            fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+                Ok((vec![blk as u8; BLOCK_SIZE], vec![!(blk as u8); 0x10]))
+            }
+
+            fn CardStats(&self) -> Result<CardStats> {
+                Ok(CardStats {
+                    free: 1,
+                    used: 2,
+                    bad: 3,
+                    seqno: 4,
+                })
+            }
+        }
+
+        let card = Card;
+        let simulation = Simulation::new(Duration::ZERO, Some(7));
+        let link = SlowLink::new(&card, &simulation);
+        let stats = |s: CardStats| (s.free, s.used, s.bad, s.seqno);
+        if link.GetBBID()? != card.GetBBID()?
+            || link.ListFiles()? != card.ListFiles()?
+            || link.DumpCurrentFS()? != card.DumpCurrentFS()?
+            || link.ReadFile("GAME.app")? != card.ReadFile("GAME.app")?
+            || link.ReadFile("missing")?.is_some()
+            || stats(link.CardStats()?) != stats(card.CardStats()?)
+        {
+            bail!("the proxy didn't pass a call through unchanged");
+        }
+        for blk in [0, 6, 8] {
+            if link.ReadSingleBlock(blk)? != card.ReadSingleBlock(blk)? {
+                bail!("block {blk:#X} didn't pass through unchanged");
+            }
+        }
+        if link.ReadSingleBlock(7).is_ok() {
+            bail!("the failure wasn't injected at block 0x7");
+        }
+        if link.ReadSingleBlock(7)? != card.ReadSingleBlock(7)? {
+            bail!("block 0x7 still failed after the injected failure");
+        }
+
+        let delayed = Simulation::new(Duration::from_millis(2), None);
+        let start = std::time::Instant::now();
+        SlowLink::new(&card, &delayed).ReadFile("GAME.app")?;
+        if start.elapsed() < Duration::from_millis(4) {
+            bail!("reading a two-block file wasn't slowed down");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+#[cfg(feature = "writing")]
+use anyhow::Result;
+
+use crate::ecc::{check256, page_ecc, EccCheck, ECC_AREA_1, ECC_AREA_2, ECC_CHUNK};
+use crate::fs::SPARE_SIZE;
+
+// offset of the factory bad block marker within a block's spare data
+const BAD_BLOCK_MARKER: usize = 5;
+
+/// Whether a block's spare data marks it bad: its marker has more than one bit clear, or it's too
+/// short to have one.
+pub fn is_bad_block(spare: &[u8]) -> bool {
+    spare
+        .get(BAD_BLOCK_MARKER)
+        .is_none_or(|&marker| marker.count_ones() < 7)
+}
+
+/// Sets the bad block marker, for a block that shouldn't be used again.
+pub fn mark_bad(spare: &mut [u8]) {
+    spare[BAD_BLOCK_MARKER] = 0;
+}
+
+/// Clears the bad block marker, for a block the user has said to treat as good.
+#[cfg(feature = "writing")]
+pub fn clear_bad(spare: &mut [u8]) {
+    spare[BAD_BLOCK_MARKER] = 0xFF;
+}
+
+// bytes at the start of an SKSA block's spare data that link it into the SA chain
+const SA_LINK_BYTES: usize = 3;
+
+/// Whether a block's spare data marks it as part of the SA chain.
+#[cfg(feature = "writing")]
+pub fn sa_marked(spare: &[u8]) -> bool {
+    spare
+        .get(..SA_LINK_BYTES)
+        .is_some_and(|link| link.iter().any(|&b| b != 0xFF))
+}
+
+/// The block the SA chain goes on to from a block with this spare data; None for the last block
+/// of the chain, and for anything outside it.
+#[cfg(feature = "writing")]
+pub fn sa_link(spare: &[u8]) -> Option<u16> {
+    spare
+        .get(..SA_LINK_BYTES)?
+        .iter()
+        .find(|&&b| b != 0xFF)
+        .map(|&b| b as u16)
+}
+
+/// Spare data for writing `block` where no spare file is available: the ECC is generated from
+/// the block's contents, and the bad block marker (and, in the SKSA, the SA link bytes, which
+/// depend on how the SKSA was laid out rather than on the block itself) are kept from the
+/// spare data currently on the console. Fails if `block` is short of a page.
+#[cfg(feature = "writing")]
+pub fn synthesize_spare(block: &[u8], existing: &[u8], in_sksa: bool) -> Result<[u8; SPARE_SIZE]> {
+    let mut spare = [0xFF; SPARE_SIZE];
+    spare[BAD_BLOCK_MARKER] = existing[BAD_BLOCK_MARKER];
+    if in_sksa {
+        spare[..SA_LINK_BYTES].copy_from_slice(&existing[..SA_LINK_BYTES]);
+    }
+    page_ecc(block, &mut spare)?;
+    Ok(spare)
+}
+
+/// Whether the ECC in a block's spare data is what the generator would produce for it.
+pub fn ecc_matches(block: &[u8], spare: &[u8]) -> bool {
+    let mut expected = [0xFF; SPARE_SIZE];
+    page_ecc(block, &mut expected).is_ok()
+        && [ECC_AREA_2, ECC_AREA_1]
+            .iter()
+            .all(|&at| spare.get(at..at + 3) == Some(&expected[at..at + 3]))
+}
+
+/// The worst of the first page's two chunks against the ECC in the spare data; a block or spare
+/// data too short to hold them can't be trusted, so is uncorrectable.
+pub fn ecc_check(block: &[u8], spare: &[u8]) -> EccCheck {
+    let check = |chunk: usize, at: usize| {
+        let stored = spare.get(at..at + 3)?.try_into().ok()?;
+        check256(
+            block.get(chunk * ECC_CHUNK..(chunk + 1) * ECC_CHUNK)?,
+            stored,
+        )
+        .ok()
+    };
+    match (check(0, ECC_AREA_1), check(1, ECC_AREA_2)) {
+        (Some(first), Some(second)) => first.max(second),
+        _ => EccCheck::Uncorrectable,
+    }
+}
+
+/// The worst of a page's chunks against the ECC in its block's spare data; only the first page's
+/// ECC is kept there, so any other page has nothing to check against.
+pub fn page_ecc_check(page: usize, data: &[u8], spare: &[u8]) -> Option<EccCheck> {
+    (page == 0).then(|| ecc_check(data, spare))
+}
+
+/// A block's spare data, field by field, for reading by hand.
+pub fn describe_spare(spare: &[u8]) -> Vec<String> {
+    if spare.len() < SPARE_SIZE {
+        return vec![format!(
+            "{} bytes, short of the {SPARE_SIZE} spare data has",
+            spare.len()
+        )];
+    }
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let link = &spare[..SA_LINK_BYTES];
+    vec![
+        format!(
+            "SA link      {} ({})",
+            hex(link),
+            match link.iter().all(|&b| b == 0xFF) {
+                true => "not in the SA chain",
+                false => "in the SA chain",
+            }
+        ),
+        format!(
+            "bad block    {:02x} ({})",
+            spare[BAD_BLOCK_MARKER],
+            match is_bad_block(spare) {
+                true => "marked bad",
+                false => "good",
+            }
+        ),
+        format!(
+            "ECC          {} (bytes 0x000-0x0FF), {} (bytes 0x100-0x1FF)",
+            hex(&spare[ECC_AREA_1..ECC_AREA_1 + 3]),
+            hex(&spare[ECC_AREA_2..ECC_AREA_2 + 3])
+        ),
+    ]
+}
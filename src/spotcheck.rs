@@ -0,0 +1,389 @@
+use std::fs::read_to_string;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::cancel::CancelToken;
+use crate::fs::BLOCK_SIZE;
+use crate::hashing::{parse_algos, HashAlgo, HashValue};
+use crate::player::Player;
+use crate::progress::Progress;
+
+// A CRC sidecar ('<nand>.crcs') is a CSV file with a header line, then one line per block:
+//
+//   block,crc32
+//   0x0,0x1A2B3C4D
+//   ...
+//
+// with the CRC-32 (IEEE) of each block's data, in block order. Other algorithms ('1 --crcs
+// --algo', or 'set hash-algos') give a column each, named in the header and holding hex digests:
+// 'block,sha256,crc32'. The CRC-32 column keeps its 0x form either way.
+
+// the hashes of each block, in the sidecar's columns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sidecar {
+    pub algos: Vec<HashAlgo>,
+    pub blocks: Vec<Vec<HashValue>>,
+}
+
+pub fn block_hashes(nand: &[u8], algos: &[HashAlgo]) -> Sidecar {
+    Sidecar {
+        algos: algos.to_vec(),
+        blocks: nand
+            .chunks(BLOCK_SIZE)
+            .map(|b| algos.iter().map(|a| a.digest(b)).collect())
+            .collect(),
+    }
+}
+
+fn cell(value: &HashValue) -> String {
+    match value.algo {
+        HashAlgo::Crc32 => format!("0x{}", value.hex().to_ascii_uppercase()),
+        _ => value.hex(),
+    }
+}
+
+fn parse_cell(algo: HashAlgo, s: &str) -> Result<HashValue> {
+    match algo {
+        HashAlgo::Crc32 => Ok(HashValue {
+            algo,
+            bytes: parse_int::parse::<u32>(s.trim())?.to_be_bytes().to_vec(),
+        }),
+        _ => HashValue::from_hex(algo, s),
+    }
+}
+
+pub fn sidecar_to_csv(sidecar: &Sidecar) -> String {
+    let names = sidecar.algos.iter().map(|a| a.name()).collect::<Vec<_>>();
+    let mut csv = format!("block,{}\n", names.join(","));
+    for (blk, hashes) in sidecar.blocks.iter().enumerate() {
+        let cells = hashes.iter().map(cell).collect::<Vec<_>>();
+        csv += &format!("{blk:#X},{}\n", cells.join(","));
+    }
+    csv
+}
+
+pub fn parse_sidecar(csv: &str) -> Result<Sidecar> {
+    let mut lines = csv.lines();
+    let header = lines.next().unwrap_or_default().trim();
+    let algos = header
+        .strip_prefix("block,")
+        .ok_or_else(|| anyhow!("not a CRC sidecar: expected a 'block,crc32' header"))
+        .and_then(parse_algos)
+        .map_err(|e| anyhow!("line 1: {e}"))?;
+    let blocks = lines
+        .filter(|l| !l.trim().is_empty())
+        .enumerate()
+        .map(|(i, line)| {
+            let cells = line.split(',').collect::<Vec<_>>();
+            if cells.len() != algos.len() + 1 {
+                bail!("line {}: expected '{header}'", i + 2);
+            }
+            if parse_int::parse::<u32>(cells[0].trim()).ok() != Some(i as u32) {
+                bail!(
+                    "line {}: expected block {i:#X}, found '{}'",
+                    i + 2,
+                    cells[0]
+                );
+            }
+            algos
+                .iter()
+                .zip(&cells[1..])
+                .map(|(&algo, c)| {
+                    parse_cell(algo, c).map_err(|e| anyhow!("line {}: '{c}': {e}", i + 2))
+                })
+                .collect()
+        })
+        .collect::<Result<_>>()?;
+    Ok(Sidecar { algos, blocks })
+}
+
+// the sidecar's columns to check: those of the `wanted` algorithms, or all of them if none are
+// asked for; a column is only ever compared with a block hashed the same way
+pub fn columns(sidecar: &[HashAlgo], wanted: Option<&[HashAlgo]>) -> Result<Vec<usize>> {
+    let Some(wanted) = wanted else {
+        return Ok((0..sidecar.len()).collect());
+    };
+    let found = wanted
+        .iter()
+        .filter_map(|w| sidecar.iter().position(|a| a == w))
+        .collect::<Vec<_>>();
+    if found.is_empty() {
+        let names = |a: &[HashAlgo]| a.iter().map(|a| a.name()).collect::<Vec<_>>().join(", ");
+        bail!(
+            "the sidecar only has {}, not {}",
+            names(sidecar),
+            names(wanted)
+        );
+    }
+    Ok(found)
+}
+
+// splitmix64; not for anything security-related, just a reproducible sample
+pub struct SampleRng(u64);
+
+impl SampleRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn from_time() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// `count` distinct blocks out of `total`, in ascending order
+pub fn sample_blocks(total: usize, count: usize, rng: &mut SampleRng) -> Vec<u32> {
+    let count = count.min(total);
+    let mut blocks = (0..total as u32).collect::<Vec<_>>();
+    for i in 0..count {
+        let j = i + rng.below(total - i);
+        blocks.swap(i, j);
+    }
+    blocks.truncate(count);
+    blocks.sort_unstable();
+    blocks
+}
+
+// the smallest fraction of differing blocks that a clean sample of this size would have caught
+// 95% of the time
+pub fn detectable_fraction(sampled: usize) -> f64 {
+    1.0 - 0.05f64.powf(1.0 / sampled as f64)
+}
+
+// reads the sampled blocks, returning those that don't match the sidecar's hashes in any of
+// `columns`; cancellable between blocks
+fn check_sample(
+    player: &dyn Player,
+    hashes: &Sidecar,
+    columns: &[usize],
+    blocks: &[u32],
+    events: bool,
+    cancel: &CancelToken,
+) -> Result<Vec<u32>> {
+    let mut mismatched = vec![];
+    let mut progress = Progress::start("spotcheck", blocks.len() as u64, BLOCK_SIZE, events);
+    for &blk in blocks {
+        match cancel.check().and_then(|_| player.ReadSingleBlock(blk)) {
+            Ok((data, _)) => {
+                let expected = &hashes.blocks[blk as usize];
+                if columns
+                    .iter()
+                    .any(|&c| expected[c].algo.digest(&data) != expected[c])
+                {
+                    mismatched.push(blk);
+                }
+                progress.inc(1);
+            }
+            Err(e) => {
+                let e = anyhow!("Failed to read block {blk:#X}: {e}");
+                progress.fail(&e.to_string());
+                return Err(e);
+            }
+        }
+    }
+    progress.finish();
+    Ok(mismatched)
+}
+
+pub fn spotcheck(
+    player: &dyn Player,
+    sidecar: &str,
+    algos: Option<&[HashAlgo]>,
+    count: usize,
+    seed: u64,
+    events: bool,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let hashes = parse_sidecar(&read_to_string(sidecar).map_err(|e| anyhow!("{sidecar}: {e}"))?)
+        .map_err(|e| anyhow!("{sidecar}: {e}"))?;
+    let columns = columns(&hashes.algos, algos).map_err(|e| anyhow!("{sidecar}: {e}"))?;
+    if hashes.blocks.is_empty() || count == 0 {
+        bail!("Nothing to check");
+    }
+
+    let total = hashes.blocks.len();
+    let blocks = sample_blocks(total, count, &mut SampleRng::new(seed));
+    println!("Checking {} of {total} blocks (seed {seed})", blocks.len());
+
+    let mismatched = check_sample(player, &hashes, &columns, &blocks, events, cancel)?;
+    if mismatched.is_empty() {
+        println!(
+            "All {} sampled blocks match. If {:.1}% or more of the blocks differed, a sample this size would have found one 95% of the time.",
+            blocks.len(),
+            detectable_fraction(blocks.len()) * 100.0
+        );
+    } else {
+        println!(
+            "{} of {} sampled blocks don't match: {}",
+            mismatched.len(),
+            blocks.len(),
+            mismatched
+                .iter()
+                .map(|b| format!("{b:#X}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bbrdb::CardStats;
+
+    #[test]
+    fn crc_sidecars() -> Result<()> {
+        let mut nand = vec![0xFF; BLOCK_SIZE * 3];
+        nand[BLOCK_SIZE..BLOCK_SIZE + 9].copy_from_slice(b"123456789");
+        let crcs = block_hashes(&nand, &[HashAlgo::Crc32]);
+        let csv = sidecar_to_csv(&crcs);
+        if parse_sidecar(&csv)? != crcs || !csv.starts_with("block,crc32\n0x0,0x") {
+            bail!("sidecar didn't round-trip, or isn't in the original format:\n{csv}");
+        }
+        let both = block_hashes(&nand, &[HashAlgo::Sha256, HashAlgo::Crc32]);
+        if parse_sidecar(&sidecar_to_csv(&both))? != both || both.blocks[1][1] != crcs.blocks[1][0]
+        {
+            bail!("a sidecar with two columns didn't round-trip");
+        }
+        if parse_sidecar("block,crc32\n0x0,0x12345678,0x0\n").is_ok()
+            || parse_sidecar("block,crc64\n0x0,0x12345678\n").is_ok()
+        {
+            bail!("a malformed sidecar was accepted");
+        }
+
+        // only columns of the asked-for algorithms are checked, and asking for one that isn't there
+        // fails rather than comparing against another column
+        use HashAlgo::*;
+        if columns(&both.algos, None)? != [0, 1]
+            || columns(&both.algos, Some(&[Crc32]))? != [1]
+            || columns(&both.algos, Some(&[Md5, Sha256]))? != [0]
+            || columns(&crcs.algos, Some(&[Sha256])).is_ok()
+        {
+            bail!("the wrong sidecar columns were chosen");
+        }
+        let sample = sample_blocks(0x1000, 64, &mut SampleRng::new(1));
+        if sample != sample_blocks(0x1000, 64, &mut SampleRng::new(1))
+            || sample.windows(2).any(|w| w[0] >= w[1])
+            || sample.len() != 64
+        {
+            bail!("block sampling isn't reproducible, or repeats blocks");
+        }
+        Ok(())
+    }
+
+    // a card holding `nand`, with one block that's changed since the sidecar was made and one
+    // that can't be read
+    struct Card {
+        nand: Vec<u8>,
+        changed: u32,
+        unreadable: u32,
+    }
+
+    impl Player for Card {
+        fn GetBBID(&self) -> Result<u32> {
+            Ok(0x1234)
+        }
+
+        fn SetLED(&self, _value: u32) -> Result<()> {
+            Ok(())
+        }
+
+        fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+            Ok(vec![])
+        }
+
+        fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+            Ok(vec![])
+        }
+
+        fn ReadFile(&self, _name: &str) -> Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+            if blk == self.unreadable {
+                bail!("Operation timed out");
+            }
+            let b = blk as usize;
+            let mut data = self.nand[b * BLOCK_SIZE..(b + 1) * BLOCK_SIZE].to_vec();
+            if blk == self.changed {
+                data[0] ^= 1;
+            }
+            Ok((data, vec![0xFF; 0x10]))
+        }
+
+        fn CardStats(&self) -> Result<CardStats> {
+            Ok(CardStats {
+                free: 0,
+                used: 0,
+                bad: 0,
+                seqno: 0,
+            })
+        }
+    }
+
+    #[test]
+    fn sampling() -> Result<()> {
+        let nand = (0..0x40 * BLOCK_SIZE)
+            .map(|i| (i / BLOCK_SIZE) as u8 ^ i as u8)
+            .collect::<Vec<_>>();
+        let hashes = block_hashes(&nand, &[HashAlgo::Crc32, HashAlgo::Sha256]);
+        let card = Card {
+            nand,
+            changed: 0x21,
+            unreadable: 0x3F,
+        };
+        let cancel = CancelToken::default();
+
+        // only the changed block is found, and only if it's in the sample
+        let check = |blocks: &[u32]| check_sample(&card, &hashes, &[0, 1], blocks, false, &cancel);
+        assert_eq!(check(&[0x0, 0x20, 0x21, 0x22])?, [0x21]);
+        assert!(check(&[0x1, 0x2])?.is_empty());
+        assert_eq!(
+            check(&[0x3E, 0x3F]).err().map(|e| e.to_string()),
+            Some("Failed to read block 0x3F: Operation timed out".to_string())
+        );
+
+        // the whole card sampled finds it whatever the seed, and asking for more blocks than
+        // there are takes them all
+        for seed in [0, 1, 0xDEAD] {
+            let all = sample_blocks(0x3F, 0x100, &mut SampleRng::new(seed));
+            assert_eq!(all, (0..0x3F).collect::<Vec<_>>());
+            assert_eq!(check(&all)?, [0x21]);
+        }
+        if sample_blocks(0x1000, 16, &mut SampleRng::new(1))
+            == sample_blocks(0x1000, 16, &mut SampleRng::new(2))
+        {
+            bail!("different seeds gave the same sample");
+        }
+
+        cancel.cancel();
+        if check(&[0x0]).is_ok() {
+            bail!("a cancelled check read on");
+        }
+
+        // a bigger clean sample rules out smaller differences
+        assert!((detectable_fraction(1) - 0.95).abs() < 1e-9);
+        assert!(
+            detectable_fraction(100) < 0.03 && detectable_fraction(100) > detectable_fraction(1000)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt::{self, Display};
+
+// What this session knows about the console's card, and the FS generation it was learned from.
+// Another tool (or another copy of this one) can change the card between commands, and every
+// change writes a new FS generation with a higher sequence number, so before a command that
+// changes the card the current number is compared with the one recorded here; if they differ,
+// whatever was known is thrown away and the user is asked before going ahead.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CardView {
+    seqno: Option<u32>,
+    // from the last 'C', or the card stats read after a change, for 'lint'
+    free_blocks: Option<u32>,
+    // a command that changes the card is running, so the next generation is this session's own
+    changing: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stale {
+    pub was: u32,
+    pub now: u32,
+}
+
+impl Display for Stale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The card has changed since this session last read it (FS sequence number {}, now {}), probably by another tool; what was known about it has been discarded.",
+            self.was, self.now
+        )
+    }
+}
+
+impl CardView {
+    // from a saved session
+    pub fn restored(free_blocks: Option<u32>, seqno: Option<u32>) -> Self {
+        Self {
+            seqno,
+            free_blocks,
+            changing: false,
+        }
+    }
+
+    pub fn seqno(&self) -> Option<u32> {
+        self.seqno
+    }
+
+    pub fn free_blocks(&self) -> Option<u32> {
+        self.free_blocks
+    }
+
+    // from 'C' on a mounted dump
+    pub fn set_free_blocks(&mut self, free_blocks: u32) {
+        self.free_blocks = Some(free_blocks);
+    }
+
+    // from 'C', which reads both at once
+    pub fn read_stats(&mut self, free_blocks: u32, seqno: u32) {
+        self.seqno = Some(seqno);
+        self.free_blocks = Some(free_blocks);
+    }
+
+    pub fn forget(&mut self) {
+        *self = Self::default();
+    }
+
+    // before a command that changes the card, with the card's sequence number now; the first
+    // time, there's nothing to compare with
+    pub fn begin_change(&mut self, seqno: u32) -> Option<Stale> {
+        self.changing = true;
+        let was = self.seqno.replace(seqno)?;
+        if was == seqno {
+            return None;
+        }
+        self.free_blocks = None;
+        Some(Stale { was, now: seqno })
+    }
+
+    // after it, with the card's sequence number and free blocks then (None if they couldn't be
+    // read), so the generation it wrote isn't taken for someone else's, and the next upload is
+    // checked against the space that's left
+    pub fn end_change(&mut self, seqno: Option<u32>, free_blocks: Option<u32>) {
+        if std::mem::take(&mut self.changing) {
+            self.seqno = seqno;
+            self.free_blocks = free_blocks;
+        }
+    }
+
+    pub fn changing(&self) -> bool {
+        self.changing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Result};
+
+    #[test]
+    fn card_changes() -> Result<()> {
+        let mut view = CardView::default();
+        if view.begin_change(5).is_some() {
+            bail!("the first command to change the card was taken for a stale one");
+        }
+        view.end_change(Some(6), Some(0x200));
+        view.read_stats(0x100, 6);
+
+        if view.begin_change(6).is_some() || view.free_blocks() != Some(0x100) {
+            bail!("an unchanged card was taken as changed");
+        }
+        view.end_change(Some(7), Some(0xF0));
+        if view.seqno() != Some(7) || view.changing() {
+            bail!("this session's own change wasn't recorded");
+        }
+        if view.free_blocks() != Some(0xF0) {
+            bail!("the free blocks weren't updated after this session's own change");
+        }
+
+        // someone else wrote two generations in between
+        match view.begin_change(9) {
+            Some(Stale { was: 7, now: 9 }) => {}
+            other => bail!("a card changed by another tool gave {other:?}"),
+        }
+        if view.free_blocks().is_some() {
+            bail!("the free blocks from before the other tool's change were kept");
+        }
+        // going ahead again, the card is as last seen
+        if view.begin_change(9).is_some() {
+            bail!("the card was still stale after being read again");
+        }
+        view.end_change(Some(9), None);
+
+        // only a command that changes the card can account for a new generation
+        view.end_change(Some(12), Some(0x10));
+        if view.begin_change(12).is_none() {
+            bail!("a change made outside any command was taken for this session's own");
+        }
+
+        view.forget();
+        if view != CardView::default() {
+            bail!("forgetting the card kept {view:?}");
+        }
+        Ok(())
+    }
+}
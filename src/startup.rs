@@ -0,0 +1,327 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use bbrdb::GlobalHandle;
+use rusb::{Device, GlobalContext};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::device::{scan_sorted, DeviceLocation};
+use crate::notes::lookup;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreferredConsole {
+    Bbid(u32),
+    Serial(String),
+}
+
+impl FromStr for PreferredConsole {
+    type Err = anyhow::Error;
+
+    // "bbid:1234ABCD" or "serial:XYZ"; a bare value isn't guessed at, as a serial number can
+    // look like hex too
+    fn from_str(s: &str) -> Result<Self> {
+        let parse_bbid = |s: &str| {
+            u32::from_str_radix(s.trim_start_matches("0x"), 16)
+                .map_err(|e| anyhow!("invalid BBID '{s}': {e}"))
+        };
+        if let Some(bbid) = s.strip_prefix("bbid:") {
+            Ok(Self::Bbid(parse_bbid(bbid)?))
+        } else if let Some(serial) = s.strip_prefix("serial:").filter(|s| !s.is_empty()) {
+            Ok(Self::Serial(serial.to_string()))
+        } else {
+            Err(anyhow!(
+                "'{s}' should be 'bbid:<BBID in hex>' or 'serial:<USB serial number>'"
+            ))
+        }
+    }
+}
+
+impl Display for PreferredConsole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bbid(bbid) => write!(f, "BBID {bbid:08X}"),
+            Self::Serial(serial) => write!(f, "serial {serial}"),
+        }
+    }
+}
+
+// what we know about a connected device without (or after) opening it
+pub struct Candidate {
+    pub index: usize,
+    pub serial: Option<String>,
+    pub bbid: Option<u32>,
+}
+
+impl PreferredConsole {
+    fn matches(&self, candidate: &Candidate) -> bool {
+        match self {
+            Self::Bbid(bbid) => candidate.bbid == Some(*bbid),
+            Self::Serial(serial) => candidate.serial.as_ref() == Some(serial),
+        }
+    }
+}
+
+// which console, if any, is opened at startup without 's'; set with 'auto_open' in the config
+// file. Opening a console claims its interface, which keeps other tools off it, so by default
+// consoles are only looked for.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoOpen {
+    // say what's connected, and leave selecting to 's'
+    #[default]
+    Never,
+    // open the preferred console if it's connected
+    Preferred,
+    // open the preferred console, or else the only one connected
+    Single,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupChoice {
+    Preferred(usize),
+    Single(usize),
+    Manual,
+}
+
+pub fn choose_startup_device(
+    candidates: &[Candidate],
+    preferred: Option<&PreferredConsole>,
+    auto_open: AutoOpen,
+) -> StartupChoice {
+    if auto_open == AutoOpen::Never {
+        return StartupChoice::Manual;
+    }
+    let matching = preferred.and_then(|p| candidates.iter().find(|c| p.matches(c)));
+    match (matching, candidates) {
+        (Some(c), _) => StartupChoice::Preferred(c.index),
+        (None, [only]) if auto_open == AutoOpen::Single => StartupChoice::Single(only.index),
+        _ => StartupChoice::Manual,
+    }
+}
+
+// what to say about the consoles found when none was opened
+pub fn detected_hint(count: usize) -> Option<String> {
+    match count {
+        0 => None,
+        1 => Some("1 console found; use 's 0' then 'B' to start using it".to_string()),
+        n => Some(format!(
+            "{n} consoles found; use 'l' and 's' to select one, then 'B'"
+        )),
+    }
+}
+
+fn read_serial(device: &Device<GlobalContext>) -> Option<String> {
+    let desc = device.device_descriptor().ok()?;
+    desc.serial_number_string_index()?;
+    device
+        .open()
+        .ok()?
+        .read_serial_number_string_ascii(&desc)
+        .ok()
+}
+
+pub fn probe_bbid(device: &Device<GlobalContext>) -> Result<u32> {
+    let mut handle = GlobalHandle::new(device)?;
+    let bbid = handle.Init().and_then(|_| handle.GetBBID());
+    let _ = handle.Close();
+    bbid
+}
+
+pub fn select_at_startup(config: &Config) -> Option<(GlobalHandle, DeviceLocation)> {
+    let players = match scan_sorted() {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("{e}");
+            return None;
+        }
+    };
+
+    let preferred = match config
+        .preferred_console
+        .as_deref()
+        .map(str::parse::<PreferredConsole>)
+        .transpose()
+    {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("preferred_console: {e}");
+            None
+        }
+    };
+
+    // nothing's opened, not even to read a BBID
+    if config.auto_open == AutoOpen::Never {
+        if preferred.is_some() {
+            println!("preferred_console is set, but auto_open is 'never'; set auto_open = \"preferred\" to open it at startup");
+        }
+        if let Some(hint) = detected_hint(players.len()) {
+            println!("{hint}");
+        }
+        return None;
+    }
+
+    if matches!(preferred, Some(PreferredConsole::Bbid(_)))
+        && !config.probe_for_preferred
+        && !players.is_empty()
+    {
+        println!("preferred_console is a BBID, but probe_for_preferred is off, so consoles can't be matched against it");
+    }
+
+    let candidates = players
+        .iter()
+        .enumerate()
+        .map(|(index, device)| Candidate {
+            index,
+            serial: match preferred {
+                Some(PreferredConsole::Serial(_)) => read_serial(device),
+                _ => None,
+            },
+            bbid: match preferred {
+                Some(PreferredConsole::Bbid(_)) if config.probe_for_preferred => {
+                    match probe_bbid(device) {
+                        Ok(bbid) => Some(bbid),
+                        Err(e) => {
+                            eprintln!("Couldn't read the BBID of player {index}: {e}");
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            },
+        })
+        .collect::<Vec<_>>();
+
+    let index = match choose_startup_device(&candidates, preferred.as_ref(), config.auto_open) {
+        StartupChoice::Preferred(index) => {
+            println!(
+                "Selected player {index}, as it matches the preferred console ({})",
+                preferred.as_ref().unwrap()
+            );
+            index
+        }
+        StartupChoice::Single(index) => {
+            if let Some(preferred) = &preferred {
+                println!(
+                    "Preferred console ({preferred}) not found; selected the only connected player"
+                );
+            }
+            index
+        }
+        StartupChoice::Manual => {
+            if let Some(preferred) = preferred.as_ref().filter(|_| !players.is_empty()) {
+                println!("Preferred console ({preferred}) not found");
+            }
+            if let Some(hint) = detected_hint(players.len()) {
+                println!("{hint}");
+            }
+            return None;
+        }
+    };
+
+    let mut handle = match GlobalHandle::new(&players[index]) {
+        Ok(h) => h,
+        Err(e) => {
+            eprintln!("{e}");
+            return None;
+        }
+    };
+    if config.auto_init {
+        match handle.Init() {
+            Ok(_) => println!("Init success"),
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+    let bbid = handle
+        .initialised()
+        .unwrap_or(false)
+        .then(|| handle.GetBBID().ok())
+        .flatten();
+    if let Ok(Some(note)) = lookup(bbid, read_serial(&players[index]).as_deref()) {
+        println!("Note: {}", note.summary());
+    }
+    println!("Opened player {index} at startup; selecting another with 's' releases it");
+    Some((handle, DeviceLocation::new(index, &players[index])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    #[test]
+    fn startup() -> Result<()> {
+        use AutoOpen::*;
+        use StartupChoice::Manual;
+
+        let candidates = |count: usize| {
+            (0..count)
+                .map(|index| Candidate {
+                    index,
+                    serial: Some(format!("SN{index}")),
+                    bbid: None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let wanted = PreferredConsole::Serial("SN1".to_string());
+        let absent = PreferredConsole::Serial("SN9".to_string());
+
+        // (auto_open, consoles connected, preferred console, expected)
+        let cases = [
+            (Never, 0, None, Manual),
+            (Never, 1, None, Manual),
+            (Never, 2, None, Manual),
+            (Never, 2, Some(&wanted), Manual),
+            (Never, 1, Some(&absent), Manual),
+            (Preferred, 0, None, Manual),
+            (Preferred, 1, None, Manual),
+            (Preferred, 2, None, Manual),
+            (Preferred, 2, Some(&wanted), StartupChoice::Preferred(1)),
+            (Preferred, 1, Some(&absent), Manual),
+            (Preferred, 2, Some(&absent), Manual),
+            (Single, 0, None, Manual),
+            (Single, 1, None, StartupChoice::Single(0)),
+            (Single, 2, None, Manual),
+            (Single, 2, Some(&wanted), StartupChoice::Preferred(1)),
+            (Single, 1, Some(&absent), StartupChoice::Single(0)),
+            (Single, 2, Some(&absent), Manual),
+        ];
+        for (auto_open, count, preferred, expected) in cases {
+            let choice = choose_startup_device(&candidates(count), preferred, auto_open);
+            if choice != expected {
+                bail!("{auto_open:?} with {count} consoles and {preferred:?} preferred chose {choice:?}, not {expected:?}");
+            }
+        }
+
+        if AutoOpen::default() != Never
+            || detected_hint(0).is_some()
+            || detected_hint(1).as_deref()
+                != Some("1 console found; use 's 0' then 'B' to start using it")
+            || !detected_hint(3).is_some_and(|h| h.starts_with("3 consoles found"))
+        {
+            bail!("startup doesn't default to just saying what it found");
+        }
+
+        // a preferred console says which it is; bare values aren't guessed at
+        for (text, expected) in [
+            ("bbid:1234ABCD", Some(PreferredConsole::Bbid(0x1234ABCD))),
+            ("bbid:0x00C0FFEE", Some(PreferredConsole::Bbid(0xC0FFEE))),
+            (
+                "serial:ABC123",
+                Some(PreferredConsole::Serial("ABC123".to_string())),
+            ),
+            ("serial:", None),
+            ("ABC123", None),
+            ("1234ABCD", None),
+            ("bbid:XYZ", None),
+        ] {
+            assert_eq!(text.parse::<PreferredConsole>().ok(), expected, "{text}");
+        }
+        assert_eq!(
+            PreferredConsole::Bbid(0xC0FFEE).to_string(),
+            "BBID 00C0FFEE"
+        );
+        Ok(())
+    }
+}
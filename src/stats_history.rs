@@ -0,0 +1,235 @@
+use std::fs::{create_dir_all, read_to_string};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+use bbrdb::CardStats;
+use chrono::Local;
+
+use crate::config::config_dir;
+use crate::sink::write_atomic;
+
+const HEADER: &str = "time,free,used,bad,seqno";
+const DEFAULT_LIMIT: usize = 1000;
+// the sparkline's levels, lowest first
+const LEVELS: &[u8] = b" .:-=+*#%@";
+const GRAPH_WIDTH: usize = 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatsRecord {
+    pub time: String,
+    pub free: u32,
+    pub used: u32,
+    pub bad: u32,
+    pub seqno: u32,
+}
+
+impl StatsRecord {
+    // the sequence number changes with every write, so it doesn't count as a change in health
+    fn same_as(&self, other: &Self) -> bool {
+        (self.free, self.used, self.bad) == (other.free, other.used, other.bad)
+    }
+}
+
+// <config dir>/stats-history/<BBID>.csv
+fn history_path(bbid: u32) -> Result<PathBuf> {
+    let dir = config_dir()
+        .ok_or_else(|| anyhow!("no config directory to keep stats history in"))?
+        .join("stats-history");
+    create_dir_all(&dir)?;
+    Ok(dir.join(format!("{bbid:08X}.csv")))
+}
+
+pub fn parse_history(csv: &str) -> Result<Vec<StatsRecord>> {
+    let mut lines = csv.lines();
+    if lines.next().map(str::trim) != Some(HEADER) {
+        bail!("not a stats history file: expected a '{HEADER}' header");
+    }
+    lines
+        .filter(|l| !l.trim().is_empty())
+        .map(|line| {
+            let fields = line.split(',').collect::<Vec<_>>();
+            let [time, free, used, bad, seqno] = fields[..] else {
+                bail!("expected 5 fields in '{line}'");
+            };
+            let num = |s: &str| {
+                s.parse::<u32>()
+                    .map_err(|e| anyhow!("'{s}' in '{line}': {e}"))
+            };
+            Ok(StatsRecord {
+                time: time.to_string(),
+                free: num(free)?,
+                used: num(used)?,
+                bad: num(bad)?,
+                seqno: num(seqno)?,
+            })
+        })
+        .collect()
+}
+
+pub fn history_to_csv(records: &[StatsRecord]) -> String {
+    let mut csv = format!("{HEADER}\n");
+    for r in records {
+        csv += &format!("{},{},{},{},{}\n", r.time, r.free, r.used, r.bad, r.seqno);
+    }
+    csv
+}
+
+// adds a record unless nothing changed since the last one, dropping the oldest past `limit`;
+// returns whether it was added
+pub fn push_record(records: &mut Vec<StatsRecord>, record: StatsRecord, limit: usize) -> bool {
+    if records.last().is_some_and(|last| last.same_as(&record)) {
+        return false;
+    }
+    records.push(record);
+    let excess = records.len().saturating_sub(limit.max(1));
+    records.drain(..excess);
+    true
+}
+
+pub fn load_history(bbid: u32) -> Result<Vec<StatsRecord>> {
+    let path = history_path(bbid)?;
+    match read_to_string(&path) {
+        Ok(s) => parse_history(&s).map_err(|e| anyhow!("{}: {e}", path.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(anyhow!("{}: {e}", path.display())),
+    }
+}
+
+pub fn record_stats(bbid: u32, stats: &CardStats, limit: Option<usize>) -> Result<()> {
+    let mut records = load_history(bbid)?;
+    let record = StatsRecord {
+        time: Local::now().to_rfc3339(),
+        free: stats.free,
+        used: stats.used,
+        bad: stats.bad,
+        seqno: stats.seqno,
+    };
+    if push_record(&mut records, record, limit.unwrap_or(DEFAULT_LIMIT)) {
+        write_atomic(history_path(bbid)?, history_to_csv(&records).as_bytes())?;
+    }
+    Ok(())
+}
+
+// one character per value (averaging neighbours if there are more than `width`), scaled
+// between the smallest and largest
+pub fn sparkline(values: &[u32], width: usize) -> String {
+    if values.is_empty() || width == 0 {
+        return String::new();
+    }
+    let buckets = values
+        .chunks(values.len().div_ceil(width))
+        .map(|c| c.iter().map(|&v| v as f64).sum::<f64>() / c.len() as f64)
+        .collect::<Vec<_>>();
+    let min = buckets.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = buckets.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    buckets
+        .iter()
+        .map(|&v| {
+            let level = if max > min {
+                ((v - min) / (max - min) * (LEVELS.len() - 1) as f64).round() as usize
+            } else {
+                LEVELS.len() / 2
+            };
+            LEVELS[level] as char
+        })
+        .collect()
+}
+
+pub fn print_history(records: &[StatsRecord], graph: bool) {
+    if records.is_empty() {
+        println!("No stats history recorded for this console yet");
+        return;
+    }
+    if graph {
+        for (name, values) in [
+            ("free", records.iter().map(|r| r.free).collect::<Vec<_>>()),
+            ("used", records.iter().map(|r| r.used).collect()),
+            ("bad", records.iter().map(|r| r.bad).collect()),
+        ] {
+            let (min, max) = (
+                values.iter().min().unwrap_or(&0),
+                values.iter().max().unwrap_or(&0),
+            );
+            println!(
+                "{name:<5} [{}] {min}-{max}",
+                sparkline(&values, GRAPH_WIDTH)
+            );
+        }
+        println!(
+            "      from {} to {}",
+            records[0].time,
+            records[records.len() - 1].time
+        );
+        return;
+    }
+    println!(
+        "{:<32} {:>6} {:>6} {:>6} {:>8}",
+        "Time", "Free", "Used", "Bad", "Seqno"
+    );
+    for r in records {
+        println!(
+            "{:<32} {:>6} {:>6} {:>6} {:>8}",
+            r.time, r.free, r.used, r.bad, r.seqno
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(time: &str, free: u32, bad: u32, seqno: u32) -> StatsRecord {
+        StatsRecord {
+            time: time.to_string(),
+            free,
+            used: 0x1000 - free - bad,
+            bad,
+            seqno,
+        }
+    }
+
+    #[test]
+    fn history() -> Result<()> {
+        let mut records = vec![];
+        assert!(push_record(&mut records, record("a", 0x800, 2, 10), 3));
+        // a write alone isn't a change in health
+        assert!(!push_record(&mut records, record("b", 0x800, 2, 11), 3));
+        assert!(push_record(&mut records, record("c", 0x7F0, 2, 12), 3));
+        assert!(push_record(&mut records, record("d", 0x7F0, 3, 13), 3));
+        assert!(push_record(&mut records, record("e", 0x7E0, 3, 14), 3));
+        let times = |r: &[StatsRecord]| r.iter().map(|r| r.time.clone()).collect::<Vec<_>>();
+        assert_eq!(times(&records), ["c", "d", "e"]);
+
+        let csv = history_to_csv(&records);
+        assert!(
+            csv.starts_with("time,free,used,bad,seqno\nc,2032,2062,2,12\n"),
+            "{csv}"
+        );
+        assert_eq!(parse_history(&csv)?, records);
+        assert_eq!(parse_history(&format!("{HEADER}\n\n"))?, []);
+        for bad in [
+            "",
+            "time,free\n",
+            "time,free,used,bad,seqno\na,1,2,3\n",
+            "time,free,used,bad,seqno\na,1,2,3,-4\n",
+        ] {
+            if parse_history(bad).is_ok() {
+                bail!("{bad:?} was read as a history");
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sparklines() {
+        assert_eq!(sparkline(&[], 10), "");
+        assert_eq!(sparkline(&[1, 2], 0), "");
+        assert_eq!(sparkline(&[0, 9, 3], 10), " @-");
+        // flat is shown in the middle, not as nothing
+        assert_eq!(sparkline(&[5, 5, 5], 10), "+++");
+        // more values than fit are averaged in neighbouring pairs
+        assert_eq!(sparkline(&[0, 0, 9, 9, 4, 5], 3), " @+");
+        assert_eq!(sparkline(&(0..100).collect::<Vec<_>>(), 60).len(), 50);
+    }
+}
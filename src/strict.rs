@@ -0,0 +1,415 @@
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::danger::parse_bbid;
+use crate::hashing::{parse_sum_line, HashValue};
+use crate::nand_write::write_args;
+use crate::provenance::SOURCE_BBID_TAG;
+
+// a manifest is in the format sha256sum writes: "<hex SHA-256>  <file name>" on each line, or
+// with other algorithms (or several), BSD-style "MD5 (<file name>) = <hex>" lines; '#' lines are
+// comments, one of which may record the console the files were dumped from
+pub struct Manifest {
+    entries: Vec<(HashValue, String)>,
+    pub source_bbid: Option<u32>,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Self> {
+        let source_bbid = text
+            .lines()
+            .find_map(|l| l.trim().strip_prefix(SOURCE_BBID_TAG))
+            .map(|b| parse_bbid(b.trim()).ok_or_else(|| anyhow!("'{}' isn't a BBID", b.trim())))
+            .transpose()?;
+        let entries = text
+            .lines()
+            .filter(|l| !l.trim().is_empty() && !l.trim_start().starts_with('#'))
+            .map(parse_sum_line)
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            entries,
+            source_bbid,
+        })
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        Self::parse(&read_to_string(path)?).map_err(|e| anyhow!("{path}: {e}"))
+    }
+
+    // each file's hash, with the file's name as the manifest gives it
+    pub fn entries(&self) -> &[(HashValue, String)] {
+        &self.entries
+    }
+
+    // entries are matched by file name, so the manifest can live anywhere; a file can have a
+    // hash from each of several algorithms
+    fn hashes_of(&self, path: &str) -> Vec<&HashValue> {
+        let name = Path::new(path).file_name().and_then(|n| n.to_str());
+        self.entries
+            .iter()
+            .filter(|(_, n)| {
+                name.is_some() && Path::new(n).file_name().and_then(|n| n.to_str()) == name
+            })
+            .map(|(h, _)| h)
+            .collect()
+    }
+}
+
+// what a write was asked to do, as far as strict mode is concerned
+pub struct WriteRequest<'a> {
+    // (path, contents) of each input file
+    pub files: Vec<(&'a str, &'a [u8])>,
+    pub expected_bbid: Option<u32>,
+    pub ranges_given: bool,
+}
+
+// what strict mode needs to know of a command that writes to the card
+#[derive(Debug, PartialEq, Eq)]
+pub struct CardWrite<'a> {
+    // local files it writes from, which the manifest has to list
+    pub inputs: Vec<&'a str>,
+    // a directory it writes the files of
+    pub dir: Option<&'a str>,
+    // whether it says which blocks (or parts of the card) it writes, rather than taking them all
+    pub ranges_given: bool,
+}
+
+// a command's arguments without its flags, or the values of those in `valued`
+fn positional<'a>(command: &[&'a str], valued: &[&str]) -> Vec<&'a str> {
+    let mut args = vec![];
+    let mut iter = command.iter().copied();
+    while let Some(arg) = iter.next() {
+        if valued.contains(&arg) {
+            iter.next();
+        } else if !arg.starts_with("--") {
+            args.push(arg);
+        }
+    }
+    args
+}
+
+// the flags strict mode adds to every command that writes
+pub const STRICT_FLAGS: [&str; 2] = ["--manifest", "--bbid"];
+
+// every command that writes to the console's card, and what strict mode checks of it; None for
+// those that don't. A transaction's uploads aren't in 'txn commit''s arguments, and a kit's files
+// are checked against the kit's own manifest
+pub fn card_write<'a>(command: &[&'a str]) -> Option<CardWrite<'a>> {
+    let targeted = |inputs| CardWrite {
+        inputs,
+        dir: None,
+        ranges_given: true,
+    };
+    let args = positional(command, &STRICT_FLAGS);
+    let subcommand = args.get(1).copied();
+    match command[0] {
+        "2" => {
+            let args = positional(command, &["--manifest", "--bbid", "--report", "--restore"]);
+            let (nand, spare, ranges) = write_args(&args[1..], command.contains(&"--no-spare"))?;
+            let mut inputs = vec![nand];
+            if spare != "-" {
+                inputs.push(spare);
+            }
+            Some(CardWrite {
+                inputs,
+                dir: None,
+                ranges_given: ranges.is_some(),
+            })
+        }
+        "Y" => Some(targeted(args.iter().skip(2).take(2).copied().collect())),
+        "4" => Some(targeted(args.iter().skip(1).take(1).copied().collect())),
+        "patch" if command.get(1) == Some(&"--local") => None,
+        "patch" => {
+            // the patch is the last argument, after a file or '--blocks range'
+            let args = positional(command, &["--manifest", "--bbid", "--blocks"]);
+            Some(targeted(
+                args.iter().skip(1).last().copied().into_iter().collect(),
+            ))
+        }
+        "6" | "7" | "relocate" | "clean" => Some(targeted(vec![])),
+        "dupes" if command.contains(&"--interactive") => Some(targeted(vec![])),
+        "txn" if subcommand == Some("commit") => Some(targeted(vec![])),
+        "ticket" if subcommand == Some("restore") => Some(targeted(vec![])),
+        "provision" if subcommand == Some("apply") && !command.contains(&"--dry-run") => {
+            Some(CardWrite {
+                inputs: vec![],
+                dir: args.get(2).copied(),
+                ranges_given: true,
+            })
+        }
+        "kit" if subcommand == Some("restore") => Some(CardWrite {
+            inputs: vec![],
+            dir: None,
+            ranges_given: args.len() > 3,
+        }),
+        "badblocks" if command.contains(&"--test") => Some(CardWrite {
+            inputs: vec![],
+            dir: None,
+            ranges_given: args.len() > 2,
+        }),
+        _ => None,
+    }
+}
+
+// the gate every command goes through: with `strict` on, one that writes to the card has to meet
+// the preconditions, given its input files' contents in `files` and '--manifest''s in `manifest`
+pub fn check_command(
+    strict: bool,
+    command: &[&str],
+    files: Vec<(&str, &[u8])>,
+    manifest: Option<&Manifest>,
+    console_bbid: Option<u32>,
+) -> Result<()> {
+    let Some(write) = card_write(command).filter(|_| strict) else {
+        return Ok(());
+    };
+    let expected_bbid = match command.iter().position(|a| *a == "--bbid") {
+        Some(i) => {
+            let bbid = command.get(i + 1).copied().unwrap_or_default();
+            Some(parse_bbid(bbid).ok_or_else(|| anyhow!("'{bbid}' isn't a valid BBID"))?)
+        }
+        None => None,
+    };
+    let request = WriteRequest {
+        files,
+        expected_bbid,
+        ranges_given: write.ranges_given,
+    };
+    check_strict(&request, manifest, console_bbid)
+}
+
+// the preconditions for a write with 'set strict-writes on', checked in order; the error says
+// exactly which one failed
+pub fn check_strict(
+    request: &WriteRequest,
+    manifest: Option<&Manifest>,
+    console_bbid: Option<u32>,
+) -> Result<()> {
+    // a write from nothing local (a delete, say) has nothing to check against one
+    let files = match manifest {
+        Some(manifest) => request.files.iter().map(|f| (manifest, f)).collect(),
+        None if request.files.is_empty() => vec![],
+        None => bail!("strict-writes: no manifest was given; add '--manifest <file>'"),
+    };
+    for (manifest, (path, data)) in files {
+        let expected = manifest.hashes_of(path);
+        if expected.is_empty() {
+            bail!("strict-writes: {path} isn't listed in the manifest");
+        }
+        // each hash is checked against the file hashed the same way
+        for expected in expected {
+            let actual = expected.algo.digest(data);
+            if actual != *expected {
+                bail!(
+                    "strict-writes: {path} has {} {}, but the manifest says {}",
+                    expected.algo.label(),
+                    actual.hex(),
+                    expected.hex()
+                );
+            }
+        }
+    }
+
+    let Some(expected) = request.expected_bbid else {
+        bail!("strict-writes: no console BBID was given; add '--bbid <BBID>'");
+    };
+    match console_bbid {
+        None => bail!("strict-writes: the console's BBID couldn't be read to check it"),
+        Some(bbid) if bbid != expected => {
+            bail!("strict-writes: the console's BBID is {bbid:08X}, not {expected:08X} as given")
+        }
+        Some(_) => {}
+    }
+
+    if !request.ranges_given {
+        bail!(
+            "strict-writes: no block ranges (or kit items) were given; the whole card can't be written by default"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::HashAlgo;
+
+    const BBID: u32 = 0x1234ABCD;
+
+    // every command that writes, in each mode, with every precondition met and then with each
+    // broken in turn: strict mode refuses saying which, and with it off they all go ahead
+    #[test]
+    fn matrix() -> Result<()> {
+        let data = |name: &str| name.bytes().cycle().take(0x100).collect::<Vec<u8>>();
+        let names = [
+            "nand.bin",
+            "spare.bin",
+            "GAME.app",
+            "fix.ips",
+            "dir/NEW.app",
+        ];
+        let sums = names
+            .iter()
+            .map(|n| format!("{}  {n}\n", HashAlgo::Sha256.digest(&data(n)).hex()))
+            .collect::<String>();
+        let manifest = Manifest::parse(&sums)?;
+        let empty = Manifest::parse("# nothing listed\n")?;
+
+        // each command, and the files it should be found to write from
+        let writes: [(&str, &[&str]); 19] = [
+            ("2 nand.bin spare.bin 0-0x40", &["nand.bin", "spare.bin"]),
+            ("2 0-0x40", &["nand.bin", "spare.bin"]),
+            ("2 nand.bin --no-spare 0x40", &["nand.bin"]),
+            ("2 nand.bin - 0x40 --report r.json --verify", &["nand.bin"]),
+            ("Y 0x40 nand.bin spare.bin", &["nand.bin", "spare.bin"]),
+            ("4 GAME.app", &["GAME.app"]),
+            ("4 --blocks --continue GAME.app", &["GAME.app"]),
+            ("patch GAME.app fix.ips", &["fix.ips"]),
+            ("patch --blocks 0x100-0x120 fix.ips", &["fix.ips"]),
+            ("6 GAME.app", &[]),
+            ("7 GAME.app OLD.app", &[]),
+            ("relocate 0x100 0x101", &[]),
+            ("clean", &[]),
+            ("dupes --interactive", &[]),
+            ("txn commit", &[]),
+            ("ticket restore 20240501-100000-000", &[]),
+            ("provision apply dir", &[]),
+            ("kit restore kit sksa fs", &[]),
+            ("badblocks audit 0x100-0x110 --test", &[]),
+        ];
+        for (line, inputs) in writes {
+            let mut command = line.split(' ').collect::<Vec<_>>();
+            command.extend(["--manifest", "m.sha256", "--bbid", "1234abcd"]);
+            let write = card_write(&command).ok_or_else(|| anyhow!("'{line}' isn't a write"))?;
+            if write.inputs != inputs {
+                bail!("'{line}' writes from {:?}, not {inputs:?}", write.inputs);
+            }
+            let contents = inputs.iter().map(|n| data(n)).collect::<Vec<_>>();
+            let files = || {
+                inputs
+                    .iter()
+                    .zip(&contents)
+                    .map(|(n, d)| (*n, &d[..]))
+                    .collect::<Vec<_>>()
+            };
+            let without = |flag: &str| {
+                let i = command.iter().position(|a| *a == flag).unwrap_or_default();
+                [&command[..i], &command[i + 2..]].concat()
+            };
+
+            // all met, or strict mode off
+            let check = |strict, command: &[&str], files, manifest, bbid| {
+                check_command(strict, command, files, manifest, bbid)
+            };
+            check(true, &command, files(), Some(&manifest), Some(BBID))?;
+            let mut changed = files();
+            let flipped = contents
+                .first()
+                .map(|d| d.iter().map(|b| !b).collect::<Vec<_>>());
+            if let Some(flipped) = &flipped {
+                changed[0].1 = flipped;
+            }
+            let broken = [
+                (
+                    without("--manifest"),
+                    files(),
+                    None,
+                    Some(BBID),
+                    "no manifest was given",
+                ),
+                (
+                    command.clone(),
+                    changed,
+                    Some(&manifest),
+                    Some(BBID),
+                    "but the manifest says",
+                ),
+                (
+                    command.clone(),
+                    files(),
+                    Some(&empty),
+                    Some(BBID),
+                    "isn't listed in the manifest",
+                ),
+                (
+                    without("--bbid"),
+                    files(),
+                    Some(&manifest),
+                    Some(BBID),
+                    "no console BBID was given",
+                ),
+                (
+                    command.clone(),
+                    files(),
+                    Some(&manifest),
+                    Some(1),
+                    "BBID is 00000001, not 1234ABCD",
+                ),
+                (
+                    command.clone(),
+                    files(),
+                    Some(&manifest),
+                    None,
+                    "BBID couldn't be read",
+                ),
+            ];
+            for (command, files, manifest, bbid, expected) in broken {
+                check(false, &command, files.clone(), manifest, bbid)?;
+                // a write from no files has no manifest to meet
+                let applies = !inputs.is_empty() || !expected.contains("manifest");
+                match check(true, &command, files, manifest, bbid) {
+                    Err(e) if applies && e.to_string().contains(expected) => {}
+                    Ok(()) if !applies => {}
+                    other => bail!("'{line}' where {expected:?} gave {other:?}"),
+                }
+            }
+        }
+
+        // the whole card, or everything in a kit, has to be asked for by ranges
+        for line in [
+            "2 nand.bin spare.bin",
+            "2",
+            "kit restore kit",
+            "badblocks audit --test",
+        ] {
+            let mut command = line.split(' ').collect::<Vec<_>>();
+            command.extend(["--manifest", "m.sha256", "--bbid", "1234ABCD"]);
+            let files = card_write(&command)
+                .ok_or_else(|| anyhow!("'{line}' isn't a write"))?
+                .inputs
+                .iter()
+                .map(|n| (*n, data(n)))
+                .collect::<Vec<_>>();
+            let files = files.iter().map(|(n, d)| (*n, &d[..])).collect::<Vec<_>>();
+            check_command(false, &command, files.clone(), Some(&manifest), Some(BBID))?;
+            match check_command(true, &command, files, Some(&manifest), Some(BBID)) {
+                Err(e) if e.to_string().contains("no block ranges") => {}
+                other => bail!("'{line}' gave {other:?}"),
+            }
+        }
+
+        // the rest aren't writes, so aren't held up in either mode
+        for line in [
+            "1 nand.bin spare.bin --manifest",
+            "3 GAME.app",
+            "L",
+            "patch --local GAME.app fix.ips out.app",
+            "txn begin",
+            "provision apply dir --dry-run",
+            "provision check dir",
+            "kit build kit",
+            "badblocks audit 0x100-0x110",
+            "dupes",
+            "ticket backups",
+        ] {
+            let command = line.split(' ').collect::<Vec<_>>();
+            if card_write(&command).is_some() {
+                bail!("'{line}' was taken for a write");
+            }
+            check_command(true, &command, vec![], None, None)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+use std::fmt::Write;
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::ranges::format_range;
+
+// per-range results of a block-by-block write or verification
+pub struct RangeOutcome {
+    pub range: Range<u16>,
+    // blocks written or checked
+    pub done: usize,
+    pub elapsed: Duration,
+    // blocks whose read-back didn't match, if verification was requested
+    pub mismatches: Option<Vec<u16>>,
+    // SHA-256 of the range's data in the file and as read back from the console, if it was read back
+    pub hashes: Option<(String, String)>,
+}
+
+#[derive(Default)]
+pub struct RangeSummary {
+    pub outcomes: Vec<RangeOutcome>,
+}
+
+impl RangeSummary {
+    pub fn total_blocks(&self) -> usize {
+        self.outcomes.iter().map(|o| o.done).sum()
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.outcomes.iter().map(|o| o.elapsed).sum()
+    }
+
+    pub fn total_mismatches(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter_map(|o| o.mismatches.as_ref())
+            .map(Vec::len)
+            .sum()
+    }
+
+    // where a block-by-block operation stopped, e.g. "while writing block 0x3F2 of range
+    // 0x300-0x400, after 242 successful blocks"; None if every range finished
+    pub fn failure_context(&self, verb: &str) -> Option<String> {
+        let last = self.outcomes.last()?;
+        if last.done >= last.range.len() {
+            return None;
+        }
+        Some(format!(
+            "while {verb} block {:#X} of range {}, after {} successful blocks",
+            last.range.start as usize + last.done,
+            format_range(&last.range),
+            self.total_blocks()
+        ))
+    }
+
+    pub fn print(&self) {
+        print!("{}", self.render());
+    }
+
+    // the table 'print' prints
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{:<16} {:>7} {:>9}  Verify", "Range", "Blocks", "Time");
+        for o in &self.outcomes {
+            let verify = match &o.mismatches {
+                None => "-".to_string(),
+                Some(m) if m.is_empty() => "ok".to_string(),
+                Some(m) => format!(
+                    "{} mismatched: {}",
+                    m.len(),
+                    m.iter()
+                        .map(|b| format!("{b:#X}"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            };
+            let partial = if o.done < o.range.len() {
+                format!(" (of {})", o.range.len())
+            } else {
+                String::new()
+            };
+            let _ = writeln!(
+                out,
+                "{:<16} {:>7} {:>8.1}s  {verify}{partial}",
+                format_range(&o.range),
+                o.done,
+                o.elapsed.as_secs_f64()
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{:<16} {:>7} {:>8.1}s  {}",
+            "Total",
+            self.total_blocks(),
+            self.total_time().as_secs_f64(),
+            if self.outcomes.iter().any(|o| o.mismatches.is_some()) {
+                format!("{} mismatched", self.total_mismatches())
+            } else {
+                "-".to_string()
+            }
+        );
+        out
+    }
+}
@@ -0,0 +1,316 @@
+use anyhow::Result;
+use bbrdb::GlobalHandle;
+use serde::Serialize;
+
+use crate::cancel::CancelToken;
+use crate::device::{scan_sorted, DeviceInfo, DeviceLocation};
+use crate::fs::SKSA_BLOCKS;
+use crate::hashing::{HashAlgo, HashValue};
+use crate::instance_lock::DeviceLock;
+use crate::player::{Backend, Player};
+
+// 'survey': every connected console's BBID and card health in one go, for keeping an inventory.
+// Each console is claimed and opened only for as long as it takes to read it, except the one this
+// session has selected, which is read through its handle as it is. A console that can't be read
+// still gets a row, saying why.
+
+const HEADER: &str = "player,serial,bbid,free,used,bad,seqno,sksa_sha256,error";
+
+// what's read from a console
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reading {
+    pub bbid: u32,
+    pub free: u32,
+    pub used: u32,
+    pub bad: u32,
+    pub seqno: u32,
+    pub sksa: HashValue,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SurveyRow {
+    pub player: usize,
+    pub serial: Option<String>,
+    pub bbid: Option<String>,
+    pub free: Option<u32>,
+    pub used: Option<u32>,
+    pub bad: Option<u32>,
+    pub seqno: Option<u32>,
+    pub sksa_sha256: Option<String>,
+    pub error: Option<String>,
+}
+
+// the BBID, the card's stats, and the SHA-256 of the whole SKSA
+pub fn read_console(player: &dyn Player, cancel: &CancelToken) -> Result<Reading> {
+    let bbid = player.GetBBID()?;
+    let stats = player.CardStats()?;
+    let mut hasher = HashAlgo::Sha256.hasher();
+    for blk in 0..SKSA_BLOCKS {
+        cancel.check()?;
+        hasher.update(&player.ReadSingleBlock(blk as u32)?.0);
+    }
+    Ok(Reading {
+        bbid,
+        free: stats.free,
+        used: stats.used,
+        bad: stats.bad,
+        seqno: stats.seqno,
+        sksa: hasher.finalize(),
+    })
+}
+
+pub fn row(player: usize, serial: Option<String>, reading: Result<Reading>) -> SurveyRow {
+    match reading {
+        Ok(r) => SurveyRow {
+            player,
+            serial,
+            bbid: Some(format!("{:08X}", r.bbid)),
+            free: Some(r.free),
+            used: Some(r.used),
+            bad: Some(r.bad),
+            seqno: Some(r.seqno),
+            sksa_sha256: Some(r.sksa.hex()),
+            error: None,
+        },
+        Err(e) => SurveyRow {
+            player,
+            serial,
+            error: Some(format!("{e:#}")),
+            ..SurveyRow::default()
+        },
+    }
+}
+
+// a field as RFC 4180 has it: quoted if it holds a comma, quote or line break, with its
+// quotes doubled
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn fields(row: &SurveyRow) -> [String; 9] {
+    let num = |n: Option<u32>| n.map(|n| n.to_string()).unwrap_or_default();
+    [
+        row.player.to_string(),
+        row.serial.clone().unwrap_or_default(),
+        row.bbid.clone().unwrap_or_default(),
+        num(row.free),
+        num(row.used),
+        num(row.bad),
+        num(row.seqno),
+        row.sksa_sha256.clone().unwrap_or_default(),
+        row.error.clone().unwrap_or_default(),
+    ]
+}
+
+pub fn survey_to_csv(rows: &[SurveyRow]) -> String {
+    let mut csv = format!("{HEADER}\n");
+    for row in rows {
+        let line = fields(row).iter().map(|f| csv_field(f)).collect::<Vec<_>>();
+        csv += &line.join(",");
+        csv += "\n";
+    }
+    csv
+}
+
+// one line per console, with the SKSA's hash shortened; errors go at the end of the line, so
+// they can be as long as they need to be
+pub fn render_table(rows: &[SurveyRow]) -> String {
+    let mut lines = vec![[
+        "Player", "Serial", "BBID", "Free", "Used", "Bad", "FS", "SKSA", "Error",
+    ]
+    .map(str::to_string)];
+    for row in rows {
+        let mut f = fields(row);
+        f[7].truncate(16);
+        f[8] = f[8].replace(['\n', '\r'], " ");
+        lines.push(f);
+    }
+    let widths = (0..8)
+        .map(|i| {
+            lines
+                .iter()
+                .map(|l| l[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+    let mut out = String::new();
+    for line in &lines {
+        let mut text = String::new();
+        for (field, width) in line.iter().zip(&widths) {
+            text += &format!("{field:<width$}  ");
+        }
+        text += &line[8];
+        out += text.trim_end();
+        out += "\n";
+    }
+    out
+}
+
+// surveys every connected device; `current` is this session's console, which is read through its
+// own handle (initialised first if it isn't yet)
+pub fn survey(
+    mut current: Option<(&DeviceLocation, &mut Box<dyn Backend>)>,
+    cancel: &CancelToken,
+) -> Result<Vec<SurveyRow>> {
+    let mut rows = vec![];
+    for (index, device) in scan_sorted()?.iter().enumerate() {
+        if cancel.is_cancelled() {
+            break;
+        }
+        let serial = DeviceInfo::query(device).ok().and_then(|i| i.serial);
+        let reading = match &mut current {
+            Some((selected, handle)) if selected.matches(device) => {
+                let ready = match handle.initialised() {
+                    Ok(true) => Ok(()),
+                    _ => handle.Init(),
+                };
+                ready.and_then(|_| read_console(&**handle, cancel))
+            }
+            _ => read_unclaimed(&DeviceLocation::new(index, device), device, cancel),
+        };
+        rows.push(row(index, serial, reading));
+    }
+    Ok(rows)
+}
+
+// claims a console no one has selected, opens it, reads it, and lets it go again
+fn read_unclaimed(
+    location: &DeviceLocation,
+    device: &rusb::Device<rusb::GlobalContext>,
+    cancel: &CancelToken,
+) -> Result<Reading> {
+    let _lock = DeviceLock::acquire(&location.lock_key())?;
+    let mut handle = GlobalHandle::new(device)?;
+    let reading = handle.Init().and_then(|_| read_console(&handle, cancel));
+    let _ = handle.Close();
+    reading
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn survey() -> Result<()> {
+        use anyhow::{anyhow, bail};
+        use bbrdb::CardStats;
+
+        use crate::fs::BLOCK_SIZE;
+
+        struct Console;
+
+        impl Player for Console {
+            fn GetBBID(&self) -> Result<u32> {
+                Ok(0x1234ABCD)
+            }
+            fn SetLED(&self, _value: u32) -> Result<()> {
+                Ok(())
+            }
+            fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+                bail!("not used")
+            }
+            fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+                bail!("not used")
+            }
+            fn ReadFile(&self, _name: &str) -> Result<Option<Vec<u8>>> {
+                bail!("not used")
+            }
+            fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+                if blk >= SKSA_BLOCKS as u32 {
+                    bail!("read block {blk:#X}, past the SKSA");
+                }
+                Ok((vec![blk as u8; BLOCK_SIZE], vec![0xFF; 0x10]))
+            }
+            fn CardStats(&self) -> Result<CardStats> {
+                Ok(CardStats {
+                    free: 3000,
+                    used: 1000,
+                    bad: 12,
+                    seqno: 77,
+                })
+            }
+        }
+
+        // the SKSA's hash is of its blocks in order
+        let reading = read_console(&Console, &CancelToken::default())?;
+        let sksa = (0..SKSA_BLOCKS)
+            .flat_map(|b| vec![b as u8; BLOCK_SIZE])
+            .collect::<Vec<_>>();
+        if reading.sksa != HashAlgo::Sha256.digest(&sksa)
+            || (reading.free, reading.bad) != (3000, 12)
+        {
+            bail!("the console was read as {reading:?}");
+        }
+        let cancelled = CancelToken::default();
+        cancelled.cancel();
+        if read_console(&Console, &cancelled).is_ok() {
+            bail!("a cancelled survey carried on reading");
+        }
+
+        // a console that couldn't be read still gets a row, with only what's known about it
+        let rows = vec![
+            row(0, Some("BB001".to_string()), Ok(reading.clone())),
+            row(
+                1,
+                None,
+                Err(anyhow!("Init failed").context("player 1, \"the old one\"")),
+            ),
+        ];
+        if rows[0].bbid.as_deref() != Some("1234ABCD")
+            || rows[0].sksa_sha256 != Some(reading.sksa.hex())
+            || rows[0].error.is_some()
+        {
+            bail!("a console that was read gave {:?}", rows[0]);
+        }
+        if rows[1]
+            != (SurveyRow {
+                player: 1,
+                error: Some("player 1, \"the old one\": Init failed".to_string()),
+                ..SurveyRow::default()
+            })
+        {
+            bail!("a console that couldn't be read gave {:?}", rows[1]);
+        }
+
+        // CSV fields with commas, quotes or line breaks in them are quoted
+        for (field, expected) in [
+            ("plain", "plain"),
+            ("", ""),
+            ("a,b", "\"a,b\""),
+            ("say \"hi\"", "\"say \"\"hi\"\"\""),
+            ("two\nlines", "\"two\nlines\""),
+            ("cr\r", "\"cr\r\""),
+        ] {
+            if csv_field(field) != expected {
+                bail!("'{field}' went into the CSV as {}", csv_field(field));
+            }
+        }
+        let csv = survey_to_csv(&rows);
+        let expected = format!(
+            "{HEADER}\n0,BB001,1234ABCD,3000,1000,12,77,{},\n1,,,,,,,,\"player 1, \"\"the old one\"\": Init failed\"\n",
+            reading.sksa.hex()
+        );
+        if csv != expected {
+            bail!("the survey's CSV was\n{csv}");
+        }
+
+        // the table lines up, with errors last and the hash shortened
+        let table = render_table(&rows);
+        let lines = table.lines().collect::<Vec<_>>();
+        if lines.len() != 3
+            || !lines[0].starts_with("Player  Serial  BBID      Free  Used  Bad  FS  SKSA")
+            || !lines[1].contains(&reading.sksa.hex()[..16])
+            || lines[1].contains(&reading.sksa.hex()[..17])
+            || !lines[2].ends_with("player 1, \"the old one\": Init failed")
+            || lines[1].find("3000") != lines[0].find("Free")
+        {
+            bail!("the survey's table was\n{table}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use std::fs::{create_dir_all, remove_dir_all};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// a directory of a test's own, removed when it's dropped, whether the test passed, failed or
+// panicked; tests running at the same time never share one
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    pub fn new(name: &str) -> std::io::Result<Self> {
+        static MADE: AtomicUsize = AtomicUsize::new(0);
+        let n = MADE.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("aulon2-{name}-{}-{n}", process::id()));
+        // left by a killed run that had the same pid
+        let _ = remove_dir_all(&path);
+        create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_to_string, write};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::call_trace;
+use crate::config::config_dir;
+use crate::jobs::say;
+use crate::player::Player;
+
+const STATS_FILE: &str = "throughput.toml";
+
+// used when there's no history at all; deliberately on the slow side
+const DEFAULT_BYTES_PER_SEC: f64 = 128.0 * 1024.0;
+
+// how much a new transfer counts towards the stored average
+const WEIGHT: f64 = 0.3;
+
+// transfers shorter than this are mostly setup overhead, so they'd skew the average
+const MIN_SAMPLE: Duration = Duration::from_secs(2);
+
+// clamp samples so that one bogus measurement can't wreck later estimates
+const MIN_BYTES_PER_SEC: f64 = 1024.0;
+const MAX_BYTES_PER_SEC: f64 = 64.0 * 1024.0 * 1024.0;
+
+// average transfer speeds in bytes per second, per console (by BBID) and over all consoles
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ThroughputStats {
+    pub global: Option<f64>,
+    pub consoles: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EstimateBasis {
+    Console,
+    Global,
+    Default,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    pub time: Duration,
+    pub basis: EstimateBasis,
+}
+
+fn console_key(bbid: u32) -> String {
+    format!("{bbid:08X}")
+}
+
+fn update(average: Option<f64>, sample: f64) -> f64 {
+    match average {
+        Some(a) => a + WEIGHT * (sample - a),
+        None => sample,
+    }
+}
+
+impl ThroughputStats {
+    pub fn estimate(&self, bbid: Option<u32>, bytes: u64) -> Estimate {
+        let console = bbid.and_then(|b| self.consoles.get(&console_key(b)));
+        let (rate, basis) = match (console, self.global) {
+            (Some(&r), _) => (r, EstimateBasis::Console),
+            (None, Some(r)) => (r, EstimateBasis::Global),
+            (None, None) => (DEFAULT_BYTES_PER_SEC, EstimateBasis::Default),
+        };
+        Estimate {
+            time: Duration::from_secs_f64(bytes as f64 / rate.max(MIN_BYTES_PER_SEC)),
+            basis,
+        }
+    }
+
+    // returns whether the transfer was long enough to be used
+    pub fn record(&mut self, bbid: Option<u32>, bytes: u64, elapsed: Duration) -> bool {
+        if elapsed < MIN_SAMPLE || bytes == 0 {
+            return false;
+        }
+        let sample =
+            (bytes as f64 / elapsed.as_secs_f64()).clamp(MIN_BYTES_PER_SEC, MAX_BYTES_PER_SEC);
+        self.global = Some(update(self.global, sample));
+        if let Some(bbid) = bbid {
+            let key = console_key(bbid);
+            let average = update(self.consoles.get(&key).copied(), sample);
+            self.consoles.insert(key, average);
+        }
+        true
+    }
+
+    // a missing or unreadable file just means no history
+    pub fn load() -> Self {
+        config_dir()
+            .and_then(|d| read_to_string(d.join(STATS_FILE)).ok())
+            .and_then(|s| toml::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let dir = config_dir().ok_or_else(|| anyhow!("no config directory"))?;
+        create_dir_all(&dir)?;
+        write(dir.join(STATS_FILE), toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Estimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = self.time.as_secs();
+        if secs < 60 {
+            write!(f, "~{secs} s")?;
+        } else {
+            write!(f, "~{} min", secs.div_ceil(60))?;
+        }
+        match self.basis {
+            EstimateBasis::Console => write!(f, " based on previous transfers with this console"),
+            EstimateBasis::Global => write!(f, " based on previous transfers with other consoles"),
+            EstimateBasis::Default => write!(f, " (no previous transfers to go by)"),
+        }
+    }
+}
+
+// times one transfer: prints an estimate up front and updates the statistics on completion
+pub struct TransferTimer {
+    bbid: Option<u32>,
+    bytes: u64,
+    started: Instant,
+}
+
+impl TransferTimer {
+    pub fn begin(player: &dyn Player, what: &str, bytes: u64) -> Self {
+        let bbid = player.GetBBID().ok();
+        say(format!(
+            "{what}: {}",
+            ThroughputStats::load().estimate(bbid, bytes)
+        ));
+        Self {
+            bbid,
+            bytes,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn complete(self) {
+        // a traced dry run's transfers were never made, so say nothing about the link
+        if call_trace::active() {
+            return;
+        }
+        let mut stats = ThroughputStats::load();
+        if stats.record(self.bbid, self.bytes, self.started.elapsed()) {
+            if let Err(e) = stats.save() {
+                eprintln!("Couldn't save throughput statistics: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+
+    #[test]
+    fn averages() -> Result<()> {
+        let mut stats = ThroughputStats::default();
+        let secs = Duration::from_secs;
+
+        // short or empty transfers aren't counted
+        if stats.record(Some(0x1234), 0x100000, secs(1)) || stats.record(None, 0, secs(10)) {
+            bail!("a transfer too short to go by was recorded");
+        }
+        assert_eq!(stats, ThroughputStats::default());
+
+        // the first sample is taken as is, for the console and overall
+        assert!(stats.record(Some(0x1234), 0x100000, secs(4)));
+        assert_eq!(stats.global, Some(0x40000 as f64));
+        assert_eq!(stats.consoles["00001234"], 0x40000 as f64);
+
+        // later ones move the average part of the way towards them
+        assert!(stats.record(None, 0x200000, secs(4)));
+        assert_eq!(stats.global, Some(0x40000 as f64 + WEIGHT * 0x40000 as f64));
+        assert_eq!(stats.consoles["00001234"], 0x40000 as f64);
+
+        // and a bogus one is clamped
+        let mut clamped = ThroughputStats::default();
+        clamped.record(Some(1), 1, secs(1000));
+        clamped.record(Some(2), u64::MAX, secs(2));
+        assert_eq!(clamped.consoles["00000001"], MIN_BYTES_PER_SEC);
+        assert_eq!(clamped.consoles["00000002"], MAX_BYTES_PER_SEC);
+
+        let stored: ThroughputStats = toml::from_str(&toml::to_string(&stats)?)?;
+        assert_eq!(stored, stats);
+        Ok(())
+    }
+
+    #[test]
+    fn estimates() -> Result<()> {
+        let mut stats = ThroughputStats::default();
+        let estimate = stats.estimate(Some(0x1234), 0x100000);
+        assert_eq!(estimate.basis, EstimateBasis::Default);
+        assert_eq!(estimate.time, Duration::from_secs(8));
+        assert_eq!(
+            estimate.to_string(),
+            "~8 s (no previous transfers to go by)"
+        );
+
+        // another console's history is better than none, and this console's better still
+        stats.record(Some(0x5678), 0x1000000, Duration::from_secs(32));
+        let estimate = stats.estimate(Some(0x1234), 0x4000000);
+        assert_eq!(estimate.basis, EstimateBasis::Global);
+        assert_eq!(
+            estimate.to_string(),
+            "~3 min based on previous transfers with other consoles"
+        );
+        stats.record(Some(0x1234), 0x1000000, Duration::from_secs(8));
+        let estimate = stats.estimate(Some(0x1234), 0x1000000);
+        assert_eq!(
+            (estimate.basis, estimate.time),
+            (EstimateBasis::Console, Duration::from_secs(8))
+        );
+        assert_eq!(stats.estimate(None, 0).basis, EstimateBasis::Global);
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt::{self, Display};
+
+use anyhow::{bail, Result};
+
+pub const TICKET_FILE: &str = "ticket.sys";
+
+pub const TICKET_SIZE: usize = 0x2B4C;
+// offsets of the content metadata head and the ticket head within a ticket
+const CMD_HEAD: usize = 0x2800;
+const TICKET_HEAD: usize = 0x29AC;
+
+pub const CMD_SIZE: usize = CMD_HEAD + 0x0C;
+pub const CMD_CONTENT_ID: usize = CMD_HEAD + 0x98;
+pub const TICKET_BBID: usize = TICKET_HEAD;
+const TICKET_TID: usize = TICKET_HEAD + 0x04;
+const TICKET_CODE: usize = TICKET_HEAD + 0x06;
+const TICKET_LIMIT: usize = TICKET_HEAD + 0x08;
+
+// tickets with the top bit of their ticket ID set are limited-play
+const TID_LIMITED: u16 = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TicketKind {
+    Permanent,
+    Limited { code: u16, limit: u16 },
+}
+
+impl Display for TicketKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Permanent => write!(f, "permanent"),
+            Self::Limited { code, limit } => write!(f, "limited (code {code}, limit {limit})"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ticket {
+    pub content_id: u32,
+    pub content_size: u32,
+    pub bbid: u32,
+    pub kind: TicketKind,
+}
+
+impl Ticket {
+    fn parse(data: &[u8]) -> Self {
+        let u16_at = |o: usize| u16::from_be_bytes([data[o], data[o + 1]]);
+        let u32_at =
+            |o: usize| u32::from_be_bytes([data[o], data[o + 1], data[o + 2], data[o + 3]]);
+
+        let tid = u16_at(TICKET_TID);
+        Self {
+            content_id: u32_at(CMD_CONTENT_ID),
+            content_size: u32_at(CMD_SIZE),
+            bbid: u32_at(TICKET_BBID),
+            kind: if tid & TID_LIMITED != 0 {
+                TicketKind::Limited {
+                    code: u16_at(TICKET_CODE),
+                    limit: u16_at(TICKET_LIMIT),
+                }
+            } else {
+                TicketKind::Permanent
+            },
+        }
+    }
+
+    pub fn app_name(&self) -> String {
+        format!("{:08x}.app", self.content_id)
+    }
+}
+
+pub struct TicketFile {
+    pub tickets: Vec<Ticket>,
+    // set when the file claims more tickets than it actually contains
+    pub truncated: Option<usize>,
+}
+
+pub fn parse_tickets(data: &[u8]) -> Result<TicketFile> {
+    if data.len() < 4 {
+        bail!("{TICKET_FILE} is too short to hold a ticket count");
+    }
+    let count = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let tickets = data[4..]
+        .chunks_exact(TICKET_SIZE)
+        .take(count)
+        .map(Ticket::parse)
+        .collect::<Vec<_>>();
+
+    Ok(TicketFile {
+        truncated: (tickets.len() < count).then_some(count),
+        tickets,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticket(content_id: u32, tid: u16) -> Vec<u8> {
+        let mut data = vec![0; TICKET_SIZE];
+        data[CMD_CONTENT_ID..CMD_CONTENT_ID + 4].copy_from_slice(&content_id.to_be_bytes());
+        data[CMD_SIZE..CMD_SIZE + 4].copy_from_slice(&0x10000u32.to_be_bytes());
+        data[TICKET_BBID..TICKET_BBID + 4].copy_from_slice(&0x1234ABCDu32.to_be_bytes());
+        data[TICKET_TID..TICKET_TID + 2].copy_from_slice(&tid.to_be_bytes());
+        data[TICKET_CODE..TICKET_CODE + 2].copy_from_slice(&3u16.to_be_bytes());
+        data[TICKET_LIMIT..TICKET_LIMIT + 2].copy_from_slice(&10u16.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn parsing() -> Result<()> {
+        let mut file = 2u32.to_be_bytes().to_vec();
+        file.extend(ticket(0x0012D687, 0x0001));
+        file.extend(ticket(0x00ABCDEF, TID_LIMITED | 0x0002));
+        let parsed = parse_tickets(&file)?;
+        assert_eq!(parsed.truncated, None);
+        assert_eq!(
+            parsed.tickets,
+            [
+                Ticket {
+                    content_id: 0x0012D687,
+                    content_size: 0x10000,
+                    bbid: 0x1234ABCD,
+                    kind: TicketKind::Permanent,
+                },
+                Ticket {
+                    content_id: 0x00ABCDEF,
+                    content_size: 0x10000,
+                    bbid: 0x1234ABCD,
+                    kind: TicketKind::Limited { code: 3, limit: 10 },
+                },
+            ]
+        );
+        assert_eq!(parsed.tickets[0].app_name(), "0012d687.app");
+        assert_eq!(
+            parsed.tickets[1].kind.to_string(),
+            "limited (code 3, limit 10)"
+        );
+
+        // a count the file doesn't hold, a partial ticket, and no count at all
+        file[..4].copy_from_slice(&5u32.to_be_bytes());
+        file.extend(&ticket(0x1, 0)[..0x100]);
+        let parsed = parse_tickets(&file)?;
+        assert_eq!((parsed.tickets.len(), parsed.truncated), (2, Some(5)));
+        assert!(parse_tickets(&[0, 0]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use std::fs::read_dir;
+#[cfg(feature = "writing")]
+use std::fs::{create_dir_all, read, remove_file};
+use std::io::ErrorKind;
+#[cfg(feature = "writing")]
+use std::path::Path;
+use std::path::PathBuf;
+
+#[cfg(feature = "writing")]
+use anyhow::bail;
+use anyhow::{anyhow, Result};
+#[cfg(feature = "writing")]
+use chrono::Local;
+
+use crate::config::config_dir;
+#[cfg(feature = "writing")]
+use crate::player::Player;
+#[cfg(feature = "writing")]
+use crate::sink::write_atomic;
+#[cfg(feature = "writing")]
+use crate::ticket::TICKET_FILE;
+
+#[cfg(feature = "writing")]
+const DEFAULT_KEEP: usize = 10;
+const BACKUP_EXT: &str = "sys";
+
+// <config dir>/ticket-backups/<BBID>/<timestamp>.sys; only made when there's a backup to put in it
+fn backup_dir(bbid: u32) -> Result<PathBuf> {
+    Ok(config_dir()
+        .ok_or_else(|| anyhow!("no config directory to keep ticket backups in"))?
+        .join("ticket-backups")
+        .join(format!("{bbid:08X}")))
+}
+
+// a timestamp, to the millisecond, with a counter after it if two backups land in the same one;
+// only digits and dashes, so one given back to 'load_backup' can't lead outside the directory
+fn valid_stamp(stamp: &str) -> bool {
+    !stamp.is_empty() && stamp.chars().all(|c| c.is_ascii_digit() || c == '-')
+}
+
+// timestamps of the backups for a console, oldest first
+pub fn list_backups(bbid: u32) -> Result<Vec<String>> {
+    let entries = match read_dir(backup_dir(bbid)?) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut stamps = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let path = e.path();
+            (path.extension()? == BACKUP_EXT)
+                .then(|| path.file_stem()?.to_str().map(str::to_string))
+                .flatten()
+        })
+        .filter(|s| valid_stamp(s))
+        .collect::<Vec<_>>();
+    // the timestamp format sorts chronologically as text
+    stamps.sort();
+    Ok(stamps)
+}
+
+#[cfg(feature = "writing")]
+pub fn touches_tickets(names: &[&str]) -> bool {
+    names.contains(&TICKET_FILE)
+}
+
+#[cfg(feature = "writing")]
+fn prune(bbid: u32, keep: usize) -> Result<()> {
+    let dir = backup_dir(bbid)?;
+    let stamps = list_backups(bbid)?;
+    for stamp in &stamps[..stamps.len().saturating_sub(keep)] {
+        remove_file(dir.join(format!("{stamp}.{BACKUP_EXT}")))?;
+    }
+    Ok(())
+}
+
+// saves the console's current ticket.sys before something changes it; if this fails, the
+// change mustn't go ahead
+#[cfg(feature = "writing")]
+pub fn backup_tickets<P: Player + ?Sized>(player: &P, keep: Option<usize>) -> Result<()> {
+    let bbid = player
+        .GetBBID()
+        .map_err(|e| anyhow!("Couldn't back up {TICKET_FILE}: couldn't read the BBID: {e}"))?;
+    let data = match player.ReadFile(TICKET_FILE) {
+        Ok(Some(d)) => d,
+        // nothing to lose
+        Ok(None) => return Ok(()),
+        Err(e) => bail!("Couldn't back up {TICKET_FILE}: {e}"),
+    };
+
+    let dir = backup_dir(bbid)?;
+    create_dir_all(&dir)?;
+    let path = unused_path(&dir, &Local::now().format("%Y%m%d-%H%M%S-%3f").to_string());
+    write_atomic(&path, &data)
+        .map_err(|e| anyhow!("Couldn't back up {TICKET_FILE} to {}: {e}", path.display()))?;
+    println!("Backed up {TICKET_FILE} to {}", path.display());
+    prune(bbid, keep.unwrap_or(DEFAULT_KEEP).max(1))
+}
+
+// a backup named for [stamp] that doesn't overwrite one already there
+#[cfg(feature = "writing")]
+fn unused_path(dir: &Path, stamp: &str) -> PathBuf {
+    (0..)
+        .map(|n| match n {
+            0 => dir.join(format!("{stamp}.{BACKUP_EXT}")),
+            n => dir.join(format!("{stamp}-{n}.{BACKUP_EXT}")),
+        })
+        .find(|p| !p.exists())
+        .unwrap()
+}
+
+#[cfg(feature = "writing")]
+pub fn load_backup(bbid: u32, stamp: &str) -> Result<Vec<u8>> {
+    if !valid_stamp(stamp) {
+        bail!("'{stamp}' isn't a backup's timestamp; 'ticket backups' lists them");
+    }
+    let path = backup_dir(bbid)?.join(format!("{stamp}.{BACKUP_EXT}"));
+    read(&path).map_err(|e| anyhow!("{}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "writing")]
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn stamps() -> Result<()> {
+        for (stamp, valid) in [
+            ("20261015-120000-123", true),
+            ("20261015-120000-123-1", true),
+            ("", false),
+            ("../../secrets", false),
+            ("20261015/120000", false),
+            ("latest", false),
+        ] {
+            assert_eq!(valid_stamp(stamp), valid, "{stamp}");
+        }
+
+        // backups in the same millisecond don't overwrite each other, and still sort in order
+        #[cfg(feature = "writing")]
+        {
+            let dir = TestDir::new("ticket-backup")?;
+            let mut names = vec![];
+            for _ in 0..3 {
+                let path = unused_path(&dir, "20261015-120000-123");
+                write_atomic(&path, b"tickets")?;
+                names.push(path.file_stem().unwrap().to_string_lossy().into_owned());
+            }
+            assert_eq!(
+                names,
+                [
+                    "20261015-120000-123",
+                    "20261015-120000-123-1",
+                    "20261015-120000-123-2"
+                ]
+            );
+            let mut sorted = names.clone();
+            sorted.sort();
+            assert_eq!(sorted, names);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+
+use crate::config::config_dir;
+
+const TITLES_FILE: &str = "titles.txt";
+
+// maps content IDs to human-readable names; one "<content id in hex> <title>" per line
+#[derive(Default)]
+pub struct TitleDb {
+    titles: HashMap<u32, String>,
+}
+
+impl TitleDb {
+    pub fn load() -> Self {
+        let Some(contents) = config_dir().and_then(|d| read_to_string(d.join(TITLES_FILE)).ok())
+        else {
+            return Self::default();
+        };
+
+        let titles = contents
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter_map(|l| {
+                let (id, title) = l.split_once(char::is_whitespace)?;
+                let id = u32::from_str_radix(id.trim_start_matches("0x"), 16).ok()?;
+                Some((id, title.trim().to_string()))
+            })
+            .collect();
+        Self { titles }
+    }
+
+    pub fn get(&self, content_id: u32) -> Option<&str> {
+        self.titles.get(&content_id).map(String::as_str)
+    }
+
+    pub fn lookup(&self, content_id: u32) -> &str {
+        self.get(content_id).unwrap_or("(unknown title)")
+    }
+}
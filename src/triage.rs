@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::fs::create_dir_all;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::fs::{FsBlock, BLOCK_SIZE, FS_REGION_BLOCKS, SKSA_BLOCKS};
+use crate::player::Player;
+use crate::sink::write_atomic;
+use crate::sizes::format_size;
+use crate::spare::is_bad_block;
+use crate::spotcheck::{sample_blocks, SampleRng};
+use crate::wrap::{stdout_width, wrap};
+
+// data blocks read (twice each) to see whether reads are stable
+const SAMPLE_BLOCKS: usize = 8;
+
+// everything triage finds out, which the conclusion is drawn from
+#[derive(Debug, Clone, Default)]
+pub struct Facts {
+    pub total_blocks: u32,
+    pub bad_blocks: u32,
+    // FS-region blocks that parse as a filesystem, and problems found in the newest of them
+    pub fs_generations: usize,
+    pub fs_problems: Vec<String>,
+    pub sksa_plausible: bool,
+    pub sampled: usize,
+    // blocks that read back differently the second time, or couldn't be read at all
+    pub unstable: usize,
+}
+
+// problems with a filesystem's chains: broken chains, sizes that don't match them (or that no
+// card could hold), and blocks claimed by more than one file
+pub fn fsck(fs: &FsBlock) -> Vec<String> {
+    let mut problems = fs
+        .suspect()
+        .map(|e| {
+            format!(
+                "{}: claims {} bytes, more than the card's {}; the entry is corrupt",
+                e.name,
+                e.size,
+                format_size(fs.capacity(), 0)
+            )
+        })
+        .collect::<Vec<_>>();
+    let mut owners = HashMap::new();
+    for entry in &fs.entries {
+        match fs.chain(entry.start) {
+            Ok(chain) => {
+                let suspect = entry.size as u64 > fs.capacity();
+                if entry.size != 0 && !suspect && chain.len() != entry.blocks() {
+                    problems.push(format!(
+                        "{}: {} bytes needs {} blocks, but its chain has {}",
+                        entry.name,
+                        entry.size,
+                        entry.blocks(),
+                        chain.len()
+                    ));
+                }
+                for blk in chain {
+                    if let Some(other) = owners.insert(blk, &entry.name) {
+                        problems.push(format!(
+                            "block {blk:#X} belongs to both {other} and {}",
+                            entry.name
+                        ));
+                    }
+                }
+            }
+            Err(e) => problems.push(format!("{}: {e}", entry.name)),
+        }
+    }
+    problems
+}
+
+// the SK's first block is encrypted code, so all that can be said is whether it's blank
+pub fn sksa_plausible(first_block: &[u8]) -> bool {
+    let erased = first_block.iter().all(|&b| b == 0xFF);
+    let zeroed = first_block.iter().all(|&b| b == 0);
+    !erased && !zeroed
+}
+
+// the decision table: the first rule that matches gives the conclusion
+pub fn conclude(facts: &Facts) -> &'static str {
+    let bad_fraction = facts.bad_blocks as f64 / facts.total_blocks.max(1) as f64;
+    if facts.unstable > 0 {
+        "Reads are unstable: the card or the connection is failing. Dump the card as soon as possible and check the cable."
+    } else if !facts.sksa_plausible && facts.fs_generations == 0 {
+        "Both the SKSA and the FS are missing: the card is blank or unreadable. It can only be recovered from a dump of this console."
+    } else if !facts.sksa_plausible {
+        "SKSA damaged, but the FS is intact: recoverable by writing the SKSA back from a dump of this console."
+    } else if facts.fs_generations == 0 {
+        "FS corrupt, but the SKSA is intact: recoverable by writing an FS block back from a dump, though files may be lost."
+    } else if !facts.fs_problems.is_empty() {
+        "The FS has inconsistencies, but the SKSA is intact: recoverable by deleting or re-uploading the affected files."
+    } else if bad_fraction > 0.02 {
+        "Unusually many bad blocks: the card is wearing out. Copy its contents to another card."
+    } else {
+        "No problems found on the card; the fault is probably elsewhere."
+    }
+}
+
+fn save(dir: &str, name: &str, data: &[u8]) {
+    let path = Path::new(dir).join(name);
+    match write_atomic(&path, data) {
+        Ok(_) => println!("Saved {}", path.display()),
+        Err(e) => eprintln!("Couldn't save {}: {e}", path.display()),
+    }
+}
+
+// gathers the facts without writing anything to the console, then says what they add up to
+pub fn triage(player: &dyn Player, save_dir: Option<&str>) -> Result<()> {
+    let facts = gather(player, save_dir)?;
+    println!();
+    println!("{}", wrap(conclude(&facts), stdout_width(), "", ""));
+    Ok(())
+}
+
+// reads what triage needs from the console, printing the facts as it goes
+fn gather(player: &dyn Player, save_dir: Option<&str>) -> Result<Facts> {
+    let mut facts = Facts::default();
+    match player.GetBBID().ok() {
+        Some(bbid) => println!("BBID:        {bbid:08X}"),
+        None => println!("BBID:        couldn't be read"),
+    }
+
+    let stats = player.CardStats()?;
+    facts.total_blocks = stats.free + stats.used + stats.bad;
+    // bad blocks as the FAT records them, and as marked in the spare data of the blocks read
+    let fat_bad = stats.bad;
+    let mut spare_bad = 0;
+    let num_blocks = facts.total_blocks as u16;
+    if let Some(dir) = save_dir {
+        create_dir_all(dir)?;
+    }
+
+    // the FS region; its spare data is read along the way
+    let fs_start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    let mut region = vec![];
+    let mut newest: Option<FsBlock> = None;
+    for blk in fs_start..num_blocks {
+        match player.ReadSingleBlock(blk as u32) {
+            Ok((data, spare)) => {
+                if is_bad_block(&spare) {
+                    spare_bad += 1;
+                }
+                if let Ok(fs) = FsBlock::parse(&data) {
+                    facts.fs_generations += 1;
+                    if newest.as_ref().is_none_or(|n| fs.seqno > n.seqno) {
+                        newest = Some(fs);
+                    }
+                }
+                region.extend_from_slice(&data);
+            }
+            Err(e) => {
+                eprintln!("Couldn't read FS block {blk:#X}: {e}");
+                region.extend_from_slice(&[0xFF; BLOCK_SIZE]);
+            }
+        }
+    }
+    if let Some(fs) = &newest {
+        facts.fs_problems = fsck(fs);
+        println!(
+            "FS:          {} of {FS_REGION_BLOCKS} generations valid, newest is #{} with {} files, {} problems",
+            facts.fs_generations,
+            fs.seqno,
+            fs.entries.len(),
+            facts.fs_problems.len()
+        );
+        for p in &facts.fs_problems {
+            println!("               {p}");
+        }
+    } else {
+        println!("FS:          no valid generations");
+    }
+
+    // the SKSA; only its first block is needed unless it's being saved
+    let sksa_blocks = if save_dir.is_some() { SKSA_BLOCKS } else { 1 };
+    let mut sksa = vec![];
+    for blk in 0..sksa_blocks {
+        match player.ReadSingleBlock(blk as u32) {
+            Ok((data, spare)) => {
+                if is_bad_block(&spare) {
+                    spare_bad += 1;
+                }
+                sksa.extend_from_slice(&data);
+            }
+            Err(e) => {
+                eprintln!("Couldn't read SKSA block {blk:#X}: {e}");
+                sksa.extend_from_slice(&[0xFF; BLOCK_SIZE]);
+            }
+        }
+    }
+    facts.sksa_plausible = sksa_plausible(&sksa[..BLOCK_SIZE]);
+    println!(
+        "SKSA:        {}",
+        if facts.sksa_plausible {
+            "present"
+        } else {
+            "first block is blank"
+        }
+    );
+
+    facts.bad_blocks = fat_bad.max(spare_bad);
+    println!(
+        "Bad blocks:  {} of {} ({fat_bad} in the FAT, {spare_bad} marked in the spare data read)",
+        facts.bad_blocks, facts.total_blocks
+    );
+
+    // a sample of data blocks, each read twice
+    let data_blocks = (fs_start.saturating_sub(SKSA_BLOCKS)) as usize;
+    let sample = sample_blocks(
+        data_blocks,
+        SAMPLE_BLOCKS,
+        &mut SampleRng::new(SampleRng::from_time()),
+    );
+    for offset in sample {
+        let blk = offset + SKSA_BLOCKS as u32;
+        facts.sampled += 1;
+        let first = player.ReadSingleBlock(blk).map(|(d, _)| d);
+        let second = player.ReadSingleBlock(blk).map(|(d, _)| d);
+        match (first, second) {
+            (Ok(a), Ok(b)) if a == b => {}
+            _ => facts.unstable += 1,
+        }
+    }
+    println!(
+        "Read test:   {} of {} sampled blocks unstable",
+        facts.unstable, facts.sampled
+    );
+
+    if let Some(dir) = save_dir {
+        save(dir, "fs.bin", &region);
+        save(dir, "sksa.bin", &sksa);
+    }
+    Ok(facts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    use anyhow::bail;
+    use bbrdb::CardStats;
+
+    use crate::fs::SPARE_SIZE;
+    use crate::genimage::{generate, Corruption, FileSpec, Pattern, Spec};
+
+    // a card held in memory, whose data blocks can read back differently each time
+    struct Card {
+        nand: Vec<u8>,
+        spare: Vec<u8>,
+        bad: u32,
+        flaky: bool,
+        reads: Cell<u8>,
+    }
+
+    impl Card {
+        fn new(spec: &Spec, sksa: bool) -> Result<Self> {
+            let mut generated = generate(spec, &|path| bail!("{path}: no local files"))?;
+            if sksa {
+                generated.nand[..BLOCK_SIZE].fill(0x5A);
+            }
+            Ok(Self {
+                nand: generated.nand,
+                spare: generated.spare,
+                bad: spec.bad_blocks.len() as u32,
+                flaky: false,
+                reads: Cell::new(0),
+            })
+        }
+
+        fn blocks(&self) -> u32 {
+            (self.nand.len() / BLOCK_SIZE) as u32
+        }
+    }
+
+    impl Player for Card {
+        fn GetBBID(&self) -> Result<u32> {
+            Ok(0x1234)
+        }
+        fn SetLED(&self, _: u32) -> Result<()> {
+            Ok(())
+        }
+        fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+            bail!("triage should read the FS region itself")
+        }
+        fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+            bail!("triage should read the FS region itself")
+        }
+        fn ReadFile(&self, _: &str) -> Result<Option<Vec<u8>>> {
+            bail!("triage shouldn't read files")
+        }
+        fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+            let blk = blk as usize;
+            let mut data = self.nand[blk * BLOCK_SIZE..(blk + 1) * BLOCK_SIZE].to_vec();
+            let data_area = SKSA_BLOCKS as u32..self.blocks() - FS_REGION_BLOCKS as u32;
+            if self.flaky && data_area.contains(&(blk as u32)) {
+                self.reads.set(self.reads.get().wrapping_add(1));
+                data[0] ^= self.reads.get();
+            }
+            let spare = self.spare[blk * SPARE_SIZE..(blk + 1) * SPARE_SIZE].to_vec();
+            Ok((data, spare))
+        }
+        fn CardStats(&self) -> Result<CardStats> {
+            Ok(CardStats {
+                free: self.blocks() - self.bad,
+                used: 0,
+                bad: self.bad,
+                seqno: 0,
+            })
+        }
+    }
+
+    // each rule of the table, and that the earlier rules win
+    #[test]
+    fn table() -> Result<()> {
+        let fine = Facts {
+            total_blocks: 0x1000,
+            fs_generations: 3,
+            sksa_plausible: true,
+            sampled: 8,
+            ..Facts::default()
+        };
+        let cases = [
+            (fine.clone(), "No problems found"),
+            (
+                Facts {
+                    bad_blocks: 0x100,
+                    ..fine.clone()
+                },
+                "Unusually many bad blocks",
+            ),
+            (
+                Facts {
+                    bad_blocks: 0x100,
+                    fs_problems: vec!["A.app: broken".to_string()],
+                    ..fine.clone()
+                },
+                "The FS has inconsistencies",
+            ),
+            (
+                Facts {
+                    fs_generations: 0,
+                    fs_problems: vec!["A.app: broken".to_string()],
+                    ..fine.clone()
+                },
+                "FS corrupt, but the SKSA is intact",
+            ),
+            (
+                Facts {
+                    sksa_plausible: false,
+                    ..fine.clone()
+                },
+                "SKSA damaged, but the FS is intact",
+            ),
+            (
+                Facts {
+                    sksa_plausible: false,
+                    fs_generations: 0,
+                    ..fine.clone()
+                },
+                "Both the SKSA and the FS are missing",
+            ),
+            (
+                Facts {
+                    sksa_plausible: false,
+                    fs_generations: 0,
+                    unstable: 1,
+                    ..fine.clone()
+                },
+                "Reads are unstable",
+            ),
+        ];
+        for (facts, expected) in cases {
+            let conclusion = conclude(&facts);
+            if !conclusion.starts_with(expected) {
+                bail!("{facts:?} concluded {conclusion:?}, not {expected:?}");
+            }
+        }
+        Ok(())
+    }
+
+    // cards as they come in for triage, from the facts read off them to the conclusion
+    #[test]
+    fn archetypes() -> Result<()> {
+        let spec = Spec {
+            blocks: 0x100,
+            generations: 2,
+            files: vec![FileSpec {
+                name: "GAME.app".to_string(),
+                pattern: Some(Pattern::Counting),
+                size: Some(0x10000),
+                ..FileSpec::default()
+            }],
+            ..Spec::default()
+        };
+
+        let healthy = Card::new(&spec, true)?;
+        let blank = Card {
+            nand: vec![0xFF; healthy.nand.len()],
+            ..Card::new(&spec, false)?
+        };
+        let no_sksa = Card::new(&spec, false)?;
+        let mut no_fs = Card::new(&spec, true)?;
+        let fs_start = (0x100 - FS_REGION_BLOCKS) * BLOCK_SIZE;
+        no_fs.nand[fs_start..].fill(0);
+        let truncated = Card::new(
+            &Spec {
+                corruptions: vec![Corruption::TruncatedChain {
+                    file: "GAME.app".to_string(),
+                }],
+                ..spec.clone()
+            },
+            true,
+        )?;
+        let worn = Card::new(
+            &Spec {
+                bad_blocks: (0x80..0x88).collect(),
+                ..spec.clone()
+            },
+            true,
+        )?;
+        let flaky = Card {
+            flaky: true,
+            ..Card::new(&spec, true)?
+        };
+
+        let cases = [
+            (healthy, "No problems found"),
+            (blank, "Both the SKSA and the FS are missing"),
+            (no_sksa, "SKSA damaged, but the FS is intact"),
+            (no_fs, "FS corrupt, but the SKSA is intact"),
+            (truncated, "The FS has inconsistencies"),
+            (worn, "Unusually many bad blocks"),
+            (flaky, "Reads are unstable"),
+        ];
+        for (card, expected) in cases {
+            let facts = gather(&card, None)?;
+            let conclusion = conclude(&facts);
+            if !conclusion.starts_with(expected) {
+                bail!("{facts:?} concluded {conclusion:?}, not {expected:?}");
+            }
+        }
+        Ok(())
+    }
+}
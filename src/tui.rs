@@ -0,0 +1,216 @@
+use std::io::{stdout, Stdout};
+use std::panic::{set_hook, take_hook, PanicHookInfo};
+use std::sync::Arc;
+
+use anyhow::Result;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+
+use crate::browse::{Action, Browser, Key, Mode, Row};
+use crate::cli::{dispatch, CliContext};
+use crate::offline::content_id;
+use crate::prompt::Prompt;
+use crate::sizes::format_size;
+use crate::titles::TitleDb;
+
+// 'browse': the terminal side of the file browser in browse.rs. Each operation leaves the full
+// screen while its command runs, so its output and any questions it asks look just as they do at
+// the prompt.
+
+type Hook = Arc<dyn Fn(&PanicHookInfo<'_>) + Sync + Send>;
+
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(stdout(), LeaveAlternateScreen);
+}
+
+// the full screen while it's in use; dropping it (unwinding included) gives the terminal back
+struct Screen {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    // the panic hook from before, put back afterwards
+    previous: Hook,
+}
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        // a panic's message would otherwise be printed to the alternate screen and lost
+        let previous: Hook = Arc::from(take_hook());
+        let chained = previous.clone();
+        set_hook(Box::new(move |info| {
+            restore_terminal();
+            chained(info);
+        }));
+        let mut screen = Self {
+            terminal: Terminal::new(CrosstermBackend::new(stdout()))?,
+            previous,
+        };
+        screen.resume()?;
+        Ok(screen)
+    }
+
+    fn suspend(&mut self) -> Result<()> {
+        disable_raw_mode()?;
+        execute!(stdout(), LeaveAlternateScreen)?;
+        self.terminal.show_cursor()?;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen)?;
+        self.terminal.clear()?;
+        Ok(())
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        restore_terminal();
+        let _ = self.terminal.show_cursor();
+        // the hook can't be changed while panicking; it's done its job by now anyway
+        if !std::thread::panicking() {
+            let previous = self.previous.clone();
+            set_hook(Box::new(move |info| previous(info)));
+        }
+    }
+}
+
+fn rows(context: &mut CliContext, titles: &TitleDb) -> Result<Vec<Row>> {
+    Ok(context
+        .list_files()?
+        .into_iter()
+        .map(|(name, size)| Row {
+            title: content_id(&name).map(|(cid, _)| titles.lookup(cid).to_string()),
+            name,
+            size,
+        })
+        .collect())
+}
+
+// the files that could be uploaded from the current directory
+fn local_files() -> Vec<String> {
+    std::fs::read_dir(".")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect()
+}
+
+fn key(code: KeyCode) -> Option<Key> {
+    Some(match code {
+        KeyCode::Up => Key::Up,
+        KeyCode::Down => Key::Down,
+        KeyCode::PageUp => Key::PageUp,
+        KeyCode::PageDown => Key::PageDown,
+        KeyCode::Home => Key::Home,
+        KeyCode::End => Key::End,
+        KeyCode::Enter => Key::Enter,
+        KeyCode::Esc => Key::Esc,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Delete => Key::Char('x'),
+        KeyCode::Char(c) => Key::Char(c),
+        _ => return None,
+    })
+}
+
+fn size(bytes: u32) -> String {
+    format_size(bytes as u64, 0)
+}
+
+fn draw(frame: &mut Frame, browser: &Browser) {
+    let [list_area, status_area] =
+        Layout::vertical([Constraint::Min(1), Constraint::Length(3)]).areas(frame.area());
+    let highlight = Style::default().add_modifier(Modifier::REVERSED);
+
+    let (title, items, selected) = match &browser.mode {
+        Mode::Pick { files, selected } => (
+            "Upload which file?".to_string(),
+            files
+                .iter()
+                .map(|f| ListItem::new(f.clone()))
+                .collect::<Vec<_>>(),
+            *selected,
+        ),
+        _ => (
+            format!("Files on the card ({})", browser.rows.len()),
+            browser
+                .rows
+                .iter()
+                .map(|r| {
+                    ListItem::new(format!(
+                        "{:>12}  {:>7}  {}",
+                        r.name,
+                        size(r.size),
+                        r.title.as_deref().unwrap_or_default()
+                    ))
+                })
+                .collect(),
+            browser.selected,
+        ),
+    };
+    let mut state = ListState::default().with_selected(Some(selected));
+    frame.render_stateful_widget(
+        List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .highlight_style(highlight),
+        list_area,
+        &mut state,
+    );
+
+    let status = match &browser.mode {
+        Mode::Rename { from, to } => format!("Rename {from} to: {to}"),
+        Mode::Pick { .. } => "↑↓ move  Enter upload  Esc cancel".to_string(),
+        _ => browser.status.clone(),
+    };
+    frame.render_widget(
+        Paragraph::new(status).block(Block::default().borders(Borders::ALL)),
+        status_area,
+    );
+}
+
+// runs the file browser until Esc, with operations going through `dispatch`
+pub fn browse(context: &mut CliContext, rl: &mut dyn Prompt) -> Result<()> {
+    let titles = TitleDb::load();
+    let mut browser = Browser::new(rows(context, &titles)?);
+    let mut screen = Screen::enter()?;
+    loop {
+        screen.terminal.draw(|frame| draw(frame, &browser))?;
+        let Event::Key(pressed) = event::read()? else {
+            continue;
+        };
+        if pressed.kind != KeyEventKind::Press {
+            continue;
+        }
+        let Some(key) = key(pressed.code) else {
+            continue;
+        };
+        match browser.handle(key) {
+            Action::None => {}
+            Action::Quit => return Ok(()),
+            Action::ListLocal => browser.pick(local_files()),
+            Action::Run(line) => {
+                screen.suspend()?;
+                println!("> {line}");
+                dispatch(context, rl, &line);
+                // so what it printed can be read before the list comes back
+                let _ = rl.readline("Press Enter to go back to the file list ");
+                screen.resume()?;
+                browser.status = format!("Ran '{line}'");
+                match rows(context, &titles) {
+                    Ok(rows) => browser.refresh(rows),
+                    Err(e) => browser.status = e.to_string(),
+                }
+            }
+        }
+    }
+}
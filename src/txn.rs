@@ -0,0 +1,527 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::read;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::badblocks::{spare_for, Overrides};
+use crate::cancel::CancelToken;
+use crate::file_digest::{Drift, FileDigest};
+use crate::fs::{FsBlock, FsEntry, BLOCK_SIZE, FAT_FREE, FS_REGION_BLOCKS, SKSA_BLOCKS};
+use crate::journal::Journal;
+use crate::lint::{check_name, Level};
+use crate::nand_read::card_blocks;
+use crate::player::Backend;
+use crate::provision::is_protected;
+use crate::relocate::{newest_fs, next_fs_block, write_verified};
+use crate::session::SavedOp;
+
+// 'txn': file operations gathered up and carried out together, all or none. They're applied to
+// a copy of the current FS in memory, and then committed as the blocks of the new files' data
+// followed by a single new FS generation. New data only goes in blocks the current generation
+// has free, so until that last write the current generation still describes the card exactly,
+// and an interruption leaves it as it was.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    // `source` is the local file the data was read from, if it was read from one
+    Upload {
+        name: String,
+        data: Vec<u8>,
+        source: Option<String>,
+    },
+    Delete(String),
+    Rename {
+        from: String,
+        to: String,
+    },
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Upload { name, data, .. } => {
+                write!(f, "upload {name} ({:#X} bytes)", data.len())
+            }
+            Self::Delete(name) => write!(f, "delete {name}"),
+            Self::Rename { from, to } => write!(f, "rename {from} to {to}"),
+        }
+    }
+}
+
+impl Op {
+    // the names on the card it touches
+    pub fn names(&self) -> Vec<&str> {
+        match self {
+            Self::Upload { name, .. } | Self::Delete(name) => vec![name],
+            Self::Rename { from, to } => vec![from, to],
+        }
+    }
+
+    // the 'txn add' arguments that queue it again, for saving with a session; an upload also has
+    // the digest of the data it queued. None for an upload that wasn't read from a file.
+    pub fn saved(&self) -> Option<SavedOp> {
+        let (args, digest) = match self {
+            Self::Upload { data, source, .. } => {
+                let source = source.as_ref()?;
+                (
+                    vec!["4", source.as_str()],
+                    Some(FileDigest::of_data(source, data)),
+                )
+            }
+            Self::Delete(name) => (vec!["6", name.as_str()], None),
+            Self::Rename { from, to } => (vec!["7", from.as_str(), to.as_str()], None),
+        };
+        Some(SavedOp {
+            args: args.into_iter().map(str::to_string).collect(),
+            digest,
+        })
+    }
+}
+
+// one of the commands 'txn add' takes: '4 file', '6 file' or '7 from to', with uploads read by
+// `read`; an upload is named after the file, without its directory
+pub fn parse_op(args: &[&str], read: impl Fn(&str) -> Result<Vec<u8>>) -> Result<Op> {
+    match args {
+        ["4", path] => {
+            let name = Path::new(path)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or(path);
+            Ok(Op::Upload {
+                name: name.to_string(),
+                data: read(path)?,
+                source: Some(path.to_string()),
+            })
+        }
+        ["6", name] => Ok(Op::Delete(name.to_string())),
+        ["7", from, to] => Ok(Op::Rename {
+            from: from.to_string(),
+            to: to.to_string(),
+        }),
+        _ => bail!("'txn add' takes one of '4 file', '6 file' or '7 from to'"),
+    }
+}
+
+pub struct Restored {
+    pub ops: Vec<Op>,
+    // what's different from when the transaction was saved; it mustn't be committed until these
+    // have been confirmed
+    pub flagged: Vec<String>,
+}
+
+// queues a saved transaction's operations again, reading uploads from their files as they are
+// now. An upload whose file has changed since it was queued is flagged, and with `strict` left
+// out; one whose file is gone is always left out.
+pub fn restore(saved: &[SavedOp], strict: bool) -> Restored {
+    let mut ops = vec![];
+    let mut flagged = vec![];
+    for (i, s) in saved.iter().enumerate() {
+        let args = s.args.iter().map(String::as_str).collect::<Vec<_>>();
+        let label = format!("operation {} ({})", i + 1, args.join(" "));
+        match s.digest.as_ref().map(|d| (d, d.check())) {
+            Some((d, Drift::Missing(e))) => {
+                flagged.push(format!(
+                    "{label}: {} can't be read any more ({e}); left out",
+                    d.path
+                ));
+                continue;
+            }
+            Some((d, Drift::Changed)) if strict => {
+                flagged.push(format!(
+                    "{label}: {} has changed since it was queued; left out, as strict-writes is on",
+                    d.path
+                ));
+                continue;
+            }
+            Some((d, Drift::Changed)) => flagged.push(format!(
+                "{label}: {} has changed since it was queued; it's uploaded as it is now",
+                d.path
+            )),
+            _ => {}
+        }
+        match parse_op(&args, |path| Ok(read(path)?)) {
+            Ok(op) => ops.push(op),
+            Err(e) => flagged.push(format!("{label}: {e}; left out")),
+        }
+    }
+    Restored { ops, flagged }
+}
+
+#[derive(Debug, Clone)]
+pub struct Applied {
+    // the new generation, its sequence number already bumped
+    pub fs: FsBlock,
+    // the blocks of new file data, in order
+    pub writes: Vec<(u16, Vec<u8>)>,
+}
+
+fn check_new_name(name: &str) -> Result<()> {
+    match check_name(name)
+        .into_iter()
+        .find(|i| i.level == Level::Error)
+    {
+        Some(issue) => bail!("{}", issue.message),
+        None => Ok(()),
+    }
+}
+
+fn apply_op(
+    current: &FsBlock,
+    fs: &mut FsBlock,
+    op: &Op,
+    usable: &dyn Fn(u16) -> bool,
+    taken: &mut HashSet<u16>,
+    writes: &mut Vec<(u16, Vec<u8>)>,
+) -> Result<()> {
+    match op {
+        Op::Upload { name, data, .. } => {
+            check_new_name(name)?;
+            if data.is_empty() {
+                bail!("can't write an empty file");
+            }
+            let old = fs.find(name).cloned();
+            if let Some(old) = &old {
+                fs.free_chain(old.start)?;
+                fs.entries.retain(|e| e.name != *name);
+            }
+            let free =
+                |b: u16| current.fat[b as usize] == FAT_FREE && !taken.contains(&b) && usable(b);
+            let chain = fs
+                .allocate(data.len().div_ceil(BLOCK_SIZE), free)
+                .map_err(|e| anyhow!("{e} (blocks freed earlier in the transaction can't be reused until it's committed)"))?;
+            for (&blk, chunk) in chain.iter().zip(data.chunks(BLOCK_SIZE)) {
+                let mut block = vec![0; BLOCK_SIZE];
+                block[..chunk.len()].copy_from_slice(chunk);
+                writes.push((blk, block));
+                taken.insert(blk);
+            }
+            fs.entries.push(FsEntry {
+                attrs: old.map_or_else(Default::default, |o| o.attrs),
+                ..FsEntry::new(name, chain[0], data.len() as u32)
+            });
+        }
+        Op::Delete(name) => {
+            if is_protected(name) {
+                bail!("{name} is a system file, which the console can't do without");
+            }
+            let Some(entry) = fs.find(name).cloned() else {
+                bail!("{name} isn't on the card");
+            };
+            fs.free_chain(entry.start)?;
+            fs.entries.retain(|e| e.name != *name);
+        }
+        Op::Rename { from, to } => {
+            if is_protected(from) {
+                bail!("{from} is a system file, which the console can't do without");
+            }
+            check_new_name(to)?;
+            if fs.find(to).is_some() {
+                bail!("{to} is already on the card");
+            }
+            match fs.entries.iter_mut().find(|e| e.name == *from) {
+                Some(entry) => entry.name = to.clone(),
+                None => bail!("{from} isn't on the card"),
+            }
+        }
+    }
+    Ok(())
+}
+
+// applies `ops` in order to a copy of `current`, failing at the first that can't be done; new
+// data goes in blocks that `usable` allows and `current` has free
+pub fn apply(current: &FsBlock, ops: &[Op], usable: impl Fn(u16) -> bool) -> Result<Applied> {
+    if current.linked {
+        bail!("multi-block FATs aren't supported");
+    }
+    let mut fs = current.clone();
+    let mut taken = HashSet::new();
+    let mut writes = vec![];
+    for (i, op) in ops.iter().enumerate() {
+        apply_op(current, &mut fs, op, &usable, &mut taken, &mut writes)
+            .map_err(|e| anyhow!("operation {} ({op}): {e}", i + 1))?;
+    }
+    // a file uploaded and then replaced or deleted within the transaction needn't be written
+    writes.retain(|(blk, _)| fs.fat[*blk as usize] != FAT_FREE);
+    fs.to_bytes()?;
+    fs.seqno += 1;
+    Ok(Applied { fs, writes })
+}
+
+// plans the transaction against the card's newest FS, writes and verifies the data blocks, and
+// only then writes and verifies the new FS generation
+fn run(
+    player: &dyn Backend,
+    ops: &[Op],
+    cancel: &CancelToken,
+    journal: &mut Journal,
+) -> Result<Applied> {
+    let num_blocks = card_blocks(player)? as u16;
+    let (fs_blk, current) = newest_fs(player, num_blocks)?;
+    let fs_start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    let applied = apply(&current, ops, |b| (SKSA_BLOCKS..fs_start).contains(&b))?;
+    let trusted = Overrides::trusted_on(player);
+    journal.record(&format!(
+        "plan: {} data blocks, then FS #{}",
+        applied.writes.len(),
+        applied.fs.seqno
+    ))?;
+    for (blk, data) in &applied.writes {
+        cancel.check()?;
+        let (_, existing) = player.ReadSingleBlock(*blk as u32)?;
+        write_verified(
+            player,
+            *blk,
+            data,
+            &spare_for(*blk, data, &existing, &trusted)?,
+        )?;
+        journal.record(&format!("wrote block {blk:#X}; verified"))?;
+    }
+
+    cancel.check()?;
+    let next = next_fs_block(player, fs_blk, num_blocks, &trusted)?;
+    let data = applied.fs.to_bytes()?;
+    let (_, existing) = player.ReadSingleBlock(next as u32)?;
+    write_verified(
+        player,
+        next,
+        &data,
+        &spare_for(next, &data, &existing, &trusted)?,
+    )?;
+    journal.record(&format!(
+        "wrote FS #{} to block {next:#X}; verified",
+        applied.fs.seqno
+    ))?;
+    Ok(applied)
+}
+
+// commits a transaction to the console, journalling every step; cancelling, or a failure, before
+// the FS is written leaves the card as it was
+pub fn commit_console(player: &dyn Backend, ops: &[Op], cancel: &CancelToken) -> Result<Applied> {
+    let mut journal = Journal::open(player.GetBBID()?, "txn")?;
+    let list = ops.iter().map(Op::to_string).collect::<Vec<_>>().join("; ");
+    journal.record(&format!("begin: {list}"))?;
+    match run(player, ops, cancel, &mut journal) {
+        Ok(applied) => {
+            journal.record("done")?;
+            Ok(applied)
+        }
+        Err(e) => {
+            // not being able to journal the failure mustn't hide it
+            let _ = journal.record(&format!("failed: {e}"));
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn transactions() -> Result<()> {
+        use crate::fs::{FAT_END, FAT_ENTRIES, FAT_RESERVED};
+
+        let mut fat = vec![FAT_FREE; FAT_ENTRIES];
+        fat[..0x40].fill(FAT_RESERVED);
+        // GAME.app is 0x40 -> 0x41, ticket.sys 0x42, GAME.sta 0x43
+        fat[0x40] = 0x41;
+        fat[0x41] = FAT_END;
+        fat[0x42] = FAT_END;
+        fat[0x43] = FAT_END;
+        let entry = FsEntry::new;
+        let mut current = FsBlock {
+            fat,
+            entries: vec![
+                entry("GAME.app", 0x40, 0x8000),
+                entry("ticket.sys", 0x42, 0x2000),
+                entry("GAME.sta", 0x43, 0x200),
+            ],
+            linked: false,
+            seqno: 4,
+        };
+        // an attribute nothing interprets, which replacing the file mustn't lose
+        current.entries[1].attrs.reserved = [0x12, 0x34];
+        let upload = |name: &str, size: usize, byte| Op::Upload {
+            name: name.to_string(),
+            data: vec![byte; size],
+            source: None,
+        };
+        let delete = |name: &str| Op::Delete(name.to_string());
+        let rename = |from: &str, to: &str| Op::Rename {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        // cards with plenty of free blocks, and with just one
+        let roomy = |b: u16| b < 0x100;
+        let tight = |b: u16| b < 0x45;
+
+        // replacing a game: its app, its ticket and its save, all at once
+        let ops = [
+            upload("GAME.app", 0x8001, 1),
+            upload("ticket.sys", 0x2000, 2),
+            delete("GAME.sta"),
+        ];
+        let applied = apply(&current, &ops, roomy)?;
+        let blocks = applied.writes.iter().map(|(b, _)| *b).collect::<Vec<_>>();
+        if blocks != [0x44, 0x45, 0x46, 0x47] {
+            bail!("the new data went to blocks {blocks:X?}");
+        }
+        let fs = &applied.fs;
+        if fs.seqno != 5
+            || fs.chain(fs.find("GAME.app").unwrap().start)? != [0x44, 0x45, 0x46]
+            || fs.find("ticket.sys").map(|e| e.start) != Some(0x47)
+            || fs.find("GAME.sta").is_some()
+            || fs.find("ticket.sys").map(|e| e.attrs) != Some(current.entries[1].attrs)
+        {
+            bail!("the new FS was {fs:X?}");
+        }
+        // the old blocks are free in the new generation, and none of them was written over
+        if [0x40, 0x41, 0x42, 0x43]
+            .iter()
+            .any(|&b| fs.fat[b] != FAT_FREE)
+        {
+            bail!("the replaced files' blocks weren't freed");
+        }
+        let ticket = &applied.writes[3].1;
+        if applied.writes[2].1[..2] != [1, 0]
+            || ticket[..0x2000].iter().any(|&b| b != 2)
+            || ticket[0x2000..].iter().any(|&b| b != 0)
+        {
+            bail!("the data blocks weren't padded or filled as expected");
+        }
+
+        // blocks freed by a delete stay the current generation's until the commit, so space freed
+        // within a transaction can't be used by it
+        let swap = [delete("GAME.app"), upload("NEW.app", 0x8000, 3)];
+        match apply(&current, &swap, tight) {
+            Err(e) if e.to_string().starts_with("operation 2 (upload NEW.app") => {}
+            other => bail!(
+                "an upload into blocks freed in the same transaction gave {:?}",
+                other.map(|a| a.writes.len())
+            ),
+        }
+        if apply(&current, &swap, |b| b < 0x46)?.writes.len() != 2 {
+            bail!("an upload that fits in the free blocks wasn't planned");
+        }
+
+        // renames are checked against the names as they'll be by then
+        let renames = [
+            rename("GAME.app", "OLD.app"),
+            rename("GAME.sta", "GAME.app"),
+        ];
+        let renamed = apply(&current, &renames, roomy)?;
+        if !renamed.writes.is_empty()
+            || renamed.fs.find("GAME.app").map(|e| e.start) != Some(0x43)
+            || renamed.fs.find("OLD.app").map(|e| e.start) != Some(0x40)
+        {
+            bail!("renames gave {:X?}", renamed.fs.entries);
+        }
+
+        // an upload replaced later in the same transaction isn't written at all
+        let twice = [upload("A.app", 0x10, 4), upload("A.app", 0x10, 5)];
+        let applied = apply(&current, &twice, roomy)?;
+        if applied.writes.len() != 1 || applied.writes[0].1[0] != 5 {
+            bail!("an upload replaced within the transaction was still written");
+        }
+
+        for (ops, expected) in [
+            (vec![delete("ticket.sys")], "system file"),
+            (vec![rename("ticket.sys", "t.sys")], "system file"),
+            (vec![delete("NONE.app")], "isn't on the card"),
+            (vec![rename("GAME.app", "GAME.sta")], "already on the card"),
+            (vec![upload("TOOLONGNAME.app", 1, 0)], "longer than 8"),
+            (vec![upload("E.app", 0, 0)], "empty"),
+            (vec![delete("GAME.sta"), delete("GAME.sta")], "operation 2"),
+        ] {
+            match apply(&current, &ops, roomy) {
+                Err(e) if e.to_string().contains(expected) => {}
+                other => bail!(
+                    "{ops:?} gave {:?}, not an error about '{expected}'",
+                    other.map(|a| a.fs.entries)
+                ),
+            }
+        }
+
+        let read = |path: &str| Ok(path.as_bytes().to_vec());
+        let from_dir = Op::Upload {
+            name: "GAME.app".to_string(),
+            data: b"dir/GAME.app".to_vec(),
+            source: Some("dir/GAME.app".to_string()),
+        };
+        if parse_op(&["4", "dir/GAME.app"], read)? != from_dir
+            || parse_op(&["6", "GAME.sta"], read)? != delete("GAME.sta")
+            || parse_op(&["7", "A.app", "B.app"], read)? != rename("A.app", "B.app")
+            || parse_op(&["5", "x"], read).is_ok()
+            || parse_op(&["7", "A.app"], read).is_ok()
+        {
+            bail!("'txn add' commands were misread");
+        }
+
+        // a transaction saved with a session and restored after its files have changed or gone
+        let dir = TestDir::new("txn")?;
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        for name in ["SAME.app", "EDIT.app", "GONE.app"] {
+            std::fs::write(path(name), name)?;
+        }
+        let ops = [
+            parse_op(&["4", &path("SAME.app")], |p| Ok(std::fs::read(p)?))?,
+            parse_op(&["4", &path("EDIT.app")], |p| Ok(std::fs::read(p)?))?,
+            parse_op(&["4", &path("GONE.app")], |p| Ok(std::fs::read(p)?))?,
+            rename("A.app", "B.app"),
+            upload("MEM.app", 0x10, 1),
+        ];
+        let saved = ops.iter().filter_map(Op::saved).collect::<Vec<_>>();
+        if saved.len() != 4 || saved[3].args != ["7", "A.app", "B.app"] || saved[3].digest.is_some()
+        {
+            bail!("the transaction was saved as {saved:?}");
+        }
+        let unchanged = restore(&saved, false);
+        if unchanged.ops != ops[..4] || !unchanged.flagged.is_empty() {
+            bail!(
+                "an unchanged transaction came back flagged: {:?}",
+                unchanged.flagged
+            );
+        }
+
+        std::fs::write(path("EDIT.app"), "edited")?;
+        std::fs::remove_file(path("GONE.app"))?;
+        let restored = restore(&saved, false);
+        let names = restored
+            .ops
+            .iter()
+            .map(|o| o.to_string())
+            .collect::<Vec<_>>();
+        if names
+            != [
+                "upload SAME.app (0x8 bytes)",
+                "upload EDIT.app (0x6 bytes)",
+                "rename A.app to B.app",
+            ]
+            || restored.flagged.len() != 2
+            || !restored.flagged[0].starts_with("operation 2 (4 ")
+            || !restored.flagged[0]
+                .contains("has changed since it was queued; it's uploaded as it is now")
+            || !restored.flagged[1].starts_with("operation 3 (4 ")
+            || !restored.flagged[1].ends_with("; left out")
+        {
+            bail!(
+                "a changed transaction came back as {names:?}, flagged {:?}",
+                restored.flagged
+            );
+        }
+        let strict = restore(&saved, true);
+        if strict.ops.len() != 2
+            || strict.flagged.len() != 2
+            || !strict.flagged[0].contains("strict-writes")
+        {
+            bail!(
+                "with strict-writes, a changed upload came back as {:?}",
+                strict.flagged
+            );
+        }
+        Ok(())
+    }
+}
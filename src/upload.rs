@@ -0,0 +1,757 @@
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_file};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::badblocks::{spare_for, Overrides};
+use crate::cancel::CancelToken;
+use crate::config::config_dir;
+use crate::fs::{FsBlock, BLOCK_SIZE, FS_REGION_BLOCKS, SKSA_BLOCKS};
+use crate::hashing::HashAlgo;
+use crate::nand_read::card_blocks;
+use crate::player::{Player, PlayerBlockWrite, PlayerWrite};
+use crate::progress::Progress;
+use crate::relocate::{newest_fs, next_fs_block, write_verified};
+use crate::sink::write_atomic;
+use crate::throughput::TransferTimer;
+use crate::txn::{apply, Op};
+
+// '4' to a console. Its WriteFile sends a file in one call, all or nothing, and there's no call that
+// adds to a file, so an upload that fails partway can only be started again; the resume file
+// records that it was tried, so the next attempt can say so. '4 --blocks' instead, as a transaction
+// does, sends the file a block at a time into blocks the card's newest FS has free, followed by a
+// new FS generation naming it. Until that last write the old generation still describes the card,
+// so an upload that stops partway can be continued as long as the card's FS and the file haven't
+// changed since: its resume file records the blocks planned and how many are written. Either way
+// the resume file is only deleted once the file read back from the console matches what was sent.
+
+// what a resume file records about an upload that hasn't finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resume {
+    pub name: String,
+    pub bbid: u32,
+    // of the local data, so a file that's changed since isn't continued
+    pub sha256: String,
+    pub size: usize,
+    // the FS generation the blocks were planned against, and the blocks, in order
+    pub seqno: u32,
+    pub chain: Vec<u16>,
+    // how many of them are written and verified
+    pub done: usize,
+}
+
+impl Resume {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut fields = HashMap::new();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("malformed line '{line}'"))?;
+            fields.insert(key.trim(), value.trim());
+        }
+        let field = |key| {
+            fields
+                .get(key)
+                .copied()
+                .ok_or_else(|| anyhow!("missing {key}"))
+        };
+        let hex = |v: &str| u32::from_str_radix(v.trim_start_matches("0x"), 16);
+        let chain = match field("chain")? {
+            "" => vec![],
+            list => list
+                .split(',')
+                .map(|b| Ok(hex(b.trim())?.try_into()?))
+                .collect::<Result<_>>()?,
+        };
+        Ok(Self {
+            name: field("name")?.to_string(),
+            bbid: hex(field("bbid")?)?,
+            sha256: field("sha256")?.to_string(),
+            size: field("size")?.parse()?,
+            seqno: field("seqno")?.parse()?,
+            chain,
+            done: field("done")?.parse()?,
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let chain = self
+            .chain
+            .iter()
+            .map(|b| format!("{b:#X}"))
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "name={}\nbbid={:08X}\nsha256={}\nsize={}\nseqno={}\nchain={chain}\ndone={}\n",
+            self.name, self.bbid, self.sha256, self.size, self.seqno, self.done
+        )
+    }
+}
+
+// where resume files are kept: '<config>/uploads'
+pub fn resume_dir() -> Result<PathBuf> {
+    let dir = config_dir()
+        .ok_or_else(|| anyhow!("no config directory to keep upload resume files in"))?
+        .join("uploads");
+    create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+// keyed by the name on the card and the data's hash, so a changed file never takes over another
+// version's progress
+fn resume_path(dir: &Path, name: &str, sha256: &str) -> PathBuf {
+    dir.join(format!("{name}.{}.txt", &sha256[..sha256.len().min(16)]))
+}
+
+// the resume files for uploads of `name`, whatever their data
+fn earlier(dir: &Path, name: &str) -> Vec<(PathBuf, Resume)> {
+    let Ok(entries) = read_dir(dir) else {
+        return vec![];
+    };
+    let mut found = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| {
+            p.file_name()
+                .and_then(|f| f.to_str())
+                .is_some_and(|f| f.starts_with(&format!("{name}.")))
+        })
+        .filter_map(|p| {
+            match read_to_string(&p)
+                .map_err(|e| e.into())
+                .and_then(|t| Resume::parse(&t))
+            {
+                Ok(r) if r.name == name => Some((p, r)),
+                Ok(_) => None,
+                Err(e) => {
+                    eprintln!("Ignoring {}: {e}", p.display());
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    found
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    // no earlier attempt, or one that got nowhere
+    Fresh,
+    // the earlier attempt's first this many blocks are on the card and can be kept
+    Continue(usize),
+    // the earlier attempt can't be continued, for this reason
+    Restart(String),
+}
+
+// whether an earlier attempt's progress can be kept for `plan`, the attempt about to be made: it
+// has to be the same data, to the same console, into the same blocks of the same FS generation
+pub fn decide(earlier: Option<&Resume>, plan: &Resume) -> Decision {
+    let Some(earlier) = earlier else {
+        return Decision::Fresh;
+    };
+    if earlier.sha256 != plan.sha256 {
+        return Decision::Restart(format!(
+            "{} has changed since the interrupted upload (its SHA-256 is different)",
+            plan.name
+        ));
+    }
+    if earlier.bbid != plan.bbid {
+        return Decision::Restart(format!(
+            "the interrupted upload was to console {:08X}",
+            earlier.bbid
+        ));
+    }
+    if earlier.seqno != plan.seqno {
+        return Decision::Restart(format!(
+            "the card's FS has changed since the interrupted upload (it was #{}, it's now #{}), so the blocks it wrote may have been used since",
+            earlier.seqno, plan.seqno
+        ));
+    }
+    if earlier.chain != plan.chain || earlier.done > earlier.chain.len() {
+        return Decision::Restart(
+            "the blocks the interrupted upload was writing to aren't the ones it would use now"
+                .to_string(),
+        );
+    }
+    match earlier.done {
+        0 => Decision::Fresh,
+        done => Decision::Continue(done),
+    }
+}
+
+// an upload planned against the card's newest FS, and what can be kept from an earlier attempt
+pub struct Planned {
+    pub record: Resume,
+    pub decision: Decision,
+    writes: Vec<(u16, Vec<u8>)>,
+    fs: FsBlock,
+    fs_blk: u16,
+}
+
+pub fn plan(player: &dyn PlayerBlockWrite, dir: &Path, name: &str, data: &[u8]) -> Result<Planned> {
+    let num_blocks = card_blocks(player)? as u16;
+    let (fs_blk, current) = newest_fs(player, num_blocks)?;
+    let fs_start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    let upload = Op::Upload {
+        name: name.to_string(),
+        data: data.to_vec(),
+        source: None,
+    };
+    let applied = apply(&current, &[upload], |b| {
+        (SKSA_BLOCKS..fs_start).contains(&b)
+    })?;
+    let record = Resume {
+        name: name.to_string(),
+        bbid: player.GetBBID()?,
+        sha256: HashAlgo::Sha256.digest(data).hex(),
+        size: data.len(),
+        seqno: current.seqno,
+        chain: applied.writes.iter().map(|(blk, _)| *blk).collect(),
+        done: 0,
+    };
+    let found = earlier(dir, name);
+    // one of the same data first, as that's the one that might be continued
+    let earlier = found
+        .iter()
+        .find(|(_, r)| r.sha256 == record.sha256)
+        .or(found.first())
+        .map(|(_, r)| r);
+    Ok(Planned {
+        decision: decide(earlier, &record),
+        record,
+        writes: applied.writes,
+        fs: applied.fs,
+        fs_blk,
+    })
+}
+
+// an upload sent with WriteFile: nothing of an earlier attempt can be kept, so there's no plan
+// beyond noting what's being sent
+pub fn whole(player: &dyn Player, name: &str, data: &[u8]) -> Result<Resume> {
+    Ok(Resume {
+        name: name.to_string(),
+        bbid: player.GetBBID()?,
+        sha256: HashAlgo::Sha256.digest(data).hex(),
+        size: data.len(),
+        seqno: player.CardStats()?.seqno,
+        chain: vec![],
+        done: 0,
+    })
+}
+
+// an earlier upload of `name` that didn't finish
+pub fn interrupted(dir: &Path, name: &str) -> Option<Resume> {
+    earlier(dir, name).into_iter().map(|(_, r)| r).next()
+}
+
+// notes `record` as the upload of its file under way; an earlier attempt that isn't being
+// continued is only in the way
+fn begin(dir: &Path, record: &Resume) -> Result<PathBuf> {
+    for (stale, _) in earlier(dir, &record.name) {
+        let _ = remove_file(stale);
+    }
+    let path = resume_path(dir, &record.name, &record.sha256);
+    write_atomic(&path, record.to_text().as_bytes())?;
+    Ok(path)
+}
+
+// the file as the console now has it has to be what was sent
+fn check_copy<P: Player + ?Sized>(player: &P, record: &Resume) -> Result<()> {
+    let name = &record.name;
+    let back = player
+        .ReadFile(name)?
+        .ok_or_else(|| anyhow!("{name} isn't on the console after it was written"))?;
+    let sha256 = HashAlgo::Sha256.digest(&back).hex();
+    if back.len() != record.size || sha256 != record.sha256 {
+        bail!(
+            "{name} read back from the console as {:#X} bytes with SHA-256 {sha256}, not the {:#X} bytes with SHA-256 {} that were sent; upload it again",
+            back.len(),
+            record.size,
+            record.sha256
+        );
+    }
+    Ok(())
+}
+
+// sends the file with WriteFile and reads it back; the resume file goes once it matches
+pub fn write_whole<P: PlayerWrite + ?Sized>(
+    player: &mut P,
+    dir: &Path,
+    record: &Resume,
+    data: &[u8],
+) -> Result<()> {
+    let path = begin(dir, record)?;
+    player.WriteFile(data, &record.name)?;
+    check_copy(player, record)?;
+    remove_file(&path)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct Uploaded {
+    // the FS generation naming the file
+    pub seqno: u32,
+    // the blocks an earlier attempt had already written
+    pub kept: usize,
+}
+
+// the data blocks from `record.done` on, noting each in the resume file at `path` as it's
+// verified, and then the FS generation
+fn send(
+    player: &dyn PlayerBlockWrite,
+    planned: &Planned,
+    record: &mut Resume,
+    path: &Path,
+    cancel: &CancelToken,
+    mut progress: Option<&mut Progress>,
+) -> Result<()> {
+    let total = planned.writes.len();
+    let trusted = Overrides::trusted_on(player);
+    for (blk, data) in &planned.writes[record.done..] {
+        cancel
+            .check()
+            .and_then(|_| player.ReadSingleBlock(*blk as u32))
+            .and_then(|(_, existing)| spare_for(*blk, data, &existing, &trusted))
+            .and_then(|spare| write_verified(player, *blk, data, &spare))
+            .map_err(|e| {
+                anyhow!(
+                    "Failed to write block {blk:#X} ({} of {total}) of {}: {e}; '4 --continue' carries on from there",
+                    record.done + 1,
+                    record.name
+                )
+            })?;
+        record.done += 1;
+        write_atomic(path, record.to_text().as_bytes())?;
+        if let Some(p) = progress.as_mut() {
+            p.inc(1);
+        }
+    }
+
+    // the file only appears once this is written
+    cancel.check()?;
+    let num_blocks = card_blocks(player)? as u16;
+    let next = next_fs_block(player, planned.fs_blk, num_blocks, &trusted)?;
+    let fs = planned.fs.to_bytes()?;
+    let (_, existing) = player.ReadSingleBlock(next as u32)?;
+    write_verified(
+        player,
+        next,
+        &fs,
+        &spare_for(next, &fs, &existing, &trusted)?,
+    )
+}
+
+// writes the file from block `from` of the plan (0, or what the decision said could be kept),
+// then its FS generation, then has the console read its FS again and reads the file back; the
+// resume file goes once it matches
+fn carry_out(
+    player: &mut dyn PlayerBlockWrite,
+    dir: &Path,
+    planned: &Planned,
+    from: usize,
+    cancel: &CancelToken,
+    progress: Option<&mut Progress>,
+) -> Result<Uploaded> {
+    let kept = from.min(planned.writes.len());
+    let mut record = Resume {
+        done: kept,
+        ..planned.record.clone()
+    };
+    let path = begin(dir, &record)?;
+    send(player, planned, &mut record, &path, cancel, progress)?;
+    player.Close()?;
+    player.Init()?;
+    check_copy(player, &record)?;
+    remove_file(&path)?;
+    Ok(Uploaded {
+        seqno: planned.fs.seqno,
+        kept,
+    })
+}
+
+pub fn upload(
+    player: &mut dyn PlayerBlockWrite,
+    dir: &Path,
+    planned: &Planned,
+    from: usize,
+    events: bool,
+    cancel: &CancelToken,
+) -> Result<Uploaded> {
+    let name = &planned.record.name;
+    let total = planned.writes.len();
+    let from = from.min(total);
+    let timer = TransferTimer::begin(player, name, (total - from) as u64 * BLOCK_SIZE as u64);
+    let mut progress = Progress::start("upload", total as u64, BLOCK_SIZE, events);
+    progress.set_message(format!("Writing {name}"));
+    progress.inc(from as u64);
+    match carry_out(player, dir, planned, from, cancel, Some(&mut progress)) {
+        Ok(uploaded) => {
+            progress.finish();
+            timer.complete();
+            Ok(uploaded)
+        }
+        Err(e) => {
+            progress.fail(&e.to_string());
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn resumed_uploads() -> Result<()> {
+        use std::cell::{Cell, RefCell};
+
+        use bbrdb::CardStats;
+
+        use crate::fs::{FAT_ENTRIES, FAT_FREE, SPARE_SIZE};
+
+        // the resume file's bookkeeping survives being written out and read back
+        let record = Resume {
+            name: "BIG.app".to_string(),
+            bbid: 0x1234ABCD,
+            sha256: HashAlgo::Sha256.digest(b"big").hex(),
+            size: 0x14123,
+            seqno: 7,
+            chain: vec![0x40, 0x41, 0x45, 0x46, 0x47, 0x100],
+            done: 3,
+        };
+        if Resume::parse(&record.to_text())? != record {
+            bail!(
+                "a resume file read back as something else:\n{}",
+                record.to_text()
+            );
+        }
+        if Resume::parse("name=BIG.app\nsha256=00\n").is_ok() {
+            bail!("a resume file missing its fields was accepted");
+        }
+
+        // whether to continue: only the same data, to the same console, into the same blocks of the
+        // same FS generation
+        let attempt = Resume {
+            done: 0,
+            ..record.clone()
+        };
+        let restarts = |earlier: Resume, expected: &str| match decide(Some(&earlier), &attempt) {
+            Decision::Restart(why) if why.contains(expected) => Ok(()),
+            other => Err(anyhow!(
+                "expected a restart for '{expected}', got {other:?}"
+            )),
+        };
+        if decide(None, &attempt) != Decision::Fresh
+            || decide(Some(&record), &attempt) != Decision::Continue(3)
+            || decide(Some(&attempt), &attempt) != Decision::Fresh
+        {
+            bail!("an earlier attempt at the same upload wasn't continued");
+        }
+        let changed = HashAlgo::Sha256.digest(b"bigger").hex();
+        restarts(
+            Resume {
+                sha256: changed.clone(),
+                ..record.clone()
+            },
+            "BIG.app has changed since",
+        )?;
+        restarts(
+            Resume {
+                bbid: 0x5678,
+                ..record.clone()
+            },
+            "was to console 00005678",
+        )?;
+        restarts(
+            Resume {
+                seqno: 6,
+                ..record.clone()
+            },
+            "it was #6, it's now #7",
+        )?;
+        restarts(
+            Resume {
+                chain: vec![0x40, 0x41, 0x42, 0x43, 0x44, 0x45],
+                ..record.clone()
+            },
+            "aren't the ones",
+        )?;
+        restarts(
+            Resume {
+                done: 7,
+                ..record.clone()
+            },
+            "aren't the ones",
+        )?;
+        if resume_path(Path::new("d"), "BIG.app", &record.sha256)
+            == resume_path(Path::new("d"), "BIG.app", &changed)
+        {
+            bail!("resume files of different data had the same name");
+        }
+
+        // a card whose writes can fail at a chosen block, once. Like a console, it reads its FS when
+        // it's initialised and answers from that until it's initialised again
+        struct Card {
+            blocks: RefCell<HashMap<u32, Vec<u8>>>,
+            writes: RefCell<Vec<u16>>,
+            fail_at: Cell<Option<u32>>,
+            cached: Option<FsBlock>,
+        }
+        impl Card {
+            fn newest(&self) -> Result<FsBlock> {
+                Ok(newest_fs(self, 0x1000)?.1)
+            }
+            fn current(&self) -> Result<&FsBlock> {
+                self.cached
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("the connection isn't initialised"))
+            }
+        }
+        impl Player for Card {
+            fn GetBBID(&self) -> Result<u32> {
+                Ok(0x1234ABCD)
+            }
+            fn SetLED(&self, _: u32) -> Result<()> {
+                Ok(())
+            }
+            fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+                Ok(vec![])
+            }
+            fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+                self.current()?.to_bytes()
+            }
+            fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+                let fs = self.current()?;
+                let Some(entry) = fs.find(name) else {
+                    return Ok(None);
+                };
+                let mut data = vec![];
+                for blk in fs.chain(entry.start)? {
+                    data.extend(self.ReadSingleBlock(blk as u32)?.0);
+                }
+                data.truncate(entry.size as usize);
+                Ok(Some(data))
+            }
+            fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+                let data = self.blocks.borrow().get(&blk).cloned();
+                Ok((
+                    data.unwrap_or_else(|| vec![0xFF; BLOCK_SIZE]),
+                    vec![0xFF; SPARE_SIZE],
+                ))
+            }
+            fn CardStats(&self) -> Result<CardStats> {
+                Ok(CardStats {
+                    free: 0x1000,
+                    used: 0,
+                    bad: 0,
+                    seqno: 0,
+                })
+            }
+        }
+        impl PlayerBlockWrite for Card {
+            fn WriteSingleBlock(&self, blk: u32, nand: &[u8], _: &[u8]) -> Result<()> {
+                if self.fail_at.get() == Some(blk) {
+                    self.fail_at.set(None);
+                    bail!("the link dropped");
+                }
+                self.writes.borrow_mut().push(blk as u16);
+                self.blocks.borrow_mut().insert(blk, nand.to_vec());
+                Ok(())
+            }
+            fn Close(&mut self) -> Result<()> {
+                self.cached = None;
+                Ok(())
+            }
+            fn Init(&mut self) -> Result<()> {
+                self.cached = Some(self.newest()?);
+                Ok(())
+            }
+        }
+        let mut card = Card {
+            blocks: RefCell::new(HashMap::new()),
+            writes: RefCell::new(vec![]),
+            fail_at: Cell::new(None),
+            cached: None,
+        };
+        let empty = FsBlock {
+            fat: vec![FAT_FREE; FAT_ENTRIES],
+            entries: vec![],
+            linked: false,
+            seqno: 5,
+        };
+        card.WriteSingleBlock(0xFF0, &empty.to_bytes()?, &[])?;
+        card.writes.borrow_mut().clear();
+        card.Init()?;
+
+        let dir = TestDir::new("upload")?;
+        let cancel = CancelToken::default();
+        let data = (0..5 * BLOCK_SIZE + 0x123)
+            .map(|i| (i * 7 / 3) as u8)
+            .collect::<Vec<_>>();
+
+        // an upload that fails at its fourth block, and is then continued from there
+        let planned = plan(&card, &dir, "BIG.app", &data)?;
+        if planned.decision != Decision::Fresh || planned.record.chain.len() != 6 {
+            bail!("a first upload was planned as {:?}", planned.decision);
+        }
+        let chain = planned.record.chain.clone();
+        card.fail_at.set(Some(chain[3] as u32));
+        if carry_out(&mut card, &dir, &planned, 0, &cancel, None).is_ok() {
+            bail!("an upload that failed partway succeeded");
+        }
+        let planned = plan(&card, &dir, "BIG.app", &data)?;
+        if planned.decision != Decision::Continue(3) {
+            bail!("a failed upload came back as {:?}", planned.decision);
+        }
+        card.writes.borrow_mut().clear();
+        let uploaded = carry_out(&mut card, &dir, &planned, 3, &cancel, None)?;
+        let writes = card.writes.borrow().clone();
+        if uploaded.kept != 3
+            || uploaded.seqno != 6
+            || writes.len() != 4
+            || writes[..3] != chain[3..]
+        {
+            bail!("continuing an upload wrote {writes:X?}, as {uploaded:?}");
+        }
+        if card.ReadFile("BIG.app")? != Some(data.clone()) || read_dir(&dir)?.count() != 0 {
+            bail!("a finished upload didn't leave the file on the card and no resume file");
+        }
+
+        // the console only sees the new FS generation once the connection is initialised again;
+        // before then the file isn't there to read back
+        let planned = plan(&card, &dir, "SEEN.app", &data)?;
+        send(
+            &card,
+            &planned,
+            &mut planned.record.clone(),
+            &dir.join("scratch"),
+            &cancel,
+            None,
+        )?;
+        if card.ReadFile("SEEN.app")?.is_some() || check_copy(&card, &planned.record).is_ok() {
+            bail!("the card's new FS generation was seen without initialising it again");
+        }
+        card.Init()?;
+        check_copy(&card, &planned.record)?;
+        remove_file(dir.join("scratch"))?;
+
+        // the local file changing between attempts is noticed, and it starts again
+        let mut edited = data.clone();
+        edited[0x10] ^= 0xFF;
+        let planned = plan(&card, &dir, "NEXT.app", &data)?;
+        card.fail_at.set(Some(planned.record.chain[2] as u32));
+        if carry_out(&mut card, &dir, &planned, 0, &cancel, None).is_ok() {
+            bail!("an upload that failed partway succeeded");
+        }
+        let planned = plan(&card, &dir, "NEXT.app", &edited)?;
+        match &planned.decision {
+            Decision::Restart(why) if why.contains("NEXT.app has changed since") => {}
+            other => bail!("an upload of a changed file was planned as {other:?}"),
+        }
+        card.writes.borrow_mut().clear();
+        carry_out(&mut card, &dir, &planned, 0, &cancel, None)?;
+        if card.writes.borrow().len() != 7
+            || card.ReadFile("NEXT.app")? != Some(edited.clone())
+            || read_dir(&dir)?.count() != 0
+        {
+            bail!("restarting the upload of a changed file went wrong");
+        }
+
+        // and so is the card's FS changing
+        let planned = plan(&card, &dir, "LAST.app", &data)?;
+        card.fail_at.set(Some(planned.record.chain[1] as u32));
+        if carry_out(&mut card, &dir, &planned, 0, &cancel, None).is_ok() {
+            bail!("an upload that failed partway succeeded");
+        }
+        let mut moved = card.newest()?;
+        moved.seqno += 1;
+        card.WriteSingleBlock(0xFF5, &moved.to_bytes()?, &[])?;
+        match plan(&card, &dir, "LAST.app", &data)?.decision {
+            Decision::Restart(why) if why.contains("it was #8, it's now #9") => {}
+            other => bail!("an upload after the FS changed was planned as {other:?}"),
+        }
+
+        // WriteFile can't be continued: a failed attempt leaves its resume file to say it was
+        // tried, which goes once a later one reads back as sent, and a copy that doesn't match
+        // keeps it
+        struct Whole {
+            files: HashMap<String, Vec<u8>>,
+            fail: bool,
+            corrupt: bool,
+        }
+        impl Player for Whole {
+            fn GetBBID(&self) -> Result<u32> {
+                Ok(0x1234ABCD)
+            }
+            fn SetLED(&self, _: u32) -> Result<()> {
+                Ok(())
+            }
+            fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+                Ok(vec![])
+            }
+            fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+                bail!("not needed")
+            }
+            fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+                Ok(self.files.get(name).cloned())
+            }
+            fn ReadSingleBlock(&self, _: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+                bail!("not needed")
+            }
+            fn CardStats(&self) -> Result<CardStats> {
+                Ok(CardStats {
+                    free: 0x1000,
+                    used: 0,
+                    bad: 0,
+                    seqno: 3,
+                })
+            }
+        }
+        impl PlayerWrite for Whole {
+            fn WriteFile(&mut self, data: &[u8], name: &str) -> Result<()> {
+                if std::mem::take(&mut self.fail) {
+                    bail!("the link dropped");
+                }
+                let mut data = data.to_vec();
+                if self.corrupt {
+                    data[0] ^= 1;
+                }
+                self.files.insert(name.to_string(), data);
+                Ok(())
+            }
+            fn DeleteFile(&mut self, _: &str) -> Result<()> {
+                Ok(())
+            }
+            fn RenameFile(&mut self, _: &str, _: &str) -> Result<()> {
+                Ok(())
+            }
+        }
+        let mut whole_card = Whole {
+            files: HashMap::new(),
+            fail: true,
+            corrupt: false,
+        };
+        let record = whole(&whole_card, "ONE.app", &data)?;
+        if write_whole(&mut whole_card, &dir, &record, &data).is_ok()
+            || interrupted(&dir, "ONE.app") != Some(record.clone())
+        {
+            bail!("a failed WriteFile left no resume file");
+        }
+        whole_card.corrupt = true;
+        if write_whole(&mut whole_card, &dir, &record, &data).is_ok()
+            || interrupted(&dir, "ONE.app").is_none()
+        {
+            bail!("a copy that didn't match what was sent was taken");
+        }
+        whole_card.corrupt = false;
+        write_whole(&mut whole_card, &dir, &record, &data)?;
+        if whole_card.files.get("ONE.app") != Some(&data) || interrupted(&dir, "ONE.app").is_some()
+        {
+            bail!("a finished WriteFile left its resume file");
+        }
+        Ok(())
+    }
+}
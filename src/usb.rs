@@ -0,0 +1,116 @@
+use rusb::Context;
+
+fn print_guidance(e: rusb::Error) {
+    eprintln!("Couldn't initialise the USB subsystem: {e}");
+    eprintln!("This isn't the same as no consoles being connected; no USB devices can be used until it's fixed.");
+    if cfg!(target_os = "windows") {
+        eprintln!("  - Make sure the console is using the WinUSB driver (for example, installed with Zadig)");
+        eprintln!(
+            "  - If another driver was installed for the console, remove it in Device Manager"
+        );
+    } else if cfg!(target_os = "macos") {
+        eprintln!("  - Make sure libusb is installed (for example, 'brew install libusb')");
+    } else {
+        eprintln!("  - Make sure libusb-1.0 is installed");
+        eprintln!("  - In a container or sandbox, make sure /dev/bus/usb is available");
+    }
+    eprintln!("Once the problem is fixed, use 'retry-usb' to try again without restarting.");
+}
+
+/// What the session holds while the USB subsystem is in use; it stays up until this is dropped.
+pub type UsbHold = Box<dyn Send>;
+
+/// Brings the USB subsystem up, for consoles to be found through. The session's own is libusb's;
+/// a front end can give another with [`crate::cli::CliContext::set_usb`].
+pub type UsbInit = Box<dyn FnMut() -> rusb::Result<UsbHold> + Send>;
+
+// the USB subsystem as the session has it: brought up the first time a command looks for
+// consoles, and with 'retry-usb' after that's failed. Consoles are found and opened through
+// libusb's global context, which the context held here keeps initialised
+pub struct Usb {
+    init: UsbInit,
+    state: UsbState,
+}
+
+enum UsbState {
+    Untried,
+    Unavailable,
+    // kept only to be dropped with the session
+    Ready { _hold: UsbHold },
+}
+
+impl Default for Usb {
+    fn default() -> Self {
+        Self::new(Box::new(|| Ok(Box::new(Context::new()?) as UsbHold)))
+    }
+}
+
+impl Usb {
+    pub fn new(init: UsbInit) -> Self {
+        Self {
+            init,
+            state: UsbState::Untried,
+        }
+    }
+
+    // whether it's up, bringing it up if it hasn't been tried; a failure is only reported the
+    // first time, after which it's left to 'retry-usb'
+    pub fn ready(&mut self) -> bool {
+        if let UsbState::Untried = self.state {
+            self.retry();
+        }
+        self.is_ready()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(self.state, UsbState::Ready { .. })
+    }
+
+    // tries to bring it up again, printing guidance if it can't be
+    pub fn retry(&mut self) -> bool {
+        self.state = match (self.init)() {
+            Ok(hold) => UsbState::Ready { _hold: hold },
+            Err(e) => {
+                print_guidance(e);
+                UsbState::Unavailable
+            }
+        };
+        self.is_ready()
+    }
+}
+
+pub fn print_unavailable() {
+    eprintln!("The USB subsystem isn't available. Fix the problem reported at startup, then use 'retry-usb'.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn ready() {
+        let tries = Arc::new(AtomicUsize::new(0));
+        let counted = |result: fn() -> rusb::Result<UsbHold>| {
+            let tries = tries.clone();
+            Box::new(move || {
+                tries.fetch_add(1, Ordering::Relaxed);
+                result()
+            }) as UsbInit
+        };
+
+        // it's brought up when first asked for, and not again
+        let mut usb = Usb::new(counted(|| Ok(Box::new(()))));
+        assert!(!usb.is_ready());
+        assert!(usb.ready() && usb.ready());
+        assert_eq!(tries.swap(0, Ordering::Relaxed), 1);
+
+        // nor is one that failed, until it's retried
+        let mut usb = Usb::new(counted(|| Err(rusb::Error::NotSupported)));
+        assert!(!usb.ready() && !usb.ready());
+        assert_eq!(tries.load(Ordering::Relaxed), 1);
+        assert!(!usb.retry());
+        assert_eq!(tries.load(Ordering::Relaxed), 2);
+    }
+}
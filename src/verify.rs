@@ -0,0 +1,246 @@
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha256};
+
+use crate::cancel::CancelToken;
+use crate::fs::BLOCK_SIZE;
+use crate::player::Player;
+use crate::progress::Progress;
+use crate::ranges::format_range;
+use crate::summary::{RangeOutcome, RangeSummary};
+use crate::throughput::TransferTimer;
+
+// reads each range back from the console and compares it with `nand`, without writing anything;
+// cancellable between blocks
+pub fn verify_ranges(
+    player: &dyn Player,
+    nand: &[u8],
+    ranges: &[Range<u16>],
+    events: bool,
+    summary: &mut RangeSummary,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let end = ranges.iter().map(|r| r.end as usize).max().unwrap_or(0);
+    if nand.len() < end * BLOCK_SIZE {
+        bail!("NAND data is too short for the selected blocks (up to {end:#X})");
+    }
+
+    let total = ranges.iter().map(|r| r.len() as u64).sum::<u64>();
+    let timer = TransferTimer::begin(player, "Verify", total * BLOCK_SIZE as u64);
+    let mut progress = Progress::start("verify", total, BLOCK_SIZE, events);
+
+    for (i, range) in ranges.iter().enumerate() {
+        progress.set_message(format!(
+            "Range {}/{} ({})",
+            i + 1,
+            ranges.len(),
+            format_range(range)
+        ));
+
+        let start = Instant::now();
+        let mut outcome = RangeOutcome {
+            range: range.clone(),
+            done: 0,
+            elapsed: Duration::ZERO,
+            mismatches: Some(vec![]),
+            hashes: None,
+        };
+        let mut local = Sha256::new();
+        let mut console = Sha256::new();
+
+        for blk in range.clone() {
+            let b = blk as usize;
+            let data = &nand[b * BLOCK_SIZE..(b + 1) * BLOCK_SIZE];
+            match cancel
+                .check()
+                .and_then(|_| player.ReadSingleBlock(blk as u32))
+            {
+                Ok((read_back, _)) => {
+                    local.update(data);
+                    console.update(&read_back);
+                    if read_back != data {
+                        outcome.mismatches.get_or_insert_with(Vec::new).push(blk);
+                    }
+                }
+                Err(e) => {
+                    outcome.elapsed = start.elapsed();
+                    summary.outcomes.push(outcome);
+                    let e = anyhow!(
+                        "Failed at block {blk:#X} of range {}: {e}",
+                        format_range(range)
+                    );
+                    progress.fail(&e.to_string());
+                    return Err(e);
+                }
+            }
+            outcome.done += 1;
+            progress.inc(1);
+        }
+
+        outcome.elapsed = start.elapsed();
+        outcome.hashes = Some((
+            format!("{:x}", local.finalize()),
+            format!("{:x}", console.finalize()),
+        ));
+        summary.outcomes.push(outcome);
+    }
+
+    progress.finish();
+    timer.complete();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::VerifyReport;
+    use bbrdb::CardStats;
+    use chrono::Local;
+
+    // a card holding `nand`, but for one block that reads back differently and one that can't
+    // be read at all
+    struct Card {
+        nand: Vec<u8>,
+        altered: u16,
+        unreadable: u16,
+    }
+
+    impl Player for Card {
+        fn GetBBID(&self) -> Result<u32> {
+            Ok(0x1234)
+        }
+
+        fn SetLED(&self, _value: u32) -> Result<()> {
+            Ok(())
+        }
+
+        fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+            Ok(vec![])
+        }
+
+        fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+            Ok(vec![])
+        }
+
+        fn ReadFile(&self, _name: &str) -> Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+            if blk == self.unreadable as u32 {
+                bail!("Operation timed out");
+            }
+            let b = blk as usize;
+            let mut data = self.nand[b * BLOCK_SIZE..(b + 1) * BLOCK_SIZE].to_vec();
+            if blk == self.altered as u32 {
+                data[0x10] ^= 0xFF;
+            }
+            Ok((data, vec![0xFF; 0x10]))
+        }
+
+        fn CardStats(&self) -> Result<CardStats> {
+            Ok(CardStats {
+                free: 1,
+                used: 2,
+                bad: 3,
+                seqno: 4,
+            })
+        }
+    }
+
+    #[test]
+    fn verifies() -> Result<()> {
+        let nand = (0..0x20 * BLOCK_SIZE)
+            .map(|i| (i / BLOCK_SIZE * 7 + i) as u8)
+            .collect::<Vec<_>>();
+        let card = Card {
+            nand: nand.clone(),
+            altered: 0x5,
+            unreadable: 0x1A,
+        };
+        let cancel = CancelToken::default();
+        let started = Local::now();
+
+        // a clean range and one with a mismatch in it, each with the hashes of both sides
+        let mut summary = RangeSummary::default();
+        verify_ranges(
+            &card,
+            &nand,
+            &[0x0..0x4, 0x4..0x8],
+            false,
+            &mut summary,
+            &cancel,
+        )?;
+        let [clean, mismatched] = &summary.outcomes[..] else {
+            bail!("{} ranges were verified", summary.outcomes.len());
+        };
+        assert_eq!((clean.done, &clean.mismatches), (4, &Some(vec![])));
+        assert_eq!(
+            (mismatched.done, &mismatched.mismatches),
+            (4, &Some(vec![0x5]))
+        );
+        let hashes = |o: &RangeOutcome| o.hashes.clone().unwrap_or_default();
+        assert_eq!(hashes(clean).0, hashes(clean).1);
+        assert_ne!(hashes(mismatched).0, hashes(mismatched).1);
+        assert_eq!(
+            hashes(clean).0,
+            format!("{:x}", Sha256::digest(&nand[..0x4 * BLOCK_SIZE]))
+        );
+        assert_eq!(summary.total_mismatches(), 1);
+        assert_eq!(summary.failure_context("verifying"), None);
+
+        let report =
+            serde_json::to_value(VerifyReport::new("verify", &card, &summary, started, true))?;
+        assert_eq!(report["bbid"], "00001234");
+        assert_eq!(report["card"]["bad"], 3);
+        assert_eq!(report["total_mismatches"], 1);
+        assert_eq!(report["ranges"][1]["mismatches"], serde_json::json!([5]));
+        assert_eq!(report["ranges"][1]["local_sha256"], hashes(mismatched).0);
+
+        // a read that fails stops it there, with what was checked so far
+        let mut summary = RangeSummary::default();
+        let e = verify_ranges(
+            &card,
+            &nand,
+            &[0x14..0x18, 0x18..0x1C],
+            false,
+            &mut summary,
+            &cancel,
+        )
+        .err()
+        .ok_or_else(|| anyhow!("the unreadable block was verified"))?;
+        assert_eq!(
+            e.to_string(),
+            "Failed at block 0x1A of range 0x18-0x1C: Operation timed out"
+        );
+        assert_eq!(
+            (summary.outcomes[1].done, &summary.outcomes[1].hashes),
+            (2, &None)
+        );
+        assert_eq!(
+            summary.failure_context("verifying").as_deref(),
+            Some("while verifying block 0x1A of range 0x18-0x1C, after 6 successful blocks")
+        );
+        let report =
+            serde_json::to_value(VerifyReport::new("verify", &card, &summary, started, false))?;
+        assert_eq!(report["complete"], false);
+        assert_eq!(report["ranges"][1]["blocks_checked"], 2);
+
+        // ranges past the end of the data aren't read at all
+        if verify_ranges(
+            &card,
+            &nand[..0x10 * BLOCK_SIZE],
+            &[0x0..0x8, 0x8..0x11],
+            false,
+            &mut RangeSummary::default(),
+            &cancel,
+        )
+        .is_ok()
+        {
+            bail!("ranges past the end of the data were verified");
+        }
+        Ok(())
+    }
+}
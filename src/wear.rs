@@ -0,0 +1,146 @@
+use std::fmt::{self, Display};
+
+use serde::Deserialize;
+
+use crate::ranges::{block_ranges, format_range};
+
+// Every block written costs it an erase cycle, and a card only has so many, so '2' warns before
+// a write that covers much of the card, and can compare it with writing only the blocks that
+// differ from what's on the console.
+
+// the '[wear]' section of the config file
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct WearPolicy {
+    // warn when a write covers more than this fraction of the card
+    pub warn_fraction: f64,
+    // ask again before such a write, unless a pre-scan shows it can't be made any smaller
+    pub confirm: bool,
+}
+
+impl Default for WearPolicy {
+    fn default() -> Self {
+        Self {
+            warn_fraction: 0.5,
+            confirm: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Advisory {
+    pub blocks: usize,
+    pub card: usize,
+    // the blocks that differ from the console's, if it was pre-scanned
+    pub differing: Option<Vec<u16>>,
+    pub confirm: bool,
+}
+
+// None if the write is small enough not to mention
+pub fn assess(
+    policy: &WearPolicy,
+    blocks: usize,
+    card: usize,
+    differing: Option<Vec<u16>>,
+) -> Option<Advisory> {
+    if card == 0 || (blocks as f64) <= policy.warn_fraction * card as f64 {
+        return None;
+    }
+    // if every block differs, there's nothing smaller to suggest
+    let confirm = policy.confirm && differing.as_ref().is_none_or(|d| d.len() < blocks);
+    Some(Advisory {
+        blocks,
+        card,
+        differing,
+        confirm,
+    })
+}
+
+impl Display for Advisory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "This write touches {:#X} of the card's {:#X} blocks ({}%), costing each of them an erase cycle.",
+            self.blocks,
+            self.card,
+            self.blocks * 100 / self.card
+        )?;
+        match &self.differing {
+            None => write!(
+                f,
+                "\nAdd '--prescan' to read the console first and find out how many blocks actually differ."
+            ),
+            Some(d) if d.is_empty() => write!(
+                f,
+                "\nThe console already holds exactly this image; nothing needs writing."
+            ),
+            Some(d) if d.len() < self.blocks => {
+                let ranges = block_ranges(d)
+                    .iter()
+                    .map(format_range)
+                    .collect::<Vec<_>>()
+                    .join(",");
+                write!(
+                    f,
+                    "\nA diff write would touch ~{:#X} blocks instead of {:#X}: give [ranges] {ranges}",
+                    d.len(),
+                    self.blocks
+                )
+            }
+            Some(_) => write!(f, "\nEvery block differs from the console's, so all of them need writing."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Result};
+
+    #[test]
+    fn wear_advisory() -> Result<()> {
+        let policy = WearPolicy::default();
+        let card = 0x1000;
+
+        if assess(&policy, 0x800, card, None).is_some() {
+            bail!("a write of exactly the threshold was warned about");
+        }
+        let Some(advisory) = assess(&policy, card, card, None) else {
+            bail!("a full-card write wasn't warned about");
+        };
+        if !advisory.confirm || !advisory.to_string().contains("--prescan") {
+            bail!(
+                "a full-card write without a pre-scan didn't ask for confirmation and suggest one"
+            );
+        }
+
+        let differing = vec![0x40, 0x41, 0x42, 0x100, 0xFF0];
+        let Some(advisory) = assess(&policy, card, card, Some(differing.clone())) else {
+            bail!("a pre-scanned full-card write wasn't warned about");
+        };
+        if !advisory.confirm || !advisory.to_string().contains("0x40-0x43,0x100,0xFF0") {
+            bail!("the diff write alternative was wrong: {advisory}");
+        }
+
+        let all = (0..card as u16).collect::<Vec<_>>();
+        if assess(&policy, card, card, Some(all)).is_none_or(|a| a.confirm) {
+            bail!("a write that can't be made smaller still asked for confirmation");
+        }
+
+        let relaxed = WearPolicy {
+            warn_fraction: 1.0,
+            confirm: false,
+        };
+        if assess(&relaxed, card, card, None).is_some() {
+            bail!("a threshold of the whole card still warned");
+        }
+        let strict = WearPolicy {
+            warn_fraction: 0.0,
+            confirm: false,
+        };
+        if assess(&strict, 1, card, None).is_none_or(|a| a.confirm) {
+            bail!("the configured threshold and confirmation weren't used");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::io::{stderr, stdout, IsTerminal};
+
+use terminal_size::{terminal_size_of, Width};
+use unicode_width::UnicodeWidthStr;
+
+// Re-flowing help and long messages to the width of the terminal they're printed on. Widths are
+// in terminal columns, so a CJK character takes two and a combining accent none. A word too long
+// for a line, such as a file path, gets a line to itself rather than being broken, so it can
+// still be copied.
+
+// the width to wrap at when the terminal's can't be found
+pub const FALLBACK_WIDTH: usize = 80;
+
+// None when the output isn't a terminal: whatever reads it gets each paragraph on one line
+fn width_of(terminal: bool, detected: Option<(Width, terminal_size::Height)>) -> Option<usize> {
+    terminal.then(|| detected.map_or(FALLBACK_WIDTH, |(Width(w), _)| w as usize))
+}
+
+pub fn stdout_width() -> Option<usize> {
+    width_of(stdout().is_terminal(), terminal_size_of(stdout()))
+}
+
+pub fn stderr_width() -> Option<usize> {
+    width_of(stderr().is_terminal(), terminal_size_of(stderr()))
+}
+
+// `text`, one paragraph to a line, in lines of at most `width` columns: the first starts with
+// `first`, and the rest (each paragraph's first line included) with `rest`, so `rest` gives a
+// hanging indent. Without a width, each paragraph is a single line.
+pub fn wrap(text: &str, width: Option<usize>, first: &str, rest: &str) -> String {
+    let mut lines = vec![];
+    for paragraph in text.split('\n') {
+        let mut line = match lines.is_empty() {
+            true => first.to_string(),
+            false => rest.to_string(),
+        };
+        let mut empty = true;
+        for word in paragraph.split_whitespace() {
+            let full = width.is_some_and(|w| line.width() + 1 + word.width() > w);
+            if !empty && full {
+                lines.push(line);
+                line = rest.to_string();
+                empty = true;
+            }
+            if !empty {
+                line.push(' ');
+            }
+            line += word;
+            empty = false;
+        }
+        lines.push(line.trim_end().to_string());
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{bail, Result};
+
+    #[test]
+    fn text_wrapping() -> Result<()> {
+        let check = |what: &str, found: String, expected: &str| -> Result<()> {
+            if found != expected {
+                bail!("{what} was wrapped as\n{found}\nnot\n{expected}");
+            }
+            Ok(())
+        };
+
+        check(
+            "a sentence",
+            wrap(
+                "the quick brown fox jumps over the lazy dog",
+                Some(16),
+                "",
+                "  ",
+            ),
+            "the quick brown\n  fox jumps over\n  the lazy dog",
+        )?;
+        check(
+            "a command's description",
+            wrap(
+                "Read [file] from the console\nand --continue resumes",
+                Some(30),
+                "  3 file - ",
+                "      ",
+            ),
+            "  3 file - Read [file] from\n      the console\n      and --continue resumes",
+        )?;
+
+        // a path too long for any line isn't broken, and doesn't drag the words around it along
+        check(
+            "a long path",
+            wrap(
+                "Couldn't read /home/someone/dumps/2024-01-01/nand.bin: not found",
+                Some(20),
+                "",
+                "",
+            ),
+            "Couldn't read\n/home/someone/dumps/2024-01-01/nand.bin:\nnot found",
+        )?;
+        check(
+            "a long first word",
+            wrap("/a/very/long/path/indeed ok", Some(10), "- ", "  "),
+            "- /a/very/long/path/indeed\n  ok",
+        )?;
+
+        // columns, not bytes or characters: each of these takes two, and the accent none
+        check(
+            "wide characters",
+            wrap("日本語 テスト ok", Some(8), "", ""),
+            "日本語\nテスト\nok",
+        )?;
+        check(
+            "accented text",
+            wrap("cafe\u{301} cafe\u{301} cafe\u{301}", Some(9), "", ""),
+            "cafe\u{301} cafe\u{301}\ncafe\u{301}",
+        )?;
+        check(
+            "a copyright sign",
+            wrap("© 2023 Jhynjhiruu", Some(6), "", ""),
+            "© 2023\nJhynjhiruu",
+        )?;
+
+        // piped: no wrapping, but the indents are kept
+        check(
+            "unwrapped text",
+            wrap("one two three\nfour five", None, "> ", "  "),
+            "> one two three\n  four five",
+        )?;
+        if width_of(false, None).is_some() || width_of(true, None) != Some(FALLBACK_WIDTH) {
+            bail!("the width wasn't None when piped and {FALLBACK_WIDTH} when undetected");
+        }
+        Ok(())
+    }
+}
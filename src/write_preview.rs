@@ -0,0 +1,491 @@
+use std::fmt::Write;
+use std::ops::Range;
+
+use anyhow::Result;
+
+use crate::fs::{FsBlock, BLOCK_SIZE, FS_REGION_BLOCKS, SKSA_BLOCKS};
+use crate::fsdiff::diff;
+use crate::hashing::{HashAlgo, HashValue};
+use crate::player::Player;
+use crate::relocate::plan;
+use crate::ticket::{parse_tickets, Ticket, CMD_CONTENT_ID, CMD_SIZE, TICKET_FILE};
+use crate::txn::{apply, Op};
+
+// What a dangerous write is about to change, shown before it's confirmed: an FS generation as the
+// entries it changes, the SKSA component by component, ticket.sys as the tickets added and
+// removed, and any other block as the bytes that change in each page. The console is read first;
+// working out the differences only needs the data on both sides.
+
+pub const PAGE_SIZE: usize = 0x200;
+// the SK is the first four blocks of the SKSA; each SA after it is a block of content metadata,
+// laid out as in a ticket, followed by its content padded to a whole block
+const SK_SIZE: usize = 4 * BLOCK_SIZE;
+const SA_NAMES: [&str; 2] = ["SA1", "SA2"];
+// bytes of each side shown around the first difference, and of each hash
+const DUMP_BYTES: usize = 0x10;
+const SHORT_HASH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockDiff {
+    // (page, bytes that differ in it) for each page with a difference
+    pub pages: Vec<(usize, usize)>,
+    pub first: Option<usize>,
+}
+
+pub fn block_diff(old: &[u8], new: &[u8]) -> BlockDiff {
+    let pages = old
+        .chunks(PAGE_SIZE)
+        .zip(new.chunks(PAGE_SIZE))
+        .enumerate()
+        .map(|(page, (o, n))| (page, o.iter().zip(n).filter(|(a, b)| a != b).count()))
+        .filter(|&(_, differing)| differing > 0)
+        .collect();
+    BlockDiff {
+        pages,
+        first: old.iter().zip(new).position(|(a, b)| a != b),
+    }
+}
+
+fn hex_row(data: &[u8], at: usize) -> String {
+    data[at..(at + DUMP_BYTES).min(data.len())]
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn render_block_diff(blk: u16, old: &[u8], new: &[u8]) -> String {
+    let d = block_diff(old, new);
+    let Some(first) = d.first else {
+        return format!("Block {blk:#X}: no bytes change\n");
+    };
+    let total = d.pages.iter().map(|&(_, n)| n).sum::<usize>();
+    let mut out = format!(
+        "Block {blk:#X}: {total} bytes change, in {} of its {} pages\n",
+        d.pages.len(),
+        old.len().div_ceil(PAGE_SIZE)
+    );
+    let pages = d
+        .pages
+        .iter()
+        .map(|(page, n)| format!("{page}: {n}"))
+        .collect::<Vec<_>>();
+    let _ = writeln!(out, "  bytes changed per page: {}", pages.join(", "));
+    let row = first / DUMP_BYTES * DUMP_BYTES;
+    let _ = writeln!(out, "  first difference at {first:#X}:");
+    let _ = writeln!(out, "    old {row:#06X}: {}", hex_row(old, row));
+    let _ = writeln!(out, "    new {row:#06X}: {}", hex_row(new, row));
+    out
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Component {
+    pub name: &'static str,
+    // the SK has no content metadata, so no content ID
+    pub content_id: Option<u32>,
+    pub size: usize,
+    pub hash: HashValue,
+}
+
+impl Component {
+    fn describe(&self) -> String {
+        let hash = &self.hash.hex()[..SHORT_HASH];
+        match self.content_id {
+            Some(id) => format!(
+                "content ID {id:08X}, {:#X} bytes, SHA-256 {hash}",
+                self.size
+            ),
+            None => format!("{:#X} bytes, SHA-256 {hash}", self.size),
+        }
+    }
+}
+
+// the SK and whichever SAs follow it; an SA whose metadata block is erased, or whose size runs
+// past the end of the SKSA, ends the list
+pub fn sksa_components(sksa: &[u8]) -> Vec<Component> {
+    let mut components = vec![];
+    if sksa.len() < SK_SIZE {
+        return components;
+    }
+    components.push(Component {
+        name: "SK",
+        content_id: None,
+        size: SK_SIZE,
+        hash: HashAlgo::Sha256.digest(&sksa[..SK_SIZE]),
+    });
+    let mut at = SK_SIZE;
+    for name in SA_NAMES {
+        let Some(cmd) = sksa.get(at..at + BLOCK_SIZE) else {
+            break;
+        };
+        let u32_at = |o: usize| u32::from_be_bytes([cmd[o], cmd[o + 1], cmd[o + 2], cmd[o + 3]]);
+        let size = u32_at(CMD_SIZE) as usize;
+        let content = at + BLOCK_SIZE;
+        if cmd.iter().all(|&b| b == 0xFF) || size == 0 || content + size > sksa.len() {
+            break;
+        }
+        components.push(Component {
+            name,
+            content_id: Some(u32_at(CMD_CONTENT_ID)),
+            size,
+            hash: HashAlgo::Sha256.digest(&sksa[content..content + size]),
+        });
+        at = content + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    }
+    components
+}
+
+pub fn render_sksa_diff(old: &[u8], new: &[u8]) -> String {
+    if old == new {
+        return "SKSA: no bytes change\n".to_string();
+    }
+    let (old, new) = (sksa_components(old), sksa_components(new));
+    let mut out = "SKSA:\n".to_string();
+    for name in ["SK"].into_iter().chain(SA_NAMES) {
+        let find = |c: &[Component]| c.iter().find(|c| c.name == name).cloned();
+        let _ = match (find(&old), find(&new)) {
+            (None, None) => continue,
+            (Some(o), None) => writeln!(out, "  {name}: removed (was {})", o.describe()),
+            (None, Some(n)) => writeln!(out, "  {name}: added, {}", n.describe()),
+            (Some(o), Some(n)) if o == n => writeln!(out, "  {name}: unchanged, {}", n.describe()),
+            (Some(o), Some(n)) => {
+                writeln!(out, "  {name}: {}\n    -> {}", o.describe(), n.describe())
+            }
+        };
+    }
+    if old == new {
+        out += "  the components are the same; only their metadata or padding changes\n";
+    }
+    out
+}
+
+// the SKSA as it will be once the blocks `ranges` cover have been written from `nand`
+pub fn written_sksa(current: &[u8], nand: &[u8], ranges: &[Range<u16>]) -> Vec<u8> {
+    let mut sksa = current.to_vec();
+    for blk in 0..SKSA_BLOCKS {
+        let at = blk as usize * BLOCK_SIZE;
+        if ranges.iter().any(|r| r.contains(&blk)) {
+            if let (Some(to), Some(from)) = (
+                sksa.get_mut(at..at + BLOCK_SIZE),
+                nand.get(at..at + BLOCK_SIZE),
+            ) {
+                to.copy_from_slice(from);
+            }
+        }
+    }
+    sksa
+}
+
+// the newest FS generation among the blocks `ranges` will write into the FS region
+pub fn written_fs(nand: &[u8], ranges: &[Range<u16>], num_blocks: u16) -> Option<FsBlock> {
+    let fs_start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    (fs_start..num_blocks)
+        .filter(|blk| ranges.iter().any(|r| r.contains(blk)))
+        .filter_map(|blk| {
+            let at = blk as usize * BLOCK_SIZE;
+            FsBlock::parse(nand.get(at..at + BLOCK_SIZE)?).ok()
+        })
+        .max_by_key(|fs| fs.seqno)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TicketDiff {
+    pub added: Vec<Ticket>,
+    pub removed: Vec<Ticket>,
+}
+
+// a missing ticket.sys has no tickets
+pub fn ticket_diff(old: Option<&[u8]>, new: Option<&[u8]>) -> Result<TicketDiff> {
+    let tickets = |data: Option<&[u8]>| -> Result<Vec<Ticket>> {
+        Ok(match data {
+            Some(d) => parse_tickets(d)?.tickets,
+            None => vec![],
+        })
+    };
+    let mut added = tickets(new)?;
+    let mut removed = vec![];
+    for ticket in tickets(old)? {
+        match added.iter().position(|t| *t == ticket) {
+            Some(i) => {
+                added.remove(i);
+            }
+            None => removed.push(ticket),
+        }
+    }
+    Ok(TicketDiff { added, removed })
+}
+
+pub fn render_ticket_diff(diff: &TicketDiff) -> String {
+    if diff.added.is_empty() && diff.removed.is_empty() {
+        return format!("{TICKET_FILE}: no tickets are added or removed\n");
+    }
+    let mut out = format!(
+        "{TICKET_FILE}: {} tickets added, {} removed\n",
+        diff.added.len(),
+        diff.removed.len()
+    );
+    for (what, tickets) in [("added", &diff.added), ("removed", &diff.removed)] {
+        for t in tickets {
+            let _ = writeln!(
+                out,
+                "  {what:<7} {} ({}), for BBID {:08X}",
+                t.app_name(),
+                t.kind,
+                t.bbid
+            );
+        }
+    }
+    out
+}
+
+// the rest read the console and print what they can; a preview that can't be worked out is only
+// noted, as it makes no difference to what's written
+
+fn current_fs(player: &dyn Player) -> Result<FsBlock> {
+    Ok(FsBlock::parse(&player.DumpCurrentFS()?)?)
+}
+
+fn print_fs_diff(old: &FsBlock, new: &FsBlock) {
+    println!("FS:");
+    diff(old, new).print();
+}
+
+pub fn preview_block(player: &dyn Player, blk: u16, num_blocks: u16, new: &[u8]) {
+    let old = match player.ReadSingleBlock(blk as u32) {
+        Ok((old, _)) => old,
+        Err(e) => {
+            println!("(couldn't read block {blk:#X} to show what changes: {e})");
+            return;
+        }
+    };
+    print!("{}", render_block_diff(blk, &old, new));
+    if blk >= num_blocks.saturating_sub(FS_REGION_BLOCKS as u16) {
+        if let (Ok(o), Ok(n)) = (FsBlock::parse(&old), FsBlock::parse(new)) {
+            print_fs_diff(&o, &n);
+        }
+    }
+}
+
+pub fn preview_image(player: &dyn Player, nand: &[u8], ranges: &[Range<u16>], num_blocks: u16) {
+    let covers = |blocks: Range<u16>| {
+        ranges
+            .iter()
+            .any(|r| r.start < blocks.end && blocks.start < r.end)
+    };
+    if covers(0..SKSA_BLOCKS) {
+        let current = (0..SKSA_BLOCKS)
+            .map(|blk| player.ReadSingleBlock(blk as u32).map(|(data, _)| data))
+            .collect::<Result<Vec<_>>>();
+        match current {
+            Ok(current) => {
+                let current = current.concat();
+                print!(
+                    "{}",
+                    render_sksa_diff(&current, &written_sksa(&current, nand, ranges))
+                );
+            }
+            Err(e) => println!("(couldn't read the SKSA to show what changes: {e})"),
+        }
+    }
+    let fs_start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    if covers(fs_start..num_blocks) {
+        match (current_fs(player), written_fs(nand, ranges, num_blocks)) {
+            (Ok(old), Some(new)) => print_fs_diff(&old, &new),
+            (Err(e), _) => println!("(couldn't read the current FS to show what changes: {e})"),
+            (_, None) => println!(
+                "FS: none of the blocks written to the FS region hold a valid FS generation"
+            ),
+        }
+    }
+}
+
+pub fn preview_txn(player: &dyn Player, ops: &[Op], num_blocks: u16) {
+    let fs_start = num_blocks.saturating_sub(FS_REGION_BLOCKS as u16);
+    let applied = current_fs(player).and_then(|fs| {
+        Ok((
+            apply(&fs, ops, |b| (SKSA_BLOCKS..fs_start).contains(&b))?,
+            fs,
+        ))
+    });
+    match applied {
+        Ok((applied, fs)) => print_fs_diff(&fs, &applied.fs),
+        Err(e) => println!("(couldn't work out the new FS to show what changes: {e})"),
+    }
+    // the last operation on ticket.sys decides what it ends up holding
+    let tickets = ops.iter().rev().find_map(|op| match op {
+        Op::Upload { name, data, .. } if name == TICKET_FILE => Some(Some(data.as_slice())),
+        Op::Delete(name) if name == TICKET_FILE => Some(None),
+        _ => None,
+    });
+    if let Some(new) = tickets {
+        preview_tickets(player, new);
+    }
+}
+
+pub fn preview_relocate(player: &dyn Player, blocks: &[u16], num_blocks: u16) {
+    match current_fs(player).and_then(|fs| Ok((plan(&fs, blocks, num_blocks)?, fs))) {
+        Ok((plan, fs)) => print_fs_diff(&fs, &plan.fs),
+        Err(e) => println!("(couldn't work out the new FS to show what changes: {e})"),
+    }
+}
+
+pub fn preview_tickets(player: &dyn Player, new: Option<&[u8]>) {
+    let diff = player
+        .ReadFile(TICKET_FILE)
+        .and_then(|old| ticket_diff(old.as_deref(), new));
+    match diff {
+        Ok(d) => print!("{}", render_ticket_diff(&d)),
+        Err(e) => println!("(couldn't compare {TICKET_FILE} to show what changes: {e})"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_previews() -> Result<()> {
+        use anyhow::bail;
+
+        use crate::ticket::{TicketKind, TICKET_SIZE};
+
+        // bytes that change are counted per page, and the first is dumped from its row
+        let old = vec![0xFF; BLOCK_SIZE];
+        let mut new = old.clone();
+        new[0x213] = 0x12;
+        new[0x214] = 0x34;
+        new[0x1000] = 0;
+        let d = block_diff(&old, &new);
+        if d.pages != [(1, 2), (8, 1)] || d.first != Some(0x213) {
+            bail!("a block's changes came out as {d:?}");
+        }
+        let text = render_block_diff(0x3FF0, &old, &new);
+        if !text.starts_with("Block 0x3FF0: 3 bytes change, in 2 of its 32 pages\n")
+            || !text.contains("per page: 1: 2, 8: 1\n")
+            || !text.contains("first difference at 0x213:")
+            || !text.contains("old 0x0210: FF FF FF FF FF FF FF FF")
+            || !text.contains("new 0x0210: FF FF FF 12 34 FF FF FF")
+        {
+            bail!("a block's changes were shown as\n{text}");
+        }
+        if block_diff(&old, &old).first.is_some()
+            || !render_block_diff(0, &old, &old).contains("no bytes change")
+        {
+            bail!("a block that doesn't change was shown as changing");
+        }
+
+        // an SKSA of the SK and two SAs, each with its content metadata
+        let sa = |sksa: &mut Vec<u8>, id: u32, content: &[u8]| {
+            let mut cmd = vec![0; BLOCK_SIZE];
+            cmd[CMD_SIZE..CMD_SIZE + 4].copy_from_slice(&(content.len() as u32).to_be_bytes());
+            cmd[CMD_CONTENT_ID..CMD_CONTENT_ID + 4].copy_from_slice(&id.to_be_bytes());
+            sksa.extend(cmd);
+            let mut padded = content.to_vec();
+            padded.resize(content.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+            sksa.extend(padded);
+        };
+        let build = |sa2: u32| {
+            let mut sksa = vec![0x5A; SK_SIZE];
+            sa(&mut sksa, 0x1002, &[1; 0x5000]);
+            sa(&mut sksa, sa2, &vec![sa2 as u8; 0x9000]);
+            sksa.resize(SKSA_BLOCKS as usize * BLOCK_SIZE, 0xFF);
+            sksa
+        };
+        let (old, new) = (build(0x1103), build(0x1104));
+        let components = sksa_components(&old);
+        let layout = components
+            .iter()
+            .map(|c| (c.name, c.content_id, c.size))
+            .collect::<Vec<_>>();
+        if layout
+            != [
+                ("SK", None, SK_SIZE),
+                ("SA1", Some(0x1002), 0x5000),
+                ("SA2", Some(0x1103), 0x9000),
+            ]
+            || components[1].hash != HashAlgo::Sha256.digest(&[1; 0x5000])
+        {
+            bail!("the SKSA's components came out as {layout:?}");
+        }
+        let text = render_sksa_diff(&old, &new);
+        if !text.contains("  SK: unchanged, 0x10000 bytes")
+            || !text.contains("  SA1: unchanged, content ID 00001002")
+            || !text.contains("  SA2: content ID 00001103, 0x9000 bytes")
+            || !text.contains("    -> content ID 00001104, 0x9000 bytes")
+        {
+            bail!("an SA2 update was shown as\n{text}");
+        }
+        let mut padding = old.clone();
+        padding[SK_SIZE + 3 * BLOCK_SIZE - 0x100] = 0xEE;
+        if !render_sksa_diff(&old, &padding).contains("only their metadata or padding") {
+            bail!("a change to an SA's padding wasn't called out");
+        }
+        if sksa_components(&vec![0xFF; SKSA_BLOCKS as usize * BLOCK_SIZE]).len() != 1 {
+            bail!("an erased SKSA was taken to have SAs");
+        }
+
+        // writing some of the SKSA's blocks leaves the rest as they are
+        let image = vec![0xAB; 0x1000 * BLOCK_SIZE];
+        let sksa = written_sksa(&old, &image, &[0..2, 0x100..0x200]);
+        if sksa[..2 * BLOCK_SIZE].iter().any(|&b| b != 0xAB)
+            || sksa[2 * BLOCK_SIZE..] != old[2 * BLOCK_SIZE..]
+        {
+            bail!("a partly written SKSA wasn't pieced together right");
+        }
+
+        // the FS written is the newest generation among the FS blocks written
+        let mut image = vec![0xFF; 0x1000 * BLOCK_SIZE];
+        for (blk, seqno) in [(0xFF0, 5), (0xFF4, 9), (0xFF8, 12)] {
+            let at = blk * BLOCK_SIZE;
+            image[at..at + BLOCK_SIZE]
+                .copy_from_slice(&crate::fs::synthetic_generation(&[0x40, 0x41], seqno));
+        }
+        let seqno = |range: Range<u16>| written_fs(&image, &[range], 0x1000).map(|fs| fs.seqno);
+        if seqno(0..0x1000) != Some(12)
+            || seqno(0xFF0..0xFF5) != Some(9)
+            || seqno(0x40..0xFF0).is_some()
+        {
+            bail!("the wrong FS generation was taken as the one written");
+        }
+
+        // tickets added and removed, matched whole so a changed limit shows as both
+        let tickets = |list: &[(u32, u16)]| {
+            let mut data = (list.len() as u32).to_be_bytes().to_vec();
+            for &(id, tid) in list {
+                let mut t = vec![0; TICKET_SIZE];
+                t[CMD_CONTENT_ID..CMD_CONTENT_ID + 4].copy_from_slice(&id.to_be_bytes());
+                t[0x29AC + 4..0x29AC + 6].copy_from_slice(&tid.to_be_bytes());
+                data.extend(t);
+            }
+            data
+        };
+        let old = tickets(&[(0x1111, 0), (0x2222, 0), (0x3333, 0x8000)]);
+        let new = tickets(&[(0x2222, 0), (0x3333, 0), (0x4444, 0)]);
+        let d = ticket_diff(Some(&old), Some(&new))?;
+        let ids = |t: &[Ticket]| t.iter().map(|t| t.content_id).collect::<Vec<_>>();
+        if ids(&d.added) != [0x3333, 0x4444] || ids(&d.removed) != [0x1111, 0x3333] {
+            bail!("tickets changed as {d:?}");
+        }
+        if d.removed[1].kind == TicketKind::Permanent {
+            bail!("the limited ticket removed was taken as permanent");
+        }
+        let text = render_ticket_diff(&d);
+        if !text.starts_with("ticket.sys: 2 tickets added, 2 removed\n")
+            || !text.contains("  added   00004444.app (permanent), for BBID 00000000\n")
+            || !text.contains("  removed 00001111.app (permanent)")
+        {
+            bail!("ticket changes were shown as\n{text}");
+        }
+        if ticket_diff(None, Some(&new))?.added.len() != 3
+            || ticket_diff(Some(&old), None)?.removed.len() != 3
+        {
+            bail!("a missing ticket.sys wasn't taken as empty");
+        }
+        if !render_ticket_diff(&ticket_diff(Some(&old), Some(&old))?)
+            .contains("no tickets are added or removed")
+        {
+            bail!("an unchanged ticket.sys was shown as changing");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,393 @@
+#![allow(non_snake_case)]
+
+// Driving the dispatcher from another crate, as a front end other than the prompt would: a
+// console simulated in memory is selected in place of a real one, and lines are dispatched to it.
+
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use anyhow::{bail, Result};
+use aulon2::cli::{dispatch, CliContext, Flow, UsbHold};
+use aulon2::fs::{
+    FsBlock, FsEntry, BLOCK_SIZE, FAT_END, FAT_ENTRIES, FAT_FREE, SKSA_BLOCKS, SPARE_SIZE,
+};
+use aulon2::player::{Backend, CardStats, Player};
+#[cfg(feature = "writing")]
+use aulon2::player::{PlayerBlockWrite, PlayerWrite};
+use chrono::{DateTime, FixedOffset};
+
+const BBID: u32 = 0x1234ABCD;
+const CARD_BLOCKS: u32 = 0x100;
+
+// what the simulated console holds, and what it's been asked to do
+#[derive(Default)]
+struct State {
+    files: Vec<(String, Vec<u8>)>,
+    calls: Vec<String>,
+    initialised: bool,
+    led: Option<u32>,
+    time: Option<DateTime<FixedOffset>>,
+    seqno: u32,
+}
+
+impl State {
+    // the files laid out one after another after the SKSA, with where each starts
+    fn layout(&self) -> Vec<(u16, &str, &[u8])> {
+        let mut next = SKSA_BLOCKS;
+        let mut layout = vec![];
+        for (name, data) in &self.files {
+            layout.push((next, name.as_str(), data.as_slice()));
+            next += data.len().div_ceil(BLOCK_SIZE).max(1) as u16;
+        }
+        layout
+    }
+
+    fn fs(&self) -> Result<Vec<u8>> {
+        let mut fat = vec![FAT_FREE; FAT_ENTRIES];
+        let mut entries = vec![];
+        for (start, name, data) in self.layout() {
+            let blocks = data.len().div_ceil(BLOCK_SIZE).max(1) as u16;
+            for blk in start..start + blocks {
+                fat[blk as usize] = if blk + 1 < start + blocks {
+                    blk + 1
+                } else {
+                    FAT_END
+                };
+            }
+            entries.push(FsEntry::new(name, start, data.len() as u32));
+        }
+        FsBlock {
+            fat,
+            entries,
+            linked: false,
+            seqno: self.seqno,
+        }
+        .to_bytes()
+    }
+}
+
+#[derive(Clone)]
+struct Simulated(Arc<Mutex<State>>);
+
+impl Simulated {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // the state, once the call `name` is recorded
+    fn call(&self, name: &str) -> std::sync::MutexGuard<'_, State> {
+        let mut state = self.state();
+        state.calls.push(name.to_string());
+        state
+    }
+}
+
+impl Player for Simulated {
+    fn GetBBID(&self) -> Result<u32> {
+        drop(self.call("GetBBID"));
+        Ok(BBID)
+    }
+
+    fn SetLED(&self, value: u32) -> Result<()> {
+        self.call("SetLED").led = Some(value);
+        Ok(())
+    }
+
+    fn ListFiles(&self) -> Result<Vec<(String, u32)>> {
+        let state = self.call("ListFiles");
+        Ok(state
+            .files
+            .iter()
+            .map(|(name, data)| (name.clone(), data.len() as u32))
+            .collect())
+    }
+
+    fn DumpCurrentFS(&self) -> Result<Vec<u8>> {
+        self.call("DumpCurrentFS").fs()
+    }
+
+    fn ReadFile(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let state = self.call("ReadFile");
+        Ok(state
+            .files
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, d)| d.clone()))
+    }
+
+    fn ReadSingleBlock(&self, blk: u32) -> Result<(Vec<u8>, Vec<u8>)> {
+        let state = self.call("ReadSingleBlock");
+        // the FS is kept in the last block of the card
+        if blk == CARD_BLOCKS - 1 {
+            return Ok((state.fs()?, vec![0xFF; SPARE_SIZE]));
+        }
+        let mut data = vec![0xFF; BLOCK_SIZE];
+        for (start, _, file) in state.layout() {
+            let Some(offset) = (blk as usize).checked_sub(start as usize) else {
+                continue;
+            };
+            let offset = offset * BLOCK_SIZE;
+            if offset < file.len() {
+                let chunk = &file[offset..file.len().min(offset + BLOCK_SIZE)];
+                data[..chunk.len()].copy_from_slice(chunk);
+            }
+        }
+        Ok((data, vec![0xFF; SPARE_SIZE]))
+    }
+
+    fn CardStats(&self) -> Result<CardStats> {
+        let state = self.call("CardStats");
+        let used = state
+            .files
+            .iter()
+            .map(|(_, d)| d.len().div_ceil(BLOCK_SIZE).max(1) as u32)
+            .sum::<u32>();
+        Ok(CardStats {
+            free: CARD_BLOCKS - SKSA_BLOCKS as u32 - used,
+            used: used + SKSA_BLOCKS as u32,
+            bad: 0,
+            seqno: state.seqno,
+        })
+    }
+}
+
+#[cfg(feature = "writing")]
+impl PlayerWrite for Simulated {
+    fn WriteFile(&mut self, data: &[u8], name: &str) -> Result<()> {
+        let mut state = self.call("WriteFile");
+        state.files.retain(|(n, _)| n != name);
+        state.files.push((name.to_string(), data.to_vec()));
+        state.seqno += 1;
+        Ok(())
+    }
+
+    fn DeleteFile(&mut self, name: &str) -> Result<()> {
+        let mut state = self.call("DeleteFile");
+        let Some(i) = state.files.iter().position(|(n, _)| n == name) else {
+            bail!("{name} isn't on the card");
+        };
+        state.files.remove(i);
+        state.seqno += 1;
+        Ok(())
+    }
+
+    fn RenameFile(&mut self, from: &str, to: &str) -> Result<()> {
+        let mut state = self.call("RenameFile");
+        let Some(file) = state.files.iter_mut().find(|(n, _)| n == from) else {
+            bail!("{from} isn't on the card");
+        };
+        file.0 = to.to_string();
+        state.seqno += 1;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "writing")]
+impl PlayerBlockWrite for Simulated {
+    fn WriteSingleBlock(&self, _blk: u32, _nand: &[u8], _spare: &[u8]) -> Result<()> {
+        bail!("the simulated console doesn't write blocks")
+    }
+
+    fn Close(&mut self) -> Result<()> {
+        self.call("Close").initialised = false;
+        Ok(())
+    }
+
+    fn Init(&mut self) -> Result<()> {
+        self.call("Init").initialised = true;
+        Ok(())
+    }
+}
+
+impl Backend for Simulated {
+    fn initialised(&self) -> Result<bool> {
+        Ok(self.state().initialised)
+    }
+
+    #[cfg(not(feature = "writing"))]
+    fn Init(&mut self) -> Result<()> {
+        self.call("Init").initialised = true;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "writing"))]
+    fn Close(&mut self) -> Result<()> {
+        self.call("Close").initialised = false;
+        Ok(())
+    }
+
+    fn SetTime(&self, time: DateTime<FixedOffset>) -> Result<()> {
+        self.call("SetTime").time = Some(time);
+        Ok(())
+    }
+
+    fn ReadSKSA(&self) -> Result<Vec<u8>> {
+        drop(self.call("ReadSKSA"));
+        Ok(vec![0; SKSA_BLOCKS as usize * BLOCK_SIZE])
+    }
+
+    #[cfg(feature = "writing")]
+    fn WriteNANDSpare(&self, _nand: &[u8], _spare: &[u8], _blocks: Option<Vec<u16>>) -> Result<()> {
+        bail!("the simulated console doesn't write whole cards")
+    }
+}
+
+// the test's own directory, removed when it's dropped, whether the test passed or panicked
+struct TestDir(PathBuf);
+
+impl TestDir {
+    fn new(name: &str) -> std::io::Result<Self> {
+        let path = std::env::temp_dir().join(format!("aulon2-{name}-{}", std::process::id()));
+        // left by a killed run that had the same pid
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn simulated_console() -> Result<()> {
+    let dir = TestDir::new("dispatch")?;
+    let game = (0..BLOCK_SIZE * 2).map(|i| i as u8).collect::<Vec<_>>();
+    let console = Simulated(Arc::new(Mutex::new(State {
+        files: vec![
+            ("GAME.app".to_string(), game.clone()),
+            ("SAVE.sta".to_string(), vec![0x5A; BLOCK_SIZE]),
+        ],
+        seqno: 1,
+        ..State::default()
+    })));
+    let mut context = CliContext::default();
+    let mut answers = VecDeque::new();
+    let mut run = |context: &mut CliContext, line: &str| {
+        let flow = dispatch(context, &mut answers, line);
+        let calls = std::mem::take(&mut console.state().calls);
+        (flow, calls)
+    };
+
+    // with nothing selected, nothing reaches the console
+    assert_eq!(run(&mut context, "I"), (Flow::Continue, vec![]));
+
+    context.select_player(Box::new(console.clone()))?;
+    let (_, calls) = run(&mut context, "B");
+    if !calls.contains(&"Init".to_string()) || !console.state().initialised {
+        bail!("'B' made the calls {calls:?}");
+    }
+    assert_eq!(run(&mut context, "I").1, ["GetBBID"]);
+    run(&mut context, "H 2");
+    assert_eq!(console.state().led, Some(2));
+    // and one command flashing it is followed by setting it back, without waiting for a prompt
+    let (_, calls) = run(&mut context, "H --during I");
+    assert_eq!(calls, ["SetLED", "GetBBID", "SetLED"]);
+    assert_eq!(console.state().led, Some(2));
+    run(&mut context, "J 2024-05-02T10:00:00+01:00");
+    assert_eq!(
+        console.state().time,
+        Some(DateTime::parse_from_rfc3339("2024-05-02T10:00:00+01:00")?)
+    );
+
+    // a file read off the card is the one the console has
+    let out = dir.join("game.app");
+    let spare = dir.join("game.spare");
+    run(
+        &mut context,
+        &format!(
+            "3 --with-spare GAME.app {} {}",
+            out.display(),
+            spare.display()
+        ),
+    );
+    if std::fs::read(&out)? != game {
+        bail!("'3' didn't read GAME.app as it is on the card");
+    }
+    assert_eq!(std::fs::read(&spare)?, vec![0xFF; SPARE_SIZE * 2]);
+
+    // and a file deleted is gone, which 'finish' checks before closing the connection
+    #[cfg(feature = "writing")]
+    {
+        let (_, calls) = run(&mut context, "6 SAVE.sta");
+        if !calls.contains(&"DeleteFile".to_string()) {
+            bail!("'6' made the calls {calls:?}");
+        }
+        assert_eq!(console.state().files.len(), 1);
+        // a block past what the FS can address isn't cut down to one it can
+        let (_, calls) = run(&mut context, "Y 65536 nand.bin spare.bin");
+        if calls.iter().any(|c| c.contains("Block")) {
+            bail!("'Y 65536' made the calls {calls:?}");
+        }
+        let (_, calls) = run(&mut context, "finish");
+        if calls.first().map(String::as_str) != Some("Close") || console.state().initialised {
+            bail!("'finish' made the calls {calls:?}");
+        }
+    }
+
+    run(&mut context, "B");
+    let (_, calls) = run(&mut context, "Q");
+    if !calls.contains(&"Close".to_string()) || console.state().initialised {
+        bail!("'Q' made the calls {calls:?}");
+    }
+
+    // 'Q' lets the console go, so it's selected again; once the session's attested, it can't
+    // be swapped for another
+    context.select_player(Box::new(console.clone()))?;
+    run(&mut context, "B");
+    run(&mut context, "session read-only-attest");
+    if context.select_player(Box::new(console.clone())).is_ok() {
+        bail!("another console was selected in an attested session");
+    }
+    assert_eq!(run(&mut context, "q").0, Flow::Quit);
+    Ok(())
+}
+
+#[test]
+fn usb_retry() {
+    let up = Arc::new(AtomicBool::new(false));
+    let tries = Arc::new(AtomicUsize::new(0));
+    let mut context = CliContext::default();
+    context.set_usb({
+        let (up, tries) = (up.clone(), tries.clone());
+        Box::new(move || {
+            tries.fetch_add(1, Ordering::Relaxed);
+            match up.load(Ordering::Relaxed) {
+                true => Ok(Box::new(()) as UsbHold),
+                false => Err(rusb::Error::NotSupported),
+            }
+        })
+    });
+    let mut answers = VecDeque::new();
+    let mut run = |line: &str| {
+        dispatch(&mut context, &mut answers, line);
+        tries.load(Ordering::Relaxed)
+    };
+
+    // it's left alone until a command looks for consoles, and once it's failed, the commands
+    // that need it say so without trying again
+    assert_eq!(run("status"), 0);
+    assert_eq!(run("l"), 1);
+    assert_eq!(run("s 0"), 1);
+    assert_eq!(run("survey"), 1);
+
+    // until 'retry-usb', which tries until it's up, and then leaves it be
+    assert_eq!(run("retry-usb"), 2);
+    up.store(true, Ordering::Relaxed);
+    assert_eq!(run("retry-usb"), 3);
+    assert_eq!(run("retry-usb"), 3);
+    assert_eq!(run("l"), 3);
+}